@@ -0,0 +1,85 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use goatns::db::{get_records, start_db, DBEntity};
+use goatns::enums::{RecordClass, RecordType};
+use goatns::zones::{FileZone, FileZoneRecord};
+use sqlx::SqlitePool;
+use tokio::runtime::Runtime;
+
+const RECORD_COUNT: usize = 500;
+
+/// Builds an in-memory database with a zone full of A records to look things up in
+async fn setup_pool() -> SqlitePool {
+    let pool = SqlitePool::connect("sqlite::memory:")
+        .await
+        .expect("failed to open in-memory database");
+    start_db(&pool).await.expect("failed to start database");
+
+    let records = (0..RECORD_COUNT)
+        .map(|index| FileZoneRecord {
+            id: None,
+            zoneid: None,
+            name: format!("host{index}"),
+            rrtype: "A".to_string(),
+            class: RecordClass::Internet,
+            rdata: format!("10.0.{}.{}", index / 256, index % 256),
+            ttl: 300,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        })
+        .collect();
+    let zone = FileZone {
+        name: "example.com".to_string(),
+        rname: "billy.example.com".to_string(),
+        records,
+        ..Default::default()
+    };
+    zone.save(&pool).await.expect("failed to save zone");
+    pool
+}
+
+/// What `get_records` used to do, formatting the query and grabbing a connection for every lookup
+async fn formatted_lookup(pool: &SqlitePool, name: &str) -> usize {
+    let query = format!(
+        "SELECT
+        record_id, zoneid, name, rclass, rrtype, rdata, ttl
+        FROM {}
+        WHERE name = ? AND rrtype = ? AND rclass = ?",
+        "records_merged"
+    );
+    let mut conn = pool.acquire().await.expect("failed to acquire connection");
+    sqlx::query(&query)
+        .bind(name)
+        .bind(RecordType::A as u16)
+        .bind(RecordClass::Internet)
+        .fetch_all(&mut *conn)
+        .await
+        .expect("failed to query")
+        .len()
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let rt = Runtime::new().expect("failed to start tokio runtime");
+    let pool = rt.block_on(setup_pool());
+
+    c.bench_function("lookup formatted query", |b| {
+        b.iter(|| rt.block_on(formatted_lookup(&pool, black_box("host250.example.com"))))
+    });
+
+    c.bench_function("lookup get_records", |b| {
+        b.iter(|| {
+            rt.block_on(get_records(
+                &pool,
+                black_box("host250.example.com".to_string()),
+                RecordType::A,
+                RecordClass::Internet,
+                true,
+            ))
+            .expect("failed to get records")
+        })
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -1,13 +1,32 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion}; // ,Bencher
 
 // use std::process::Termination;
+use goatns::enums::RecordType;
 use goatns::get_question_qname;
+use goatns::loadtest::build_query;
+use goatns::{Header, Question, HEADER_BYTES};
+use packed_struct::PackedStruct;
 
 fn criterion_benchmark(c: &mut Criterion) {
     let input = [7, 101, 120, 97, 109, 112, 108, 101, 3, 99, 111, 109, 0].to_vec();
     c.bench_function("name_as_bytes", |b| {
         b.iter(|| get_question_qname(black_box(&input)))
     });
+
+    // what the server does with every query before it goes to the datastore
+    let query =
+        build_query(1234, "www.example.com", RecordType::AAAA).expect("failed to build query");
+    c.bench_function("parse query", |b| {
+        b.iter(|| {
+            let query = black_box(&query);
+            let mut header: [u8; HEADER_BYTES] = [0; HEADER_BYTES];
+            header.copy_from_slice(&query[0..HEADER_BYTES]);
+            (
+                Header::unpack(&header).expect("failed to parse header"),
+                Question::from_packets(&query[HEADER_BYTES..]).expect("failed to parse question"),
+            )
+        })
+    });
 }
 
 // fn bench_get_question_qname(rdata: Vec<u8>) {
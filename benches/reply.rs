@@ -0,0 +1,56 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use goatns::enums::{Rcode, RecordClass, RecordType};
+use goatns::reply::{reply_builder, Answer, Reply};
+use goatns::resourcerecord::{DNSCharString, InternalResourceRecord};
+use goatns::{Question, UDP_BUFFER_SIZE};
+
+/// A reply with a handful of A records and a TXT record, about what a normal lookup would return
+fn build_reply() -> Reply {
+    let mut reply = reply_builder(1234, Rcode::NoError).expect("failed to build reply");
+    reply.question = Some(Question::new(
+        b"example.com".to_vec(),
+        RecordType::A,
+        RecordClass::Internet,
+    ));
+    reply.answers = (0..8u32)
+        .map(|index| InternalResourceRecord::A {
+            address: 0x0a000000 + index,
+            ttl: 300,
+            rclass: RecordClass::Internet,
+        })
+        .map(Answer::from)
+        .collect();
+    reply.answers.push(
+        InternalResourceRecord::TXT {
+            txtdata: vec![DNSCharString::from("v=spf1 include:example.com ~all")],
+            ttl: 300,
+            class: RecordClass::Internet,
+        }
+        .into(),
+    );
+    reply
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let reply = build_reply();
+
+    c.bench_function("reply as_bytes", |b| {
+        b.iter(|| {
+            black_box(&reply)
+                .as_bytes()
+                .expect("failed to build packet")
+        })
+    });
+
+    c.bench_function("reply as_bytes_udp", |b| {
+        b.iter(|| {
+            black_box(&reply)
+                .as_bytes_udp(black_box(UDP_BUFFER_SIZE))
+                .expect("failed to build packet")
+        })
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
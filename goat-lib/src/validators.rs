@@ -33,3 +33,19 @@ pub fn dns_name(name: &str) -> bool {
     }
     true
 }
+
+/// Checks the name a record's stored under, which is relative to the zone. Service labels like `_dmarc` and
+/// `_443._tcp` are only allowed if `underscores` is set, and the first label can be `*` for wildcards. Long labels
+/// aren't checked here, they're refused when the record's served.
+pub fn record_name(name: &str, underscores: bool) -> bool {
+    if name.is_empty() || name == "@" {
+        return true;
+    }
+    name.split('.').enumerate().all(|(index, label)| {
+        (index == 0 && label == "*")
+            || (!label.is_empty()
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || (underscores && c == '_')))
+    })
+}
@@ -0,0 +1,273 @@
+//! Gets the web server's certificate from an ACME CA (eg Let's Encrypt) and keeps it renewed
+//!
+//! The DNS-01 challenges are answered by GoatNS itself - while an order's in flight the `_acme-challenge` TXT records
+//! are held in [CHALLENGES] and served ahead of anything in the datastore, so it works even when the names aren't in
+//! one of our zones' records (they do have to be delegated to us, though). The new cert and key are written over
+//! `api_tls_cert` and `api_tls_key`, and [crate::web::tls::reload_loop] picks them up from there.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, OrderStatus,
+};
+use rcgen::{CertificateParams, DistinguishedName, KeyPair};
+
+use crate::config::{AcmeConfig, ConfigFile};
+use crate::enums::RecordClass;
+use crate::error::GoatNsError;
+use crate::resourcerecord::{DNSCharString, InternalResourceRecord};
+use crate::zones::ZoneRecord;
+use crate::Question;
+
+/// How long the challenge TXT records live in caches, they're only around for a minute or two
+const CHALLENGE_TTL: u32 = 60;
+/// How many times we check on an order before giving up
+const ORDER_POLL_ATTEMPTS: u32 = 30;
+/// How long we wait between checks on an order
+const ORDER_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    /// The `_acme-challenge` names (lowercase, no trailing dot) we're answering for right now, and their TXT values
+    pub static ref CHALLENGES: RwLock<HashMap<String, Vec<String>>> = RwLock::new(HashMap::new());
+}
+
+impl From<instant_acme::Error> for GoatNsError {
+    fn from(error: instant_acme::Error) -> Self {
+        GoatNsError::Acme(error.to_string())
+    }
+}
+
+impl From<rcgen::Error> for GoatNsError {
+    fn from(error: rcgen::Error) -> Self {
+        GoatNsError::Acme(format!("Failed to build the certificate request: {error}"))
+    }
+}
+
+/// The name the DNS-01 challenge for a domain lives at
+pub fn challenge_name(domain: &str) -> String {
+    format!(
+        "_acme-challenge.{}",
+        domain
+            .trim_start_matches("*.")
+            .trim_end_matches('.')
+            .to_ascii_lowercase()
+    )
+}
+
+/// Start answering a challenge
+pub fn add_challenge(domain: &str, value: String) {
+    if let Ok(mut challenges) = CHALLENGES.write() {
+        challenges
+            .entry(challenge_name(domain))
+            .or_default()
+            .push(value);
+    }
+}
+
+/// Stop answering the challenges for a domain
+pub fn remove_challenges(domain: &str) {
+    if let Ok(mut challenges) = CHALLENGES.write() {
+        challenges.remove(&challenge_name(domain));
+    }
+}
+
+/// If the question's for one of the challenges we're answering, here's the answer
+pub fn challenge_answer(question: &Question) -> Option<ZoneRecord> {
+    if question.qtype != crate::enums::RecordType::TXT || question.qclass != RecordClass::Internet {
+        return None;
+    }
+    let name = question.lookup_name();
+    let challenges = CHALLENGES.read().ok()?;
+    let values = challenges.get(std::str::from_utf8(&name).ok()?)?;
+    Some(ZoneRecord {
+        name,
+        typerecords: values
+            .iter()
+            .map(|value| InternalResourceRecord::TXT {
+                txtdata: vec![DNSCharString::from(value.as_str())],
+                ttl: CHALLENGE_TTL,
+                class: RecordClass::Internet,
+            })
+            .collect(),
+    })
+}
+
+/// Load the account from `account_path`, or sign up for one and save it there
+async fn get_account(acme: &AcmeConfig) -> Result<Account, GoatNsError> {
+    if acme.account_path.exists() {
+        let credentials: AccountCredentials =
+            serde_json::from_str(&std::fs::read_to_string(&acme.account_path)?).map_err(|err| {
+                GoatNsError::Acme(format!(
+                    "Failed to parse ACME account from {:?}: {err}",
+                    acme.account_path
+                ))
+            })?;
+        return Ok(Account::from_credentials(credentials).await?);
+    }
+
+    let contact: Vec<String> = acme
+        .contact
+        .iter()
+        .map(|email| format!("mailto:{email}"))
+        .collect();
+    let contact: Vec<&str> = contact.iter().map(String::as_str).collect();
+    let (account, credentials) = Account::create(
+        &NewAccount {
+            contact: &contact,
+            terms_of_service_agreed: true,
+            only_return_existing: false,
+        },
+        acme.directory_url.as_str(),
+        None,
+    )
+    .await?;
+    let credentials = serde_json::to_string_pretty(&credentials)
+        .map_err(|err| GoatNsError::Acme(format!("Failed to serialize ACME account: {err}")))?;
+    std::fs::write(&acme.account_path, credentials)?;
+    log::info!("Created ACME account, saved to {:?}", acme.account_path);
+    Ok(account)
+}
+
+/// Order a certificate for the configured domains and write it (and its new key) to the API TLS files
+pub async fn provision(config: &ConfigFile) -> Result<(), GoatNsError> {
+    let domains = config.acme.domains(&config.hostname);
+    log::info!("Requesting a certificate for {}", domains.join(", "));
+
+    let account = get_account(&config.acme).await?;
+    let identifiers: Vec<Identifier> = domains
+        .iter()
+        .map(|domain| Identifier::Dns(domain.clone()))
+        .collect();
+    let mut order = account
+        .new_order(&NewOrder {
+            identifiers: &identifiers,
+        })
+        .await?;
+
+    let res = complete_order(&mut order, &domains).await;
+    for domain in domains.iter() {
+        remove_challenges(domain);
+    }
+    let key_pem = res?;
+
+    let mut cert_pem = None;
+    for _ in 0..ORDER_POLL_ATTEMPTS {
+        match order.certificate().await? {
+            Some(cert) => {
+                cert_pem = Some(cert);
+                break;
+            }
+            None => tokio::time::sleep(ORDER_POLL_INTERVAL).await,
+        }
+    }
+    let cert_pem = cert_pem.ok_or_else(|| {
+        GoatNsError::Acme("Timed out waiting for the certificate to be issued".to_string())
+    })?;
+
+    // key first, so the reloader doesn't pair a new cert with the old key
+    std::fs::write(&config.api_tls_key, key_pem)?;
+    std::fs::write(&config.api_tls_cert, cert_pem)?;
+    log::info!("Saved new certificate to {:?}", config.api_tls_cert);
+    Ok(())
+}
+
+/// Answer the order's challenges, wait for it to be ready and finalize it, returning the new private key
+async fn complete_order(
+    order: &mut instant_acme::Order,
+    domains: &[String],
+) -> Result<String, GoatNsError> {
+    let authorizations = order.authorizations().await?;
+    let mut ready = vec![];
+    for authz in &authorizations {
+        match authz.status {
+            AuthorizationStatus::Pending => {}
+            AuthorizationStatus::Valid => continue,
+            status => {
+                return Err(GoatNsError::Acme(format!(
+                    "Authorization for {:?} is {status:?}",
+                    authz.identifier
+                )))
+            }
+        }
+        let challenge = authz
+            .challenges
+            .iter()
+            .find(|challenge| challenge.r#type == ChallengeType::Dns01)
+            .ok_or_else(|| {
+                GoatNsError::Acme(format!(
+                    "No DNS-01 challenge offered for {:?}",
+                    authz.identifier
+                ))
+            })?;
+        let Identifier::Dns(domain) = &authz.identifier;
+        add_challenge(domain, order.key_authorization(challenge).dns_value());
+        ready.push(challenge.url.clone());
+    }
+    for url in ready.iter() {
+        order.set_challenge_ready(url).await?;
+    }
+
+    let mut status = order.refresh().await?.status;
+    for _ in 0..ORDER_POLL_ATTEMPTS {
+        if matches!(status, OrderStatus::Ready | OrderStatus::Invalid) {
+            break;
+        }
+        tokio::time::sleep(ORDER_POLL_INTERVAL).await;
+        status = order.refresh().await?.status;
+    }
+    if status != OrderStatus::Ready {
+        return Err(GoatNsError::Acme(format!(
+            "Order didn't become ready, it's {status:?}"
+        )));
+    }
+
+    let mut params = CertificateParams::new(domains.to_vec())?;
+    params.distinguished_name = DistinguishedName::new();
+    let key = KeyPair::generate()?;
+    let csr = params.serialize_request(&key)?;
+    order.finalize(csr.der()).await?;
+    Ok(key.serialize_pem())
+}
+
+/// How long until the certificate at `path` expires, [None] if it's missing or we can't read it
+pub fn cert_expires_in(path: &Path) -> Option<Duration> {
+    let pem = std::fs::read(path).ok()?;
+    let (_, pem) = x509_parser::pem::parse_x509_pem(&pem).ok()?;
+    let cert = pem.parse_x509().ok()?;
+    cert.validity()
+        .time_to_expiration()
+        .map(|left| Duration::from_secs(left.whole_seconds().max(0).unsigned_abs()))
+}
+
+/// Does the certificate need getting or renewing?
+pub fn needs_renewal(config: &ConfigFile) -> bool {
+    match cert_expires_in(&config.api_tls_cert) {
+        Some(left) => left < Duration::from_secs(config.acme.renew_days * 86400),
+        None => true,
+    }
+}
+
+/// Check the certificate every `check_hours` and renew it when it's close to expiring
+pub async fn renewal_loop(config: ConfigFile) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(config.acme.check_hours.max(1) * 3600));
+    // the first tick's immediate, and startup's already had a go
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if !needs_renewal(&config) {
+            log::debug!(
+                "Certificate at {:?} doesn't need renewing yet",
+                config.api_tls_cert
+            );
+            continue;
+        }
+        if let Err(err) = provision(&config).await {
+            log::error!("Failed to renew the certificate: {err:?}");
+        }
+    }
+}
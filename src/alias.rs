@@ -0,0 +1,142 @@
+//! ALIAS record flattening.
+//!
+//! An ALIAS record works like a CNAME that's allowed at the zone apex, when someone asks for A/AAAA records at a name
+//! that only has an ALIAS, we resolve the target upstream and serve its addresses as if they were our own.
+
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::system_conf::read_system_conf;
+use trust_dns_resolver::TokioAsyncResolver;
+
+use crate::enums::{RecordClass, RecordType};
+use crate::error::GoatNsError;
+use crate::resourcerecord::InternalResourceRecord;
+
+static ALIAS_RESOLVER: OnceLock<TokioAsyncResolver> = OnceLock::new();
+
+/// How long we'll wait on upstream for an ALIAS target, this needs to leave room inside [crate::REPLY_TIMEOUT_MS] to actually answer
+pub const ALIAS_LOOKUP_TIMEOUT_MS: u64 = crate::REPLY_TIMEOUT_MS / 2;
+
+/// Set up the resolver used for flattening, if `upstreams` is empty the system resolver configuration is used.
+///
+/// Only the first call does anything, later ones are ignored.
+pub fn init_resolver(upstreams: &[SocketAddr]) {
+    ALIAS_RESOLVER.get_or_init(|| build_resolver(upstreams));
+}
+
+/// The resolver defaults are a 5 second timeout with two attempts, which is far longer than the client will wait for us
+fn resolver_opts(mut opts: ResolverOpts) -> ResolverOpts {
+    opts.timeout = Duration::from_millis(ALIAS_LOOKUP_TIMEOUT_MS);
+    opts.attempts = 1;
+    opts
+}
+
+fn build_resolver(upstreams: &[SocketAddr]) -> TokioAsyncResolver {
+    if upstreams.is_empty() {
+        match read_system_conf() {
+            Ok((config, opts)) => return TokioAsyncResolver::tokio(config, resolver_opts(opts)),
+            Err(err) => {
+                log::warn!("Couldn't load the system resolver config for ALIAS flattening, using defaults: {err:?}");
+            }
+        }
+        return TokioAsyncResolver::tokio(
+            ResolverConfig::default(),
+            resolver_opts(ResolverOpts::default()),
+        );
+    }
+    let mut config = ResolverConfig::new();
+    for upstream in upstreams {
+        config.add_name_server(NameServerConfig::new(*upstream, Protocol::Udp));
+    }
+    TokioAsyncResolver::tokio(config, resolver_opts(ResolverOpts::default()))
+}
+
+/// The upstream resolver, set up with the system config if [init_resolver] hasn't been called
+pub(crate) fn resolver() -> &'static TokioAsyncResolver {
+    ALIAS_RESOLVER.get_or_init(|| build_resolver(&[]))
+}
+
+/// Look up the ALIAS target and turn its addresses into records for `rrtype`, which needs to be A or AAAA.
+///
+/// The TTL served is the lower of the ALIAS record's TTL and what's left on the upstream answer, so we don't hand out addresses for longer than upstream said they're good for.
+pub async fn flatten(
+    alias: &InternalResourceRecord,
+    rrtype: RecordType,
+) -> Result<Vec<InternalResourceRecord>, GoatNsError> {
+    let (target, alias_ttl, rclass) = match alias {
+        InternalResourceRecord::ALIAS {
+            target,
+            ttl,
+            rclass,
+        } => (target.name.as_str(), *ttl, *rclass),
+        _ => {
+            return Err(GoatNsError::InvalidValue(format!(
+                "Can't flatten a non-ALIAS record: {alias:?}"
+            )))
+        }
+    };
+    // make sure the upstream doesn't go looking in a search domain
+    let target = match target.ends_with('.') {
+        true => target.to_string(),
+        false => format!("{target}."),
+    };
+
+    match rrtype {
+        RecordType::A => {
+            let lookup = resolver()
+                .ipv4_lookup(target.as_str())
+                .await
+                .map_err(|err| {
+                    GoatNsError::Generic(format!(
+                        "Failed to resolve ALIAS target {target}: {err:?}"
+                    ))
+                })?;
+            let ttl = flattened_ttl(alias_ttl, lookup.valid_until());
+            Ok(lookup
+                .iter()
+                .map(|address| InternalResourceRecord::A {
+                    address: address.0.into(),
+                    ttl,
+                    rclass,
+                })
+                .collect())
+        }
+        RecordType::AAAA => {
+            let lookup = resolver()
+                .ipv6_lookup(target.as_str())
+                .await
+                .map_err(|err| {
+                    GoatNsError::Generic(format!(
+                        "Failed to resolve ALIAS target {target}: {err:?}"
+                    ))
+                })?;
+            let ttl = flattened_ttl(alias_ttl, lookup.valid_until());
+            Ok(lookup
+                .iter()
+                .map(|address| InternalResourceRecord::AAAA {
+                    address: address.0.into(),
+                    ttl,
+                    rclass,
+                })
+                .collect())
+        }
+        _ => Err(GoatNsError::InvalidValue(format!(
+            "ALIAS records only flatten to A or AAAA, not {rrtype}"
+        ))),
+    }
+}
+
+/// Should we try flattening an ALIAS for this query?
+pub fn flattens_to(rrtype: RecordType, rclass: RecordClass) -> bool {
+    rclass == RecordClass::Internet && matches!(rrtype, RecordType::A | RecordType::AAAA)
+}
+
+fn flattened_ttl(alias_ttl: u32, valid_until: Instant) -> u32 {
+    let remaining = valid_until
+        .saturating_duration_since(Instant::now())
+        .as_secs();
+    alias_ttl.min(u32::try_from(remaining).unwrap_or(u32::MAX))
+}
@@ -0,0 +1,140 @@
+//! Backups of every zone and who owns them, from `--export-all`, `GET /api/admin/export` and the like
+//!
+//! A backup's either one JSON file in the zone schema (with the owners in it), or a BIND zone file for each zone with
+//! the owners next to them in `owners.json`. Either can be put back with `--restore` or `POST /api/admin/restore`.
+//! They can also be made on a schedule, see [scheduled].
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::GoatNsError;
+use crate::interop::{from_bind, to_bind, ZoneFileFormat};
+use crate::zone_schema::{backup_from_json, backup_to_json, ZoneBackup};
+
+pub mod s3;
+pub mod scheduled;
+
+/// The file a JSON backup's written to
+pub const BACKUP_FILENAME: &str = "zones.json";
+/// Where the owners go when the zones are in zone files
+pub const OWNERS_FILENAME: &str = "owners.json";
+/// The extension zone files get in a backup
+pub const ZONE_FILE_EXTENSION: &str = "zone";
+
+/// The zone file a zone's written to, the root zone doesn't have a name so it gets one
+pub fn zone_filename(name: &str) -> String {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let name = match name.is_empty() {
+        true => "root".to_string(),
+        false => name.replace(['/', '\\'], "_"),
+    };
+    format!("{name}.{ZONE_FILE_EXTENSION}")
+}
+
+/// The files that make up a backup, filename -> contents
+pub fn backup_files(
+    backup: &ZoneBackup,
+    format: ZoneFileFormat,
+) -> Result<BTreeMap<String, String>, GoatNsError> {
+    let mut files = BTreeMap::new();
+    match format {
+        ZoneFileFormat::GoatNs => {
+            files.insert(BACKUP_FILENAME.to_string(), backup_to_json(backup)?);
+        }
+        ZoneFileFormat::Bind => {
+            for zone in backup.zones.iter() {
+                let filename = zone_filename(&zone.name);
+                if files.insert(filename.clone(), to_bind(zone)?).is_some() {
+                    return Err(GoatNsError::InvalidValue(format!(
+                        "More than one zone would be written to {filename}"
+                    )));
+                }
+            }
+            files.insert(
+                OWNERS_FILENAME.to_string(),
+                serde_json::to_string_pretty(&backup.owners).map_err(|err| {
+                    GoatNsError::InvalidValue(format!("Failed to serialize owners: {err:?}"))
+                })?,
+            );
+        }
+        other => {
+            return Err(GoatNsError::InvalidValue(format!(
+                "Backups can't be written in {other} format, use goatns or bind"
+            )))
+        }
+    }
+    Ok(files)
+}
+
+/// Write a backup to a directory, making it if it's not there. Each file's written next to where it's going then
+/// moved into place, so there's never half a file. Returns the files written.
+pub fn write_backup(
+    directory: &Path,
+    backup: &ZoneBackup,
+    format: ZoneFileFormat,
+) -> Result<Vec<PathBuf>, GoatNsError> {
+    fs::create_dir_all(directory)?;
+    let mut written = vec![];
+    for (filename, contents) in backup_files(backup, format)? {
+        let path = directory.join(&filename);
+        let partial = directory.join(format!(".{filename}.partial"));
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, &path)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Turn the files from [backup_files] back into a backup
+pub fn backup_from_files(files: &BTreeMap<String, String>) -> Result<ZoneBackup, GoatNsError> {
+    if let Some(json) = files.get(BACKUP_FILENAME) {
+        return backup_from_json(json);
+    }
+    let mut backup = ZoneBackup::default();
+    for (filename, contents) in files.iter() {
+        if !filename.ends_with(&format!(".{ZONE_FILE_EXTENSION}")) {
+            continue;
+        }
+        let zone = from_bind(contents, None)
+            .map_err(|err| GoatNsError::FileError(format!("Failed to read {filename}: {err:?}")))?;
+        backup.zones.push(zone);
+    }
+    if backup.zones.is_empty() {
+        return Err(GoatNsError::FileError(format!(
+            "The backup doesn't have a {BACKUP_FILENAME} or any .{ZONE_FILE_EXTENSION} files in it"
+        )));
+    }
+    if let Some(owners) = files.get(OWNERS_FILENAME) {
+        backup.owners = serde_json::from_str(owners).map_err(|err| {
+            GoatNsError::FileError(format!("Failed to read {OWNERS_FILENAME}: {err}"))
+        })?;
+    }
+    Ok(backup)
+}
+
+/// Read a backup from a JSON file, or a directory [write_backup] wrote to
+pub fn read_backup(path: &Path) -> Result<ZoneBackup, GoatNsError> {
+    if !path.is_dir() {
+        return backup_from_json(&fs::read_to_string(path)?);
+    }
+    let mut files = BTreeMap::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let Ok(filename) = entry.file_name().into_string() else {
+            continue;
+        };
+        if filename == BACKUP_FILENAME
+            || filename == OWNERS_FILENAME
+            || filename.ends_with(&format!(".{ZONE_FILE_EXTENSION}"))
+        {
+            files.insert(filename, fs::read_to_string(entry.path())?);
+        }
+    }
+    backup_from_files(&files).map_err(|err| match err {
+        GoatNsError::FileError(message) => {
+            GoatNsError::FileError(format!("{}: {message}", path.display()))
+        }
+        err => err,
+    })
+}
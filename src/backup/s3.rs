@@ -0,0 +1,269 @@
+//! Just enough of the S3 API to keep backups in a bucket - putting, listing and deleting objects - signed with AWS
+//! Signature Version 4 so it works with AWS and the S3-compatible services.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use regex::Regex;
+use reqwest::Method;
+use sha2::{Digest, Sha256};
+
+use crate::config::S3BackupConfig;
+use crate::error::GoatNsError;
+
+/// How long a request can take, backups can be big
+const S3_TIMEOUT: Duration = Duration::from_secs(300);
+
+const HMAC_BLOCK_SIZE: usize = 64;
+
+/// HMAC-SHA256 (RFC2104)
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; HMAC_BLOCK_SIZE];
+    if key.len() > HMAC_BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|byte| byte ^ 0x36));
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/// Percent-encode everything but the unreserved characters, and `/` too if it's not a path
+pub fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut res = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                res.push(byte as char)
+            }
+            b'/' if !encode_slash => res.push('/'),
+            _ => res.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    res
+}
+
+/// The query string, encoded and sorted the way it's signed (so it can be sent that way too)
+pub fn canonical_query(query: &[(&str, &str)]) -> String {
+    let mut query: Vec<(String, String)> = query
+        .iter()
+        .map(|(key, value)| (uri_encode(key, true), uri_encode(value, true)))
+        .collect();
+    query.sort();
+    query
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// A request to be signed, the path's already encoded and the header names are lowercase
+pub struct SigV4Request<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub query: &'a [(&'a str, &'a str)],
+    pub headers: &'a [(&'a str, &'a str)],
+    /// Hex SHA256 of the body
+    pub payload_hash: &'a str,
+    /// eg `20130524T000000Z`
+    pub amz_date: &'a str,
+}
+
+/// The `Authorization` header for a request
+pub fn sigv4_authorization(
+    request: &SigV4Request,
+    region: &str,
+    access_key_id: &str,
+    secret_access_key: &str,
+) -> String {
+    let query = canonical_query(request.query);
+    let mut headers: Vec<(&str, &str)> = request
+        .headers
+        .iter()
+        .map(|(name, value)| (*name, value.trim()))
+        .collect();
+    headers.sort();
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{value}\n"))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let canonical_request = format!(
+        "{}\n{}\n{query}\n{canonical_headers}\n{signed_headers}\n{}",
+        request.method, request.path, request.payload_hash
+    );
+    let date = request.amz_date.get(..8).unwrap_or_default();
+    let scope = format!("{date}/{region}/s3/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{scope}\n{:x}",
+        request.amz_date,
+        Sha256::digest(canonical_request.as_bytes())
+    );
+
+    let key = hmac_sha256(
+        format!("AWS4{secret_access_key}").as_bytes(),
+        date.as_bytes(),
+    );
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, b"s3");
+    let key = hmac_sha256(&key, b"aws4_request");
+    let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+    format!("AWS4-HMAC-SHA256 Credential={access_key_id}/{scope}, SignedHeaders={signed_headers}, Signature={signature}")
+}
+
+/// Talks to one bucket
+pub struct S3Client {
+    config: S3BackupConfig,
+    client: reqwest::Client,
+}
+
+impl S3Client {
+    pub fn new(config: &S3BackupConfig) -> Self {
+        Self {
+            config: config.clone(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// The prefix backups are kept under
+    pub fn prefix(&self) -> &str {
+        &self.config.prefix
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response, GoatNsError> {
+        let endpoint = &self.config.endpoint;
+        let host = match (endpoint.host_str(), endpoint.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_string(),
+            (None, _) => {
+                return Err(GoatNsError::InvalidValue(format!(
+                    "S3 endpoint {endpoint} doesn't have a host"
+                )))
+            }
+        };
+        let mut path = format!(
+            "{}/{}",
+            endpoint.path().trim_end_matches('/'),
+            uri_encode(&self.config.bucket, true),
+        );
+        // listing's done on the bucket itself
+        if !key.is_empty() {
+            path = format!("{path}/{}", uri_encode(key, false));
+        }
+        let payload_hash = format!("{:x}", Sha256::digest(&body));
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = sigv4_authorization(
+            &SigV4Request {
+                method: method.as_str(),
+                path: &path,
+                query,
+                headers: &[
+                    ("host", host.as_str()),
+                    ("x-amz-content-sha256", payload_hash.as_str()),
+                    ("x-amz-date", amz_date.as_str()),
+                ],
+                payload_hash: &payload_hash,
+                amz_date: &amz_date,
+            },
+            &self.config.region,
+            &self.config.access_key_id,
+            &self.config.secret_access_key,
+        );
+
+        let mut url = endpoint.clone();
+        url.set_path(&path);
+        let query_string = canonical_query(query);
+        url.set_query((!query.is_empty()).then_some(query_string.as_str()));
+        let response = self
+            .client
+            .request(method.clone(), url)
+            .timeout(S3_TIMEOUT)
+            .header("x-amz-content-sha256", &payload_hash)
+            .header("x-amz-date", &amz_date)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(GoatNsError::Generic(format!(
+                "S3 {method} of {key:?} failed with {status}: {text}"
+            )));
+        }
+        Ok(response)
+    }
+
+    /// Upload an object
+    pub async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), GoatNsError> {
+        self.send(Method::PUT, key, &[], body).await.map(|_| ())
+    }
+
+    /// Delete an object
+    pub async fn delete(&self, key: &str) -> Result<(), GoatNsError> {
+        self.send(Method::DELETE, key, &[], vec![])
+            .await
+            .map(|_| ())
+    }
+
+    /// The keys of every object starting with `prefix`
+    pub async fn list(&self, prefix: &str) -> Result<Vec<String>, GoatNsError> {
+        let key_regex = Regex::new("<Key>([^<]*)</Key>")?;
+        let token_regex = Regex::new("<NextContinuationToken>([^<]*)</NextContinuationToken>")?;
+        let mut keys = vec![];
+        let mut token: Option<String> = None;
+        loop {
+            let body = {
+                let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+                if let Some(token) = token.as_deref() {
+                    query.push(("continuation-token", token));
+                }
+                self.send(Method::GET, "", &query, vec![])
+                    .await?
+                    .text()
+                    .await?
+            };
+            keys.extend(
+                key_regex
+                    .captures_iter(&body)
+                    .filter_map(|captures| captures.get(1))
+                    .map(|key| unescape_xml(key.as_str())),
+            );
+            match token_regex
+                .captures(&body)
+                .and_then(|captures| captures.get(1))
+            {
+                Some(next) => token = Some(unescape_xml(next.as_str())),
+                None => break,
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// The entities S3 uses in its listings
+fn unescape_xml(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
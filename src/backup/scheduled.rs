@@ -0,0 +1,186 @@
+//! Backing up on a schedule, see [crate::config::BackupConfig]
+//!
+//! Each backup's named for when it was made (eg `goatns-20240101T000000Z.sqlite`, or a directory of that name for
+//! exports) which is how the old ones are found for pruning. Anything that isn't named like that is left alone.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use super::s3::S3Client;
+use super::write_backup;
+use crate::config::{BackupConfig, BackupFormat};
+use crate::datastore::ZoneStore;
+use crate::error::GoatNsError;
+use crate::interop::ZoneFileFormat;
+use crate::metrics::{Metrics, METRICS};
+
+/// What every backup's name starts with
+pub const BACKUP_NAME_PREFIX: &str = "goatns-";
+const BACKUP_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const SQLITE_SUFFIX: &str = ".sqlite";
+
+/// What a backup made at `time` is called
+pub fn backup_name(time: DateTime<Utc>, format: BackupFormat) -> String {
+    let name = format!("{BACKUP_NAME_PREFIX}{}", time.format(BACKUP_TIME_FORMAT));
+    match format {
+        BackupFormat::Sqlite => format!("{name}{SQLITE_SUFFIX}"),
+        BackupFormat::GoatNs | BackupFormat::Bind => name,
+    }
+}
+
+/// When a backup was made, going by its name. [None] if it's not one of ours.
+pub fn backup_time(name: &str) -> Option<NaiveDateTime> {
+    let time = name.strip_prefix(BACKUP_NAME_PREFIX)?;
+    let time = time.strip_suffix(SQLITE_SUFFIX).unwrap_or(time);
+    NaiveDateTime::parse_from_str(time, BACKUP_TIME_FORMAT).ok()
+}
+
+/// The backups that aren't in the newest `keep`, oldest first
+pub fn expired_backups(names: impl IntoIterator<Item = String>, keep: usize) -> Vec<String> {
+    let mut backups: Vec<(NaiveDateTime, String)> = names
+        .into_iter()
+        .filter_map(|name| backup_time(&name).map(|time| (time, name)))
+        .collect();
+    backups.sort();
+    let expired = backups.len().saturating_sub(keep);
+    backups
+        .into_iter()
+        .take(expired)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// Delete all but the newest `keep` backups in a directory, returns what was deleted
+pub fn prune_directory(directory: &Path, keep: usize) -> Result<Vec<String>, GoatNsError> {
+    let names: Vec<String> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    let expired = expired_backups(names, keep);
+    for name in expired.iter() {
+        let path = directory.join(name);
+        match path.is_dir() {
+            true => fs::remove_dir_all(&path)?,
+            false => fs::remove_file(&path)?,
+        }
+    }
+    Ok(expired)
+}
+
+/// Delete all but the newest `keep` backups in the bucket, returns what was deleted
+async fn prune_bucket(client: &S3Client, keep: usize) -> Result<Vec<String>, GoatNsError> {
+    let keys = client.list(client.prefix()).await?;
+    // exports are a few objects under the backup's name
+    let name_of = |key: &str| {
+        key.strip_prefix(client.prefix())
+            .and_then(|key| key.split('/').next())
+            .map(str::to_string)
+    };
+    let names: BTreeSet<String> = keys.iter().filter_map(|key| name_of(key)).collect();
+    let expired = expired_backups(names, keep);
+    for key in keys.iter() {
+        if name_of(key).is_some_and(|name| expired.contains(&name)) {
+            client.delete(key).await?;
+        }
+    }
+    Ok(expired)
+}
+
+/// Upload a backup, the files in it go under its name if it's a directory
+async fn upload(client: &S3Client, name: &str, path: &Path) -> Result<(), GoatNsError> {
+    if !path.is_dir() {
+        return client
+            .put(&format!("{}{name}", client.prefix()), fs::read(path)?)
+            .await;
+    }
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let key = format!(
+            "{}{name}/{}",
+            client.prefix(),
+            entry.file_name().to_string_lossy()
+        );
+        client.put(&key, fs::read(entry.path())?).await?;
+    }
+    Ok(())
+}
+
+/// Make a backup, upload it if there's a bucket and prune the old ones. Returns the backup's name.
+pub async fn run_backup<S: ZoneStore>(
+    store: &S,
+    config: &BackupConfig,
+) -> Result<String, GoatNsError> {
+    let name = backup_name(Utc::now(), config.format);
+    // without a directory it's made somewhere temporary, and that's cleaned up once it's uploaded
+    let directory = match &config.directory {
+        Some(directory) => directory.clone(),
+        None => std::env::temp_dir().join(format!("goatns-backups-{}", std::process::id())),
+    };
+    fs::create_dir_all(&directory)?;
+    let path = directory.join(&name);
+
+    match config.format {
+        BackupFormat::Sqlite => {
+            // so a failed snapshot isn't mistaken for a backup
+            let partial = directory.join(format!(".{name}.partial"));
+            if partial.exists() {
+                fs::remove_file(&partial)?;
+            }
+            store.snapshot(&partial).await?;
+            fs::rename(&partial, &path)?;
+        }
+        BackupFormat::GoatNs | BackupFormat::Bind => {
+            let format = match config.format {
+                BackupFormat::Bind => ZoneFileFormat::Bind,
+                _ => ZoneFileFormat::GoatNs,
+            };
+            write_backup(&path, &store.export_all().await?, format)?;
+        }
+    }
+
+    if config.directory.is_some() {
+        let pruned = prune_directory(&directory, config.keep)?;
+        if !pruned.is_empty() {
+            log::info!(
+                "Pruned old backups from {}: {pruned:?}",
+                directory.display()
+            );
+        }
+    }
+    if let Some(s3) = &config.s3 {
+        let client = S3Client::new(s3);
+        let uploaded = upload(&client, &name, &path).await;
+        if config.directory.is_none() {
+            match path.is_dir() {
+                true => fs::remove_dir_all(&path)?,
+                false => fs::remove_file(&path)?,
+            }
+        }
+        uploaded?;
+        let pruned = prune_bucket(&client, config.keep).await?;
+        if !pruned.is_empty() {
+            log::info!("Pruned old backups from the bucket: {pruned:?}");
+        }
+    }
+    Ok(name)
+}
+
+/// [run_backup], with how it went logged and in the metrics
+pub async fn scheduled_backup<S: ZoneStore>(store: &S, config: &BackupConfig) {
+    match run_backup(store, config).await {
+        Ok(name) => {
+            log::info!("Made backup {name}");
+            Metrics::set(
+                &METRICS.backup_last_success,
+                Utc::now().timestamp().max(0) as u64,
+            );
+        }
+        Err(err) => {
+            log::error!("Scheduled backup failed: {err:?}");
+            Metrics::increment(&METRICS.backups_failed);
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Answers that don't come from any zone, see [BuiltinZones]
+//!
+//! - `id.server` and `hostname.bind` TXT queries in the CHAOS class say which server answered, which is handy when
+//!   there's a few of them behind the one address ([RFC4892](https://www.rfc-editor.org/rfc/rfc4892)).
+//! - The reverse zones for private and link-local IPv4 space are answered with NXDOMAIN the way the AS112 servers do
+//!   ([RFC6304](https://www.rfc-editor.org/rfc/rfc6304)), so lookups for them don't go any further. Zones in the
+//!   datastore still win, so your own `168.192.in-addr.arpa` works.
+//! - `status.goatns.internal` TXT queries get the version, uptime and query counts, so you can keep an eye on it with
+//!   `dig`. Only localhost and the `status_clients` get an answer, everyone else is refused.
+
+use std::net::IpAddr;
+use std::sync::{OnceLock, RwLock};
+use std::time::Instant;
+
+use crate::config::BuiltinZones;
+use crate::enums::{RecordClass, RecordType};
+use crate::metrics::{Metrics, METRICS};
+use crate::resourcerecord::{DNSCharString, DomainName, InternalResourceRecord};
+use crate::zones::{NegativeAnswer, ZoneRecord};
+use crate::Question;
+
+/// The names the server's identity is answered for
+pub const SERVER_ID_NAMES: [&str; 2] = ["id.server", "hostname.bind"];
+
+/// Where the server's status is answered
+pub const STATUS_ZONE: &str = "status.goatns.internal";
+
+/// The AS112 zones' SOA values, from [RFC7534](https://www.rfc-editor.org/rfc/rfc7534) section 3.2.1
+const AS112_MNAME: &str = "prisoner.iana.org";
+const AS112_RNAME: &str = "hostmaster.root-servers.org";
+const AS112_TIMER: u32 = 604800;
+
+static BUILTIN: RwLock<BuiltinZones> = RwLock::new(BuiltinZones {
+    server_id: None,
+    as112: false,
+    status: false,
+    status_clients: Vec::new(),
+});
+
+/// When the server started, for the uptime in the status
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// Set what's answered, from the config at startup
+pub fn init(builtin: &BuiltinZones) {
+    STARTED.get_or_init(Instant::now);
+    match BUILTIN.write() {
+        Ok(mut current) => *current = builtin.clone(),
+        Err(err) => log::error!("Failed to set up the built-in zones: {err:?}"),
+    }
+}
+
+/// What's being answered right now
+pub fn current() -> BuiltinZones {
+    BUILTIN
+        .read()
+        .map(|builtin| builtin.clone())
+        .unwrap_or_default()
+}
+
+/// The reverse zones AS112 answers for: 10/8, 172.16/12, 192.168/16 and 169.254/16
+pub fn as112_zones() -> Vec<String> {
+    let mut zones = vec!["10.in-addr.arpa".to_string()];
+    zones.extend((16..=31).map(|octet| format!("{octet}.172.in-addr.arpa")));
+    zones.push("168.192.in-addr.arpa".to_string());
+    zones.push("254.169.in-addr.arpa".to_string());
+    zones
+}
+
+/// The server's identity, if it's set and that's what they're asking for
+pub fn server_id_answer(builtin: &BuiltinZones, question: &Question) -> Option<ZoneRecord> {
+    let server_id = builtin.server_id.as_ref()?;
+    if question.qclass != RecordClass::Chaos
+        || !matches!(question.qtype, RecordType::TXT | RecordType::ANY)
+    {
+        return None;
+    }
+    let name = question.lookup_name();
+    let qname = std::str::from_utf8(&name).ok()?;
+    if !SERVER_ID_NAMES.contains(&qname.trim_end_matches('.')) {
+        return None;
+    }
+    Some(ZoneRecord {
+        name,
+        typerecords: vec![InternalResourceRecord::TXT {
+            txtdata: vec![DNSCharString::from(server_id.as_str())],
+            ttl: 0,
+            class: RecordClass::Chaos,
+        }],
+    })
+}
+
+/// If AS112 answers are on and the name's in one of [as112_zones], that zone's empty. It exists at the apex, which
+/// only has the SOA.
+pub fn as112_answer(builtin: &BuiltinZones, question: &Question) -> Option<NegativeAnswer> {
+    if !builtin.as112 || question.qclass != RecordClass::Internet {
+        return None;
+    }
+    let name = question.lookup_name();
+    let qname = std::str::from_utf8(&name).ok()?.trim_end_matches('.');
+    let zone = as112_zones().into_iter().find(|zone| {
+        qname == zone
+            || qname
+                .strip_suffix(zone.as_str())
+                .is_some_and(|rest| rest.ends_with('.'))
+    })?;
+    Some(NegativeAnswer {
+        name_exists: qname == zone,
+        zone_enabled: true,
+        soa: InternalResourceRecord::SOA {
+            zone: DomainName::from(zone.as_str()),
+            mname: DomainName::from(AS112_MNAME),
+            rname: DomainName::from(AS112_RNAME),
+            serial: 1,
+            refresh: AS112_TIMER,
+            retry: 60,
+            expire: AS112_TIMER,
+            minimum: AS112_TIMER,
+            rclass: RecordClass::Internet,
+        },
+        zone: zone.into_bytes(),
+    })
+}
+
+/// What's in the status, as `(name, value)`. Each one's also answered on its own at `{name}.status.goatns.internal`.
+pub fn status_values() -> Vec<(&'static str, String)> {
+    let uptime = STARTED
+        .get()
+        .map(|started| started.elapsed().as_secs())
+        .unwrap_or_default();
+    vec![
+        ("version", env!("CARGO_PKG_VERSION").to_string()),
+        ("uptime", uptime.to_string()),
+        (
+            "queries.udp",
+            Metrics::get(&METRICS.queries_udp).to_string(),
+        ),
+        (
+            "queries.tcp",
+            Metrics::get(&METRICS.queries_tcp).to_string(),
+        ),
+        (
+            "queries.doh",
+            Metrics::get(&METRICS.queries_doh).to_string(),
+        ),
+    ]
+}
+
+/// The server's status as TXT records of `name=value`, if it's on and that's what they're asking for. Check
+/// [status_allowed] before sending it.
+pub fn status_answer(builtin: &BuiltinZones, question: &Question) -> Option<ZoneRecord> {
+    if !builtin.status
+        || question.qclass != RecordClass::Internet
+        || !matches!(question.qtype, RecordType::TXT | RecordType::ANY)
+    {
+        return None;
+    }
+    let name = question.lookup_name();
+    let qname = std::str::from_utf8(&name).ok()?.trim_end_matches('.');
+    let wanted = match qname == STATUS_ZONE {
+        true => None,
+        false => Some(qname.strip_suffix(STATUS_ZONE)?.strip_suffix('.')?),
+    };
+    let typerecords: Vec<InternalResourceRecord> = status_values()
+        .into_iter()
+        .filter(|(key, _)| wanted.is_none() || wanted == Some(*key))
+        .map(|(key, value)| InternalResourceRecord::TXT {
+            txtdata: vec![DNSCharString::from(format!("{key}={value}").as_str())],
+            ttl: 0,
+            class: RecordClass::Internet,
+        })
+        .collect();
+    if typerecords.is_empty() {
+        return None;
+    }
+    Some(ZoneRecord { name, typerecords })
+}
+
+/// Can this client see the status? Localhost always can, and clients we don't know the address of can't.
+pub fn status_allowed(builtin: &BuiltinZones, client: Option<IpAddr>) -> bool {
+    let Some(client) = client.map(|client| client.to_canonical()) else {
+        return false;
+    };
+    client.is_loopback()
+        || builtin
+            .status_clients
+            .iter()
+            .any(|net| net.contains(&client))
+}
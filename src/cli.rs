@@ -1,6 +1,12 @@
 //! Code related to CLI things
 //!
 
+use std::io::{IsTerminal, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
 use clap::{arg, command, value_parser, Arg, ArgMatches};
 use dialoguer::theme::ColorfulTheme;
 use dialoguer::{Confirm, Input};
@@ -8,10 +14,14 @@ use tokio::io::AsyncWriteExt;
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::sleep;
 
-use crate::config::ConfigFile;
-use crate::datastore::Command;
+use crate::backup::{read_backup, write_backup};
+use crate::config::{ConfigFile, ImportTtlPolicy};
+use crate::datastore::{Command, Queued};
 use crate::enums::SystemState;
-use crate::zones::FileZone;
+use crate::interop::{export_zone, ZoneFileFormat};
+use crate::zones::{
+    FileZone, ImportProgress, ImportProgressCallback, ImportStrategy, ImportSummary,
+};
 
 /// Handles the command-line arguments.
 pub fn clap_parser() -> ArgMatches {
@@ -43,11 +53,37 @@ pub fn clap_parser() -> ArgMatches {
                 .help("Export a single zone.")
                 .value_parser(value_parser!(String)),
         )
+        .arg(
+            Arg::new("export_all")
+                .long("export-all")
+                .help("Back up every zone and who owns them to the --output directory.")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .help("Directory to write backups to.")
+                .value_parser(value_parser!(String)),
+        )
+        .arg(
+            Arg::new("restore")
+                .long("restore")
+                .help("Restore a backup from --export-all, either the directory or its zones.json.")
+                .value_parser(value_parser!(String)),
+        )
         .arg(
             Arg::new("import_zones")
                 .short('i')
                 .long("import-zones")
-                .help("Import a single zone file.")
+                .help("Import the zones in --filename, which can be - for stdin or a zip, tar or tar.gz of zone files.")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("zone_name_from_filename")
+                .long("zone-name-from-filename")
+                .help("Name each file's zone after the file, eg example.com.zone is example.com.")
+                .requires("import_zones")
                 .action(clap::ArgAction::SetTrue),
         )
         .arg(
@@ -63,12 +99,103 @@ pub fn clap_parser() -> ArgMatches {
                 .help("Filename to save to (used in other commands).")
                 .value_parser(value_parser!(String)),
         )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .help("File format for importing and exporting zones.")
+                .value_parser(["goatns", "cloudflare", "route53", "bind"])
+                .default_value("goatns"),
+        )
+        .arg(
+            Arg::new("strategy")
+                .long("strategy")
+                .help("How to import zones that already exist.")
+                .value_parser(["replace", "merge", "skip-existing"])
+                .default_value("replace"),
+        )
+        .arg(
+            Arg::new("dry_run")
+                .long("dry-run")
+                .help("Show what an import would do without saving anything.")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("add_admin")
                 .long("add-admin")
                 .help("Add a new admin user.")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("trace_qname")
+                .long("trace-qname")
+                .help("Trace queries for names matching this regex, see /api/admin/trace.")
+                .value_parser(value_parser!(String)),
+        )
+        .arg(
+            Arg::new("loadtest")
+                .long("loadtest")
+                .help("Flood a DNS server with UDP queries and report latency and errors, then quit.")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("loadtest_target")
+                .long("loadtest-target")
+                .help("Server to load test, defaults to the one in the config.")
+                .value_parser(value_parser!(SocketAddr)),
+        )
+        .arg(
+            Arg::new("loadtest_qps")
+                .long("loadtest-qps")
+                .help("Queries per second to send.")
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::new("loadtest_duration")
+                .long("loadtest-duration")
+                .help("How many seconds to run the load test for.")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("10"),
+        )
+        .arg(
+            Arg::new("loadtest_names")
+                .long("loadtest-names")
+                .help("Names to query, like example.com:A:3,www.example.com:AAAA (name:type:weight).")
+                .value_parser(value_parser!(String))
+                .default_value(crate::loadtest::DEFAULT_NAME_MIX),
+        )
+        .arg(
+            Arg::new("loadtest_max_p99_ms")
+                .long("loadtest-max-p99-ms")
+                .help("Fail the load test if p99 latency is over this many milliseconds.")
+                .value_parser(value_parser!(f64)),
+        )
+        .arg(
+            Arg::new("loadtest_max_error_rate")
+                .long("loadtest-max-error-rate")
+                .help("Fail the load test if more than this fraction (0-1) of queries time out or error.")
+                .value_parser(value_parser!(f64)),
+        )
+        .arg(
+            Arg::new("doctor")
+                .long("doctor")
+                .help("Check a running server's DNS, DoH, TLS certificate, database and zone file, then quit.")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("doctor_name")
+                .long("doctor-name")
+                .help("Name for --doctor to look up, like www.example.com:AAAA. Defaults to the healthcheck name's A record.")
+                .requires("doctor")
+                .value_parser(value_parser!(String)),
+        )
+        .arg(
+            Arg::new("doctor_json")
+                .long("doctor-json")
+                .help("Print the --doctor report as JSON.")
+                .requires("doctor")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("use_zonefile")
                 .long("using-zonefile")
@@ -80,9 +207,9 @@ pub fn clap_parser() -> ArgMatches {
 
 /// Turns the clap inputs into actions.
 pub async fn cli_commands(
-    tx: mpsc::Sender<Command>,
+    tx: mpsc::Sender<Queued>,
     clap_results: &ArgMatches,
-    zone_file: &Option<String>,
+    config: &ConfigFile,
 ) -> Result<SystemState, String> {
     if clap_results.get_flag("add_admin") {
         let _ = add_admin_user(tx).await;
@@ -93,10 +220,32 @@ pub async fn cli_commands(
         return Ok(SystemState::ShuttingDown);
     }
 
+    let format = match clap_results.get_one::<String>("format") {
+        Some(val) => ZoneFileFormat::from_str(val).map_err(|err| format!("{err:?}"))?,
+        None => ZoneFileFormat::default(),
+    };
+    let strategy = match clap_results.get_one::<String>("strategy") {
+        Some(val) => ImportStrategy::from_str(val).map_err(|err| format!("{err:?}"))?,
+        None => ImportStrategy::default(),
+    };
+    let dry_run = clap_results.get_flag("dry_run");
+
     // Load the specified zone file on startup
     if clap_results.get_flag("use_zonefile") {
-        if let Some(zone_file) = zone_file {
-            if let Err(error) = import_zones(tx.clone(), zone_file.to_owned(), None).await {
+        if let Some(zone_file) = &config.zone_file {
+            if let Err(error) = import_zones(
+                tx.clone(),
+                zone_file.to_owned(),
+                None,
+                false,
+                ZoneFileFormat::GoatNs,
+                config.import_ttl.clone(),
+                ImportStrategy::Replace,
+                false,
+                None,
+            )
+            .await
+            {
                 log::error!("Failed to import zone file! {error:?}");
                 return Ok(SystemState::ShuttingDown);
             }
@@ -105,8 +254,8 @@ pub async fn cli_commands(
 
     if let Some(zone_name) = clap_results.get_one::<String>("export_zone") {
         if let Some(output_filename) = clap_results.get_one::<String>("filename") {
-            log::info!("Exporting zone {zone_name} to {output_filename}");
-            let res = export_zone_file(tx, zone_name, output_filename).await;
+            log::info!("Exporting zone {zone_name} to {output_filename} as {format}");
+            let res = export_zone_file(tx, zone_name, output_filename, format).await;
             if let Err(err) = res {
                 log::error!("{err}");
             }
@@ -117,12 +266,45 @@ pub async fn cli_commands(
         }
     };
 
+    if clap_results.get_flag("export_all") {
+        if let Some(directory) = clap_results.get_one::<String>("output") {
+            log::info!("Backing up every zone to {directory} as {format}");
+            if let Err(err) = export_all_zones(tx, directory, format).await {
+                log::error!("{err}");
+            }
+            return Ok(SystemState::Export);
+        } else {
+            log::error!("You need to specify a directory to save to with --output.");
+            return Ok(SystemState::ShuttingDown);
+        }
+    }
+
+    if let Some(path) = clap_results.get_one::<String>("restore") {
+        log::info!("Restoring backup from {path} with strategy {strategy}");
+        let summary = restore_backup(tx, path, config.import_ttl.clone(), strategy, dry_run)
+            .await
+            .map_err(|e| format!("Error restoring {path}: {e}"))?;
+        log::info!("Restored {path}: {summary}");
+        return Ok(SystemState::Import);
+    }
+
     if clap_results.get_flag("import_zones") {
         if let Some(filename) = clap_results.get_one::<String>("filename") {
-            log::info!("Importing zones from {filename}");
-            import_zones(tx, filename.to_owned(), None)
-                .await
-                .map_err(|e| format!("Error importing {filename}: {e:?}"))?;
+            log::info!("Importing zones from {filename} as {format} with strategy {strategy}");
+            let summary = import_zones(
+                tx,
+                filename.to_owned(),
+                None,
+                clap_results.get_flag("zone_name_from_filename"),
+                format,
+                config.import_ttl.clone(),
+                strategy,
+                dry_run,
+                import_progress_bar(),
+            )
+            .await
+            .map_err(|e| format!("Error importing {filename}: {e:?}"))?;
+            log::info!("Imported {filename}: {summary}");
 
             return Ok(SystemState::Import);
         } else {
@@ -132,10 +314,23 @@ pub async fn cli_commands(
     };
     if let Some(zone_name) = clap_results.get_one::<String>("import_zone") {
         if let Some(filename) = clap_results.get_one::<String>("filename") {
-            log::info!("Importing zones from {filename}");
-            import_zones(tx, filename.to_owned(), Some(zone_name.to_owned()))
-                .await
-                .map_err(|e| format!("Error importing {filename}: {e:?}"))?;
+            log::info!(
+                "Importing {zone_name} from {filename} as {format} with strategy {strategy}"
+            );
+            let summary = import_zones(
+                tx,
+                filename.to_owned(),
+                Some(zone_name.to_owned()),
+                false,
+                format,
+                config.import_ttl.clone(),
+                strategy,
+                dry_run,
+                import_progress_bar(),
+            )
+            .await
+            .map_err(|e| format!("Error importing {filename}: {e:?}"))?;
+            log::info!("Imported {zone_name} from {filename}: {summary}");
 
             return Ok(SystemState::Import);
         } else {
@@ -143,9 +338,102 @@ pub async fn cli_commands(
             return Ok(SystemState::ShuttingDown);
         }
     };
+    if let Some(pattern) = clap_results.get_one::<String>("trace_qname") {
+        crate::query_trace::enable(pattern, None)
+            .map_err(|err| format!("Couldn't trace queries matching {pattern:?}: {err:?}"))?;
+    }
     Ok(SystemState::Server)
 }
 
+/// Runs `--loadtest` against the configured server (or `--loadtest-target`), fails if it goes over the limits
+pub async fn loadtest(clap_results: &ArgMatches, config: &ConfigFile) -> Result<(), String> {
+    let target = match clap_results.get_one::<SocketAddr>("loadtest_target") {
+        Some(target) => *target,
+        None => {
+            let target = config
+                .dns_listener_address()
+                .map_err(|_| "Couldn't work out the DNS server's address".to_string())?;
+            // listening everywhere means we can reach it locally
+            match target.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => {
+                    SocketAddr::new(Ipv4Addr::LOCALHOST.into(), target.port())
+                }
+                IpAddr::V6(ip) if ip.is_unspecified() => {
+                    SocketAddr::new(Ipv6Addr::LOCALHOST.into(), target.port())
+                }
+                _ => target,
+            }
+        }
+    };
+    let names = crate::loadtest::parse_name_mix(
+        clap_results
+            .get_one::<String>("loadtest_names")
+            .map(String::as_str)
+            .unwrap_or(crate::loadtest::DEFAULT_NAME_MIX),
+    )
+    .map_err(|err| format!("Bad --loadtest-names: {err:?}"))?;
+    let options = crate::loadtest::LoadTestOptions {
+        target,
+        names,
+        qps: clap_results
+            .get_one::<u32>("loadtest_qps")
+            .copied()
+            .unwrap_or(1000),
+        duration: Duration::from_secs(
+            clap_results
+                .get_one::<u64>("loadtest_duration")
+                .copied()
+                .unwrap_or(10),
+        ),
+        max_p99: clap_results
+            .get_one::<f64>("loadtest_max_p99_ms")
+            .map(|ms| Duration::from_secs_f64(ms.max(0.0) / 1000.0)),
+        max_error_rate: clap_results
+            .get_one::<f64>("loadtest_max_error_rate")
+            .copied(),
+        ..Default::default()
+    };
+    log::info!(
+        "Load testing {} at {} qps for {}s",
+        options.target,
+        options.qps,
+        options.duration.as_secs()
+    );
+    let report = crate::loadtest::run(&options)
+        .await
+        .map_err(|err| format!("Load test failed to run: {err:?}"))?;
+    println!("{report}");
+    report
+        .check(&options)
+        .map_err(|errors| format!("Load test failed: {}", errors.join(", ")))
+}
+
+/// Runs `--doctor`, it fails if any of the checks did
+pub async fn doctor(clap_results: &ArgMatches, config: &ConfigFile) -> Result<(), String> {
+    let mut options = crate::doctor::DoctorOptions::new(config);
+    if let Some(question) = clap_results.get_one::<String>("doctor_name") {
+        options = options
+            .with_question(question)
+            .map_err(|err| format!("Bad --doctor-name: {err:?}"))?;
+    }
+    let report = crate::doctor::run(config, &options).await;
+    match clap_results.get_flag("doctor_json") {
+        true => println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "passed": report.passed(),
+                "checks": report.checks,
+            }))
+            .map_err(|err| format!("Failed to serialize the report: {err:?}"))?
+        ),
+        false => println!("{report}"),
+    }
+    match report.passed() {
+        true => Ok(()),
+        false => Err("Some of the doctor's checks failed".to_string()),
+    }
+}
+
 /// Output a default configuration file, based on the [crate::config::ConfigFile] object.
 pub fn default_config() {
     let output = match serde_json::to_string_pretty(&ConfigFile::default()) {
@@ -160,9 +448,10 @@ pub fn default_config() {
 
 /// Dump a zone to a file
 pub async fn export_zone_file(
-    tx: mpsc::Sender<Command>,
+    tx: mpsc::Sender<Queued>,
     zone_name: &String,
     filename: &String,
+    format: ZoneFileFormat,
 ) -> Result<(), String> {
     // make a channel
 
@@ -172,14 +461,15 @@ pub async fn export_zone_file(
         name: Some(zone_name.clone()),
         resp: tx_oneshot,
     };
-    if let Err(error) = tx.send(ds_req).await {
+    if let Err(error) = tx.send(ds_req.into()).await {
         return Err(format!(
             "failed to send to datastore from export_zone_file {error:?}"
         ));
     };
 
     let zone: Option<FileZone> = match rx_oneshot.await {
-        Ok(value) => value,
+        Ok(Ok(value)) => value,
+        Ok(Err(err)) => return Err(format!("datastore failed to get zone {zone_name}: {err:?}")),
         Err(err) => return Err(format!("rx from ds failed {err:?}")),
     };
     eprintln!("Got filezone: {zone:?}");
@@ -189,9 +479,9 @@ pub async fn export_zone_file(
             log::warn!("Couldn't find the zone {zone_name}");
             return Ok(());
         }
-        Some(zone) => serde_json::to_string_pretty(&zone).map_err(|err| {
+        Some(zone) => export_zone(&zone, format).map_err(|err| {
             format!(
-                "Failed to serialize zone {zone_name} to json: {err:?}",
+                "Failed to serialize zone {zone_name} to {format}: {err:?}",
                 zone_name = zone_name,
                 err = err
             )
@@ -211,19 +501,84 @@ pub async fn export_zone_file(
     Ok(())
 }
 
-/// Import zones from a file
+/// Back up every zone and their owners to a directory
+pub async fn export_all_zones(
+    tx: mpsc::Sender<Queued>,
+    directory: &str,
+    format: ZoneFileFormat,
+) -> Result<(), String> {
+    let (tx_oneshot, rx_oneshot) = oneshot::channel();
+    tx.send(Command::ExportAll { resp: tx_oneshot }.into())
+        .await
+        .map_err(|err| format!("failed to send to datastore from export_all_zones {err:?}"))?;
+    let backup = match rx_oneshot.await {
+        Ok(Ok(value)) => value,
+        Ok(Err(err)) => return Err(format!("datastore failed to export zones: {err:?}")),
+        Err(err) => return Err(format!("rx from ds failed {err:?}")),
+    };
+    let written = write_backup(Path::new(directory), &backup, format)
+        .map_err(|err| format!("Failed to write backup to {directory}: {err:?}"))?;
+    log::info!(
+        "Backed up {} zones to {} files in {directory}",
+        backup.zones.len(),
+        written.len()
+    );
+    Ok(())
+}
+
+/// Restore a backup from [export_all_zones], returns what happened to the records
+pub async fn restore_backup(
+    tx: mpsc::Sender<Queued>,
+    path: &str,
+    ttl_policy: ImportTtlPolicy,
+    strategy: ImportStrategy,
+    dry_run: bool,
+) -> Result<ImportSummary, String> {
+    let backup = read_backup(Path::new(path)).map_err(|err| format!("{err:?}"))?;
+    let (tx_oneshot, rx_oneshot) = oneshot::channel();
+    tx.send(
+        Command::RestoreBackup {
+            backup,
+            ttl_policy,
+            strategy,
+            dry_run,
+            resp: tx_oneshot,
+        }
+        .into(),
+    )
+    .await
+    .map_err(|err| format!("failed to send to datastore from restore_backup {err:?}"))?;
+    match rx_oneshot.await {
+        Ok(res) => res.map_err(|err| format!("Failed to restore backup: {err:?}")),
+        Err(_) => Err("Datastore went away before the restore finished".to_string()),
+    }
+}
+
+/// Import zones from a file (or `-` for stdin), returns what happened to the records
 pub async fn import_zones(
-    tx: mpsc::Sender<Command>,
+    tx: mpsc::Sender<Queued>,
     filename: String,
     zone_name: Option<String>,
-) -> Result<(), String> {
+    zone_name_from_filename: bool,
+    format: ZoneFileFormat,
+    ttl_policy: ImportTtlPolicy,
+    strategy: ImportStrategy,
+    dry_run: bool,
+    progress: Option<ImportProgressCallback>,
+) -> Result<ImportSummary, String> {
     let (tx_oneshot, mut rx_oneshot) = oneshot::channel();
     let msg = Command::ImportFile {
         filename,
         resp: tx_oneshot,
         zone_name,
+        zone_name_from_filename,
+        format,
+        ttl_policy,
+        strategy,
+        dry_run,
+        progress,
     };
-    if let Err(err) = tx.send(msg).await {
+    if let Err(err) = tx.send(msg.into()).await {
         log::error!("Failed to send message to datastore: {err:?}");
     }
     loop {
@@ -234,16 +589,53 @@ pub async fn import_zones(
                     break;
                 }
             }
-            Ok(()) => break,
+            Ok(res) => {
+                return res.map_err(|err| format!("Failed to import zones: {err:?}"));
+            }
         };
         sleep(std::time::Duration::from_micros(500)).await;
     }
-    Ok(())
+    Err("Datastore went away before the import finished".to_string())
     // rx_oneshot.await.map_err(|e| format!("Failed to receive result: {e:?}"))
 }
 
+/// How wide the import progress bar is, in characters
+const PROGRESS_BAR_WIDTH: usize = 40;
+
+/// Draws the import progress bar on stderr, if there's someone there to see it
+fn import_progress_bar() -> Option<ImportProgressCallback> {
+    if !std::io::stderr().is_terminal() {
+        return None;
+    }
+    Some(ImportProgressCallback::new(render_import_progress))
+}
+
+fn render_import_progress(progress: &ImportProgress) {
+    // go by records since zones can be wildly different sizes
+    let filled = match progress.records_total {
+        0 => PROGRESS_BAR_WIDTH,
+        total => progress.records_done * PROGRESS_BAR_WIDTH / total,
+    };
+    let mut stderr = std::io::stderr();
+    // it's only a progress bar, if stderr's gone away there's not much to be done about it
+    let _ = write!(
+        stderr,
+        "\r[{}{}] {}/{} zones, {}/{} records",
+        "#".repeat(filled),
+        " ".repeat(PROGRESS_BAR_WIDTH.saturating_sub(filled)),
+        progress.zones_done,
+        progress.zones_total,
+        progress.records_done,
+        progress.records_total,
+    );
+    if progress.zones_done == progress.zones_total {
+        let _ = writeln!(stderr);
+    }
+    let _ = stderr.flush();
+}
+
 /// Presents the CLI UI to add an admin user.
-pub async fn add_admin_user(tx: mpsc::Sender<Command>) -> Result<(), ()> {
+pub async fn add_admin_user(tx: mpsc::Sender<Queued>) -> Result<(), ()> {
     // prompt for the username
     println!("Creating admin user, please enter their username from the identity provider");
     let username: String = Input::with_theme(&ColorfulTheme::default())
@@ -298,19 +690,19 @@ Authref:  {authref}
         resp: tx_oneshot,
     };
     // send command
-    if let Err(error) = tx.send(new_user).await {
+    if let Err(error) = tx.send(new_user.into()).await {
         log::error!("Failed to send new user command for username {username:?}: {error:?}");
         return Err(());
     };
     // wait for the response
     match rx_oneshot.await {
         Ok(res) => match res {
-            true => {
-                log::info!("Successfully created user!");
+            Ok(user) => {
+                log::info!("Successfully created user! id={:?}", user.id);
                 Ok(())
             }
-            false => {
-                log::error!("Failed to create user! Check datastore logs.");
+            Err(err) => {
+                log::error!("Failed to create user: {err:?}");
                 Err(())
             }
         },
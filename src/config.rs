@@ -5,8 +5,10 @@ use config::{Config, File};
 use flexi_logger::filter::{LogLineFilter, LogLineWriter};
 use flexi_logger::{DeferredNow, LoggerHandle};
 use gethostname::gethostname;
+use ipnet::IpNet;
 use rand::distributions::{Alphanumeric, DistString};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::io::ErrorKind;
 use std::net::IpAddr;
@@ -15,7 +17,7 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use url::Url;
 
-use crate::enums::ContactDetails;
+use crate::enums::{ContactDetails, Rcode};
 use crate::error::GoatNsError;
 use crate::web::utils::Urls;
 
@@ -31,25 +33,994 @@ pub struct IPAllowList {
     pub shutdown: Vec<IpAddr>,
 }
 
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// Values applied to newly created zones so they're valid and delegable straight away
+pub struct ZoneDefaults {
+    /// Name servers to add as NS records at the apex of new zones, uses the server's hostname if empty
+    pub nameservers: Vec<String>,
+    /// Hostmaster mailbox in RNAME format (eg `hostmaster.example.com`), used when the zone doesn't specify one
+    pub rname: Option<String>,
+    /// SOA REFRESH, defaults to 7200
+    pub refresh: u32,
+    /// SOA RETRY, defaults to 3600
+    pub retry: u32,
+    /// SOA EXPIRE, defaults to 1209600 (two weeks)
+    pub expire: u32,
+    /// SOA MINIMUM, defaults to 3600
+    pub minimum: u32,
+    /// TTL on the automatically created NS records, defaults to 86400
+    pub ns_ttl: u32,
+}
+
+impl Default for ZoneDefaults {
+    fn default() -> Self {
+        Self {
+            nameservers: vec![],
+            rname: None,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 3600,
+            ns_ttl: 86400,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+/// What to do with imported records whose TTL is outside the allowed range
+pub enum TtlOutOfRange {
+    /// Bring it up to the floor or down to the ceiling
+    #[default]
+    Clamp,
+    /// Leave the record out
+    Skip,
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// TTL checks applied to records when zones are imported
+pub struct ImportTtlPolicy {
+    /// Used for records without a TTL (or with a TTL of 0), defaults to 3600
+    pub default_ttl: u32,
+    /// The lowest TTL allowed, defaults to 1
+    pub min_ttl: u32,
+    /// The highest TTL allowed, defaults to 2147483647 which is the most [RFC2181](https://www.rfc-editor.org/rfc/rfc2181#section-8) allows
+    pub max_ttl: u32,
+    /// What to do with records outside `min_ttl` - `max_ttl`
+    pub out_of_range: TtlOutOfRange,
+}
+
+impl Default for ImportTtlPolicy {
+    fn default() -> Self {
+        Self {
+            default_ttl: 3600,
+            min_ttl: 1,
+            max_ttl: 2147483647,
+            out_of_range: TtlOutOfRange::Clamp,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// How many requests `/api` and `/auth` take before they start answering with 429s
+pub struct RateLimits {
+    /// Turn rate limiting on, defaults to true
+    pub enabled: bool,
+    /// How long each budget lasts before it's topped back up, in seconds. Defaults to 60
+    pub window_secs: u64,
+    /// Requests each client IP gets per window. Defaults to 600
+    pub per_ip: u32,
+    /// Requests each API token gets per window, on top of the IP's limit. Defaults to 300
+    pub per_token: u32,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_secs: 60,
+            per_ip: 600,
+            per_token: 300,
+        }
+    }
+}
+
+impl RateLimits {
+    /// Problems with the config
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.enabled {
+            if self.window_secs == 0 {
+                errors.push("rate_limits.window_secs needs to be at least 1".to_string());
+            }
+            if self.per_ip == 0 || self.per_token == 0 {
+                errors.push(
+                    "rate_limits.per_ip and rate_limits.per_token need to be at least 1"
+                        .to_string(),
+                );
+            }
+        }
+        errors
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Eq, PartialEq, Clone, Copy)]
+#[serde(default)]
+/// Checks on queries before they're answered, they're all off by default
+pub struct QueryChecks {
+    /// Only answer queries - the QR bit's clear and there's one question - anything else gets FORMERR. UDP queries from
+    /// ports nothing should send them from (0, echo, daytime, chargen and time) are dropped.
+    pub strict: bool,
+    /// Don't reply to queries that fail the checks at all, so forged ones can't bounce traffic off us
+    pub drop: bool,
+}
+
+impl QueryChecks {
+    /// Problems with the config
+    pub fn check(&self) -> Vec<String> {
+        match self.drop && !self.strict {
+            true => vec!["query_checks.drop needs query_checks.strict to be set".to_string()],
+            false => vec![],
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// Answers that don't come from a zone, see [crate::builtin]
+pub struct BuiltinZones {
+    /// Answer CHAOS TXT queries for `id.server` and `hostname.bind` with this, eg the server's name. They're refused
+    /// if it's not set
+    pub server_id: Option<String>,
+    /// Answer for the reverse zones of private and link-local IPv4 space with NXDOMAIN like AS112 does, if there
+    /// isn't a zone for them
+    pub as112: bool,
+    /// Answer TXT queries for `status.goatns.internal` with the server's version, uptime and query counts
+    pub status: bool,
+    /// Who else can ask for `status.goatns.internal`, localhost always can
+    pub status_clients: Vec<IpNet>,
+}
+
+impl BuiltinZones {
+    /// Problems with the config
+    pub fn check(&self) -> Vec<String> {
+        match &self.server_id {
+            Some(server_id) if server_id.is_empty() || server_id.len() > 255 => {
+                vec!["builtin_zones.server_id needs to be between 1 and 255 bytes".to_string()]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Eq, PartialEq, Clone, Copy)]
+#[serde(default)]
+/// How much each user can create, so one person can't fill up a shared server. 0 means there's no limit, which is the
+/// default. Admins don't have limits, and can give users their own on the admin page.
+pub struct QuotaConfig {
+    /// How many zones a user can own
+    pub max_zones: u32,
+    /// How many records each of their zones can have
+    pub max_records_per_zone: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// A Lua script that gets to change answers before they're sent, see [crate::policy]. Needs the `response-policy`
+/// feature.
+pub struct ResponsePolicyConfig {
+    /// The script, nothing's run if it's not set
+    pub script: Option<PathBuf>,
+    /// The function in the script that's called for each reply
+    pub function: String,
+    /// How many Lua instructions a call can take before it's stopped and the answers are sent as they were
+    pub max_instructions: u64,
+}
+
+impl Default for ResponsePolicyConfig {
+    fn default() -> Self {
+        Self {
+            script: None,
+            function: "policy".to_string(),
+            max_instructions: 1_000_000,
+        }
+    }
+}
+
+impl ResponsePolicyConfig {
+    /// Problems with the config
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = vec![];
+        let Some(script) = &self.script else {
+            return errors;
+        };
+        if cfg!(not(feature = "response-policy")) {
+            errors.push(
+                "response_policy.script is set but goatns was built without the response-policy feature"
+                    .to_string(),
+            );
+        }
+        if !script.is_file() {
+            errors.push(format!(
+                "response_policy.script {} isn't a file",
+                script.display()
+            ));
+        }
+        if self.function.is_empty() {
+            errors.push("response_policy.function can't be empty".to_string());
+        }
+        if self.max_instructions == 0 {
+            errors.push("response_policy.max_instructions needs to be at least 1".to_string());
+        }
+        errors
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// Where FORWARD records are looked up, see [crate::forward]
+pub struct ForwardConfig {
+    /// The servers to ask, eg the old provider's name servers during a migration. They're asked without recursion, in
+    /// order until one answers
+    pub upstreams: Vec<SocketAddr>,
+    /// The longest TTL a forwarded answer's sent with, and how long it's cached for
+    pub max_ttl: u32,
+    /// How long to wait for each upstream to answer, in milliseconds. Between them they have to answer well inside the
+    /// time we've got to reply, so this times the number of upstreams can't be more than [crate::forward::FORWARD_DEADLINE_MS]
+    pub timeout_ms: u64,
+    /// How many answers to keep in the cache, 0 turns it off
+    pub cache_size: usize,
+}
+
+impl Default for ForwardConfig {
+    fn default() -> Self {
+        Self {
+            upstreams: vec![],
+            max_ttl: 300,
+            timeout_ms: 300,
+            cache_size: 1000,
+        }
+    }
+}
+
+impl ForwardConfig {
+    /// Problems with the config, `listen` is where we're answering DNS queries so we don't forward to ourselves
+    pub fn check(&self, listen: Option<IpAddr>, port: u16) -> Vec<String> {
+        let mut errors = vec![];
+        for upstream in &self.upstreams {
+            let ours = match listen {
+                Some(listen) if !listen.is_unspecified() => upstream.ip().to_canonical() == listen,
+                _ => upstream.ip().is_loopback() || upstream.ip().is_unspecified(),
+            };
+            if ours && upstream.port() == port {
+                errors.push(format!(
+                    "forward.upstreams can't include {upstream}, that's this server"
+                ));
+            }
+        }
+        if self.timeout_ms == 0 {
+            errors.push("forward.timeout_ms needs to be at least 1".to_string());
+        }
+        let worst_case = (self.upstreams.len() as u64).saturating_mul(self.timeout_ms);
+        if worst_case > crate::forward::FORWARD_DEADLINE_MS {
+            errors.push(format!(
+                "forward.timeout_ms of {}ms for each of {} upstreams could take {worst_case}ms, it needs to fit inside {}ms so the client gets an answer",
+                self.timeout_ms,
+                self.upstreams.len(),
+                crate::forward::FORWARD_DEADLINE_MS
+            ));
+        }
+        errors
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+/// What queries for zones that have been turned off get back
+pub enum DisabledZoneRcode {
+    /// REFUSED, like we don't serve the zone at all
+    #[default]
+    Refused,
+    /// SERVFAIL, so resolvers try another server
+    ServFail,
+}
+
+impl From<DisabledZoneRcode> for Rcode {
+    fn from(value: DisabledZoneRcode) -> Self {
+        match value {
+            DisabledZoneRcode::Refused => Rcode::Refused,
+            DisabledZoneRcode::ServFail => Rcode::ServFail,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+/// How to reach the syslog server
+pub enum SyslogTransport {
+    /// A local datagram socket, like `/dev/log`
+    #[default]
+    Unix,
+    Udp,
+    Tcp,
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// Sending logs to syslog, see [crate::log_sinks]
+pub struct SyslogConfig {
+    pub transport: SyslogTransport,
+    /// The socket's path for `unix`, `host:port` for `udp` and `tcp`. Defaults to `/dev/log`
+    pub address: String,
+    /// The facility the messages are from, eg `daemon` or `local0`
+    pub facility: String,
+    /// The APP-NAME in each message, defaults to `goatns`
+    pub app_name: String,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            transport: SyslogTransport::Unix,
+            address: "/dev/log".to_string(),
+            facility: "daemon".to_string(),
+            app_name: "goatns".to_string(),
+        }
+    }
+}
+
+impl SyslogConfig {
+    /// Problems with the config
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.address.is_empty() {
+            errors.push("logging.syslog.address can't be empty".to_string());
+        } else if self.transport != SyslogTransport::Unix
+            && self
+                .address
+                .rsplit_once(':')
+                .and_then(|(_, port)| port.parse::<u16>().ok())
+                .is_none()
+        {
+            errors.push(format!(
+                "logging.syslog.address needs to be host:port for udp and tcp, got {:?}",
+                self.address
+            ));
+        }
+        if crate::log_sinks::facility_code(&self.facility).is_none() {
+            errors.push(format!(
+                "logging.syslog.facility {:?} isn't a syslog facility",
+                self.facility
+            ));
+        }
+        if self.app_name.is_empty()
+            || self.app_name.len() > 48
+            || !self.app_name.chars().all(|c| c.is_ascii_graphic())
+        {
+            errors.push(
+                "logging.syslog.app_name needs to be 1 to 48 printable ASCII characters"
+                    .to_string(),
+            );
+        }
+        errors
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// Where logs go, see [crate::log_sinks]
+pub struct LoggingConfig {
+    /// Log to the console (stderr), turn it off if the journal's already picking up the output
+    pub console: bool,
+    /// Send logs to syslog too
+    pub syslog: Option<SyslogConfig>,
+    /// Send logs to the systemd journal too
+    pub journald: bool,
+    /// Log every query, with its qname and qtype as structured fields
+    pub log_queries: bool,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            console: true,
+            syslog: None,
+            journald: false,
+            log_queries: false,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Problems with the config
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if !self.console && self.syslog.is_none() && !self.journald {
+            errors.push(
+                "logging.console can only be turned off if logs are going to syslog or journald"
+                    .to_string(),
+            );
+        }
+        if let Some(syslog) = &self.syslog {
+            errors.extend(syslog.check());
+        }
+        errors
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+/// OpenTelemetry traces and metrics, sent to an OTLP collector over HTTP. The standard `OTEL_*` environment variables
+/// win over these if they're set.
+pub struct OtelConfig {
+    /// Turn it all off, nothing's set up or sent anywhere. It's always off in tests, since the global subscriber can
+    /// only be set once.
+    pub enabled: bool,
+    /// The collector, eg `http://localhost:4318`. Falls back to `OTEL_EXPORTER_OTLP_ENDPOINT`, then the OTLP default
+    pub endpoint: Option<Url>,
+    /// How many traces to keep, from 0.0 (none) to 1.0 (all of them). Child spans follow their parent's decision.
+    pub sample_ratio: f64,
+    /// The `service.name` on everything that's sent
+    pub service_name: String,
+    /// Send metrics for the DNS server as well as traces
+    pub metrics: bool,
+    /// How often metrics are sent, in seconds
+    pub metrics_interval: u64,
+}
+
+// the ratio's checked so it's never NaN
+impl Eq for OtelConfig {}
+
+impl Default for OtelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: !cfg!(test),
+            endpoint: None,
+            sample_ratio: 1.0,
+            service_name: "goatns".to_string(),
+            metrics: false,
+            metrics_interval: 60,
+        }
+    }
+}
+
+impl OtelConfig {
+    /// Problems with the config
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if !(0.0..=1.0).contains(&self.sample_ratio) {
+            errors.push("otel.sample_ratio needs to be between 0.0 and 1.0".to_string());
+        }
+        if self.service_name.trim().is_empty() {
+            errors.push("otel.service_name can't be empty".to_string());
+        }
+        if self.metrics_interval == 0 {
+            errors.push("otel.metrics_interval needs to be at least 1".to_string());
+        }
+        if let Some(endpoint) = &self.endpoint {
+            if !["http", "https"].contains(&endpoint.scheme()) {
+                errors.push(format!(
+                    "otel.endpoint needs to be an http or https URL, got {endpoint}"
+                ));
+            }
+        }
+        errors
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// Security headers sent with web responses
+pub struct SecurityHeaders {
+    /// Strict-Transport-Security max-age in seconds, 0 turns the header off. Defaults to 31536000 (a year)
+    pub hsts_max_age: u64,
+    /// Add `includeSubDomains` to Strict-Transport-Security
+    pub hsts_include_subdomains: bool,
+    /// Add `preload` to Strict-Transport-Security, needs a max-age of at least a year and `hsts_include_subdomains`
+    pub hsts_preload: bool,
+    /// Referrer-Policy, defaults to `strict-origin-when-cross-origin`
+    pub referrer_policy: String,
+    /// Permissions-Policy, defaults to turning off the camera, microphone, geolocation and payment APIs
+    pub permissions_policy: String,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            hsts_max_age: 31536000,
+            hsts_include_subdomains: false,
+            hsts_preload: false,
+            referrer_policy: "strict-origin-when-cross-origin".to_string(),
+            permissions_policy: "camera=(), microphone=(), geolocation=(), payment=()".to_string(),
+        }
+    }
+}
+
+impl SecurityHeaders {
+    /// The Strict-Transport-Security header value, if it's turned on
+    pub fn hsts(&self) -> Option<String> {
+        if self.hsts_max_age == 0 {
+            return None;
+        }
+        let mut value = format!("max-age={}", self.hsts_max_age);
+        if self.hsts_include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.hsts_preload {
+            value.push_str("; preload");
+        }
+        Some(value)
+    }
+
+    /// Problems with the config, preloading has [requirements](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Strict-Transport-Security#preloading_strict_transport_security)
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.hsts_preload {
+            if self.hsts_max_age < 31536000 {
+                errors.push(
+                    "security_headers.hsts_max_age must be at least 31536000 (a year) when hsts_preload is set"
+                        .to_string(),
+                );
+            }
+            if !self.hsts_include_subdomains {
+                errors.push(
+                    "security_headers.hsts_include_subdomains must be set when hsts_preload is set"
+                        .to_string(),
+                );
+            }
+        }
+        errors
+    }
+}
+
+/// Where the web server's paths are, DoH can't go under these unless it's on its own port
+const WEB_RESERVED_PATHS: [&str; 8] = [
+    "/ui", "/api", "/auth", "/static", "/status", "/healthz", "/readyz", "/metrics",
+];
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// DNS over HTTPS ([RFC8484](https://www.rfc-editor.org/rfc/rfc8484))
+pub struct DohConfig {
+    /// Serve DNS over HTTPS at all, defaults to true
+    pub enabled: bool,
+    /// The paths it's served on, defaults to `["/dns-query"]`
+    pub paths: Vec<String>,
+    /// Serve it on this port by itself instead of alongside the UI and API
+    pub port: Option<u16>,
+    /// Who's allowed to use it, anyone can unless something's set here
+    pub auth: DohAuthConfig,
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// Restricting who can use DoH. Any of the methods that are set up will let a client in, requests without one are
+/// refused before they're parsed.
+pub struct DohAuthConfig {
+    /// Tokens clients can send as `Authorization: Bearer <token>`
+    #[serde(skip_serializing)]
+    pub bearer_tokens: Vec<String>,
+    /// Let users in with their API tokens, sent as `Authorization: Bearer <token_key>:<token_secret>`
+    pub api_tokens: bool,
+    /// How many days of per-token usage to keep for the token owners' profile pages, 0 stops counting. Defaults to 30
+    pub usage_days: u32,
+    /// Let clients in with certificates signed by the CAs in this PEM file. Needs `doh.port`, since the UI and API
+    /// listener doesn't ask for client certificates
+    pub client_ca: Option<PathBuf>,
+}
+
+impl Default for DohAuthConfig {
+    fn default() -> Self {
+        Self {
+            bearer_tokens: vec![],
+            api_tokens: false,
+            usage_days: 30,
+            client_ca: None,
+        }
+    }
+}
+
+impl DohAuthConfig {
+    /// Is anything restricting access?
+    pub fn enabled(&self) -> bool {
+        !self.bearer_tokens.is_empty() || self.api_tokens || self.client_ca.is_some()
+    }
+
+    /// Is usage by API tokens being counted?
+    pub fn usage_enabled(&self) -> bool {
+        self.api_tokens && self.usage_days > 0
+    }
+}
+
+impl Default for DohConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            paths: vec!["/dns-query".to_string()],
+            port: None,
+            auth: DohAuthConfig::default(),
+        }
+    }
+}
+
+impl DohConfig {
+    /// Problems with the config, the paths have to be somewhere the rest of the web server isn't
+    pub fn check(&self, api_port: u16) -> Vec<String> {
+        let mut errors = vec![];
+        if !self.enabled {
+            return errors;
+        }
+        if self.paths.is_empty() {
+            errors.push("doh.paths needs at least one path when DoH is enabled".to_string());
+        }
+        for (index, path) in self.paths.iter().enumerate() {
+            if !path.starts_with('/') || path.len() < 2 || path.ends_with('/') {
+                errors.push(format!(
+                    "doh.paths entry {path:?} needs to start with a / and not end with one"
+                ));
+            }
+            if self.paths[..index].contains(path) {
+                errors.push(format!("doh.paths has {path:?} more than once"));
+            }
+            if self.port.is_none()
+                && WEB_RESERVED_PATHS.iter().any(|reserved| {
+                    path.as_str() == *reserved || path.starts_with(&format!("{reserved}/"))
+                })
+            {
+                errors.push(format!(
+                    "doh.paths entry {path:?} clashes with the web UI or API, use a different path or set doh.port"
+                ));
+            }
+        }
+        if self.port == Some(api_port) {
+            errors.push(format!(
+                "doh.port ({api_port}) is the same as api_port, leave it unset to share the port"
+            ));
+        }
+        if self
+            .auth
+            .bearer_tokens
+            .iter()
+            .any(|token| token.trim().is_empty())
+        {
+            errors.push("doh.auth.bearer_tokens can't have empty tokens in it".to_string());
+        }
+        if let Some(client_ca) = &self.auth.client_ca {
+            if self.port.is_none() {
+                errors.push("doh.auth.client_ca needs doh.port to be set".to_string());
+            }
+            if !client_ca.exists() {
+                errors.push(format!(
+                    "doh.auth.client_ca file {client_ca:?} doesn't exist"
+                ));
+            }
+        }
+        errors
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// Where packet captures go when `capture_packets` is on, and how much of them to keep
+pub struct PacketCaptureConfig {
+    /// Defaults to `./captures`
+    pub directory: String,
+    /// Start a new file once the current one's this many bytes, defaults to 10MB
+    pub max_file_size: u64,
+    /// How many capture files to keep, the oldest are deleted first. Defaults to 10
+    pub max_files: usize,
+    /// How many captures can wait to be written before they're dropped, defaults to 1024
+    pub queue_size: usize,
+}
+
+impl Default for PacketCaptureConfig {
+    fn default() -> Self {
+        Self {
+            directory: "./captures".to_string(),
+            max_file_size: 10_000_000,
+            max_files: 10,
+            queue_size: 1024,
+        }
+    }
+}
+
+impl PacketCaptureConfig {
+    /// Problems with the config
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.max_file_size < 1024 {
+            errors.push("packet_capture.max_file_size needs to be at least 1024".to_string());
+        }
+        if self.max_files == 0 || self.queue_size == 0 {
+            errors.push(
+                "packet_capture.max_files and packet_capture.queue_size need to be at least 1"
+                    .to_string(),
+            );
+        }
+        errors
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// Getting the web server's certificate from an ACME CA, with GoatNS answering the DNS-01 challenges itself
+pub struct AcmeConfig {
+    /// Get and renew the certificate automatically, defaults to false
+    pub enabled: bool,
+    /// The CA's directory, defaults to Let's Encrypt's production one
+    pub directory_url: Url,
+    /// Email addresses the CA can contact about the account
+    pub contact: Vec<String>,
+    /// Names to put on the certificate, defaults to the hostname. They need to be delegated to this server.
+    pub domains: Vec<String>,
+    /// Renew the certificate when it's got less than this many days left, defaults to 30
+    pub renew_days: u64,
+    /// How often to check if it needs renewing, in hours. Defaults to 12
+    pub check_hours: u64,
+    /// Where the ACME account's credentials are kept, defaults to `./certificates/acme_account.json`
+    pub account_path: PathBuf,
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            #[allow(clippy::expect_used)]
+            directory_url: Url::from_str("https://acme-v02.api.letsencrypt.org/directory")
+                .expect("Internal error parsing the Let's Encrypt directory URL"),
+            contact: vec![],
+            domains: vec![],
+            renew_days: 30,
+            check_hours: 12,
+            account_path: PathBuf::from("./certificates/acme_account.json"),
+        }
+    }
+}
+
+impl AcmeConfig {
+    /// The names the certificate's for
+    pub fn domains(&self, hostname: &str) -> Vec<String> {
+        match self.domains.is_empty() {
+            true => vec![hostname.to_string()],
+            false => self.domains.clone(),
+        }
+    }
+
+    /// Problems with the config
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.enabled {
+            if self.renew_days == 0 {
+                errors.push("acme.renew_days needs to be at least 1".to_string());
+            }
+            if self.domains.iter().any(|domain| domain.trim().is_empty()) {
+                errors.push("acme.domains can't have empty names in it".to_string());
+            }
+        }
+        errors
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+/// What scheduled backups are made of
+pub enum BackupFormat {
+    /// A copy of the whole database, made with `VACUUM INTO`
+    #[default]
+    Sqlite,
+    /// Every zone and who owns them in GoatNS' JSON format, like `--export-all`
+    GoatNs,
+    /// A zone file for each zone, and who owns them
+    Bind,
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// Backing up on a schedule, which is off unless there's a directory or a bucket to put them in
+pub struct BackupConfig {
+    /// Where to keep backups
+    pub directory: Option<PathBuf>,
+    /// An S3-compatible bucket to upload backups to, as well as (or instead of) the directory
+    pub s3: Option<S3BackupConfig>,
+    /// `sqlite` (the default), `goatns` or `bind`
+    pub format: BackupFormat,
+    /// How often to back up, in seconds. Defaults to 86400 (a day), the first one's made after the first interval
+    pub interval_seconds: u64,
+    /// How many backups to keep, the oldest are deleted first. Defaults to 7
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            s3: None,
+            format: BackupFormat::default(),
+            interval_seconds: 86400,
+            keep: 7,
+        }
+    }
+}
+
+impl BackupConfig {
+    /// Is there anywhere to put backups?
+    pub fn enabled(&self) -> bool {
+        self.directory.is_some() || self.s3.is_some()
+    }
+
+    /// Problems with the config
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if !self.enabled() {
+            return errors;
+        }
+        if self.interval_seconds < 60 {
+            errors.push("backups.interval_seconds needs to be at least 60".to_string());
+        }
+        if self.keep == 0 {
+            errors.push("backups.keep needs to be at least 1".to_string());
+        }
+        if let Some(s3) = &self.s3 {
+            if !["http", "https"].contains(&s3.endpoint.scheme()) || s3.endpoint.host().is_none() {
+                errors.push(format!(
+                    "backups.s3.endpoint needs to be an http(s) URL, got {}",
+                    s3.endpoint
+                ));
+            }
+            if s3.bucket.trim().is_empty() {
+                errors.push("backups.s3.bucket can't be empty".to_string());
+            }
+            if s3.access_key_id.is_empty() || s3.secret_access_key.is_empty() {
+                errors.push(
+                    "backups.s3.access_key_id and backups.s3.secret_access_key need to be set"
+                        .to_string(),
+                );
+            }
+        }
+        errors
+    }
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_s3_prefix() -> String {
+    "goatns/".to_string()
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+/// An S3-compatible bucket (AWS, MinIO, R2 etc), it's used path-style, eg `https://s3.example.com/bucket/key`
+pub struct S3BackupConfig {
+    /// eg `https://s3.ap-southeast-2.amazonaws.com`
+    pub endpoint: Url,
+    pub bucket: String,
+    /// Defaults to `us-east-1`, which is what most S3-compatible services expect
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Put in front of the backups' names, defaults to `goatns/`
+    #[serde(default = "default_s3_prefix")]
+    pub prefix: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+#[serde(default)]
+/// Keeping zones the same as the zone files in a directory (or a git repository), so they're managed by version control.
+/// It's off unless there's a directory.
+pub struct SyncConfig {
+    /// Where the zone files are, `*.zone` (BIND) and `*.json` (GoatNS) files in it and under it are read
+    pub directory: Option<PathBuf>,
+    /// A git repository to clone into `directory`, it's pulled before each sync
+    pub git_url: Option<String>,
+    /// The branch to pull, defaults to `main`
+    pub git_branch: String,
+    /// How often to sync, in seconds. Defaults to 60, and it's done at startup too
+    pub interval_seconds: u64,
+    /// Delete zones when their files go away, otherwise they're left as they are and can be edited again
+    pub prune: bool,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            git_url: None,
+            git_branch: "main".to_string(),
+            interval_seconds: 60,
+            prune: false,
+        }
+    }
+}
+
+impl SyncConfig {
+    /// Is there anywhere to sync from?
+    pub fn enabled(&self) -> bool {
+        self.directory.is_some()
+    }
+
+    /// Problems with the config
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if !self.enabled() {
+            if self.git_url.is_some() {
+                errors.push(
+                    "sync.git_url needs sync.directory to be set to clone it into".to_string(),
+                );
+            }
+            return errors;
+        }
+        if self.interval_seconds < 10 {
+            errors.push("sync.interval_seconds needs to be at least 10".to_string());
+        }
+        // they're passed to git, so they can't look like options
+        if let Some(git_url) = &self.git_url {
+            if git_url.is_empty() || git_url.starts_with('-') {
+                errors.push(format!("sync.git_url isn't valid: {git_url:?}"));
+            }
+        }
+        if self.git_branch.is_empty() || self.git_branch.starts_with('-') {
+            errors.push(format!(
+                "sync.git_branch isn't valid: {:?}",
+                self.git_branch
+            ));
+        }
+        errors
+    }
+}
+
 #[derive(Debug, Deserialize, Eq, PartialEq, Clone, Serialize)]
 /// The main config blob for GoatNS, write this as a JSON file and load it and it'll make things go.
 pub struct ConfigFile {
     /// The server's hostname when generating an SOA record, defaults to the results of gethostname()
     pub hostname: String,
-    /// DNS listener address, default is 127.0.0.1
+    /// DNS and API listener address, default is 127.0.0.1. IPv6 is fine, eg `::` to listen on everything
     pub address: String,
+    /// Sets IPV6_V6ONLY on the listeners when `address` is IPv6. `false` means `::` accepts IPv4 too, leave it unset for the OS default
+    #[serde(default)]
+    pub ipv6_only: Option<bool>,
     /// Listen for DNS queries on this port, default is 15353
     pub port: u16,
     /// If we should capture packets on request/response
     pub capture_packets: bool,
+    /// Where captures are written and how many are kept
+    #[serde(default)]
+    pub packet_capture: PacketCaptureConfig,
     /// Default is "DEBUG"
     pub log_level: String,
-    /// How long until we drop TCP client connections, defaults to 5 seconds.
+    /// Where logs go besides the console
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// OpenTelemetry traces and metrics
+    #[serde(default)]
+    pub otel: OtelConfig,
+    /// How long a TCP client gets to send the rest of a query once it's started, defaults to 5 seconds.
     pub tcp_client_timeout: u64,
-    /// Enable a HINFO record at hinfo.goat
+    /// How long a TCP connection can sit idle between queries before it's closed, defaults to 10 seconds. It's sent to
+    /// clients asking for edns-tcp-keepalive (RFC7828).
+    pub tcp_idle_timeout: u64,
+    /// Close TCP connections after this many queries, defaults to 100
+    pub tcp_max_queries: u32,
+    /// TCP queries bigger than this many bytes get the connection closed, defaults to 4096
+    pub tcp_max_message_size: u16,
+    /// Deprecated and ignored, add a HINFO record to a zone instead
     pub enable_hinfo: bool,
     /// The location for the zone sqlite file
     pub sqlite_path: String,
+    /// If set, DNS lookups are answered from this SQLite file (eg a replica of `sqlite_path`), opened read-only
+    #[serde(default)]
+    pub sqlite_read_path: Option<String>,
+    /// Most connections in the write pool, defaults to 10
+    pub sql_max_connections: u32,
+    /// Most connections in the read pool, defaults to 10
+    pub sql_read_max_connections: u32,
+    /// Use SQLite's write-ahead log with `synchronous=NORMAL`, which is much quicker for writes. Turn it off if
+    /// `sqlite_path` is on a network filesystem, WAL needs shared memory. Defaults to true
+    pub sqlite_wal: bool,
     /// Where the JSON zone file is
     pub zone_file: Option<String>,
     /// List of "valid" TLDs - if this is empty let anything be created
@@ -57,6 +1028,14 @@ pub struct ConfigFile {
     /// IP Allow lists
     #[serde(flatten)]
     pub ip_allow_lists: IPAllowList,
+    /// Load balancers and reverse proxies we believe about who the client is, from PROXY headers on DNS over TCP and
+    /// the `Forwarded` / `X-Forwarded-For` headers on the web server
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+    /// Expect a PROXY protocol v2 header at the start of each DNS over TCP connection, connections from anywhere
+    /// other than `trusted_proxies` get dropped
+    #[serde(default)]
+    pub proxy_protocol: bool,
     /// Do you really want an API?
     pub enable_api: bool,
     /// API / Web UI Port
@@ -65,6 +1044,9 @@ pub struct ConfigFile {
     pub api_tls_cert: PathBuf,
     /// TLS key path
     pub api_tls_key: PathBuf,
+    /// How often to check the cert and key for changes and reload them, in seconds. 0 turns it off, a SIGHUP always
+    /// reloads them. Defaults to 60
+    pub api_tls_reload_seconds: u64,
     /// Static File Directory for api things
     pub api_static_dir: String,
     /// Secret for cookie storage - it'll randomly generate on startup by default
@@ -83,16 +1065,92 @@ pub struct ConfigFile {
     #[serde(default)]
     /// A list of scopes to request from the IdP
     pub oauth2_user_scopes: Vec<String>,
+    /// How long OIDC discovery data is used before it's refreshed in the background, in seconds
+    pub oauth2_metadata_ttl: u64,
+    /// How long past the TTL we'll keep using cached OIDC discovery data if the IdP can't be reached, in seconds
+    pub oauth2_metadata_grace: u64,
     /// Log things sometimes
     pub sql_log_statements: bool,
     /// When queries take more than this many seconds, log them
     pub sql_log_slow_duration: u64,
     /// Clean up sessions table every n seconds
     pub sql_db_cleanup_seconds: u64,
+    /// Keep the records DNS queries ask for in memory for up to this many seconds (or their TTL, if that's shorter).
+    /// 0 turns it off, which is the default. Changes made through the API and web UI purge it, if you edit the DB
+    /// directly purge it with `POST /api/admin/cache/purge`.
+    pub record_cache_seconds: u64,
+    /// When the datastore takes more than this many milliseconds to handle a command, log it. 0 turns it off
+    pub datastore_slow_command_ms: u64,
+    /// How many commands the datastore handles at once, changes to the same zone still happen one at a time
+    pub datastore_workers: u32,
     /// Administrator contact details
     pub admin_contact: ContactDetails,
     /// Allow auto-provisioning of users
     pub user_auto_provisioning: bool,
+    /// Upstream resolvers used to flatten ALIAS records, eg `["1.1.1.1:53"]`. Uses the system resolver config if empty.
+    #[serde(default)]
+    pub alias_resolvers: Vec<SocketAddr>,
+    /// SOA values and name servers for new zones
+    #[serde(default)]
+    pub zone_defaults: ZoneDefaults,
+    /// How often to send a query to our own UDP listener for `/readyz`, in seconds. 0 turns it off.
+    pub healthcheck_interval: u64,
+    /// How often to check with the parent zones that our zones are delegated to us properly, in seconds. 0 (the
+    /// default) turns it off.
+    pub delegation_check_interval: u64,
+    /// The name to ask for when self-checking, defaults to the hostname. An NXDOMAIN still counts as healthy.
+    #[serde(default)]
+    pub healthcheck_name: Option<String>,
+    /// HSTS and friends for the web UI
+    #[serde(default)]
+    pub security_headers: SecurityHeaders,
+    /// DNS over HTTPS paths and port
+    #[serde(default)]
+    pub doh: DohConfig,
+    /// TTL defaults and limits for imported zones
+    #[serde(default)]
+    pub import_ttl: ImportTtlPolicy,
+    /// Request budgets for the API and auth endpoints
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    /// What DNS queries have to look like to get an answer
+    #[serde(default)]
+    pub query_checks: QueryChecks,
+    /// Answers that don't come from a zone
+    #[serde(default)]
+    pub builtin_zones: BuiltinZones,
+    /// What queries for disabled zones get, `refused` (the default) or `servfail`
+    #[serde(default)]
+    pub disabled_zone_rcode: DisabledZoneRcode,
+    /// Leave out the authority and additional sections unless they're needed, like in referrals and negative answers
+    #[serde(default)]
+    pub minimal_responses: bool,
+    /// How many zones and records users can have
+    #[serde(default)]
+    pub quotas: QuotaConfig,
+    /// A script that can reorder, filter or change answers
+    #[serde(default)]
+    pub response_policy: ResponsePolicyConfig,
+    /// Where FORWARD records are looked up
+    #[serde(default)]
+    pub forward: ForwardConfig,
+    /// Names answered with these addresses ahead of any zone, like a hosts file, eg `"nas.lan" = ["192.168.1.10"]`
+    #[serde(default)]
+    pub host_overrides: BTreeMap<String, Vec<IpAddr>>,
+    /// Where to POST events like ownership transfers to, as JSON. Nothing's sent if it's not set.
+    #[serde(default)]
+    pub webhook_url: Option<Url>,
+    /// Map tiles for the LOC record preview, with `{z}`, `{x}` and `{y}` in it. Empty turns the map off.
+    pub map_tile_url: String,
+    /// Get the web server's certificate by ACME
+    #[serde(default)]
+    pub acme: AcmeConfig,
+    /// Scheduled backups
+    #[serde(default)]
+    pub backups: BackupConfig,
+    /// Keep zones in sync with zone files
+    #[serde(default)]
+    pub sync: SyncConfig,
 }
 
 fn generate_cookie_secret() -> String {
@@ -105,24 +1163,62 @@ impl ConfigFile {
         serde_json::to_string_pretty(self).map_err(|e| format!("Failed to serialize config: {e:?}"))
     }
 
+    /// Parse the listen address, IPv6 literals can be bare or in brackets, eg `::1` or `[::1]`
+    pub fn listen_ip(&self) -> Result<IpAddr, GoatNsError> {
+        let address = self.address.trim();
+        let unbracketed = address
+            .strip_prefix('[')
+            .and_then(|a| a.strip_suffix(']'))
+            .unwrap_or(address);
+        match unbracketed.parse::<IpAddr>() {
+            Ok(val) => Ok(val),
+            Err(err) => match address.parse::<SocketAddr>() {
+                Ok(_) => Err(GoatNsError::StartupError(format!(
+                    "Listen address {address:?} includes a port, set port/api_port instead"
+                ))),
+                Err(_) => Err(GoatNsError::StartupError(format!(
+                    "Failed to parse listen address {address:?}: {err:?}"
+                ))),
+            },
+        }
+    }
+
     /// Get a bindable SocketAddr for use in the DNS listeners
     pub fn dns_listener_address(&self) -> Result<SocketAddr, Option<String>> {
-        let listen_addr = format!("{}:{}", &self.address, &self.port);
-
-        listen_addr.parse::<SocketAddr>().map_err(|e| {
-            log::error!("Failed to parse address: {e:?}");
-            None
-        })
+        self.listen_ip()
+            .map(|ip| SocketAddr::new(ip, self.port))
+            .map_err(|e| {
+                log::error!("Failed to parse address: {e:?}");
+                None
+            })
     }
 
     /// get a string version of the listener address
     pub fn api_listener_address(&self) -> Result<SocketAddr, GoatNsError> {
-        Ok(SocketAddr::new(
-            self.address.parse().map_err(|err| {
-                GoatNsError::StartupError(format!("Failed to parse IP {:?}", err))
-            })?,
-            self.api_port,
-        ))
+        Ok(SocketAddr::new(self.listen_ip()?, self.api_port))
+    }
+
+    /// Where DoH listens if it's got its own port
+    pub fn doh_listener_address(&self) -> Result<Option<SocketAddr>, GoatNsError> {
+        match self.doh.port {
+            Some(port) => Ok(Some(SocketAddr::new(self.listen_ip()?, port))),
+            None => Ok(None),
+        }
+    }
+
+    /// The name servers new zones get NS records for
+    pub fn default_nameservers(&self) -> Vec<String> {
+        match self.zone_defaults.nameservers.is_empty() {
+            true => vec![self.hostname.clone()],
+            false => self.zone_defaults.nameservers.clone(),
+        }
+    }
+
+    /// The name the DNS self-check asks for
+    pub fn healthcheck_name(&self) -> String {
+        self.healthcheck_name
+            .clone()
+            .unwrap_or_else(|| self.hostname.clone())
     }
 
     /// It's a sekret!
@@ -158,6 +1254,57 @@ impl ConfigFile {
     ) -> Result<(), Vec<String>> {
         let mut errors: Vec<String> = vec![];
 
+        if let Err(err) = config.listen_ip() {
+            errors.push(format!("{err:?}"));
+        }
+
+        errors.extend(config.logging.check());
+        errors.extend(config.otel.check());
+        errors.extend(config.security_headers.check());
+        errors.extend(config.rate_limits.check());
+        errors.extend(config.query_checks.check());
+        errors.extend(config.builtin_zones.check());
+        errors.extend(config.response_policy.check());
+        errors.extend(config.forward.check(config.listen_ip().ok(), config.port));
+        for (name, addresses) in config.host_overrides.iter() {
+            if !goat_lib::validators::dns_name(&crate::name_policy::normalise(name)) {
+                errors.push(format!("host_overrides has an invalid name: {name:?}"));
+            }
+            if addresses.is_empty() {
+                errors.push(format!("host_overrides.{name} needs at least one address"));
+            }
+        }
+        errors.extend(config.doh.check(config.api_port));
+        errors.extend(crate::wizards::loc::check_map_tile_url(
+            &config.map_tile_url,
+        ));
+        errors.extend(config.acme.check());
+        errors.extend(config.backups.check());
+        errors.extend(config.sync.check());
+        if config.capture_packets {
+            errors.extend(config.packet_capture.check());
+        }
+        if config.import_ttl.min_ttl > config.import_ttl.max_ttl {
+            errors.push(format!(
+                "import_ttl.min_ttl ({}) is higher than import_ttl.max_ttl ({})",
+                config.import_ttl.min_ttl, config.import_ttl.max_ttl
+            ));
+        }
+
+        if config.sql_max_connections == 0 || config.sql_read_max_connections == 0 {
+            errors.push(
+                "sql_max_connections and sql_read_max_connections need to be at least 1"
+                    .to_string(),
+            );
+        }
+        if config.datastore_workers == 0 {
+            errors.push("datastore_workers needs to be at least 1".to_string());
+        }
+
+        if config.enable_hinfo {
+            log::warn!("enable_hinfo is deprecated and does nothing, add a HINFO record to a zone if you want one served");
+        }
+
         if config.api_tls_cert.starts_with("~") {
             #[cfg(test)]
             eprintln!(
@@ -177,14 +1324,15 @@ impl ConfigFile {
             );
         }
 
-        if !config.api_tls_key.exists() {
+        // ACME writes them if they're not there yet
+        if !config.acme.enabled && !config.api_tls_key.exists() {
             errors.push(format!(
                 "Failed to find API TLS Key file: {:?}",
                 config.api_tls_key
             ));
         };
 
-        if !config.api_tls_cert.exists() {
+        if !config.acme.enabled && !config.api_tls_cert.exists() {
             errors.push(format!(
                 "Failed to find API TLS cert file: {:?}",
                 config.api_tls_cert
@@ -278,22 +1426,36 @@ impl Default for ConfigFile {
         Self {
             hostname,
             address: "127.0.0.1".to_string(),
+            ipv6_only: None,
             port: 15353,
             capture_packets: false,
+            packet_capture: PacketCaptureConfig::default(),
             log_level: "INFO".to_string(),
+            logging: LoggingConfig::default(),
+            otel: OtelConfig::default(),
             tcp_client_timeout: 5,
+            tcp_idle_timeout: 10,
+            tcp_max_queries: 100,
+            tcp_max_message_size: 4096,
             enable_hinfo: false,
             allowed_tlds: vec![],
             ip_allow_lists: IPAllowList {
                 // axfr: vec![],
                 shutdown: vec![],
             },
+            trusted_proxies: vec![],
+            proxy_protocol: false,
             sqlite_path: String::from("~/.cache/goatns.sqlite"),
+            sqlite_read_path: None,
+            sql_max_connections: 10,
+            sql_read_max_connections: 10,
+            sqlite_wal: true,
             zone_file: None,
             enable_api: false,
             api_port: 9000,
             api_tls_cert: PathBuf::from("./certificates/cert.pem"),
             api_tls_key: PathBuf::from("./certificates/key.pem"),
+            api_tls_reload_seconds: 60,
             api_static_dir: String::from("./static_files/"),
             api_cookie_secret: generate_cookie_secret(),
             oauth2_client_id: String::from(""),
@@ -304,11 +1466,38 @@ impl Default for ConfigFile {
             oauth2_secret: String::from(""),
             oauth2_config_url: String::from(""),
             oauth2_user_scopes: vec!["openid".to_string(), "email".to_string()],
+            oauth2_metadata_ttl: 3600,
+            oauth2_metadata_grace: 86400,
             sql_log_slow_duration: 5,
             sql_log_statements: false,
             sql_db_cleanup_seconds: 3600, // one hour
+            record_cache_seconds: 0,
+            datastore_slow_command_ms: 1000,
+            datastore_workers: 8,
             admin_contact: Default::default(),
             user_auto_provisioning: false,
+            alias_resolvers: vec![],
+            zone_defaults: ZoneDefaults::default(),
+            healthcheck_interval: 30,
+            delegation_check_interval: 0,
+            healthcheck_name: None,
+            security_headers: SecurityHeaders::default(),
+            doh: DohConfig::default(),
+            import_ttl: ImportTtlPolicy::default(),
+            rate_limits: RateLimits::default(),
+            query_checks: QueryChecks::default(),
+            builtin_zones: BuiltinZones::default(),
+            disabled_zone_rcode: DisabledZoneRcode::default(),
+            minimal_responses: false,
+            quotas: QuotaConfig::default(),
+            response_policy: ResponsePolicyConfig::default(),
+            forward: ForwardConfig::default(),
+            host_overrides: BTreeMap::new(),
+            webhook_url: None,
+            map_tile_url: crate::wizards::loc::DEFAULT_MAP_TILE_URL.to_string(),
+            acme: AcmeConfig::default(),
+            backups: BackupConfig::default(),
+            sync: SyncConfig::default(),
         }
     }
 }
@@ -376,21 +1565,54 @@ impl From<Config> for ConfigFile {
         ConfigFile {
             hostname,
             address: config.get("address").unwrap_or(Self::default().address),
+            ipv6_only: config.get("ipv6_only").unwrap_or(Self::default().ipv6_only),
             port: config.get("port").unwrap_or_default(),
             capture_packets: config.get("capture_packets").unwrap_or_default(),
+            packet_capture: config
+                .get("packet_capture")
+                .unwrap_or(Self::default().packet_capture),
             log_level: config.get("log_level").unwrap_or(Self::default().log_level),
+            logging: config.get("logging").unwrap_or(Self::default().logging),
+            otel: config.get("otel").unwrap_or(Self::default().otel),
             enable_hinfo: config
                 .get("enable_hinfo")
                 .unwrap_or(Self::default().enable_hinfo),
             ip_allow_lists: config
                 .get("ip_allow_lists")
                 .unwrap_or(Self::default().ip_allow_lists),
+            trusted_proxies: config
+                .get("trusted_proxies")
+                .unwrap_or(Self::default().trusted_proxies),
+            proxy_protocol: config
+                .get("proxy_protocol")
+                .unwrap_or(Self::default().proxy_protocol),
             tcp_client_timeout: config
                 .get("tcp_client_timeout")
                 .unwrap_or(Self::default().tcp_client_timeout),
+            tcp_idle_timeout: config
+                .get("tcp_idle_timeout")
+                .unwrap_or(Self::default().tcp_idle_timeout),
+            tcp_max_queries: config
+                .get("tcp_max_queries")
+                .unwrap_or(Self::default().tcp_max_queries),
+            tcp_max_message_size: config
+                .get("tcp_max_message_size")
+                .unwrap_or(Self::default().tcp_max_message_size),
             sqlite_path: config
                 .get("sqlite_path")
                 .unwrap_or(Self::default().sqlite_path),
+            sqlite_read_path: config
+                .get("sqlite_read_path")
+                .unwrap_or(Self::default().sqlite_read_path),
+            sql_max_connections: config
+                .get("sql_max_connections")
+                .unwrap_or(Self::default().sql_max_connections),
+            sql_read_max_connections: config
+                .get("sql_read_max_connections")
+                .unwrap_or(Self::default().sql_read_max_connections),
+            sqlite_wal: config
+                .get("sqlite_wal")
+                .unwrap_or(Self::default().sqlite_wal),
             allowed_tlds: config
                 .get("allowed_tlds")
                 .unwrap_or(Self::default().allowed_tlds),
@@ -405,6 +1627,9 @@ impl From<Config> for ConfigFile {
             api_tls_key: config
                 .get("api_tls_key")
                 .unwrap_or(Self::default().api_tls_key),
+            api_tls_reload_seconds: config
+                .get("api_tls_reload_seconds")
+                .unwrap_or(Self::default().api_tls_reload_seconds),
             api_static_dir: config
                 .get("api_static_dir")
                 .unwrap_or(Self::default().api_static_dir),
@@ -424,6 +1649,12 @@ impl From<Config> for ConfigFile {
             oauth2_user_scopes: config
                 .get("oauth2_user_scopes")
                 .unwrap_or(Self::default().oauth2_user_scopes),
+            oauth2_metadata_ttl: config
+                .get("oauth2_metadata_ttl")
+                .unwrap_or(Self::default().oauth2_metadata_ttl),
+            oauth2_metadata_grace: config
+                .get("oauth2_metadata_grace")
+                .unwrap_or(Self::default().oauth2_metadata_grace),
             sql_log_slow_duration: config
                 .get("sql_log_slow_duration")
                 .unwrap_or(Self::default().sql_log_slow_duration),
@@ -433,12 +1664,75 @@ impl From<Config> for ConfigFile {
             sql_db_cleanup_seconds: config
                 .get("sql_db_cleanup_seconds")
                 .unwrap_or(Self::default().sql_db_cleanup_seconds),
+            record_cache_seconds: config
+                .get("record_cache_seconds")
+                .unwrap_or(Self::default().record_cache_seconds),
+            datastore_slow_command_ms: config
+                .get("datastore_slow_command_ms")
+                .unwrap_or(Self::default().datastore_slow_command_ms),
+            datastore_workers: config
+                .get("datastore_workers")
+                .unwrap_or(Self::default().datastore_workers),
             admin_contact: config
                 .get("admin_contact")
                 .unwrap_or(Self::default().admin_contact),
             user_auto_provisioning: config
                 .get("user_auto_provisioning")
                 .unwrap_or(Self::default().user_auto_provisioning),
+            alias_resolvers: config
+                .get("alias_resolvers")
+                .unwrap_or(Self::default().alias_resolvers),
+            zone_defaults: config
+                .get("zone_defaults")
+                .unwrap_or(Self::default().zone_defaults),
+            healthcheck_interval: config
+                .get("healthcheck_interval")
+                .unwrap_or(Self::default().healthcheck_interval),
+            delegation_check_interval: config
+                .get("delegation_check_interval")
+                .unwrap_or(Self::default().delegation_check_interval),
+            healthcheck_name: config
+                .get("healthcheck_name")
+                .unwrap_or(Self::default().healthcheck_name),
+            security_headers: config
+                .get("security_headers")
+                .unwrap_or(Self::default().security_headers),
+            doh: config.get("doh").unwrap_or(Self::default().doh),
+            import_ttl: config
+                .get("import_ttl")
+                .unwrap_or(Self::default().import_ttl),
+            rate_limits: config
+                .get("rate_limits")
+                .unwrap_or(Self::default().rate_limits),
+            query_checks: config
+                .get("query_checks")
+                .unwrap_or(Self::default().query_checks),
+            builtin_zones: config
+                .get("builtin_zones")
+                .unwrap_or(Self::default().builtin_zones),
+            disabled_zone_rcode: config
+                .get("disabled_zone_rcode")
+                .unwrap_or(Self::default().disabled_zone_rcode),
+            minimal_responses: config
+                .get("minimal_responses")
+                .unwrap_or(Self::default().minimal_responses),
+            quotas: config.get("quotas").unwrap_or(Self::default().quotas),
+            response_policy: config
+                .get("response_policy")
+                .unwrap_or(Self::default().response_policy),
+            forward: config.get("forward").unwrap_or(Self::default().forward),
+            host_overrides: config
+                .get("host_overrides")
+                .unwrap_or(Self::default().host_overrides),
+            webhook_url: config
+                .get("webhook_url")
+                .unwrap_or(Self::default().webhook_url),
+            map_tile_url: config
+                .get("map_tile_url")
+                .unwrap_or(Self::default().map_tile_url),
+            acme: config.get("acme").unwrap_or(Self::default().acme),
+            backups: config.get("backups").unwrap_or(Self::default().backups),
+            sync: config.get("sync").unwrap_or(Self::default().sync),
         }
     }
 }
@@ -481,6 +1775,23 @@ pub async fn setup_logging(
         )
     })?;
 
+    crate::log_sinks::set_log_queries(config.logging.log_queries);
+    // don't go connecting to things when we're only checking the config
+    let sinks = match clap_results.get_flag("configcheck") {
+        true => None,
+        false => crate::log_sinks::LogSinks::new(&config.logging, &config.hostname)?,
+    };
+    let logger = match sinks {
+        Some(sinks) => {
+            let logger = logger.log_to_writer(Box::new(sinks));
+            match config.logging.console {
+                true => logger.duplicate_to_stderr(flexi_logger::Duplicate::All),
+                false => logger,
+            }
+        }
+        None => logger,
+    };
+
     logger
         .write_mode(flexi_logger::WriteMode::Async)
         .filter(Box::new(LogFilter {
@@ -0,0 +1,166 @@
+//! A short-lived in-memory cache of the records DNS queries ask for, so busy names don't hit the database every time
+//!
+//! Entries last for `record_cache_seconds` or the records' TTL, whichever's shorter. Changes made through the
+//! datastore empty it, and the API and web UI send a [CachePurge] once theirs are committed. Changes made anywhere else
+//! (eg editing the database by hand) show up when the entries expire, or straight away after a purge.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::enums::RecordClass;
+use crate::resourcerecord::InternalResourceRecord;
+
+/// Past this many entries, new ones aren't cached until some expire
+pub const MAX_CACHE_ENTRIES: usize = 100_000;
+
+/// What to drop from the cache
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CachePurge {
+    /// Everything
+    All,
+    /// The entries for a name, optionally just one type code
+    Name { name: String, rrtype: Option<u16> },
+    /// Everything at or below a zone's name
+    Zone(String),
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct CacheKey {
+    /// Lowercase, no trailing dot
+    name: String,
+    rrtype: u16,
+    rclass: RecordClass,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    expires: Instant,
+    records: Vec<InternalResourceRecord>,
+}
+
+/// Cached lookups, shared by every clone. The default one's turned off and never keeps anything.
+#[derive(Clone, Debug, Default)]
+pub struct RecordCache {
+    max_age: Duration,
+    entries: Arc<RwLock<HashMap<CacheKey, CacheEntry>>>,
+}
+
+/// Cache keys ignore case and the trailing dot, like the lookups do
+fn normalise(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+impl RecordCache {
+    /// Keep lookups for up to `max_age`, a zero duration turns it off
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.max_age.is_zero()
+    }
+
+    /// The cached records, if there's any that haven't expired
+    pub fn get(
+        &self,
+        name: &str,
+        rrtype: u16,
+        rclass: RecordClass,
+    ) -> Option<Vec<InternalResourceRecord>> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let key = CacheKey {
+            name: normalise(name),
+            rrtype,
+            rclass,
+        };
+        let entries = self.entries.read().ok()?;
+        entries
+            .get(&key)
+            .filter(|entry| entry.expires > Instant::now())
+            .map(|entry| entry.records.clone())
+    }
+
+    /// Keep the result of a lookup, empty results aren't cached so new records show up straight away
+    pub fn insert(
+        &self,
+        name: &str,
+        rrtype: u16,
+        rclass: RecordClass,
+        records: &[InternalResourceRecord],
+    ) {
+        let Some(min_ttl) = records.iter().map(|record| *record.ttl()).min() else {
+            return;
+        };
+        let max_age = self.max_age.min(Duration::from_secs(min_ttl.into()));
+        if max_age.is_zero() {
+            return;
+        }
+        let Ok(mut entries) = self.entries.write() else {
+            return;
+        };
+        let now = Instant::now();
+        if entries.len() >= MAX_CACHE_ENTRIES {
+            entries.retain(|_, entry| entry.expires > now);
+            if entries.len() >= MAX_CACHE_ENTRIES {
+                return;
+            }
+        }
+        entries.insert(
+            CacheKey {
+                name: normalise(name),
+                rrtype,
+                rclass,
+            },
+            CacheEntry {
+                expires: now + max_age,
+                records: records.to_vec(),
+            },
+        );
+    }
+
+    /// Drop entries from the cache, returning how many went
+    pub fn purge(&self, purge: &CachePurge) -> usize {
+        let Ok(mut entries) = self.entries.write() else {
+            return 0;
+        };
+        let before = entries.len();
+        match purge {
+            CachePurge::All => entries.clear(),
+            CachePurge::Name { name, rrtype } => {
+                let name = normalise(name);
+                entries.retain(|key, _| {
+                    key.name != name || rrtype.is_some_and(|rrtype| key.rrtype != rrtype)
+                });
+            }
+            CachePurge::Zone(zone) => {
+                let zone = normalise(zone);
+                // everything's below the root
+                if zone.is_empty() {
+                    entries.clear();
+                    return before;
+                }
+                let suffix = format!(".{zone}");
+                entries.retain(|key, _| key.name != zone && !key.name.ends_with(&suffix));
+            }
+        }
+        before - entries.len()
+    }
+
+    /// How many entries there are, including expired ones that haven't been cleaned up yet
+    pub fn len(&self) -> usize {
+        self.entries
+            .read()
+            .map(|entries| entries.len())
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,75 @@
+//! Lets the datastore's workers handle commands at the same time without changes to a zone getting in each other's way
+//!
+//! Lookups and anything else that doesn't change a zone go straight through, each store call is its own transaction.
+//! Changes to a zone wait for other changes to the same zone, and bulk changes (imports and restores) wait for every
+//! other change to finish and hold up any more until they're done.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{
+    Mutex as AsyncMutex, OwnedMutexGuard, OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock,
+};
+
+/// What a command needs to itself while it's handled, see [crate::datastore::Command::scope]
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Scope {
+    /// Doesn't change any zones
+    Free,
+    /// Changes the zone with this ID
+    ZoneId(i64),
+    /// Changes (or creates) the zone with this name
+    ZoneName(String),
+    /// Changes any number of zones
+    Everything,
+}
+
+/// Held while a command's handled, dropping it lets whatever's waiting go
+#[derive(Debug, Default)]
+pub struct ScopeGuard {
+    _shared: Option<OwnedRwLockReadGuard<()>>,
+    _exclusive: Option<OwnedRwLockWriteGuard<()>>,
+    _zone: Option<OwnedMutexGuard<()>>,
+}
+
+/// The locks for the zones being changed right now
+#[derive(Debug, Default)]
+pub struct ZoneLocks {
+    /// Zone changes hold it shared, bulk changes hold it exclusively
+    everything: Arc<RwLock<()>>,
+    zones: Mutex<HashMap<Scope, Arc<AsyncMutex<()>>>>,
+}
+
+impl ZoneLocks {
+    /// Wait until nothing else is using the scope, then hold it until the guard's dropped
+    pub async fn lock(&self, scope: &Scope) -> ScopeGuard {
+        match scope {
+            Scope::Free => ScopeGuard::default(),
+            Scope::Everything => ScopeGuard {
+                _exclusive: Some(self.everything.clone().write_owned().await),
+                ..Default::default()
+            },
+            Scope::ZoneId(_) | Scope::ZoneName(_) => {
+                let shared = self.everything.clone().read_owned().await;
+                let zone = self.zone_lock(scope).lock_owned().await;
+                ScopeGuard {
+                    _shared: Some(shared),
+                    _zone: Some(zone),
+                    ..Default::default()
+                }
+            }
+        }
+    }
+
+    /// The lock for one zone, making it if nothing's using it yet
+    fn zone_lock(&self, scope: &Scope) -> Arc<AsyncMutex<()>> {
+        // nothing's held while the map's locked, so it's still fine if something panicked with it
+        let mut zones = self
+            .zones
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // the ones only the map has aren't being held or waited for
+        zones.retain(|_, lock| Arc::strong_count(lock) > 1);
+        zones.entry(scope.clone()).or_default().clone()
+    }
+}
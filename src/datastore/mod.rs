@@ -0,0 +1,926 @@
+//! The datastore, which answers [Command]s from the servers using whichever [ZoneStore] it was started with
+
+use std::path::Path;
+use std::str::from_utf8;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::backup::scheduled::scheduled_backup;
+use crate::config::{BackupConfig, ImportTtlPolicy};
+use crate::db::{User, ZoneOwnership};
+use crate::enums::{RecordClass, RecordType};
+use crate::error::GoatNsError;
+use crate::health::{Health, HEALTH};
+use crate::interop::{load_import, ZoneFileFormat};
+use crate::metrics::{record_command, Metrics, METRICS};
+use crate::name_policy::normalise;
+use crate::resourcerecord::InternalResourceRecord;
+use crate::sync::SyncSummary;
+use crate::zone_schema::ZoneBackup;
+use crate::zones::{
+    Delegation, FileZone, FileZoneRecord, ImportProgressCallback, ImportStrategy, ImportSummary,
+    NegativeAnswer, ZoneJournalEntry, ZoneRecord,
+};
+use async_trait::async_trait;
+use log::debug;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::sync::Semaphore;
+use tokio_util::task::TaskTracker;
+use tracing::{error, instrument};
+
+mod cache;
+mod locks;
+mod sqlite;
+
+pub use cache::{CachePurge, RecordCache};
+pub use locks::{Scope, ZoneLocks};
+pub use sqlite::SqliteStore;
+
+/// Every command gets either what it asked for or the error explaining why it didn't
+type Responder<T> = oneshot::Sender<Result<T, GoatNsError>>;
+
+#[derive(Debug)]
+/// Commands that can be sent to the datastore
+pub enum Command {
+    /// Query a record from the database
+    GetRecord {
+        /// Reversed vec of the name
+        name: Vec<u8>,
+        /// The type of record to get
+        rrtype: RecordType,
+        /// The class of record to get
+        rclass: RecordClass,
+        /// The response channel
+        resp: Responder<Option<ZoneRecord>>,
+    },
+    /// Query records of a type we don't have a [RecordType] for, they're served from their generic rdata (RFC3597)
+    GetGenericRecord {
+        /// Reversed vec of the name
+        name: Vec<u8>,
+        /// The type code of the record to get
+        rrtype: u16,
+        /// The class of record to get
+        rclass: RecordClass,
+        /// The response channel
+        resp: Responder<Option<ZoneRecord>>,
+    },
+    /// Check if a name is at or below a delegation in one of our zones
+    GetDelegation {
+        /// The name that was queried
+        name: Vec<u8>,
+        /// The class that was queried
+        rclass: RecordClass,
+        /// The response channel, `None` means we're authoritative for the name
+        resp: Responder<Option<Delegation>>,
+    },
+    /// Work out how to answer when there's no records for a query
+    GetNegativeAnswer {
+        /// The name that was queried
+        name: Vec<u8>,
+        /// The class that was queried
+        rclass: RecordClass,
+        /// The response channel, `None` means it's not in any of our zones
+        resp: Responder<Option<NegativeAnswer>>,
+    },
+    /// Query a zone from the database
+    GetZone {
+        /// If you know the ID supply it
+        id: Option<i64>,
+        /// If you know the name supply it
+        name: Option<String>,
+        /// The response channel
+        resp: Responder<Option<FileZone>>,
+    },
+    /// Query a list of zones from the database
+    GetZoneNames {
+        /// Filter by user
+        user: User,
+        /// The offset to start at
+        offset: i64,
+        /// The number of records to return
+        limit: i64,
+        /// The response channel
+        resp: Responder<Vec<FileZone>>,
+    },
+    /// Import a file directly into the database
+    ImportFile {
+        /// Filename to load
+        filename: String,
+        /// If you only want to import a single zone, specify the name
+        zone_name: Option<String>,
+        /// Each file's zone is named after the file, for archives of zone files, see [crate::interop::load_import]
+        zone_name_from_filename: bool,
+        /// What format the file's in
+        format: ZoneFileFormat,
+        /// TTL defaults and limits to apply to the records
+        ttl_policy: ImportTtlPolicy,
+        /// What to do with zones that already exist
+        strategy: ImportStrategy,
+        /// Work out what would happen, but don't save anything
+        dry_run: bool,
+        /// Told how it's going after each zone
+        progress: Option<ImportProgressCallback>,
+        /// The response channel
+        resp: Responder<ImportSummary>,
+    },
+    /// Shutdown the datastore
+    Shutdown,
+    /// Create a new zone
+    CreateZone {
+        /// Zone data
+        zone: FileZone,
+
+        /// Zone ownership
+        userid: i64,
+
+        /// The response channel
+        resp: Responder<FileZone>,
+    },
+    /// Delete a zone
+    DeleteZone,
+    /// update a zone
+    UpdateZone,
+    /// Delete user
+    DeleteUser,
+    /// Create a new user
+    CreateUser {
+        /// Username
+        username: String,
+        /// Reference from OIDC
+        authref: String,
+        /// Is this user an admin?
+        admin: bool,
+        /// Is this user disabled?
+        disabled: bool,
+        /// The response channel
+        resp: Responder<Box<User>>,
+    },
+    /// Get a user
+    GetUser {
+        /// Database ID if you have it
+        id: Option<i64>,
+        /// username if you have it
+        username: Option<String>,
+        /// The response channel
+        resp: Responder<()>,
+    },
+    /// Update a user
+    UpdateUser,
+    /// Remove zone ownership (optionally include a user)
+    DeleteOwnership {
+        /// Zone ID
+        zoneid: i64,
+        /// User ID (db ID)
+        userid: Option<i64>,
+        /// The response channel
+        resp: Responder<()>,
+    },
+    /// Get ownership of a zone, or zones for a user
+    GetOwnership {
+        /// Zone ID
+        zoneid: Option<i64>,
+        /// User ID
+        userid: Option<i64>,
+        /// The response channel
+        resp: Responder<Vec<Arc<ZoneOwnership>>>,
+    },
+    /// Create ownership of a zone
+    PostOwnership {
+        /// Zone ID
+        zoneid: i64,
+        /// User ID
+        userid: i64,
+        /// The response channel
+        resp: Responder<ZoneOwnership>,
+    },
+    /// Set the TTL of every record in a zone, the journal entry can put them back
+    SetZoneTtls {
+        /// Zone ID
+        zoneid: i64,
+        /// The new TTL
+        ttl: u32,
+        /// Who's making the change
+        userid: i64,
+        /// The response channel
+        resp: Responder<ZoneJournalEntry>,
+    },
+    /// Turn a zone on or off, queries for a disabled zone get `disabled_zone_rcode` but the records are kept
+    SetZoneEnabled {
+        /// Zone ID
+        zoneid: i64,
+        /// On or off
+        enabled: bool,
+        /// The response channel
+        resp: Responder<()>,
+    },
+    /// Undo a change from a zone's journal
+    UndoJournalEntry {
+        /// Zone ID
+        zoneid: i64,
+        /// Journal entry ID
+        id: i64,
+        /// The response channel
+        resp: Responder<ZoneJournalEntry>,
+    },
+    /// Drop cached lookups, eg after the database has been edited directly
+    PurgeCache {
+        /// What to drop
+        purge: CachePurge,
+        /// The response channel, with how many entries were dropped
+        resp: Responder<usize>,
+    },
+    /// Re-read a zone from storage and drop everything cached for it
+    ReloadZone {
+        /// Zone ID
+        id: i64,
+        /// The response channel
+        resp: Responder<ZoneReload>,
+    },
+    /// Get every zone and who owns them, for backups
+    ExportAll {
+        /// The response channel
+        resp: Responder<ZoneBackup>,
+    },
+    /// Restore a backup from [Command::ExportAll]
+    RestoreBackup {
+        /// What to restore
+        backup: ZoneBackup,
+        /// TTL defaults and limits to apply to the records
+        ttl_policy: ImportTtlPolicy,
+        /// What to do with zones that already exist
+        strategy: ImportStrategy,
+        /// Work out what would happen, but don't save anything
+        dry_run: bool,
+        /// The response channel
+        resp: Responder<ImportSummary>,
+    },
+}
+
+impl Command {
+    /// What sort of command it is, for logs and metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Command::GetRecord { .. } => "get_record",
+            Command::GetGenericRecord { .. } => "get_generic_record",
+            Command::GetDelegation { .. } => "get_delegation",
+            Command::GetNegativeAnswer { .. } => "get_negative_answer",
+            Command::GetZone { .. } => "get_zone",
+            Command::GetZoneNames { .. } => "get_zone_names",
+            Command::ImportFile { .. } => "import_file",
+            Command::Shutdown => "shutdown",
+            Command::CreateZone { .. } => "create_zone",
+            Command::DeleteZone => "delete_zone",
+            Command::UpdateZone => "update_zone",
+            Command::DeleteUser => "delete_user",
+            Command::CreateUser { .. } => "create_user",
+            Command::GetUser { .. } => "get_user",
+            Command::UpdateUser => "update_user",
+            Command::DeleteOwnership { .. } => "delete_ownership",
+            Command::GetOwnership { .. } => "get_ownership",
+            Command::PostOwnership { .. } => "post_ownership",
+            Command::SetZoneTtls { .. } => "set_zone_ttls",
+            Command::SetZoneEnabled { .. } => "set_zone_enabled",
+            Command::UndoJournalEntry { .. } => "undo_journal_entry",
+            Command::PurgeCache { .. } => "purge_cache",
+            Command::ReloadZone { .. } => "reload_zone",
+            Command::ExportAll { .. } => "export_all",
+            Command::RestoreBackup { .. } => "restore_backup",
+        }
+    }
+
+    /// Which zones it changes, so changes to the same zone happen one at a time
+    pub fn scope(&self) -> Scope {
+        match self {
+            Command::CreateZone { zone, .. } => Scope::ZoneName(normalise(&zone.name)),
+            Command::DeleteOwnership { zoneid, .. }
+            | Command::PostOwnership { zoneid, .. }
+            | Command::SetZoneTtls { zoneid, .. }
+            | Command::SetZoneEnabled { zoneid, .. }
+            | Command::UndoJournalEntry { zoneid, .. } => Scope::ZoneId(*zoneid),
+            Command::ReloadZone { id, .. } => Scope::ZoneId(*id),
+            Command::ImportFile { .. } | Command::RestoreBackup { .. } => Scope::Everything,
+            Command::GetRecord { .. }
+            | Command::GetGenericRecord { .. }
+            | Command::GetDelegation { .. }
+            | Command::GetNegativeAnswer { .. }
+            | Command::GetZone { .. }
+            | Command::GetZoneNames { .. }
+            | Command::Shutdown
+            | Command::DeleteZone
+            | Command::UpdateZone
+            | Command::DeleteUser
+            | Command::CreateUser { .. }
+            | Command::GetUser { .. }
+            | Command::UpdateUser
+            | Command::GetOwnership { .. }
+            | Command::PurgeCache { .. }
+            | Command::ExportAll { .. } => Scope::Free,
+        }
+    }
+}
+
+/// A [Command] on its way to the datastore, with when it was sent so we can tell how long it waited
+#[derive(Debug)]
+pub struct Queued {
+    /// What's being asked for
+    pub command: Command,
+    /// When it was sent
+    pub sent: Instant,
+}
+
+impl From<Command> for Queued {
+    fn from(command: Command) -> Self {
+        Self {
+            command,
+            sent: Instant::now(),
+        }
+    }
+}
+
+/// What [Command::ReloadZone] found
+#[derive(Debug)]
+pub struct ZoneReload {
+    /// The zone as it is in storage now
+    pub zone: FileZone,
+    /// How many cached lookups were dropped
+    pub purged: usize,
+}
+
+/// Somewhere to keep zones, records and who owns them. The datastore is generic over this so the storage can be swapped out.
+#[async_trait]
+pub trait ZoneStore: Clone + Send + Sync + 'static {
+    /// Get the records matching a query, with their TTLs normalised to the lowest of them
+    async fn get_record(
+        &self,
+        name: &str,
+        rrtype: RecordType,
+        rclass: RecordClass,
+    ) -> Result<Vec<InternalResourceRecord>, GoatNsError>;
+    /// Like [ZoneStore::get_record], but by type code, for types that only exist as generic records
+    async fn get_generic_record(
+        &self,
+        name: &str,
+        rrtype: u16,
+        rclass: RecordClass,
+    ) -> Result<Vec<InternalResourceRecord>, GoatNsError>;
+    /// The zone a name's in - the closest one at or above it, found by walking up its labels
+    async fn get_closest_zone(&self, name: &str) -> Result<Option<FileZone>, GoatNsError>;
+    /// The highest zone cut at or above a name in its closest zone, with glue for the name servers under it
+    async fn get_delegation(
+        &self,
+        name: &str,
+        rclass: RecordClass,
+    ) -> Result<Option<Delegation>, GoatNsError>;
+    /// Find the zone a name's in and whether anything exists at or below it, for NXDOMAIN/NODATA answers
+    async fn get_negative_answer(
+        &self,
+        name: &str,
+        rclass: RecordClass,
+    ) -> Result<Option<NegativeAnswer>, GoatNsError>;
+    /// Add a record, or update it if it matches an existing one
+    async fn put_record(&self, record: FileZoneRecord) -> Result<FileZoneRecord, GoatNsError>;
+
+    /// Get a zone and its records by ID or name
+    async fn get_zone(
+        &self,
+        id: Option<i64>,
+        name: Option<String>,
+    ) -> Result<Option<FileZone>, GoatNsError>;
+    /// The zones a user can see, admins can see everything
+    async fn get_zones_for_user(
+        &self,
+        user: &User,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<FileZone>, GoatNsError>;
+    /// Create the zone and make the user its owner
+    async fn create_zone(&self, zone: FileZone, userid: i64) -> Result<FileZone, GoatNsError>;
+    /// Save the zone's SOA data and replace its records
+    async fn update_zone(&self, zone: FileZone) -> Result<FileZone, GoatNsError>;
+    /// Delete the zone, its records and its ownership
+    async fn delete_zone(&self, id: i64) -> Result<(), GoatNsError>;
+    /// Import zones in one go, either they all work or none of them do
+    async fn import_zones(
+        &self,
+        zones: Vec<FileZone>,
+        ttl_policy: &ImportTtlPolicy,
+        strategy: ImportStrategy,
+        dry_run: bool,
+        progress: Option<&ImportProgressCallback>,
+    ) -> Result<ImportSummary, GoatNsError>;
+    /// Every zone and who owns them as they were at one moment, without their database IDs
+    async fn export_all(&self) -> Result<ZoneBackup, GoatNsError>;
+    /// Import a backup's zones and give them back their owners, all in one go like [ZoneStore::import_zones].
+    /// Owners who aren't users any more are skipped.
+    async fn restore_backup(
+        &self,
+        backup: ZoneBackup,
+        ttl_policy: &ImportTtlPolicy,
+        strategy: ImportStrategy,
+        dry_run: bool,
+    ) -> Result<ImportSummary, GoatNsError>;
+    /// Make the zones match the ones from files (with the names of the files) and mark them as synced, all in one go.
+    /// Synced zones that aren't in `zones` any more are deleted if `prune` is set, otherwise they're just not synced.
+    async fn sync_zones(
+        &self,
+        zones: Vec<(String, FileZone)>,
+        ttl_policy: &ImportTtlPolicy,
+        prune: bool,
+    ) -> Result<SyncSummary, GoatNsError>;
+
+    /// Create a user
+    async fn create_user(&self, user: User) -> Result<Box<User>, GoatNsError>;
+
+    /// The zones a user owns
+    async fn get_ownership(&self, userid: i64) -> Result<Vec<Arc<ZoneOwnership>>, GoatNsError>;
+    /// Make the user an owner of the zone
+    async fn add_ownership(&self, zoneid: i64, userid: i64) -> Result<ZoneOwnership, GoatNsError>;
+    /// Remove ownership of a zone, for just the one user if `userid` is set
+    async fn delete_ownership(&self, zoneid: i64, userid: Option<i64>) -> Result<(), GoatNsError>;
+
+    /// Set the TTL of every record in the zone in one go, and journal the old ones
+    async fn set_zone_ttls(
+        &self,
+        zoneid: i64,
+        ttl: u32,
+        userid: i64,
+    ) -> Result<ZoneJournalEntry, GoatNsError>;
+    /// Turn the zone on or off
+    async fn set_zone_enabled(&self, zoneid: i64, enabled: bool) -> Result<(), GoatNsError>;
+    /// Undo a journalled change to a zone
+    async fn undo_journal_entry(
+        &self,
+        zoneid: i64,
+        id: i64,
+    ) -> Result<ZoneJournalEntry, GoatNsError>;
+
+    /// Drop cached lookups so the next ones go to the storage, returning how many went
+    fn purge_cache(&self, purge: &CachePurge) -> usize;
+
+    /// Write a consistent copy of the whole store to a new file, for backups
+    async fn snapshot(&self, path: &Path) -> Result<(), GoatNsError>;
+
+    /// Regular housekeeping, like removing expired API tokens and records
+    async fn cleanup(&self) -> Result<(), GoatNsError>;
+}
+
+async fn handle_get_command<S: ZoneStore>(
+    store: &S,
+    name: Vec<u8>,
+    rrtype: u16,
+    rclass: RecordClass,
+) -> Result<Option<ZoneRecord>, GoatNsError> {
+    debug!(
+        "query name={:?} rrtype={} rclass={rclass}",
+        from_utf8(&name).unwrap_or("-"),
+        crate::generic::type_name(rrtype),
+    );
+
+    #[cfg(feature = "fault-injection")]
+    crate::fault_injection::current().db_error()?;
+
+    // query the database
+    let db_name = from_utf8(&name)?;
+
+    let typerecords = match RecordType::from(&rrtype) {
+        RecordType::InvalidType => store.get_generic_record(db_name, rrtype, rclass).await,
+        known => store.get_record(db_name, known, rclass).await,
+    }
+    .inspect_err(|err| log::error!("Failed to query db: {err:?}"))?;
+
+    match typerecords.is_empty() {
+        true => Ok(None),
+        false => Ok(Some(ZoneRecord { name, typerecords })),
+    }
+}
+
+/// Send the result back to whoever asked, they might have given up waiting so this only logs failures
+fn respond<T: std::fmt::Debug>(resp: Responder<T>, res: Result<T, GoatNsError>) {
+    if let Err(error) = resp.send(res) {
+        debug!("error sending response from data store: {:?}", error)
+    };
+}
+
+/// Import a file into the store. Normally, you shouldn't use this directly, call it through calls to the datastore.
+pub async fn handle_import_file<S: ZoneStore>(
+    store: &S,
+    filename: String,
+    zone_name: Option<String>,
+    zone_name_from_filename: bool,
+    format: ZoneFileFormat,
+    ttl_policy: &ImportTtlPolicy,
+    strategy: ImportStrategy,
+    dry_run: bool,
+    progress: Option<&ImportProgressCallback>,
+) -> Result<ImportSummary, GoatNsError> {
+    let zones: Vec<FileZone> = load_import(
+        &filename,
+        format,
+        zone_name.as_deref(),
+        zone_name_from_filename,
+    )?;
+
+    let zones: Vec<FileZone> = match zone_name {
+        Some(name) => {
+            let name = normalise(&name);
+            zones
+                .into_iter()
+                .filter(|z| normalise(&z.name) == name)
+                .collect()
+        }
+        None => zones,
+    };
+
+    if zones.is_empty() {
+        log::warn!("No zones to import!");
+        return Err(GoatNsError::EmptyFile);
+    }
+
+    let summary = store
+        .import_zones(zones, ttl_policy, strategy, dry_run, progress)
+        .await?;
+    log::info!("Completed import process: {summary}");
+    Ok(summary)
+}
+
+#[instrument(level = "info", skip(store))]
+pub(crate) async fn handle_message<S: ZoneStore>(cmd: Command, store: &S) -> Result<(), String> {
+    match cmd {
+        Command::GetZone { id, name, resp } => {
+            respond(resp, store.get_zone(id, name).await);
+        }
+        Command::GetZoneNames {
+            resp,
+            user,
+            offset,
+            limit,
+        } => {
+            log::debug!("GetZoneNames: user={user:?}");
+            respond(resp, store.get_zones_for_user(&user, offset, limit).await);
+        }
+        Command::Shutdown => {
+            #[cfg(test)]
+            println!("### Datastore was sent shutdown message, shutting down.");
+            log::info!("Datastore was sent shutdown message, shutting down.");
+            return Err("Datastore was sent shutdown message, shutting down.".to_string());
+        }
+        Command::ImportFile {
+            filename,
+            resp,
+            zone_name,
+            zone_name_from_filename,
+            format,
+            ttl_policy,
+            strategy,
+            dry_run,
+            progress,
+        } => {
+            let res = handle_import_file(
+                store,
+                filename,
+                zone_name,
+                zone_name_from_filename,
+                format,
+                &ttl_policy,
+                strategy,
+                dry_run,
+                progress.as_ref(),
+            )
+            .await;
+            if let Err(err) = &res {
+                log::error!("Failed to import file: {err:?}");
+            }
+            respond(resp, res);
+        }
+        Command::GetRecord {
+            name,
+            rrtype,
+            rclass,
+            resp,
+        } => {
+            respond(
+                resp,
+                handle_get_command(store, name, rrtype as u16, rclass).await,
+            );
+        }
+        Command::GetGenericRecord {
+            name,
+            rrtype,
+            rclass,
+            resp,
+        } => {
+            respond(resp, handle_get_command(store, name, rrtype, rclass).await);
+        }
+        Command::GetDelegation { name, rclass, resp } => {
+            let res = match from_utf8(&name) {
+                Ok(name) => store.get_delegation(name, rclass).await,
+                Err(err) => Err(err.into()),
+            };
+            respond(resp, res);
+        }
+        Command::GetNegativeAnswer { name, rclass, resp } => {
+            let res = match from_utf8(&name) {
+                Ok(name) => store.get_negative_answer(name, rclass).await,
+                Err(err) => Err(err.into()),
+            };
+            respond(resp, res);
+        }
+        Command::CreateZone { zone, userid, resp } => {
+            let res = store
+                .create_zone(zone, userid)
+                .await
+                .inspect(|zone| log::info!("Created zone: {:?}", zone))
+                .inspect_err(|err| log::error!("Failed to create zone: {err:?}"));
+            respond(resp, res);
+        }
+        Command::DeleteZone => {
+            error!("Unimplemented: Command::DeleteZone")
+        }
+        Command::UpdateZone => {
+            error!("Unimplemented: Command::PatchZone")
+        }
+        Command::CreateUser {
+            username,
+            authref,
+            admin,
+            disabled,
+            resp,
+        } => {
+            let new_user = User {
+                username,
+                authref: Some(authref),
+                admin,
+                disabled,
+                ..Default::default()
+            };
+            log::debug!("Creating: {new_user:?}");
+            let username = new_user.username.clone();
+            let res = store
+                .create_user(new_user)
+                .await
+                .inspect_err(|error| log::error!("Failed to create {username}: {error:?}"));
+            respond(resp, res);
+        }
+        Command::DeleteUser => error!("Unimplemented: Command::DeleteUser"),
+        Command::GetUser { resp, .. } => {
+            error!("Unimplemented: Command::GetUser");
+            respond(
+                resp,
+                Err(GoatNsError::Generic(
+                    "Command::GetUser isn't implemented".to_string(),
+                )),
+            );
+        }
+        Command::UpdateUser => error!("Unimplemented: Command::PatchUser"),
+        Command::DeleteOwnership {
+            zoneid,
+            userid,
+            resp,
+        } => {
+            respond(resp, store.delete_ownership(zoneid, userid).await);
+        }
+        Command::GetOwnership {
+            zoneid: _,
+            userid,
+            resp,
+        } => {
+            let res = match userid {
+                Some(userid) => store.get_ownership(userid).await.inspect_err(|err| {
+                    error!("Failed to get all zone_ownership for user {userid}: {err:?}")
+                }),
+                None => {
+                    error!("Unmatched arm in getownership");
+                    Err(GoatNsError::InvalidValue(
+                        "GetOwnership needs a userid".to_string(),
+                    ))
+                }
+            };
+            respond(resp, res);
+        }
+        Command::PostOwnership {
+            zoneid,
+            userid,
+            resp,
+        } => {
+            respond(resp, store.add_ownership(zoneid, userid).await);
+        }
+        Command::SetZoneTtls {
+            zoneid,
+            ttl,
+            userid,
+            resp,
+        } => {
+            let res = store
+                .set_zone_ttls(zoneid, ttl, userid)
+                .await
+                .inspect(|entry| log::info!("zoneid={zoneid} {}", entry.description))
+                .inspect_err(|err| log::error!("Failed to set TTLs for zoneid={zoneid}: {err:?}"));
+            respond(resp, res);
+        }
+        Command::SetZoneEnabled {
+            zoneid,
+            enabled,
+            resp,
+        } => {
+            let res = store
+                .set_zone_enabled(zoneid, enabled)
+                .await
+                .inspect(|_| log::info!("zoneid={zoneid} enabled={enabled}"))
+                .inspect_err(|err| {
+                    log::error!("Failed to set enabled={enabled} for zoneid={zoneid}: {err:?}")
+                });
+            respond(resp, res);
+        }
+        Command::UndoJournalEntry { zoneid, id, resp } => {
+            let res = store
+                .undo_journal_entry(zoneid, id)
+                .await
+                .inspect(|entry| log::info!("zoneid={zoneid} undid: {}", entry.description))
+                .inspect_err(|err| {
+                    log::error!("Failed to undo journal entry {id} for zoneid={zoneid}: {err:?}")
+                });
+            respond(resp, res);
+        }
+        Command::PurgeCache { purge, resp } => {
+            let purged = store.purge_cache(&purge);
+            log::info!("Purged {purged} cached lookups for {purge:?}");
+            respond(resp, Ok(purged));
+        }
+        Command::ReloadZone { id, resp } => {
+            let res = match store.get_zone(Some(id), None).await {
+                Ok(Some(zone)) => {
+                    let purged = store.purge_cache(&CachePurge::Zone(zone.name.clone()));
+                    log::info!(
+                        "Reloaded zone {} ({} records), purged {purged} cached lookups",
+                        zone.name,
+                        zone.records.len()
+                    );
+                    Ok(ZoneReload { zone, purged })
+                }
+                Ok(None) => Err(sqlx::Error::RowNotFound.into()),
+                Err(err) => Err(err),
+            }
+            .inspect_err(|err| log::error!("Failed to reload zoneid={id}: {err:?}"));
+            respond(resp, res);
+        }
+        Command::ExportAll { resp } => {
+            let res = store
+                .export_all()
+                .await
+                .inspect(|backup| log::info!("Exported {} zones", backup.zones.len()))
+                .inspect_err(|err| log::error!("Failed to export zones: {err:?}"));
+            respond(resp, res);
+        }
+        Command::RestoreBackup {
+            backup,
+            ttl_policy,
+            strategy,
+            dry_run,
+            resp,
+        } => {
+            let res = store
+                .restore_backup(backup, &ttl_policy, strategy, dry_run)
+                .await
+                .inspect_err(|err| log::error!("Failed to restore backup: {err:?}"));
+            respond(resp, res);
+        }
+    }
+    Ok(())
+}
+
+/// Runs the store's housekeeping every `period`
+async fn cron_cleanup<S: ZoneStore>(store: S, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        if let Err(error) = store.cleanup().await {
+            log::error!("Datastore cleanup failed: {error:?}");
+        }
+    }
+}
+
+/// Backs the store up every `interval_seconds`, the first one's after the first interval so one-off commands don't
+/// make one
+async fn cron_backup<S: ZoneStore>(store: S, config: BackupConfig) {
+    let period = Duration::from_secs(config.interval_seconds);
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+        scheduled_backup(&store, &config).await;
+    }
+}
+
+/// A zone that's already there gets locked by its ID whether the command names it or not, so they exclude each other
+pub(crate) async fn resolve_scope<S: ZoneStore>(scope: Scope, store: &S) -> Scope {
+    let Scope::ZoneName(name) = scope else {
+        return scope;
+    };
+    match store.get_zone(None, Some(name.clone())).await {
+        Ok(Some(FileZone { id: Some(id), .. })) => Scope::ZoneId(id),
+        Ok(_) => Scope::ZoneName(name),
+        Err(err) => {
+            log::debug!("Couldn't look up zone {name} to lock it, locking by name: {err:?}");
+            Scope::ZoneName(name)
+        }
+    }
+}
+
+/// Handle a command, keeping track of how long it waited and how long it took
+async fn handle_queued<S: ZoneStore>(
+    command: Command,
+    sent: Instant,
+    behind: usize,
+    store: &S,
+    slow_command: Option<Duration>,
+) {
+    let kind = command.kind();
+    let waited = sent.elapsed();
+    let started = Instant::now();
+    // the only thing it can fail at is shutting down, which it's already logged
+    let _ = handle_message(command, store).await;
+    let took = started.elapsed();
+
+    let slow = slow_command.is_some_and(|limit| took > limit);
+    if slow {
+        log::warn!(
+            "Datastore took {took:?} to handle {kind}, after it waited {waited:?} with {behind} more queued behind it"
+        );
+    }
+    record_command(kind, waited, took, slow);
+}
+
+/// Manages the datastore, waits for signals from the server instances and responds with data. Up to `workers` commands
+/// are handled at once, with changes to the same zone taking turns (see [ZoneLocks]) before they take up a worker, and
+/// up to `workers` changes waiting their turn. Commands that take longer than `slow_command` to handle are logged.
+pub async fn manager<S: ZoneStore>(
+    mut rx: mpsc::Receiver<Queued>,
+    store: S,
+    cron_db_cleanup_timer: Option<Duration>,
+    backups: Option<BackupConfig>,
+    slow_command: Option<Duration>,
+    workers: u32,
+) -> Result<(), String> {
+    if let Some(timer) = cron_db_cleanup_timer {
+        log::debug!("Spawning DB cron cleanup task");
+        tokio::spawn(cron_cleanup(store.clone(), timer));
+    }
+    if let Some(backups) = backups.filter(|backups| backups.enabled()) {
+        log::debug!("Spawning backup task, every {}s", backups.interval_seconds);
+        tokio::spawn(cron_backup(store.clone(), backups));
+    }
+
+    Health::set(&HEALTH.datastore, true);
+    let workers = workers.max(1);
+    let permits = Arc::new(Semaphore::new(workers as usize));
+    let pending_changes = Arc::new(Semaphore::new(workers as usize));
+    let locks = Arc::new(ZoneLocks::default());
+    let tasks = TaskTracker::new();
+    while let Some(Queued { command, sent }) = rx.recv().await {
+        let behind = rx.len();
+        Metrics::set(&METRICS.datastore_queue_depth, behind as u64);
+        Metrics::raise(&METRICS.datastore_queue_depth_max, behind as u64);
+
+        if let Command::Shutdown = command {
+            // let whatever's running or waiting on a zone finish first
+            tasks.close();
+            tasks.wait().await;
+            handle_queued(command, sent, behind, &store, slow_command).await;
+            break;
+        }
+
+        let store = store.clone();
+        let scope = command.scope();
+        if let Scope::Free = scope {
+            // when every worker's busy, commands wait in the queue
+            let Ok(permit) = permits.clone().acquire_owned().await else {
+                break;
+            };
+            tasks.spawn(async move {
+                handle_queued(command, sent, behind, &store, slow_command).await;
+                drop(permit);
+            });
+            continue;
+        }
+
+        // changes get their zone before a worker, so the ones waiting on another change to the same zone don't hold
+        // up everything else, but there's only so many of them waiting before the rest wait in the queue
+        let Ok(pending) = pending_changes.clone().acquire_owned().await else {
+            break;
+        };
+        let locks = locks.clone();
+        let permits = permits.clone();
+        tasks.spawn(async move {
+            let _pending = pending;
+            let _guard = locks.lock(&resolve_scope(scope, &store).await).await;
+            let Ok(_permit) = permits.acquire().await else {
+                return;
+            };
+            handle_queued(command, sent, behind, &store, slow_command).await;
+        });
+    }
+    Health::set(&HEALTH.datastore, false);
+    #[cfg(test)]
+    println!("### manager is done!");
+    Ok(())
+}
@@ -0,0 +1,652 @@
+//! The SQLite [ZoneStore], which is what GoatNS normally runs with
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{Pool, Row, Sqlite, SqliteConnection, Transaction};
+
+use super::cache::{CachePurge, RecordCache};
+use super::ZoneStore;
+use crate::config::ImportTtlPolicy;
+use crate::db::{self, DBEntity, SyncedZone, User, UserAuthToken, ZoneOwnership};
+use crate::enums::{RecordClass, RecordType};
+use crate::error::GoatNsError;
+use crate::resourcerecord::InternalResourceRecord;
+use crate::sync::SyncSummary;
+use crate::utils::{enclosing_names, label_suffix_offset};
+use crate::zone_events::{self, ZoneEvent};
+use crate::zone_schema::ZoneBackup;
+use crate::zones::{
+    default_serial, Delegation, FileZone, FileZoneRecord, ImportProgress, ImportProgressCallback,
+    ImportStrategy, ImportSummary, NegativeAnswer, ZoneJournalEntry,
+};
+
+/// Keeps everything in SQLite
+#[derive(Clone, Debug)]
+pub struct SqliteStore {
+    pool: Pool<Sqlite>,
+    /// DNS lookups go here, it's the same as `pool` unless there's a read replica
+    read_pool: Pool<Sqlite>,
+    /// Records DNS lookups have asked for recently, it's off unless [SqliteStore::with_record_cache] is used
+    cache: RecordCache,
+}
+
+impl SqliteStore {
+    /// Use an existing connection pool, [crate::db::start_db] should already have been run on it
+    pub fn new(pool: Pool<Sqlite>) -> Self {
+        Self {
+            read_pool: pool.clone(),
+            pool,
+            cache: RecordCache::default(),
+        }
+    }
+
+    /// Answer DNS lookups from a different pool, eg a read-only replica
+    pub fn with_read_pool(self, read_pool: Pool<Sqlite>) -> Self {
+        Self { read_pool, ..self }
+    }
+
+    /// Keep the records DNS lookups ask for around for up to `max_age`, zero turns it off
+    pub fn with_record_cache(self, max_age: Duration) -> Self {
+        Self {
+            cache: RecordCache::new(max_age),
+            ..self
+        }
+    }
+
+    /// Throw away a dry run, or save it for real
+    async fn finish_import(
+        &self,
+        txn: Transaction<'_, Sqlite>,
+        dry_run: bool,
+    ) -> Result<(), GoatNsError> {
+        if dry_run {
+            txn.rollback().await.inspect_err(|err| {
+                log::error!("Failed to roll back dry run transaction! {err:?}")
+            })?;
+        } else {
+            txn.commit()
+                .await
+                .inspect_err(|err| log::error!("Failed to commit transaction! {:?}", err))?;
+            self.invalidate_cache();
+        }
+        Ok(())
+    }
+
+    /// Something's changed, so nothing cached can be trusted
+    fn invalidate_cache(&self) {
+        let purged = self.cache.purge(&CachePurge::All);
+        if purged > 0 {
+            log::debug!("Dropped {purged} cached lookups after a change");
+        }
+    }
+}
+
+/// Import zones into a transaction, the caller commits it (or doesn't)
+async fn import_zones_with_txn(
+    txn: &mut SqliteConnection,
+    zones: Vec<FileZone>,
+    ttl_policy: &ImportTtlPolicy,
+    strategy: ImportStrategy,
+    dry_run: bool,
+    progress: Option<&ImportProgressCallback>,
+) -> Result<ImportSummary, GoatNsError> {
+    let mut summary = ImportSummary {
+        dry_run,
+        ..Default::default()
+    };
+    let mut state = ImportProgress {
+        zones_total: zones.len(),
+        records_total: zones.iter().map(|zone| zone.records.len()).sum(),
+        ..Default::default()
+    };
+    for mut zone in zones {
+        zone.normalise_names()?;
+        zone.inherit_default_ttl_with_txn(txn).await?;
+        let zone_summary = zone.apply_ttl_policy(ttl_policy);
+        let zone_summary = zone
+            .import_with_txn(txn, strategy, zone_summary)
+            .await
+            .inspect_err(|err| log::error!("Failed to save zone {}: {err:?}", zone.name))?;
+        log::info!(
+            "Imported {} with strategy {strategy}: {zone_summary}",
+            zone.name
+        );
+        summary.add(&zone_summary);
+        if let Some(progress) = progress {
+            state.zones_done += 1;
+            state.records_done += zone.records.len();
+            progress.update(&state);
+        }
+    }
+    Ok(summary)
+}
+
+#[async_trait]
+impl ZoneStore for SqliteStore {
+    async fn get_record(
+        &self,
+        name: &str,
+        rrtype: RecordType,
+        rclass: RecordClass,
+    ) -> Result<Vec<InternalResourceRecord>, GoatNsError> {
+        if let Some(records) = self.cache.get(name, rrtype as u16, rclass) {
+            return Ok(records);
+        }
+        let records =
+            db::get_records(&self.read_pool, name.to_string(), rrtype, rclass, true).await?;
+        self.cache.insert(name, rrtype as u16, rclass, &records);
+        Ok(records)
+    }
+
+    async fn get_generic_record(
+        &self,
+        name: &str,
+        rrtype: u16,
+        rclass: RecordClass,
+    ) -> Result<Vec<InternalResourceRecord>, GoatNsError> {
+        if let Some(records) = self.cache.get(name, rrtype, rclass) {
+            return Ok(records);
+        }
+        let records =
+            db::get_records_by_code(&self.read_pool, name.to_string(), rrtype, rclass, true)
+                .await?;
+        self.cache.insert(name, rrtype, rclass, &records);
+        Ok(records)
+    }
+
+    async fn get_closest_zone(&self, name: &str) -> Result<Option<FileZone>, GoatNsError> {
+        // zone names are compared the same way as the full names in `records_merged`, lowercased without the
+        // trailing dot, and the longest one's the closest
+        let name = name.to_ascii_lowercase();
+        let names = enclosing_names(&name);
+        if names.is_empty() {
+            return Ok(None);
+        }
+        let sql = format!(
+            "SELECT * FROM zones WHERE lower(rtrim(name, '.')) IN ({})
+            ORDER BY length(rtrim(name, '.')) DESC LIMIT 1",
+            vec!["?"; names.len()].join(", ")
+        );
+        let mut query = sqlx::query(&sql);
+        for name in names {
+            query = query.bind(name);
+        }
+        Ok(query
+            .fetch_optional(&self.read_pool)
+            .await?
+            .map(FileZone::from))
+    }
+
+    async fn get_delegation(
+        &self,
+        name: &str,
+        rclass: RecordClass,
+    ) -> Result<Option<Delegation>, GoatNsError> {
+        let Some(zone) = self.get_closest_zone(name).await? else {
+            return Ok(None);
+        };
+        // NS records in the zone that aren't at its apex, at or above the name - shortest first, since that's the cut we'd hit first walking down from the apex
+        let rows = sqlx::query(
+            "SELECT record_id, zoneid, name, rclass, rrtype, rdata, ttl
+            FROM records_merged
+            WHERE zoneid = ?4 AND name != ?5 AND rrtype = ?2 AND rclass = ?3
+            AND (name = ?1 OR substr(?1, -length(name) - 1) = '.' || name)
+            ORDER BY length(name), record_id",
+        )
+        .bind(name)
+        .bind(RecordType::NS as u16)
+        .bind(rclass)
+        .bind(zone.id)
+        .bind(zone.name.trim_end_matches('.').to_ascii_lowercase())
+        .fetch_all(&self.read_pool)
+        .await?;
+
+        let cut: String = match rows.first() {
+            Some(row) => row.get("name"),
+            None => return Ok(None),
+        };
+        let mut nameservers = vec![];
+        for row in rows {
+            let row_name: String = row.get("name");
+            if row_name == cut {
+                nameservers.push(InternalResourceRecord::try_from(row)?);
+            }
+        }
+
+        // name servers under the cut can't be found without glue
+        let mut glue = vec![];
+        for nameserver in &nameservers {
+            let InternalResourceRecord::NS { nsdname, .. } = nameserver else {
+                continue;
+            };
+            if label_suffix_offset(nsdname.name.as_bytes(), cut.as_bytes()).is_none() {
+                continue;
+            }
+            for rrtype in [RecordType::A, RecordType::AAAA] {
+                for address in
+                    db::get_records(&self.read_pool, nsdname.name.clone(), rrtype, rclass, false)
+                        .await?
+                {
+                    glue.push((nsdname.name.as_bytes().to_vec(), address));
+                }
+            }
+        }
+
+        Ok(Some(Delegation {
+            name: cut.into_bytes(),
+            nameservers,
+            glue,
+        }))
+    }
+
+    async fn get_negative_answer(
+        &self,
+        name: &str,
+        rclass: RecordClass,
+    ) -> Result<Option<NegativeAnswer>, GoatNsError> {
+        let Some(zone) = self.get_closest_zone(name).await? else {
+            return Ok(None);
+        };
+
+        let mname: Option<String> = sqlx::query_scalar(
+            "SELECT rdata FROM records WHERE zoneid = ? AND (name IS NULL OR name = '' OR name = '@') AND rrtype = ?
+            ORDER BY id LIMIT 1",
+        )
+        .bind(zone.id)
+        .bind(RecordType::NS as u16)
+        .fetch_optional(&self.read_pool)
+        .await?;
+
+        // the apex always exists, otherwise it's there if it or anything under it has records
+        let name_exists = name.eq_ignore_ascii_case(zone.name.trim_end_matches('.'))
+            || sqlx::query(
+                "SELECT 1 FROM records_merged WHERE rclass = ?2
+                AND (name = ?1 OR substr(name, -length(?1) - 1) = '.' || ?1) LIMIT 1",
+            )
+            .bind(name)
+            .bind(rclass)
+            .fetch_optional(&self.read_pool)
+            .await?
+            .is_some();
+
+        Ok(Some(NegativeAnswer {
+            zone: zone.name.trim_end_matches('.').as_bytes().to_vec(),
+            soa: zone.soa(mname.as_deref().map(|mname| mname.trim_end_matches('.'))),
+            name_exists,
+            zone_enabled: zone.enabled,
+        }))
+    }
+
+    async fn put_record(&self, mut record: FileZoneRecord) -> Result<FileZoneRecord, GoatNsError> {
+        let mut txn = self.pool.begin().await?;
+        if let Some(zoneid) = record.zoneid {
+            let zone_name: String = sqlx::query_scalar("SELECT name FROM zones WHERE id = ?")
+                .bind(zoneid)
+                .fetch_one(&mut *txn)
+                .await?;
+            record.normalise_name(&zone_name)?;
+        }
+        let record = record.save_with_txn(&mut txn).await?;
+        txn.commit().await?;
+        self.invalidate_cache();
+        Ok(*record)
+    }
+
+    async fn get_zone(
+        &self,
+        id: Option<i64>,
+        name: Option<String>,
+    ) -> Result<Option<FileZone>, GoatNsError> {
+        let mut txn = self.pool.begin().await?;
+        db::get_zone_with_txn(&mut txn, id, name).await
+    }
+
+    async fn get_zones_for_user(
+        &self,
+        user: &User,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<FileZone>, GoatNsError> {
+        let mut txn = self.pool.begin().await?;
+        user.get_zones_for_user(&mut txn, offset, limit).await
+    }
+
+    async fn create_zone(&self, mut zone: FileZone, userid: i64) -> Result<FileZone, GoatNsError> {
+        zone.normalise_names()?;
+        zone.check_cname_rules()?;
+        let mut txn = self.pool.begin().await?;
+        let zone = zone.save_with_txn(&mut txn).await?;
+        let zoneid = zone.id.ok_or_else(|| {
+            GoatNsError::Generic(format!("Zone {} didn't get an ID when saved", zone.name))
+        })?;
+        ZoneOwnership {
+            id: None,
+            userid,
+            zoneid,
+        }
+        .save_with_txn(&mut txn)
+        .await?;
+        txn.commit().await?;
+        self.invalidate_cache();
+        Ok(*zone)
+    }
+
+    async fn update_zone(&self, mut zone: FileZone) -> Result<FileZone, GoatNsError> {
+        zone.normalise_names()?;
+        zone.check_cname_rules()?;
+        let zone = zone.save(&self.pool).await?;
+        self.invalidate_cache();
+        Ok(*zone)
+    }
+
+    async fn delete_zone(&self, id: i64) -> Result<(), GoatNsError> {
+        let mut txn = self.pool.begin().await?;
+        FileZone::get_with_txn(&mut txn, &id)
+            .await?
+            .delete_with_txn(&mut txn)
+            .await?;
+        txn.commit().await?;
+        self.invalidate_cache();
+        Ok(())
+    }
+
+    async fn import_zones(
+        &self,
+        zones: Vec<FileZone>,
+        ttl_policy: &ImportTtlPolicy,
+        strategy: ImportStrategy,
+        dry_run: bool,
+        progress: Option<&ImportProgressCallback>,
+    ) -> Result<ImportSummary, GoatNsError> {
+        let mut txn = self.pool.begin().await?;
+        let summary =
+            import_zones_with_txn(&mut txn, zones, ttl_policy, strategy, dry_run, progress).await?;
+        self.finish_import(txn, dry_run).await?;
+        Ok(summary)
+    }
+
+    async fn export_all(&self) -> Result<ZoneBackup, GoatNsError> {
+        // one transaction, so the zones and owners are from the same moment
+        let mut txn = self.pool.begin().await?;
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM zones ORDER BY name")
+            .fetch_all(&mut *txn)
+            .await?;
+        let mut backup = ZoneBackup::default();
+        for id in ids {
+            if let Some(mut zone) = db::get_zone_with_txn(&mut txn, Some(id), None).await? {
+                // the IDs mean nothing anywhere else
+                zone.id = None;
+                for record in zone.records.iter_mut() {
+                    record.id = None;
+                    record.zoneid = None;
+                }
+                backup.zones.push(zone);
+            }
+        }
+        let rows = sqlx::query(
+            "SELECT zones.name, users.username
+            FROM ownership, zones, users
+            WHERE ownership.zoneid = zones.id AND ownership.userid = users.id
+            ORDER BY zones.name, users.username",
+        )
+        .fetch_all(&mut *txn)
+        .await?;
+        for row in rows {
+            backup
+                .owners
+                .entry(row.get("name"))
+                .or_default()
+                .push(row.get("username"));
+        }
+        txn.rollback().await?;
+        Ok(backup)
+    }
+
+    async fn restore_backup(
+        &self,
+        backup: ZoneBackup,
+        ttl_policy: &ImportTtlPolicy,
+        strategy: ImportStrategy,
+        dry_run: bool,
+    ) -> Result<ImportSummary, GoatNsError> {
+        let mut txn = self.pool.begin().await?;
+        let summary =
+            import_zones_with_txn(&mut txn, backup.zones, ttl_policy, strategy, dry_run, None)
+                .await?;
+        for (zone_name, usernames) in backup.owners {
+            let zone = db::get_zone_with_txn(&mut txn, None, Some(zone_name.clone()))
+                .await?
+                .and_then(|zone| zone.id);
+            // skipped zones mightn't be there
+            let Some(zoneid) = zone else {
+                log::warn!("Not restoring owners of {zone_name}, it's not in the database");
+                continue;
+            };
+            for username in usernames {
+                let userid: Option<i64> =
+                    sqlx::query_scalar("SELECT id FROM users WHERE username = ?")
+                        .bind(&username)
+                        .fetch_optional(&mut *txn)
+                        .await?;
+                let Some(userid) = userid else {
+                    log::warn!("Not making {username} an owner of {zone_name}, there's no user with that name");
+                    continue;
+                };
+                sqlx::query(&format!(
+                    "INSERT OR IGNORE INTO {} (zoneid, userid) VALUES (?, ?)",
+                    ZoneOwnership::TABLE
+                ))
+                .bind(zoneid)
+                .bind(userid)
+                .execute(&mut *txn)
+                .await?;
+            }
+        }
+        self.finish_import(txn, dry_run).await?;
+        log::info!("Restored backup: {summary}");
+        Ok(summary)
+    }
+
+    async fn sync_zones(
+        &self,
+        zones: Vec<(String, FileZone)>,
+        ttl_policy: &ImportTtlPolicy,
+        prune: bool,
+    ) -> Result<SyncSummary, GoatNsError> {
+        let mut txn = self.pool.begin().await?;
+        let mut summary = SyncSummary::default();
+        let mut synced = HashSet::new();
+        for (source, mut zone) in zones {
+            zone.id = None;
+            zone.normalise_names()?;
+            let ttl_summary = zone.apply_ttl_policy(ttl_policy);
+            let existing = db::get_zone_with_txn(&mut txn, None, Some(zone.name.clone())).await?;
+            let auto_serial = zone.serial == 0;
+            if auto_serial {
+                zone.serial = match &existing {
+                    Some(existing) => existing.serial,
+                    None => default_serial(),
+                };
+            }
+            let changed = match &existing {
+                Some(existing) => !existing.diff(&zone).is_empty(),
+                None => true,
+            };
+            // the serial was what's there already, and it needs to move on if the zone's changed
+            let bump_serial = changed && auto_serial && existing.is_some();
+            if changed {
+                zone.import_with_txn(&mut txn, ImportStrategy::Replace, ttl_summary)
+                    .await
+                    .map_err(|err| match err {
+                        GoatNsError::InvalidValue(msg) => {
+                            GoatNsError::InvalidValue(format!("{source}: {msg}"))
+                        }
+                        err => err,
+                    })?;
+            }
+            let zoneid = match existing.and_then(|existing| existing.id) {
+                Some(zoneid) => zoneid,
+                None => db::get_zone_with_txn(&mut txn, None, Some(zone.name.clone()))
+                    .await?
+                    .and_then(|zone| zone.id)
+                    .ok_or_else(|| {
+                        GoatNsError::InvalidValue(format!("Zone {} has no ID", zone.name))
+                    })?,
+            };
+            if bump_serial {
+                FileZone::bump_serial_with_txn(zoneid, &mut txn).await?;
+            }
+            let previous = SyncedZone::get(&mut txn, zoneid).await?;
+            if changed || previous.map(|previous| previous.source) != Some(source.clone()) {
+                SyncedZone {
+                    zoneid,
+                    source,
+                    synced: Utc::now(),
+                }
+                .save_with_txn(&mut txn)
+                .await?;
+            }
+            match changed {
+                true => summary.updated.push(zone.name),
+                false => summary.unchanged += 1,
+            }
+            synced.insert(zoneid);
+        }
+
+        for gone in SyncedZone::get_all(&mut txn).await? {
+            if synced.contains(&gone.zoneid) {
+                continue;
+            }
+            let zone = FileZone::get_with_txn(&mut txn, &gone.zoneid).await?;
+            match prune {
+                true => {
+                    zone.delete_with_txn(&mut txn).await?;
+                    summary.deleted.push(zone.name);
+                }
+                false => {
+                    SyncedZone::delete_with_txn(&mut txn, gone.zoneid).await?;
+                    summary.released.push(zone.name);
+                }
+            }
+        }
+        self.finish_import(txn, false).await?;
+        Ok(summary)
+    }
+
+    async fn create_user(&self, user: User) -> Result<Box<User>, GoatNsError> {
+        user.save(&self.pool).await
+    }
+
+    async fn get_ownership(&self, userid: i64) -> Result<Vec<Arc<ZoneOwnership>>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "SELECT * FROM {} WHERE userid = ?",
+            ZoneOwnership::TABLE
+        ))
+        .bind(userid)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(res
+            .into_iter()
+            .map(|row| Arc::new(ZoneOwnership::from(row)))
+            .collect())
+    }
+
+    async fn add_ownership(&self, zoneid: i64, userid: i64) -> Result<ZoneOwnership, GoatNsError> {
+        let ownership = ZoneOwnership {
+            id: None,
+            userid,
+            zoneid,
+        }
+        .save(&self.pool)
+        .await?;
+        Ok(*ownership)
+    }
+
+    async fn delete_ownership(&self, zoneid: i64, userid: Option<i64>) -> Result<(), GoatNsError> {
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE zoneid = ? AND (? IS NULL OR userid = ?)",
+            ZoneOwnership::TABLE
+        ))
+        .bind(zoneid)
+        .bind(userid)
+        .bind(userid)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_zone_ttls(
+        &self,
+        zoneid: i64,
+        ttl: u32,
+        userid: i64,
+    ) -> Result<ZoneJournalEntry, GoatNsError> {
+        let mut txn = self.pool.begin().await?;
+        // make sure it's there, otherwise there's nothing to update and it looks like every TTL matched
+        FileZone::get_with_txn(&mut txn, &zoneid).await?;
+        let entry = FileZone::set_ttls_with_txn(zoneid, ttl, userid, &mut txn).await?;
+        let serial = FileZone::get_serial_with_txn(zoneid, &mut txn).await?;
+        txn.commit().await?;
+        self.invalidate_cache();
+        zone_events::publish(ZoneEvent::SerialBumped { zoneid, serial });
+        Ok(*entry)
+    }
+
+    async fn set_zone_enabled(&self, zoneid: i64, enabled: bool) -> Result<(), GoatNsError> {
+        let mut txn = self.pool.begin().await?;
+        FileZone::set_enabled_with_txn(zoneid, enabled, &mut txn).await?;
+        txn.commit().await?;
+        self.invalidate_cache();
+        Ok(())
+    }
+
+    async fn undo_journal_entry(
+        &self,
+        zoneid: i64,
+        id: i64,
+    ) -> Result<ZoneJournalEntry, GoatNsError> {
+        let mut txn = self.pool.begin().await?;
+        let entry = ZoneJournalEntry::get_with_txn(&mut txn, &id).await?;
+        // so you can't undo another zone's changes by guessing IDs
+        if entry.zoneid != zoneid {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        let entry = entry.undo_with_txn(&mut txn).await?;
+        let serial = FileZone::get_serial_with_txn(zoneid, &mut txn).await?;
+        txn.commit().await?;
+        self.invalidate_cache();
+        zone_events::publish(ZoneEvent::SerialBumped { zoneid, serial });
+        Ok(*entry)
+    }
+
+    fn purge_cache(&self, purge: &CachePurge) -> usize {
+        self.cache.purge(purge)
+    }
+
+    async fn snapshot(&self, path: &Path) -> Result<(), GoatNsError> {
+        let path = path.to_str().ok_or_else(|| {
+            GoatNsError::InvalidValue(format!(
+                "Can't snapshot to {}, it's not UTF-8",
+                path.display()
+            ))
+        })?;
+        sqlx::query("VACUUM INTO ?")
+            .bind(path)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn cleanup(&self) -> Result<(), GoatNsError> {
+        UserAuthToken::cleanup(&self.pool).await?;
+        if FileZoneRecord::cleanup(&self.pool).await? > 0 {
+            self.invalidate_cache();
+        }
+        Ok(())
+    }
+}
@@ -1,20 +1,30 @@
 use crate::error::GoatNsError;
 use crate::resourcerecord::SetTTL;
+use std::collections::HashSet;
+use std::net::IpAddr;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::config::ConfigFile;
+use crate::config::{ConfigFile, QuotaConfig};
 use crate::enums::{RecordClass, RecordType};
 
 use crate::resourcerecord::InternalResourceRecord;
-use crate::zones::{FileZone, FileZoneRecord};
+use crate::zones::{
+    ChangeSetStatus, FileZone, FileZoneRecord, ImportStrategy, ImportSummary,
+    OwnershipTransferStatus, RecordChange, ZoneChangeSet, ZoneJournalEntry, ZoneOwnershipTransfer,
+    ZoneRegistration, ZoneTemplate,
+};
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
 use concread::cowcell::asynch::CowCellReadTxn;
+use futures::future::BoxFuture;
 use openidconnect::SubjectIdentifier;
 use serde::{Deserialize, Serialize};
-use sqlx::sqlite::{SqliteArguments, SqliteConnectOptions, SqliteRow};
+use sqlx::sqlite::{
+    SqliteArguments, SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteRow,
+    SqliteSynchronous,
+};
 use sqlx::{Arguments, ConnectOptions, FromRow, Pool, Row, Sqlite, SqliteConnection, SqlitePool};
 use tokio::time;
 use tracing::{debug, error, instrument};
@@ -25,31 +35,94 @@ pub mod test;
 
 const SQL_VIEW_RECORDS: &str = "records_merged";
 
+// The lookup queries are kept as static strings so they're not formatted on every lookup, keep the view name in sync
+// with SQL_VIEW_RECORDS.
+const SQL_GET_RECORDS: &str = "SELECT
+        record_id, zoneid, name, rclass, rrtype, rdata, ttl
+        FROM records_merged
+        WHERE name = ? AND rrtype = ? AND rclass = ?";
+const SQL_GET_FZR_BY_NAME: &str = "select * from records_merged where name = ?";
+const SQL_GET_FZR_BY_NAME_RRTYPE: &str =
+    "select *, record_id as id from records_merged where name = ? AND rrtype = ?";
+
 /// Setup the database connection and pool
 pub async fn get_conn(
     config_reader: CowCellReadTxn<ConfigFile>,
 ) -> Result<SqlitePool, GoatNsError> {
-    let db_path: &str = &shellexpand::full(&config_reader.sqlite_path)
-        .map_err(|err| GoatNsError::StartupError(err.to_string()))?;
-    let db_url = format!("sqlite://{db_path}?mode=rwc");
+    connect_pool(
+        &config_reader,
+        &config_reader.sqlite_path,
+        false,
+        config_reader.sql_max_connections,
+    )
+    .await
+}
+
+/// The read-only pool for DNS lookups, if `sqlite_read_path` is set
+pub async fn get_read_conn(
+    config_reader: CowCellReadTxn<ConfigFile>,
+) -> Result<Option<SqlitePool>, GoatNsError> {
+    match &config_reader.sqlite_read_path {
+        Some(path) => Ok(Some(
+            connect_pool(
+                &config_reader,
+                path,
+                true,
+                config_reader.sql_read_max_connections,
+            )
+            .await?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Open the main database without being able to change it (or create it if it's not there), for `--doctor`
+pub async fn get_conn_read_only(config: &ConfigFile) -> Result<SqlitePool, GoatNsError> {
+    connect_pool(config, &config.sqlite_path, true, 1).await
+}
+
+async fn connect_pool(
+    config: &ConfigFile,
+    path: &str,
+    read_only: bool,
+    max_connections: u32,
+) -> Result<SqlitePool, GoatNsError> {
+    let db_path: &str =
+        &shellexpand::full(path).map_err(|err| GoatNsError::StartupError(err.to_string()))?;
+    let db_url = match read_only {
+        true => format!("sqlite://{db_path}?mode=ro"),
+        false => format!("sqlite://{db_path}?mode=rwc"),
+    };
     log::debug!("Opening Database: {db_url}");
 
     let options = SqliteConnectOptions::from_str(&db_url)?;
-    let options = if config_reader.sql_log_statements {
+    let options = if config.sql_log_statements {
         options.log_statements(log::LevelFilter::Trace)
     } else {
         options.log_statements(log::LevelFilter::Off)
     };
+    // lookups on a read-only replica don't get a say in how it's journalled
+    let options = if config.sqlite_wal && !read_only {
+        options
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+    } else {
+        options
+    };
     // log anything that takes longer than 1s
     let options = options.log_slow_statements(
         log::LevelFilter::Warn,
-        Duration::from_secs(config_reader.sql_log_slow_duration),
+        Duration::from_secs(config.sql_log_slow_duration),
     );
 
-    SqlitePool::connect_with(options).await.map_err(|err| {
-        error!("Error opening SQLite DB ({db_url:?}): {err:?}");
-        err.into()
-    })
+    SqlitePoolOptions::new()
+        .max_connections(max_connections)
+        .connect_with(options)
+        .await
+        .map_err(|err| {
+            error!("Error opening SQLite DB ({db_url:?}): {err:?}");
+            err.into()
+        })
 }
 
 /// Do the basic setup and checks (if we write any)
@@ -59,10 +132,70 @@ pub async fn start_db(pool: &SqlitePool) -> Result<(), GoatNsError> {
     UserAuthToken::create_table(pool).await?;
     FileZoneRecord::create_table(pool).await?;
     ZoneOwnership::create_table(pool).await?;
+    ZoneTemplate::create_table(pool).await?;
+    ZoneChangeSet::create_table(pool).await?;
+    ZoneJournalEntry::create_table(pool).await?;
+    ZoneOwnershipTransfer::create_table(pool).await?;
+    OidcCache::create_table(pool).await?;
+    ReservedName::create_table(pool).await?;
+    SyncedZone::create_table(pool).await?;
+    UserSettings::create_table(pool).await?;
+    UserQuota::create_table(pool).await?;
+    DohUsage::create_table(pool).await?;
     log::info!("Completed DB Startup!");
     Ok(())
 }
 
+/// How many goes [with_write_txn] gets before it gives up
+pub const WRITE_TXN_ATTEMPTS: u32 = 5;
+
+/// SQLite only lets one transaction write at a time, and one that read something before another one committed has to
+/// start again. This is it saying so.
+pub fn is_write_conflict(err: &GoatNsError) -> bool {
+    match err {
+        GoatNsError::SqlxError(sqlx::Error::Database(err)) => err
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            // the extended codes have the primary one in the bottom byte, SQLITE_BUSY and SQLITE_LOCKED
+            .is_some_and(|code| matches!(code & 0xff, 5 | 6)),
+        _ => false,
+    }
+}
+
+/// Runs `f` in a transaction and commits it if it returns `Ok(Ok(_))`, `Ok(Err(_))` rolls it back and hands the error
+/// back. If it fails because another writer got in first (see [is_write_conflict]) the whole thing's tried again, up
+/// to [WRITE_TXN_ATTEMPTS] times, so `f` needs to be fine with running more than once.
+pub async fn with_write_txn<T, E, F>(
+    pool: &SqlitePool,
+    mut f: F,
+) -> Result<Result<T, E>, GoatNsError>
+where
+    F: for<'c> FnMut(&'c mut SqliteConnection) -> BoxFuture<'c, Result<Result<T, E>, GoatNsError>>,
+{
+    let mut attempt = 1;
+    loop {
+        let res: Result<Result<T, E>, GoatNsError> = async {
+            let mut txn = pool.begin().await?;
+            let res = f(&mut *txn).await?;
+            if res.is_ok() {
+                txn.commit().await?;
+            }
+            Ok(res)
+        }
+        .await;
+        match res {
+            Err(err) if attempt < WRITE_TXN_ATTEMPTS && is_write_conflict(&err) => {
+                log::debug!("Write conflict on attempt {attempt}, trying again: {err:?}");
+                // a bit of jitter so they don't all come back at once
+                let wait = 10 * u64::from(attempt) + rand::random::<u64>() % 10;
+                time::sleep(Duration::from_millis(wait)).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
 #[derive(Clone, Deserialize, Serialize, Debug)]
 /// DB Representation of a user
 pub struct User {
@@ -305,11 +438,12 @@ pub async fn get_zone_with_txn(
 ) -> Result<Option<FileZone>, GoatNsError> {
     let result = sqlx::query(
         "SELECT
-        id, name, rname, serial, refresh, retry, expire, minimum
+        id, name, rname, serial, refresh, retry, expire, minimum, review_required, default_ttl, enabled, public,
+        registrar, domain_expires, notes
         FROM zones
-        WHERE name = ? or id = ? LIMIT 1",
+        WHERE lower(rtrim(name, '.')) = ? or id = ? LIMIT 1",
     )
-    .bind(name)
+    .bind(name.as_deref().map(crate::name_policy::normalise))
     .bind(id)
     .fetch_optional(&mut *txn)
     .await?;
@@ -327,7 +461,12 @@ pub async fn get_zone_with_txn(
                 retry: row.get(5),
                 expire: row.get(6),
                 minimum: row.get(7),
+                default_ttl: row.get(9),
                 records: vec![],
+                review_required: row.get(8),
+                enabled: row.get(10),
+                public: row.get(11),
+                registration: registration_from_row(&row),
             }
         }
     };
@@ -341,7 +480,7 @@ pub async fn get_zone_with_txn(
 
     let result = sqlx::query(
         "SELECT
-        id, zoneid, name, ttl, rrtype, rclass, rdata
+        id, zoneid, name, ttl, rrtype, rclass, rdata, comment, tags, expires_at
         FROM records
         WHERE zoneid = ?",
     )
@@ -365,7 +504,7 @@ impl TryFrom<SqliteRow> for InternalResourceRecord {
         let record_id: i64 = row.get(0);
         let record_class: u16 = row.get(3);
         let record_type: u16 = row.get(4);
-        let rrtype: &str = RecordType::from(&record_type).into();
+        let rrtype = crate::generic::type_name(record_type);
         let rdata: String = row.get(5);
         let ttl: u32 = row.get(6);
         InternalResourceRecord::try_from(FileZoneRecord {
@@ -373,9 +512,12 @@ impl TryFrom<SqliteRow> for InternalResourceRecord {
             ttl,
             zoneid: row.get("zoneid"),
             id: Some(record_id),
-            rrtype: rrtype.to_string(),
+            rrtype,
             class: RecordClass::from(&record_class),
             rdata,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
         })
     }
 }
@@ -390,23 +532,30 @@ pub async fn get_records(
     rclass: RecordClass,
     normalize_ttls: bool,
 ) -> Result<Vec<InternalResourceRecord>, GoatNsError> {
-    let query = format!(
-        "SELECT
-        record_id, zoneid, name, rclass, rrtype, rdata, ttl
-        FROM {}
-        WHERE name = ? AND rrtype = ? AND rclass = ?",
-        SQL_VIEW_RECORDS
-    );
+    get_records_by_code(conn, name, rrtype as u16, rclass, normalize_ttls).await
+}
 
-    let res = sqlx::query(&query)
+/// Like [get_records], but by the type code so it works for types we don't have a [RecordType] for
+pub async fn get_records_by_code(
+    conn: &Pool<Sqlite>,
+    name: String,
+    rrtype: u16,
+    rclass: RecordClass,
+    normalize_ttls: bool,
+) -> Result<Vec<InternalResourceRecord>, GoatNsError> {
+    let res = sqlx::query(SQL_GET_RECORDS)
+        .persistent(true)
         .bind(&name)
-        .bind(rrtype as u16)
+        .bind(rrtype)
         .bind(rclass)
-        .fetch_all(&mut *conn.acquire().await?)
+        .fetch_all(conn)
         .await?;
 
     if res.is_empty() {
-        eprintln!("No results returned for {name} {rrtype} {rclass}");
+        eprintln!(
+            "No results returned for {name} {} {rclass}",
+            crate::generic::type_name(rrtype)
+        );
         log::trace!("No results returned for {name} ");
     }
 
@@ -433,11 +582,7 @@ pub async fn get_records(
                 }
             };
 
-            results
-                .to_vec()
-                .iter()
-                .map(|r| r.clone().set_ttl(min_ttl))
-                .collect()
+            results.into_iter().map(|r| r.set_ttl(min_ttl)).collect()
         }
         false => {
             #[cfg(test)]
@@ -452,7 +597,7 @@ impl FileZone {
     pub async fn with_zone_records(self, txn: &mut SqliteConnection) -> Self {
         let records: Vec<FileZoneRecord> = match sqlx::query(
             "SELECT
-            id, zoneid, name, ttl, rrtype, rclass, rdata
+            id, zoneid, name, ttl, rrtype, rclass, rdata, comment, tags, expires_at
             FROM records
             WHERE zoneid = ?",
         )
@@ -479,7 +624,7 @@ impl FileZone {
             Some(id) => {
                 let res = sqlx::query(
                     "SELECT
-                    id, zoneid, name, ttl, rrtype, rclass, rdata
+                    id, zoneid, name, ttl, rrtype, rclass, rdata, comment, tags, expires_at
                     FROM records
                     WHERE zoneid = ?",
                 )
@@ -503,25 +648,354 @@ impl FileZone {
         }
     }
 
-    pub async fn get_orphans(pool: &SqlitePool) -> Result<Vec<FileZone>, GoatNsError> {
+    /// Zones nobody owns, eg ones loaded from the CLI or restored without their owners
+    pub async fn get_orphans(txn: &mut SqliteConnection) -> Result<Vec<FileZone>, GoatNsError> {
         let res = sqlx::query(
             "
-            SELECT name, userid from zones
+            SELECT zones.* from zones
             LEFT OUTER JOIN ownership on zones.id = ownership.zoneid
-            where userid IS NULL",
+            where ownership.userid IS NULL
+            ORDER BY zones.name",
         )
-        .fetch_all(&mut *pool.acquire().await?)
+        .fetch_all(&mut *txn)
         .await?;
         let res: Vec<FileZone> = res.into_iter().map(|r| r.into()).collect();
         Ok(res)
     }
+
+    /// Give the orphaned zones out of `zoneids` to the user, zones that have an owner (or don't exist) are left alone.
+    /// Returns the names of the ones that were assigned.
+    pub async fn assign_orphans(
+        txn: &mut SqliteConnection,
+        zoneids: &[i64],
+        userid: i64,
+    ) -> Result<Vec<String>, GoatNsError> {
+        let mut assigned = vec![];
+        for zoneid in zoneids {
+            let res = sqlx::query(
+                "INSERT INTO ownership (zoneid, userid)
+                SELECT id, ? FROM zones
+                WHERE id = ? AND id NOT IN (SELECT zoneid FROM ownership)",
+            )
+            .bind(userid)
+            .bind(zoneid)
+            .execute(&mut *txn)
+            .await?;
+            if res.rows_affected() > 0 {
+                let name: String = sqlx::query_scalar("SELECT name FROM zones WHERE id = ?")
+                    .bind(zoneid)
+                    .fetch_one(&mut *txn)
+                    .await?;
+                assigned.push(name);
+            }
+        }
+        Ok(assigned)
+    }
+
+    /// Write an imported zone using the given strategy. `summary` comes from [FileZone::apply_ttl_policy] and
+    /// gets updated with what actually happened to the records.
+    pub async fn import_with_txn(
+        &self,
+        txn: &mut SqliteConnection,
+        strategy: ImportStrategy,
+        mut summary: ImportSummary,
+    ) -> Result<ImportSummary, GoatNsError> {
+        self.check_cname_rules()?;
+        let existing = match get_zone_with_txn(txn, None, Some(self.name.clone())).await? {
+            Some(val) => val,
+            None => {
+                self.save_with_txn(txn).await?;
+                return Ok(summary);
+            }
+        };
+
+        if strategy == ImportStrategy::Replace {
+            let new_keys: HashSet<_> = self.records.iter().map(|r| r.import_key()).collect();
+            summary.removed += existing
+                .records
+                .iter()
+                .filter(|r| !new_keys.contains(&r.import_key()))
+                .count();
+            self.save_with_txn(txn).await?;
+            return Ok(summary);
+        }
+
+        let zoneid = existing.id.ok_or_else(|| {
+            GoatNsError::InvalidValue(format!("Zone {} has no ID", existing.name))
+        })?;
+        if strategy == ImportStrategy::Merge && !self.matching_data(&existing) {
+            FileZone {
+                id: Some(zoneid),
+                records: vec![],
+                ..self.clone()
+            }
+            .update_with_txn(txn)
+            .await?;
+        }
+
+        let existing_names: HashSet<String> =
+            existing.records.iter().map(|r| r.import_key().0).collect();
+        let mut changed = false;
+        for record in &self.records {
+            let key = record.import_key();
+            if strategy == ImportStrategy::SkipExisting && existing_names.contains(&key.0) {
+                summary.imported = summary.imported.saturating_sub(1);
+                summary.skipped += 1;
+                continue;
+            }
+            if let Some(current) = existing.records.iter().find(|r| r.import_key() == key) {
+                summary.imported = summary.imported.saturating_sub(1);
+                if current.ttl == record.ttl
+                    && current.comment == record.comment
+                    && current.tags == record.tags
+                    && current.expires_at == record.expires_at
+                {
+                    summary.unchanged += 1;
+                    continue;
+                }
+                FileZoneRecord {
+                    id: current.id,
+                    zoneid: Some(zoneid),
+                    name: current.name.clone(),
+                    ..record.clone()
+                }
+                .update_with_txn(txn)
+                .await?;
+                summary.updated += 1;
+                changed = true;
+                continue;
+            }
+            FileZoneRecord {
+                id: None,
+                zoneid: Some(zoneid),
+                name: key.0,
+                ..record.clone()
+            }
+            .save_with_txn(txn)
+            .await?;
+            changed = true;
+        }
+        if changed {
+            // what's there already might clash with what we've added
+            FileZone::check_cname_rules_with_txn(zoneid, txn).await?;
+            FileZone::bump_serial_with_txn(zoneid, txn).await?;
+        }
+        Ok(summary)
+    }
+
+    /// Check the zone's records as they are in the transaction follow the CNAME rules, do this after changing them and before committing
+    pub async fn check_cname_rules_with_txn(
+        zoneid: i64,
+        txn: &mut SqliteConnection,
+    ) -> Result<(), GoatNsError> {
+        match get_zone_with_txn(txn, Some(zoneid), None).await? {
+            Some(zone) => zone.check_cname_rules(),
+            None => Err(sqlx::Error::RowNotFound.into()),
+        }
+    }
+
+    /// The zone's serial, without loading the rest of it
+    pub async fn get_serial_with_txn(
+        zoneid: i64,
+        txn: &mut SqliteConnection,
+    ) -> Result<u32, GoatNsError> {
+        let serial: Option<u32> = sqlx::query_scalar("SELECT serial FROM zones WHERE id = ?")
+            .bind(zoneid)
+            .fetch_optional(&mut *txn)
+            .await?;
+        serial.ok_or_else(|| sqlx::Error::RowNotFound.into())
+    }
+
+    /// Bump the zone's serial so secondaries know something's changed, it's done in the one statement so writers
+    /// can't trip over each other. It ends up as [crate::zones::next_serial] of what was there.
+    pub async fn bump_serial_with_txn(
+        zoneid: i64,
+        txn: &mut SqliteConnection,
+    ) -> Result<(), GoatNsError> {
+        let res =
+            sqlx::query("UPDATE zones SET serial = MAX((serial + 1) % 4294967296, ?) WHERE id = ?")
+                .bind(crate::zones::serial_floor())
+                .bind(zoneid)
+                .execute(&mut *txn)
+                .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(())
+    }
+
+    /// Set the TTL that records without one get, `None` means the server's import default. It doesn't change any
+    /// records, so the serial stays where it is
+    pub async fn set_default_ttl_with_txn(
+        zoneid: i64,
+        default_ttl: Option<u32>,
+        txn: &mut SqliteConnection,
+    ) -> Result<(), GoatNsError> {
+        let res = sqlx::query("UPDATE zones SET default_ttl = ? WHERE id = ?")
+            .bind(default_ttl)
+            .bind(zoneid)
+            .execute(&mut *txn)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(())
+    }
+
+    /// Imports that don't say what the default TTL is (eg a zone file without `$TTL`) keep the one the zone's got
+    pub async fn inherit_default_ttl_with_txn(
+        &mut self,
+        txn: &mut SqliteConnection,
+    ) -> Result<(), GoatNsError> {
+        if self.default_ttl.is_none() {
+            if let Some(existing) = FileZone::get_by_name(txn, &self.name).await? {
+                self.default_ttl = existing.default_ttl;
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the TTL of every record in the zone, and journal what they were so it can be undone
+    pub async fn set_ttls_with_txn(
+        zoneid: i64,
+        ttl: u32,
+        userid: i64,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<ZoneJournalEntry>, GoatNsError> {
+        let previous: Vec<(i64, u32)> =
+            sqlx::query_as("SELECT id, ttl FROM records WHERE zoneid = ? AND ttl != ? ORDER BY id")
+                .bind(zoneid)
+                .bind(ttl)
+                .fetch_all(&mut *txn)
+                .await?;
+        if previous.is_empty() {
+            return Err(GoatNsError::InvalidValue(format!(
+                "All the records already have a TTL of {ttl}"
+            )));
+        }
+        sqlx::query("UPDATE records SET ttl = ? WHERE zoneid = ?")
+            .bind(ttl)
+            .bind(zoneid)
+            .execute(&mut *txn)
+            .await?;
+        FileZone::bump_serial_with_txn(zoneid, &mut *txn).await?;
+
+        ZoneJournalEntry {
+            id: None,
+            zoneid,
+            userid,
+            description: format!("Set the TTL of {} records to {ttl}", previous.len()),
+            created: Utc::now(),
+            undo: previous
+                .into_iter()
+                .map(|(id, ttl)| RecordChange::SetTtl { id, ttl })
+                .collect(),
+            undone: false,
+        }
+        .save_with_txn(txn)
+        .await
+    }
+
+    /// Turn the zone on or off, the records stay where they are either way
+    pub async fn set_enabled_with_txn(
+        zoneid: i64,
+        enabled: bool,
+        txn: &mut SqliteConnection,
+    ) -> Result<(), GoatNsError> {
+        let res = sqlx::query("UPDATE zones SET enabled = ? WHERE id = ?")
+            .bind(enabled)
+            .bind(zoneid)
+            .execute(&mut *txn)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(())
+    }
+
+    /// Make the zone show up in [crate::web::rdap] or not
+    pub async fn set_public_with_txn(
+        zoneid: i64,
+        public: bool,
+        txn: &mut SqliteConnection,
+    ) -> Result<(), GoatNsError> {
+        let res = sqlx::query("UPDATE zones SET public = ? WHERE id = ?")
+            .bind(public)
+            .bind(zoneid)
+            .execute(&mut *txn)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(())
+    }
+
+    /// Set the registrar, domain expiry and notes, all at once
+    pub async fn set_registration_with_txn(
+        zoneid: i64,
+        registration: &ZoneRegistration,
+        txn: &mut SqliteConnection,
+    ) -> Result<(), GoatNsError> {
+        let res = sqlx::query(
+            "UPDATE zones SET registrar = ?, domain_expires = ?, notes = ? WHERE id = ?",
+        )
+        .bind(&registration.registrar)
+        .bind(registration.expires.map(|expires| expires.to_string()))
+        .bind(&registration.notes)
+        .bind(zoneid)
+        .execute(&mut *txn)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(())
+    }
+
+    /// Public zones with names matching `pattern`, where `*` matches anything, without their records
+    pub async fn get_public_with_txn(
+        txn: &mut SqliteConnection,
+        pattern: &str,
+        limit: i64,
+    ) -> Result<Vec<FileZone>, GoatNsError> {
+        let pattern = pattern
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+            .replace('*', "%");
+        let rows = sqlx::query(&format!(
+            "SELECT * FROM {} WHERE public AND rtrim(name, '.') LIKE ? ESCAPE '\\' ORDER BY name LIMIT ?",
+            Self::TABLE
+        ))
+        .bind(pattern)
+        .bind(limit)
+        .fetch_all(&mut *txn)
+        .await?;
+        Ok(rows.into_iter().map(FileZone::from).collect())
+    }
+
+    /// The NS records at the zone's apex
+    pub async fn apex_nameservers_with_txn(
+        zoneid: i64,
+        txn: &mut SqliteConnection,
+    ) -> Result<Vec<String>, GoatNsError> {
+        let nameservers: Vec<String> = sqlx::query_scalar(
+            "SELECT rdata FROM records WHERE zoneid = ? AND (name IS NULL OR name = '' OR name = '@') AND rrtype = ?
+            ORDER BY rdata",
+        )
+        .bind(zoneid)
+        .bind(RecordType::NS as u16)
+        .fetch_all(&mut *txn)
+        .await?;
+        Ok(nameservers)
+    }
 }
 
+/// Export a zone in the versioned format from [crate::zone_schema]
 pub async fn export_zone_json(pool: &SqlitePool, id: i64) -> Result<String, String> {
     let zone = FileZone::get(pool, id)
         .await
         .map_err(|e| format!("{e:?}"))?;
-    zone.json()
+    crate::zone_schema::zones_to_json(&[*zone]).map_err(|e| format!("{e:?}"))
 }
 
 #[async_trait]
@@ -594,12 +1068,76 @@ impl DBEntity for FileZone {
                 refresh INTEGER NOT NULL,
                 retry INTEGER NOT NULL,
                 expire INTEGER NOT NULL,
-                minimum INTEGER NOT NULL
+                minimum INTEGER NOT NULL,
+                review_required BOOL NOT NULL DEFAULT 0,
+                default_ttl INTEGER,
+                enabled BOOL NOT NULL DEFAULT 1,
+                public BOOL NOT NULL DEFAULT 0,
+                registrar TEXT,
+                domain_expires TEXT,
+                notes TEXT
             )"#,
         )
         .execute(&mut *tx)
         .await?;
 
+        let columns: Vec<String> = sqlx::query(&format!("PRAGMA table_info({})", Self::TABLE))
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(|row| row.get("name"))
+            .collect();
+        if !columns.iter().any(|c| c == "review_required") {
+            log::info!(
+                "Adding the review_required column to the {} table",
+                Self::TABLE
+            );
+            sqlx::query(&format!(
+                "ALTER TABLE \"{}\" ADD COLUMN review_required BOOL NOT NULL DEFAULT 0",
+                Self::TABLE
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+        if !columns.iter().any(|c| c == "default_ttl") {
+            log::info!("Adding the default_ttl column to the {} table", Self::TABLE);
+            sqlx::query(&format!(
+                "ALTER TABLE \"{}\" ADD COLUMN default_ttl INTEGER",
+                Self::TABLE
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+        if !columns.iter().any(|c| c == "enabled") {
+            log::info!("Adding the enabled column to the {} table", Self::TABLE);
+            sqlx::query(&format!(
+                "ALTER TABLE \"{}\" ADD COLUMN enabled BOOL NOT NULL DEFAULT 1",
+                Self::TABLE
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+        if !columns.iter().any(|c| c == "public") {
+            log::info!("Adding the public column to the {} table", Self::TABLE);
+            sqlx::query(&format!(
+                "ALTER TABLE \"{}\" ADD COLUMN public BOOL NOT NULL DEFAULT 0",
+                Self::TABLE
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+        for column in ["registrar", "domain_expires", "notes"] {
+            if !columns.iter().any(|c| c == column) {
+                log::info!("Adding the {column} column to the {} table", Self::TABLE);
+                sqlx::query(&format!(
+                    "ALTER TABLE \"{}\" ADD COLUMN {column} TEXT",
+                    Self::TABLE
+                ))
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
         // .execute(tx).await;
         log::debug!("Ensuring DB Records index exists");
         sqlx::query(
@@ -612,6 +1150,14 @@ impl DBEntity for FileZone {
         )
         .execute(&mut *tx)
         .await?;
+        // finding the zone a name's in looks zones up like this, see SqliteStore::get_closest_zone
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS
+            ind_zones_lookup_name
+            ON zones ( lower(rtrim(name, '.')) )",
+        )
+        .execute(&mut *tx)
+        .await?;
         tx.commit().await?;
         Ok(())
     }
@@ -644,7 +1190,7 @@ impl DBEntity for FileZone {
 
         let records = sqlx::query(
             "SELECT
-            id, zoneid, name, ttl, rrtype, rclass, rdata
+            id, zoneid, name, ttl, rrtype, rclass, rdata, comment, tags, expires_at
             FROM records
             WHERE zoneid = ?",
         )
@@ -666,10 +1212,13 @@ impl DBEntity for FileZone {
         txn: &mut SqliteConnection,
         name: &str,
     ) -> Result<Option<Box<Self>>, GoatNsError> {
-        match sqlx::query(&format!("SELECT * from {} where name=?", Self::TABLE))
-            .bind(name)
-            .fetch_one(&mut *txn)
-            .await
+        match sqlx::query(&format!(
+            "SELECT * from {} where lower(rtrim(name, '.'))=?",
+            Self::TABLE
+        ))
+        .bind(crate::name_policy::normalise(name))
+        .fetch_one(&mut *txn)
+        .await
         {
             Ok(val) => Ok(Some(Box::new(val.into()))),
             Err(sqlx::Error::RowNotFound) => Ok(None),
@@ -730,8 +1279,8 @@ impl DBEntity for FileZone {
                 let minimum = self.minimum.to_string();
 
                 sqlx::query(
-                    "INSERT INTO zones (id, name, rname, serial, refresh, retry, expire, minimum)
-                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    "INSERT INTO zones (id, name, rname, serial, refresh, retry, expire, minimum, review_required, default_ttl, enabled, public, registrar, domain_expires, notes)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
                 )
                 .bind(self.id)
                 .bind(&self.name)
@@ -741,6 +1290,13 @@ impl DBEntity for FileZone {
                 .bind(&retry)
                 .bind(&expire)
                 .bind(&minimum)
+                .bind(self.review_required)
+                .bind(self.default_ttl)
+                .bind(self.enabled)
+                .bind(self.public)
+                .bind(&self.registration.registrar)
+                .bind(self.registration.expires.map(|expires| expires.to_string()))
+                .bind(&self.registration.notes)
                 .execute(&mut *txn)
                 .await?;
 
@@ -788,16 +1344,20 @@ impl DBEntity for FileZone {
             .await?;
 
         // add the records for the zone
-        for mut record in self.records.clone() {
-            record.zoneid = updated_zone.id;
-            #[cfg(test)]
-            eprintln!("Creating new zone record: {record:?}");
-            log::trace!("Creating new zone record: {record:?}");
-            if record.name == "@" {
-                record.name = "".to_string();
-            }
-            record.save_with_txn(txn).await?;
-        }
+        let records: Vec<FileZoneRecord> = self
+            .records
+            .iter()
+            .map(|record| FileZoneRecord {
+                zoneid: updated_zone.id,
+                name: match record.name.as_str() {
+                    "@" => "".to_string(),
+                    _ => record.name.clone(),
+                },
+                ..record.clone()
+            })
+            .collect();
+        log::trace!("Creating {} new zone records", records.len());
+        FileZoneRecord::insert_many_with_txn(&records, txn).await?;
 
         debug!("Done creating zone!");
 
@@ -823,7 +1383,7 @@ impl DBEntity for FileZone {
     ) -> Result<Box<Self>, GoatNsError> {
         let _res = sqlx::query(
             "UPDATE zones
-            set rname = ?, serial = ?, refresh = ?, retry = ?, expire = ?, minimum =?
+            set rname = ?, serial = ?, refresh = ?, retry = ?, expire = ?, minimum =?, review_required = ?, default_ttl = ?
             WHERE id = ?",
         )
         .bind(&self.rname)
@@ -832,6 +1392,8 @@ impl DBEntity for FileZone {
         .bind(self.retry)
         .bind(self.expire)
         .bind(self.minimum)
+        .bind(self.review_required)
+        .bind(self.default_ttl)
         .bind(self.id)
         .execute(txn)
         .await?;
@@ -856,6 +1418,26 @@ impl DBEntity for FileZone {
             .execute(&mut *txn)
             .await?;
 
+        sqlx::query(&format!(
+            "DELETE FROM {} where zoneid = ?",
+            SyncedZone::TABLE
+        ))
+        .bind(self.id)
+        .execute(&mut *txn)
+        .await?;
+
+        // the foreign keys won't let the zone go while anything still points at it
+        for table in [
+            ZoneChangeSet::TABLE,
+            ZoneJournalEntry::TABLE,
+            ZoneOwnershipTransfer::TABLE,
+        ] {
+            sqlx::query(&format!("DELETE FROM {table} where zoneid = ?"))
+                .bind(self.id)
+                .execute(&mut *txn)
+                .await?;
+        }
+
         // delete all the records
         sqlx::query("DELETE FROM records where zoneid = ?")
             .bind(self.id)
@@ -881,11 +1463,28 @@ impl From<SqliteRow> for FileZone {
             retry: input.get("retry"),
             expire: input.get("expire"),
             minimum: input.get("minimum"),
+            default_ttl: input.try_get("default_ttl").unwrap_or(None),
             records: vec![], // can't fill this out yet
+            review_required: input.try_get("review_required").unwrap_or(false),
+            enabled: input.try_get("enabled").unwrap_or(true),
+            public: input.try_get("public").unwrap_or(false),
+            registration: registration_from_row(&input),
         }
     }
 }
 
+/// The expiry's stored as `YYYY-MM-DD`, anything else is treated as not being set
+fn registration_from_row(row: &SqliteRow) -> ZoneRegistration {
+    ZoneRegistration {
+        registrar: row.try_get("registrar").unwrap_or(None),
+        expires: row
+            .try_get::<Option<String>, _>("domain_expires")
+            .unwrap_or(None)
+            .and_then(|expires| chrono::NaiveDate::parse_from_str(&expires, "%Y-%m-%d").ok()),
+        notes: row.try_get("notes").unwrap_or(None),
+    }
+}
+
 #[async_trait]
 impl DBEntity for FileZoneRecord {
     const TABLE: &'static str = "records";
@@ -905,11 +1504,37 @@ impl DBEntity for FileZoneRecord {
             rrtype  INTEGER NOT NULL,
             rclass  INTEGER NOT NULL,
             rdata   TEXT NOT NULL,
+            comment TEXT,
+            tags    TEXT, /* JSON object of key/value pairs */
+            expires_at INTEGER, /* unix timestamp, it's deleted after this */
             FOREIGN KEY(zoneid) REFERENCES zones(id)
         )",
         )
         .execute(&mut *tx)
         .await?;
+
+        // add the columns that came later
+        let columns: Vec<String> = sqlx::query(&format!("PRAGMA table_info({})", Self::TABLE))
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(|row| row.get("name"))
+            .collect();
+        for (column, column_type) in [
+            ("comment", "TEXT"),
+            ("tags", "TEXT"),
+            ("expires_at", "INTEGER"),
+        ] {
+            if !columns.iter().any(|c| c == column) {
+                log::info!("Adding the {column} column to the {} table", Self::TABLE);
+                sqlx::query(&format!(
+                    "ALTER TABLE \"{}\" ADD COLUMN {column} {column_type}",
+                    Self::TABLE
+                ))
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
         log::debug!("Ensuring DB Records index exists");
         sqlx::query(
             "CREATE UNIQUE INDEX
@@ -921,20 +1546,33 @@ impl DBEntity for FileZoneRecord {
         )
         .execute(&mut *tx)
         .await?;
-        log::debug!("Ensuring DB Records view exists");
-        // this view lets us query based on the full name
+        // lookups are on the view's full name, which is worked out from the zone's, so an index on the stored name
+        // never got used and only slowed down writes
+        sqlx::query("DROP INDEX IF EXISTS ind_records_lookup")
+            .execute(&mut *tx)
+            .await?;
+        log::debug!("Ensuring DB Records view is up to date");
+        // views don't hold any data, so it's rebuilt every time in case the definition's changed
+        sqlx::query(&format!("DROP VIEW IF EXISTS {}", SQL_VIEW_RECORDS))
+            .execute(&mut *tx)
+            .await?;
+        // this view lets us query based on the full name, which is lowercased without a trailing dot to match lookups.
+        // names are stored relative to the zone, but older rows might be fully-qualified with a trailing dot.
+        // expired records are left out, so they stop being served before the cleanup gets to them
         sqlx::query(
-        &format!("CREATE VIEW IF NOT EXISTS {} ( record_id, zoneid, rrtype, rclass, rdata, name, ttl ) as
+        &format!("CREATE VIEW {} ( record_id, zoneid, rrtype, rclass, rdata, name, ttl ) as
         SELECT records.id as record_id, zones.id as zoneid, records.rrtype, records.rclass ,records.rdata,
         CASE
-            WHEN records.name is NULL THEN zones.name
-            ELSE records.name || '.' || zones.name
+            WHEN records.name is NULL OR records.name = '' OR records.name = '@' THEN lower(rtrim(zones.name, '.'))
+            WHEN records.name LIKE '%.' THEN lower(rtrim(records.name, '.'))
+            ELSE lower(rtrim(records.name, '.') || '.' || rtrim(zones.name, '.'))
         END AS name,
         CASE WHEN records.ttl is NULL then zones.minimum
             WHEN records.ttl > zones.minimum THEN records.ttl
             ELSE records.ttl
         END AS ttl
-        from records, zones where records.zoneid = zones.id", SQL_VIEW_RECORDS)
+        from records, zones where records.zoneid = zones.id AND zones.enabled
+            AND (records.expires_at IS NULL OR records.expires_at > CAST(strftime('%s', 'now') AS INTEGER))", SQL_VIEW_RECORDS)
     ).execute(&mut *tx).await?;
         tx.commit().await?;
         Ok(())
@@ -971,13 +1609,11 @@ impl DBEntity for FileZoneRecord {
         txn: &mut SqliteConnection,
         name: &str,
     ) -> Result<Vec<Box<Self>>, GoatNsError> {
-        let res = sqlx::query(&format!(
-            "select * from {} where name = ?",
-            SQL_VIEW_RECORDS
-        ))
-        .bind(name)
-        .fetch_all(txn)
-        .await?;
+        let res = sqlx::query(SQL_GET_FZR_BY_NAME)
+            .persistent(true)
+            .bind(name)
+            .fetch_all(txn)
+            .await?;
         let res = res
             .iter()
             .filter_map(|r| match FileZoneRecord::try_from(r) {
@@ -1029,7 +1665,7 @@ impl DBEntity for FileZoneRecord {
         eprintln!(
             "save_with_txn rtype: {} => {}",
             self.rrtype.clone(),
-            RecordType::from(self.rrtype.clone())
+            self.rrtype_code()
         );
         let existing_record = sqlx::query("SELECT id, zoneid, name, ttl, rrtype, rclass, rdata from records WHERE
         id = ? AND  zoneid = ? AND  name = ? AND  ttl = ? AND  rrtype = ? AND  rclass = ? AND rdata = ? LIMIT 1")
@@ -1037,7 +1673,7 @@ impl DBEntity for FileZoneRecord {
             .bind(self.zoneid) // TODO zoneid could be a none, which would work out bad
             .bind(&record_name)
             .bind(self.ttl)
-            .bind(RecordType::from(self.rrtype.clone()))
+            .bind(self.rrtype_code())
             .bind(self.class)
             .bind(self.rdata.to_string())
             .fetch_optional(&mut *txn).await?;
@@ -1046,9 +1682,12 @@ impl DBEntity for FileZoneRecord {
         args.add(self.zoneid);
         args.add(record_name);
         args.add(self.ttl);
-        args.add(RecordType::from(self.rrtype.clone()));
+        args.add(self.rrtype_code());
         args.add(self.class);
         args.add(self.clone().rdata);
+        args.add(self.comment.clone());
+        args.add(self.tags_json());
+        args.add(self.expires_at_timestamp());
 
         if let Some(er) = &existing_record {
             let id: i64 = er.get("id");
@@ -1060,36 +1699,42 @@ impl DBEntity for FileZoneRecord {
                 #[cfg(test)]
                 eprintln!("Found an existing record while saving!");
                 sqlx::query_with(
-                    "UPDATE records set zoneid = ?1, name = ?2, ttl = ?3, rrtype = ?4, rclass = ?5, rdata = ?6
-                            WHERE id =?
+                    "UPDATE records set zoneid = ?1, name = ?2, ttl = ?3, rrtype = ?4, rclass = ?5, rdata = ?6, comment = ?7, tags = ?8, expires_at = ?9
+                            WHERE id = ?10
                         ",
                     args,
                 )
             }
             None => match self.id {
                 Some(id) => sqlx::query(
-                    "INSERT INTO records (id, zoneid, name, ttl, rrtype, rclass, rdata)
-                                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                    "INSERT INTO records (id, zoneid, name, ttl, rrtype, rclass, rdata, comment, tags, expires_at)
+                                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
                                 ",
                 )
                 .bind(id)
                 .bind(self.zoneid)
                 .bind(self.name.clone())
                 .bind(self.ttl)
-                .bind(RecordType::from(self.rrtype.clone()))
+                .bind(self.rrtype_code())
                 .bind(self.class)
-                .bind(self.rdata.clone()),
+                .bind(self.rdata.clone())
+                .bind(self.comment.clone())
+                .bind(self.tags_json())
+                .bind(self.expires_at_timestamp()),
                 None => sqlx::query(
-                    "INSERT INTO records (zoneid, name, ttl, rrtype, rclass, rdata)
-                                        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                    "INSERT INTO records (zoneid, name, ttl, rrtype, rclass, rdata, comment, tags, expires_at)
+                                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
                                     ",
                 )
                 .bind(self.zoneid)
                 .bind(self.name.clone())
                 .bind(self.ttl)
-                .bind(RecordType::from(self.rrtype.clone()))
+                .bind(self.rrtype_code())
                 .bind(self.class)
-                .bind(self.rdata.clone()),
+                .bind(self.rdata.clone())
+                .bind(self.comment.clone())
+                .bind(self.tags_json())
+                .bind(self.expires_at_timestamp()),
             },
         };
         #[cfg(test)]
@@ -1110,13 +1755,31 @@ impl DBEntity for FileZoneRecord {
         error!("Unimplemented: FileZoneRecord::create_with_txn");
         Err(sqlx::Error::RowNotFound.into())
     }
-    /// create from scratch
+    /// replace the record with the same id in the same zone
     async fn update_with_txn<'t>(
         &self,
-        _txn: &mut SqliteConnection,
+        txn: &mut SqliteConnection,
     ) -> Result<Box<Self>, GoatNsError> {
-        error!("Unimplemented: FileZoneRecord::update_with_txn");
-        Err(sqlx::Error::RowNotFound.into())
+        let res = sqlx::query(
+            "UPDATE records set name = ?, ttl = ?, rrtype = ?, rclass = ?, rdata = ?, comment = ?, tags = ?, expires_at = ?
+            WHERE id = ? AND zoneid = ?",
+        )
+        .bind(&self.name)
+        .bind(self.ttl)
+        .bind(self.rrtype_code())
+        .bind(self.class)
+        .bind(&self.rdata)
+        .bind(&self.comment)
+        .bind(self.tags_json())
+        .bind(self.expires_at_timestamp())
+        .bind(self.id)
+        .bind(self.zoneid)
+        .execute(&mut *txn)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(Box::new(self.to_owned()))
     }
     async fn delete(&self, pool: &Pool<Sqlite>) -> Result<(), GoatNsError> {
         let mut txn = pool.begin().await?;
@@ -1436,6 +2099,10 @@ impl DBEntity for User {
         &self,
         txn: &mut SqliteConnection,
     ) -> Result<Box<Self>, GoatNsError> {
+        if self.disabled {
+            // their API tokens stop working now, not when the DoH auth cache gets around to it
+            crate::web::middleware::doh_auth::forget_verified_api_tokens();
+        }
         let query = format!("UPDATE {} set displayname = ?, username = ?, email = ?, disabled = ?, authref = ?, admin = ? WHERE id = ?", Self::TABLE);
         sqlx::query(&query)
             .bind(&self.displayname)
@@ -1477,21 +2144,61 @@ pub struct UserAuthToken {
     pub userid: i64,
     pub tokenkey: String,
     pub tokenhash: String,
+    /// When it was last used to log in or make an API request
+    #[serde(default)]
+    pub last_used: Option<DateTime<Utc>>,
 }
 
+/// How often [UserAuthToken::record_use] bothers writing to the database for a token
+const TOKEN_LAST_USED_RESOLUTION: i64 = 60;
+
 impl UserAuthToken {
-    pub async fn get_authtoken(
+    /// Note that the token's been used. It's only written once a minute, so busy tokens don't hammer the database.
+    pub async fn record_use(
         pool: &SqlitePool,
-        tokenkey: String,
-    ) -> Result<UserAuthToken, GoatNsError> {
-        let res = sqlx::query(&format!(
-            "select id, issued, expiry, tokenkey, tokenhash, userid from {} where tokenkey = ?",
+        tokenkey: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), GoatNsError> {
+        sqlx::query(&format!(
+            "UPDATE {} SET last_used = ?1 WHERE tokenkey = ?2 AND (last_used IS NULL OR CAST(last_used AS INTEGER) <= ?1 - ?3)",
             Self::TABLE
         ))
+        .bind(now.timestamp())
         .bind(tokenkey)
-        .fetch_one(&mut *pool.acquire().await?)
+        .bind(TOKEN_LAST_USED_RESOLUTION)
+        .execute(pool)
         .await?;
-        res.try_into()
+        Ok(())
+    }
+
+    /// Swap the secret for a new one, the ID, key and name stay the same
+    pub async fn rotate_with_txn(
+        &self,
+        txn: &mut SqliteConnection,
+        issued: DateTime<Utc>,
+        expiry: Option<DateTime<Utc>>,
+        tokenhash: String,
+    ) -> Result<Box<Self>, GoatNsError> {
+        crate::web::middleware::doh_auth::forget_verified_api_tokens();
+        Self {
+            issued,
+            expiry,
+            tokenhash,
+            ..self.to_owned()
+        }
+        .update_with_txn(txn)
+        .await
+    }
+
+    pub async fn get_authtoken(
+        pool: &SqlitePool,
+        tokenkey: String,
+    ) -> Result<UserAuthToken, GoatNsError> {
+        let res = sqlx::query(&format!("select * from {} where tokenkey = ?", Self::TABLE))
+            .bind(tokenkey)
+            .fetch_one(&mut *pool.acquire().await?)
+            .await?;
+        res.try_into()
     }
 
     pub async fn cleanup(pool: &SqlitePool) -> Result<(), GoatNsError> {
@@ -1556,6 +2263,7 @@ impl DBEntity for UserAuthToken {
                         tokenkey TEXT NOT NULL,
                         tokenhash TEXT NOT NULL,
                         userid INTEGER NOT NULL,
+                        last_used TEXT,
                         FOREIGN KEY(userid) REFERENCES users(id)
                     )"#,
                     Self::TABLE
@@ -1572,6 +2280,7 @@ impl DBEntity for UserAuthToken {
 
                 let mut found_name = false;
                 let mut found_tokenkey = false;
+                let mut found_last_used = false;
                 for row in res.iter() {
                     let rowname: &str = row.get("name");
                     if rowname == "name" {
@@ -1584,6 +2293,19 @@ impl DBEntity for UserAuthToken {
                         log::debug!("Found the tokenkey column in the {} table", Self::TABLE);
                         found_tokenkey = true;
                     }
+                    if rowname == "last_used" {
+                        found_last_used = true;
+                    }
+                }
+
+                if !found_last_used {
+                    log::info!("Adding the last_used column to the {} table", Self::TABLE);
+                    sqlx::query(&format!(
+                        "ALTER TABLE \"{}\" ADD COLUMN last_used TEXT",
+                        Self::TABLE
+                    ))
+                    .execute(&mut *conn)
+                    .await?;
                 }
 
                 if !found_name {
@@ -1755,12 +2477,27 @@ impl DBEntity for UserAuthToken {
     ) -> Result<Box<Self>, GoatNsError> {
         todo!();
     }
-    /// create from scratch
+    /// Updates the name, secret and lifetime, the key and user can't change
     async fn update_with_txn<'t>(
         &self,
-        _txn: &mut SqliteConnection,
+        txn: &mut SqliteConnection,
     ) -> Result<Box<Self>, GoatNsError> {
-        todo!();
+        let res = sqlx::query(&format!(
+            "UPDATE {} SET name = ?, issued = ?, expiry = ?, tokenhash = ? WHERE id = ? AND userid = ?",
+            Self::TABLE
+        ))
+        .bind(&self.name)
+        .bind(self.issued.timestamp())
+        .bind(self.expiry.map(|v| v.timestamp()))
+        .bind(&self.tokenhash)
+        .bind(self.id)
+        .bind(self.userid)
+        .execute(txn)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(Box::new(self.to_owned()))
     }
 
     /// delete the entity from the database
@@ -1772,6 +2509,7 @@ impl DBEntity for UserAuthToken {
     }
     /// delete the entity from the database, but you're in a transaction
     async fn delete_with_txn(&self, txn: &mut SqliteConnection) -> Result<(), GoatNsError> {
+        crate::web::middleware::doh_auth::forget_verified_api_tokens();
         sqlx::query(&format!("DELETE FROM {} where id = ?", &Self::TABLE))
             .bind(self.id)
             .execute(txn)
@@ -1805,6 +2543,15 @@ impl TryFrom<SqliteRow> for UserAuthToken {
         let issued = chrono::NaiveDateTime::parse_from_str(&issued, "%s")?;
         let issued: DateTime<Utc> = chrono::TimeZone::from_utc_datetime(&Utc, &issued);
 
+        let last_used: Option<String> = input.get("last_used");
+        let last_used = match last_used {
+            None => None,
+            Some(val) => {
+                let last_used = chrono::NaiveDateTime::parse_from_str(&val, "%s")?;
+                Some(chrono::TimeZone::from_utc_datetime(&Utc, &last_used))
+            }
+        };
+
         Ok(Self {
             id: input.get("id"),
             name: input.get("name"),
@@ -1813,103 +2560,2046 @@ impl TryFrom<SqliteRow> for UserAuthToken {
             userid: input.get("userid"),
             tokenkey: input.get("tokenkey"),
             tokenhash: input.get("tokenhash"),
+            last_used,
         })
     }
 }
 
-/// Run this periodically to clean up expired DB things
-pub async fn cron_db_cleanup(pool: Pool<Sqlite>, period: Duration, max_iter: Option<usize>) {
-    let mut interval = time::interval(period);
-    let mut iterations = 0;
-    loop {
-        interval.tick().await;
+impl ZoneTemplate {
+    /// Get all the templates, for showing in the zone creation form
+    pub async fn get_all(pool: &SqlitePool) -> Result<Vec<ZoneTemplate>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "SELECT id, name, description, records FROM {} ORDER BY name",
+            Self::TABLE
+        ))
+        .fetch_all(&mut *pool.acquire().await?)
+        .await?;
 
-        if let Err(error) = UserAuthToken::cleanup(&pool).await {
-            log::error!("Failed to clean up UserAuthToken objects in DB cron: {error:?}");
+        res.into_iter().map(ZoneTemplate::try_from).collect()
+    }
+
+    fn records_json(&self) -> Result<String, GoatNsError> {
+        serde_json::to_string(&self.records).map_err(|err| {
+            GoatNsError::InvalidValue(format!("Failed to serialize template records: {err:?}"))
+        })
+    }
+}
+
+#[async_trait]
+impl DBEntity for ZoneTemplate {
+    const TABLE: &'static str = "zone_templates";
+
+    async fn create_table(pool: &SqlitePool) -> Result<(), GoatNsError> {
+        let mut tx = pool.begin().await?;
+
+        log::debug!("Ensuring DB {} table exists", Self::TABLE);
+        sqlx::query(&format!(
+            r#"CREATE TABLE IF NOT EXISTS
+                {} (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    description TEXT NOT NULL DEFAULT "",
+                    records TEXT NOT NULL
+                )"#,
+            Self::TABLE
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(&format!(
+            "CREATE UNIQUE INDEX IF NOT EXISTS
+            ind_{0}_name
+            ON {0} ( name )",
+            Self::TABLE
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get(pool: &Pool<Sqlite>, id: i64) -> Result<Box<Self>, GoatNsError> {
+        let mut conn = pool.acquire().await?;
+        Self::get_with_txn(&mut *conn, &id).await
+    }
+
+    async fn get_with_txn<'t>(
+        txn: &mut SqliteConnection,
+        id: &i64,
+    ) -> Result<Box<Self>, GoatNsError> {
+        let res: Self = sqlx::query(&format!(
+            "SELECT id, name, description, records FROM {} WHERE id = ?",
+            Self::TABLE
+        ))
+        .bind(id)
+        .fetch_one(txn)
+        .await?
+        .try_into()?;
+        Ok(Box::new(res))
+    }
+
+    async fn get_by_name<'t>(
+        txn: &mut SqliteConnection,
+        name: &str,
+    ) -> Result<Option<Box<Self>>, GoatNsError> {
+        match sqlx::query(&format!(
+            "SELECT id, name, description, records FROM {} WHERE name = ?",
+            Self::TABLE
+        ))
+        .bind(name)
+        .fetch_optional(txn)
+        .await?
+        {
+            Some(row) => Ok(Some(Box::new(row.try_into()?))),
+            None => Ok(None),
         }
-        if let Some(max_iter) = max_iter {
-            iterations += 1;
-            if iterations >= max_iter {
-                break;
-            }
+    }
+
+    async fn get_all_by_name<'t>(
+        _txn: &mut SqliteConnection,
+        _name: &str,
+    ) -> Result<Vec<Box<Self>>, GoatNsError> {
+        unimplemented!("Template names are unique, use get_by_name")
+    }
+
+    async fn get_all_user(
+        _pool: &Pool<Sqlite>,
+        _userid: i64,
+    ) -> Result<Vec<Arc<Self>>, GoatNsError> {
+        unimplemented!("Templates aren't owned by users")
+    }
+
+    async fn save(&self, pool: &Pool<Sqlite>) -> Result<Box<Self>, GoatNsError> {
+        let mut txn = pool.begin().await?;
+        let res = self.save_with_txn(&mut txn).await?;
+        txn.commit().await?;
+        Ok(res)
+    }
+
+    /// Creates the template if it doesn't have an ID, otherwise updates it
+    async fn save_with_txn<'t>(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        match self.id {
+            Some(_) => self.update_with_txn(txn).await,
+            None => self.create_with_txn(txn).await,
         }
     }
-}
 
-pub async fn get_zones_with_txn(
-    txn: &mut SqliteConnection,
-    lim: i64,
-    offset: i64,
-) -> Result<Vec<FileZone>, GoatNsError> {
-    let result = sqlx::query(
-        "SELECT
-        *
-        FROM zones
-        LIMIT ? OFFSET ? ",
-    )
-    .bind(lim)
-    .bind(offset)
-    .fetch_all(&mut *txn)
-    .await?;
+    async fn create_with_txn<'t>(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "INSERT INTO {} (name, description, records) VALUES (?, ?, ?)",
+            Self::TABLE
+        ))
+        .bind(&self.name)
+        .bind(&self.description)
+        .bind(self.records_json()?)
+        .execute(txn)
+        .await?;
 
-    let rows: Vec<FileZone> = result
-        .into_iter()
-        .map(|row| {
-            #[cfg(test)]
-            eprintln!("Building FileZone");
-            row.into()
-        })
-        .collect();
-    Ok(rows)
-}
+        Ok(Box::new(Self {
+            id: Some(res.last_insert_rowid()),
+            ..self.to_owned()
+        }))
+    }
 
-impl TryFrom<&SqliteRow> for FileZoneRecord {
-    type Error = GoatNsError;
-    fn try_from(row: &SqliteRow) -> Result<Self, Self::Error> {
-        row.to_owned().try_into()
+    async fn update_with_txn<'t>(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "UPDATE {} SET name = ?, description = ?, records = ? WHERE id = ?",
+            Self::TABLE
+        ))
+        .bind(&self.name)
+        .bind(&self.description)
+        .bind(self.records_json()?)
+        .bind(self.id)
+        .execute(txn)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(Box::new(self.to_owned()))
+    }
+
+    async fn delete(&self, pool: &Pool<Sqlite>) -> Result<(), GoatNsError> {
+        let mut txn = pool.begin().await?;
+        self.delete_with_txn(&mut txn).await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_with_txn(&self, txn: &mut SqliteConnection) -> Result<(), GoatNsError> {
+        sqlx::query(&format!("DELETE FROM {} WHERE id = ?", Self::TABLE))
+            .bind(self.id)
+            .execute(txn)
+            .await?;
+        Ok(())
     }
 }
 
-impl TryFrom<SqliteRow> for FileZoneRecord {
+impl TryFrom<SqliteRow> for ZoneTemplate {
     type Error = GoatNsError;
     fn try_from(row: SqliteRow) -> Result<Self, Self::Error> {
-        let name: String = row.get("name");
-        let rrtype: i32 = row.get("rrtype");
-        let rrtype = RecordType::from(&(rrtype as u16));
-        let class: u16 = row.get("rclass");
-        let rdata: String = row.get("rdata");
-        let ttl: u32 = row.get("ttl");
-
-        if let RecordType::ANY = rrtype {
-            return Err(GoatNsError::RFC8482);
-        }
-
-        Ok(FileZoneRecord {
-            zoneid: row.get("zoneid"),
+        let records: String = row.get("records");
+        let records: Vec<FileZoneRecord> = serde_json::from_str(&records).map_err(|err| {
+            GoatNsError::InvalidValue(format!("Failed to parse template records: {err:?}"))
+        })?;
+        Ok(Self {
             id: row.get("id"),
-            name,
-            rrtype: rrtype.to_string(),
-            class: RecordClass::from(&class),
-            rdata,
-            ttl,
+            name: row.get("name"),
+            description: row.get("description"),
+            records,
         })
     }
 }
 
-pub async fn get_all_fzr_by_name<'t>(
+/// Apply a single [RecordChange] to a zone, if the record it's changing isn't there you get a [sqlx::Error::RowNotFound]
+pub async fn apply_record_change_with_txn(
+    zoneid: i64,
+    change: &RecordChange,
     txn: &mut SqliteConnection,
-    name: &str,
-    rrtype: u16,
-) -> Result<Vec<FileZoneRecord>, GoatNsError> {
-    let res = sqlx::query(&format!(
-        "select *, record_id as id from {} where name = ? AND rrtype = ?",
-        SQL_VIEW_RECORDS
-    ))
-    .bind(name)
-    .bind(rrtype)
-    .fetch_all(txn)
-    .await?;
-
-    let res = res.into_iter().filter_map(|r| r.try_into().ok()).collect();
-    Ok(res)
+) -> Result<(), GoatNsError> {
+    match change {
+        RecordChange::Create(record) => {
+            let record = FileZoneRecord {
+                id: None,
+                zoneid: Some(zoneid),
+                ..record.clone()
+            };
+            record.save_with_txn(&mut *txn).await?;
+        }
+        RecordChange::Update(record) => {
+            if record.id.is_none() {
+                return Err(GoatNsError::InvalidValue(
+                    "Record updates need an id".to_string(),
+                ));
+            }
+            let record = FileZoneRecord {
+                zoneid: Some(zoneid),
+                ..record.clone()
+            };
+            record.update_with_txn(&mut *txn).await?;
+        }
+        RecordChange::Delete(id) => {
+            let res = sqlx::query("DELETE FROM records WHERE id = ? AND zoneid = ?")
+                .bind(id)
+                .bind(zoneid)
+                .execute(&mut *txn)
+                .await?;
+            if res.rows_affected() == 0 {
+                return Err(sqlx::Error::RowNotFound.into());
+            }
+        }
+        RecordChange::SetTtl { id, ttl } => {
+            let res = sqlx::query("UPDATE records SET ttl = ? WHERE id = ? AND zoneid = ?")
+                .bind(ttl)
+                .bind(id)
+                .bind(zoneid)
+                .execute(&mut *txn)
+                .await?;
+            if res.rows_affected() == 0 {
+                return Err(sqlx::Error::RowNotFound.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+impl ZoneChangeSet {
+    /// The change-sets waiting on an owner for a given zone, oldest first
+    pub async fn get_pending_for_zone(
+        txn: &mut SqliteConnection,
+        zoneid: i64,
+    ) -> Result<Vec<ZoneChangeSet>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "SELECT id, zoneid, userid, description, created, status, changes FROM {}
+            WHERE zoneid = ? AND status = ? ORDER BY created",
+            Self::TABLE
+        ))
+        .bind(zoneid)
+        .bind(ChangeSetStatus::Pending.to_string())
+        .fetch_all(txn)
+        .await?;
+
+        res.into_iter().map(ZoneChangeSet::try_from).collect()
+    }
+
+    /// How many change-sets are waiting on the user, for the zones they own
+    pub async fn count_pending_for_owner(
+        txn: &mut SqliteConnection,
+        userid: i64,
+    ) -> Result<i64, GoatNsError> {
+        let count: i64 = sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM {} AS changesets
+            JOIN {} AS ownership ON changesets.zoneid = ownership.zoneid
+            WHERE ownership.userid = ? AND changesets.status = ?",
+            Self::TABLE,
+            ZoneOwnership::TABLE
+        ))
+        .bind(userid)
+        .bind(ChangeSetStatus::Pending.to_string())
+        .fetch_one(txn)
+        .await?;
+        Ok(count)
+    }
+
+    fn changes_json(&self) -> Result<String, GoatNsError> {
+        serde_json::to_string(&self.changes).map_err(|err| {
+            GoatNsError::InvalidValue(format!("Failed to serialize change-set: {err:?}"))
+        })
+    }
+
+    /// Apply all the changes to the zone, bump the serial and mark the change-set approved.
+    ///
+    /// Everything happens in the transaction you pass in, so if one change fails none of them stick.
+    pub async fn approve_with_txn(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        if self.status != ChangeSetStatus::Pending {
+            return Err(GoatNsError::InvalidValue(format!(
+                "Change-set is {}, not Pending",
+                self.status
+            )));
+        }
+        for change in self.changes.iter() {
+            apply_record_change_with_txn(self.zoneid, change, &mut *txn).await?;
+        }
+        FileZone::check_cname_rules_with_txn(self.zoneid, &mut *txn).await?;
+        FileZone::bump_serial_with_txn(self.zoneid, &mut *txn).await?;
+        self.set_status_with_txn(txn, ChangeSetStatus::Approved)
+            .await
+    }
+
+    /// Throw the change-set away without touching the zone
+    pub async fn reject_with_txn(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        if self.status != ChangeSetStatus::Pending {
+            return Err(GoatNsError::InvalidValue(format!(
+                "Change-set is {}, not Pending",
+                self.status
+            )));
+        }
+        self.set_status_with_txn(txn, ChangeSetStatus::Rejected)
+            .await
+    }
+
+    async fn set_status_with_txn(
+        &self,
+        txn: &mut SqliteConnection,
+        status: ChangeSetStatus,
+    ) -> Result<Box<Self>, GoatNsError> {
+        Self {
+            status,
+            ..self.to_owned()
+        }
+        .update_with_txn(txn)
+        .await
+    }
+}
+
+#[async_trait]
+impl DBEntity for ZoneChangeSet {
+    const TABLE: &'static str = "zone_changesets";
+
+    async fn create_table(pool: &SqlitePool) -> Result<(), GoatNsError> {
+        let mut tx = pool.begin().await?;
+
+        log::debug!("Ensuring DB {} table exists", Self::TABLE);
+        sqlx::query(&format!(
+            r#"CREATE TABLE IF NOT EXISTS
+                {} (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    zoneid INTEGER NOT NULL,
+                    userid INTEGER NOT NULL,
+                    description TEXT NOT NULL DEFAULT "",
+                    created TEXT NOT NULL,
+                    status TEXT NOT NULL,
+                    changes TEXT NOT NULL,
+                    FOREIGN KEY(zoneid) REFERENCES zones(id),
+                    FOREIGN KEY(userid) REFERENCES users(id)
+                )"#,
+            Self::TABLE
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(&format!(
+            "CREATE INDEX IF NOT EXISTS
+            ind_{0}_zoneid_status
+            ON {0} ( zoneid, status )",
+            Self::TABLE
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get(pool: &Pool<Sqlite>, id: i64) -> Result<Box<Self>, GoatNsError> {
+        let mut conn = pool.acquire().await?;
+        Self::get_with_txn(&mut *conn, &id).await
+    }
+
+    async fn get_with_txn<'t>(
+        txn: &mut SqliteConnection,
+        id: &i64,
+    ) -> Result<Box<Self>, GoatNsError> {
+        let res: Self = sqlx::query(&format!(
+            "SELECT id, zoneid, userid, description, created, status, changes FROM {} WHERE id = ?",
+            Self::TABLE
+        ))
+        .bind(id)
+        .fetch_one(txn)
+        .await?
+        .try_into()?;
+        Ok(Box::new(res))
+    }
+
+    async fn get_by_name<'t>(
+        _txn: &mut SqliteConnection,
+        _name: &str,
+    ) -> Result<Option<Box<Self>>, GoatNsError> {
+        unimplemented!("Change-sets don't have names")
+    }
+
+    async fn get_all_by_name<'t>(
+        _txn: &mut SqliteConnection,
+        _name: &str,
+    ) -> Result<Vec<Box<Self>>, GoatNsError> {
+        unimplemented!("Change-sets don't have names")
+    }
+
+    /// The change-sets a user has proposed
+    async fn get_all_user(pool: &Pool<Sqlite>, userid: i64) -> Result<Vec<Arc<Self>>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "SELECT id, zoneid, userid, description, created, status, changes FROM {}
+            WHERE userid = ? ORDER BY created",
+            Self::TABLE
+        ))
+        .bind(userid)
+        .fetch_all(&mut *pool.acquire().await?)
+        .await?;
+        res.into_iter()
+            .map(|row| ZoneChangeSet::try_from(row).map(Arc::new))
+            .collect()
+    }
+
+    async fn save(&self, pool: &Pool<Sqlite>) -> Result<Box<Self>, GoatNsError> {
+        let mut txn = pool.begin().await?;
+        let res = self.save_with_txn(&mut txn).await?;
+        txn.commit().await?;
+        Ok(res)
+    }
+
+    /// Creates the change-set if it doesn't have an ID, otherwise updates it
+    async fn save_with_txn<'t>(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        match self.id {
+            Some(_) => self.update_with_txn(txn).await,
+            None => self.create_with_txn(txn).await,
+        }
+    }
+
+    async fn create_with_txn<'t>(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "INSERT INTO {} (zoneid, userid, description, created, status, changes) VALUES (?, ?, ?, ?, ?, ?)",
+            Self::TABLE
+        ))
+        .bind(self.zoneid)
+        .bind(self.userid)
+        .bind(&self.description)
+        .bind(self.created.timestamp())
+        .bind(self.status.to_string())
+        .bind(self.changes_json()?)
+        .execute(txn)
+        .await?;
+
+        Ok(Box::new(Self {
+            id: Some(res.last_insert_rowid()),
+            ..self.to_owned()
+        }))
+    }
+
+    async fn update_with_txn<'t>(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "UPDATE {} SET description = ?, status = ?, changes = ? WHERE id = ?",
+            Self::TABLE
+        ))
+        .bind(&self.description)
+        .bind(self.status.to_string())
+        .bind(self.changes_json()?)
+        .bind(self.id)
+        .execute(txn)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(Box::new(self.to_owned()))
+    }
+
+    async fn delete(&self, pool: &Pool<Sqlite>) -> Result<(), GoatNsError> {
+        let mut txn = pool.begin().await?;
+        self.delete_with_txn(&mut txn).await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_with_txn(&self, txn: &mut SqliteConnection) -> Result<(), GoatNsError> {
+        sqlx::query(&format!("DELETE FROM {} WHERE id = ?", Self::TABLE))
+            .bind(self.id)
+            .execute(txn)
+            .await?;
+        Ok(())
+    }
+}
+
+impl TryFrom<SqliteRow> for ZoneChangeSet {
+    type Error = GoatNsError;
+    fn try_from(row: SqliteRow) -> Result<Self, Self::Error> {
+        let changes: String = row.get("changes");
+        let changes: Vec<RecordChange> = serde_json::from_str(&changes).map_err(|err| {
+            GoatNsError::InvalidValue(format!("Failed to parse change-set: {err:?}"))
+        })?;
+        let status: String = row.get("status");
+        let created: String = row.get("created");
+        let created = chrono::NaiveDateTime::parse_from_str(&created, "%s")?;
+        let created: DateTime<Utc> = chrono::TimeZone::from_utc_datetime(&Utc, &created);
+        Ok(Self {
+            id: row.get("id"),
+            zoneid: row.get("zoneid"),
+            userid: row.get("userid"),
+            description: row.get("description"),
+            created,
+            status: ChangeSetStatus::try_from(status.as_str())?,
+            changes,
+        })
+    }
+}
+
+impl ZoneJournalEntry {
+    /// The most recent entries for a zone, newest first
+    pub async fn get_for_zone(
+        txn: &mut SqliteConnection,
+        zoneid: i64,
+        limit: i64,
+    ) -> Result<Vec<ZoneJournalEntry>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "SELECT id, zoneid, userid, description, created, undo, undone FROM {}
+            WHERE zoneid = ? ORDER BY created DESC, id DESC LIMIT ?",
+            Self::TABLE
+        ))
+        .bind(zoneid)
+        .bind(limit)
+        .fetch_all(txn)
+        .await?;
+
+        res.into_iter().map(ZoneJournalEntry::try_from).collect()
+    }
+
+    fn undo_json(&self) -> Result<String, GoatNsError> {
+        serde_json::to_string(&self.undo).map_err(|err| {
+            GoatNsError::InvalidValue(format!("Failed to serialize journal entry: {err:?}"))
+        })
+    }
+
+    /// Put the zone back how it was, bump the serial and mark the entry undone.
+    ///
+    /// Records that have been deleted since are skipped, everything else happens in the transaction you pass in.
+    pub async fn undo_with_txn(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        if self.undone {
+            return Err(GoatNsError::InvalidValue(
+                "That change has already been undone".to_string(),
+            ));
+        }
+        for change in self.undo.iter() {
+            match apply_record_change_with_txn(self.zoneid, change, &mut *txn).await {
+                Ok(()) | Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        FileZone::check_cname_rules_with_txn(self.zoneid, &mut *txn).await?;
+        FileZone::bump_serial_with_txn(self.zoneid, &mut *txn).await?;
+        Self {
+            undone: true,
+            ..self.to_owned()
+        }
+        .update_with_txn(txn)
+        .await
+    }
+}
+
+#[async_trait]
+impl DBEntity for ZoneJournalEntry {
+    const TABLE: &'static str = "zone_journal";
+
+    async fn create_table(pool: &SqlitePool) -> Result<(), GoatNsError> {
+        let mut tx = pool.begin().await?;
+
+        log::debug!("Ensuring DB {} table exists", Self::TABLE);
+        sqlx::query(&format!(
+            r#"CREATE TABLE IF NOT EXISTS
+                {} (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    zoneid INTEGER NOT NULL,
+                    userid INTEGER NOT NULL,
+                    description TEXT NOT NULL DEFAULT "",
+                    created TEXT NOT NULL,
+                    undo TEXT NOT NULL,
+                    undone BOOL NOT NULL DEFAULT 0,
+                    FOREIGN KEY(zoneid) REFERENCES zones(id),
+                    FOREIGN KEY(userid) REFERENCES users(id)
+                )"#,
+            Self::TABLE
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(&format!(
+            "CREATE INDEX IF NOT EXISTS
+            ind_{0}_zoneid
+            ON {0} ( zoneid )",
+            Self::TABLE
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get(pool: &Pool<Sqlite>, id: i64) -> Result<Box<Self>, GoatNsError> {
+        let mut conn = pool.acquire().await?;
+        Self::get_with_txn(&mut *conn, &id).await
+    }
+
+    async fn get_with_txn<'t>(
+        txn: &mut SqliteConnection,
+        id: &i64,
+    ) -> Result<Box<Self>, GoatNsError> {
+        let res: Self = sqlx::query(&format!(
+            "SELECT id, zoneid, userid, description, created, undo, undone FROM {} WHERE id = ?",
+            Self::TABLE
+        ))
+        .bind(id)
+        .fetch_one(txn)
+        .await?
+        .try_into()?;
+        Ok(Box::new(res))
+    }
+
+    async fn get_by_name<'t>(
+        _txn: &mut SqliteConnection,
+        _name: &str,
+    ) -> Result<Option<Box<Self>>, GoatNsError> {
+        unimplemented!("Journal entries don't have names")
+    }
+
+    async fn get_all_by_name<'t>(
+        _txn: &mut SqliteConnection,
+        _name: &str,
+    ) -> Result<Vec<Box<Self>>, GoatNsError> {
+        unimplemented!("Journal entries don't have names")
+    }
+
+    /// The changes a user has made
+    async fn get_all_user(pool: &Pool<Sqlite>, userid: i64) -> Result<Vec<Arc<Self>>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "SELECT id, zoneid, userid, description, created, undo, undone FROM {}
+            WHERE userid = ? ORDER BY created",
+            Self::TABLE
+        ))
+        .bind(userid)
+        .fetch_all(&mut *pool.acquire().await?)
+        .await?;
+        res.into_iter()
+            .map(|row| ZoneJournalEntry::try_from(row).map(Arc::new))
+            .collect()
+    }
+
+    async fn save(&self, pool: &Pool<Sqlite>) -> Result<Box<Self>, GoatNsError> {
+        let mut txn = pool.begin().await?;
+        let res = self.save_with_txn(&mut txn).await?;
+        txn.commit().await?;
+        Ok(res)
+    }
+
+    /// Creates the entry if it doesn't have an ID, otherwise updates it
+    async fn save_with_txn<'t>(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        match self.id {
+            Some(_) => self.update_with_txn(txn).await,
+            None => self.create_with_txn(txn).await,
+        }
+    }
+
+    async fn create_with_txn<'t>(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "INSERT INTO {} (zoneid, userid, description, created, undo, undone) VALUES (?, ?, ?, ?, ?, ?)",
+            Self::TABLE
+        ))
+        .bind(self.zoneid)
+        .bind(self.userid)
+        .bind(&self.description)
+        .bind(self.created.timestamp())
+        .bind(self.undo_json()?)
+        .bind(self.undone)
+        .execute(txn)
+        .await?;
+
+        Ok(Box::new(Self {
+            id: Some(res.last_insert_rowid()),
+            ..self.to_owned()
+        }))
+    }
+
+    async fn update_with_txn<'t>(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "UPDATE {} SET description = ?, undo = ?, undone = ? WHERE id = ?",
+            Self::TABLE
+        ))
+        .bind(&self.description)
+        .bind(self.undo_json()?)
+        .bind(self.undone)
+        .bind(self.id)
+        .execute(txn)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(Box::new(self.to_owned()))
+    }
+
+    async fn delete(&self, pool: &Pool<Sqlite>) -> Result<(), GoatNsError> {
+        let mut txn = pool.begin().await?;
+        self.delete_with_txn(&mut txn).await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_with_txn(&self, txn: &mut SqliteConnection) -> Result<(), GoatNsError> {
+        sqlx::query(&format!("DELETE FROM {} WHERE id = ?", Self::TABLE))
+            .bind(self.id)
+            .execute(txn)
+            .await?;
+        Ok(())
+    }
+}
+
+impl TryFrom<SqliteRow> for ZoneJournalEntry {
+    type Error = GoatNsError;
+    fn try_from(row: SqliteRow) -> Result<Self, Self::Error> {
+        let undo: String = row.get("undo");
+        let undo: Vec<RecordChange> = serde_json::from_str(&undo).map_err(|err| {
+            GoatNsError::InvalidValue(format!("Failed to parse journal entry: {err:?}"))
+        })?;
+        let created: String = row.get("created");
+        let created = chrono::NaiveDateTime::parse_from_str(&created, "%s")?;
+        let created: DateTime<Utc> = chrono::TimeZone::from_utc_datetime(&Utc, &created);
+        Ok(Self {
+            id: row.get("id"),
+            zoneid: row.get("zoneid"),
+            userid: row.get("userid"),
+            description: row.get("description"),
+            created,
+            undo,
+            undone: row.get("undone"),
+        })
+    }
+}
+
+/// Transfers come back with the names of the zone and users so they can be shown to people
+const SQL_SELECT_OWNERSHIP_TRANSFERS: &str =
+    "SELECT ownership_transfers.id, ownership_transfers.zoneid,
+    from_userid, to_userid, created, status,
+    zones.name AS zone, from_user.username AS from_username, to_user.username AS to_username
+    FROM ownership_transfers
+    JOIN zones ON zones.id = ownership_transfers.zoneid
+    JOIN users AS from_user ON from_user.id = ownership_transfers.from_userid
+    JOIN users AS to_user ON to_user.id = ownership_transfers.to_userid";
+
+impl ZoneOwnershipTransfer {
+    /// The transfer waiting on someone for a zone, there's only ever one at a time
+    pub async fn get_pending_for_zone(
+        txn: &mut SqliteConnection,
+        zoneid: i64,
+    ) -> Result<Option<ZoneOwnershipTransfer>, GoatNsError> {
+        sqlx::query(&format!(
+            "{SQL_SELECT_OWNERSHIP_TRANSFERS} WHERE ownership_transfers.zoneid = ? AND status = ?"
+        ))
+        .bind(zoneid)
+        .bind(OwnershipTransferStatus::Pending.to_string())
+        .fetch_optional(txn)
+        .await?
+        .map(ZoneOwnershipTransfer::try_from)
+        .transpose()
+    }
+
+    /// The zones people are trying to give a user, oldest first
+    pub async fn get_pending_for_user(
+        txn: &mut SqliteConnection,
+        userid: i64,
+    ) -> Result<Vec<ZoneOwnershipTransfer>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "{SQL_SELECT_OWNERSHIP_TRANSFERS} WHERE to_userid = ? AND status = ? ORDER BY created"
+        ))
+        .bind(userid)
+        .bind(OwnershipTransferStatus::Pending.to_string())
+        .fetch_all(txn)
+        .await?;
+        res.into_iter()
+            .map(ZoneOwnershipTransfer::try_from)
+            .collect()
+    }
+
+    /// Finish off a pending transfer. Accepting it swaps the old owner out for the new one, declining or cancelling
+    /// just closes it.
+    pub async fn resolve_with_txn(
+        &self,
+        txn: &mut SqliteConnection,
+        status: OwnershipTransferStatus,
+    ) -> Result<Box<Self>, GoatNsError> {
+        if self.status != OwnershipTransferStatus::Pending {
+            return Err(GoatNsError::InvalidValue(format!(
+                "Ownership transfer is {}, not Pending",
+                self.status
+            )));
+        }
+        match status {
+            OwnershipTransferStatus::Pending => {
+                return Err(GoatNsError::InvalidValue(
+                    "Ownership transfer is already Pending".to_string(),
+                ))
+            }
+            OwnershipTransferStatus::Accepted => {
+                let res = sqlx::query("DELETE FROM ownership WHERE zoneid = ? AND userid = ?")
+                    .bind(self.zoneid)
+                    .bind(self.from_userid)
+                    .execute(&mut *txn)
+                    .await?;
+                if res.rows_affected() == 0 {
+                    return Err(GoatNsError::InvalidValue(format!(
+                        "{} doesn't own {} any more",
+                        self.from_username, self.zone
+                    )));
+                }
+                // they might've already been a co-owner
+                sqlx::query("INSERT OR IGNORE INTO ownership (zoneid, userid) VALUES (?, ?)")
+                    .bind(self.zoneid)
+                    .bind(self.to_userid)
+                    .execute(&mut *txn)
+                    .await?;
+            }
+            OwnershipTransferStatus::Declined | OwnershipTransferStatus::Cancelled => {}
+        }
+        Self {
+            status,
+            ..self.to_owned()
+        }
+        .update_with_txn(txn)
+        .await
+    }
+}
+
+#[async_trait]
+impl DBEntity for ZoneOwnershipTransfer {
+    const TABLE: &'static str = "ownership_transfers";
+
+    async fn create_table(pool: &SqlitePool) -> Result<(), GoatNsError> {
+        let mut tx = pool.begin().await?;
+
+        log::debug!("Ensuring DB {} table exists", Self::TABLE);
+        sqlx::query(&format!(
+            r#"CREATE TABLE IF NOT EXISTS
+                {} (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    zoneid INTEGER NOT NULL,
+                    from_userid INTEGER NOT NULL,
+                    to_userid INTEGER NOT NULL,
+                    created TEXT NOT NULL,
+                    status TEXT NOT NULL,
+                    FOREIGN KEY(zoneid) REFERENCES zones(id),
+                    FOREIGN KEY(from_userid) REFERENCES users(id),
+                    FOREIGN KEY(to_userid) REFERENCES users(id)
+                )"#,
+            Self::TABLE
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        for column in ["zoneid", "to_userid"] {
+            sqlx::query(&format!(
+                "CREATE INDEX IF NOT EXISTS
+                ind_{0}_{1}_status
+                ON {0} ( {1}, status )",
+                Self::TABLE,
+                column
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get(pool: &Pool<Sqlite>, id: i64) -> Result<Box<Self>, GoatNsError> {
+        let mut conn = pool.acquire().await?;
+        Self::get_with_txn(&mut *conn, &id).await
+    }
+
+    async fn get_with_txn<'t>(
+        txn: &mut SqliteConnection,
+        id: &i64,
+    ) -> Result<Box<Self>, GoatNsError> {
+        let res: Self = sqlx::query(&format!(
+            "{SQL_SELECT_OWNERSHIP_TRANSFERS} WHERE ownership_transfers.id = ?"
+        ))
+        .bind(id)
+        .fetch_one(txn)
+        .await?
+        .try_into()?;
+        Ok(Box::new(res))
+    }
+
+    async fn get_by_name<'t>(
+        _txn: &mut SqliteConnection,
+        _name: &str,
+    ) -> Result<Option<Box<Self>>, GoatNsError> {
+        unimplemented!("Ownership transfers don't have names")
+    }
+
+    async fn get_all_by_name<'t>(
+        _txn: &mut SqliteConnection,
+        _name: &str,
+    ) -> Result<Vec<Box<Self>>, GoatNsError> {
+        unimplemented!("Ownership transfers don't have names")
+    }
+
+    /// The transfers a user's been on either end of
+    async fn get_all_user(pool: &Pool<Sqlite>, userid: i64) -> Result<Vec<Arc<Self>>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "{SQL_SELECT_OWNERSHIP_TRANSFERS} WHERE from_userid = ?1 OR to_userid = ?1 ORDER BY created"
+        ))
+        .bind(userid)
+        .fetch_all(&mut *pool.acquire().await?)
+        .await?;
+        res.into_iter()
+            .map(|row| ZoneOwnershipTransfer::try_from(row).map(Arc::new))
+            .collect()
+    }
+
+    async fn save(&self, pool: &Pool<Sqlite>) -> Result<Box<Self>, GoatNsError> {
+        let mut txn = pool.begin().await?;
+        let res = self.save_with_txn(&mut txn).await?;
+        txn.commit().await?;
+        Ok(res)
+    }
+
+    /// Creates the transfer if it doesn't have an ID, otherwise updates it
+    async fn save_with_txn<'t>(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        match self.id {
+            Some(_) => self.update_with_txn(txn).await,
+            None => self.create_with_txn(txn).await,
+        }
+    }
+
+    /// Only one transfer can be pending for a zone, and you can't give a zone to yourself
+    async fn create_with_txn<'t>(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        if self.from_userid == self.to_userid {
+            return Err(GoatNsError::InvalidValue(
+                "You can't transfer a zone to yourself".to_string(),
+            ));
+        }
+        if Self::get_pending_for_zone(&mut *txn, self.zoneid)
+            .await?
+            .is_some()
+        {
+            return Err(GoatNsError::InvalidValue(
+                "There's already an ownership transfer pending for this zone".to_string(),
+            ));
+        }
+        let res = sqlx::query(&format!(
+            "INSERT INTO {} (zoneid, from_userid, to_userid, created, status) VALUES (?, ?, ?, ?, ?)",
+            Self::TABLE
+        ))
+        .bind(self.zoneid)
+        .bind(self.from_userid)
+        .bind(self.to_userid)
+        .bind(self.created.timestamp())
+        .bind(self.status.to_string())
+        .execute(&mut *txn)
+        .await?;
+
+        Self::get_with_txn(txn, &res.last_insert_rowid()).await
+    }
+
+    async fn update_with_txn<'t>(
+        &self,
+        txn: &mut SqliteConnection,
+    ) -> Result<Box<Self>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "UPDATE {} SET status = ? WHERE id = ?",
+            Self::TABLE
+        ))
+        .bind(self.status.to_string())
+        .bind(self.id)
+        .execute(txn)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(Box::new(self.to_owned()))
+    }
+
+    async fn delete(&self, pool: &Pool<Sqlite>) -> Result<(), GoatNsError> {
+        let mut txn = pool.begin().await?;
+        self.delete_with_txn(&mut txn).await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_with_txn(&self, txn: &mut SqliteConnection) -> Result<(), GoatNsError> {
+        sqlx::query(&format!("DELETE FROM {} WHERE id = ?", Self::TABLE))
+            .bind(self.id)
+            .execute(txn)
+            .await?;
+        Ok(())
+    }
+}
+
+impl TryFrom<SqliteRow> for ZoneOwnershipTransfer {
+    type Error = GoatNsError;
+    fn try_from(row: SqliteRow) -> Result<Self, Self::Error> {
+        let status: String = row.get("status");
+        let created: String = row.get("created");
+        let created = chrono::NaiveDateTime::parse_from_str(&created, "%s")?;
+        let created: DateTime<Utc> = chrono::TimeZone::from_utc_datetime(&Utc, &created);
+        Ok(Self {
+            id: row.get("id"),
+            zoneid: row.get("zoneid"),
+            from_userid: row.get("from_userid"),
+            to_userid: row.get("to_userid"),
+            created,
+            status: OwnershipTransferStatus::try_from(status.as_str())?,
+            zone: row.get("zone"),
+            from_username: row.get("from_username"),
+            to_username: row.get("to_username"),
+        })
+    }
+}
+
+/// Run this periodically to clean up expired DB things
+pub async fn cron_db_cleanup(pool: Pool<Sqlite>, period: Duration, max_iter: Option<usize>) {
+    let mut interval = time::interval(period);
+    let mut iterations = 0;
+    loop {
+        interval.tick().await;
+
+        if let Err(error) = UserAuthToken::cleanup(&pool).await {
+            log::error!("Failed to clean up UserAuthToken objects in DB cron: {error:?}");
+        }
+        if let Err(error) = FileZoneRecord::cleanup(&pool).await {
+            log::error!("Failed to clean up expired records in DB cron: {error:?}");
+        }
+        if let Some(max_iter) = max_iter {
+            iterations += 1;
+            if iterations >= max_iter {
+                break;
+            }
+        }
+    }
+}
+
+pub async fn get_zones_with_txn(
+    txn: &mut SqliteConnection,
+    lim: i64,
+    offset: i64,
+) -> Result<Vec<FileZone>, GoatNsError> {
+    let result = sqlx::query(
+        "SELECT
+        *
+        FROM zones
+        LIMIT ? OFFSET ? ",
+    )
+    .bind(lim)
+    .bind(offset)
+    .fetch_all(&mut *txn)
+    .await?;
+
+    let rows: Vec<FileZone> = result
+        .into_iter()
+        .map(|row| {
+            #[cfg(test)]
+            eprintln!("Building FileZone");
+            row.into()
+        })
+        .collect();
+    Ok(rows)
+}
+
+impl TryFrom<&SqliteRow> for FileZoneRecord {
+    type Error = GoatNsError;
+    fn try_from(row: &SqliteRow) -> Result<Self, Self::Error> {
+        row.to_owned().try_into()
+    }
+}
+
+impl TryFrom<SqliteRow> for FileZoneRecord {
+    type Error = GoatNsError;
+    fn try_from(row: SqliteRow) -> Result<Self, Self::Error> {
+        let name: String = row.get("name");
+        let rrtype: i32 = row.get("rrtype");
+        let rrtype = rrtype as u16;
+        let class: u16 = row.get("rclass");
+        let rdata: String = row.get("rdata");
+        let ttl: u32 = row.get("ttl");
+        // these aren't in the records_merged view, so they might not be there
+        let comment: Option<String> = row.try_get("comment").unwrap_or(None);
+        let tags: Option<String> = row.try_get("tags").unwrap_or(None);
+        let expires_at: Option<i64> = row.try_get("expires_at").unwrap_or(None);
+        let tags = match tags {
+            Some(tags) => serde_json::from_str(&tags).map_err(|err| {
+                GoatNsError::InvalidValue(format!("Failed to parse record tags: {err:?}"))
+            })?,
+            None => Default::default(),
+        };
+
+        if rrtype == RecordType::ANY as u16 {
+            return Err(GoatNsError::RFC8482);
+        }
+
+        Ok(FileZoneRecord {
+            zoneid: row.get("zoneid"),
+            id: row.get("id"),
+            name,
+            rrtype: crate::generic::type_name(rrtype),
+            class: RecordClass::from(&class),
+            rdata,
+            ttl,
+            comment,
+            tags,
+            expires_at: expires_at.and_then(|ts| DateTime::from_timestamp(ts, 0)),
+        })
+    }
+}
+
+/// Records per INSERT when saving lots of them, at ten columns each this stays under SQLite's default limit of 999 variables
+const RECORD_INSERT_BATCH: usize = 90;
+
+impl FileZoneRecord {
+    /// Insert new records a batch at a time, which is a lot quicker than saving them one by one. Records without an ID get one assigned.
+    pub async fn insert_many_with_txn(
+        records: &[FileZoneRecord],
+        txn: &mut SqliteConnection,
+    ) -> Result<(), GoatNsError> {
+        for batch in records.chunks(RECORD_INSERT_BATCH) {
+            let mut args = SqliteArguments::default();
+            for record in batch {
+                args.add(record.id);
+                args.add(record.zoneid);
+                args.add(record.name.clone());
+                args.add(record.ttl);
+                args.add(record.rrtype_code());
+                args.add(record.class);
+                args.add(record.rdata.clone());
+                args.add(record.comment.clone());
+                args.add(record.tags_json());
+                args.add(record.expires_at_timestamp());
+            }
+            let values = vec!["(?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"; batch.len()].join(", ");
+            sqlx::query_with(
+                &format!(
+                    "INSERT INTO records (id, zoneid, name, ttl, rrtype, rclass, rdata, comment, tags, expires_at) VALUES {values}"
+                ),
+                args,
+            )
+            .execute(&mut *txn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Delete the records that've expired and bump the serials of the zones they were in, returns how many went
+    pub async fn cleanup(pool: &SqlitePool) -> Result<u64, GoatNsError> {
+        let now = Utc::now().timestamp();
+        let mut txn = pool.begin().await?;
+        let zoneids: Vec<i64> = sqlx::query_scalar(
+            "SELECT DISTINCT zoneid FROM records WHERE expires_at NOT NULL AND expires_at <= ?",
+        )
+        .bind(now)
+        .fetch_all(&mut *txn)
+        .await?;
+        if zoneids.is_empty() {
+            return Ok(0);
+        }
+        let res = sqlx::query("DELETE FROM records WHERE expires_at NOT NULL AND expires_at <= ?")
+            .bind(now)
+            .execute(&mut *txn)
+            .await?;
+        for zoneid in zoneids.iter() {
+            FileZone::bump_serial_with_txn(*zoneid, &mut txn).await?;
+        }
+        txn.commit().await?;
+        log::info!(
+            "Deleted {} expired records from {} zones",
+            res.rows_affected(),
+            zoneids.len()
+        );
+        Ok(res.rows_affected())
+    }
+
+    /// Search the records a user can see by their comments and tags, see [FileZoneRecord::matches_search] for the query format
+    pub async fn search(
+        txn: &mut SqliteConnection,
+        user: &User,
+        query: &str,
+    ) -> Result<Vec<FileZoneRecord>, GoatNsError> {
+        let query_string = match user.admin {
+            true => {
+                "SELECT records.id, records.zoneid, records.name, ttl, rrtype, rclass, rdata, comment, tags, expires_at
+                    FROM records
+                    WHERE (comment IS NOT NULL OR tags IS NOT NULL)"
+            }
+            false => {
+                "SELECT records.id, records.zoneid, records.name, ttl, rrtype, rclass, rdata, comment, tags, expires_at
+                    FROM records, ownership
+                    WHERE (comment IS NOT NULL OR tags IS NOT NULL)
+                        AND records.zoneid = ownership.zoneid
+                        AND ownership.userid = ?"
+            }
+        };
+        let sql_query = sqlx::query(query_string);
+        let sql_query = match user.admin {
+            true => sql_query,
+            false => sql_query.bind(user.id),
+        };
+
+        let res = sql_query.fetch_all(txn).await?;
+        Ok(res
+            .into_iter()
+            .filter_map(|row| FileZoneRecord::try_from(row).ok())
+            .filter(|record| record.matches_search(query))
+            .collect())
+    }
+
+    /// The A and AAAA records a user can see that point at the address, and PTR records for its reverse name. These
+    /// come from the records_merged view, so the names are the full names rather than relative to the zone.
+    pub async fn lookup_address(
+        txn: &mut SqliteConnection,
+        user: &User,
+        ip: &IpAddr,
+    ) -> Result<Vec<FileZoneRecord>, GoatNsError> {
+        let query_string = match user.admin {
+            true => {
+                "SELECT *, record_id AS id FROM records_merged
+                    WHERE rrtype IN (?, ?) OR (rrtype = ? AND name = ?)"
+            }
+            false => {
+                "SELECT records_merged.*, record_id AS id
+                    FROM records_merged, ownership
+                    WHERE records_merged.zoneid = ownership.zoneid
+                        AND ownership.userid = ?
+                        AND (rrtype IN (?, ?) OR (rrtype = ? AND name = ?))"
+            }
+        };
+        let sql_query = sqlx::query(query_string);
+        let sql_query = match user.admin {
+            true => sql_query,
+            false => sql_query.bind(user.id),
+        };
+        let res = sql_query
+            .bind(RecordType::A as u16)
+            .bind(RecordType::AAAA as u16)
+            .bind(RecordType::PTR as u16)
+            .bind(crate::utils::reverse_name(ip))
+            .fetch_all(txn)
+            .await?;
+
+        // AAAA records can be written a few ways, so the addresses are compared here rather than in the query
+        Ok(res
+            .into_iter()
+            .filter_map(|row| FileZoneRecord::try_from(row).ok())
+            .filter(|record| {
+                record.rrtype == "PTR" || record.rdata.trim().parse::<IpAddr>().ok() == Some(*ip)
+            })
+            .collect())
+    }
+}
+
+pub async fn get_all_fzr_by_name<'t>(
+    txn: &mut SqliteConnection,
+    name: &str,
+    rrtype: u16,
+) -> Result<Vec<FileZoneRecord>, GoatNsError> {
+    let res = sqlx::query(SQL_GET_FZR_BY_NAME_RRTYPE)
+        .persistent(true)
+        .bind(name)
+        .bind(rrtype)
+        .fetch_all(txn)
+        .await?;
+
+    let res = res.into_iter().filter_map(|r| r.try_into().ok()).collect();
+    Ok(res)
+}
+
+/// The last OIDC discovery document and signing keys we got from the IdP, so logins keep working
+/// when it's unreachable
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OidcCache {
+    /// The issuer URL it was discovered from
+    pub issuer: String,
+    /// The provider metadata, as JSON
+    pub metadata: String,
+    /// The JWKS, as JSON - it's not part of the serialized metadata
+    pub jwks: String,
+    /// When we last pulled it from the IdP
+    pub updated: DateTime<Utc>,
+}
+
+impl OidcCache {
+    pub const TABLE: &'static str = "oidc_cache";
+
+    pub async fn create_table(pool: &SqlitePool) -> Result<(), GoatNsError> {
+        log::debug!("Ensuring DB {} table exists", Self::TABLE);
+        sqlx::query(&format!(
+            r#"CREATE TABLE IF NOT EXISTS
+                {} (
+                    issuer TEXT PRIMARY KEY NOT NULL,
+                    metadata TEXT NOT NULL,
+                    jwks TEXT NOT NULL,
+                    updated INTEGER NOT NULL
+                )"#,
+            Self::TABLE
+        ))
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Get the cached metadata for an issuer, if we've got any
+    pub async fn get(pool: &SqlitePool, issuer: &str) -> Result<Option<Self>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "SELECT issuer, metadata, jwks, updated FROM {} WHERE issuer = ?",
+            Self::TABLE
+        ))
+        .bind(issuer)
+        .fetch_optional(pool)
+        .await?;
+        res.map(Self::try_from).transpose()
+    }
+
+    /// Store it, replacing whatever was there before for the issuer
+    pub async fn save(&self, pool: &SqlitePool) -> Result<(), GoatNsError> {
+        sqlx::query(&format!(
+            "INSERT INTO {} (issuer, metadata, jwks, updated) VALUES (?, ?, ?, ?)
+            ON CONFLICT(issuer) DO UPDATE SET
+            metadata = excluded.metadata, jwks = excluded.jwks, updated = excluded.updated",
+            Self::TABLE
+        ))
+        .bind(&self.issuer)
+        .bind(&self.metadata)
+        .bind(&self.jwks)
+        .bind(self.updated.timestamp())
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+impl TryFrom<SqliteRow> for OidcCache {
+    type Error = GoatNsError;
+    fn try_from(row: SqliteRow) -> Result<Self, Self::Error> {
+        let updated: i64 = row.get("updated");
+        let updated = DateTime::from_timestamp(updated, 0).ok_or_else(|| {
+            GoatNsError::InvalidValue(format!("Invalid OIDC cache timestamp: {updated}"))
+        })?;
+        Ok(Self {
+            issuer: row.get("issuer"),
+            metadata: row.get("metadata"),
+            jwks: row.get("jwks"),
+            updated,
+        })
+    }
+}
+
+/// A zone name admins have said nobody can create, on top of the ones in [crate::name_policy::BUILTIN_RESERVED_NAMES]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReservedName {
+    /// Stored lower-case without the trailing dot, names under it are reserved too
+    pub name: String,
+    /// Why it's reserved
+    pub comment: String,
+}
+
+impl ReservedName {
+    pub const TABLE: &'static str = "reserved_names";
+
+    pub async fn create_table(pool: &SqlitePool) -> Result<(), GoatNsError> {
+        log::debug!("Ensuring DB {} table exists", Self::TABLE);
+        sqlx::query(&format!(
+            r#"CREATE TABLE IF NOT EXISTS
+                {} (
+                    name TEXT PRIMARY KEY NOT NULL,
+                    comment TEXT NOT NULL DEFAULT ''
+                )"#,
+            Self::TABLE
+        ))
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Everything that's reserved, sorted by name
+    pub async fn get_all(txn: &mut SqliteConnection) -> Result<Vec<Self>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "SELECT name, comment FROM {} ORDER BY name",
+            Self::TABLE
+        ))
+        .fetch_all(txn)
+        .await?;
+        Ok(res.into_iter().map(Self::from).collect())
+    }
+
+    /// Reserve the name, updating the comment if it's already there
+    pub async fn save(&self, pool: &SqlitePool) -> Result<(), GoatNsError> {
+        let name = crate::name_policy::normalise(&self.name);
+        if name.is_empty() {
+            return Err(GoatNsError::InvalidValue(
+                "Reserved names can't be empty".to_string(),
+            ));
+        }
+        sqlx::query(&format!(
+            "INSERT INTO {} (name, comment) VALUES (?, ?)
+            ON CONFLICT(name) DO UPDATE SET comment = excluded.comment",
+            Self::TABLE
+        ))
+        .bind(name)
+        .bind(&self.comment)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Let people have the name again
+    pub async fn delete(pool: &SqlitePool, name: &str) -> Result<(), GoatNsError> {
+        let res = sqlx::query(&format!("DELETE FROM {} WHERE name = ?", Self::TABLE))
+            .bind(crate::name_policy::normalise(name))
+            .execute(pool)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(())
+    }
+}
+
+impl From<SqliteRow> for ReservedName {
+    fn from(row: SqliteRow) -> Self {
+        Self {
+            name: row.get("name"),
+            comment: row.get("comment"),
+        }
+    }
+}
+
+/// A zone that's kept in sync with a zone file by [crate::sync], it can't be edited any other way
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncedZone {
+    pub zoneid: i64,
+    /// The file it came from, relative to the sync directory
+    pub source: String,
+    /// When it last changed because of the file
+    pub synced: DateTime<Utc>,
+}
+
+impl SyncedZone {
+    pub const TABLE: &'static str = "synced_zones";
+
+    pub async fn create_table(pool: &SqlitePool) -> Result<(), GoatNsError> {
+        log::debug!("Ensuring DB {} table exists", Self::TABLE);
+        sqlx::query(&format!(
+            r#"CREATE TABLE IF NOT EXISTS
+                {} (
+                    zoneid INTEGER PRIMARY KEY NOT NULL,
+                    source TEXT NOT NULL,
+                    synced INTEGER NOT NULL,
+                    FOREIGN KEY(zoneid) REFERENCES zones(id)
+                )"#,
+            Self::TABLE
+        ))
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// If the zone's synced, where from
+    pub async fn get(txn: &mut SqliteConnection, zoneid: i64) -> Result<Option<Self>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "SELECT zoneid, source, synced FROM {} WHERE zoneid = ?",
+            Self::TABLE
+        ))
+        .bind(zoneid)
+        .fetch_optional(txn)
+        .await?;
+        res.map(Self::try_from).transpose()
+    }
+
+    /// Every synced zone
+    pub async fn get_all(txn: &mut SqliteConnection) -> Result<Vec<Self>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "SELECT zoneid, source, synced FROM {} ORDER BY zoneid",
+            Self::TABLE
+        ))
+        .fetch_all(txn)
+        .await?;
+        res.into_iter().map(Self::try_from).collect()
+    }
+
+    /// Store it, replacing whatever was there for the zone
+    pub async fn save_with_txn(&self, txn: &mut SqliteConnection) -> Result<(), GoatNsError> {
+        sqlx::query(&format!(
+            "INSERT INTO {} (zoneid, source, synced) VALUES (?, ?, ?)
+            ON CONFLICT(zoneid) DO UPDATE SET source = excluded.source, synced = excluded.synced",
+            Self::TABLE
+        ))
+        .bind(self.zoneid)
+        .bind(&self.source)
+        .bind(self.synced.timestamp())
+        .execute(txn)
+        .await?;
+        Ok(())
+    }
+
+    /// Stop syncing the zone, it's left as it is
+    pub async fn delete_with_txn(
+        txn: &mut SqliteConnection,
+        zoneid: i64,
+    ) -> Result<(), GoatNsError> {
+        sqlx::query(&format!("DELETE FROM {} WHERE zoneid = ?", Self::TABLE))
+            .bind(zoneid)
+            .execute(txn)
+            .await?;
+        Ok(())
+    }
+
+    /// An error if the zone's synced, for everything that'd change it
+    pub async fn check_editable(
+        txn: &mut SqliteConnection,
+        zoneid: i64,
+    ) -> Result<(), GoatNsError> {
+        match Self::get(txn, zoneid).await? {
+            Some(synced) => Err(GoatNsError::InvalidValue(format!(
+                "This zone is kept in sync with {}, change it there",
+                synced.source
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl TryFrom<SqliteRow> for SyncedZone {
+    type Error = GoatNsError;
+    fn try_from(row: SqliteRow) -> Result<Self, Self::Error> {
+        let synced: i64 = row.get("synced");
+        let synced = DateTime::from_timestamp(synced, 0).ok_or_else(|| {
+            GoatNsError::InvalidValue(format!("Invalid sync timestamp: {synced}"))
+        })?;
+        Ok(Self {
+            zoneid: row.get("zoneid"),
+            source: row.get("source"),
+            synced,
+        })
+    }
+}
+
+/// Someone's preferences for the web UI, until they change anything they get [UserSettings::default]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(default)]
+pub struct UserSettings {
+    /// Times are shown in this, `UTC` or an offset like `+10:00`
+    pub timezone: String,
+    /// How many rows to show on a page, 10 to 500
+    pub rows_per_page: u32,
+    /// The TTL filled in when adding records, the usual default if it's not set
+    pub default_ttl: Option<u32>,
+    /// Say on the dashboard when someone wants to give you a zone
+    pub notify_transfers: bool,
+    /// Say on the dashboard when there's changes waiting for you to review
+    pub notify_changesets: bool,
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        Self {
+            timezone: "UTC".to_string(),
+            rows_per_page: 20,
+            default_ttl: None,
+            notify_transfers: true,
+            notify_changesets: true,
+        }
+    }
+}
+
+impl UserSettings {
+    pub const TABLE: &'static str = "user_settings";
+
+    pub async fn create_table(pool: &SqlitePool) -> Result<(), GoatNsError> {
+        log::debug!("Ensuring DB {} table exists", Self::TABLE);
+        sqlx::query(&format!(
+            r#"CREATE TABLE IF NOT EXISTS
+                {} (
+                    userid INTEGER PRIMARY KEY NOT NULL,
+                    timezone TEXT NOT NULL,
+                    rows_per_page INTEGER NOT NULL,
+                    default_ttl INTEGER,
+                    notify_transfers BOOLEAN NOT NULL,
+                    notify_changesets BOOLEAN NOT NULL,
+                    FOREIGN KEY(userid) REFERENCES users(id)
+                )"#,
+            Self::TABLE
+        ))
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The user's settings, or the defaults if they've never saved any
+    pub async fn get(txn: &mut SqliteConnection, userid: i64) -> Result<Self, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "SELECT timezone, rows_per_page, default_ttl, notify_transfers, notify_changesets
+            FROM {} WHERE userid = ?",
+            Self::TABLE
+        ))
+        .bind(userid)
+        .fetch_optional(txn)
+        .await?;
+        Ok(res.map(Self::from).unwrap_or_default())
+    }
+
+    /// Check them and store them for the user
+    pub async fn save(&self, pool: &SqlitePool, userid: i64) -> Result<(), GoatNsError> {
+        self.check()?;
+        sqlx::query(&format!(
+            "INSERT INTO {} (userid, timezone, rows_per_page, default_ttl, notify_transfers, notify_changesets)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT(userid) DO UPDATE SET
+            timezone = excluded.timezone, rows_per_page = excluded.rows_per_page,
+            default_ttl = excluded.default_ttl, notify_transfers = excluded.notify_transfers,
+            notify_changesets = excluded.notify_changesets",
+            Self::TABLE
+        ))
+        .bind(userid)
+        .bind(self.timezone.trim())
+        .bind(self.rows_per_page)
+        .bind(self.default_ttl)
+        .bind(self.notify_transfers)
+        .bind(self.notify_changesets)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// An error saying what's wrong, if anything is
+    pub fn check(&self) -> Result<(), GoatNsError> {
+        if parse_utc_offset(&self.timezone).is_none() {
+            return Err(GoatNsError::InvalidValue(format!(
+                "Invalid timezone {:?}, it needs to be UTC or an offset like +10:00",
+                self.timezone
+            )));
+        }
+        if !(10..=500).contains(&self.rows_per_page) {
+            return Err(GoatNsError::InvalidValue(
+                "Rows per page needs to be between 10 and 500".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The time in the user's timezone, for showing in the UI
+    pub fn localtime(&self, when: DateTime<Utc>) -> String {
+        match parse_utc_offset(&self.timezone) {
+            Some(offset) => when
+                .with_timezone(&offset)
+                .format("%Y-%m-%d %H:%M:%S %:z")
+                .to_string(),
+            None => when.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        }
+    }
+}
+
+/// `UTC` (or `Z`) or an offset like `+10:00` or `-0330`
+pub fn parse_utc_offset(timezone: &str) -> Option<FixedOffset> {
+    let timezone = timezone.trim();
+    if timezone.eq_ignore_ascii_case("utc") || timezone.eq_ignore_ascii_case("z") {
+        return FixedOffset::east_opt(0);
+    }
+    let (sign, rest) = match timezone.split_at_checked(1)? {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() == 4 => rest.split_at_checked(2)?,
+        None => (rest, "0"),
+    };
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+impl From<SqliteRow> for UserSettings {
+    fn from(row: SqliteRow) -> Self {
+        Self {
+            timezone: row.get("timezone"),
+            rows_per_page: row.get("rows_per_page"),
+            default_ttl: row.get("default_ttl"),
+            notify_transfers: row.get("notify_transfers"),
+            notify_changesets: row.get("notify_changesets"),
+        }
+    }
+}
+
+/// An admin's override of the server's [QuotaConfig] for a user, where it's [None] the server's applies
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserQuota {
+    pub userid: i64,
+    pub max_zones: Option<u32>,
+    pub max_records_per_zone: Option<u32>,
+}
+
+impl UserQuota {
+    pub const TABLE: &'static str = "user_quotas";
+
+    pub async fn create_table(pool: &SqlitePool) -> Result<(), GoatNsError> {
+        log::debug!("Ensuring DB {} table exists", Self::TABLE);
+        sqlx::query(&format!(
+            r#"CREATE TABLE IF NOT EXISTS
+                {} (
+                    userid INTEGER PRIMARY KEY NOT NULL,
+                    max_zones INTEGER,
+                    max_records_per_zone INTEGER,
+                    FOREIGN KEY(userid) REFERENCES users(id)
+                )"#,
+            Self::TABLE
+        ))
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The user's override, if they've got one
+    pub async fn get(txn: &mut SqliteConnection, userid: i64) -> Result<Option<Self>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "SELECT userid, max_zones, max_records_per_zone FROM {} WHERE userid = ?",
+            Self::TABLE
+        ))
+        .bind(userid)
+        .fetch_optional(txn)
+        .await?;
+        Ok(res.map(Self::from))
+    }
+
+    /// Every override, with the username it's for
+    pub async fn get_all(txn: &mut SqliteConnection) -> Result<Vec<(String, Self)>, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "SELECT q.userid, q.max_zones, q.max_records_per_zone, users.username
+            FROM {} q, users WHERE q.userid = users.id ORDER BY users.username",
+            Self::TABLE
+        ))
+        .fetch_all(txn)
+        .await?;
+        Ok(res
+            .into_iter()
+            .map(|row| (row.get("username"), Self::from(row)))
+            .collect())
+    }
+
+    /// Store it, replacing the user's last one
+    pub async fn save(&self, pool: &SqlitePool) -> Result<(), GoatNsError> {
+        sqlx::query(&format!(
+            "INSERT INTO {} (userid, max_zones, max_records_per_zone) VALUES (?, ?, ?)
+            ON CONFLICT(userid) DO UPDATE SET
+            max_zones = excluded.max_zones, max_records_per_zone = excluded.max_records_per_zone",
+            Self::TABLE
+        ))
+        .bind(self.userid)
+        .bind(self.max_zones)
+        .bind(self.max_records_per_zone)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Put the user back on the server's quotas
+    pub async fn delete(pool: &SqlitePool, userid: i64) -> Result<(), GoatNsError> {
+        sqlx::query(&format!("DELETE FROM {} WHERE userid = ?", Self::TABLE))
+            .bind(userid)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// The quotas that apply to the user, admins don't have any
+    pub async fn for_user(
+        txn: &mut SqliteConnection,
+        user: &User,
+        config: &QuotaConfig,
+    ) -> Result<QuotaConfig, GoatNsError> {
+        let userid = match (user.admin, user.id) {
+            (false, Some(userid)) => userid,
+            _ => return Ok(QuotaConfig::default()),
+        };
+        Ok(match Self::get(txn, userid).await? {
+            Some(quota) => QuotaConfig {
+                max_zones: quota.max_zones.unwrap_or(config.max_zones),
+                max_records_per_zone: quota
+                    .max_records_per_zone
+                    .unwrap_or(config.max_records_per_zone),
+            },
+            None => *config,
+        })
+    }
+
+    /// An error if the user can't own `adding` more zones
+    pub async fn check_zones(
+        txn: &mut SqliteConnection,
+        user: &User,
+        config: &QuotaConfig,
+        adding: usize,
+    ) -> Result<(), GoatNsError> {
+        let quota = Self::for_user(txn, user, config).await?;
+        if quota.max_zones == 0 {
+            return Ok(());
+        }
+        let owned: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM ownership WHERE userid = ?")
+            .bind(user.id)
+            .fetch_one(&mut *txn)
+            .await?;
+        if owned as usize + adding > quota.max_zones as usize {
+            return Err(GoatNsError::QuotaExceeded(format!(
+                "You can only have {} zones and you've got {owned}, ask an admin if you need more",
+                quota.max_zones
+            )));
+        }
+        Ok(())
+    }
+
+    /// An error if `adding` more records would put the zone (which might not exist yet) over the user's quota
+    pub async fn check_records(
+        txn: &mut SqliteConnection,
+        user: &User,
+        config: &QuotaConfig,
+        zoneid: Option<i64>,
+        adding: usize,
+    ) -> Result<(), GoatNsError> {
+        let quota = Self::for_user(txn, user, config).await?;
+        if quota.max_records_per_zone == 0 {
+            return Ok(());
+        }
+        let existing: i64 = match zoneid {
+            Some(zoneid) => {
+                sqlx::query_scalar("SELECT COUNT(*) FROM records WHERE zoneid = ?")
+                    .bind(zoneid)
+                    .fetch_one(&mut *txn)
+                    .await?
+            }
+            None => 0,
+        };
+        if existing as usize + adding > quota.max_records_per_zone as usize {
+            return Err(GoatNsError::QuotaExceeded(format!(
+                "Zones can only have {} records, ask an admin if you need more",
+                quota.max_records_per_zone
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl From<SqliteRow> for UserQuota {
+    fn from(row: SqliteRow) -> Self {
+        Self {
+            userid: row.get("userid"),
+            max_zones: row.get("max_zones"),
+            max_records_per_zone: row.get("max_records_per_zone"),
+        }
+    }
+}
+
+/// How many DoH queries an API token made for a name on a day, they're counted up by [crate::web::doh::usage]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DohUsage {
+    pub tokenkey: String,
+    /// The day in UTC
+    pub day: NaiveDate,
+    pub name: String,
+    pub queries: u64,
+}
+
+/// What a token's been used for lately, for its owner's profile page
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DohTokenUsage {
+    /// The token's name, not the key
+    pub token_name: String,
+    pub tokenkey: String,
+    /// Queries each day, newest first
+    pub days: Vec<(NaiveDate, u64)>,
+    /// The names it asked for the most
+    pub top_names: Vec<(String, u64)>,
+}
+
+impl DohUsage {
+    pub const TABLE: &'static str = "doh_usage";
+
+    pub async fn create_table(pool: &SqlitePool) -> Result<(), GoatNsError> {
+        log::debug!("Ensuring DB {} table exists", Self::TABLE);
+        sqlx::query(&format!(
+            r#"CREATE TABLE IF NOT EXISTS
+                {} (
+                    tokenkey TEXT NOT NULL,
+                    day TEXT NOT NULL,
+                    name TEXT NOT NULL,
+                    queries INTEGER NOT NULL,
+                    PRIMARY KEY(tokenkey, day, name)
+                )"#,
+            Self::TABLE
+        ))
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Add the queries on to whatever's already been counted
+    pub async fn add_with_txn(&self, txn: &mut SqliteConnection) -> Result<(), GoatNsError> {
+        sqlx::query(&format!(
+            "INSERT INTO {} (tokenkey, day, name, queries) VALUES (?, ?, ?, ?)
+            ON CONFLICT(tokenkey, day, name) DO UPDATE SET queries = queries + excluded.queries",
+            Self::TABLE
+        ))
+        .bind(&self.tokenkey)
+        .bind(self.day.to_string())
+        .bind(&self.name)
+        .bind(self.queries as i64)
+        .execute(txn)
+        .await?;
+        Ok(())
+    }
+
+    /// Usage of each of the user's tokens from `since` on, with the `top` names each asked for
+    pub async fn for_user(
+        txn: &mut SqliteConnection,
+        userid: i64,
+        since: NaiveDate,
+        top: usize,
+    ) -> Result<Vec<DohTokenUsage>, GoatNsError> {
+        let days = sqlx::query(&format!(
+            "SELECT user_tokens.name AS token_name, u.tokenkey, u.day, SUM(u.queries) AS queries
+            FROM {} u, user_tokens
+            WHERE u.tokenkey = user_tokens.tokenkey AND user_tokens.userid = ? AND u.day >= ?
+            GROUP BY u.tokenkey, u.day
+            ORDER BY user_tokens.name, u.tokenkey, u.day DESC",
+            Self::TABLE
+        ))
+        .bind(userid)
+        .bind(since.to_string())
+        .fetch_all(&mut *txn)
+        .await?;
+
+        let mut usage: Vec<DohTokenUsage> = vec![];
+        for row in days {
+            let tokenkey: String = row.get("tokenkey");
+            let day: String = row.get("day");
+            let day = NaiveDate::from_str(&day).map_err(|err| {
+                GoatNsError::InvalidValue(format!("Invalid DoH usage day {day:?}: {err:?}"))
+            })?;
+            let queries: i64 = row.get("queries");
+            // they're ordered by token, so it's either the last one or a new one
+            if let Some(token) = usage.last_mut().filter(|token| token.tokenkey == tokenkey) {
+                token.days.push((day, queries as u64));
+                continue;
+            }
+            usage.push(DohTokenUsage {
+                token_name: row.get("token_name"),
+                tokenkey,
+                days: vec![(day, queries as u64)],
+                top_names: vec![],
+            });
+        }
+
+        let names = sqlx::query(&format!(
+            "SELECT tokenkey, name, queries FROM (
+                SELECT u.tokenkey, u.name, SUM(u.queries) AS queries,
+                    ROW_NUMBER() OVER (PARTITION BY u.tokenkey ORDER BY SUM(u.queries) DESC, u.name) AS position
+                FROM {} u, user_tokens
+                WHERE u.tokenkey = user_tokens.tokenkey AND user_tokens.userid = ? AND u.day >= ?
+                GROUP BY u.tokenkey, u.name
+            ) WHERE position <= ? ORDER BY tokenkey, position",
+            Self::TABLE
+        ))
+        .bind(userid)
+        .bind(since.to_string())
+        .bind(top as i64)
+        .fetch_all(&mut *txn)
+        .await?;
+        for row in names {
+            let tokenkey: String = row.get("tokenkey");
+            let queries: i64 = row.get("queries");
+            if let Some(token) = usage.iter_mut().find(|token| token.tokenkey == tokenkey) {
+                token.top_names.push((row.get("name"), queries as u64));
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Forget about anything from before `before`, and tokens that have been deleted
+    pub async fn cleanup(pool: &SqlitePool, before: NaiveDate) -> Result<u64, GoatNsError> {
+        let res = sqlx::query(&format!(
+            "DELETE FROM {0} WHERE day < ? OR tokenkey NOT IN (SELECT tokenkey FROM {1})",
+            Self::TABLE,
+            UserAuthToken::TABLE
+        ))
+        .bind(before.to_string())
+        .execute(pool)
+        .await?;
+        Ok(res.rows_affected())
+    }
 }
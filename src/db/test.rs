@@ -2,7 +2,11 @@ use super::*;
 
 use crate::enums::{RecordClass, RecordType};
 use crate::error::GoatNsError;
-use crate::zones::{FileZone, FileZoneRecord};
+use crate::zones::{
+    next_serial, ChangeSetStatus, FileZone, FileZoneRecord, ImportStrategy, ImportSummary,
+    OwnershipTransferStatus, RecordChange, ZoneChangeSet, ZoneJournalEntry, ZoneOwnershipTransfer,
+    ZoneTemplate,
+};
 
 #[tokio::test]
 async fn create_user() -> Result<(), GoatNsError> {
@@ -52,6 +56,9 @@ pub async fn test_create_example_com_records(
             rdata,
             id: None,
             ttl: i as u32,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
         }
         .save(&pool)
         .await?;
@@ -147,6 +154,9 @@ async fn test_db_create_records() -> Result<(), GoatNsError> {
         rrtype: rrtype.into(),
         class: RecordClass::Internet.into(),
         rdata: "test txt".to_string(),
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
     };
     println!("rec to create: {rec_to_create:?}");
     if let Err(error) = rec_to_create.save(&pool).await {
@@ -204,6 +214,9 @@ async fn test_all_db_things() -> Result<(), GoatNsError> {
         rrtype: rrtype.into(),
         class: RecordClass::Internet.into(),
         rdata: "test txt".to_string(),
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
     };
     println!("rec to create: {rec_to_create:?}");
     if let Err(err) = rec_to_create.save(&pool).await {
@@ -305,7 +318,7 @@ async fn test_export_zone() -> Result<(), GoatNsError> {
     println!("found {} records", exported_zone.records.len());
     assert_eq!(exported_zone.records.len(), records_to_create);
 
-    let json_result = serde_json::to_string_pretty(&exported_zone).unwrap();
+    let json_result = crate::zone_schema::zones_to_json(&[*exported_zone.clone()]).unwrap();
 
     println!("{json_result}");
 
@@ -363,3 +376,1234 @@ async fn load_then_export() -> Result<(), GoatNsError> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_zone_template_create_and_apply() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let template = ZoneTemplate {
+        id: None,
+        name: "web hosting".to_string(),
+        description: "www and mail".to_string(),
+        records: vec![
+            FileZoneRecord {
+                id: None,
+                zoneid: None,
+                name: "www".to_string(),
+                rrtype: RecordType::CNAME.to_string(),
+                class: RecordClass::Internet,
+                rdata: "{zone}".to_string(),
+                ttl: 300,
+                comment: None,
+                tags: Default::default(),
+                expires_at: None,
+            },
+            FileZoneRecord {
+                id: None,
+                zoneid: None,
+                name: "@".to_string(),
+                rrtype: RecordType::MX.to_string(),
+                class: RecordClass::Internet,
+                rdata: "10 mail.{zone}".to_string(),
+                ttl: 300,
+                comment: None,
+                tags: Default::default(),
+                expires_at: None,
+            },
+        ],
+    }
+    .save(&pool)
+    .await?;
+
+    let template_id = template.id.expect("template didn't get an id");
+    let from_db = ZoneTemplate::get(&pool, template_id).await?;
+    assert_eq!(from_db, template);
+    assert_eq!(ZoneTemplate::get_all(&pool).await?.len(), 1);
+
+    let records = from_db.apply("example.com");
+    assert_eq!(records[0].rdata, "example.com");
+    assert_eq!(records[1].rdata, "10 mail.example.com");
+    assert!(records.iter().all(|r| r.id.is_none() && r.zoneid.is_none()));
+
+    // names are unique
+    let res = ZoneTemplate {
+        id: None,
+        ..*template.clone()
+    }
+    .save(&pool)
+    .await;
+    assert!(res.is_err());
+
+    from_db.delete(&pool).await?;
+    assert!(ZoneTemplate::get_all(&pool).await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_record_comments_and_tags() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+    // make sure the column migration is idempotent
+    FileZoneRecord::create_table(&pool).await?;
+
+    let mut zone = test_example_com_zone();
+    zone.records = vec![FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: "_weird".to_string(),
+        rrtype: RecordType::TXT.to_string(),
+        class: RecordClass::Internet,
+        rdata: "verification=12345".to_string(),
+        ttl: 300,
+        comment: Some("Needed for the vendor verification".to_string()),
+        tags: [("team".to_string(), "billing".to_string())].into(),
+        expires_at: None,
+    }];
+    zone.save(&pool).await?;
+
+    let exported = FileZone::get(&pool, 1).await?;
+    assert_eq!(exported.records.len(), 1);
+    assert_eq!(exported.records[0].comment, zone.records[0].comment);
+    assert_eq!(exported.records[0].tags, zone.records[0].tags);
+
+    // tags should survive a JSON export and import
+    let json = exported.json().expect("Failed to export zone");
+    let imported: FileZone = serde_json::from_str(&json).expect("Failed to parse zone");
+    assert_eq!(imported.records[0].tags, zone.records[0].tags);
+
+    let user = User {
+        admin: true,
+        ..User::default()
+    };
+    let mut txn = pool.begin().await?;
+    assert_eq!(
+        FileZoneRecord::search(&mut txn, &user, "team=billing")
+            .await?
+            .len(),
+        1
+    );
+    assert_eq!(
+        FileZoneRecord::search(&mut txn, &user, "VENDOR")
+            .await?
+            .len(),
+        1
+    );
+    assert!(FileZoneRecord::search(&mut txn, &user, "team=sales")
+        .await?
+        .is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_zone_changeset_approve() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+    // make sure the column migration is idempotent
+    FileZone::create_table(&pool).await?;
+
+    let user = User {
+        username: "reviewer".to_string(),
+        email: "reviewer@hello.goat".to_string(),
+        ..User::default()
+    }
+    .save(&pool)
+    .await?;
+    let userid = user.id.expect("User should have an ID after saving");
+
+    let record = FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: "www".to_string(),
+        rrtype: RecordType::A.to_string(),
+        class: RecordClass::Internet,
+        rdata: "192.0.2.1".to_string(),
+        ttl: 300,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    };
+    let mut zone = test_example_com_zone();
+    zone.review_required = true;
+    zone.records = vec![
+        record.clone(),
+        FileZoneRecord {
+            name: "old".to_string(),
+            ..record.clone()
+        },
+    ];
+    zone.save(&pool).await?;
+
+    let saved = FileZone::get(&pool, 1).await?;
+    assert!(saved.review_required);
+    let www = saved
+        .records
+        .iter()
+        .find(|r| r.name == "www")
+        .expect("Couldn't find www record")
+        .clone();
+    let old = saved
+        .records
+        .iter()
+        .find(|r| r.name == "old")
+        .expect("Couldn't find old record")
+        .clone();
+
+    let changeset = ZoneChangeSet {
+        id: None,
+        zoneid: 1,
+        userid,
+        description: "Move www and add mail".to_string(),
+        created: chrono::Utc::now(),
+        status: ChangeSetStatus::Pending,
+        changes: vec![
+            RecordChange::Create(FileZoneRecord {
+                name: "mail".to_string(),
+                ..record.clone()
+            }),
+            RecordChange::Update(FileZoneRecord {
+                rdata: "192.0.2.2".to_string(),
+                ..www.clone()
+            }),
+            RecordChange::Delete(old.id.expect("Record should have an ID")),
+        ],
+    }
+    .save(&pool)
+    .await?;
+
+    let mut txn = pool.begin().await?;
+    let pending = ZoneChangeSet::get_pending_for_zone(&mut txn, 1).await?;
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].changes, changeset.changes);
+
+    let approved = changeset.approve_with_txn(&mut txn).await?;
+    txn.commit().await?;
+    assert_eq!(approved.status, ChangeSetStatus::Approved);
+
+    let updated = FileZone::get(&pool, 1).await?;
+    assert_eq!(updated.serial, next_serial(saved.serial));
+    assert_eq!(updated.records.len(), 2);
+    assert!(updated.records.iter().any(|r| r.name == "mail"));
+    assert!(updated
+        .records
+        .iter()
+        .any(|r| r.name == "www" && r.rdata == "192.0.2.2"));
+
+    // approving twice shouldn't work
+    let mut txn = pool.begin().await?;
+    assert!(approved.approve_with_txn(&mut txn).await.is_err());
+    assert!(ZoneChangeSet::get_pending_for_zone(&mut txn, 1)
+        .await?
+        .is_empty());
+
+    // a change-set which can't apply cleanly shouldn't leave anything behind
+    let broken = ZoneChangeSet {
+        id: None,
+        zoneid: 1,
+        userid,
+        description: String::new(),
+        created: chrono::Utc::now(),
+        status: ChangeSetStatus::Pending,
+        changes: vec![
+            RecordChange::Create(FileZoneRecord {
+                name: "ftp".to_string(),
+                ..record.clone()
+            }),
+            RecordChange::Delete(12345),
+        ],
+    }
+    .save_with_txn(&mut txn)
+    .await?;
+    txn.commit().await?;
+
+    let mut txn = pool.begin().await?;
+    assert!(broken.approve_with_txn(&mut txn).await.is_err());
+    txn.rollback().await?;
+    let updated = FileZone::get(&pool, 1).await?;
+    assert_eq!(updated.serial, next_serial(saved.serial));
+    assert!(!updated.records.iter().any(|r| r.name == "ftp"));
+
+    // the change-sets go with the zone
+    updated.delete(&pool).await?;
+    assert!(FileZone::get(&pool, 1).await.is_err());
+    let mut txn = pool.begin().await?;
+    assert!(ZoneChangeSet::get_pending_for_zone(&mut txn, 1)
+        .await?
+        .is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_zone_set_ttls_and_undo() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let user = User {
+        username: "migrator".to_string(),
+        email: "migrator@hello.goat".to_string(),
+        ..User::default()
+    }
+    .save(&pool)
+    .await?;
+    let userid = user.id.expect("User should have an ID after saving");
+
+    let record = FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: "www".to_string(),
+        rrtype: RecordType::A.to_string(),
+        class: RecordClass::Internet,
+        rdata: "192.0.2.1".to_string(),
+        ttl: 3600,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    };
+    let mut zone = test_example_com_zone();
+    zone.records = vec![
+        record.clone(),
+        FileZoneRecord {
+            name: "mail".to_string(),
+            ttl: 86400,
+            ..record.clone()
+        },
+        FileZoneRecord {
+            name: "already".to_string(),
+            ttl: 300,
+            ..record.clone()
+        },
+    ];
+    zone.save(&pool).await?;
+    let saved = FileZone::get(&pool, 1).await?;
+
+    let mut txn = pool.begin().await?;
+    let entry = FileZone::set_ttls_with_txn(1, 300, userid, &mut txn).await?;
+    txn.commit().await?;
+    // only the ones that changed need putting back
+    assert_eq!(entry.undo.len(), 2);
+    assert_eq!(entry.description, "Set the TTL of 2 records to 300");
+
+    let updated = FileZone::get(&pool, 1).await?;
+    assert_eq!(updated.serial, next_serial(saved.serial));
+    assert!(updated.records.iter().all(|r| r.ttl == 300));
+
+    // nothing left to change
+    let mut txn = pool.begin().await?;
+    assert!(FileZone::set_ttls_with_txn(1, 300, userid, &mut txn)
+        .await
+        .is_err());
+
+    let journal = ZoneJournalEntry::get_for_zone(&mut txn, 1, 10).await?;
+    assert_eq!(journal.len(), 1);
+    assert_eq!(journal[0].undo, entry.undo);
+
+    let undone = journal[0].undo_with_txn(&mut txn).await?;
+    txn.commit().await?;
+    assert!(undone.undone);
+
+    let restored = FileZone::get(&pool, 1).await?;
+    assert_eq!(restored.serial, next_serial(next_serial(saved.serial)));
+    for record in restored.records.iter() {
+        let original = saved
+            .records
+            .iter()
+            .find(|r| r.id == record.id)
+            .expect("Record went missing");
+        assert_eq!(record.ttl, original.ttl);
+    }
+
+    // undoing twice shouldn't work
+    let mut txn = pool.begin().await?;
+    let journal = ZoneJournalEntry::get_for_zone(&mut txn, 1, 10).await?;
+    assert!(journal[0].undone);
+    assert!(journal[0].undo_with_txn(&mut txn).await.is_err());
+    txn.rollback().await?;
+
+    // the journal goes with the zone
+    restored.delete(&pool).await?;
+    assert!(FileZone::get(&pool, 1).await.is_err());
+    let mut txn = pool.begin().await?;
+    assert!(ZoneJournalEntry::get_for_zone(&mut txn, 1, 10)
+        .await?
+        .is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_import_batches_and_progress() -> Result<(), GoatNsError> {
+    use crate::datastore::{SqliteStore, ZoneStore};
+    use crate::zones::{ImportProgress, ImportProgressCallback};
+    use std::sync::Mutex;
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    // enough to need more than one INSERT
+    let records: Vec<FileZoneRecord> = (0..250)
+        .map(|i| FileZoneRecord {
+            id: None,
+            zoneid: None,
+            name: format!("host{i}"),
+            rrtype: RecordType::A.to_string(),
+            class: RecordClass::Internet,
+            rdata: format!("192.0.2.{}", i % 250),
+            ttl: 300,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        })
+        .collect();
+    let big = FileZone {
+        records,
+        ..test_example_com_zone()
+    };
+    let small = FileZone {
+        id: None,
+        name: "small.goat".to_string(),
+        records: vec![],
+        ..test_example_com_zone()
+    };
+
+    let updates: Arc<Mutex<Vec<ImportProgress>>> = Arc::default();
+    let seen = updates.clone();
+    let progress = ImportProgressCallback::new(move |progress| {
+        seen.lock()
+            .expect("Progress lock was poisoned")
+            .push(progress.clone())
+    });
+
+    let summary = SqliteStore::new(pool.clone())
+        .import_zones(
+            vec![big, small],
+            &Default::default(),
+            Default::default(),
+            false,
+            Some(&progress),
+        )
+        .await?;
+    assert_eq!(summary.zones, 2);
+
+    let saved = FileZone::get(&pool, 1).await?;
+    assert_eq!(saved.records.len(), 250);
+    assert!(saved.records.iter().any(|r| r.name == "host249"));
+
+    let updates = updates.lock().expect("Progress lock was poisoned");
+    assert_eq!(
+        *updates,
+        vec![
+            ImportProgress {
+                zones_done: 1,
+                zones_total: 2,
+                records_done: 250,
+                records_total: 250,
+            },
+            ImportProgress {
+                zones_done: 2,
+                zones_total: 2,
+                records_done: 250,
+                records_total: 250,
+            },
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_oidc_cache() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let issuer = "https://idp.example.com";
+    assert!(OidcCache::get(&pool, issuer).await?.is_none());
+
+    let updated = DateTime::from_timestamp(1700000000, 0).expect("bad timestamp");
+    let cache = OidcCache {
+        issuer: issuer.to_string(),
+        metadata: r#"{"issuer":"https://idp.example.com"}"#.to_string(),
+        jwks: r#"{"keys":[]}"#.to_string(),
+        updated,
+    };
+    cache.save(&pool).await?;
+    assert_eq!(OidcCache::get(&pool, issuer).await?, Some(cache.clone()));
+
+    // saving again replaces it
+    let newer = OidcCache {
+        jwks: r#"{"keys":[{}]}"#.to_string(),
+        updated: updated + chrono::TimeDelta::seconds(60),
+        ..cache
+    };
+    newer.save(&pool).await?;
+    assert_eq!(OidcCache::get(&pool, issuer).await?, Some(newer));
+    Ok(())
+}
+
+#[test]
+fn test_record_name_rules() {
+    let record = |name: &str, rrtype: &str| FileZoneRecord {
+        rrtype: rrtype.to_string(),
+        ..test_a_record(name, "192.0.2.1", 300)
+    };
+    for (name, rrtype) in [
+        ("_dmarc", "TXT"),
+        ("_443._tcp.www", "TLSA"),
+        ("_sip._tcp", "SRV"),
+        ("*", "A"),
+        ("*.www", "AAAA"),
+        ("@", "A"),
+        ("mail-1", "A"),
+    ] {
+        assert!(
+            record(name, rrtype).check_name().is_ok(),
+            "{name} should be fine for {rrtype}"
+        );
+    }
+    for (name, rrtype) in [
+        ("_dmarc", "A"),
+        ("www._tcp", "AAAA"),
+        ("www..example", "TXT"),
+        ("www.*", "TXT"),
+        ("not a name", "TXT"),
+    ] {
+        assert!(
+            matches!(
+                record(name, rrtype).check_name(),
+                Err(GoatNsError::InvalidValue(_))
+            ),
+            "{name} shouldn't be allowed for {rrtype}"
+        );
+    }
+
+    // they're checked on the way in
+    let mut zone = test_example_com_zone();
+    zone.records = vec![record("_dmarc.example.com.", "A")];
+    assert!(zone.normalise_names().is_err());
+    zone.records = vec![record("_dmarc.example.com.", "TXT")];
+    assert!(zone.normalise_names().is_ok());
+    assert_eq!(zone.records[0].name, "_dmarc");
+}
+
+#[test]
+fn test_cname_rules() {
+    let cname = |name: &str, target: &str| FileZoneRecord {
+        rrtype: RecordType::CNAME.to_string(),
+        ..test_a_record(name, target, 300)
+    };
+    let mut zone = test_example_com_zone();
+    zone.records = vec![
+        test_a_record("@", "192.0.2.1", 300),
+        cname("www", "example.com"),
+        cname("ftp", "www.example.com"),
+    ];
+    assert!(zone.check_cname_rules().is_ok());
+
+    zone.records.push(cname("@", "example.net"));
+    let msg = format!(
+        "{:?}",
+        zone.check_cname_rules()
+            .expect_err("CNAME at the apex should fail")
+    );
+    assert!(msg.contains("ALIAS"), "{msg}");
+    zone.records.pop();
+
+    zone.records.push(test_a_record("WWW", "192.0.2.2", 300));
+    let msg = format!(
+        "{:?}",
+        zone.check_cname_rules()
+            .expect_err("CNAME with other data should fail")
+    );
+    assert!(
+        msg.contains("www.example.com") && msg.contains("found A"),
+        "{msg}"
+    );
+    zone.records.pop();
+
+    zone.records.push(cname("ftp", "example.com"));
+    assert!(zone.check_cname_rules().is_err());
+    zone.records.pop();
+
+    // FORWARD records get the name to themselves too
+    let forward = |name: &str, target: &str| FileZoneRecord {
+        rrtype: RecordType::FORWARD.to_string(),
+        ..test_a_record(name, target, 300)
+    };
+    zone.records.push(forward("legacy", "legacy.example.net"));
+    assert!(zone.check_cname_rules().is_ok());
+    zone.records.push(test_a_record("legacy", "192.0.2.3", 300));
+    let msg = format!(
+        "{:?}",
+        zone.check_cname_rules()
+            .expect_err("FORWARD with other data should fail")
+    );
+    assert!(msg.contains("FORWARD") && msg.contains("found A"), "{msg}");
+    zone.records.pop();
+    zone.records.push(forward("@", "example.net"));
+    assert!(zone.check_cname_rules().is_err());
+}
+
+#[tokio::test]
+/// a merge that adds a CNAME next to existing records gets rolled back
+async fn test_import_cname_conflict() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+    let mut zone = test_example_com_zone();
+    zone.records = vec![test_a_record("www", "192.0.2.1", 300)];
+    zone.save(&pool).await?;
+
+    zone.records = vec![FileZoneRecord {
+        rrtype: RecordType::CNAME.to_string(),
+        ..test_a_record("www", "example.net", 300)
+    }];
+    let mut txn = pool.begin().await?;
+    let res = zone
+        .import_with_txn(&mut txn, ImportStrategy::Merge, ImportSummary::default())
+        .await;
+    assert!(matches!(res, Err(GoatNsError::InvalidValue(_))));
+    drop(txn);
+
+    let zone = FileZone::get(&pool, 1).await?;
+    assert_eq!(zone.records.len(), 1);
+    assert_eq!(zone.records[0].rrtype, "A");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_zone_default_ttl() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let mut zone = test_example_com_zone();
+    zone.default_ttl = Some(600);
+    zone.save(&pool).await?;
+    assert_eq!(FileZone::get(&pool, 1).await?.default_ttl, Some(600));
+
+    let mut txn = pool.begin().await?;
+    FileZone::set_default_ttl_with_txn(1, Some(900), &mut txn).await?;
+    // imports that don't say keep the zone's
+    let mut import = test_example_com_zone();
+    import.inherit_default_ttl_with_txn(&mut txn).await?;
+    assert_eq!(import.default_ttl, Some(900));
+
+    FileZone::set_default_ttl_with_txn(1, None, &mut txn).await?;
+    assert!(FileZone::set_default_ttl_with_txn(2, Some(60), &mut txn)
+        .await
+        .is_err());
+    txn.commit().await?;
+    assert_eq!(FileZone::get(&pool, 1).await?.default_ttl, None);
+    Ok(())
+}
+
+fn test_a_record(name: &str, rdata: &str, ttl: u32) -> FileZoneRecord {
+    FileZoneRecord::new(name, RecordType::A, rdata, ttl)
+}
+
+/// Sets up example.com with two records, then imports over the top of it
+async fn import_with_strategy(
+    strategy: ImportStrategy,
+) -> Result<(ImportSummary, FileZone), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let mut zone = test_example_com_zone();
+    zone.records = vec![
+        test_a_record("www", "192.0.2.1", 300),
+        test_a_record("mail", "192.0.2.2", 300),
+    ];
+    zone.save(&pool).await?;
+
+    let mut import = test_example_com_zone();
+    import.records = vec![
+        test_a_record("www", "192.0.2.1", 600),
+        test_a_record("mail", "192.0.2.9", 300),
+        test_a_record("new", "192.0.2.3", 300),
+    ];
+    let summary = ImportSummary {
+        zones: 1,
+        imported: import.records.len(),
+        ..Default::default()
+    };
+    let mut txn = pool.begin().await?;
+    let summary = import.import_with_txn(&mut txn, strategy, summary).await?;
+    txn.commit().await?;
+
+    Ok((summary, *FileZone::get(&pool, 1).await?))
+}
+
+#[tokio::test]
+async fn test_import_strategies() -> Result<(), GoatNsError> {
+    let (summary, zone) = import_with_strategy(ImportStrategy::Merge).await?;
+    assert_eq!(summary.imported, 2);
+    assert_eq!(summary.updated, 1);
+    assert_eq!(zone.records.len(), 4);
+    assert!(zone.records.iter().any(|r| r.name == "www" && r.ttl == 600));
+    assert_eq!(
+        zone.serial,
+        next_serial(0),
+        "merging should bump the serial"
+    );
+
+    let (summary, zone) = import_with_strategy(ImportStrategy::SkipExisting).await?;
+    assert_eq!(summary.imported, 1);
+    assert_eq!(summary.skipped, 2);
+    assert_eq!(zone.records.len(), 3);
+    assert!(zone.records.iter().any(|r| r.name == "www" && r.ttl == 300));
+
+    let (summary, zone) = import_with_strategy(ImportStrategy::Replace).await?;
+    assert_eq!(summary.imported, 3);
+    assert_eq!(summary.removed, 1);
+    assert_eq!(zone.records.len(), 3);
+
+    // running the same merge twice shouldn't change anything the second time
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+    let mut zone = test_example_com_zone();
+    zone.records = vec![test_a_record("www", "192.0.2.1", 300)];
+    for _ in 0..2 {
+        let mut txn = pool.begin().await?;
+        let summary = ImportSummary {
+            zones: 1,
+            imported: 1,
+            ..Default::default()
+        };
+        let summary = zone
+            .import_with_txn(&mut txn, ImportStrategy::Merge, summary)
+            .await?;
+        txn.commit().await?;
+        assert!(summary.imported + summary.unchanged == 1);
+    }
+    let zone = FileZone::get(&pool, 1).await?;
+    assert_eq!(zone.records.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_import_normalises_names() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let mut zone = test_example_com_zone();
+    zone.id = None;
+    zone.name = "Example.COM.".to_string();
+    zone.records = vec![
+        test_a_record("www.example.com.", "192.0.2.1", 300),
+        test_a_record("MAIL.Example.com", "192.0.2.2", 300),
+        test_a_record("example.com.", "192.0.2.3", 300),
+        test_a_record("Ftp", "192.0.2.4", 300),
+    ];
+    zone.normalise_names()?;
+    assert_eq!(zone.name, "example.com");
+    let names: Vec<&str> = zone.records.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, vec!["www", "mail", "@", "ftp"]);
+
+    let summary = ImportSummary {
+        zones: 1,
+        imported: zone.records.len(),
+        ..Default::default()
+    };
+    let mut txn = pool.begin().await?;
+    zone.import_with_txn(&mut txn, ImportStrategy::Merge, summary)
+        .await?;
+    // a row from before names were normalised
+    let mut legacy = test_a_record("legacy.example.com.", "192.0.2.5", 300);
+    legacy.zoneid = Some(1);
+    legacy.save_with_txn(&mut txn).await?;
+    txn.commit().await?;
+
+    for name in [
+        "www.example.com",
+        "mail.example.com",
+        "example.com",
+        "ftp.example.com",
+        "legacy.example.com",
+    ] {
+        let res = get_records(
+            &pool,
+            name.to_string(),
+            RecordType::A,
+            RecordClass::Internet,
+            false,
+        )
+        .await?;
+        assert_eq!(res.len(), 1, "{name} should have one record");
+    }
+
+    // finding the zone doesn't care how it's written
+    let mut txn = pool.begin().await?;
+    assert!(
+        get_zone_with_txn(&mut txn, None, Some("EXAMPLE.com.".to_string()))
+            .await?
+            .is_some()
+    );
+    assert!(FileZone::get_by_name(&mut txn, "example.com.")
+        .await?
+        .is_some());
+
+    // absolute names that aren't in the zone don't go in
+    let mut other = test_example_com_zone();
+    other.records = vec![test_a_record("www.example.net.", "192.0.2.1", 300)];
+    assert!(matches!(
+        other.normalise_names(),
+        Err(GoatNsError::InvalidValue(_))
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_zone_ownership_transfer() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let mut userids = vec![];
+    for username in ["giver", "taker"] {
+        let user = User {
+            username: username.to_string(),
+            email: format!("{username}@hello.goat"),
+            ..User::default()
+        }
+        .save(&pool)
+        .await?;
+        userids.push(user.id.expect("User should have an ID after saving"));
+    }
+    let (from_userid, to_userid) = (userids[0], userids[1]);
+
+    let zone = test_example_com_zone().save(&pool).await?;
+    let zoneid = zone.id.expect("Zone should have an ID after saving");
+    ZoneOwnership {
+        id: None,
+        userid: from_userid,
+        zoneid,
+    }
+    .save(&pool)
+    .await?;
+
+    let transfer = ZoneOwnershipTransfer {
+        id: None,
+        zoneid,
+        from_userid,
+        to_userid,
+        created: chrono::Utc::now(),
+        status: OwnershipTransferStatus::Pending,
+        zone: Default::default(),
+        from_username: Default::default(),
+        to_username: Default::default(),
+    };
+
+    let mut txn = pool.begin().await?;
+    // you can't give it to yourself
+    assert!(ZoneOwnershipTransfer {
+        to_userid: from_userid,
+        ..transfer.clone()
+    }
+    .save_with_txn(&mut txn)
+    .await
+    .is_err());
+
+    let saved = transfer.save_with_txn(&mut txn).await?;
+    assert_eq!(saved.zone, zone.name);
+    assert_eq!(saved.from_username, "giver");
+    assert_eq!(saved.to_username, "taker");
+    // only one at a time
+    assert!(transfer.save_with_txn(&mut txn).await.is_err());
+
+    let pending = ZoneOwnershipTransfer::get_pending_for_user(&mut txn, to_userid).await?;
+    assert_eq!(pending.len(), 1);
+    assert!(
+        ZoneOwnershipTransfer::get_pending_for_zone(&mut txn, zoneid)
+            .await?
+            .is_some()
+    );
+
+    let accepted = saved
+        .resolve_with_txn(&mut txn, OwnershipTransferStatus::Accepted)
+        .await?;
+    assert_eq!(accepted.status, OwnershipTransferStatus::Accepted);
+    // it's done, so it can't be done again
+    assert!(accepted
+        .resolve_with_txn(&mut txn, OwnershipTransferStatus::Cancelled)
+        .await
+        .is_err());
+
+    assert!(
+        ZoneOwnership::get_ownership_by_userid(&mut txn, &from_userid, &zoneid)
+            .await?
+            .is_none()
+    );
+    assert!(
+        ZoneOwnership::get_ownership_by_userid(&mut txn, &to_userid, &zoneid)
+            .await?
+            .is_some()
+    );
+    assert!(
+        ZoneOwnershipTransfer::get_pending_for_user(&mut txn, to_userid)
+            .await?
+            .is_empty()
+    );
+    txn.commit().await?;
+
+    // a resolved transfer doesn't keep the zone around
+    zone.delete(&pool).await?;
+    assert!(FileZone::get(&pool, zoneid).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_record_expiry() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let expiring = |name: &str, expires_at: Option<chrono::DateTime<chrono::Utc>>| FileZoneRecord {
+        expires_at,
+        ..test_a_record(name, "192.0.2.1", 300)
+    };
+    let mut zone = test_example_com_zone();
+    zone.records = vec![
+        expiring(
+            "old",
+            Some(chrono::Utc::now() - chrono::TimeDelta::minutes(5)),
+        ),
+        expiring(
+            "soon",
+            Some(chrono::Utc::now() + chrono::TimeDelta::hours(1)),
+        ),
+        expiring("forever", None),
+    ];
+    zone.save(&pool).await?;
+    let serial = FileZone::get(&pool, 1).await?.serial;
+
+    // it's not served once it's expired, even before it's cleaned up
+    let served = |name: &'static str| {
+        let pool = pool.clone();
+        async move {
+            get_records(
+                &pool,
+                format!("{name}.example.com"),
+                RecordType::A,
+                RecordClass::Internet,
+                false,
+            )
+            .await
+            .map(|records| records.len())
+        }
+    };
+    assert_eq!(served("old").await?, 0);
+    assert_eq!(served("soon").await?, 1);
+    assert_eq!(served("forever").await?, 1);
+
+    assert_eq!(FileZoneRecord::cleanup(&pool).await?, 1);
+    let zone = FileZone::get(&pool, 1).await?;
+    assert_eq!(zone.records.len(), 2);
+    assert_eq!(zone.serial, next_serial(serial));
+    let soon = zone
+        .records
+        .iter()
+        .find(|record| record.name == "soon")
+        .expect("Couldn't find the record that hasn't expired");
+    assert!(soon.expires_at.is_some());
+
+    // nothing to do this time, so the serial stays put
+    assert_eq!(FileZoneRecord::cleanup(&pool).await?, 0);
+    assert_eq!(FileZone::get(&pool, 1).await?.serial, next_serial(serial));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_lookup_address() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let user = User {
+        username: "looker".to_string(),
+        email: "looker@hello.goat".to_string(),
+        ..User::default()
+    }
+    .save(&pool)
+    .await?;
+    let ptr = |name: &str, target: &str| FileZoneRecord {
+        rrtype: RecordType::PTR.to_string(),
+        ..test_a_record(name, target, 300)
+    };
+    let aaaa = |name: &str, address: &str| FileZoneRecord {
+        rrtype: RecordType::AAAA.to_string(),
+        ..test_a_record(name, address, 300)
+    };
+    let zones = [
+        (
+            "example.com",
+            vec![
+                test_a_record("www", "192.0.2.10", 300),
+                test_a_record("other", "192.0.2.11", 300),
+                aaaa("www", "2001:DB8:0::10"),
+            ],
+        ),
+        (
+            "2.0.192.in-addr.arpa",
+            vec![
+                ptr("10", "www.example.com."),
+                ptr("11", "other.example.com."),
+            ],
+        ),
+        ("example.net", vec![test_a_record("@", "192.0.2.10", 300)]),
+    ];
+    for (index, (name, records)) in zones.into_iter().enumerate() {
+        let zone = FileZone {
+            id: None,
+            name: name.to_string(),
+            records,
+            ..test_example_com_zone()
+        }
+        .save(&pool)
+        .await?;
+        // they don't own example.net
+        if index < 2 {
+            ZoneOwnership {
+                id: None,
+                userid: user.id.expect("User should have an ID after saving"),
+                zoneid: zone.id.expect("Zone should have an ID after saving"),
+            }
+            .save(&pool)
+            .await?;
+        }
+    }
+
+    let mut txn = pool.begin().await?;
+    let ip = "192.0.2.10".parse().expect("Failed to parse IP");
+    let mut names: Vec<(String, String)> = FileZoneRecord::lookup_address(&mut txn, &user, &ip)
+        .await?
+        .into_iter()
+        .map(|record| (record.rrtype, record.name))
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            ("A".to_string(), "www.example.com".to_string()),
+            ("PTR".to_string(), "10.2.0.192.in-addr.arpa".to_string()),
+        ]
+    );
+
+    let admin = User {
+        admin: true,
+        ..(*user).clone()
+    };
+    assert_eq!(
+        FileZoneRecord::lookup_address(&mut txn, &admin, &ip)
+            .await?
+            .len(),
+        3
+    );
+
+    // AAAA records match however they're written
+    let ip = "2001:db8::10".parse().expect("Failed to parse IP");
+    let found = FileZoneRecord::lookup_address(&mut txn, &user, &ip).await?;
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].rrtype, "AAAA");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_zone_orphans() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let user = User {
+        username: "adopter".to_string(),
+        email: "adopter@hello.goat".to_string(),
+        ..User::default()
+    }
+    .save(&pool)
+    .await?;
+    let userid = user.id.expect("User should have an ID after saving");
+
+    let mut zoneids = vec![];
+    for name in ["example.com", "example.net"] {
+        let zone = FileZone {
+            id: None,
+            name: name.to_string(),
+            ..test_example_com_zone()
+        }
+        .save(&pool)
+        .await?;
+        zoneids.push(zone.id.expect("Zone should have an ID after saving"));
+    }
+    ZoneOwnership {
+        id: None,
+        userid,
+        zoneid: zoneids[0],
+    }
+    .save(&pool)
+    .await?;
+
+    let mut txn = pool.begin().await?;
+    let orphans = FileZone::get_orphans(&mut txn).await?;
+    assert_eq!(
+        orphans
+            .iter()
+            .map(|zone| zone.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["example.net"]
+    );
+    assert_eq!(orphans[0].id, Some(zoneids[1]));
+
+    // the owned one's skipped, as is one that doesn't exist
+    let assigned =
+        FileZone::assign_orphans(&mut txn, &[zoneids[0], zoneids[1], 9999], userid).await?;
+    assert_eq!(assigned, vec!["example.net".to_string()]);
+    assert!(FileZone::get_orphans(&mut txn).await?.is_empty());
+    assert!(FileZone::assign_orphans(&mut txn, &zoneids, userid)
+        .await?
+        .is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_user_settings() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let user = User {
+        username: "settler".to_string(),
+        email: "settler@hello.goat".to_string(),
+        ..User::default()
+    }
+    .save(&pool)
+    .await?;
+    let userid = user.id.expect("User should have an ID after saving");
+
+    let mut conn = pool.acquire().await?;
+    assert_eq!(
+        UserSettings::get(&mut conn, userid).await?,
+        UserSettings::default()
+    );
+
+    let settings = UserSettings {
+        timezone: "+10:00".to_string(),
+        rows_per_page: 50,
+        default_ttl: Some(300),
+        notify_transfers: false,
+        notify_changesets: true,
+    };
+    settings.save(&pool, userid).await?;
+    assert_eq!(UserSettings::get(&mut conn, userid).await?, settings);
+
+    // saving again updates rather than failing
+    let settings = UserSettings {
+        default_ttl: None,
+        ..settings
+    };
+    settings.save(&pool, userid).await?;
+    assert_eq!(UserSettings::get(&mut conn, userid).await?, settings);
+
+    let bad_timezone = UserSettings {
+        timezone: "Australia/Brisbane".to_string(),
+        ..UserSettings::default()
+    };
+    assert!(bad_timezone.save(&pool, userid).await.is_err());
+    let bad_rows = UserSettings {
+        rows_per_page: 5000,
+        ..UserSettings::default()
+    };
+    assert!(bad_rows.check().is_err());
+    assert_eq!(UserSettings::get(&mut conn, userid).await?, settings);
+
+    let when = chrono::DateTime::parse_from_rfc3339("2024-01-01T12:00:00Z")
+        .expect("Failed to parse the test time")
+        .with_timezone(&Utc);
+    assert_eq!(settings.localtime(when), "2024-01-01 22:00:00 +10:00");
+    Ok(())
+}
+
+#[test]
+fn test_parse_utc_offset() {
+    for (timezone, seconds) in [
+        ("UTC", 0),
+        ("z", 0),
+        ("+10:00", 36000),
+        ("-0330", -12600),
+        ("+5", 18000),
+        (" +05:45 ", 20700),
+    ] {
+        assert_eq!(
+            parse_utc_offset(timezone).map(|offset| offset.local_minus_utc()),
+            Some(seconds),
+            "{timezone}"
+        );
+    }
+    for timezone in ["", "10:00", "+15:00", "+10:60", "+ab", "GMT"] {
+        assert!(parse_utc_offset(timezone).is_none(), "{timezone}");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_serial_bumps() -> Result<(), GoatNsError> {
+    // it needs a real file, so the writers get their own connections and snapshots like they would in prod
+    let path = std::env::temp_dir().join(format!("goatns-serials-{}.sqlite", std::process::id()));
+    let options = SqliteConnectOptions::new()
+        .filename(&path)
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(WRITE_TXN_ATTEMPTS)
+        .connect_with(options)
+        .await?;
+    start_db(&pool).await?;
+    test_example_com_zone().save(&pool).await?;
+    let serial = FileZone::get(&pool, 1).await?.serial;
+
+    // everyone reads before they write, so most of them trip over someone else's commit and have to start again.
+    // Each writer can only lose out to each of the others once, so they all fit in the attempts they get.
+    let writers: Vec<_> = (0..WRITE_TXN_ATTEMPTS)
+        .map(|i| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                with_write_txn(&pool, |txn| {
+                    Box::pin(async move {
+                        FileZone::get_serial_with_txn(1, txn).await?;
+                        tokio::task::yield_now().await;
+                        FileZoneRecord {
+                            zoneid: Some(1),
+                            name: format!("writer{i}"),
+                            rrtype: RecordType::A.to_string(),
+                            class: RecordClass::Internet,
+                            rdata: format!("192.0.2.{i}"),
+                            id: None,
+                            ttl: 60,
+                            comment: None,
+                            tags: Default::default(),
+                            expires_at: None,
+                        }
+                        .save_with_txn(txn)
+                        .await?;
+                        FileZone::bump_serial_with_txn(1, txn).await?;
+                        Ok(Ok::<(), ()>(()))
+                    })
+                })
+                .await
+            })
+        })
+        .collect();
+    for writer in writers {
+        assert_eq!(writer.await.expect("Writer panicked")?, Ok(()));
+    }
+
+    let zone = FileZone::get(&pool, 1).await?;
+    assert_eq!(zone.records.len(), WRITE_TXN_ATTEMPTS as usize);
+    let expected = (0..WRITE_TXN_ATTEMPTS).fold(serial, |serial, _| next_serial(serial));
+    assert_eq!(zone.serial, expected);
+
+    // and it carries on from today's YYYYMMDD00 if it's behind, or wraps around
+    let mut txn = pool.begin().await?;
+    for (current, expected) in [(1, next_serial(0)), (u32::MAX, next_serial(u32::MAX))] {
+        sqlx::query("UPDATE zones SET serial = ? WHERE id = 1")
+            .bind(current)
+            .execute(&mut *txn)
+            .await?;
+        FileZone::bump_serial_with_txn(1, &mut txn).await?;
+        assert_eq!(FileZone::get_serial_with_txn(1, &mut txn).await?, expected);
+    }
+    txn.rollback().await?;
+
+    pool.close().await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+    Ok(())
+}
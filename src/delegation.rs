@@ -0,0 +1,380 @@
+//! Checking our zones are delegated to us properly, by asking the parent zone's servers
+//!
+//! For each zone we find the parent zone's name servers with the upstream resolver (the same one ALIAS flattening
+//! uses, see [crate::alias]), then ask each of them directly for the zone's NS and DS records. Problems show up on
+//! the zone's page and are sent to the webhook when they change.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+use trust_dns_resolver::proto::op::{Message, MessageType, OpCode, Query};
+use trust_dns_resolver::proto::rr::{Name, RData, RecordType};
+
+use crate::config::ConfigFile;
+use crate::datastore::ZoneStore;
+use crate::error::GoatNsError;
+use crate::name_policy::normalise;
+use crate::webhook::WebhookEvent;
+use crate::zones::FileZone;
+use crate::UDP_BUFFER_SIZE;
+
+/// How long a parent server gets to answer
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+static REPORTS: RwLock<BTreeMap<String, DelegationReport>> = RwLock::new(BTreeMap::new());
+
+/// Something wrong with how a zone's delegated
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum DelegationProblem {
+    /// Couldn't find the name servers for any zone above this one
+    NoParent,
+    /// A parent server didn't answer, or answered with an error
+    ParentUnreachable { server: String, error: String },
+    /// A parent server doesn't delegate the zone at all
+    NotDelegated { server: String },
+    /// A parent server delegates the zone to a different set of name servers than the zone's NS records
+    WrongNameServers {
+        server: String,
+        expected: Vec<String>,
+        found: Vec<String>,
+    },
+    /// A name server inside the zone doesn't have glue addresses at the parent, so it can't be found
+    MissingGlue { server: String, nameserver: String },
+    /// The parent has DS records, but we don't sign zones, so validating resolvers will fail lookups
+    UnexpectedDs { server: String },
+}
+
+impl Display for DelegationProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DelegationProblem::NoParent => {
+                write!(f, "Couldn't find the parent zone's name servers")
+            }
+            DelegationProblem::ParentUnreachable { server, error } => {
+                write!(f, "Parent server {server} failed: {error}")
+            }
+            DelegationProblem::NotDelegated { server } => {
+                write!(f, "Parent server {server} doesn't delegate the zone")
+            }
+            DelegationProblem::WrongNameServers {
+                server,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Parent server {server} delegates to {} but the zone has {}",
+                found.join(", "),
+                expected.join(", ")
+            ),
+            DelegationProblem::MissingGlue { server, nameserver } => {
+                write!(f, "Parent server {server} has no glue for {nameserver}")
+            }
+            DelegationProblem::UnexpectedDs { server } => write!(
+                f,
+                "Parent server {server} has DS records, but this server doesn't sign zones"
+            ),
+        }
+    }
+}
+
+/// How a zone's delegation looked the last time it was checked
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DelegationReport {
+    pub zone: String,
+    /// The zone above it, if we found it
+    pub parent: Option<String>,
+    pub checked: DateTime<Utc>,
+    pub problems: Vec<DelegationProblem>,
+}
+
+/// The zone's name servers, from the NS records at its apex
+pub fn expected_nameservers(zone: &FileZone) -> Vec<String> {
+    let zone_name = normalise(&zone.name);
+    let mut nameservers: Vec<String> = zone
+        .records
+        .iter()
+        .filter(|record| record.rrtype.eq_ignore_ascii_case("NS"))
+        .filter(|record| {
+            let name = normalise(&record.name);
+            name.is_empty() || name == "@" || name == zone_name
+        })
+        .map(|record| normalise(&record.rdata))
+        .collect();
+    nameservers.sort();
+    nameservers.dedup();
+    nameservers
+}
+
+/// Checks a parent server's answer to an NS query for the zone
+pub fn check_referral(
+    zone: &str,
+    expected: &[String],
+    server: &str,
+    reply: &Message,
+) -> Vec<DelegationProblem> {
+    let zone = normalise(zone);
+    // a referral has them in the authority section, but if the parent's also authoritative for the zone they're answers
+    let mut found: Vec<String> = reply
+        .answers()
+        .iter()
+        .chain(reply.name_servers())
+        .filter(|record| normalise(&record.name().to_ascii()) == zone)
+        .filter_map(|record| match record.data() {
+            Some(RData::NS(ns)) => Some(normalise(&ns.0.to_ascii())),
+            _ => None,
+        })
+        .collect();
+    found.sort();
+    found.dedup();
+
+    if found.is_empty() {
+        return vec![DelegationProblem::NotDelegated {
+            server: server.to_string(),
+        }];
+    }
+
+    let mut problems = vec![];
+    if found != expected {
+        problems.push(DelegationProblem::WrongNameServers {
+            server: server.to_string(),
+            expected: expected.to_vec(),
+            found: found.clone(),
+        });
+    }
+
+    // the additional section might've been cut off, so glue can't be judged
+    if !reply.truncated() {
+        for nameserver in found.iter().filter(|ns| in_zone(ns, &zone)) {
+            let has_glue = reply.additionals().iter().any(|record| {
+                normalise(&record.name().to_ascii()) == *nameserver
+                    && matches!(record.data(), Some(RData::A(_)) | Some(RData::AAAA(_)))
+            });
+            if !has_glue {
+                problems.push(DelegationProblem::MissingGlue {
+                    server: server.to_string(),
+                    nameserver: nameserver.clone(),
+                });
+            }
+        }
+    }
+    problems
+}
+
+/// Checks a parent server's answer to a DS query for the zone
+pub fn check_ds(server: &str, reply: &Message) -> Vec<DelegationProblem> {
+    match reply
+        .answers()
+        .iter()
+        .any(|record| record.record_type() == RecordType::DS)
+    {
+        true => vec![DelegationProblem::UnexpectedDs {
+            server: server.to_string(),
+        }],
+        false => vec![],
+    }
+}
+
+/// Is the name the zone or under it?
+fn in_zone(name: &str, zone: &str) -> bool {
+    name == zone
+        || name
+            .strip_suffix(zone)
+            .is_some_and(|rest| rest.ends_with('.'))
+}
+
+/// The zones above this one, closest first, ending with the root
+fn parents(zone: &str) -> Vec<String> {
+    let zone = normalise(zone);
+    let mut parents: Vec<String> = vec![];
+    let mut rest = zone.as_str();
+    while let Some((_, parent)) = rest.split_once('.') {
+        parents.push(parent.to_string());
+        rest = parent;
+    }
+    parents.push(String::new());
+    parents
+}
+
+/// Find the closest zone above this one that has name servers, and their addresses
+async fn parent_servers(zone: &str) -> Option<(String, Vec<(String, IpAddr)>)> {
+    let resolver = crate::alias::resolver();
+    for parent in parents(zone) {
+        let lookup = match resolver.ns_lookup(format!("{parent}.")).await {
+            Ok(lookup) => lookup,
+            Err(err) => {
+                log::trace!("No name servers for {parent:?}: {err:?}");
+                continue;
+            }
+        };
+        let mut servers = vec![];
+        for ns in lookup.iter() {
+            let ns_name = ns.0.to_ascii();
+            match resolver.lookup_ip(ns_name.as_str()).await {
+                Ok(addresses) => {
+                    if let Some(address) = addresses.iter().next() {
+                        servers.push((normalise(&ns_name), address));
+                    }
+                }
+                Err(err) => log::debug!("Couldn't find the address of {ns_name}: {err:?}"),
+            }
+        }
+        if !servers.is_empty() {
+            return Some((parent, servers));
+        }
+    }
+    None
+}
+
+/// A non-recursive query for `name` with a random ID
+pub(crate) fn query_message(name: &str, rtype: RecordType) -> Result<Message, GoatNsError> {
+    let name = Name::from_ascii(format!("{}.", normalise(name)))
+        .map_err(|err| GoatNsError::InvalidValue(format!("Invalid name {name:?}: {err:?}")))?;
+    let mut message = Message::new();
+    message
+        .set_id(rand::random())
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(false)
+        .add_query(Query::query(name, rtype));
+    Ok(message)
+}
+
+/// Ask a server directly, without recursion. FORWARD records are looked up this way too, see [crate::forward]
+pub(crate) async fn query(
+    target: SocketAddr,
+    name: &str,
+    rtype: RecordType,
+    wait: Duration,
+) -> Result<Message, GoatNsError> {
+    let message = query_message(name, rtype)?;
+    let id = message.id();
+    let packet = message
+        .to_vec()
+        .map_err(|err| GoatNsError::Generic(format!("Failed to build query: {err:?}")))?;
+
+    let bind_addr: SocketAddr = match target {
+        SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(target).await?;
+    socket.send(&packet).await?;
+
+    let mut buf = [0u8; UDP_BUFFER_SIZE];
+    let len = timeout(wait, socket.recv(&mut buf))
+        .await
+        .map_err(|_| GoatNsError::Generic(format!("Query to {target} timed out")))??;
+    let reply = Message::from_vec(&buf[..len])
+        .map_err(|err| GoatNsError::Generic(format!("Bad reply from {target}: {err:?}")))?;
+    if reply.id() != id || reply.message_type() != MessageType::Response {
+        return Err(GoatNsError::Generic(format!(
+            "Unexpected reply from {target}"
+        )));
+    }
+    Ok(reply)
+}
+
+/// Ask one of the parent's servers for the zone's NS and DS records
+async fn check_server(
+    zone: &str,
+    expected: &[String],
+    server: &str,
+    address: IpAddr,
+) -> Result<Vec<DelegationProblem>, GoatNsError> {
+    let target = SocketAddr::new(address, 53);
+    let mut problems = check_referral(
+        zone,
+        expected,
+        server,
+        &query(target, zone, RecordType::NS, QUERY_TIMEOUT).await?,
+    );
+    problems.extend(check_ds(
+        server,
+        &query(target, zone, RecordType::DS, QUERY_TIMEOUT).await?,
+    ));
+    Ok(problems)
+}
+
+/// Ask each of the parent's servers about the zone
+pub async fn check_zone(zone: &str, expected: &[String]) -> DelegationReport {
+    let (parent, servers) = match parent_servers(zone).await {
+        Some((parent, servers)) => (Some(parent), servers),
+        None => (None, vec![]),
+    };
+    let mut problems = vec![];
+    if parent.is_none() {
+        problems.push(DelegationProblem::NoParent);
+    }
+    for (server, address) in servers {
+        match check_server(zone, expected, &server, address).await {
+            Ok(found) => problems.extend(found),
+            Err(err) => problems.push(DelegationProblem::ParentUnreachable {
+                server,
+                error: format!("{err:?}"),
+            }),
+        }
+    }
+    DelegationReport {
+        zone: normalise(zone),
+        parent,
+        checked: Utc::now(),
+        problems,
+    }
+}
+
+/// The last check of the zone, if it's been checked
+pub fn report(zone: &str) -> Option<DelegationReport> {
+    REPORTS.read().ok()?.get(&normalise(zone)).cloned()
+}
+
+/// Keep the report, handing back the problems if they're different to last time
+fn store_report(report: DelegationReport) -> Option<Vec<DelegationProblem>> {
+    let mut reports = REPORTS.write().ok()?;
+    let changed = reports
+        .get(&report.zone)
+        .map(|previous| previous.problems != report.problems)
+        // no news is good news the first time round
+        .unwrap_or(!report.problems.is_empty());
+    let problems = report.problems.clone();
+    reports.insert(report.zone.clone(), report);
+    changed.then_some(problems)
+}
+
+/// Checks every zone every `delegation_check_interval` seconds, forever
+pub async fn check_loop<S: ZoneStore>(store: S, config: ConfigFile) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.delegation_check_interval));
+    loop {
+        interval.tick().await;
+        let zones = match store.export_all().await {
+            Ok(backup) => backup.zones,
+            Err(err) => {
+                log::error!("Failed to get the zones to check their delegations: {err:?}");
+                continue;
+            }
+        };
+        for zone in zones {
+            let report = check_zone(&zone.name, &expected_nameservers(&zone)).await;
+            for problem in report.problems.iter() {
+                log::warn!("Delegation problem with {}: {problem}", report.zone);
+            }
+            let name = report.zone.clone();
+            if let Some(problems) = store_report(report) {
+                crate::webhook::send(
+                    config.webhook_url.clone(),
+                    WebhookEvent::Delegation {
+                        zone: name,
+                        problems,
+                    },
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,614 @@
+//! Self-checks against a running server, for `goatns --doctor`
+//!
+//! It asks the DNS listeners (UDP and TCP) and DoH for a name it expects to exist, connects to the web server with
+//! certificate checking turned on, looks at the certificate file's expiry, has SQLite check the database over, and
+//! compares the zone file (if there is one) with what's in the database. Nothing's changed by any of it, the database
+//! is opened read-only.
+//!
+//! Each check comes back as OK, a warning, a failure, or skipped when it can't be done with this config. Only
+//! failures count against the overall result.
+
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use trust_dns_resolver::proto::op::{Message, MessageType, ResponseCode};
+use trust_dns_resolver::proto::rr::RecordType as UpstreamType;
+
+use crate::config::{ConfigFile, ImportTtlPolicy};
+use crate::db::get_zone_with_txn;
+use crate::enums::RecordType;
+use crate::error::GoatNsError;
+use crate::health::self_check_target;
+use crate::zones::{load_zones, FileZone, ZoneDiff};
+
+/// How long to wait for each query or connection
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Certificates with less than this many days left get a warning, unless ACME's renewing it
+pub const CERT_WARN_DAYS: i64 = 14;
+
+/// How a check went
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+    /// It couldn't be checked with this config
+    Skip,
+}
+
+impl Display for CheckStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            CheckStatus::Ok => "OK",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+            CheckStatus::Skip => "SKIP",
+        })
+    }
+}
+
+/// One of the things `--doctor` looks at
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    /// What was found, for people to read
+    pub detail: String,
+}
+
+impl Check {
+    pub fn new(name: &str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Everything `--doctor` found
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+}
+
+impl DoctorReport {
+    /// Nothing failed, warnings and skipped checks are fine
+    pub fn passed(&self) -> bool {
+        !self
+            .checks
+            .iter()
+            .any(|check| check.status == CheckStatus::Fail)
+    }
+
+    fn count(&self, status: CheckStatus) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.status == status)
+            .count()
+    }
+}
+
+impl Display for DoctorReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "[{:>4}] {}: {}", check.status, check.name, check.detail)?;
+        }
+        write!(
+            f,
+            "{} ok, {} warnings, {} failed, {} skipped",
+            self.count(CheckStatus::Ok),
+            self.count(CheckStatus::Warn),
+            self.count(CheckStatus::Fail),
+            self.count(CheckStatus::Skip)
+        )
+    }
+}
+
+/// What to look up
+#[derive(Clone, Debug)]
+pub struct DoctorOptions {
+    pub qname: String,
+    pub rrtype: RecordType,
+    pub wait: Duration,
+}
+
+impl DoctorOptions {
+    /// Looks up the healthcheck name's A record
+    pub fn new(config: &ConfigFile) -> Self {
+        Self {
+            qname: config.healthcheck_name(),
+            rrtype: RecordType::A,
+            wait: CHECK_TIMEOUT,
+        }
+    }
+
+    /// Set the name to look up from something like `www.example.com:AAAA`, the type defaults to A
+    pub fn with_question(mut self, input: &str) -> Result<Self, GoatNsError> {
+        let (qname, rrtype) = match input.split_once(':') {
+            Some((qname, rrtype)) => (qname, rrtype),
+            None => (input, "A"),
+        };
+        let qname = qname.trim().trim_end_matches('.');
+        if qname.is_empty() {
+            return Err(GoatNsError::InvalidValue(format!("No name in {input:?}")));
+        }
+        self.rrtype = match RecordType::from(rrtype.trim().to_uppercase().as_str()) {
+            RecordType::InvalidType => {
+                return Err(GoatNsError::InvalidValue(format!(
+                    "Unknown record type {rrtype:?} in {input:?}"
+                )))
+            }
+            rrtype => rrtype,
+        };
+        self.qname = qname.to_string();
+        Ok(self)
+    }
+
+    fn question(&self) -> String {
+        format!("{} {}", self.qname, self.rrtype)
+    }
+}
+
+/// Run all the checks
+pub async fn run(config: &ConfigFile, options: &DoctorOptions) -> DoctorReport {
+    let mut report = DoctorReport::default();
+    let rrtype = UpstreamType::from(options.rrtype as u16);
+
+    match config.dns_listener_address() {
+        Ok(listener) => {
+            let target = self_check_target(listener);
+            let started = Instant::now();
+            let reply =
+                crate::delegation::query(target, &options.qname, rrtype, options.wait).await;
+            report.checks.push(answer_check(
+                "dns_udp",
+                &options.question(),
+                reply,
+                started.elapsed(),
+            ));
+            let started = Instant::now();
+            let reply = query_tcp(target, &options.qname, rrtype, options.wait).await;
+            report.checks.push(answer_check(
+                "dns_tcp",
+                &options.question(),
+                reply,
+                started.elapsed(),
+            ));
+        }
+        Err(_) => {
+            for name in ["dns_udp", "dns_tcp"] {
+                report.checks.push(Check::new(
+                    name,
+                    CheckStatus::Fail,
+                    "Couldn't work out the DNS server's address",
+                ));
+            }
+        }
+    }
+
+    report.checks.push(doh_check(config, options, rrtype).await);
+    report
+        .checks
+        .push(tls_trusted_check(config, options.wait).await);
+    let warn_days = match config.acme.enabled {
+        true => i64::try_from(config.acme.renew_days).unwrap_or(i64::MAX),
+        false => CERT_WARN_DAYS,
+    };
+    report
+        .checks
+        .push(match std::fs::read(&config.api_tls_cert) {
+            Ok(pem) => certificate_check(&pem, chrono::Utc::now(), warn_days),
+            Err(err) => Check::new(
+                "tls_certificate",
+                CheckStatus::Fail,
+                format!("Couldn't read {:?}: {err}", config.api_tls_cert),
+            ),
+        });
+
+    match crate::db::get_conn_read_only(config).await {
+        Ok(pool) => {
+            report.checks.push(integrity_check(&pool).await);
+            report.checks.push(match &config.zone_file {
+                None => Check::new("zone_file_drift", CheckStatus::Skip, "There's no zone_file"),
+                Some(zone_file) => match load_zones(zone_file) {
+                    Ok(zones) => zone_file_drift(&pool, zones, &config.import_ttl)
+                        .await
+                        .unwrap_or_else(|err| {
+                            Check::new(
+                                "zone_file_drift",
+                                CheckStatus::Fail,
+                                format!("Failed to compare {zone_file} with the database: {err:?}"),
+                            )
+                        }),
+                    Err(err) => Check::new(
+                        "zone_file_drift",
+                        CheckStatus::Fail,
+                        format!("Failed to load {zone_file}: {err:?}"),
+                    ),
+                },
+            });
+            pool.close().await;
+        }
+        Err(err) => {
+            report.checks.push(Check::new(
+                "database_integrity",
+                CheckStatus::Fail,
+                format!("Couldn't open {}: {err:?}", config.sqlite_path),
+            ));
+            report.checks.push(Check::new(
+                "zone_file_drift",
+                CheckStatus::Skip,
+                "The database couldn't be opened",
+            ));
+        }
+    }
+    report
+}
+
+/// Did the server answer the question? Not having the record is only a warning, since it's still answering.
+pub fn answer_check(
+    name: &str,
+    question: &str,
+    reply: Result<Message, GoatNsError>,
+    elapsed: Duration,
+) -> Check {
+    let reply = match reply {
+        Ok(reply) => reply,
+        Err(err) => {
+            return Check::new(
+                name,
+                CheckStatus::Fail,
+                format!("No answer for {question}: {err:?}"),
+            )
+        }
+    };
+    let ms = elapsed.as_millis();
+    match (reply.response_code(), reply.answers().len()) {
+        (ResponseCode::NoError, 0) | (ResponseCode::NXDomain, _) => Check::new(
+            name,
+            CheckStatus::Warn,
+            format!(
+                "Answered {} for {question} in {ms}ms, but there's no record",
+                reply.response_code()
+            ),
+        ),
+        (ResponseCode::NoError, answers) => Check::new(
+            name,
+            CheckStatus::Ok,
+            format!("Answered {question} with {answers} records in {ms}ms"),
+        ),
+        (rcode, _) => Check::new(
+            name,
+            CheckStatus::Fail,
+            format!("Answered {rcode} for {question} in {ms}ms"),
+        ),
+    }
+}
+
+/// The same query [crate::delegation::query] sends, over TCP
+async fn query_tcp(
+    target: SocketAddr,
+    name: &str,
+    rtype: UpstreamType,
+    wait: Duration,
+) -> Result<Message, GoatNsError> {
+    let message = crate::delegation::query_message(name, rtype)?;
+    let packet = message
+        .to_vec()
+        .map_err(|err| GoatNsError::Generic(format!("Failed to build query: {err:?}")))?;
+    let len = u16::try_from(packet.len())
+        .map_err(|_| GoatNsError::Generic("The query's too big for TCP".to_string()))?;
+    let buf = timeout(wait, async {
+        let mut stream = TcpStream::connect(target).await?;
+        stream.write_all(&len.to_be_bytes()).await?;
+        stream.write_all(&packet).await?;
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).await?;
+        let mut buf = vec![0u8; u16::from_be_bytes(len).into()];
+        stream.read_exact(&mut buf).await?;
+        Ok::<_, GoatNsError>(buf)
+    })
+    .await
+    .map_err(|_| GoatNsError::Generic(format!("Query to {target} timed out")))??;
+    reply_to(&message, &buf, &target.to_string())
+}
+
+/// Parse a reply and make sure it's for our query
+fn reply_to(query: &Message, buf: &[u8], from: &str) -> Result<Message, GoatNsError> {
+    let reply = Message::from_vec(buf)
+        .map_err(|err| GoatNsError::Generic(format!("Bad reply from {from}: {err:?}")))?;
+    if reply.id() != query.id() || reply.message_type() != MessageType::Response {
+        return Err(GoatNsError::Generic(format!(
+            "Unexpected reply from {from}"
+        )));
+    }
+    Ok(reply)
+}
+
+/// A client that connects to `hostname` at `target`, so the certificate's checked against the right name
+fn https_client(
+    hostname: &str,
+    target: SocketAddr,
+    wait: Duration,
+    check_certificate: bool,
+) -> Result<reqwest::Client, GoatNsError> {
+    reqwest::Client::builder()
+        .resolve(hostname, target)
+        .timeout(wait)
+        .danger_accept_invalid_certs(!check_certificate)
+        .build()
+        .map_err(|err| GoatNsError::Generic(format!("Failed to build HTTPS client: {err:?}")))
+}
+
+/// Ask the first DoH path with a POST. The certificate isn't checked here, that's [tls_trusted_check]'s job.
+async fn doh_check(config: &ConfigFile, options: &DoctorOptions, rrtype: UpstreamType) -> Check {
+    let name = "doh";
+    if !config.doh.enabled {
+        return Check::new(name, CheckStatus::Skip, "DoH is turned off");
+    }
+    let token = config.doh.auth.bearer_tokens.first();
+    if config.doh.auth.enabled() && token.is_none() {
+        return Check::new(
+            name,
+            CheckStatus::Skip,
+            "DoH needs an API token or client certificate, and there's no bearer token to use",
+        );
+    }
+    let Some(path) = config.doh.paths.first() else {
+        return Check::new(name, CheckStatus::Skip, "There's no doh.paths to ask");
+    };
+    let listener = match config.doh_listener_address() {
+        Ok(Some(listener)) => Ok(listener),
+        Ok(None) => config.api_listener_address(),
+        Err(err) => Err(err),
+    };
+    let listener = match listener {
+        Ok(listener) => self_check_target(listener),
+        Err(err) => {
+            return Check::new(
+                name,
+                CheckStatus::Fail,
+                format!("Couldn't work out DoH's address: {err:?}"),
+            )
+        }
+    };
+    let url = format!("https://{}:{}{path}", config.hostname, listener.port());
+
+    let started = Instant::now();
+    let reply = async {
+        let message = crate::delegation::query_message(&options.qname, rrtype)?;
+        let packet = message
+            .to_vec()
+            .map_err(|err| GoatNsError::Generic(format!("Failed to build query: {err:?}")))?;
+        let mut request = https_client(&config.hostname, listener, options.wait, false)?
+            .post(&url)
+            .header("Content-Type", "application/dns-message")
+            .header("Accept", "application/dns-message")
+            .body(packet);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|err| GoatNsError::Generic(format!("Failed to ask {url}: {err:?}")))?;
+        if !response.status().is_success() {
+            return Err(GoatNsError::Generic(format!(
+                "{url} answered HTTP {}",
+                response.status()
+            )));
+        }
+        let body = response
+            .bytes()
+            .await
+            .map_err(|err| GoatNsError::Generic(format!("Failed to read from {url}: {err:?}")))?;
+        reply_to(&message, &body, &url)
+    }
+    .await;
+    answer_check(name, &options.question(), reply, started.elapsed())
+}
+
+/// Connect to the web server the way a browser would, so a self-signed certificate or a missing intermediate shows up
+async fn tls_trusted_check(config: &ConfigFile, wait: Duration) -> Check {
+    let name = "tls_trusted";
+    let target = match config.api_listener_address() {
+        Ok(listener) => self_check_target(listener),
+        Err(err) => {
+            return Check::new(
+                name,
+                CheckStatus::Fail,
+                format!("Couldn't work out the web server's address: {err:?}"),
+            )
+        }
+    };
+    let url = format!("https://{}:{}/healthz", config.hostname, target.port());
+    let client = match https_client(&config.hostname, target, wait, true) {
+        Ok(client) => client,
+        Err(err) => return Check::new(name, CheckStatus::Fail, format!("{err:?}")),
+    };
+    match client.get(&url).send().await {
+        Ok(_) => Check::new(
+            name,
+            CheckStatus::Ok,
+            format!("The certificate on {url} is trusted"),
+        ),
+        Err(err) => Check::new(
+            name,
+            CheckStatus::Fail,
+            format!("Couldn't connect to {url} with certificate checking on: {err:?}"),
+        ),
+    }
+}
+
+/// Is the certificate file in date, with the chain in order? Expiring in `warn_days` or less is a warning.
+pub fn certificate_check(pem: &[u8], now: chrono::DateTime<chrono::Utc>, warn_days: i64) -> Check {
+    let name = "tls_certificate";
+    let pems = match x509_parser::pem::Pem::iter_from_buffer(pem).collect::<Result<Vec<_>, _>>() {
+        Ok(pems) => pems,
+        Err(err) => {
+            return Check::new(
+                name,
+                CheckStatus::Fail,
+                format!("Couldn't read the PEM file: {err:?}"),
+            )
+        }
+    };
+    let mut certs = vec![];
+    for pem in &pems {
+        match pem.parse_x509() {
+            Ok(cert) => certs.push(cert),
+            Err(err) => {
+                return Check::new(
+                    name,
+                    CheckStatus::Fail,
+                    format!("Couldn't parse a certificate: {err:?}"),
+                )
+            }
+        }
+    }
+    let Some(leaf) = certs.first() else {
+        return Check::new(
+            name,
+            CheckStatus::Fail,
+            "There's no certificates in the file",
+        );
+    };
+
+    let validity = leaf.validity();
+    if validity.not_before.timestamp() > now.timestamp() {
+        return Check::new(
+            name,
+            CheckStatus::Fail,
+            format!(
+                "{} isn't valid until {}",
+                leaf.subject(),
+                validity.not_before
+            ),
+        );
+    }
+    let days_left = (validity.not_after.timestamp() - now.timestamp()).div_euclid(86400);
+    if days_left < 0 {
+        return Check::new(
+            name,
+            CheckStatus::Fail,
+            format!("{} expired on {}", leaf.subject(), validity.not_after),
+        );
+    }
+    // each certificate should be followed by the one that issued it
+    if let Some(index) = certs
+        .windows(2)
+        .position(|pair| pair[0].issuer().as_raw() != pair[1].subject().as_raw())
+    {
+        return Check::new(
+            name,
+            CheckStatus::Warn,
+            format!(
+                "Certificate {} in the chain wasn't issued by the next one ({}), they might be out of order",
+                index + 1,
+                certs[index + 1].subject()
+            ),
+        );
+    }
+    Check::new(
+        name,
+        match days_left <= warn_days {
+            true => CheckStatus::Warn,
+            false => CheckStatus::Ok,
+        },
+        format!(
+            "{} expires in {days_left} days ({}), {} certificates in the chain",
+            leaf.subject(),
+            validity.not_after,
+            certs.len()
+        ),
+    )
+}
+
+/// Have SQLite check the database for corruption
+pub async fn integrity_check(pool: &SqlitePool) -> Check {
+    let name = "database_integrity";
+    match sqlx::query_scalar::<_, String>("PRAGMA integrity_check")
+        .fetch_all(pool)
+        .await
+    {
+        Ok(rows) if rows == ["ok"] => Check::new(name, CheckStatus::Ok, "SQLite says it's ok"),
+        Ok(rows) => Check::new(
+            name,
+            CheckStatus::Fail,
+            rows.into_iter().take(10).collect::<Vec<_>>().join("; "),
+        ),
+        Err(err) => Check::new(
+            name,
+            CheckStatus::Fail,
+            format!("Failed to check the database: {err:?}"),
+        ),
+    }
+}
+
+/// Compare the zone file with the database, the zones are treated the way importing them would
+pub async fn zone_file_drift(
+    pool: &SqlitePool,
+    zones: Vec<FileZone>,
+    ttl_policy: &ImportTtlPolicy,
+) -> Result<Check, GoatNsError> {
+    let name = "zone_file_drift";
+    let total = zones.len();
+    let mut conn = pool.acquire().await?;
+    let mut drifted = vec![];
+    for mut zone in zones {
+        zone.normalise_names()?;
+        zone.inherit_default_ttl_with_txn(&mut conn).await?;
+        zone.apply_ttl_policy(ttl_policy);
+        match get_zone_with_txn(&mut conn, None, Some(zone.name.clone())).await? {
+            None => drifted.push(format!("{} isn't in the database", zone.name)),
+            Some(existing) => {
+                let diff = existing.diff(&zone);
+                if !diff.is_empty() {
+                    drifted.push(format!("{}: {}", zone.name, describe_diff(&diff)));
+                }
+            }
+        }
+    }
+    Ok(match drifted.is_empty() {
+        true => Check::new(
+            name,
+            CheckStatus::Ok,
+            format!("All {total} zones match the database"),
+        ),
+        false => Check::new(
+            name,
+            CheckStatus::Warn,
+            format!(
+                "{} of {total} zones are different in the database - {}",
+                drifted.len(),
+                drifted.join(", ")
+            ),
+        ),
+    })
+}
+
+fn describe_diff(diff: &ZoneDiff) -> String {
+    let mut changes = vec![];
+    if !diff.soa.is_empty() {
+        let fields: Vec<&str> = diff.soa.iter().map(|soa| soa.field.as_str()).collect();
+        changes.push(format!("{} differ", fields.join("/")));
+    }
+    for (count, what) in [
+        (diff.added.len(), "only in the file"),
+        (diff.removed.len(), "only in the database"),
+        (diff.updated.len(), "changed"),
+    ] {
+        if count > 0 {
+            changes.push(format!("{count} records {what}"));
+        }
+    }
+    changes.join(", ")
+}
@@ -0,0 +1,146 @@
+//! Just enough EDNS(0) ([RFC6891](https://www.rfc-editor.org/rfc/rfc6891)) to find the OPT record in a query and
+//! send options back
+
+use bytes::{BufMut, BytesMut};
+
+use crate::{HEADER_BYTES, UDP_BUFFER_SIZE};
+
+/// The OPT pseudo-record's RRTYPE
+pub const OPT_RRTYPE: u16 = 41;
+/// edns-tcp-keepalive, ref [RFC7828](https://www.rfc-editor.org/rfc/rfc7828)
+pub const EDNS_TCP_KEEPALIVE: u16 = 11;
+/// The most clients without EDNS can take over UDP, ref [RFC1035](https://www.rfc-editor.org/rfc/rfc1035) 4.2.1
+pub const MIN_UDP_PAYLOAD_SIZE: usize = 512;
+
+/// An OPT record, the bits of it we care about anyway
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Edns {
+    /// The biggest UDP payload the sender can handle, it's in the CLASS field
+    pub udp_payload_size: u16,
+    /// EDNS options, code and value
+    pub options: Vec<(u16, Vec<u8>)>,
+}
+
+impl Edns {
+    /// Finds the OPT record in a query, if there is one. Only the additional section's checked since that's where it
+    /// lives, queries with answer or authority records don't get looked at.
+    pub fn from_query(buf: &[u8]) -> Result<Option<Self>, String> {
+        if buf.len() < HEADER_BYTES {
+            return Err(format!(
+                "Query's too short for a header: {} bytes",
+                buf.len()
+            ));
+        }
+        let count_at = |offset: usize| u16::from_be_bytes([buf[offset], buf[offset + 1]]);
+        let (qdcount, ancount, nscount, arcount) =
+            (count_at(4), count_at(6), count_at(8), count_at(10));
+        if ancount != 0 || nscount != 0 {
+            return Ok(None);
+        }
+
+        let mut offset = HEADER_BYTES;
+        for _ in 0..qdcount {
+            offset = skip_name(buf, offset)? + 4;
+        }
+        for _ in 0..arcount {
+            offset = skip_name(buf, offset)?;
+            let fixed = buf
+                .get(offset..offset + 10)
+                .ok_or_else(|| format!("Additional record at {offset} is truncated"))?;
+            let rrtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+            let class = u16::from_be_bytes([fixed[2], fixed[3]]);
+            let rdlength = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+            offset += 10;
+            let rdata = buf
+                .get(offset..offset + rdlength)
+                .ok_or_else(|| format!("Additional record rdata at {offset} is truncated"))?;
+            offset += rdlength;
+            if rrtype == OPT_RRTYPE {
+                return Ok(Some(Self {
+                    udp_payload_size: class,
+                    options: parse_options(rdata)?,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The value for an option, if it's set
+    pub fn option(&self, code: u16) -> Option<&[u8]> {
+        self.options
+            .iter()
+            .find(|(option_code, _)| *option_code == code)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Sets an option, replacing it if it's already there
+    pub fn set_option(&mut self, code: u16, value: Vec<u8>) {
+        self.options.retain(|(option_code, _)| *option_code != code);
+        self.options.push((code, value));
+    }
+
+    /// Writes the OPT record, it's always owned by the root
+    pub fn write_to(&self, buf: &mut BytesMut) {
+        let rdata_len: usize = self.options.iter().map(|(_, value)| 4 + value.len()).sum();
+        buf.reserve(11 + rdata_len);
+        buf.put_u8(0);
+        buf.put_u16(OPT_RRTYPE);
+        buf.put_u16(self.udp_payload_size);
+        // extended rcode, version 0 and no flags
+        buf.put_u32(0);
+        #[allow(clippy::cast_possible_truncation)]
+        buf.put_u16(rdata_len as u16);
+        for (code, value) in &self.options {
+            buf.put_u16(*code);
+            #[allow(clippy::cast_possible_truncation)]
+            buf.put_u16(value.len() as u16);
+            buf.put_slice(value);
+        }
+    }
+}
+
+/// The biggest reply to send over UDP for a query - what its OPT record says the client can take, but no more than
+/// [UDP_BUFFER_SIZE], and [MIN_UDP_PAYLOAD_SIZE] if it didn't send one (or it can't be parsed)
+pub fn udp_reply_size(query: &[u8]) -> usize {
+    match Edns::from_query(query) {
+        Ok(Some(edns)) => {
+            usize::from(edns.udp_payload_size).clamp(MIN_UDP_PAYLOAD_SIZE, UDP_BUFFER_SIZE)
+        }
+        _ => MIN_UDP_PAYLOAD_SIZE,
+    }
+}
+
+/// Returns the offset just past the name starting at `offset`, a compression pointer ends it
+fn skip_name(buf: &[u8], mut offset: usize) -> Result<usize, String> {
+    loop {
+        let label_len = *buf
+            .get(offset)
+            .ok_or_else(|| format!("Name at {offset} ran off the end of the query"))?
+            as usize;
+        match label_len {
+            0 => return Ok(offset + 1),
+            len if len & 0b1100_0000 == 0b1100_0000 => return Ok(offset + 2),
+            len if len > 63 => return Err(format!("Invalid label length {len} at {offset}")),
+            len => offset += len + 1,
+        }
+    }
+}
+
+fn parse_options(mut rdata: &[u8]) -> Result<Vec<(u16, Vec<u8>)>, String> {
+    let mut options = vec![];
+    while !rdata.is_empty() {
+        let (code, len) = match rdata {
+            [code_hi, code_lo, len_hi, len_lo, ..] => (
+                u16::from_be_bytes([*code_hi, *code_lo]),
+                u16::from_be_bytes([*len_hi, *len_lo]) as usize,
+            ),
+            _ => return Err("EDNS option header is truncated".to_string()),
+        };
+        let value = rdata
+            .get(4..4 + len)
+            .ok_or_else(|| format!("EDNS option {code} is truncated"))?;
+        options.push((code, value.to_vec()));
+        rdata = &rdata[4 + len..];
+    }
+    Ok(options)
+}
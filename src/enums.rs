@@ -36,6 +36,8 @@ pub enum OpCode {
     // IQuery = 1, an inverse query (IQUERY) - obsolete in https://www.rfc-editor.org/rfc/rfc3425
     /// Server status request (STATUS)
     Status = 2,
+    /// Zone change notification (NOTIFY), ref [RFC1996](https://www.rfc-editor.org/rfc/rfc1996)
+    Notify = 4,
     /// 3-15            reserved for future use
     Reserved = 15,
 }
@@ -44,6 +46,7 @@ impl From<u8> for OpCode {
         match input {
             0 => Self::Query,
             2 => Self::Status,
+            4 => Self::Notify,
             _ => Self::Reserved,
         }
     }
@@ -54,6 +57,7 @@ impl From<OpCode> for i32 {
         match val {
             OpCode::Query => 0b00,
             OpCode::Status => 0b10,
+            OpCode::Notify => 0b100,
             //  Self::Reserved
             _ => 0b11,
         }
@@ -117,6 +121,10 @@ pub enum RecordType {
     ANY = 255,
     /// Certification Authority Restriction - <https://www.rfc-editor.org/rfc/rfc6844.txt>
     CAA = 257,
+    /// ALIAS pseudo-record, flattened into A/AAAA answers at query time. Uses the same private-use code as PowerDNS.
+    ALIAS = 65401,
+    /// FORWARD pseudo-record, the name's looked up on the `forward.upstreams` at query time. Private-use, like ALIAS.
+    FORWARD = 65402,
     InvalidType,
 }
 
@@ -145,6 +153,8 @@ impl From<&u16> for RecordType {
             255 => Self::ANY,
             256 => Self::URI,
             257 => Self::CAA,
+            65401 => Self::ALIAS,
+            65402 => Self::FORWARD,
             _ => Self::InvalidType,
         }
     }
@@ -161,10 +171,12 @@ impl From<&str> for RecordType {
         match input {
             "A" => Self::A,
             "AAAA" => Self::AAAA, // https://www.rfc-editor.org/rfc/rfc3596#section-2.1
+            "ALIAS" => Self::ALIAS,
             "ANY" => Self::ANY,
             "AXFR" => Self::AXFR,
             "CAA" => Self::CAA,
             "CNAME" => Self::CNAME,
+            "FORWARD" => Self::FORWARD,
             "HINFO" => Self::HINFO,
             "LOC" => Self::LOC,
             "MAILB" => Self::MAILB,
@@ -191,10 +203,12 @@ impl From<RecordType> for &'static str {
         match input {
             RecordType::A => "A",
             RecordType::AAAA => "AAAA",
+            RecordType::ALIAS => "ALIAS",
             RecordType::ANY => "ANY",
             RecordType::AXFR => "AXFR",
             RecordType::CAA => "CAA",
             RecordType::CNAME => "CNAME",
+            RecordType::FORWARD => "FORWARD",
             RecordType::HINFO => "HINFO",
             RecordType::LOC => "LOC",
             RecordType::MAILB => "MAILB",
@@ -225,12 +239,20 @@ impl Display for RecordType {
 
 impl From<InternalResourceRecord> for RecordType {
     fn from(input: InternalResourceRecord) -> RecordType {
+        RecordType::from(&input)
+    }
+}
+
+impl From<&InternalResourceRecord> for RecordType {
+    fn from(input: &InternalResourceRecord) -> RecordType {
         match input {
             InternalResourceRecord::A { .. } => RecordType::A,
             InternalResourceRecord::AAAA { .. } => RecordType::AAAA,
+            InternalResourceRecord::ALIAS { .. } => RecordType::ALIAS,
             InternalResourceRecord::AXFR { .. } => RecordType::AXFR,
             InternalResourceRecord::CAA { .. } => RecordType::CAA,
             InternalResourceRecord::CNAME { .. } => RecordType::CNAME,
+            InternalResourceRecord::FORWARD { .. } => RecordType::FORWARD,
             InternalResourceRecord::HINFO { .. } => RecordType::HINFO,
             InternalResourceRecord::InvalidType => RecordType::InvalidType,
             InternalResourceRecord::LOC { .. } => RecordType::LOC,
@@ -241,6 +263,7 @@ impl From<InternalResourceRecord> for RecordType {
             InternalResourceRecord::SOA { .. } => RecordType::SOA,
             InternalResourceRecord::TXT { .. } => RecordType::TXT,
             InternalResourceRecord::URI { .. } => RecordType::URI,
+            InternalResourceRecord::Generic { rrtype, .. } => RecordType::from(rrtype),
         }
     }
 }
@@ -257,6 +280,7 @@ impl RecordType {
             | RecordType::HINFO
             | RecordType::LOC
             | RecordType::MX
+            | RecordType::NAPTR
             | RecordType::NS
             | RecordType::PTR
             | RecordType::SOA
@@ -267,7 +291,7 @@ impl RecordType {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Sequence, sqlx::Type, ToSchema)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Sequence, sqlx::Type, ToSchema)]
 #[repr(i64)]
 /// CLASS fields appear in resource records, most entries should be IN, but CHAOS is typically used for management-layer things. Ref RFC1035 3.2.4.
 pub enum RecordClass {
@@ -1,5 +1,6 @@
 use std::str::Utf8Error;
 
+use axum::http::StatusCode;
 use packed_struct::PackingError;
 
 /// When things go awry
@@ -26,6 +27,12 @@ pub enum GoatNsError {
     Generic(String),
     Regex(String),
     InvalidValue(String),
+    /// Getting a certificate from the ACME CA didn't work
+    Acme(String),
+    /// The user's already got as much as they're allowed, see [crate::config::QuotaConfig]
+    QuotaExceeded(String),
+    /// The response policy script didn't load or run, see [crate::config::ResponsePolicyConfig]
+    Script(String),
 }
 
 impl From<regex::Error> for GoatNsError {
@@ -75,6 +82,13 @@ impl From<sea_orm::DbErr> for GoatNsError {
     }
 }
 
+#[cfg(feature = "response-policy")]
+impl From<mlua::Error> for GoatNsError {
+    fn from(error: mlua::Error) -> Self {
+        GoatNsError::Script(error.to_string())
+    }
+}
+
 impl From<GoatNsError> for std::io::Error {
     fn from(error: GoatNsError) -> Self {
         match error {
@@ -89,3 +103,52 @@ impl From<GoatNsError> for std::io::Error {
         }
     }
 }
+
+impl GoatNsError {
+    /// The HTTP status to send back when this makes it out to the web API or UI
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            GoatNsError::Csrf(_) | GoatNsError::QuotaExceeded(_) => StatusCode::FORBIDDEN,
+            GoatNsError::InvalidName
+            | GoatNsError::InvalidValue(_)
+            | GoatNsError::Utf8Error(_)
+            | GoatNsError::DateParseError(_)
+            | GoatNsError::Regex(_)
+            | GoatNsError::BytePackingError(_)
+            | GoatNsError::EmptyFile
+            | GoatNsError::RFC8482 => StatusCode::BAD_REQUEST,
+            GoatNsError::SqlxError(sqlx::Error::RowNotFound)
+            | GoatNsError::SeaOrm(sea_orm::DbErr::RecordNotFound(_)) => StatusCode::NOT_FOUND,
+            // someone else's server let us down
+            GoatNsError::Oidc(_) | GoatNsError::ReqwestError(_) | GoatNsError::Acme(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// What to tell the user - the details of client errors, but nothing about our insides when it's our fault
+    pub fn user_message(&self) -> String {
+        match self {
+            GoatNsError::InvalidValue(msg)
+            | GoatNsError::QuotaExceeded(msg)
+            | GoatNsError::Regex(msg)
+            | GoatNsError::DateParseError(msg)
+            | GoatNsError::BytePackingError(msg) => msg.clone(),
+            GoatNsError::Csrf(_) => {
+                "CSRF validation failed, please reload the page and try again".to_string()
+            }
+            GoatNsError::InvalidName => "Invalid name".to_string(),
+            GoatNsError::Utf8Error(_) => "Invalid UTF-8".to_string(),
+            GoatNsError::EmptyFile => "The file was empty".to_string(),
+            GoatNsError::RFC8482 => "ANY queries aren't supported, see RFC8482".to_string(),
+            _ => match self.status_code() {
+                StatusCode::NOT_FOUND => "Not found".to_string(),
+                StatusCode::BAD_GATEWAY => {
+                    "An upstream service failed, please try again".to_string()
+                }
+                _ => "Something went wrong, please try again".to_string(),
+            },
+        }
+    }
+}
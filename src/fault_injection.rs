@@ -0,0 +1,98 @@
+//! Making things go wrong on purpose, so you can see how resolvers (and whatever's behind them) cope with a server
+//! that's slow, loses replies or can't reach its database.
+//!
+//! Only built with the `fault-injection` feature, and everything's off until an admin turns it on with
+//! `PUT /api/admin/faults`. Don't run this anywhere you care about.
+
+use std::sync::RwLock;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::error::GoatNsError;
+
+/// The longest a reply can be held back for
+pub const MAX_DELAY_MS: u64 = 30_000;
+
+/// How often things go wrong, the probabilities are between 0.0 (never) and 1.0 (every time)
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(default)]
+pub struct Faults {
+    /// Chance of a reply being held back for `delay_ms` before it's sent
+    pub delay_probability: f64,
+    /// How long delayed replies are held back for, in milliseconds
+    pub delay_ms: u64,
+    /// Chance of a UDP reply not being sent at all
+    pub drop_udp_probability: f64,
+    /// Chance of a record lookup failing like the database did, which gets a SERVFAIL
+    pub db_error_probability: f64,
+}
+
+impl Faults {
+    /// Make sure it makes sense
+    pub fn check(&self) -> Result<(), GoatNsError> {
+        for (name, probability) in [
+            ("delay_probability", self.delay_probability),
+            ("drop_udp_probability", self.drop_udp_probability),
+            ("db_error_probability", self.db_error_probability),
+        ] {
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(GoatNsError::InvalidValue(format!(
+                    "{name} needs to be between 0.0 and 1.0, got {probability}"
+                )));
+            }
+        }
+        if self.delay_ms > MAX_DELAY_MS {
+            return Err(GoatNsError::InvalidValue(format!(
+                "delay_ms can't be more than {MAX_DELAY_MS}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// How long to hold this reply back, if it is
+    pub fn delay(&self) -> Option<Duration> {
+        match self.delay_ms > 0 && roll(self.delay_probability) {
+            true => Some(Duration::from_millis(self.delay_ms)),
+            false => None,
+        }
+    }
+
+    /// Should this UDP reply go missing?
+    pub fn drop_udp(&self) -> bool {
+        roll(self.drop_udp_probability)
+    }
+
+    /// Should this lookup fail?
+    pub fn db_error(&self) -> Result<(), GoatNsError> {
+        match roll(self.db_error_probability) {
+            true => Err(GoatNsError::Generic("Injected database error".to_string())),
+            false => Ok(()),
+        }
+    }
+}
+
+fn roll(probability: f64) -> bool {
+    probability > 0.0 && rand::random::<f64>() < probability
+}
+
+lazy_static! {
+    static ref FAULTS: RwLock<Faults> = RwLock::new(Faults::default());
+}
+
+/// What's being injected right now
+pub fn current() -> Faults {
+    FAULTS.read().map(|faults| *faults).unwrap_or_default()
+}
+
+/// Change what's being injected, `Faults::default()` turns it all off
+pub fn set(faults: Faults) -> Result<(), GoatNsError> {
+    faults.check()?;
+    let mut current = FAULTS
+        .write()
+        .map_err(|err| GoatNsError::Generic(format!("Faults lock is poisoned: {err:?}")))?;
+    *current = faults;
+    log::warn!("Fault injection is now {faults:?}");
+    Ok(())
+}
@@ -0,0 +1,303 @@
+//! FORWARD records, for names that are still served somewhere else.
+//!
+//! When a query lands on a name with a FORWARD record and nothing else, the record's target is looked up on the
+//! `forward.upstreams` and whatever they say is sent back as our answer, which is handy while moving a zone over a
+//! piece at a time. Upstreams are asked without recursion, in order until one of them answers, and answers are cached
+//! for up to `forward.max_ttl`.
+//!
+//! So it can't go around in circles, queries from the upstreams themselves don't get forwarded, and the config check
+//! won't let this server be one of its own upstreams.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use trust_dns_resolver::proto::op::{Message, ResponseCode};
+use trust_dns_resolver::proto::rr::{RData, Record, RecordType as UpstreamType};
+use trust_dns_resolver::proto::serialize::binary::{BinEncodable, BinEncoder, EncodeMode};
+
+use crate::config::ForwardConfig;
+use crate::enums::RecordClass;
+use crate::error::GoatNsError;
+use crate::name_policy::normalise;
+use crate::reply::Answer;
+use crate::resourcerecord::{InternalResourceRecord, SetTTL};
+
+/// The longest we'll spend on the upstreams for one query, it has to leave time to answer inside [crate::REPLY_TIMEOUT_MS]
+pub const FORWARD_DEADLINE_MS: u64 = crate::REPLY_TIMEOUT_MS * 3 / 4;
+
+/// How long an upstream's negative answer is kept if it doesn't send a SOA, before `max_ttl` is applied
+pub const NEGATIVE_TTL: u32 = 60;
+
+static SETTINGS: RwLock<ForwardConfig> = RwLock::new(ForwardConfig {
+    upstreams: Vec::new(),
+    max_ttl: 300,
+    timeout_ms: 300,
+    cache_size: 1000,
+});
+
+lazy_static! {
+    static ref CACHE: Mutex<HashMap<(String, u16), Cached>> = Mutex::new(HashMap::new());
+}
+
+/// What an upstream said about a name
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Forwarded {
+    /// Records to answer with, the ones owned by the target have no name so they're sent as the question's name
+    Answers(Vec<Answer>),
+    /// The name's there, but there's nothing of that type
+    NoData,
+    /// The name doesn't exist upstream
+    NameError,
+}
+
+#[derive(Clone, Debug)]
+struct Cached {
+    forwarded: Forwarded,
+    expires: Instant,
+}
+
+/// Set where to forward to, from the config at startup. Anything cached is thrown away.
+pub fn init(config: &ForwardConfig) {
+    match SETTINGS.write() {
+        Ok(mut current) => *current = config.clone(),
+        Err(err) => log::error!("Failed to set up forwarding: {err:?}"),
+    }
+    if let Ok(mut cache) = CACHE.lock() {
+        cache.clear();
+    }
+}
+
+fn settings() -> ForwardConfig {
+    SETTINGS
+        .read()
+        .map(|settings| settings.clone())
+        .unwrap_or_default()
+}
+
+/// Is this client one of the upstreams? They don't get forwarded answers, that way lies loops.
+pub fn is_upstream(client: Option<IpAddr>) -> bool {
+    let Some(client) = client else {
+        return false;
+    };
+    let client = client.to_canonical();
+    settings()
+        .upstreams
+        .iter()
+        .any(|upstream| upstream.ip().to_canonical() == client)
+}
+
+/// Look up `target` on the upstreams, from the cache if we asked recently
+pub async fn resolve(
+    target: &str,
+    qtype: u16,
+    rclass: RecordClass,
+) -> Result<Forwarded, GoatNsError> {
+    let settings = settings();
+    if settings.upstreams.is_empty() {
+        return Err(GoatNsError::Generic(
+            "There's a FORWARD record but no forward.upstreams to ask".to_string(),
+        ));
+    }
+    let key = (normalise(target), qtype);
+    if let Some(forwarded) = cached(&key) {
+        return Ok(forwarded);
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(FORWARD_DEADLINE_MS);
+    let mut last_error = None;
+    for upstream in &settings.upstreams {
+        // the last one only gets what's left
+        let wait = Duration::from_millis(settings.timeout_ms)
+            .min(deadline.saturating_duration_since(Instant::now()));
+        if wait.is_zero() {
+            log::debug!(
+                "Ran out of time forwarding {} before asking {upstream}",
+                key.0
+            );
+            break;
+        }
+        let message = match crate::delegation::query(
+            *upstream,
+            &key.0,
+            UpstreamType::from(qtype),
+            wait,
+        )
+        .await
+        {
+            Ok(message) => message,
+            Err(err) => {
+                log::debug!("Forwarding {} to {upstream} failed: {err:?}", key.0);
+                last_error = Some(err);
+                continue;
+            }
+        };
+        match from_message(&message, &key.0, rclass, settings.max_ttl) {
+            Ok((forwarded, ttl)) => {
+                store(key, &forwarded, ttl, settings.cache_size);
+                return Ok(forwarded);
+            }
+            Err(err) => {
+                log::debug!("Bad answer from {upstream} for {}: {err:?}", key.0);
+                last_error = Some(err);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        GoatNsError::Generic(format!("None of the upstreams answered for {}", key.0))
+    }))
+}
+
+fn cached(key: &(String, u16)) -> Option<Forwarded> {
+    let mut cache = CACHE.lock().ok()?;
+    let entry = cache.get(key)?;
+    let remaining = entry.expires.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        cache.remove(key);
+        return None;
+    }
+    let remaining = u32::try_from(remaining.as_secs()).unwrap_or(u32::MAX);
+    Some(match &entry.forwarded {
+        // count the TTLs down so the answer doesn't outlive what upstream said
+        Forwarded::Answers(answers) => Forwarded::Answers(
+            answers
+                .iter()
+                .map(|answer| Answer {
+                    name: answer.name.clone(),
+                    record: answer
+                        .record
+                        .clone()
+                        .set_ttl((*answer.record.ttl()).min(remaining)),
+                })
+                .collect(),
+        ),
+        other => other.clone(),
+    })
+}
+
+fn store(key: (String, u16), forwarded: &Forwarded, ttl: u32, cache_size: usize) {
+    if cache_size == 0 || ttl == 0 {
+        return;
+    }
+    let Ok(mut cache) = CACHE.lock() else {
+        return;
+    };
+    if cache.len() >= cache_size && !cache.contains_key(&key) {
+        let now = Instant::now();
+        cache.retain(|_, entry| entry.expires > now);
+        if cache.len() >= cache_size {
+            cache.clear();
+        }
+    }
+    cache.insert(
+        key,
+        Cached {
+            forwarded: forwarded.clone(),
+            expires: Instant::now() + Duration::from_secs(ttl.into()),
+        },
+    );
+}
+
+/// Turn an upstream's reply into something we can answer with, and how long it's good for. TTLs are capped at `max_ttl`.
+pub fn from_message(
+    message: &Message,
+    target: &str,
+    rclass: RecordClass,
+    max_ttl: u32,
+) -> Result<(Forwarded, u32), GoatNsError> {
+    if message.truncated() {
+        return Err(GoatNsError::Generic(
+            "Upstream's answer was truncated".to_string(),
+        ));
+    }
+    match message.response_code() {
+        ResponseCode::NoError => {}
+        ResponseCode::NXDomain => {
+            return Ok((Forwarded::NameError, negative_ttl(message, max_ttl)));
+        }
+        other => {
+            return Err(GoatNsError::Generic(format!("Upstream answered {other}")));
+        }
+    }
+    if message.answers().is_empty() {
+        // an empty answer from something that isn't authoritative is probably a referral or a lame server
+        if !message.authoritative() {
+            return Err(GoatNsError::Generic(
+                "Upstream isn't authoritative for the name".to_string(),
+            ));
+        }
+        return Ok((Forwarded::NoData, negative_ttl(message, max_ttl)));
+    }
+
+    let target = normalise(target);
+    let mut answers = vec![];
+    for record in message.answers() {
+        let owner = normalise(&record.name().to_ascii());
+        let record = to_internal(record, rclass, max_ttl)?;
+        answers.push(match owner == target {
+            true => Answer::from(record),
+            false => Answer::named(owner.into_bytes(), record),
+        });
+    }
+    let ttl = answers
+        .iter()
+        .map(|answer| *answer.record.ttl())
+        .min()
+        .unwrap_or(0);
+    Ok((Forwarded::Answers(answers), ttl))
+}
+
+/// How long a negative answer's good for, from the SOA in the authority section ref RFC2308 section 5
+fn negative_ttl(message: &Message, max_ttl: u32) -> u32 {
+    message
+        .name_servers()
+        .iter()
+        .find_map(|record| match record.data() {
+            Some(RData::SOA(soa)) => Some(record.ttl().min(soa.minimum())),
+            _ => None,
+        })
+        .unwrap_or(NEGATIVE_TTL)
+        .min(max_ttl)
+}
+
+/// Addresses become our own records, anything else is carried as its wire format
+fn to_internal(
+    record: &Record,
+    rclass: RecordClass,
+    max_ttl: u32,
+) -> Result<InternalResourceRecord, GoatNsError> {
+    let ttl = record.ttl().min(max_ttl);
+    let Some(rdata) = record.data() else {
+        return Err(GoatNsError::Generic(format!(
+            "Upstream sent a {} record with no data",
+            record.record_type()
+        )));
+    };
+    Ok(match rdata {
+        RData::A(address) => InternalResourceRecord::A {
+            address: address.0.into(),
+            ttl,
+            rclass,
+        },
+        RData::AAAA(address) => InternalResourceRecord::AAAA {
+            address: address.0.into(),
+            ttl,
+            rclass,
+        },
+        rdata => {
+            // canonical form, so names in it aren't compressed against a message we're not sending
+            let mut buf = vec![];
+            let mut encoder = BinEncoder::with_mode(&mut buf, EncodeMode::Signing);
+            rdata.emit(&mut encoder).map_err(|err| {
+                GoatNsError::Generic(format!("Failed to encode upstream's record: {err:?}"))
+            })?;
+            InternalResourceRecord::Generic {
+                rrtype: record.record_type().into(),
+                rdata: buf,
+                ttl,
+                rclass,
+            }
+        }
+    })
+}
@@ -0,0 +1,97 @@
+//! Record types we don't know about, using the generic syntax from [RFC3597](https://www.rfc-editor.org/rfc/rfc3597).
+//!
+//! The type's written as `TYPE<number>` and the rdata as `\# <length> <hex>`, eg `TYPE65534` and `\# 4 0a000001`. The
+//! rdata's served exactly as it's stored, so new record types can be used before GoatNS knows what's in them.
+
+use crate::enums::RecordType;
+use crate::error::GoatNsError;
+
+/// The start of rdata in the generic format
+pub const GENERIC_RDATA_PREFIX: &str = "\\#";
+
+/// Works out the type code for a record type, either from its name or as `TYPE<number>`
+pub fn parse_type(input: &str) -> Option<u16> {
+    let input = input.trim();
+    if let Some(prefix) = input.get(0..4) {
+        if prefix.eq_ignore_ascii_case("TYPE") {
+            if let Ok(code) = input[4..].parse::<u16>() {
+                return Some(code);
+            }
+        }
+    }
+    match RecordType::from(input.to_uppercase().as_str()) {
+        RecordType::InvalidType => None,
+        rrtype => Some(rrtype as u16),
+    }
+}
+
+/// The name we use for a type code, `TYPE<number>` if we don't have one for it
+pub fn type_name(code: u16) -> String {
+    match RecordType::from(&code) {
+        RecordType::InvalidType => format!("TYPE{code}"),
+        rrtype => rrtype.to_string(),
+    }
+}
+
+/// Query-only and pseudo types - QTYPEs like ANY and AXFR, OPT, and the private ALIAS and FORWARD - which can't be stored
+/// as generic records
+pub fn is_meta_type(code: u16) -> bool {
+    // RFC6895 section 3.1 - 128 to 255 are for QTYPEs and meta-TYPEs
+    matches!(code, 0 | 41 | 128..=255)
+        || code == RecordType::ALIAS as u16
+        || code == RecordType::FORWARD as u16
+}
+
+/// Types that can only be served from generic records, because we don't have first-class support for them
+pub fn is_generic_only(code: u16) -> bool {
+    !is_meta_type(code) && !RecordType::from(&code).supported()
+}
+
+/// If the rdata's in the generic format
+pub fn is_generic_rdata(rdata: &str) -> bool {
+    rdata.trim_start().starts_with(GENERIC_RDATA_PREFIX)
+}
+
+/// Parses `\# <length> <hex>` into the bytes that go on the wire, the hex can be split up with spaces
+pub fn parse_rdata(rdata: &str) -> Result<Vec<u8>, GoatNsError> {
+    let mut fields = rdata.split_whitespace();
+    if fields.next() != Some(GENERIC_RDATA_PREFIX) {
+        return Err(GoatNsError::InvalidValue(format!(
+            "Generic rdata needs to start with {GENERIC_RDATA_PREFIX}, got {rdata:?}"
+        )));
+    }
+    let length: usize = fields
+        .next()
+        .ok_or_else(|| {
+            GoatNsError::InvalidValue("Generic rdata is missing its length".to_string())
+        })?
+        .parse()
+        .map_err(|err| {
+            GoatNsError::InvalidValue(format!("Generic rdata has an invalid length: {err:?}"))
+        })?;
+    let hex_data: String = fields.collect();
+    let bytes = hex::decode(&hex_data).map_err(|err| {
+        GoatNsError::InvalidValue(format!(
+            "Generic rdata has invalid hex {hex_data:?}: {err:?}"
+        ))
+    })?;
+    if bytes.len() != length || length > u16::MAX as usize {
+        return Err(GoatNsError::InvalidValue(format!(
+            "Generic rdata says it's {length} bytes long but it's {} bytes",
+            bytes.len()
+        )));
+    }
+    Ok(bytes)
+}
+
+/// Writes rdata in the generic format
+pub fn format_rdata(rdata: &[u8]) -> String {
+    match rdata.is_empty() {
+        true => format!("{GENERIC_RDATA_PREFIX} 0"),
+        false => format!(
+            "{GENERIC_RDATA_PREFIX} {} {}",
+            rdata.len(),
+            hex::encode(rdata)
+        ),
+    }
+}
@@ -0,0 +1,189 @@
+//! Liveness and readiness tracking, for `/healthz` and `/readyz`
+//!
+//! The listeners and datastore flag themselves up (and down) here, and [self_check] periodically asks the local UDP
+//! listener a question to make sure queries actually get answered.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use bytes::{BufMut, BytesMut};
+use packed_struct::prelude::*;
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+use crate::enums::{OpCode, PacketType, Rcode, RecordClass, RecordType};
+use crate::error::GoatNsError;
+use crate::{Header, Question, HEADER_BYTES, REPLY_TIMEOUT_MS, UDP_BUFFER_SIZE};
+
+/// What's up and running
+#[derive(Debug, Default)]
+pub struct Health {
+    /// The datastore manager is processing commands
+    pub datastore: AtomicBool,
+    /// The UDP listener is bound
+    pub udp_listener: AtomicBool,
+    /// The TCP listener is bound
+    pub tcp_listener: AtomicBool,
+    /// The last self-query got an answer
+    pub self_check: AtomicBool,
+}
+
+impl Health {
+    /// Flag a component as up or down
+    pub fn set(flag: &AtomicBool, value: bool) {
+        flag.store(value, Ordering::Relaxed);
+    }
+
+    /// Check a component
+    pub fn get(flag: &AtomicBool) -> bool {
+        flag.load(Ordering::Relaxed)
+    }
+
+    /// A snapshot of everything, `self_check` is only counted when it's enabled
+    pub fn report(&self, database: bool, self_check_enabled: bool) -> HealthReport {
+        let self_check = match self_check_enabled {
+            true => Some(Self::get(&self.self_check)),
+            false => None,
+        };
+        HealthReport {
+            datastore: Self::get(&self.datastore),
+            udp_listener: Self::get(&self.udp_listener),
+            tcp_listener: Self::get(&self.tcp_listener),
+            database,
+            self_check,
+        }
+    }
+}
+
+/// The server-wide health flags
+pub static HEALTH: Health = Health {
+    datastore: AtomicBool::new(false),
+    udp_listener: AtomicBool::new(false),
+    tcp_listener: AtomicBool::new(false),
+    self_check: AtomicBool::new(false),
+};
+
+/// What `/readyz` hands back
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct HealthReport {
+    pub datastore: bool,
+    pub udp_listener: bool,
+    pub tcp_listener: bool,
+    pub database: bool,
+    /// [None] if the self-check is turned off
+    pub self_check: Option<bool>,
+}
+
+impl HealthReport {
+    /// Is everything we care about up?
+    pub fn ready(&self) -> bool {
+        self.datastore
+            && self.udp_listener
+            && self.tcp_listener
+            && self.database
+            && self.self_check.unwrap_or(true)
+    }
+}
+
+/// Where to send the self-query, listening on "everything" means we can talk to it on localhost
+pub fn self_check_target(listener: SocketAddr) -> SocketAddr {
+    match listener.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), listener.port())
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), listener.port())
+        }
+        _ => listener,
+    }
+}
+
+/// Send a single query to the listener and check we get a sensible answer back.
+///
+/// NXDOMAIN is fine - it means the datastore looked and didn't find anything - but SERVFAIL or silence isn't.
+pub async fn self_check(target: SocketAddr, qname: &str) -> Result<(), GoatNsError> {
+    let id: u16 = rand::random();
+    let header = Header {
+        id,
+        qr: PacketType::Query,
+        opcode: OpCode::Query,
+        authoritative: false,
+        truncated: false,
+        recursion_desired: false,
+        recursion_available: false,
+        z: false,
+        ad: false,
+        cd: false,
+        rcode: Rcode::NoError,
+        qdcount: 1,
+        ancount: 0,
+        nscount: 0,
+        arcount: 0,
+    };
+    let mut packet = BytesMut::with_capacity(UDP_BUFFER_SIZE);
+    packet.put_slice(&header.pack()?);
+    Question::new(
+        qname.as_bytes().to_vec(),
+        RecordType::A,
+        RecordClass::Internet,
+    )
+    .write_to(&mut packet)?;
+
+    let bind_addr: SocketAddr = match target {
+        SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(target).await?;
+    socket.send(&packet).await?;
+
+    let mut buf = [0u8; UDP_BUFFER_SIZE];
+    let len = timeout(
+        Duration::from_millis(REPLY_TIMEOUT_MS * 2),
+        socket.recv(&mut buf),
+    )
+    .await
+    .map_err(|_| GoatNsError::Generic(format!("Self-check query to {target} timed out")))??;
+    if len < HEADER_BYTES {
+        return Err(GoatNsError::Generic(format!(
+            "Self-check reply from {target} was too short ({len} bytes)"
+        )));
+    }
+    let mut reply_header: [u8; HEADER_BYTES] = [0; HEADER_BYTES];
+    reply_header.copy_from_slice(&buf[0..HEADER_BYTES]);
+    let reply_header = Header::unpack(&reply_header)?;
+    if reply_header.id != id || reply_header.qr != PacketType::Answer {
+        return Err(GoatNsError::Generic(format!(
+            "Self-check got an unexpected reply from {target}: {reply_header:?}"
+        )));
+    }
+    match reply_header.rcode {
+        Rcode::NoError | Rcode::NameError => Ok(()),
+        rcode => Err(GoatNsError::Generic(format!(
+            "Self-check query to {target} got {rcode:?}"
+        ))),
+    }
+}
+
+/// Runs [self_check] forever, updating [HEALTH] with the result
+pub async fn self_check_loop(listener: SocketAddr, qname: String, interval: Duration) {
+    let target = self_check_target(listener);
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        match self_check(target, &qname).await {
+            Ok(()) => {
+                if !Health::get(&HEALTH.self_check) {
+                    log::info!("DNS self-check against {target} passed");
+                }
+                Health::set(&HEALTH.self_check, true);
+            }
+            Err(err) => {
+                log::warn!("DNS self-check against {target} failed: {err:?}");
+                Health::set(&HEALTH.self_check, false);
+            }
+        }
+    }
+}
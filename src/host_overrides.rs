@@ -0,0 +1,99 @@
+//! Host to address overrides, like a hosts file, so a home lab can have `nas.lan` without setting up a whole zone
+//!
+//! They're checked before anything in the datastore, so an overridden name always gets the configured addresses.
+//! Only the exact name's overridden - names below it and anything else in the same zone are still answered from the
+//! zones. Queries for other types of record at an overridden name get an empty answer.
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+use crate::enums::{RecordClass, RecordType};
+use crate::name_policy::normalise;
+use crate::resourcerecord::InternalResourceRecord;
+use crate::Question;
+
+/// The TTL on answers from the overrides, short so changes show up quickly
+pub const OVERRIDE_TTL: u32 = 60;
+
+static OVERRIDES: RwLock<HostOverrides> = RwLock::new(HostOverrides {
+    hosts: BTreeMap::new(),
+});
+
+/// Names and the addresses to answer with
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HostOverrides {
+    hosts: BTreeMap<String, Vec<IpAddr>>,
+}
+
+impl From<&BTreeMap<String, Vec<IpAddr>>> for HostOverrides {
+    fn from(hosts: &BTreeMap<String, Vec<IpAddr>>) -> Self {
+        let mut overrides = Self::default();
+        for (name, addresses) in hosts {
+            overrides
+                .hosts
+                .entry(normalise(name))
+                .or_default()
+                .extend(addresses);
+        }
+        overrides
+    }
+}
+
+impl HostOverrides {
+    /// The records for an overridden name, `None` if it's not one of them and the zones should answer
+    pub fn answer(&self, question: &Question) -> Option<Vec<InternalResourceRecord>> {
+        if question.qclass != RecordClass::Internet {
+            return None;
+        }
+        let name = question.lookup_name();
+        let addresses = self
+            .hosts
+            .get(&normalise(std::str::from_utf8(&name).ok()?))?;
+        Some(
+            addresses
+                .iter()
+                .filter_map(|address| match (address, question.qtype) {
+                    (IpAddr::V4(address), RecordType::A | RecordType::ANY) => {
+                        Some(InternalResourceRecord::A {
+                            address: u32::from(*address),
+                            ttl: OVERRIDE_TTL,
+                            rclass: RecordClass::Internet,
+                        })
+                    }
+                    (IpAddr::V6(address), RecordType::AAAA | RecordType::ANY) => {
+                        Some(InternalResourceRecord::AAAA {
+                            address: u128::from(*address),
+                            ttl: OVERRIDE_TTL,
+                            rclass: RecordClass::Internet,
+                        })
+                    }
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    /// How many names are overridden
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    /// If nothing's overridden
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+}
+
+/// Set the overrides, from the config at startup
+pub fn init(hosts: &BTreeMap<String, Vec<IpAddr>>) {
+    match OVERRIDES.write() {
+        Ok(mut current) => *current = HostOverrides::from(hosts),
+        Err(err) => log::error!("Failed to set up the host overrides: {err:?}"),
+    }
+}
+
+/// Check the configured overrides for the question, see [HostOverrides::answer]
+pub fn answer(question: &Question) -> Option<Vec<InternalResourceRecord>> {
+    OVERRIDES.read().ok()?.answer(question)
+}
@@ -0,0 +1,191 @@
+//! Where zone imports read their files from, so hundreds of zones can be moved in one go.
+//!
+//! The filename can be `-` for stdin, and zip, tar and gzipped tar archives get each file in them imported. A plain
+//! gzipped file is treated as the one file. What it is gets worked out from the first few bytes rather than the file
+//! name, so `curl ... | goatns --import-zones -f -` works with any of them.
+
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+
+use crate::error::GoatNsError;
+
+/// The filename that means "read stdin"
+pub const STDIN: &str = "-";
+/// How much we'll read, after decompressing, so a zip bomb can't eat all the memory
+pub const MAX_IMPORT_SIZE: u64 = 256 * 1024 * 1024;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+/// Where tar puts `ustar`, ref POSIX.1-1988
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_MAGIC: &[u8] = b"ustar";
+/// Extensions that are dropped to get a zone's name from its file
+const ZONE_FILE_EXTENSIONS: [&str; 5] = ["zone", "json", "db", "txt", "gz"];
+
+/// A file to import zones from
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ZoneSource {
+    /// Where it came from, the path inside the archive for archives
+    pub name: String,
+    pub contents: String,
+}
+
+/// Is the file an archive, rather than a zone file? It only reads the start of it.
+pub fn is_archive_file(path: &Path) -> Result<bool, GoatNsError> {
+    let mut start = Vec::with_capacity(TAR_MAGIC_OFFSET + TAR_MAGIC.len());
+    std::fs::File::open(path)
+        .map_err(|err| GoatNsError::FileError(format!("Failed to open {path:?}: {err}")))?
+        .take((TAR_MAGIC_OFFSET + TAR_MAGIC.len()) as u64)
+        .read_to_end(&mut start)?;
+    Ok(is_archive(&start))
+}
+
+/// Does it look like gzip, zip or tar?
+pub fn is_archive(bytes: &[u8]) -> bool {
+    bytes.starts_with(GZIP_MAGIC) || bytes.starts_with(ZIP_MAGIC) || is_tar(bytes)
+}
+
+fn is_tar(bytes: &[u8]) -> bool {
+    bytes
+        .get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len())
+        .is_some_and(|magic| magic == TAR_MAGIC)
+}
+
+/// Read the file (or stdin if it's [STDIN]) and pull the zone files out of it
+pub fn read_sources(filename: &str) -> Result<Vec<ZoneSource>, GoatNsError> {
+    let bytes = match filename {
+        STDIN => read_limited(std::io::stdin().lock(), "stdin")?,
+        _ => read_limited(
+            std::fs::File::open(filename).map_err(|err| {
+                GoatNsError::FileError(format!("Failed to open {filename}: {err}"))
+            })?,
+            filename,
+        )?,
+    };
+    let name = match filename {
+        STDIN => "stdin",
+        _ => filename,
+    };
+    sources_from_bytes(name, bytes)
+}
+
+/// Pull the zone files out of an archive, anything that isn't one is a single zone file called `name`
+pub fn sources_from_bytes(name: &str, bytes: Vec<u8>) -> Result<Vec<ZoneSource>, GoatNsError> {
+    if bytes.starts_with(ZIP_MAGIC) {
+        return from_zip(bytes);
+    }
+    if bytes.starts_with(GZIP_MAGIC) {
+        let bytes = read_limited(MultiGzDecoder::new(bytes.as_slice()), name)?;
+        if is_tar(&bytes) {
+            return from_tar(&bytes);
+        }
+        let name = name
+            .strip_suffix(".gz")
+            .or_else(|| name.strip_suffix(".GZ"))
+            .unwrap_or(name);
+        return Ok(vec![source(name, bytes)?]);
+    }
+    if is_tar(&bytes) {
+        return from_tar(&bytes);
+    }
+    Ok(vec![source(name, bytes)?])
+}
+
+fn from_zip(bytes: Vec<u8>) -> Result<Vec<ZoneSource>, GoatNsError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))
+        .map_err(|err| GoatNsError::FileError(format!("Failed to read zip file: {err:?}")))?;
+    let mut sources = vec![];
+    let mut budget = MAX_IMPORT_SIZE;
+    for index in 0..archive.len() {
+        let file = archive
+            .by_index(index)
+            .map_err(|err| GoatNsError::FileError(format!("Failed to read zip file: {err:?}")))?;
+        let name = file.name().to_string();
+        if file.is_dir() || skipped(&name) {
+            continue;
+        }
+        let contents = read_limited_to(file, &name, budget)?;
+        budget = budget.saturating_sub(contents.len() as u64);
+        sources.push(source(&name, contents)?);
+    }
+    Ok(sources)
+}
+
+fn from_tar(bytes: &[u8]) -> Result<Vec<ZoneSource>, GoatNsError> {
+    let mut archive = tar::Archive::new(bytes);
+    let mut sources = vec![];
+    let entries = archive
+        .entries()
+        .map_err(|err| GoatNsError::FileError(format!("Failed to read tar file: {err}")))?;
+    for entry in entries {
+        let entry = entry
+            .map_err(|err| GoatNsError::FileError(format!("Failed to read tar file: {err}")))?;
+        let name = entry
+            .path()
+            .map_err(|err| GoatNsError::FileError(format!("Bad file name in tar file: {err}")))?
+            .to_string_lossy()
+            .to_string();
+        if !entry.header().entry_type().is_file() || skipped(&name) {
+            continue;
+        }
+        let contents = read_limited(entry, &name)?;
+        sources.push(source(&name, contents)?);
+    }
+    Ok(sources)
+}
+
+/// Things that end up in archives that aren't zone files, like macOS's `__MACOSX/` and `._` files
+fn skipped(name: &str) -> bool {
+    name.starts_with("__MACOSX/") || file_name(name).starts_with('.')
+}
+
+fn source(name: &str, contents: Vec<u8>) -> Result<ZoneSource, GoatNsError> {
+    let contents = String::from_utf8(contents)
+        .map_err(|_| GoatNsError::FileError(format!("{name} isn't UTF-8 text")))?;
+    Ok(ZoneSource {
+        name: name.to_string(),
+        contents,
+    })
+}
+
+fn read_limited(reader: impl Read, name: &str) -> Result<Vec<u8>, GoatNsError> {
+    read_limited_to(reader, name, MAX_IMPORT_SIZE)
+}
+
+fn read_limited_to(reader: impl Read, name: &str, limit: u64) -> Result<Vec<u8>, GoatNsError> {
+    let mut buf = vec![];
+    reader
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut buf)
+        .map_err(|err| GoatNsError::FileError(format!("Failed to read {name}: {err}")))?;
+    if buf.len() as u64 > limit {
+        return Err(GoatNsError::FileError(format!(
+            "{name} is more than {MAX_IMPORT_SIZE} bytes, which is too much to import in one go"
+        )));
+    }
+    Ok(buf)
+}
+
+fn file_name(name: &str) -> &str {
+    name.rsplit(['/', '\\']).next().unwrap_or(name)
+}
+
+/// The zone a file's for, going by its name - `zones/example.com.zone` is `example.com`, and so is `example.com`
+pub fn zone_name_from_filename(name: &str) -> Option<String> {
+    let mut zone_name = file_name(name);
+    while let Some((rest, extension)) = zone_name.rsplit_once('.') {
+        if !ZONE_FILE_EXTENSIONS
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(extension))
+        {
+            break;
+        }
+        zone_name = rest;
+    }
+    match zone_name.trim_end_matches('.') {
+        "" => None,
+        zone_name => Some(zone_name.to_string()),
+    }
+}
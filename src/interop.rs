@@ -0,0 +1,716 @@
+//! Converting zones to and from other providers' export formats.
+//!
+//! Currently handles the Cloudflare API's DNS records list (`GET /zones/:id/dns_records`), the
+//! output of `aws route53 list-resource-record-sets` and BIND zone files.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::enums::RecordClass;
+use crate::error::GoatNsError;
+use crate::import_source::{is_archive_file, read_sources, STDIN};
+use crate::name_policy::normalise;
+use crate::resourcerecord::parse_txt_rdata;
+use crate::zone_schema::{zones_from_json, zones_to_json};
+use crate::zonefile::{
+    parse_records, qualified_rdata, read_with_includes, soa_owner, ttl_directive, Soa,
+};
+use crate::zones::{rname_default, FileZone, FileZoneRecord};
+
+/// Cloudflare uses a TTL of 1 to mean "automatic"
+const CLOUDFLARE_AUTO_TTL: u32 = 1;
+/// What we turn an automatic TTL into, which is what Cloudflare uses for non-proxied records
+const CLOUDFLARE_AUTO_TTL_SECONDS: u32 = 300;
+/// Record types where Cloudflare keeps the first field of the rdata in `priority`
+const CLOUDFLARE_PRIORITY_TYPES: &[&str] = &["MX", "SRV", "URI"];
+/// Record types where Route53 expects the rdata's hostname to be fully qualified with a trailing dot
+const ROUTE53_HOSTNAME_TYPES: &[&str] = &["CNAME", "DNAME", "MX", "NS", "PTR", "SRV"];
+/// Record types where Route53 (and zone files) want the value as quoted character-strings
+const ROUTE53_QUOTED_TYPES: &[&str] = &["SPF", "TXT"];
+/// The TTL Route53 gives its own SOA records
+const ROUTE53_DEFAULT_TTL: u32 = 900;
+/// Longest character-string allowed in a TXT record
+const TXT_STRING_MAX: usize = 255;
+
+/// The file formats we can import and export zones in
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ZoneFileFormat {
+    /// Our own JSON format, see [crate::zone_schema]
+    #[default]
+    GoatNs,
+    /// A Cloudflare API export
+    Cloudflare,
+    /// A Route53 `list-resource-record-sets` dump
+    Route53,
+    /// A BIND zone file
+    Bind,
+}
+
+impl Display for ZoneFileFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ZoneFileFormat::GoatNs => "goatns",
+            ZoneFileFormat::Cloudflare => "cloudflare",
+            ZoneFileFormat::Route53 => "route53",
+            ZoneFileFormat::Bind => "bind",
+        })
+    }
+}
+
+impl FromStr for ZoneFileFormat {
+    type Err = GoatNsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "goatns" => Ok(ZoneFileFormat::GoatNs),
+            "cloudflare" => Ok(ZoneFileFormat::Cloudflare),
+            "route53" => Ok(ZoneFileFormat::Route53),
+            "bind" => Ok(ZoneFileFormat::Bind),
+            _ => Err(GoatNsError::InvalidValue(format!(
+                "Unknown zone file format: {s}"
+            ))),
+        }
+    }
+}
+
+/// A record from the Cloudflare DNS records API
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct CloudflareRecord {
+    /// Cloudflare's record ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The zone the record's in, it's included in API responses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone_name: Option<String>,
+    /// Fully qualified name
+    pub name: String,
+    /// Record type
+    #[serde(rename = "type")]
+    pub rrtype: String,
+    /// The rdata, minus the priority for MX/SRV/URI records
+    pub content: String,
+    /// Time to live, 1 means automatic
+    #[serde(default = "cloudflare_auto_ttl")]
+    pub ttl: u32,
+    /// The priority for MX/SRV/URI records
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
+    /// If the record's proxied, we can't do anything with this but it's kept so exports look like Cloudflare's
+    #[serde(default)]
+    pub proxied: bool,
+    /// Free-text comment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Tags in `name:value` form
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+fn cloudflare_auto_ttl() -> u32 {
+    CLOUDFLARE_AUTO_TTL
+}
+
+/// Cloudflare exports are either the raw API response or just the list of records
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum CloudflareExport {
+    Response { result: Vec<CloudflareRecord> },
+    Records(Vec<CloudflareRecord>),
+}
+
+/// A record set from `aws route53 list-resource-record-sets`
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Route53RecordSet {
+    /// Fully qualified name, with a trailing dot
+    #[serde(rename = "Name")]
+    pub name: String,
+    /// Record type
+    #[serde(rename = "Type")]
+    pub rrtype: String,
+    /// Time to live, alias records don't have one
+    #[serde(rename = "TTL", default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+    /// The values in the set
+    #[serde(
+        rename = "ResourceRecords",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub resource_records: Vec<Route53Value>,
+    /// Route53 alias target, these point at AWS resources so we can't import them
+    #[serde(
+        rename = "AliasTarget",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub alias_target: Option<serde_json::Value>,
+}
+
+/// A single value in a [Route53RecordSet]
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Route53Value {
+    /// The rdata
+    #[serde(rename = "Value")]
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Route53Export {
+    #[serde(rename = "ResourceRecordSets")]
+    resource_record_sets: Vec<Route53RecordSet>,
+}
+
+/// Load zones from a file in the given format, `zone_name` is required for formats which don't say what zone they're for
+pub fn load_zones_with_format(
+    filename: &str,
+    format: ZoneFileFormat,
+    zone_name: Option<&str>,
+) -> Result<Vec<FileZone>, GoatNsError> {
+    let read_file = || -> Result<String, GoatNsError> {
+        let mut buf = String::new();
+        File::open(filename)
+            .map_err(|err| GoatNsError::FileError(format!("Failed to open zone file: {err:?}")))?
+            .read_to_string(&mut buf)
+            .inspect_err(|err| log::error!("Failed to read {}: {:?}", filename, err))?;
+        Ok(buf)
+    };
+    match format {
+        ZoneFileFormat::GoatNs => crate::zones::load_zones(filename),
+        ZoneFileFormat::Cloudflare => from_cloudflare(&read_file()?, zone_name),
+        ZoneFileFormat::Route53 => from_route53(&read_file()?, zone_name).map(|zone| vec![zone]),
+        ZoneFileFormat::Bind => from_bind(
+            &read_with_includes(Path::new(filename), true, zone_name)?,
+            zone_name,
+        )
+        .map(|zone| vec![zone]),
+    }
+}
+
+/// Load zones from a file's contents in the given format, like [load_zones_with_format] but `$INCLUDE`s aren't
+/// followed since there's nowhere to follow them from
+pub fn load_zones_from_str(
+    input: &str,
+    format: ZoneFileFormat,
+    zone_name: Option<&str>,
+) -> Result<Vec<FileZone>, GoatNsError> {
+    match format {
+        ZoneFileFormat::GoatNs => zones_from_json(input),
+        ZoneFileFormat::Cloudflare => from_cloudflare(input, zone_name),
+        ZoneFileFormat::Route53 => from_route53(input, zone_name).map(|zone| vec![zone]),
+        ZoneFileFormat::Bind => from_bind(input, zone_name).map(|zone| vec![zone]),
+    }
+}
+
+/// Load the zones for an import. `filename` can be `-` for stdin, or an archive of zone files, see
+/// [crate::import_source]. With `zone_name_from_filename` each file only gets to have the zone it's named for, like
+/// giving that file `zone_name`.
+pub fn load_import(
+    filename: &str,
+    format: ZoneFileFormat,
+    zone_name: Option<&str>,
+    zone_name_from_filename: bool,
+) -> Result<Vec<FileZone>, GoatNsError> {
+    // plain files are read the way they always have been, so $INCLUDEs still work
+    if filename != STDIN && !zone_name_from_filename && !is_archive_file(Path::new(filename))? {
+        return load_zones_with_format(filename, format, zone_name);
+    }
+    let mut zones = vec![];
+    for source in read_sources(filename)? {
+        let file_zone_name = match zone_name_from_filename {
+            true => Some(
+                crate::import_source::zone_name_from_filename(&source.name).ok_or_else(|| {
+                    GoatNsError::InvalidValue(format!(
+                        "Couldn't get a zone name from {}",
+                        source.name
+                    ))
+                })?,
+            ),
+            false => zone_name.map(str::to_string),
+        };
+        let loaded = load_zones_from_str(&source.contents, format, file_zone_name.as_deref())
+            .map_err(|err| GoatNsError::InvalidValue(format!("{}: {err:?}", source.name)))?;
+        match (zone_name_from_filename, file_zone_name) {
+            (true, Some(file_zone_name)) => {
+                let file_zone_name = normalise(&file_zone_name);
+                let before = zones.len();
+                zones.extend(
+                    loaded
+                        .into_iter()
+                        .filter(|zone| normalise(&zone.name) == file_zone_name),
+                );
+                if zones.len() == before {
+                    return Err(GoatNsError::InvalidValue(format!(
+                        "{} doesn't have the zone {file_zone_name} in it",
+                        source.name
+                    )));
+                }
+            }
+            _ => zones.extend(loaded),
+        }
+    }
+    Ok(zones)
+}
+
+/// Serialize a zone in the given format
+pub fn export_zone(zone: &FileZone, format: ZoneFileFormat) -> Result<String, GoatNsError> {
+    match format {
+        ZoneFileFormat::GoatNs => zones_to_json(std::slice::from_ref(zone)),
+        ZoneFileFormat::Cloudflare => to_cloudflare(zone),
+        ZoneFileFormat::Route53 => to_route53(zone),
+        ZoneFileFormat::Bind => to_bind(zone),
+    }
+}
+
+/// Turn a fully qualified name into one relative to the zone, returns None if it's not in the zone
+pub(crate) fn relative_name(fqdn: &str, zone_name: &str) -> Option<String> {
+    let fqdn = fqdn.trim_end_matches('.');
+    let zone_name = zone_name.trim_end_matches('.');
+    if fqdn.eq_ignore_ascii_case(zone_name) {
+        return Some("@".to_string());
+    }
+    let suffix = format!(".{}", zone_name.to_ascii_lowercase());
+    match fqdn.to_ascii_lowercase().ends_with(&suffix) {
+        true => Some(fqdn[..fqdn.len() - suffix.len()].to_string()),
+        false => None,
+    }
+}
+
+/// Turn a name relative to the zone into a fully qualified one (without the trailing dot)
+fn absolute_name(name: &str, zone_name: &str) -> String {
+    match name {
+        "" | "@" => zone_name.to_string(),
+        _ => format!("{name}.{zone_name}"),
+    }
+}
+
+/// Parse a Cloudflare export, records are grouped into zones by their `zone_name`, falling back to `zone_name`
+pub fn from_cloudflare(input: &str, zone_name: Option<&str>) -> Result<Vec<FileZone>, GoatNsError> {
+    let records = match serde_json::from_str(input).map_err(|err| {
+        GoatNsError::InvalidValue(format!("Failed to parse Cloudflare export: {err:?}"))
+    })? {
+        CloudflareExport::Response { result } => result,
+        CloudflareExport::Records(records) => records,
+    };
+
+    let mut zones: BTreeMap<String, FileZone> = BTreeMap::new();
+    for record in records {
+        let zone = match record.zone_name.as_deref().or(zone_name) {
+            Some(val) => val.trim_end_matches('.').to_string(),
+            None => {
+                return Err(GoatNsError::InvalidValue(format!(
+                    "Record {} doesn't have a zone_name, please specify the zone",
+                    record.name
+                )))
+            }
+        };
+        let name = match relative_name(&record.name, &zone) {
+            Some(val) => val,
+            None => {
+                log::warn!("Skipping {} as it's not in {zone}", record.name);
+                continue;
+            }
+        };
+        if record.proxied {
+            log::warn!(
+                "{} {} is proxied by Cloudflare, importing the origin value",
+                record.name,
+                record.rrtype
+            );
+        }
+        let rdata = match record.priority {
+            Some(priority) if CLOUDFLARE_PRIORITY_TYPES.contains(&record.rrtype.as_str()) => {
+                format!("{priority} {}", record.content)
+            }
+            _ => record.content.clone(),
+        };
+        let ttl = match record.ttl {
+            CLOUDFLARE_AUTO_TTL => CLOUDFLARE_AUTO_TTL_SECONDS,
+            ttl => ttl,
+        };
+        let tags = record
+            .tags
+            .iter()
+            .map(|tag| match tag.split_once(':') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None => (tag.to_string(), String::new()),
+            })
+            .collect();
+
+        zones
+            .entry(zone.clone())
+            .or_insert_with(|| FileZone {
+                name: zone.clone(),
+                rname: rname_default(),
+                ..Default::default()
+            })
+            .records
+            .push(FileZoneRecord {
+                id: None,
+                zoneid: None,
+                name,
+                rrtype: record.rrtype.to_uppercase(),
+                class: RecordClass::Internet,
+                rdata,
+                ttl,
+                comment: record.comment.clone(),
+                tags,
+                expires_at: None,
+            });
+    }
+    Ok(zones.into_values().collect())
+}
+
+/// Export a zone as the body of a Cloudflare DNS records list response
+pub fn to_cloudflare(zone: &FileZone) -> Result<String, GoatNsError> {
+    let result: Vec<CloudflareRecord> = zone
+        .records
+        .iter()
+        .map(|record| {
+            let (priority, content) =
+                match CLOUDFLARE_PRIORITY_TYPES.contains(&record.rrtype.as_str()) {
+                    true => match record.rdata.split_once(' ') {
+                        Some((priority, content)) => match priority.parse::<u16>() {
+                            Ok(priority) => (Some(priority), content.to_string()),
+                            Err(_) => (None, record.rdata.clone()),
+                        },
+                        None => (None, record.rdata.clone()),
+                    },
+                    false => (None, record.rdata.clone()),
+                };
+            CloudflareRecord {
+                id: None,
+                zone_name: Some(zone.name.clone()),
+                name: absolute_name(&record.name, &zone.name),
+                rrtype: record.rrtype.clone(),
+                content,
+                ttl: record.ttl,
+                priority,
+                proxied: false,
+                comment: record.comment.clone(),
+                tags: record
+                    .tags
+                    .iter()
+                    .map(|(key, value)| format!("{key}:{value}"))
+                    .collect(),
+            }
+        })
+        .collect();
+    serde_json::to_string_pretty(&CloudflareExport::Response { result })
+        .map_err(|err| GoatNsError::InvalidValue(format!("Failed to serialize zone: {err:?}")))
+}
+
+/// Route53 escapes some characters in names as `\ddd` octal, eg `\052` for `*`
+fn route53_unescape(name: &str) -> String {
+    let mut res = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let digits: String = chars.clone().take(3).collect();
+            if digits.len() == 3 && digits.chars().all(|d| d.is_ascii_digit()) {
+                if let Ok(value) = u8::from_str_radix(&digits, 8) {
+                    res.push(value as char);
+                    chars.nth(2);
+                    continue;
+                }
+            }
+        }
+        res.push(c);
+    }
+    res
+}
+
+/// Turns `"foo" "bar"` into `foobar`
+fn unquote_txt(value: &str) -> String {
+    let value = value.trim();
+    let inner = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(val) => val,
+        None => return value.to_string(),
+    };
+    inner
+        .split("\" \"")
+        .collect::<String>()
+        .replace("\\\"", "\"")
+}
+
+/// Turns `foobar` into `"foobar"`, splitting it into 255-character strings if needed. Rdata that's already quoted
+/// strings keeps them.
+fn quote_txt(value: &str) -> String {
+    parse_txt_rdata(value)
+        .iter()
+        .flat_map(|string| {
+            let chars: Vec<char> = String::from_utf8_lossy(&string.data).chars().collect();
+            if chars.is_empty() {
+                return vec!["\"\"".to_string()];
+            }
+            chars
+                .chunks(TXT_STRING_MAX)
+                .map(|chunk| {
+                    format!(
+                        "\"{}\"",
+                        chunk.iter().collect::<String>().replace('"', "\\\"")
+                    )
+                })
+                .collect::<Vec<String>>()
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Parse a Route53 `list-resource-record-sets` dump, the zone name is taken from the SOA record if it's not specified.
+pub fn from_route53(input: &str, zone_name: Option<&str>) -> Result<FileZone, GoatNsError> {
+    let export: Route53Export = serde_json::from_str(input).map_err(|err| {
+        GoatNsError::InvalidValue(format!("Failed to parse Route53 export: {err:?}"))
+    })?;
+
+    let soa = export
+        .resource_record_sets
+        .iter()
+        .find(|set| set.rrtype == "SOA");
+
+    let zone_name = match (zone_name, soa) {
+        (Some(name), _) => name.trim_end_matches('.').to_string(),
+        (None, Some(soa)) => route53_unescape(soa.name.trim_end_matches('.')),
+        (None, None) => {
+            return Err(GoatNsError::InvalidValue(
+                "No SOA record in the Route53 export, please specify the zone".to_string(),
+            ))
+        }
+    };
+
+    let mut zone = FileZone {
+        name: zone_name.clone(),
+        rname: rname_default(),
+        ..Default::default()
+    };
+
+    // MNAME RNAME SERIAL REFRESH RETRY EXPIRE MINIMUM
+    if let Some(value) = soa.and_then(|soa| soa.resource_records.first()) {
+        let fields: Vec<&str> = value.value.split_whitespace().collect();
+        if let [_mname, rname, serial, refresh, retry, expire, minimum] = fields.as_slice() {
+            zone.rname = rname.trim_end_matches('.').to_string();
+            zone.serial = serial.parse().unwrap_or_default();
+            zone.refresh = refresh.parse().unwrap_or_default();
+            zone.retry = retry.parse().unwrap_or_default();
+            zone.expire = expire.parse().unwrap_or_default();
+            zone.minimum = minimum.parse().unwrap_or_default();
+        } else {
+            log::warn!("Couldn't parse the SOA record: {}", value.value);
+        }
+    }
+
+    for set in export.resource_record_sets {
+        if set.rrtype == "SOA" {
+            continue;
+        }
+        let set_name = route53_unescape(&set.name);
+        if set.alias_target.is_some() {
+            log::warn!(
+                "Skipping Route53 alias record {} {}, it points at an AWS resource",
+                set_name,
+                set.rrtype
+            );
+            continue;
+        }
+        let name = match relative_name(&set_name, &zone_name) {
+            Some(val) => val,
+            None => {
+                log::warn!("Skipping {set_name} as it's not in {zone_name}");
+                continue;
+            }
+        };
+        let ttl = set.ttl.unwrap_or(ROUTE53_DEFAULT_TTL);
+        for value in set.resource_records {
+            let rdata = if ROUTE53_QUOTED_TYPES.contains(&set.rrtype.as_str()) {
+                unquote_txt(&value.value)
+            } else if ROUTE53_HOSTNAME_TYPES.contains(&set.rrtype.as_str()) {
+                value.value.trim_end_matches('.').to_string()
+            } else {
+                value.value
+            };
+            zone.records.push(FileZoneRecord {
+                id: None,
+                zoneid: None,
+                name: name.clone(),
+                rrtype: set.rrtype.clone(),
+                class: RecordClass::Internet,
+                rdata,
+                ttl,
+                comment: None,
+                tags: Default::default(),
+                expires_at: None,
+            });
+        }
+    }
+    Ok(zone)
+}
+
+/// Export a zone in the same shape as `aws route53 list-resource-record-sets`, records with the same name and type are grouped into sets.
+pub fn to_route53(zone: &FileZone) -> Result<String, GoatNsError> {
+    let mut sets: BTreeMap<(String, String), Route53RecordSet> = BTreeMap::new();
+
+    let apex = format!("{}.", zone.name);
+    let mname = zone
+        .records
+        .iter()
+        .find(|record| record.rrtype == "NS" && (record.name == "@" || record.name.is_empty()))
+        .map(|record| format!("{}.", record.rdata.trim_end_matches('.')))
+        .unwrap_or_else(|| apex.clone());
+    sets.insert(
+        (apex.clone(), "SOA".to_string()),
+        Route53RecordSet {
+            name: apex,
+            rrtype: "SOA".to_string(),
+            ttl: Some(ROUTE53_DEFAULT_TTL),
+            resource_records: vec![Route53Value {
+                value: format!(
+                    "{mname} {}. {} {} {} {} {}",
+                    zone.rname.trim_end_matches('.'),
+                    zone.serial,
+                    zone.refresh,
+                    zone.retry,
+                    zone.expire,
+                    zone.minimum
+                ),
+            }],
+            alias_target: None,
+        },
+    );
+
+    for record in zone.records.iter() {
+        let name = format!("{}.", absolute_name(&record.name, &zone.name));
+        let value = if ROUTE53_QUOTED_TYPES.contains(&record.rrtype.as_str()) {
+            quote_txt(&record.rdata)
+        } else if ROUTE53_HOSTNAME_TYPES.contains(&record.rrtype.as_str()) {
+            format!("{}.", record.rdata.trim_end_matches('.'))
+        } else {
+            record.rdata.clone()
+        };
+        let set = sets
+            .entry((name.clone(), record.rrtype.clone()))
+            .or_insert_with(|| Route53RecordSet {
+                name,
+                rrtype: record.rrtype.clone(),
+                ttl: Some(record.ttl),
+                resource_records: vec![],
+                alias_target: None,
+            });
+        if set.ttl != Some(record.ttl) {
+            log::warn!(
+                "Route53 needs one TTL per record set, using {:?} for {} {}",
+                set.ttl,
+                set.name,
+                set.rrtype
+            );
+        }
+        set.resource_records.push(Route53Value { value });
+    }
+
+    serde_json::to_string_pretty(&Route53Export {
+        resource_record_sets: sets.into_values().collect(),
+    })
+    .map_err(|err| GoatNsError::InvalidValue(format!("Failed to serialize zone: {err:?}")))
+}
+
+/// Parse a BIND zone file, the zone name is taken from the SOA record if it's not specified. The SOA's values go in the
+/// zone's fields rather than becoming a record, apart from the mname, which comes from the apex NS records when the
+/// zone's served.
+pub fn from_bind(input: &str, zone_name: Option<&str>) -> Result<FileZone, GoatNsError> {
+    let zone_name = match zone_name {
+        Some(name) => name.trim_end_matches('.').to_string(),
+        None => soa_owner(input)?.ok_or_else(|| {
+            GoatNsError::InvalidValue(
+                "No SOA record in the zone file, please specify the zone".to_string(),
+            )
+        })?,
+    };
+    let mut records = parse_records(input, &zone_name)?;
+
+    let mut zone = FileZone {
+        name: zone_name.clone(),
+        rname: rname_default(),
+        default_ttl: ttl_directive(input)?,
+        ..Default::default()
+    };
+    if let Some(index) = records
+        .iter()
+        .position(|record| record.rrtype == "SOA" && record.name == "@")
+    {
+        let soa = Soa::from_str(&records.remove(index).rdata)?;
+        zone.rname = soa.rname;
+        zone.serial = soa.serial;
+        zone.refresh = soa.refresh;
+        zone.retry = soa.retry;
+        zone.expire = soa.expire;
+        zone.minimum = soa.minimum;
+    }
+    // TXT records that are one string are kept the way they'd be typed in, without the quotes
+    for record in records
+        .iter_mut()
+        .filter(|record| ROUTE53_QUOTED_TYPES.contains(&record.rrtype.as_str()))
+    {
+        if let [string] = parse_txt_rdata(&record.rdata).as_slice() {
+            record.rdata = String::from_utf8_lossy(&string.data).to_string();
+        }
+    }
+    records.retain(|record| {
+        if record.rrtype == "SOA" {
+            log::warn!(
+                "Skipping SOA record for {} in {zone_name}, only the apex can have one",
+                record.name
+            );
+        }
+        record.rrtype != "SOA"
+    });
+    zone.records = records;
+    Ok(zone)
+}
+
+/// Export a zone as a BIND zone file, with the SOA made from the zone's fields and the first apex NS as the mname
+pub fn to_bind(zone: &FileZone) -> Result<String, GoatNsError> {
+    let apex = zone.name.trim_end_matches('.');
+    let mname = zone
+        .records
+        .iter()
+        .find(|record| record.rrtype == "NS" && (record.name == "@" || record.name.is_empty()))
+        .map(|record| record.rdata.trim_end_matches('.'))
+        .unwrap_or(apex);
+    let soa = Soa {
+        mname: format!("{mname}."),
+        // rnames are mailboxes, the first @ becomes a dot
+        rname: format!(
+            "{}.",
+            zone.rname.replacen('@', ".", 1).trim_end_matches('.')
+        ),
+        serial: zone.serial,
+        refresh: zone.refresh,
+        retry: zone.retry,
+        expire: zone.expire,
+        minimum: zone.minimum,
+    };
+    let mut res = format!("$ORIGIN {apex}.\n");
+    if let Some(ttl) = zone.default_ttl {
+        res.push_str(&format!("$TTL {ttl}\n"));
+    }
+    res.push_str(&format!("@ {} IN SOA {soa}\n", zone.minimum));
+    for record in zone.records.iter() {
+        let name = match record.name.as_str() {
+            "" => "@",
+            name => name,
+        };
+        let rdata = match ROUTE53_QUOTED_TYPES.contains(&record.rrtype.as_str()) {
+            true => quote_txt(&record.rdata),
+            false => qualified_rdata(&record.rrtype, &record.rdata),
+        };
+        res.push_str(&format!(
+            "{name} {} {} {} {rdata}\n",
+            record.ttl, record.class, record.rrtype
+        ));
+    }
+    Ok(res)
+}
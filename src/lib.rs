@@ -25,35 +25,65 @@ extern crate lazy_static;
 
 use crate::enums::*;
 use crate::utils::*;
+use bytes::{BufMut, BytesMut};
 use error::GoatNsError;
 use log::trace;
 use packed_struct::prelude::*;
 use std::fmt::{Debug, Display};
 use std::str::from_utf8;
 
+pub mod acme;
+pub mod alias;
+pub mod backup;
+pub mod builtin;
 pub mod cli;
 /// Configuration handling for the server
 pub mod config;
 /// The data-storing backend for zone information and (eventually) caching.
 pub mod datastore;
 pub mod db;
+pub mod delegation;
+pub mod doctor;
+pub mod edns;
 pub mod enums;
 pub mod error;
+#[cfg(feature = "fault-injection")]
+pub mod fault_injection;
+pub mod forward;
+pub mod generic;
+pub mod health;
+pub mod host_overrides;
+pub mod import_source;
+pub mod interop;
+pub mod loadtest;
+pub mod log_sinks;
 pub(crate) mod logging;
+pub mod metrics;
+pub mod name_policy;
 pub mod packet_dumper;
+#[cfg(feature = "response-policy")]
+pub mod policy;
+pub mod proxy;
+pub mod query_trace;
 pub mod reply;
 pub mod resourcerecord;
 pub mod serializers;
 pub mod servers;
+pub mod sync;
 #[cfg(test)]
 mod tests;
 pub mod utils;
+pub mod webhook;
+pub mod wizards;
 /// Configuration and management API
 #[macro_use]
 pub mod web;
+pub mod zone_events;
+pub mod zone_schema;
+pub mod zonefile;
 pub mod zones;
 
-/// Internal limit of in-flight requests
+/// Internal limit of in-flight requests, both queries being handled and requests queued for the datastore
 pub const MAX_IN_FLIGHT: usize = 512;
 /// The size of a DNS request header
 pub const HEADER_BYTES: usize = 12;
@@ -134,6 +164,29 @@ impl Default for Header {
 }
 
 impl Header {
+    /// A query header with the given ID, for building queries (or replies with [crate::reply::ReplyBuilder]) by hand
+    pub fn new(id: u16) -> Self {
+        Self {
+            id,
+            ..Default::default()
+        }
+    }
+
+    /// The query ID
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// The response code
+    pub fn rcode(&self) -> Rcode {
+        self.rcode
+    }
+
+    /// Is the answer authoritative?
+    pub fn authoritative(&self) -> bool {
+        self.authoritative
+    }
+
     pub fn as_answer(self) -> Header {
         let mut response = self;
         response.qr = PacketType::Answer;
@@ -161,7 +214,58 @@ pub struct ResourceRecord {
     /// For example, the if the TYPE is A and the CLASS is IN, the RDATA field is a 4 octet ARPA Internet address.
     pub rdata: Vec<u8>,
 }
-impl ResourceRecord {}
+impl ResourceRecord {
+    /// Writes the wire format of an answer record straight into the reply buffer, without building a [ResourceRecord] first
+    pub fn write_record(
+        buf: &mut BytesMut,
+        name: &[u8],
+        record_type: u16,
+        class: RecordClass,
+        ttl: u32,
+        rdata: &[u8],
+    ) -> Result<(), GoatNsError> {
+        let record_name_bytes = name_as_bytes(name, Some(HEADER_BYTES as u16), None)?;
+        Self::write_record_with_name(buf, &record_name_bytes, record_type, class, ttl, rdata)
+    }
+
+    /// Like [ResourceRecord::write_record], but with the owner name already in wire format, for records that aren't named after the question
+    pub fn write_record_with_name(
+        buf: &mut BytesMut,
+        record_name_bytes: &[u8],
+        record_type: u16,
+        class: RecordClass,
+        ttl: u32,
+        rdata: &[u8],
+    ) -> Result<(), GoatNsError> {
+        buf.reserve(record_name_bytes.len() + 10 + rdata.len());
+        buf.put_slice(record_name_bytes);
+        // type
+        buf.put_u16(record_type);
+        // class
+        buf.put_u16(class as u16);
+        // reply ttl
+        buf.put_u32(ttl);
+        #[allow(clippy::cast_possible_truncation)]
+        // reply data length
+        buf.put_u16(rdata.len() as u16);
+        // rdata
+        buf.put_slice(rdata);
+        Ok(())
+    }
+
+    /// Writes this record's wire format into the buffer
+    pub fn write_to(&self, buf: &mut BytesMut) -> Result<(), GoatNsError> {
+        trace!("{:?}", self);
+        Self::write_record(
+            buf,
+            &self.name,
+            self.record_type as u16,
+            self.class,
+            self.ttl,
+            &self.rdata,
+        )
+    }
+}
 
 impl TryFrom<ResourceRecord> for Vec<u8> {
     type Error = GoatNsError;
@@ -173,27 +277,9 @@ impl TryFrom<ResourceRecord> for Vec<u8> {
 impl TryFrom<&ResourceRecord> for Vec<u8> {
     type Error = GoatNsError;
     fn try_from(record: &ResourceRecord) -> Result<Self, Self::Error> {
-        let mut retval: Vec<u8> = vec![];
-
-        trace!("{:?}", record);
-
-        let record_name_bytes = name_as_bytes(&record.name, Some(HEADER_BYTES as u16), None)?;
-        retval.extend(record_name_bytes);
-        // type
-        retval.extend((record.record_type as u16).to_be_bytes());
-        // class
-        retval.extend((record.class as u16).to_be_bytes());
-        // reply ttl
-        let ttl_bytes: [u8; 4] = record.ttl.to_be_bytes();
-        trace!("ttl_bytes: {:?}", ttl_bytes);
-        retval.extend(ttl_bytes);
-        #[allow(clippy::cast_possible_truncation)]
-        // reply data length
-        retval.extend((record.rdata.len() as u16).to_be_bytes());
-        // rdata
-        retval.extend(record.rdata.clone());
-
-        Ok(retval)
+        let mut retval = BytesMut::new();
+        record.write_to(&mut retval)?;
+        Ok(retval.into())
     }
 }
 
@@ -204,6 +290,8 @@ pub struct Question {
     qname: Vec<u8>,
     /// The Record type that is being requested, eg A, NS, MX, TXT etc.
     qtype: RecordType,
+    /// The type code from the query, so types we don't know about (RFC3597) can still be looked up and echoed back
+    qtype_code: u16,
     /// The class, (typically IN for "Internet")
     qclass: RecordClass,
 }
@@ -217,8 +305,10 @@ impl Display for Question {
             }
         };
         f.write_fmt(format_args!(
-            "QNAME={} QTYPE={:?} QCLASS={}",
-            qname, self.qtype, self.qclass,
+            "QNAME={} QTYPE={} QCLASS={}",
+            qname,
+            crate::generic::type_name(self.qtype_code),
+            self.qclass,
         ))
     }
 }
@@ -272,25 +362,50 @@ pub fn get_question_qname(input_val: &[u8]) -> Result<Vec<u8>, String> {
         );
         buf = buf[label_len + 1..buf.len()].to_vec();
         trace!("After slicing buf:  {buf:?}");
-        if buf[0] != 0 {
+        if buf.first().is_some_and(|next| *next != 0) {
             result.push(46);
         }
-        if result.len() > 255 {
+        // 255 on the wire is 253 once the label lengths are dots and there's no trailing null
+        if result.len() > 253 {
             return Err(format!(
                 "qname length over 255 while parsing question: {result:?}"
             ));
         }
     }
-    let result_string = match from_utf8(&result) {
-        Ok(value) => value.to_owned().to_lowercase(),
-        Err(error) => return Err(format!("{error:?}")),
+    if buf.is_empty() {
+        return Err(format!(
+            "qname wasn't terminated while parsing {input_val:?}"
+        ));
+    }
+    // the case is kept so the question goes back the way it came in, lookups use [Question::lookup_name]
+    if let Err(error) = from_utf8(&result) {
+        return Err(format!("{error:?}"));
     };
     #[cfg(test)]
-    eprintln!("Returning from get_question_qname {result_string:?}");
-    Ok(result_string.as_bytes().to_vec())
+    eprintln!("Returning from get_question_qname {:?}", from_utf8(&result));
+    Ok(result)
 }
 
 impl Question {
+    pub fn new(qname: Vec<u8>, qtype: RecordType, qclass: RecordClass) -> Self {
+        Self {
+            qname,
+            qtype,
+            qtype_code: qtype as u16,
+            qclass,
+        }
+    }
+
+    /// Builds a question from the type code, which doesn't have to be one we know about
+    pub fn with_type_code(qname: Vec<u8>, qtype_code: u16, qclass: RecordClass) -> Self {
+        Self {
+            qname,
+            qtype: RecordType::from(&qtype_code),
+            qtype_code,
+            qclass,
+        }
+    }
+
     fn normalized_name(&self) -> Result<String, String> {
         match from_utf8(&self.qname) {
             Ok(value) => Ok(value.to_lowercase()),
@@ -301,12 +416,30 @@ impl Question {
         }
     }
 
+    /// The name to look things up with, the same way names are stored - see [name_policy::normalise]. Anything that
+    /// isn't UTF-8 is just lowercased.
+    pub fn lookup_name(&self) -> Vec<u8> {
+        match std::str::from_utf8(&self.qname) {
+            Ok(name) => name_policy::normalise(name).into_bytes(),
+            Err(_) => {
+                let mut name = self.qname.to_ascii_lowercase();
+                if name.last() == Some(&b'.') {
+                    name.pop();
+                }
+                name
+            }
+        }
+    }
+
     /// hand it the buffer and the things, and get back a [Question]
-    fn from_packets(buf: &[u8]) -> Result<Self, String> {
+    pub fn from_packets(buf: &[u8]) -> Result<Self, String> {
         let qname = get_question_qname(buf)?;
 
-        // skip past the end of the question
-        let read_pointer = qname.len() + 2;
+        // skip past the end of the name, the root is just the null
+        let read_pointer = match qname.is_empty() {
+            true => 1,
+            false => qname.len() + 2,
+        };
         if buf.len() <= read_pointer + 1 {
             return Err(format!(
                 "Packet not long enough, looked for {read_pointer}, got {}",
@@ -321,7 +454,7 @@ impl Question {
             );
         }
         qtype_bytes.copy_from_slice(&buf[read_pointer..read_pointer + 2]);
-        let qtype = RecordType::from(&u16::from_be_bytes(qtype_bytes));
+        let qtype_code = u16::from_be_bytes(qtype_bytes);
         let mut qclass_bytes: [u8; 2] = [0; 2];
         if buf.len() <= read_pointer + 3 {
             return Err("Buffer length too short to get two bytes when I asked for it from the header for the QCLASS"
@@ -336,22 +469,24 @@ impl Question {
         qclass_bytes.copy_from_slice(&buf[read_pointer + 2..read_pointer + 4]);
         let qclass: RecordClass = RecordClass::from(&u16::from_be_bytes(qclass_bytes));
 
-        Ok(Question {
-            qname,
-            qtype,
-            qclass,
-        })
+        Ok(Question::with_type_code(qname, qtype_code, qclass))
     }
 
     /// turn a question into a vec of bytes to send back to the user
     fn try_to_bytes(&self) -> Result<Vec<u8>, GoatNsError> {
-        let mut retval: Vec<u8> = vec![];
+        let mut retval = BytesMut::new();
+        self.write_to(&mut retval)?;
+        Ok(retval.into())
+    }
 
+    /// Writes the question's wire format into the buffer
+    pub fn write_to(&self, buf: &mut BytesMut) -> Result<(), GoatNsError> {
         let name_bytes = name_as_bytes(&self.qname, None, None)?;
-        retval.extend(name_bytes);
-        retval.extend((self.qtype as u16).to_be_bytes());
-        retval.extend((self.qclass as u16).to_be_bytes());
-        Ok(retval)
+        buf.reserve(name_bytes.len() + 4);
+        buf.put_slice(&name_bytes);
+        buf.put_u16(self.qtype_code);
+        buf.put_u16(self.qclass as u16);
+        Ok(())
     }
 }
 
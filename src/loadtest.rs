@@ -0,0 +1,418 @@
+//! Floods a DNS server with UDP queries and reports how it went, for `goatns --loadtest`
+//!
+//! Queries go out at a steady rate from a single socket, picking names from the mix in proportion to their
+//! weights. Anything not answered within the timeout counts as a timeout, anything that doesn't come back as
+//! NOERROR or NXDOMAIN counts as an error. Latencies are only from the answered queries.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use packed_struct::prelude::*;
+use tokio::net::UdpSocket;
+
+use crate::enums::{Rcode, RecordClass, RecordType};
+use crate::error::GoatNsError;
+use crate::{Header, Question, HEADER_BYTES, UDP_BUFFER_SIZE};
+
+/// What gets queried if you don't say otherwise
+pub const DEFAULT_NAME_MIX: &str = "example.com:A";
+
+/// A name to query, and how often compared to the others
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeightedQuestion {
+    pub name: String,
+    pub rrtype: RecordType,
+    pub weight: u32,
+}
+
+/// Parses a mix like `example.com:A:3,www.example.com:AAAA`, the type defaults to A and the weight to 1
+pub fn parse_name_mix(input: &str) -> Result<Vec<WeightedQuestion>, GoatNsError> {
+    let mut mix = vec![];
+    for entry in input.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut parts = entry.split(':');
+        let name = parts.next().unwrap_or_default().trim_end_matches('.');
+        if name.is_empty() {
+            return Err(GoatNsError::InvalidValue(format!("No name in {entry:?}")));
+        }
+        let rrtype = match parts.next() {
+            None => RecordType::A,
+            Some(rrtype) => match RecordType::from(rrtype.to_uppercase().as_str()) {
+                RecordType::InvalidType => {
+                    return Err(GoatNsError::InvalidValue(format!(
+                        "Unknown record type {rrtype:?} in {entry:?}"
+                    )))
+                }
+                rrtype => rrtype,
+            },
+        };
+        let weight = match parts.next() {
+            None => 1,
+            Some(weight) => weight.parse::<u32>().map_err(|err| {
+                GoatNsError::InvalidValue(format!("Bad weight in {entry:?}: {err:?}"))
+            })?,
+        };
+        if parts.next().is_some() {
+            return Err(GoatNsError::InvalidValue(format!(
+                "Too many parts in {entry:?}, should be name:type:weight"
+            )));
+        }
+        if weight > 0 {
+            mix.push(WeightedQuestion {
+                name: name.to_string(),
+                rrtype,
+                weight,
+            });
+        }
+    }
+    if mix.is_empty() {
+        return Err(GoatNsError::InvalidValue(
+            "Need at least one name to query".to_string(),
+        ));
+    }
+    Ok(mix)
+}
+
+/// Builds the query packet
+pub fn build_query(id: u16, name: &str, rrtype: RecordType) -> Result<Vec<u8>, GoatNsError> {
+    let header = Header {
+        id,
+        authoritative: false,
+        qdcount: 1,
+        ..Default::default()
+    }
+    .pack()
+    .map_err(|err| GoatNsError::Generic(format!("Failed to pack header: {err:?}")))?;
+    let mut buf = BytesMut::with_capacity(UDP_BUFFER_SIZE);
+    buf.extend_from_slice(&header);
+    Question::new(name.as_bytes().to_vec(), rrtype, RecordClass::Internet).write_to(&mut buf)?;
+    Ok(buf.to_vec())
+}
+
+/// The value at `pct` (0-100) in an already sorted list, nearest-rank
+pub fn percentile(sorted: &[Duration], pct: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.clamp(1, sorted.len()) - 1).copied()
+}
+
+/// How to run the load test
+#[derive(Clone, Debug)]
+pub struct LoadTestOptions {
+    pub target: SocketAddr,
+    /// Queries per second to send
+    pub qps: u32,
+    pub duration: Duration,
+    pub names: Vec<WeightedQuestion>,
+    /// How long to wait for an answer before it's a timeout
+    pub timeout: Duration,
+    /// Fail the run if p99 latency's over this
+    pub max_p99: Option<Duration>,
+    /// Fail the run if more than this fraction of queries time out or error
+    pub max_error_rate: Option<f64>,
+}
+
+impl Default for LoadTestOptions {
+    fn default() -> Self {
+        Self {
+            target: SocketAddr::from(([127, 0, 0, 1], 15353)),
+            qps: 1000,
+            duration: Duration::from_secs(10),
+            names: vec![WeightedQuestion {
+                name: "example.com".to_string(),
+                rrtype: RecordType::A,
+                weight: 1,
+            }],
+            timeout: Duration::from_secs(2),
+            max_p99: None,
+            max_error_rate: None,
+        }
+    }
+}
+
+/// How the load test went
+#[derive(Clone, Debug, Default)]
+pub struct LoadTestReport {
+    pub sent: u64,
+    pub answered: u64,
+    pub timeouts: u64,
+    /// Answers by response code
+    pub rcodes: BTreeMap<String, u64>,
+    /// Answers we couldn't parse, didn't match anything we sent or turned up after the timeout
+    pub bad_replies: u64,
+    pub p50: Option<Duration>,
+    pub p99: Option<Duration>,
+    pub max: Option<Duration>,
+    pub elapsed: Duration,
+}
+
+impl LoadTestReport {
+    /// Answers that weren't NOERROR or NXDOMAIN
+    pub fn error_answers(&self) -> u64 {
+        self.rcodes
+            .iter()
+            .filter(|(rcode, _)| {
+                ![
+                    format!("{:?}", Rcode::NoError),
+                    format!("{:?}", Rcode::NameError),
+                ]
+                .contains(rcode)
+            })
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Timeouts and errors, as a fraction of what was sent
+    pub fn error_rate(&self) -> f64 {
+        match self.sent {
+            0 => 0.0,
+            sent => (self.timeouts + self.error_answers() + self.bad_replies) as f64 / sent as f64,
+        }
+    }
+
+    /// What was actually sent per second
+    pub fn achieved_qps(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.sent as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    /// Checks the results against the limits, returning what went over
+    pub fn check(&self, options: &LoadTestOptions) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+        if let Some(max_p99) = options.max_p99 {
+            match self.p99 {
+                Some(p99) if p99 > max_p99 => errors.push(format!(
+                    "p99 latency {:.3}ms is over the limit of {:.3}ms",
+                    p99.as_secs_f64() * 1000.0,
+                    max_p99.as_secs_f64() * 1000.0
+                )),
+                None => errors.push("Nothing was answered, so there's no p99".to_string()),
+                _ => {}
+            }
+        }
+        if let Some(max_error_rate) = options.max_error_rate {
+            if self.error_rate() > max_error_rate {
+                errors.push(format!(
+                    "Error rate {:.4} is over the limit of {max_error_rate:.4}",
+                    self.error_rate()
+                ));
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+}
+
+fn format_latency(value: Option<Duration>) -> String {
+    match value {
+        Some(value) => format!("{:.3}ms", value.as_secs_f64() * 1000.0),
+        None => "-".to_string(),
+    }
+}
+
+impl Display for LoadTestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "sent={} answered={} timeouts={} bad_replies={} elapsed={:.2}s qps={:.1}",
+            self.sent,
+            self.answered,
+            self.timeouts,
+            self.bad_replies,
+            self.elapsed.as_secs_f64(),
+            self.achieved_qps()
+        )?;
+        writeln!(
+            f,
+            "latency p50={} p99={} max={}",
+            format_latency(self.p50),
+            format_latency(self.p99),
+            format_latency(self.max)
+        )?;
+        let rcodes: Vec<String> = self
+            .rcodes
+            .iter()
+            .map(|(rcode, count)| format!("{rcode}={count}"))
+            .collect();
+        write!(
+            f,
+            "rcodes {} error_rate={:.4}",
+            rcodes.join(" "),
+            self.error_rate()
+        )
+    }
+}
+
+/// Picks names in proportion to their weights, going round in order so runs are repeatable
+fn weighted_schedule(names: &[WeightedQuestion]) -> Vec<&WeightedQuestion> {
+    names
+        .iter()
+        .flat_map(|question| std::iter::repeat(question).take(question.weight as usize))
+        .collect()
+}
+
+/// Runs the load test, see the module docs
+pub async fn run(options: &LoadTestOptions) -> Result<LoadTestReport, GoatNsError> {
+    if options.qps == 0 {
+        return Err(GoatNsError::InvalidValue(
+            "qps needs to be more than zero".to_string(),
+        ));
+    }
+    let bind_addr: SocketAddr = match options.target {
+        SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+        SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+    };
+    let socket = UdpSocket::bind(bind_addr)
+        .await
+        .map_err(|err| GoatNsError::StartupError(format!("Failed to bind socket: {err:?}")))?;
+    socket.connect(options.target).await.map_err(|err| {
+        GoatNsError::StartupError(format!("Failed to connect to {}: {err:?}", options.target))
+    })?;
+    let socket = Arc::new(socket);
+
+    let schedule = weighted_schedule(&options.names);
+    // pre-build the packets, so we're testing the server and not ourselves
+    let packets = schedule
+        .iter()
+        .map(|question| build_query(0, &question.name, question.rrtype))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // query id -> when it was sent
+    let outstanding: Arc<Mutex<HashMap<u16, Instant>>> = Arc::default();
+    let report: Arc<Mutex<LoadTestReport>> = Arc::default();
+    let latencies: Arc<Mutex<Vec<Duration>>> = Arc::default();
+
+    let receiver = {
+        let socket = socket.clone();
+        let outstanding = outstanding.clone();
+        let report = report.clone();
+        let latencies = latencies.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; UDP_BUFFER_SIZE];
+            loop {
+                let len = match socket.recv(&mut buf).await {
+                    Ok(len) => len,
+                    // ICMP unreachable and the like show up here, the timeouts will catch them
+                    Err(err) => {
+                        log::debug!("Failed to receive reply: {err:?}");
+                        continue;
+                    }
+                };
+                let received = Instant::now();
+                let header = buf
+                    .get(0..HEADER_BYTES)
+                    .and_then(|bytes| <[u8; HEADER_BYTES]>::try_from(bytes).ok())
+                    .and_then(|bytes| Header::unpack(&bytes).ok());
+                let sent = header.as_ref().and_then(|header| {
+                    outstanding
+                        .lock()
+                        .ok()
+                        .and_then(|mut outstanding| outstanding.remove(&header.id()))
+                });
+                let Ok(mut report) = report.lock() else {
+                    break;
+                };
+                match (header, sent) {
+                    (Some(header), Some(sent)) => {
+                        report.answered += 1;
+                        *report
+                            .rcodes
+                            .entry(format!("{:?}", header.rcode()))
+                            .or_default() += 1;
+                        if let Ok(mut latencies) = latencies.lock() {
+                            latencies.push(received.duration_since(sent));
+                        }
+                    }
+                    _ => report.bad_replies += 1,
+                }
+            }
+        })
+    };
+
+    let started = Instant::now();
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / f64::from(options.qps)));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Burst);
+    let mut next_id: u16 = 0;
+    let mut sent: u64 = 0;
+    while started.elapsed() < options.duration {
+        interval.tick().await;
+        let mut packet = packets[(sent % packets.len() as u64) as usize].clone();
+        // skip ids that are still waiting on an answer
+        let now = Instant::now();
+        {
+            let mut outstanding = outstanding
+                .lock()
+                .map_err(|err| GoatNsError::Generic(format!("Lock poisoned: {err:?}")))?;
+            let mut tries = 0;
+            while outstanding.contains_key(&next_id) && tries < u16::MAX {
+                next_id = next_id.wrapping_add(1);
+                tries += 1;
+            }
+            outstanding.insert(next_id, now);
+        }
+        packet[0..2].copy_from_slice(&next_id.to_be_bytes());
+        next_id = next_id.wrapping_add(1);
+        if let Err(err) = socket.send(&packet).await {
+            log::debug!("Failed to send query: {err:?}");
+        }
+        sent += 1;
+        if sent % u64::from(options.qps) == 0 {
+            expire(&outstanding, &report, options.timeout)?;
+        }
+    }
+    let send_elapsed = started.elapsed();
+
+    // give the stragglers a chance
+    let deadline = Instant::now() + options.timeout;
+    while Instant::now() < deadline {
+        if outstanding.lock().map(|o| o.is_empty()).unwrap_or(true) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    receiver.abort();
+    expire(&outstanding, &report, Duration::ZERO)?;
+
+    let mut report = report
+        .lock()
+        .map_err(|err| GoatNsError::Generic(format!("Lock poisoned: {err:?}")))?
+        .clone();
+    let mut latencies = latencies
+        .lock()
+        .map_err(|err| GoatNsError::Generic(format!("Lock poisoned: {err:?}")))?
+        .clone();
+    latencies.sort();
+    report.sent = sent;
+    report.elapsed = send_elapsed;
+    report.p50 = percentile(&latencies, 50.0);
+    report.p99 = percentile(&latencies, 99.0);
+    report.max = latencies.last().copied();
+    Ok(report)
+}
+
+/// Anything that's been waiting longer than `timeout` is a timeout
+fn expire(
+    outstanding: &Mutex<HashMap<u16, Instant>>,
+    report: &Mutex<LoadTestReport>,
+    timeout: Duration,
+) -> Result<(), GoatNsError> {
+    let mut outstanding = outstanding
+        .lock()
+        .map_err(|err| GoatNsError::Generic(format!("Lock poisoned: {err:?}")))?;
+    let before = outstanding.len();
+    outstanding.retain(|_, sent| sent.elapsed() < timeout);
+    report
+        .lock()
+        .map_err(|err| GoatNsError::Generic(format!("Lock poisoned: {err:?}")))?
+        .timeouts += (before - outstanding.len()) as u64;
+    Ok(())
+}
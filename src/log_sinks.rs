@@ -0,0 +1,417 @@
+//! Extra places to send logs besides the console, see [crate::config::LoggingConfig]
+//!
+//! - syslog, as [RFC5424](https://www.rfc-editor.org/rfc/rfc5424) messages over a unix socket, UDP or TCP (framed
+//!   with octet counting from [RFC6587](https://www.rfc-editor.org/rfc/rfc6587#section-3.4.1)). Structured fields
+//!   go in the STRUCTURED-DATA.
+//! - the systemd journal, using its [native protocol](https://systemd.io/JOURNAL_NATIVE_PROTOCOL/) so structured
+//!   fields become journal fields you can filter on, eg `journalctl QTYPE=AAAA`.
+//!
+//! Structured fields come from the `log` macros' key-values, like the query log does with `qname` and `qtype`.
+
+use std::io::{Error, ErrorKind, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use flexi_logger::writers::LogWriter;
+use flexi_logger::DeferredNow;
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, Record};
+
+use crate::config::{LoggingConfig, SyslogConfig, SyslogTransport};
+
+/// The target query log lines are logged with
+pub const QUERY_LOG_TARGET: &str = "goatns::query";
+/// Where journald listens for native protocol messages
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+/// The SD-ID structured fields go under. 32473 is the example enterprise number from
+/// [RFC5612](https://www.rfc-editor.org/rfc/rfc5612), GoatNS doesn't have one of its own.
+pub const SYSLOG_SD_ID: &str = "goatns@32473";
+/// The name journal entries are tagged with
+pub const JOURNALD_IDENTIFIER: &str = "goatns";
+
+const SYSLOG_TIMEOUT: Duration = Duration::from_secs(1);
+
+static LOG_QUERIES: AtomicBool = AtomicBool::new(false);
+
+/// Turn the query log on or off
+pub fn set_log_queries(enabled: bool) {
+    LOG_QUERIES.store(enabled, Ordering::Relaxed);
+}
+
+/// If every query should be logged
+pub fn log_queries() -> bool {
+    LOG_QUERIES.load(Ordering::Relaxed)
+}
+
+/// The syslog facilities from RFC5424 section 6.2.1, by the names syslog.conf uses
+pub fn facility_code(name: &str) -> Option<u8> {
+    let code = match name.to_ascii_lowercase().as_str() {
+        "kern" => 0,
+        "user" => 1,
+        "mail" => 2,
+        "daemon" => 3,
+        "auth" => 4,
+        "syslog" => 5,
+        "lpr" => 6,
+        "news" => 7,
+        "uucp" => 8,
+        "cron" => 9,
+        "authpriv" => 10,
+        "ftp" => 11,
+        "local0" => 16,
+        "local1" => 17,
+        "local2" => 18,
+        "local3" => 19,
+        "local4" => 20,
+        "local5" => 21,
+        "local6" => 22,
+        "local7" => 23,
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// The syslog severity for a log level, which journald uses for PRIORITY too
+pub fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        self.0.push((key.as_str().to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+/// The record's key-values, in the order they were logged
+pub fn fields(record: &Record) -> Vec<(String, String)> {
+    let mut fields = Fields(vec![]);
+    if let Err(err) = record.key_values().visit(&mut fields) {
+        eprintln!("Failed to read the fields of a log record: {err:?}");
+    }
+    fields.0
+}
+
+/// Replaces anything that can't be in a syslog header field, which is printable ASCII without spaces
+fn header_field(value: &str, max_len: usize) -> String {
+    match value.is_empty() {
+        true => "-".to_string(),
+        false => value
+            .chars()
+            .take(max_len)
+            .map(|c| match c.is_ascii_graphic() {
+                true => c,
+                false => '_',
+            })
+            .collect(),
+    }
+}
+
+/// The record as an RFC5424 message, eg
+/// `<30>1 2024-01-01T00:00:00.000000Z ns1.example.com goatns 1234 query [goatns@32473 qname="example.com"] Query`
+pub fn rfc5424(
+    record: &Record,
+    when: DateTime<Utc>,
+    hostname: &str,
+    app_name: &str,
+    facility: u8,
+) -> String {
+    let msgid = match record.target() {
+        QUERY_LOG_TARGET => "query",
+        _ => "-",
+    };
+    let fields = fields(record);
+    let structured_data = match fields.is_empty() {
+        true => "-".to_string(),
+        false => {
+            let params: Vec<String> = fields
+                .iter()
+                .map(|(key, value)| {
+                    let key: String = key
+                        .chars()
+                        .filter(|c| c.is_ascii_graphic() && !['=', ']', '"'].contains(c))
+                        .take(32)
+                        .collect();
+                    let value = value
+                        .replace('\\', "\\\\")
+                        .replace('"', "\\\"")
+                        .replace(']', "\\]");
+                    format!(" {key}=\"{value}\"")
+                })
+                .collect();
+            format!("[{SYSLOG_SD_ID}{}]", params.concat())
+        }
+    };
+    format!(
+        "<{}>1 {} {} {} {} {msgid} {structured_data} {}",
+        u16::from(facility) * 8 + u16::from(severity(record.level())),
+        when.to_rfc3339_opts(SecondsFormat::Micros, true),
+        header_field(hostname, 255),
+        header_field(app_name, 48),
+        std::process::id(),
+        record.args()
+    )
+}
+
+/// Journal field names are upper case letters, numbers and underscores, and can't start with an underscore
+fn journal_field_name(key: &str) -> String {
+    key.to_ascii_uppercase()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect::<String>()
+        .trim_start_matches('_')
+        .chars()
+        .take(64)
+        .collect()
+}
+
+fn journal_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    // values with newlines in them need the length-prefixed form
+    match value.contains('\n') {
+        true => {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        }
+        false => entry.push(b'='),
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// The record as a journald native protocol datagram
+pub fn journal_entry(record: &Record, identifier: &str) -> Vec<u8> {
+    let mut entry = vec![];
+    journal_field(&mut entry, "MESSAGE", &record.args().to_string());
+    journal_field(
+        &mut entry,
+        "PRIORITY",
+        &severity(record.level()).to_string(),
+    );
+    journal_field(&mut entry, "SYSLOG_IDENTIFIER", identifier);
+    journal_field(&mut entry, "TARGET", record.target());
+    if let Some(file) = record.file() {
+        journal_field(&mut entry, "CODE_FILE", file);
+    }
+    if let Some(line) = record.line() {
+        journal_field(&mut entry, "CODE_LINE", &line.to_string());
+    }
+    for (key, value) in fields(record) {
+        let name = journal_field_name(&key);
+        if !name.is_empty() {
+            journal_field(&mut entry, &name, &value);
+        }
+    }
+    entry
+}
+
+enum SyslogSocket {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixDatagram),
+}
+
+impl SyslogSocket {
+    fn connect(config: &SyslogConfig) -> Result<Self, Error> {
+        match config.transport {
+            SyslogTransport::Udp => {
+                let socket = UdpSocket::bind(match config.address.starts_with('[') {
+                    true => "[::]:0",
+                    false => "0.0.0.0:0",
+                })?;
+                socket.connect(&config.address)?;
+                Ok(Self::Udp(socket))
+            }
+            SyslogTransport::Tcp => {
+                let stream = TcpStream::connect(&config.address)?;
+                stream.set_write_timeout(Some(SYSLOG_TIMEOUT))?;
+                Ok(Self::Tcp(stream))
+            }
+            #[cfg(unix)]
+            SyslogTransport::Unix => {
+                let socket = std::os::unix::net::UnixDatagram::unbound()?;
+                socket.connect(&config.address)?;
+                Ok(Self::Unix(socket))
+            }
+            #[cfg(not(unix))]
+            SyslogTransport::Unix => Err(Error::new(
+                ErrorKind::Unsupported,
+                "Unix sockets aren't supported on this platform",
+            )),
+        }
+    }
+
+    fn send(&mut self, message: &str) -> Result<(), Error> {
+        match self {
+            Self::Udp(socket) => socket.send(message.as_bytes()).map(|_| ()),
+            Self::Tcp(stream) => {
+                stream.write_all(format!("{} {message}", message.len()).as_bytes())
+            }
+            #[cfg(unix)]
+            Self::Unix(socket) => socket.send(message.as_bytes()).map(|_| ()),
+        }
+    }
+}
+
+/// Sends log lines to syslog
+pub struct SyslogWriter {
+    config: SyslogConfig,
+    facility: u8,
+    hostname: String,
+    socket: Mutex<Option<SyslogSocket>>,
+}
+
+impl SyslogWriter {
+    /// Connects to the syslog server, so a bad address shows up at startup
+    pub fn new(config: &SyslogConfig, hostname: &str) -> Result<Self, Error> {
+        let facility = facility_code(&config.facility).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown syslog facility {:?}", config.facility),
+            )
+        })?;
+        Ok(Self {
+            socket: Mutex::new(Some(SyslogSocket::connect(config)?)),
+            config: config.clone(),
+            facility,
+            hostname: hostname.to_string(),
+        })
+    }
+}
+
+impl LogWriter for SyslogWriter {
+    fn write(&self, _now: &mut DeferredNow, record: &Record) -> Result<(), Error> {
+        let message = rfc5424(
+            record,
+            Utc::now(),
+            &self.hostname,
+            &self.config.app_name,
+            self.facility,
+        );
+        let mut socket = self
+            .socket
+            .lock()
+            .map_err(|_| Error::new(ErrorKind::Other, "The syslog socket lock is poisoned"))?;
+        if let Some(connected) = socket.as_mut() {
+            if connected.send(&message).is_ok() {
+                return Ok(());
+            }
+        }
+        // the server might've restarted, so have one go at reconnecting
+        *socket = None;
+        let mut reconnected = SyslogSocket::connect(&self.config)?;
+        reconnected.send(&message)?;
+        *socket = Some(reconnected);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), Error> {
+        match self.socket.lock() {
+            Ok(mut socket) => match socket.as_mut() {
+                Some(SyslogSocket::Tcp(stream)) => stream.flush(),
+                _ => Ok(()),
+            },
+            Err(_) => Err(Error::new(
+                ErrorKind::Other,
+                "The syslog socket lock is poisoned",
+            )),
+        }
+    }
+}
+
+/// Sends log lines to the systemd journal
+pub struct JournaldWriter {
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+}
+
+impl JournaldWriter {
+    /// Checks journald's there, so it shows up at startup if it's not
+    pub fn new() -> Result<Self, Error> {
+        #[cfg(unix)]
+        {
+            let socket = std::os::unix::net::UnixDatagram::unbound()?;
+            socket.connect(JOURNALD_SOCKET)?;
+            Ok(Self { socket })
+        }
+        #[cfg(not(unix))]
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "journald isn't supported on this platform",
+        ))
+    }
+}
+
+impl LogWriter for JournaldWriter {
+    fn write(&self, _now: &mut DeferredNow, record: &Record) -> Result<(), Error> {
+        #[cfg(unix)]
+        self.socket
+            .send(&journal_entry(record, JOURNALD_IDENTIFIER))
+            .map(|_| ())?;
+        #[cfg(not(unix))]
+        let _ = record;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Writes to all the configured sinks, one failing doesn't stop the others getting it
+pub struct LogSinks {
+    writers: Vec<Box<dyn LogWriter>>,
+}
+
+impl LogSinks {
+    /// Connects to whatever's configured, `None` if that's nothing
+    pub fn new(config: &LoggingConfig, hostname: &str) -> Result<Option<Self>, Error> {
+        let mut writers: Vec<Box<dyn LogWriter>> = vec![];
+        if let Some(syslog) = &config.syslog {
+            writers.push(Box::new(SyslogWriter::new(syslog, hostname)?));
+        }
+        if config.journald {
+            writers.push(Box::new(JournaldWriter::new()?));
+        }
+        Ok(match writers.is_empty() {
+            true => None,
+            false => Some(Self { writers }),
+        })
+    }
+}
+
+impl LogWriter for LogSinks {
+    fn write(&self, now: &mut DeferredNow, record: &Record) -> Result<(), Error> {
+        let mut result = Ok(());
+        for writer in self.writers.iter() {
+            if let Err(err) = writer.write(now, record) {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    fn flush(&self) -> Result<(), Error> {
+        let mut result = Ok(());
+        for writer in self.writers.iter() {
+            if let Err(err) = writer.flush() {
+                result = Err(err);
+            }
+        }
+        result
+    }
+}
@@ -1,11 +1,22 @@
 //! Logging and OTEL related thingies
 
+use std::sync::OnceLock;
+use std::time::Duration;
+
 use init_tracing_opentelemetry::tracing_subscriber_ext;
+use opentelemetry::metrics::{Counter, Histogram};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::MetricsExporterBuilder;
+use opentelemetry_sdk::Resource;
 
 use tracing_subscriber::layer::SubscriberExt;
 
 use tracing_subscriber::EnvFilter;
 
+use crate::config::OtelConfig;
+use crate::reply::Reply;
+use crate::servers::QueryProtocol;
+
 #[allow(dead_code)]
 pub(crate) fn build_loglevel_filter_layer() -> EnvFilter {
     // filter what is output on log (fmt)
@@ -24,8 +35,37 @@ pub(crate) fn build_loglevel_filter_layer() -> EnvFilter {
     EnvFilter::from_default_env()
 }
 
-#[allow(dead_code)]
-pub(crate) fn init_otel_subscribers() -> Result<(), String> {
+/// The OTEL libraries get their settings from the environment, so the config's put there unless it's already set
+fn apply_otel_env(config: &OtelConfig) {
+    let set_default = |name: &str, value: String| {
+        if std::env::var_os(name).is_none() {
+            std::env::set_var(name, value);
+        }
+    };
+    if let Some(endpoint) = &config.endpoint {
+        set_default(
+            "OTEL_EXPORTER_OTLP_ENDPOINT",
+            endpoint.as_str().trim_end_matches('/').to_string(),
+        );
+    }
+    set_default("OTEL_SERVICE_NAME", config.service_name.clone());
+    set_default(
+        "OTEL_TRACES_SAMPLER",
+        "parentbased_traceidratio".to_string(),
+    );
+    set_default("OTEL_TRACES_SAMPLER_ARG", config.sample_ratio.to_string());
+}
+
+/// Sets up tracing (and metrics, if they're on), unless OTEL's turned off
+pub(crate) fn init_otel_subscribers(config: &OtelConfig) -> Result<(), String> {
+    if !config.enabled {
+        log::info!("OpenTelemetry is turned off");
+        return Ok(());
+    }
+    apply_otel_env(config);
+    if config.metrics {
+        init_otel_metrics(config)?;
+    }
     //setup a temporary subscriber to log output during setup
     let subscriber = tracing_subscriber::registry()
         .with(build_loglevel_filter_layer())
@@ -39,3 +79,77 @@ pub(crate) fn init_otel_subscribers() -> Result<(), String> {
     tracing::subscriber::set_global_default(subscriber).map_err(|err| err.to_string())?;
     Ok(())
 }
+
+/// What's recorded about DNS queries
+struct DnsMetrics {
+    queries: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+static DNS_METRICS: OnceLock<DnsMetrics> = OnceLock::new();
+
+/// Starts sending metrics to the collector every `metrics_interval` seconds
+fn init_otel_metrics(config: &OtelConfig) -> Result<(), String> {
+    // follow the protocol the traces are using
+    let protocol = std::env::var("OTEL_EXPORTER_OTLP_METRICS_PROTOCOL")
+        .or_else(|_| std::env::var("OTEL_EXPORTER_OTLP_PROTOCOL"))
+        .unwrap_or_default();
+    let exporter: MetricsExporterBuilder = match protocol.as_str() {
+        "grpc" => opentelemetry_otlp::new_exporter().tonic().into(),
+        _ => opentelemetry_otlp::new_exporter().http().into(),
+    };
+    let provider = opentelemetry_otlp::new_pipeline()
+        .metrics(opentelemetry_sdk::runtime::Tokio)
+        .with_exporter(exporter)
+        .with_resource(Resource::default())
+        .with_period(Duration::from_secs(config.metrics_interval))
+        .build()
+        .map_err(|err| format!("Failed to set up OpenTelemetry metrics: {err:?}"))?;
+    opentelemetry::global::set_meter_provider(provider);
+
+    let meter = opentelemetry::global::meter("goatns");
+    let metrics = DnsMetrics {
+        queries: meter
+            .u64_counter("goatns.dns.queries")
+            .with_description("DNS queries answered, by protocol and response code")
+            .init(),
+        duration: meter
+            .f64_histogram("goatns.dns.query.duration")
+            .with_description("How long it took to answer DNS queries")
+            .with_unit("s")
+            .init(),
+    };
+    if DNS_METRICS.set(metrics).is_err() {
+        log::warn!("OpenTelemetry metrics were already set up");
+    }
+    Ok(())
+}
+
+/// The attributes a query's counted under
+pub(crate) fn query_attributes(
+    protocol: QueryProtocol,
+    result: &Result<Reply, String>,
+) -> [KeyValue; 2] {
+    let rcode = match result {
+        Ok(reply) => format!("{:?}", reply.header.rcode()),
+        // nothing was sent back
+        Err(_) => "Dropped".to_string(),
+    };
+    [
+        KeyValue::new("protocol", protocol.to_string()),
+        KeyValue::new("rcode", rcode),
+    ]
+}
+
+/// Counts a query and how long it took, if metrics are on
+pub(crate) fn record_query(
+    protocol: QueryProtocol,
+    result: &Result<Reply, String>,
+    elapsed: Duration,
+) {
+    if let Some(metrics) = DNS_METRICS.get() {
+        let attributes = query_attributes(protocol, result);
+        metrics.queries.add(1, &attributes);
+        metrics.duration.record(elapsed.as_secs_f64(), &attributes);
+    }
+}
@@ -58,8 +58,29 @@ async fn run() -> Result<(), GoatNsError> {
         });
     };
 
+    if clap_results.get_flag("doctor") {
+        let result = goatns::cli::doctor(&clap_results, &config.read()).await;
+        logger.shutdown();
+        return result.map_err(GoatNsError::Generic);
+    }
+
+    if clap_results.get_flag("loadtest") {
+        let result = goatns::cli::loadtest(&clap_results, &config.read()).await;
+        logger.shutdown();
+        return result.map_err(GoatNsError::Generic);
+    }
+
     log::info!("Configuration: {}", *config.read());
 
+    goatns::alias::init_resolver(&config.read().alias_resolvers);
+    goatns::builtin::init(&config.read().builtin_zones);
+    goatns::forward::init(&config.read().forward);
+    goatns::host_overrides::init(&config.read().host_overrides);
+    #[cfg(feature = "response-policy")]
+    goatns::policy::init(&config.read().response_policy)?;
+    goatns::servers::init_disabled_zone_rcode(config.read().disabled_zone_rcode);
+    goatns::servers::init_minimal_responses(config.read().minimal_responses);
+
     let (agent_tx, datastore_sender, datastore_receiver) = start_channels();
 
     // start up the DB
@@ -68,24 +89,43 @@ async fn run() -> Result<(), GoatNsError> {
         .map_err(|err| GoatNsError::StartupError(format!("DB Setup failed: {:?}", err)))?;
 
     db::start_db(&connpool).await?;
+    goatns::metrics::register_pool("write", connpool.clone());
+
+    let mut store = datastore::SqliteStore::new(connpool.clone());
+    if let Some(read_pool) = db::get_read_conn(config.read())
+        .await
+        .map_err(|err| GoatNsError::StartupError(format!("Read-only DB setup failed: {:?}", err)))?
+    {
+        goatns::metrics::register_pool("read", read_pool.clone());
+        store = store.with_read_pool(read_pool);
+    }
+    if config.read().record_cache_seconds > 0 {
+        store = store.with_record_cache(Duration::from_secs(config.read().record_cache_seconds));
+    }
 
     // start all the things!
+    let sync_store = store.clone();
+    let delegation_store = store.clone();
     let datastore_manager = tokio::spawn(datastore::manager(
         datastore_receiver,
-        connpool.clone(),
+        store,
         Some(Duration::from_secs(config.read().sql_db_cleanup_seconds)),
+        Some(config.read().backups.clone()),
+        match config.read().datastore_slow_command_ms {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        },
+        config.read().datastore_workers,
     ));
 
-    match goatns::cli::cli_commands(
-        datastore_sender.clone(),
-        &clap_results,
-        &config.read().zone_file,
-    )
-    .await
-    {
+    let cli_config = config.read();
+    match goatns::cli::cli_commands(datastore_sender.clone(), &clap_results, &cli_config).await {
         Ok(resp) => {
             if resp == SystemState::Server {
                 let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+                if config.read().capture_packets {
+                    goatns::packet_dumper::start(&config.read().packet_capture);
+                }
                 let udpserver = tokio::spawn(servers::udp_server(
                     config.read(),
                     datastore_sender.clone(),
@@ -97,6 +137,30 @@ async fn run() -> Result<(), GoatNsError> {
                     agent_tx.clone(),
                 ));
 
+                // the DNS servers are up, so we can answer the challenges
+                let acme_config = config.read();
+                if acme_config.acme.enabled {
+                    if goatns::acme::needs_renewal(&acme_config) {
+                        if let Err(err) = goatns::acme::provision(&acme_config).await {
+                            log::error!("Failed to get a certificate by ACME: {err:?}");
+                        }
+                    }
+                    tokio::spawn(goatns::acme::renewal_loop((*acme_config).clone()));
+                }
+
+                let sync_config = config.read();
+                if sync_config.sync.enabled() {
+                    tokio::spawn(goatns::sync::sync_loop(sync_store, (*sync_config).clone()));
+                }
+
+                let delegation_config = config.read();
+                if delegation_config.delegation_check_interval > 0 {
+                    tokio::spawn(goatns::delegation::check_loop(
+                        delegation_store,
+                        (*delegation_config).clone(),
+                    ));
+                }
+
                 let apiserver =
                     goatns::web::build(datastore_sender.clone(), config.read(), connpool.clone())
                         .await?;
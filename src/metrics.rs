@@ -0,0 +1,281 @@
+//! Internal counters, so you can tell when the server's struggling
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+use sqlx::SqlitePool;
+
+use crate::servers::QueryProtocol;
+
+/// Counters for things going wrong under load
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Queries that came in over UDP
+    pub queries_udp: AtomicU64,
+    /// Queries that came in over TCP
+    pub queries_tcp: AtomicU64,
+    /// Queries that came in over DoH
+    pub queries_doh: AtomicU64,
+    /// Queries answered with SERVFAIL because too many were already being handled
+    pub queries_saturated: AtomicU64,
+    /// Queries that weren't answered because they failed the `query_checks`
+    pub queries_dropped: AtomicU64,
+    /// Queries answered with SERVFAIL because the datastore's queue was full
+    pub datastore_saturated: AtomicU64,
+    /// Packet captures that couldn't be written
+    pub captures_dropped: AtomicU64,
+    /// Scheduled backups that didn't work
+    pub backups_failed: AtomicU64,
+    /// When the last scheduled backup worked, as a UNIX timestamp. 0 if one hasn't yet
+    pub backup_last_success: AtomicU64,
+    /// Zone syncs that didn't work
+    pub syncs_failed: AtomicU64,
+    /// How many commands were waiting for the datastore when it picked up the last one
+    pub datastore_queue_depth: AtomicU64,
+    /// The most commands there's been waiting for the datastore
+    pub datastore_queue_depth_max: AtomicU64,
+}
+
+impl Metrics {
+    /// Bump a counter by one
+    pub fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Read a counter
+    pub fn get(counter: &AtomicU64) -> u64 {
+        counter.load(Ordering::Relaxed)
+    }
+
+    /// Set a gauge
+    pub fn set(gauge: &AtomicU64, value: u64) {
+        gauge.store(value, Ordering::Relaxed);
+    }
+
+    /// The query counter for a protocol
+    pub(crate) fn queries(&self, protocol: QueryProtocol) -> &AtomicU64 {
+        match protocol {
+            QueryProtocol::Udp => &self.queries_udp,
+            QueryProtocol::Tcp => &self.queries_tcp,
+            QueryProtocol::DoH => &self.queries_doh,
+        }
+    }
+
+    /// Raise a gauge to the value if it's higher
+    pub fn raise(gauge: &AtomicU64, value: u64) {
+        gauge.fetch_max(value, Ordering::Relaxed);
+    }
+}
+
+/// The server-wide counters
+pub static METRICS: Metrics = Metrics {
+    queries_udp: AtomicU64::new(0),
+    queries_tcp: AtomicU64::new(0),
+    queries_doh: AtomicU64::new(0),
+    queries_saturated: AtomicU64::new(0),
+    queries_dropped: AtomicU64::new(0),
+    datastore_saturated: AtomicU64::new(0),
+    captures_dropped: AtomicU64::new(0),
+    backups_failed: AtomicU64::new(0),
+    backup_last_success: AtomicU64::new(0),
+    syncs_failed: AtomicU64::new(0),
+    datastore_queue_depth: AtomicU64::new(0),
+    datastore_queue_depth_max: AtomicU64::new(0),
+};
+
+/// How the datastore's been getting on with one kind of command
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CommandStats {
+    /// How many have been handled
+    pub count: u64,
+    /// Time spent handling them
+    pub handling: Duration,
+    /// Time they spent in the queue before they were handled
+    pub waiting: Duration,
+    /// How many took longer than `datastore_slow_command_ms`
+    pub slow: u64,
+}
+
+/// Datastore timings, by [crate::datastore::Command::kind]
+static COMMANDS: Mutex<BTreeMap<&'static str, CommandStats>> = Mutex::new(BTreeMap::new());
+
+/// Count a command the datastore's handled
+pub fn record_command(kind: &'static str, waiting: Duration, handling: Duration, slow: bool) {
+    match COMMANDS.lock() {
+        Ok(mut commands) => {
+            let stats = commands.entry(kind).or_default();
+            stats.count += 1;
+            stats.handling += handling;
+            stats.waiting += waiting;
+            if slow {
+                stats.slow += 1;
+            }
+        }
+        Err(err) => log::error!("Failed to record datastore metrics for {kind}: {err:?}"),
+    }
+}
+
+/// The datastore's timings for a kind of command, if it's handled any
+pub fn command_stats(kind: &str) -> Option<CommandStats> {
+    COMMANDS
+        .lock()
+        .ok()
+        .and_then(|commands| commands.get(kind).copied())
+}
+
+/// Database pools to report on, added at startup
+static POOLS: RwLock<Vec<(&'static str, SqlitePool)>> = RwLock::new(Vec::new());
+
+/// Include a database pool's connection counts in the metrics
+pub fn register_pool(name: &'static str, pool: SqlitePool) {
+    match POOLS.write() {
+        Ok(mut pools) => pools.push((name, pool)),
+        Err(err) => log::error!("Failed to register the {name} pool for metrics: {err:?}"),
+    }
+}
+
+/// The metrics in Prometheus' text format
+pub fn render() -> String {
+    let mut output = String::new();
+    let _ = writeln!(
+        output,
+        "# HELP goatns_queries_total Queries that came in, by protocol"
+    );
+    let _ = writeln!(output, "# TYPE goatns_queries_total counter");
+    for protocol in [QueryProtocol::Udp, QueryProtocol::Tcp, QueryProtocol::DoH] {
+        let _ = writeln!(
+            output,
+            "goatns_queries_total{{protocol=\"{protocol}\"}} {}",
+            Metrics::get(METRICS.queries(protocol))
+        );
+    }
+    for (name, help, counter) in [
+        (
+            "goatns_queries_saturated_total",
+            "Queries answered with SERVFAIL because too many were already being handled",
+            &METRICS.queries_saturated,
+        ),
+        (
+            "goatns_queries_dropped_total",
+            "Queries that weren't answered because they failed the query checks",
+            &METRICS.queries_dropped,
+        ),
+        (
+            "goatns_datastore_saturated_total",
+            "Queries answered with SERVFAIL because the datastore's queue was full",
+            &METRICS.datastore_saturated,
+        ),
+        (
+            "goatns_captures_dropped_total",
+            "Packet captures that were dropped because the writer was behind or failing",
+            &METRICS.captures_dropped,
+        ),
+        (
+            "goatns_backups_failed_total",
+            "Scheduled backups that failed",
+            &METRICS.backups_failed,
+        ),
+        (
+            "goatns_syncs_failed_total",
+            "Zone syncs from files that failed",
+            &METRICS.syncs_failed,
+        ),
+    ] {
+        let _ = writeln!(output, "# HELP {name} {help}");
+        let _ = writeln!(output, "# TYPE {name} counter");
+        let _ = writeln!(output, "{name} {}", Metrics::get(counter));
+    }
+
+    let _ = writeln!(
+        output,
+        "# HELP goatns_backup_last_success_timestamp_seconds When the last scheduled backup worked, 0 if none have"
+    );
+    let _ = writeln!(
+        output,
+        "# TYPE goatns_backup_last_success_timestamp_seconds gauge"
+    );
+    let _ = writeln!(
+        output,
+        "goatns_backup_last_success_timestamp_seconds {}",
+        Metrics::get(&METRICS.backup_last_success)
+    );
+
+    for (name, help, value) in [
+        (
+            "goatns_datastore_queue_depth",
+            "Commands waiting for the datastore when it picked up the last one",
+            &METRICS.datastore_queue_depth,
+        ),
+        (
+            "goatns_datastore_queue_depth_max",
+            "The most commands that have been waiting for the datastore",
+            &METRICS.datastore_queue_depth_max,
+        ),
+    ] {
+        let _ = writeln!(output, "# HELP {name} {help}");
+        let _ = writeln!(output, "# TYPE {name} gauge");
+        let _ = writeln!(output, "{name} {}", Metrics::get(value));
+    }
+
+    let commands = match COMMANDS.lock() {
+        Ok(commands) => commands.clone(),
+        Err(err) => {
+            log::error!("Failed to read datastore metrics: {err:?}");
+            BTreeMap::new()
+        }
+    };
+    let families: [(&str, &str, fn(&CommandStats) -> String); 4] = [
+        (
+            "goatns_datastore_commands_total",
+            "Commands handled by the datastore",
+            |stats| stats.count.to_string(),
+        ),
+        (
+            "goatns_datastore_command_seconds_total",
+            "Time the datastore's spent handling commands",
+            |stats| stats.handling.as_secs_f64().to_string(),
+        ),
+        (
+            "goatns_datastore_queue_wait_seconds_total",
+            "Time commands spent waiting for the datastore",
+            |stats| stats.waiting.as_secs_f64().to_string(),
+        ),
+        (
+            "goatns_datastore_slow_commands_total",
+            "Commands that took the datastore longer than datastore_slow_command_ms",
+            |stats| stats.slow.to_string(),
+        ),
+    ];
+    for (name, help, value) in families {
+        let _ = writeln!(output, "# HELP {name} {help}");
+        let _ = writeln!(output, "# TYPE {name} counter");
+        for (kind, stats) in commands.iter() {
+            let _ = writeln!(output, "{name}{{command=\"{kind}\"}} {}", value(stats));
+        }
+    }
+
+    let _ = writeln!(
+        output,
+        "# HELP goatns_db_pool_connections Database pool connections"
+    );
+    let _ = writeln!(output, "# TYPE goatns_db_pool_connections gauge");
+    if let Ok(pools) = POOLS.read() {
+        for (pool_name, pool) in pools.iter() {
+            let idle = pool.num_idle() as u32;
+            let size = pool.size();
+            let _ = writeln!(
+                output,
+                "goatns_db_pool_connections{{pool=\"{pool_name}\",state=\"idle\"}} {idle}"
+            );
+            let _ = writeln!(
+                output,
+                "goatns_db_pool_connections{{pool=\"{pool_name}\",state=\"in_use\"}} {}",
+                size.saturating_sub(idle)
+            );
+        }
+    }
+    output
+}
@@ -0,0 +1,174 @@
+//! What zones people are allowed to create. Creating zones through the API and UI, and importing them through the
+//! API, all check names here.
+//!
+//! A name has to be a valid DNS name, end in one of the `allowed_tlds` (if any are set), and can't be one of the
+//! reserved names or under one. The reserved names are [BUILTIN_RESERVED_NAMES], the server's own `hostname`, and
+//! whatever admins have added to the database as [ReservedName]s.
+
+use goat_lib::validators::dns_name;
+use sqlx::SqliteConnection;
+use trust_dns_resolver::proto::rr::Name;
+
+use crate::config::ConfigFile;
+use crate::db::ReservedName;
+use crate::error::GoatNsError;
+use crate::utils::check_valid_tld;
+
+/// The special-use names from [RFC6761](https://www.rfc-editor.org/rfc/rfc6761) and
+/// [RFC6762](https://www.rfc-editor.org/rfc/rfc6762), which shouldn't be served by anyone
+pub const BUILTIN_RESERVED_NAMES: [&str; 8] = [
+    "example",
+    "example.com",
+    "example.net",
+    "example.org",
+    "invalid",
+    "local",
+    "localhost",
+    "test",
+];
+
+/// Lower-cases the name, takes off the trailing dot and punycodes internationalised names, so names compare properly.
+/// Names that can't be punycoded are just lower-cased, and validation rejects them later.
+pub fn normalise(name: &str) -> String {
+    let name = name.trim().trim_end_matches('.');
+    if name.is_ascii() {
+        return name.to_ascii_lowercase();
+    }
+    match Name::from_utf8(name) {
+        Ok(idn) => idn.to_ascii().trim_end_matches('.').to_ascii_lowercase(),
+        Err(err) => {
+            log::debug!("Failed to punycode {name:?}: {err:?}");
+            name.to_lowercase()
+        }
+    }
+}
+
+/// Whether any of the labels are punycode, eg `xn--bcher-kva.example`
+pub fn is_idn(name: &str) -> bool {
+    name.split('.')
+        .any(|label| label.len() > 4 && label[..4].eq_ignore_ascii_case("xn--"))
+}
+
+/// The other way from [normalise], turns punycode back into unicode for showing people, so
+/// `xn--bcher-kva.example` is `bücher.example`. Anything that isn't punycode comes back as it was.
+pub fn to_unicode(name: &str) -> String {
+    if !is_idn(name) {
+        return name.to_string();
+    }
+    match Name::from_ascii(name) {
+        Ok(idn) => {
+            let unicode = idn.to_utf8();
+            match name.ends_with('.') {
+                true => unicode,
+                false => unicode.trim_end_matches('.').to_string(),
+            }
+        }
+        Err(err) => {
+            log::debug!("Failed to decode punycode {name:?}: {err:?}");
+            name.to_string()
+        }
+    }
+}
+
+/// A record's name the way it's stored - relative to the zone, normalised, and `@` for the apex. Names that end in the
+/// zone name have it taken off, with or without a trailing dot, so `www.example.com` and `www.example.com.` in
+/// `example.com` are both `www`. Fully-qualified names (with the dot) outside the zone are an error.
+pub fn relative_name(name: &str, zone: &str) -> Result<String, GoatNsError> {
+    let name = name.trim();
+    if name.is_empty() || name == "@" {
+        return Ok("@".to_string());
+    }
+    let fully_qualified = name.ends_with('.');
+    let name = normalise(name);
+    let zone = normalise(zone);
+    if name == zone {
+        return Ok("@".to_string());
+    }
+    if let Some(relative) = name.strip_suffix(&format!(".{zone}")) {
+        return Ok(relative.to_string());
+    }
+    match fully_qualified {
+        true => Err(GoatNsError::InvalidValue(format!(
+            "{name}. isn't in the zone {zone}"
+        ))),
+        false => Ok(name),
+    }
+}
+
+/// The rules for zone names on this server
+#[derive(Clone, Debug, Default)]
+pub struct NamePolicy {
+    allowed_tlds: Vec<String>,
+    reserved: Vec<String>,
+}
+
+impl NamePolicy {
+    /// The policy for this config, with `reserved` on top of the built-in reserved names
+    pub fn new(config: &ConfigFile, reserved: impl IntoIterator<Item = String>) -> Self {
+        let mut reserved: Vec<String> = BUILTIN_RESERVED_NAMES
+            .iter()
+            .map(|name| name.to_string())
+            .chain(reserved)
+            .chain(std::iter::once(config.hostname.clone()))
+            .map(|name| normalise(&name))
+            .filter(|name| !name.is_empty())
+            .collect();
+        reserved.sort();
+        reserved.dedup();
+        Self {
+            allowed_tlds: config
+                .allowed_tlds
+                .iter()
+                .map(|tld| normalise(tld))
+                .collect(),
+            reserved,
+        }
+    }
+
+    /// The policy for this config, with the reserved names admins have added to the database
+    pub async fn load(
+        txn: &mut SqliteConnection,
+        config: &ConfigFile,
+    ) -> Result<Self, GoatNsError> {
+        let reserved = ReservedName::get_all(txn).await?;
+        Ok(Self::new(
+            config,
+            reserved.into_iter().map(|reserved| reserved.name),
+        ))
+    }
+
+    /// The reserved name that covers this one, if there is one
+    pub fn reserved_by(&self, name: &str) -> Option<&str> {
+        let name = normalise(name);
+        self.reserved
+            .iter()
+            .find(|reserved| name == **reserved || name.ends_with(&format!(".{reserved}")))
+            .map(|reserved| reserved.as_str())
+    }
+
+    /// Returns an [GoatNsError::InvalidValue] saying what's wrong if the zone can't be created
+    pub fn check(&self, name: &str) -> Result<(), GoatNsError> {
+        let normalised = normalise(name);
+        if normalised.is_empty() {
+            return Err(GoatNsError::InvalidValue(
+                "Zone name cannot be empty!".to_string(),
+            ));
+        }
+        if !dns_name(&normalised) {
+            return Err(GoatNsError::InvalidValue(format!(
+                "Invalid DNS name: {name}"
+            )));
+        }
+        if !check_valid_tld(&normalised, &self.allowed_tlds) {
+            return Err(GoatNsError::InvalidValue(format!(
+                "Invalid TLD for this system: {name}"
+            )));
+        }
+        if let Some(reserved) = self.reserved_by(&normalised) {
+            return Err(GoatNsError::InvalidValue(format!(
+                "{name} is reserved ({reserved}) and can't be used as a zone name"
+            )));
+        }
+        Ok(())
+    }
+}
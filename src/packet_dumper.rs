@@ -1,8 +1,43 @@
+//! Packet captures, written as pcap files Wireshark and friends can open
+//!
+//! The DNS payloads are wrapped in made-up IPv4 and UDP headers (from `192.0.2.1` to `192.0.2.53`, which are
+//! documentation addresses) since we only ever see the DNS message itself. Writing happens in the background so
+//! answering queries never waits on the disk - if the writer falls behind, captures are dropped and counted in
+//! `goatns_captures_dropped_total`. Files are rotated once they hit `max_file_size`, and only the newest `max_files`
+//! are kept.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use chrono::{DateTime, Utc};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
 use tracing::{debug, error};
 
+use crate::config::PacketCaptureConfig;
+use crate::metrics::{Metrics, METRICS};
+
+/// Capture files are named `{CAPTURE_PREFIX}{timestamp}.pcap`, anything else in the directory is left alone
+const CAPTURE_PREFIX: &str = "goatns-";
+/// LINKTYPE_RAW, the packets start at the IP header
+const LINKTYPE_RAW: u32 = 101;
+/// The pcap global header's size
+const PCAP_HEADER_BYTES: u64 = 24;
+/// Each packet's record header
+const PCAP_RECORD_HEADER_BYTES: usize = 16;
+/// IPv4 and UDP headers
+const FAKE_HEADER_BYTES: usize = 28;
+/// The largest payload that fits in an IPv4 UDP packet
+const MAX_PAYLOAD: usize = 65535 - FAKE_HEADER_BYTES;
+
+const CLIENT_ADDRESS: [u8; 4] = [192, 0, 2, 1];
+const CLIENT_PORT: u16 = 53000;
+const SERVER_ADDRESS: [u8; 4] = [192, 0, 2, 53];
+const SERVER_PORT: u16 = 53;
+
+#[derive(Clone, Copy, Debug)]
 pub enum DumpType {
     ClientRequest,
     // Header
@@ -16,26 +51,211 @@ impl core::fmt::Display for DumpType {
     }
 }
 
-/// Dumps the bytes of a given vector to a templated packet
-pub async fn dump_bytes(bytes: Vec<u8>, dump_type: DumpType) {
-    let now: DateTime<Utc> = Utc::now();
+/// A captured packet, waiting to be written
+#[derive(Debug)]
+struct Capture {
+    timestamp: SystemTime,
+    bytes: Vec<u8>,
+    dump_type: DumpType,
+}
+
+/// Where captures get sent to be written, set by [start]
+static CAPTURE_QUEUE: OnceLock<mpsc::Sender<Capture>> = OnceLock::new();
+
+/// Start the background writer, captures before this (or if it fails to start) are dropped
+pub fn start(config: &PacketCaptureConfig) {
+    let (tx, rx) = mpsc::channel(config.queue_size.max(1));
+    if CAPTURE_QUEUE.set(tx).is_err() {
+        log::warn!("Packet capture writer is already running");
+        return;
+    }
+    tokio::spawn(capture_writer(rx, config.clone()));
+}
 
+/// Queue up the bytes of a packet to be written to the current capture file
+pub async fn dump_bytes(bytes: Vec<u8>, dump_type: DumpType) {
     debug!("bytes: {:?}", bytes);
-    let filename = format!(
-        "./captures/{}-{}.cap",
+    let capture = Capture {
+        timestamp: SystemTime::now(),
+        bytes,
         dump_type,
-        now.format("%Y-%m-%dT%H%M%SZ")
-    );
-    let mut fh = match File::create(&filename).await {
+    };
+    let queued = match CAPTURE_QUEUE.get() {
+        Some(queue) => queue.try_send(capture).is_ok(),
+        None => false,
+    };
+    if !queued {
+        Metrics::increment(&METRICS.captures_dropped);
+    }
+}
+
+/// The pcap file header
+pub fn pcap_header() -> Vec<u8> {
+    let mut res = Vec::with_capacity(PCAP_HEADER_BYTES as usize);
+    res.extend(0xa1b2c3d4u32.to_le_bytes());
+    // version 2.4
+    res.extend(2u16.to_le_bytes());
+    res.extend(4u16.to_le_bytes());
+    // timezone offset and timestamp accuracy, both always zero
+    res.extend(0i32.to_le_bytes());
+    res.extend(0u32.to_le_bytes());
+    // snaplen
+    res.extend(65535u32.to_le_bytes());
+    res.extend(LINKTYPE_RAW.to_le_bytes());
+    res
+}
+
+/// The ones-complement sum used for IPv4 header checksums
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// A pcap record for the DNS payload, wrapped in IPv4 and UDP headers going the right way for the dump type
+pub fn pcap_record(timestamp: SystemTime, payload: &[u8], dump_type: DumpType) -> Vec<u8> {
+    let payload = &payload[..payload.len().min(MAX_PAYLOAD)];
+    let (src, src_port, dst, dst_port) = match dump_type {
+        DumpType::ClientRequest => (CLIENT_ADDRESS, CLIENT_PORT, SERVER_ADDRESS, SERVER_PORT),
+    };
+    let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let packet_len = FAKE_HEADER_BYTES + payload.len();
+
+    let mut res = Vec::with_capacity(PCAP_RECORD_HEADER_BYTES + packet_len);
+    res.extend((since_epoch.as_secs() as u32).to_le_bytes());
+    res.extend(since_epoch.subsec_micros().to_le_bytes());
+    res.extend((packet_len as u32).to_le_bytes());
+    res.extend((packet_len as u32).to_le_bytes());
+
+    let mut ip_header = Vec::with_capacity(20);
+    // version 4, 5 words of header
+    ip_header.push(0x45);
+    ip_header.push(0);
+    ip_header.extend((packet_len as u16).to_be_bytes());
+    // identification, flags and fragment offset
+    ip_header.extend([0, 0, 0, 0]);
+    // TTL and the protocol (UDP)
+    ip_header.extend([64, 17]);
+    ip_header.extend([0, 0]);
+    ip_header.extend(src);
+    ip_header.extend(dst);
+    let checksum = ipv4_checksum(&ip_header);
+    ip_header[10..12].copy_from_slice(&checksum.to_be_bytes());
+    res.extend(ip_header);
+
+    res.extend(src_port.to_be_bytes());
+    res.extend(dst_port.to_be_bytes());
+    res.extend(((8 + payload.len()) as u16).to_be_bytes());
+    // a zero UDP checksum means there isn't one, which IPv4 allows
+    res.extend([0, 0]);
+    res.extend(payload);
+    res
+}
+
+/// The capture files in the directory, oldest first
+fn capture_files(directory: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = match std::fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(CAPTURE_PREFIX) && name.ends_with(".pcap"))
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    // the timestamps in the names sort the same way they happened
+    files.sort();
+    files
+}
+
+/// Remove the oldest capture files so there's only `max_files` left
+pub fn apply_retention(directory: &Path, max_files: usize) {
+    let files = capture_files(directory);
+    let excess = files.len().saturating_sub(max_files);
+    for file in files.iter().take(excess) {
+        match std::fs::remove_file(file) {
+            Ok(_) => debug!("Removed old capture file {file:?}"),
+            Err(err) => error!("Failed to remove old capture file {file:?}: {err:?}"),
+        }
+    }
+}
+
+/// The file that's being written to
+struct CaptureFile {
+    file: File,
+    size: u64,
+}
+
+/// Start a new capture file, and clean up old ones
+async fn open_capture_file(config: &PacketCaptureConfig) -> Option<CaptureFile> {
+    let directory = PathBuf::from(shellexpand::tilde(&config.directory).to_string());
+    if let Err(err) = tokio::fs::create_dir_all(&directory).await {
+        error!("couldn't create capture directory {directory:?}: {err:?}");
+        return None;
+    }
+    let now: DateTime<Utc> = Utc::now();
+    let filename = directory.join(format!(
+        "{CAPTURE_PREFIX}{}.pcap",
+        now.format("%Y-%m-%dT%H%M%S%.6fZ")
+    ));
+    let mut file = match File::create(&filename).await {
         Ok(value) => value,
         Err(error) => {
-            error!("couldn't open {} for writing: {:?}", filename, error);
-            return;
+            error!("couldn't open {filename:?} for writing: {error:?}");
+            return None;
         }
     };
+    if let Err(err) = file.write_all(&pcap_header()).await {
+        error!("Failed to write pcap header to {filename:?}: {err:?}");
+        return None;
+    }
+    debug!("Started capture file {filename:?}");
+    // the new one counts towards the limit
+    apply_retention(&directory, config.max_files.max(1));
+    Some(CaptureFile {
+        file,
+        size: PCAP_HEADER_BYTES,
+    })
+}
 
-    match fh.write_all(&bytes).await {
-        Ok(_) => debug!("Successfully wrote packet to {}", &filename),
-        Err(error) => debug!("Failed to write to {}: {:?}", filename, error),
-    };
+/// Writes the queued captures out, rotating the files as they fill up
+async fn capture_writer(mut rx: mpsc::Receiver<Capture>, config: PacketCaptureConfig) {
+    let mut current: Option<CaptureFile> = None;
+    while let Some(capture) = rx.recv().await {
+        let record = pcap_record(capture.timestamp, &capture.bytes, capture.dump_type);
+        let full = current
+            .as_ref()
+            .is_some_and(|file| file.size + record.len() as u64 > config.max_file_size);
+        if full || current.is_none() {
+            if let Some(mut file) = current.take() {
+                let _ = file.file.flush().await;
+            }
+            current = open_capture_file(&config).await;
+        }
+        let written = match current.as_mut() {
+            Some(file) => match file.file.write_all(&record).await {
+                Ok(_) => {
+                    file.size += record.len() as u64;
+                    true
+                }
+                Err(err) => {
+                    error!("Failed to write capture: {err:?}");
+                    // try a new file next time
+                    current = None;
+                    false
+                }
+            },
+            None => false,
+        };
+        if !written {
+            Metrics::increment(&METRICS.captures_dropped);
+        }
+    }
 }
@@ -0,0 +1,280 @@
+//! A hook for changing answers before they go out, eg to hand out different addresses depending on where the query
+//! came from, put the closest server first or hide records from some clients.
+//!
+//! Only built with the `response-policy` feature, and only runs if `response_policy.script` is set. The function's
+//! called with the client's IP (`nil` if we don't know it), the query name, the query type and the answers we'd
+//! otherwise send, as a list of `{name = "www.example.com", type = "A", ttl = 300, record = {...}}`. It returns a list of what to send instead,
+//! each entry is one of:
+//!
+//! - the position of one of the answers it was given (starting at 1), to send that one
+//! - `{index = 1, ttl = 30}` to send that answer with a different TTL
+//! - `{type = "A", data = "192.0.2.1", ttl = 60}` to send a new record for the query name, the data's the same as it'd
+//!   be in a zone and the TTL's [DEFAULT_TTL] if it's left out
+//!
+//! Returning `nil` sends the answers as they were. If the script fails or takes too long the original answers are
+//! sent, so a broken script can't take the server down with it.
+//!
+//! Each thread gets its own Lua state, with only the `string`, `table`, `math` and `utf8` libraries loaded.
+
+use std::cell::RefCell;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use mlua::{Function, HookTriggers, Lua, LuaOptions, LuaSerdeExt, StdLib, Value, VmState};
+use serde::{Deserialize, Serialize};
+
+use crate::config::ResponsePolicyConfig;
+use crate::error::GoatNsError;
+use crate::reply::Answer;
+use crate::resourcerecord::{InternalResourceRecord, SetTTL};
+use crate::zones::FileZoneRecord;
+use crate::Question;
+
+/// How often the instruction count's checked
+const HOOK_INTERVAL: u32 = 1000;
+/// The TTL on records the script makes if it doesn't say
+pub const DEFAULT_TTL: u32 = 60;
+
+/// The script, ready for each thread to load
+#[derive(Clone, Debug)]
+pub struct Script {
+    /// Shows up in Lua's error messages
+    pub name: String,
+    pub source: String,
+    pub function: String,
+    pub max_instructions: u64,
+}
+
+impl Script {
+    /// Read the script from the config, `None` if there isn't one
+    pub fn from_config(config: &ResponsePolicyConfig) -> Result<Option<Self>, GoatNsError> {
+        let Some(path) = &config.script else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            name: path.display().to_string(),
+            source: std::fs::read_to_string(path)
+                .map_err(|err| GoatNsError::FileError(format!("{}: {err:?}", path.display())))?,
+            function: config.function.clone(),
+            max_instructions: config.max_instructions,
+        }))
+    }
+}
+
+/// A loaded script
+pub struct Policy {
+    lua: Lua,
+    function: Function,
+    instructions: Arc<AtomicU64>,
+}
+
+/// One of the answers, as the script sees it
+#[derive(Serialize)]
+struct Candidate<'a> {
+    name: String,
+    #[serde(rename = "type")]
+    rrtype: String,
+    ttl: u32,
+    record: &'a InternalResourceRecord,
+}
+
+/// One of the things the script can send back
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Decision {
+    Keep(usize),
+    KeepWithTtl {
+        index: usize,
+        ttl: Option<u32>,
+    },
+    New {
+        #[serde(rename = "type")]
+        rrtype: String,
+        data: String,
+        ttl: Option<u32>,
+    },
+}
+
+impl Policy {
+    pub fn new(script: &Script) -> Result<Self, GoatNsError> {
+        let lua = Lua::new_with(
+            StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8,
+            LuaOptions::default(),
+        )?;
+        // the base library's always there, but it can load files
+        for name in ["dofile", "loadfile"] {
+            lua.globals().set(name, Value::Nil)?;
+        }
+        lua.load(script.source.as_str())
+            .set_name(script.name.as_str())
+            .exec()?;
+        let function: Function = lua.globals().get(script.function.as_str()).map_err(|err| {
+            GoatNsError::Script(format!(
+                "{} doesn't have a function called {}: {err}",
+                script.name, script.function
+            ))
+        })?;
+
+        let instructions = Arc::new(AtomicU64::new(0));
+        let counter = instructions.clone();
+        let max_instructions = script.max_instructions;
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(HOOK_INTERVAL),
+            move |_lua, _debug| match counter.fetch_add(HOOK_INTERVAL.into(), Ordering::Relaxed)
+                >= max_instructions
+            {
+                true => Err(mlua::Error::RuntimeError(format!(
+                    "stopped after {max_instructions} instructions"
+                ))),
+                false => Ok(VmState::Continue),
+            },
+        );
+
+        Ok(Self {
+            lua,
+            function,
+            instructions,
+        })
+    }
+
+    /// Run the script over the answers, and get back what to send
+    pub fn apply(
+        &self,
+        client: Option<IpAddr>,
+        question: &Question,
+        answers: &[Answer],
+    ) -> Result<Vec<Answer>, GoatNsError> {
+        let qname = String::from_utf8_lossy(&question.qname).to_string();
+        let candidates: Vec<Candidate> = answers
+            .iter()
+            .map(|answer| Candidate {
+                name: answer
+                    .name
+                    .as_ref()
+                    .map(|name| String::from_utf8_lossy(name).to_string())
+                    .unwrap_or_else(|| qname.clone()),
+                rrtype: crate::generic::type_name(answer.record.rrtype_code()),
+                ttl: *answer.record.ttl(),
+                record: &answer.record,
+            })
+            .collect();
+
+        self.instructions.store(0, Ordering::Relaxed);
+        let result: Value = self.function.call((
+            client.map(|ip| ip.to_string()),
+            qname.as_str(),
+            crate::generic::type_name(question.qtype_code),
+            self.lua.to_value(&candidates)?,
+        ))?;
+        let decisions: Option<Vec<Decision>> = self.lua.from_value(result)?;
+        let Some(decisions) = decisions else {
+            return Ok(answers.to_vec());
+        };
+
+        decisions
+            .into_iter()
+            .map(|decision| match decision {
+                Decision::Keep(index) => answer_at(answers, index),
+                Decision::KeepWithTtl { index, ttl } => {
+                    let answer = answer_at(answers, index)?;
+                    Ok(match ttl {
+                        Some(ttl) => Answer {
+                            record: answer.record.set_ttl(ttl),
+                            ..answer
+                        },
+                        None => answer,
+                    })
+                }
+                Decision::New { rrtype, data, ttl } => {
+                    InternalResourceRecord::try_from(FileZoneRecord {
+                        id: None,
+                        zoneid: None,
+                        name: qname.clone(),
+                        rrtype,
+                        class: question.qclass,
+                        rdata: data,
+                        ttl: ttl.unwrap_or(DEFAULT_TTL),
+                        comment: None,
+                        tags: Default::default(),
+                        expires_at: None,
+                    })
+                    .map(Answer::from)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Lua counts from 1
+fn answer_at(answers: &[Answer], index: usize) -> Result<Answer, GoatNsError> {
+    index
+        .checked_sub(1)
+        .and_then(|index| answers.get(index))
+        .cloned()
+        .ok_or_else(|| {
+            GoatNsError::Script(format!(
+                "there's no answer {index}, there's only {}",
+                answers.len()
+            ))
+        })
+}
+
+static SCRIPT: RwLock<Option<Arc<Script>>> = RwLock::new(None);
+
+thread_local! {
+    static POLICY: RefCell<Option<Policy>> = const { RefCell::new(None) };
+}
+
+/// Load the script from the config, it's loaded once here so a broken one stops the server from starting
+pub fn init(config: &ResponsePolicyConfig) -> Result<(), GoatNsError> {
+    let script = Script::from_config(config)?;
+    if let Some(script) = &script {
+        Policy::new(script)?;
+        log::info!("Loaded response policy {}", script.name);
+    }
+    let mut current = SCRIPT.write().map_err(|err| {
+        GoatNsError::Generic(format!("Response policy lock is poisoned: {err:?}"))
+    })?;
+    *current = script.map(Arc::new);
+    Ok(())
+}
+
+/// Run the script over the answers if there is one, if it doesn't work out they're sent as they were
+pub fn apply(client: Option<IpAddr>, question: &Question, answers: Vec<Answer>) -> Vec<Answer> {
+    let script = match SCRIPT.read() {
+        Ok(script) => script.clone(),
+        Err(err) => {
+            log::error!("Response policy lock is poisoned: {err:?}");
+            return answers;
+        }
+    };
+    let Some(script) = script else {
+        return answers;
+    };
+    POLICY.with(|policy| {
+        let mut policy = policy.borrow_mut();
+        if policy.is_none() {
+            match Policy::new(&script) {
+                Ok(loaded) => *policy = Some(loaded),
+                Err(err) => {
+                    log::error!("Failed to load response policy {}: {err:?}", script.name);
+                    return answers;
+                }
+            }
+        }
+        let Some(policy) = policy.as_ref() else {
+            return answers;
+        };
+        match policy.apply(client, question, &answers) {
+            Ok(changed) => changed,
+            Err(err) => {
+                log::warn!(
+                    "Response policy {} failed, sending the answers unchanged: {err:?}",
+                    script.name
+                );
+                answers
+            }
+        }
+    })
+}
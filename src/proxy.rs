@@ -0,0 +1,165 @@
+//! Finding the real client address when there's a load balancer or reverse proxy in front of us - the PROXY protocol
+//! ([v2](https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt)) for DNS over TCP, and the `Forwarded`
+//! ([RFC7239](https://www.rfc-editor.org/rfc/rfc7239)) / `X-Forwarded-For` headers for the web server.
+//!
+//! Addresses only get believed if they came from one of the `trusted_proxies`.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use ipnet::IpNet;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Every PROXY v2 header starts with this
+pub const PROXY_V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+/// The signature, version/command, family/protocol and the length of the rest
+const PROXY_V2_FIXED_BYTES: usize = 16;
+
+/// If the address is one of the proxies we trust to tell us who the client is
+pub fn is_trusted_proxy(addr: &IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    let addr = addr.to_canonical();
+    trusted_proxies.iter().any(|net| net.contains(&addr))
+}
+
+/// Parses a complete PROXY v2 header, returning the client's address. `LOCAL` connections (eg, health checks from the
+/// proxy itself) and address families we don't handle give back `None`, so the peer's address gets used.
+pub fn parse_proxy_v2(header: &[u8]) -> Result<Option<SocketAddr>, String> {
+    if header.len() < PROXY_V2_FIXED_BYTES {
+        return Err(format!(
+            "PROXY header is too short, got {} bytes",
+            header.len()
+        ));
+    }
+    if header[0..12] != PROXY_V2_SIGNATURE {
+        return Err("PROXY header signature doesn't match".to_string());
+    }
+    let version = header[12] >> 4;
+    if version != 2 {
+        return Err(format!("Unsupported PROXY protocol version {version}"));
+    }
+    let length = u16::from_be_bytes([header[14], header[15]]) as usize;
+    let body = header
+        .get(PROXY_V2_FIXED_BYTES..PROXY_V2_FIXED_BYTES + length)
+        .ok_or_else(|| {
+            format!("PROXY header says it has {length} bytes of addresses, but was short")
+        })?;
+
+    match header[12] & 0x0F {
+        // LOCAL
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        command => return Err(format!("Unknown PROXY command {command}")),
+    }
+
+    // the high nibble's the address family, the low one is the transport
+    match header[13] >> 4 {
+        // AF_INET
+        0x1 => {
+            let addresses = body
+                .get(0..12)
+                .ok_or_else(|| "PROXY header is too short for IPv4 addresses".to_string())?;
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        // AF_INET6
+        0x2 => {
+            let addresses = body
+                .get(0..36)
+                .ok_or_else(|| "PROXY header is too short for IPv6 addresses".to_string())?;
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(&addresses[0..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), port)))
+        }
+        // AF_UNSPEC, AF_UNIX and whatever else
+        _ => Ok(None),
+    }
+}
+
+/// Reads the PROXY v2 header off the start of a connection, and returns who's really on the other end
+pub async fn read_proxy_v2<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    peer: SocketAddr,
+) -> Result<SocketAddr, String> {
+    let mut header = vec![0u8; PROXY_V2_FIXED_BYTES];
+    reader
+        .read_exact(&mut header)
+        .await
+        .map_err(|err| format!("Failed to read PROXY header: {err:?}"))?;
+    let length = u16::from_be_bytes([header[14], header[15]]) as usize;
+    header.resize(PROXY_V2_FIXED_BYTES + length, 0);
+    reader
+        .read_exact(&mut header[PROXY_V2_FIXED_BYTES..])
+        .await
+        .map_err(|err| format!("Failed to read PROXY header addresses: {err:?}"))?;
+    Ok(parse_proxy_v2(&header)?.unwrap_or(peer))
+}
+
+/// Pulls the address out of a `for=` value in a Forwarded header, or an entry in X-Forwarded-For
+fn parse_forwarded_addr(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Some(rest) = value.strip_prefix('[') {
+        // [2001:db8::1]:4711
+        return rest.split(']').next()?.parse().ok();
+    }
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// The addresses in a `Forwarded` header, in the order they were added
+fn forwarded_for(header: &str) -> Vec<Option<IpAddr>> {
+    header
+        .split(',')
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| parse_forwarded_addr(value))
+            })
+        })
+        .collect()
+}
+
+/// Works out the client's address for a web request.
+///
+/// If the peer's a trusted proxy, we walk back through the forwarding header (`Forwarded` if it's there, otherwise
+/// `X-Forwarded-For`) until we find an address that isn't one of ours - anything before that could've been made up by
+/// the client. If there's something in there we can't parse, the last address we could trust is used.
+pub fn client_ip_from_headers(
+    peer: IpAddr,
+    forwarded: Option<&str>,
+    x_forwarded_for: Option<&str>,
+    trusted_proxies: &[IpNet],
+) -> IpAddr {
+    if !is_trusted_proxy(&peer, trusted_proxies) {
+        return peer;
+    }
+    let hops: Vec<Option<IpAddr>> = match (forwarded, x_forwarded_for) {
+        (Some(forwarded), _) => forwarded_for(forwarded),
+        (None, Some(x_forwarded_for)) => x_forwarded_for
+            .split(',')
+            .map(parse_forwarded_addr)
+            .collect(),
+        (None, None) => return peer,
+    };
+
+    let mut client = peer;
+    for hop in hops.into_iter().rev() {
+        match hop {
+            Some(addr) => {
+                client = addr;
+                if !is_trusted_proxy(&addr, trusted_proxies) {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    client
+}
@@ -0,0 +1,217 @@
+//! Tracing the queries for particular names, to work out why they're being answered the way they are
+//!
+//! When a pattern's set (with `--trace-qname` or `POST /api/admin/trace`), every query whose name matches it gets the
+//! packets, what the datastore decided and how long it took kept in a ring buffer, which `GET /api/admin/trace` hands
+//! back. Nothing's collected for names that don't match, and nothing at all when it's turned off.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::str::from_utf8;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::error::GoatNsError;
+use crate::reply::Reply;
+use crate::servers::QueryProtocol;
+use crate::{Question, HEADER_BYTES};
+
+/// How many traced queries are kept when it's not specified
+pub const DEFAULT_TRACE_CAPACITY: usize = 200;
+/// The most traced queries that'll be kept
+pub const MAX_TRACE_CAPACITY: usize = 10000;
+
+/// One traced query
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct TraceEntry {
+    #[schema(value_type = String)]
+    pub timestamp: DateTime<Utc>,
+    pub qname: String,
+    pub qtype: String,
+    pub protocol: String,
+    /// The query as it came in, as hex
+    pub request: String,
+    /// What we sent back, as hex. Empty if we didn't answer
+    pub response: String,
+    /// What happened along the way
+    pub events: Vec<String>,
+    /// How long it took to answer, in microseconds
+    pub duration_us: u64,
+}
+
+/// What's being traced and what's been caught so far
+#[derive(Debug, Default)]
+pub struct QueryTracer {
+    pattern: Option<Regex>,
+    capacity: usize,
+    entries: VecDeque<TraceEntry>,
+}
+
+/// The trace status, for the API
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct TraceStatus {
+    /// The qname pattern that's being traced, [None] if it's off
+    pub pattern: Option<String>,
+    pub capacity: usize,
+    pub entries: Vec<TraceEntry>,
+}
+
+/// Saves taking the lock for every query when tracing's off, which is nearly always
+static TRACING: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref TRACER: RwLock<QueryTracer> = RwLock::new(QueryTracer::default());
+}
+
+tokio::task_local! {
+    /// Where [note] puts things while a traced query's being answered
+    static EVENTS: RefCell<Vec<String>>;
+}
+
+/// Start tracing names matching `pattern` (a case-insensitive regex), keeping the last `capacity` of them. Anything
+/// already caught is thrown away.
+pub fn enable(pattern: &str, capacity: Option<usize>) -> Result<(), GoatNsError> {
+    let capacity = capacity.unwrap_or(DEFAULT_TRACE_CAPACITY);
+    if capacity == 0 || capacity > MAX_TRACE_CAPACITY {
+        return Err(GoatNsError::InvalidValue(format!(
+            "Trace capacity needs to be between 1 and {MAX_TRACE_CAPACITY}"
+        )));
+    }
+    let regex = Regex::new(&format!("(?i){pattern}"))?;
+    let mut tracer = TRACER
+        .write()
+        .map_err(|err| GoatNsError::Generic(format!("Query tracer lock is poisoned: {err:?}")))?;
+    *tracer = QueryTracer {
+        pattern: Some(regex),
+        capacity,
+        entries: VecDeque::with_capacity(capacity),
+    };
+    TRACING.store(true, Ordering::Relaxed);
+    log::info!("Tracing queries matching {pattern:?}");
+    Ok(())
+}
+
+/// Stop tracing, and throw away what's been caught
+pub fn disable() {
+    TRACING.store(false, Ordering::Relaxed);
+    if let Ok(mut tracer) = TRACER.write() {
+        *tracer = QueryTracer::default();
+    }
+    log::info!("Stopped tracing queries");
+}
+
+/// Is anything being traced?
+pub fn is_enabled() -> bool {
+    TRACING.load(Ordering::Relaxed)
+}
+
+/// What's being traced and what's been caught, oldest first
+pub fn status() -> TraceStatus {
+    match TRACER.read() {
+        Ok(tracer) => TraceStatus {
+            pattern: tracer
+                .pattern
+                .as_ref()
+                .map(|regex| regex.as_str().trim_start_matches("(?i)").to_string()),
+            capacity: tracer.capacity,
+            entries: tracer.entries.iter().cloned().collect(),
+        },
+        Err(_) => TraceStatus {
+            pattern: None,
+            capacity: 0,
+            entries: vec![],
+        },
+    }
+}
+
+/// Would a query for this name be traced?
+pub fn matches(qname: &str) -> bool {
+    match TRACER.read() {
+        Ok(tracer) => tracer
+            .pattern
+            .as_ref()
+            .is_some_and(|regex| regex.is_match(qname.trim_end_matches('.'))),
+        Err(_) => false,
+    }
+}
+
+/// Note down something that happened while answering a query. It's only kept if the query's being traced, and the
+/// message isn't even built otherwise
+pub fn note<F: FnOnce() -> String>(message: F) {
+    let _ = EVENTS.try_with(|events| events.borrow_mut().push(message()));
+}
+
+/// Run the query handler, keeping anything it [note]s
+pub async fn capture<F, T>(handler: F) -> (Vec<String>, T)
+where
+    F: Future<Output = T>,
+{
+    EVENTS
+        .scope(RefCell::new(vec![]), async move {
+            let res = handler.await;
+            let events = EVENTS.with(|events| events.take());
+            (events, res)
+        })
+        .await
+}
+
+/// Keep the query if its name matches the pattern, the oldest one's dropped once it's full
+pub(crate) fn finish(
+    request: &[u8],
+    protocol: QueryProtocol,
+    result: &Result<Reply, String>,
+    mut events: Vec<String>,
+    elapsed: Duration,
+) {
+    // the reply doesn't always have the question in it (eg SERVFAILs), so it's read from the request
+    let question = match request.get(HEADER_BYTES..).map(Question::from_packets) {
+        Some(Ok(question)) => question,
+        _ => return,
+    };
+    let qname = from_utf8(&question.qname)
+        .unwrap_or("<unable to parse>")
+        .to_string();
+    if !matches(&qname) {
+        return;
+    }
+    let response = match result {
+        Ok(reply) => match reply.as_bytes() {
+            Ok(bytes) => hex::encode(bytes),
+            Err(err) => {
+                events.push(format!("Failed to turn the reply into bytes: {err:?}"));
+                String::new()
+            }
+        },
+        Err(err) => {
+            events.push(format!("No reply: {err}"));
+            String::new()
+        }
+    };
+    let entry = TraceEntry {
+        timestamp: Utc::now(),
+        qname,
+        qtype: crate::generic::type_name(question.qtype_code),
+        protocol: protocol.to_string(),
+        request: hex::encode(request),
+        response,
+        events,
+        duration_us: elapsed.as_micros() as u64,
+    };
+    log::trace!("Traced query: {entry:?}");
+    if let Ok(mut tracer) = TRACER.write() {
+        // it might've been turned off while we were answering
+        if tracer.pattern.is_none() {
+            return;
+        }
+        if tracer.entries.len() >= tracer.capacity {
+            tracer.entries.pop_front();
+        }
+        tracer.entries.push_back(entry);
+    }
+}
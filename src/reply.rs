@@ -1,106 +1,310 @@
-use crate::enums::{PacketType, Rcode};
+use crate::edns::Edns;
+use crate::enums::{PacketType, Rcode, RecordType};
 use crate::error::GoatNsError;
 use crate::resourcerecord::{DNSCharString, InternalResourceRecord};
+use crate::utils::{label_suffix_offset, name_as_bytes};
+use crate::zones::{Delegation, NegativeAnswer};
 use crate::{Header, Question};
-use crate::{ResourceRecord, UDP_BUFFER_SIZE};
-use log::error;
+use crate::{ResourceRecord, HEADER_BYTES, UDP_BUFFER_SIZE};
+use bytes::{BufMut, BytesMut};
 use packed_struct::prelude::*;
 
+/// A record in the answer section, and who owns it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Answer {
+    /// The owner name, `None` means it's the question's name (and it's sent with the question's case)
+    pub name: Option<Vec<u8>>,
+    pub record: InternalResourceRecord,
+}
+
+impl Answer {
+    /// A record owned by something other than the question's name, eg further along a CNAME chain
+    pub fn named(name: Vec<u8>, record: InternalResourceRecord) -> Self {
+        Self {
+            name: Some(name),
+            record,
+        }
+    }
+}
+
+impl From<InternalResourceRecord> for Answer {
+    /// An answer for the question's name
+    fn from(record: InternalResourceRecord) -> Self {
+        Self { name: None, record }
+    }
+}
+
+/// The owner name in wire format. Records are named for the question or things above it, so they can usually point
+/// at some part of the question instead of being written out.
+fn owner_name_bytes(qname: Option<&[u8]>, name: &[u8]) -> Result<Vec<u8>, GoatNsError> {
+    match qname.and_then(|qname| label_suffix_offset(qname, name)) {
+        Some(offset) => name_as_bytes(name, Some((HEADER_BYTES + offset) as u16), None),
+        None => name_as_bytes(name, None, None),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Reply {
     pub header: Header,
     pub question: Option<Question>,
-    pub answers: Vec<InternalResourceRecord>,
+    pub answers: Vec<Answer>,
     pub authorities: Vec<ResourceRecord>,
     pub additional: Vec<ResourceRecord>,
+    /// The OPT record goes on the end of the additional section, if the query had one and we're sending options back
+    pub edns: Option<Edns>,
 }
 
 impl Reply {
-    /// This is used to turn into a series of bytes to yeet back to the client, needs to take a mutable self because the answers record length goes into the header
-    pub async fn as_bytes(&self) -> Result<Vec<u8>, GoatNsError> {
-        let mut retval: Vec<u8> = vec![];
+    /// This is used to turn into a series of bytes to yeet back to the client, the answer count in the header is set from the answers as they're written
+    pub fn as_bytes(&self) -> Result<Vec<u8>, GoatNsError> {
+        let mut retval = BytesMut::with_capacity(UDP_BUFFER_SIZE);
 
-        // so we can set the headers
-        let mut final_reply = self.clone();
-        final_reply.header.ancount = final_reply.answers.len() as u16;
+        // so we can set the headers, only the header needs copying
+        let mut header = self.header.clone();
+        header.ancount = self.answers.len() as u16;
+        header.nscount = self.authorities.len() as u16;
+        header.arcount = (self.additional.len() + usize::from(self.edns.is_some())) as u16;
         // use the packed_struct to build the bytes
-        let reply_header = final_reply.header.pack()?;
-        retval.extend(reply_header);
+        retval.put_slice(&header.pack()?);
 
         // need to add the question in here
-        if let Some(question) = &final_reply.question {
-            retval.extend(question.try_to_bytes()?);
-
-            for answer in &final_reply.answers {
-                let ttl: &u32 = match answer {
-                    InternalResourceRecord::A { ttl, .. } => ttl,
-                    InternalResourceRecord::AAAA { ttl, .. } => ttl,
-                    InternalResourceRecord::AXFR { ttl, .. } => ttl,
-                    InternalResourceRecord::CAA { ttl, .. } => ttl,
-                    InternalResourceRecord::CNAME { ttl, .. } => ttl,
-                    InternalResourceRecord::HINFO { ttl, .. } => ttl,
-                    InternalResourceRecord::InvalidType => &1u32,
-                    InternalResourceRecord::LOC { ttl, .. } => ttl,
-                    InternalResourceRecord::MX { ttl, .. } => ttl,
-                    InternalResourceRecord::NAPTR { ttl, .. } => ttl,
-                    InternalResourceRecord::NS { ttl, .. } => ttl,
-                    InternalResourceRecord::PTR { ttl, .. } => ttl,
-                    InternalResourceRecord::SOA { minimum, .. } => minimum,
-                    InternalResourceRecord::TXT { ttl, .. } => ttl,
-                    InternalResourceRecord::URI { ttl, .. } => ttl,
-                };
+        if let Some(question) = &self.question {
+            question.write_to(&mut retval)?;
 
-                let answer_record = ResourceRecord {
-                    name: question.qname.clone(),
-                    record_type: answer.to_owned().into(),
-                    class: question.qclass,
-                    ttl: *ttl,
-                    rdata: answer.as_bytes(&question.qname)?,
+            for answer in &self.answers {
+                let record = &answer.record;
+                let ttl = match record {
+                    InternalResourceRecord::InvalidType => 1u32,
+                    _ => *record.ttl(),
+                };
+                let name_bytes = match &answer.name {
+                    Some(name) => owner_name_bytes(Some(&question.qname), name)?,
+                    None => name_as_bytes(&question.qname, Some(HEADER_BYTES as u16), None)?,
                 };
-                let reply_bytes: Vec<u8> = answer_record.try_into()?;
-                retval.extend(reply_bytes);
+                ResourceRecord::write_record_with_name(
+                    &mut retval,
+                    &name_bytes,
+                    record.rrtype_code(),
+                    question.qclass,
+                    ttl,
+                    &record.as_bytes(&question.qname)?,
+                )?;
             }
         }
 
-        for authority in &final_reply.authorities {
-            error!(
-                "Should be handling authority rr's in reply: {:?}",
-                authority
-            );
+        let qname = self.question.as_ref().map(|q| q.qname.as_slice());
+        for record in self.authorities.iter().chain(&self.additional) {
+            // these are named for the zone or things in it
+            let name_bytes = owner_name_bytes(qname, &record.name)?;
+            ResourceRecord::write_record_with_name(
+                &mut retval,
+                &name_bytes,
+                record.record_type as u16,
+                record.class,
+                record.ttl,
+                &record.rdata,
+            )?;
         }
+        if let Some(edns) = &self.edns {
+            edns.write_to(&mut retval);
+        }
+
+        Ok(retval.into())
+    }
 
-        for additional in &final_reply.additional {
-            error!(
-                "Should be handling additional rr's in reply: {:?}",
-                additional
-            );
+    /// The bytes to send back over UDP to a client that can take `max_size` bytes (see [crate::edns::udp_reply_size]).
+    /// If it doesn't fit, it's sent [Reply::truncated] so they ask again over TCP.
+    pub fn as_bytes_udp(&self, max_size: usize) -> Result<Vec<u8>, GoatNsError> {
+        let result = self.as_bytes()?;
+        if result.len() <= max_size {
+            return Ok(result);
         }
+        self.truncated().as_bytes()
+    }
 
-        Ok(retval)
+    /// The reply with the truncated flag set and the records left out, for when it's too big to send
+    pub fn truncated(&self) -> Reply {
+        let mut header = self.header.clone();
+        header.truncated = true;
+        Self {
+            header,
+            question: self.question.clone(),
+            answers: vec![],
+            authorities: vec![],
+            additional: vec![],
+            edns: self.edns.clone(),
+        }
     }
 
-    /// because sometimes you need to trunc that junk
-    pub async fn as_bytes_udp(&self) -> Result<Vec<u8>, GoatNsError> {
-        let mut result = self.as_bytes().await?;
-        if result.len() > UDP_BUFFER_SIZE {
-            result.truncate(UDP_BUFFER_SIZE);
+    /// The reply without the authority and additional sections, unless it needs them. Referrals and negative answers
+    /// (including a CNAME chain that doesn't end in what was asked for) keep theirs, and so does the OPT record.
+    pub fn minimal(self) -> Reply {
+        let answered = match &self.question {
+            Some(question) => self.answers.iter().any(|answer| {
+                question.qtype == RecordType::ANY
+                    || answer.record.rrtype_code() == question.qtype_code
+            }),
+            None => false,
         };
-        Ok(result)
-    }
-
-    /// checks to see if it's over the max length set in [UDP_BUFFER_SIZE] and set the truncated flag if it is
-    pub async fn check_set_truncated(&self) -> Reply {
-        if let Ok(ret_bytes) = self.as_bytes().await {
-            if ret_bytes.len() > UDP_BUFFER_SIZE {
-                let mut header = self.header.clone();
-                header.truncated = true;
-                return Self {
-                    header,
-                    ..self.clone()
-                };
-            }
+        match self.header.rcode == Rcode::NoError && answered {
+            true => Self {
+                authorities: vec![],
+                additional: vec![],
+                ..self
+            },
+            false => self,
         }
-        self.clone()
+    }
+
+    /// Checks to see if it's over `max_size` bytes, and makes it [Reply::truncated] if it is
+    pub fn check_set_truncated(&self, max_size: usize) -> Reply {
+        match self.as_bytes() {
+            Ok(bytes) if bytes.len() > max_size => self.truncated(),
+            _ => self.clone(),
+        }
+    }
+}
+
+/// Builds a [Reply] to a query, without needing a datastore or any of the servers running. This is the way to use
+/// GoatNS' packet layer from another crate, and it's part of the public API so it follows semver - breaking changes
+/// only come with a new major (or pre-1.0, minor) version.
+///
+/// The header's counts are worked out when the reply's turned into bytes, so there's no need to set them.
+///
+/// ```
+/// use goatns::enums::{Rcode, RecordClass, RecordType};
+/// use goatns::reply::ReplyBuilder;
+/// use goatns::resourcerecord::InternalResourceRecord;
+/// use goatns::{Header, Question};
+///
+/// // normally this comes from the query, eg with `Header::unpack`
+/// let query = Header::new(0x1234);
+/// let reply = ReplyBuilder::new(query)
+///     .question(Question::new(
+///         b"example.com".to_vec(),
+///         RecordType::A,
+///         RecordClass::Internet,
+///     ))
+///     .answer(InternalResourceRecord::A {
+///         address: u32::from(std::net::Ipv4Addr::new(192, 0, 2, 1)),
+///         ttl: 300,
+///         rclass: RecordClass::Internet,
+///     })
+///     .build();
+///
+/// assert_eq!(reply.header.id(), 0x1234);
+/// assert_eq!(reply.header.rcode(), Rcode::NoError);
+/// let packet = reply.as_bytes().expect("failed to encode reply");
+/// // one answer
+/// assert_eq!(packet[7], 1);
+/// ```
+///
+/// Errors are just an [Rcode]:
+///
+/// ```
+/// use goatns::enums::Rcode;
+/// use goatns::reply::ReplyBuilder;
+/// use goatns::Header;
+///
+/// let reply = ReplyBuilder::new(Header::new(1)).rcode(Rcode::Refused).build();
+/// assert_eq!(reply.header.rcode(), Rcode::Refused);
+/// assert!(reply.answers.is_empty());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReplyBuilder {
+    reply: Reply,
+}
+
+impl ReplyBuilder {
+    /// Start a reply to the query with this header. The ID, opcode and recursion desired flag are kept, and the reply's
+    /// authoritative with NOERROR until you say otherwise.
+    pub fn new(query: Header) -> Self {
+        Self {
+            reply: Reply {
+                header: Header {
+                    id: query.id,
+                    qr: PacketType::Answer,
+                    opcode: query.opcode,
+                    authoritative: true,
+                    recursion_desired: query.recursion_desired,
+                    rcode: Rcode::NoError,
+                    ..Header::default()
+                },
+                question: None,
+                answers: vec![],
+                authorities: vec![],
+                additional: vec![],
+                edns: None,
+            },
+        }
+    }
+
+    /// The question being answered, answers are named after it unless they're added with [ReplyBuilder::named_answer]
+    pub fn question(mut self, question: Question) -> Self {
+        self.reply.header.qdcount = 1;
+        self.reply.question = Some(question);
+        self
+    }
+
+    /// Add a record to the answer section
+    pub fn answer(mut self, record: InternalResourceRecord) -> Self {
+        self.reply.answers.push(record.into());
+        self
+    }
+
+    /// Add a record that's owned by another name to the answer section, eg the target of a CNAME
+    pub fn named_answer(mut self, name: &[u8], record: InternalResourceRecord) -> Self {
+        self.reply
+            .answers
+            .push(Answer::named(name.to_vec(), record));
+        self
+    }
+
+    /// Add records to the answer section
+    pub fn answers<I: IntoIterator<Item = InternalResourceRecord>>(mut self, records: I) -> Self {
+        self.reply
+            .answers
+            .extend(records.into_iter().map(Answer::from));
+        self
+    }
+
+    /// Add a record to the authority section
+    pub fn authority(mut self, record: ResourceRecord) -> Self {
+        self.reply.authorities.push(record);
+        self
+    }
+
+    /// Add a record to the additional section
+    pub fn additional(mut self, record: ResourceRecord) -> Self {
+        self.reply.additional.push(record);
+        self
+    }
+
+    /// Send an OPT record back
+    pub fn edns(mut self, edns: Edns) -> Self {
+        self.reply.edns = Some(edns);
+        self
+    }
+
+    /// Set the response code, defaults to NOERROR
+    pub fn rcode(mut self, rcode: Rcode) -> Self {
+        self.reply.header.rcode = rcode;
+        self
+    }
+
+    /// Set the authoritative answer flag, it's on by default since GoatNS is an authoritative server
+    pub fn authoritative(mut self, authoritative: bool) -> Self {
+        self.reply.header.authoritative = authoritative;
+        self
+    }
+
+    /// Finish up, the header's counts match what's in the sections
+    pub fn build(self) -> Reply {
+        let mut reply = self.reply;
+        reply.header.ancount = reply.answers.len() as u16;
+        reply.header.nscount = reply.authorities.len() as u16;
+        reply.header.arcount = (reply.additional.len() + usize::from(reply.edns.is_some())) as u16;
+        reply
     }
 }
 
@@ -118,6 +322,7 @@ pub fn reply_builder(id: u16, rcode: Rcode) -> Result<Reply, String> {
         answers: vec![],
         authorities: vec![],
         additional: vec![],
+        edns: None,
     })
 }
 
@@ -127,6 +332,94 @@ pub fn reply_nxdomain(id: u16) -> Result<Reply, String> {
     reply_builder(id, Rcode::NameError)
 }
 
+/// Build a NXDOMAIN or NODATA response, with the zone's SOA in the authority section so resolvers know how long to cache it for
+pub fn reply_negative(
+    id: u16,
+    question: &Question,
+    negative: &NegativeAnswer,
+) -> Result<Reply, String> {
+    // RFC 2308 - 2.1 Name Error and 2.2 No Data - <https://www.rfc-editor.org/rfc/rfc2308#section-2>
+    let rcode = match negative.name_exists {
+        true => Rcode::NoError,
+        false => Rcode::NameError,
+    };
+    let rdata = negative
+        .soa
+        .as_bytes(&question.qname)
+        .map_err(|err| format!("Failed to encode SOA for negative answer: {err:?}"))?;
+    Ok(Reply {
+        header: Header {
+            id,
+            qr: PacketType::Answer,
+            rcode,
+            authoritative: true,
+            qdcount: 1,
+            nscount: 1,
+            ..Header::default()
+        },
+        question: Some(question.clone()),
+        answers: vec![],
+        authorities: vec![ResourceRecord {
+            name: negative.zone.clone(),
+            record_type: RecordType::SOA,
+            class: question.qclass,
+            // the SOA minimum is the negative caching TTL
+            ttl: *negative.soa.ttl(),
+            rdata,
+        }],
+        additional: vec![],
+        edns: None,
+    })
+}
+
+/// Refer the query to the name servers for a delegated child zone, with glue for any that are inside it
+pub fn reply_referral(
+    id: u16,
+    question: &Question,
+    delegation: &Delegation,
+) -> Result<Reply, String> {
+    let to_record = |name: &[u8], record: &InternalResourceRecord| {
+        Ok::<_, String>(ResourceRecord {
+            name: name.to_vec(),
+            record_type: record.into(),
+            class: question.qclass,
+            ttl: *record.ttl(),
+            rdata: record
+                .as_bytes(&question.qname)
+                .map_err(|err| format!("Failed to encode referral record: {err:?}"))?,
+        })
+    };
+    let authorities = delegation
+        .nameservers
+        .iter()
+        .map(|ns| to_record(&delegation.name, ns))
+        .collect::<Result<Vec<_>, _>>()?;
+    let additional = delegation
+        .glue
+        .iter()
+        .map(|(name, address)| to_record(name, address))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // RFC 1034 4.3.2 - referrals aren't authoritative, the child zone is
+    Ok(Reply {
+        header: Header {
+            id,
+            qr: PacketType::Answer,
+            rcode: Rcode::NoError,
+            authoritative: false,
+            qdcount: 1,
+            nscount: authorities.len() as u16,
+            arcount: additional.len() as u16,
+            ..Header::default()
+        },
+        question: Some(question.clone()),
+        answers: vec![],
+        authorities,
+        additional,
+        edns: None,
+    })
+}
+
 /// Reply to an ANY request with a HINFO "RFC8482" "" response
 pub fn reply_any(id: u16, question: &Question) -> Result<Reply, String> {
     Ok(Reply {
@@ -145,8 +438,10 @@ pub fn reply_any(id: u16, question: &Question) -> Result<Reply, String> {
             os: Some(DNSCharString::from("")),
             ttl: 3789,
             rclass: question.qclass,
-        }],
+        }
+        .into()],
         authorities: vec![],
         additional: vec![],
+        edns: None,
     })
 }
@@ -1,6 +1,6 @@
 use crate::enums::{RecordClass, RecordType};
 use crate::error::GoatNsError;
-use crate::utils::{dms_to_u32, hexdump, name_as_bytes};
+use crate::utils::{dms_to_u32, hexdump, label_suffix_offset, name_as_bytes};
 use crate::zones::FileZoneRecord;
 use crate::HEADER_BYTES;
 use core::fmt::Debug;
@@ -10,7 +10,6 @@ use num_traits::Num;
 use packed_struct::prelude::*;
 use regex::Regex;
 use serde::{Deserialize, Serialize, Serializer};
-use std::env::consts;
 use std::str::{from_utf8, FromStr};
 use std::string::FromUtf8Error;
 use tracing::error;
@@ -124,8 +123,31 @@ impl From<DNSCharString> for Vec<u8> {
     }
 }
 
+/// The longest a `<character-string>` can be, longer TXT data has to be split into several of them
+pub const CHARACTER_STRING_MAX: usize = 255;
+
 impl DNSCharString {
-    /// Returns the bytes for a packet, ie - the length and then the string
+    /// The data split into `<character-string>`-sized pieces, there's always at least one (even if it's empty)
+    pub fn chunks(&self) -> Vec<&[u8]> {
+        if self.data.is_empty() {
+            return vec![&[]];
+        }
+        self.data.chunks(CHARACTER_STRING_MAX).collect()
+    }
+
+    /// The bytes for a packet with the data split into as many `<character-string>`s as it needs, which is what TXT
+    /// records do with long values
+    pub fn as_split_bytes(&self) -> Vec<u8> {
+        let mut res: Vec<u8> =
+            Vec::with_capacity(self.data.len() + self.data.len() / CHARACTER_STRING_MAX + 1);
+        for chunk in self.chunks() {
+            res.push(chunk.len() as u8);
+            res.extend(chunk);
+        }
+        res
+    }
+
+    /// Returns the bytes for a packet, ie - the length and then the string, anything past 255 bytes is dropped
     fn as_bytes(&self) -> Vec<u8> {
         let mut res: Vec<u8> = self.data.to_vec();
         // <character-string> is a single length octet followed by that number of characters.  <character-string> is treated as binary information, and can be up to 256 characters in length (including the length octet).
@@ -135,6 +157,30 @@ impl DNSCharString {
     }
 }
 
+/// Splits TXT rdata into the `<character-string>`s it was written as. Rdata that's quoted strings in zone file style
+/// (eg `"v=spf1 include:example.net " "-all"`) keeps each string separate, anything else is one string. Either way,
+/// strings over 255 bytes get split up when they're sent.
+pub fn parse_txt_rdata(rdata: &str) -> Vec<DNSCharString> {
+    let trimmed = rdata.trim();
+    if trimmed.len() > 1 && trimmed.starts_with('"') && trimmed.ends_with('"') {
+        if let Ok(fields) = split_quoted_fields(trimmed) {
+            return fields
+                .iter()
+                .map(|field| DNSCharString::from(field.as_str()))
+                .collect();
+        }
+    }
+    vec![DNSCharString::from(rdata)]
+}
+
+/// What a TXT record's strings add up to, which is how most things (SPF, DKIM and so on) read them
+pub fn txt_value(rdata: &str) -> String {
+    parse_txt_rdata(rdata)
+        .iter()
+        .map(|string| String::from_utf8_lossy(&string.data).to_string())
+        .collect()
+}
+
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 /// Internal representation of a resource record
@@ -152,6 +198,12 @@ pub enum InternalResourceRecord {
         ttl: u32,
         rclass: RecordClass,
     }, // 28 https://www.rfc-editor.org/rfc/rfc3596#section-2.1
+    /// Apex CNAME workaround, the target's A/AAAA records get served in its place
+    ALIAS {
+        target: DomainName,
+        ttl: u32,
+        rclass: RecordClass,
+    },
     AXFR {
         ttl: u32,
         rclass: RecordClass,
@@ -171,6 +223,12 @@ pub enum InternalResourceRecord {
         ttl: u32,
         rclass: RecordClass,
     }, // 5 the canonical name for an alias
+    /// Answered by asking the `forward.upstreams` about the target, see [crate::forward]
+    FORWARD {
+        target: DomainName,
+        ttl: u32,
+        rclass: RecordClass,
+    },
     LOC {
         ttl: u32,
         rclass: RecordClass,
@@ -228,6 +286,12 @@ pub enum InternalResourceRecord {
         // aware that this may cause loop conditions and should act
         // accordingly.
         flags: String,
+        /// Specifies the service(s) available down this rewrite path, eg `E2U+sip` for ENUM
+        services: DNSCharString,
+        /// The substitution expression applied to the original string held by the client, eg `!^.*$!sip:info@example.com!`
+        regexp: DNSCharString,
+        /// The next domain name to query for, `.` if the regexp is used instead
+        replacement: DomainName,
     },
     NS {
         nsdname: DomainName,
@@ -268,7 +332,8 @@ pub enum InternalResourceRecord {
         rclass: RecordClass,
     }, // 15 mail exchange
     TXT {
-        txtdata: DNSCharString,
+        /// Each of these is sent as one or more `<character-string>`s, see [parse_txt_rdata]
+        txtdata: Vec<DNSCharString>,
         ttl: u32,
         class: RecordClass,
     }, // 16 text strings
@@ -279,9 +344,60 @@ pub enum InternalResourceRecord {
         ttl: u32,
         rclass: RecordClass,
     },
+    /// A type we don't handle ourselves, the rdata's stored and served as-is. Ref RFC3597.
+    Generic {
+        rrtype: u16,
+        rdata: Vec<u8>,
+        ttl: u32,
+        rclass: RecordClass,
+    },
     InvalidType,
 }
 
+/// Splits rdata on whitespace, keeping "quoted strings" together (without their quotes) and handling `\"` and `\\` escapes inside them
+pub fn split_quoted_fields(input: &str) -> Result<Vec<String>, GoatNsError> {
+    let mut fields: Vec<String> = vec![];
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut field = String::new();
+        if c == '"' {
+            let mut closed = false;
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => match chars.next() {
+                        Some(escaped) => field.push(escaped),
+                        None => break,
+                    },
+                    '"' => {
+                        closed = true;
+                        break;
+                    }
+                    _ => field.push(c),
+                }
+            }
+            if !closed {
+                return Err(GoatNsError::Generic(format!(
+                    "Unterminated quoted string in {input:?}"
+                )));
+            }
+        } else {
+            field.push(c);
+            while let Some(c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                field.push(*c);
+                chars.next();
+            }
+        }
+        fields.push(field);
+    }
+    Ok(fields)
+}
+
 impl TryFrom<FileZoneRecord> for InternalResourceRecord {
     type Error = GoatNsError;
     /// This is where we convert from the JSON blob in the file to an internal representation of the data.
@@ -295,7 +411,30 @@ impl TryFrom<FileZoneRecord> for InternalResourceRecord {
             record.name.len())));
         };
 
-        match record.rrtype.as_str() {
+        if crate::generic::is_generic_rdata(&record.rdata) {
+            let rrtype = crate::generic::parse_type(&record.rrtype).ok_or_else(|| {
+                GoatNsError::Generic(format!("Invalid type specified: {:?}", record.rrtype))
+            })?;
+            if crate::generic::is_meta_type(rrtype) {
+                return Err(GoatNsError::Generic(format!(
+                    "{} records can't be stored with generic rdata",
+                    record.rrtype
+                )));
+            }
+            return Ok(InternalResourceRecord::Generic {
+                rrtype,
+                rdata: crate::generic::parse_rdata(&record.rdata)?,
+                ttl: record.ttl,
+                rclass: record.class,
+            });
+        }
+
+        // TYPE1 is just another way of saying A
+        let rrtype = match crate::generic::parse_type(&record.rrtype) {
+            Some(code) => crate::generic::type_name(code),
+            None => record.rrtype.clone(),
+        };
+        match rrtype.as_str() {
             "A" => {
                 let address: u32 = match std::net::Ipv4Addr::from_str(&record.rdata) {
                     Ok(value) => value.into(),
@@ -335,6 +474,16 @@ impl TryFrom<FileZoneRecord> for InternalResourceRecord {
                     rclass: record.class,
                 })
             }
+            "ALIAS" => Ok(InternalResourceRecord::ALIAS {
+                target: DomainName::from(record.rdata),
+                ttl: record.ttl,
+                rclass: record.class,
+            }),
+            "FORWARD" => Ok(InternalResourceRecord::FORWARD {
+                target: DomainName::from(record.rdata),
+                ttl: record.ttl,
+                rclass: record.class,
+            }),
             "CNAME" => Ok(InternalResourceRecord::CNAME {
                 cname: DomainName::from(record.rdata),
                 ttl: record.ttl,
@@ -346,9 +495,7 @@ impl TryFrom<FileZoneRecord> for InternalResourceRecord {
                 rclass: record.class,
             }),
             "TXT" => Ok(InternalResourceRecord::TXT {
-                txtdata: DNSCharString {
-                    data: record.rdata.into_bytes(),
-                },
+                txtdata: parse_txt_rdata(&record.rdata),
                 ttl: record.ttl,
                 class: record.class,
             }),
@@ -377,6 +524,81 @@ impl TryFrom<FileZoneRecord> for InternalResourceRecord {
                     rclass: record.class,
                 })
             }
+            "HINFO" => {
+                // "cpu" "os"
+                let fields = split_quoted_fields(&record.rdata)?;
+                if fields.len() != 2 {
+                    return Err(GoatNsError::Generic(format!(
+                        "While trying to parse HINFO record, got {} fields in {:?}, expected 2.",
+                        fields.len(),
+                        record.rdata
+                    )));
+                }
+                if fields.iter().any(|field| field.len() > 255) {
+                    return Err(GoatNsError::Generic(format!(
+                        "HINFO fields can't be longer than 255 characters: {:?}",
+                        record.rdata
+                    )));
+                }
+                Ok(InternalResourceRecord::HINFO {
+                    cpu: Some(DNSCharString::from(fields[0].as_str())),
+                    os: Some(DNSCharString::from(fields[1].as_str())),
+                    ttl: record.ttl,
+                    rclass: record.class,
+                })
+            }
+            "NAPTR" => {
+                // order preference "flags" "services" "regexp" replacement
+                let fields = split_quoted_fields(&record.rdata)?;
+                if fields.len() != 6 {
+                    return Err(GoatNsError::Generic(format!(
+                        "While trying to parse NAPTR record, got {} fields in {:?}, expected 6.",
+                        fields.len(),
+                        record.rdata
+                    )));
+                }
+                let order = u16::from_str(&fields[0]).map_err(|error| {
+                    GoatNsError::Generic(format!(
+                        "Failed to parse NAPTR order {} into number: {:?}",
+                        fields[0], error
+                    ))
+                })?;
+                let preference = u16::from_str(&fields[1]).map_err(|error| {
+                    GoatNsError::Generic(format!(
+                        "Failed to parse NAPTR preference {} into number: {:?}",
+                        fields[1], error
+                    ))
+                })?;
+                if !fields[2].chars().all(|c| c.is_ascii_alphanumeric()) {
+                    return Err(GoatNsError::Generic(format!(
+                        "NAPTR flags can only be A-Z and 0-9, got {:?}",
+                        fields[2]
+                    )));
+                }
+                for field in &fields[2..5] {
+                    if field.len() > 255 {
+                        return Err(GoatNsError::Generic(format!(
+                            "NAPTR field is longer than 255 characters: {field:?}"
+                        )));
+                    }
+                }
+                if !fields[4].is_empty() && fields[5] != "." {
+                    return Err(GoatNsError::Generic(
+                        "NAPTR records can have a regexp or a replacement, not both".to_string(),
+                    ));
+                }
+                Ok(InternalResourceRecord::NAPTR {
+                    ttl: record.ttl,
+                    rclass: record.class,
+                    domain: DomainName::from(record.name.as_str()),
+                    order,
+                    preference,
+                    flags: fields[2].to_uppercase(),
+                    services: DNSCharString::from(fields[3].as_str()),
+                    regexp: DNSCharString::from(fields[4].as_str()),
+                    replacement: DomainName::from(fields[5].as_str()),
+                })
+            }
             "NS" => Ok(InternalResourceRecord::NS {
                 nsdname: DomainName::from(record.rdata),
                 ttl: record.ttl,
@@ -491,6 +713,11 @@ impl TryFrom<FileZoneRecord> for InternalResourceRecord {
                     rclass: record.class,
                 })
             }
+            _ if crate::generic::parse_type(&rrtype).is_some() => {
+                Err(GoatNsError::Generic(format!(
+                    "{rrtype} records need their rdata in the generic format, eg: \\# 4 0a000001"
+                )))
+            }
             _ => Err(GoatNsError::Generic("Invalid type specified!".to_string())),
         }
     }
@@ -510,9 +737,11 @@ impl PartialEq<RecordType> for InternalResourceRecord {
         match self {
             InternalResourceRecord::A { .. } => other == &RecordType::A,
             InternalResourceRecord::AAAA { .. } => other == &RecordType::AAAA,
+            InternalResourceRecord::ALIAS { .. } => other == &RecordType::ALIAS,
             InternalResourceRecord::AXFR { .. } => other == &RecordType::AXFR,
             InternalResourceRecord::CAA { .. } => other == &RecordType::CAA,
             InternalResourceRecord::CNAME { .. } => other == &RecordType::CNAME,
+            InternalResourceRecord::FORWARD { .. } => other == &RecordType::FORWARD,
             InternalResourceRecord::HINFO { .. } => other == &RecordType::HINFO,
             InternalResourceRecord::InvalidType => other == &RecordType::InvalidType,
             InternalResourceRecord::LOC { .. } => other == &RecordType::LOC,
@@ -523,6 +752,7 @@ impl PartialEq<RecordType> for InternalResourceRecord {
             InternalResourceRecord::SOA { .. } => other == &RecordType::SOA,
             InternalResourceRecord::TXT { .. } => other == &RecordType::TXT,
             InternalResourceRecord::URI { .. } => other == &RecordType::URI,
+            InternalResourceRecord::Generic { rrtype, .. } => *rrtype == *other as u16,
         }
     }
 }
@@ -540,6 +770,10 @@ impl InternalResourceRecord {
                 log::trace!("turning CNAME {cname:?} into bytes");
                 cname.as_bytes(Some(HEADER_BYTES as u16), Some(question))
             }
+            InternalResourceRecord::ALIAS { target, .. }
+            | InternalResourceRecord::FORWARD { target, .. } => {
+                target.as_bytes(Some(HEADER_BYTES as u16), Some(question))
+            }
             InternalResourceRecord::LOC {
                 ttl,
                 version,
@@ -581,9 +815,19 @@ impl InternalResourceRecord {
                 ..
             } => {
                 let zone_as_bytes = zone.name.as_bytes().to_vec();
-                let mut res: Vec<u8> =
-                    mname.as_bytes(Some(HEADER_BYTES as u16), Some(&zone_as_bytes))?;
-                res.extend(rname.as_bytes(Some(HEADER_BYTES as u16), Some(&zone_as_bytes))?);
+                // the zone's usually the question, but in the authority section of a negative answer it's the tail end of it
+                let zone_offset = label_suffix_offset(question, &zone_as_bytes);
+                let soa_name = |name: &DomainName| match zone_offset {
+                    Some(0) => name.as_bytes(Some(HEADER_BYTES as u16), Some(&zone_as_bytes)),
+                    Some(offset)
+                        if label_suffix_offset(name.name.as_bytes(), &zone_as_bytes).is_some() =>
+                    {
+                        name.as_bytes(Some((HEADER_BYTES + offset) as u16), Some(&zone_as_bytes))
+                    }
+                    _ => name.as_bytes(None, None),
+                };
+                let mut res: Vec<u8> = soa_name(mname)?;
+                res.extend(soa_name(rname)?);
                 res.extend(serial.to_be_bytes());
                 res.extend(refresh.to_be_bytes());
                 res.extend(retry.to_be_bytes());
@@ -591,7 +835,10 @@ impl InternalResourceRecord {
                 res.extend(minimum.to_be_bytes());
                 Ok(res)
             }
-            InternalResourceRecord::TXT { txtdata, .. } => Ok(txtdata.as_bytes()),
+            InternalResourceRecord::TXT { txtdata, .. } => Ok(txtdata
+                .iter()
+                .flat_map(DNSCharString::as_split_bytes)
+                .collect()),
             InternalResourceRecord::URI {
                 priority,
                 weight,
@@ -604,27 +851,16 @@ impl InternalResourceRecord {
                 res.extend(&target.data);
                 Ok(res)
             }
+            InternalResourceRecord::Generic { rdata, .. } => Ok(rdata.clone()),
             InternalResourceRecord::HINFO { cpu, os, .. } => {
                 let mut hinfo_bytes: Vec<u8> = vec![];
-                match cpu {
-                    Some(value) => {
-                        hinfo_bytes.extend(&value.as_bytes());
-                    }
-                    None => {
-                        hinfo_bytes.extend([consts::ARCH.len() as u8]);
-                        hinfo_bytes.extend(consts::ARCH.as_bytes());
-                    }
-                };
-
-                match os {
-                    Some(value) => {
-                        hinfo_bytes.extend(&value.as_bytes());
-                    }
-                    None => {
-                        hinfo_bytes.extend([consts::OS.len() as u8]);
-                        hinfo_bytes.extend(consts::OS.as_bytes());
+                // unset fields go out as empty strings, we don't want to tell the world what we're running on
+                for field in [cpu, os] {
+                    match field {
+                        Some(value) => hinfo_bytes.extend(&value.as_bytes()),
+                        None => hinfo_bytes.push(0),
                     }
-                };
+                }
                 Ok(hinfo_bytes)
             }
             // InternalResourceRecord::MINFO { ttl, .. } => ttl(),
@@ -653,9 +889,27 @@ impl InternalResourceRecord {
 
                 Ok(result)
             }
-            InternalResourceRecord::NAPTR { .. } => {
-                error!("Asked for an NAPTR as_bytes, returning null");
-                Ok(Vec::new())
+            InternalResourceRecord::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+                ..
+            } => {
+                let mut res: Vec<u8> = vec![];
+                res.extend(order.to_be_bytes());
+                res.extend(preference.to_be_bytes());
+                res.extend(DNSCharString::from(flags.as_str()).as_bytes());
+                res.extend(services.as_bytes());
+                res.extend(regexp.as_bytes());
+                // RFC3403 4.1 - the replacement isn't allowed to be compressed
+                match replacement.name.trim_end_matches('.') {
+                    "" => res.push(0),
+                    name => res.extend(name_as_bytes(name.as_bytes(), None, None)?),
+                }
+                Ok(res)
             }
         }
     }
@@ -675,9 +929,11 @@ impl InternalResourceRecord {
         match self {
             InternalResourceRecord::A { ttl, .. } => ttl,
             InternalResourceRecord::AAAA { ttl, .. } => ttl,
+            InternalResourceRecord::ALIAS { ttl, .. } => ttl,
             InternalResourceRecord::AXFR { ttl, .. } => ttl,
             InternalResourceRecord::CAA { ttl, .. } => ttl,
             InternalResourceRecord::CNAME { ttl, .. } => ttl,
+            InternalResourceRecord::FORWARD { ttl, .. } => ttl,
             InternalResourceRecord::LOC { ttl, .. } => ttl,
             InternalResourceRecord::NAPTR { ttl, .. } => ttl,
             InternalResourceRecord::NS { ttl, .. } => ttl,
@@ -687,9 +943,18 @@ impl InternalResourceRecord {
             InternalResourceRecord::MX { ttl, .. } => ttl,
             InternalResourceRecord::TXT { ttl, .. } => ttl,
             InternalResourceRecord::URI { ttl, .. } => ttl,
+            InternalResourceRecord::Generic { ttl, .. } => ttl,
             InternalResourceRecord::InvalidType => &0,
         }
     }
+
+    /// The type code that goes on the wire, this is how you get the type of [InternalResourceRecord::Generic] records
+    pub fn rrtype_code(&self) -> u16 {
+        match self {
+            InternalResourceRecord::Generic { rrtype, .. } => *rrtype,
+            _ => RecordType::from(self) as u16,
+        }
+    }
 }
 
 pub trait SetTTL {
@@ -713,6 +978,11 @@ impl SetTTL for InternalResourceRecord {
                 rclass,
                 ttl,
             },
+            Self::ALIAS { target, rclass, .. } => Self::ALIAS {
+                target,
+                ttl,
+                rclass,
+            },
             Self::AXFR { rclass, .. } => Self::AXFR { ttl, rclass },
             Self::CAA {
                 flag,
@@ -728,6 +998,11 @@ impl SetTTL for InternalResourceRecord {
                 ttl,
             },
             Self::CNAME { cname, rclass, .. } => Self::CNAME { cname, ttl, rclass },
+            Self::FORWARD { target, rclass, .. } => Self::FORWARD {
+                target,
+                ttl,
+                rclass,
+            },
             Self::LOC {
                 rclass,
                 version,
@@ -755,6 +1030,9 @@ impl SetTTL for InternalResourceRecord {
                 order,
                 preference,
                 flags,
+                services,
+                regexp,
+                replacement,
                 ..
             } => Self::NAPTR {
                 rclass,
@@ -762,6 +1040,9 @@ impl SetTTL for InternalResourceRecord {
                 order,
                 preference,
                 flags,
+                services,
+                regexp,
+                replacement,
                 ttl,
             },
             Self::NS {
@@ -840,6 +1121,17 @@ impl SetTTL for InternalResourceRecord {
                 rclass,
                 ttl,
             },
+            Self::Generic {
+                rrtype,
+                rdata,
+                rclass,
+                ..
+            } => Self::Generic {
+                rrtype,
+                rdata,
+                ttl,
+                rclass,
+            },
             //  Self::InvalidType => &0,
             _ => {
                 log::error!("Tried to set TTL on an invalid type! {:?}", self);
@@ -891,6 +1183,9 @@ mod tests {
             class: RecordClass::Internet,
             zoneid: Some(1),
             id: None,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
         };
         debug!("fzr: {fzr}");
         let converted = match Ipv6Addr::from_str(&fzr.rdata) {
@@ -920,12 +1215,12 @@ mod tests {
     #[test]
     fn resourcerecord_txt() {
         let foo = InternalResourceRecord::TXT {
-            txtdata: DNSCharString::from("Hello world"),
+            txtdata: vec![DNSCharString::from("Hello world")],
             ttl: 1,
             class: RecordClass::Internet,
         };
         if let InternalResourceRecord::TXT { txtdata, .. } = foo {
-            let foo_bytes: Vec<u8> = txtdata.into();
+            let foo_bytes: Vec<u8> = txtdata[0].clone().into();
             assert_eq!(foo_bytes[0], 11);
         };
     }
@@ -1168,6 +1463,115 @@ impl TryFrom<&str> for FileLocRecord {
     }
 }
 
+/// The lowest altitude a LOC record can have, 100km below the WGS 84 spheroid
+pub const LOC_MIN_ALTITUDE: i32 = -100_000;
+/// The highest altitude we'll take, 10,000km up is plenty
+pub const LOC_MAX_ALTITUDE: i32 = 10_000_000;
+/// The biggest size or precision a LOC record can encode, 9 * 10^9 cm
+pub const LOC_MAX_PRECISION: u32 = 90_000_000;
+
+fn default_loc_size() -> u32 {
+    DEFAULT_LOC_SIZE
+}
+fn default_loc_horiz_pre() -> u32 {
+    DEFAULT_LOC_HORIZ_PRE
+}
+fn default_loc_vert_pre() -> u32 {
+    DEFAULT_LOC_VERT_PRE
+}
+
+/// A location in decimal degrees, the way maps and phones hand them out, for turning into LOC rdata without
+/// having to work out degrees, minutes and seconds by hand
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, utoipa::ToSchema)]
+pub struct DecimalLocation {
+    /// -90 to 90, north is positive
+    pub latitude: f64,
+    /// -180 to 180, east is positive
+    pub longitude: f64,
+    /// In metres above the WGS 84 spheroid
+    #[serde(default)]
+    pub altitude: i32,
+    /// The diameter of the thing being described, in metres
+    #[serde(default = "default_loc_size")]
+    pub size: u32,
+    /// How accurate the position is, in metres
+    #[serde(default = "default_loc_horiz_pre")]
+    pub horiz_pre: u32,
+    /// How accurate the altitude is, in metres
+    #[serde(default = "default_loc_vert_pre")]
+    pub vert_pre: u32,
+}
+
+impl DecimalLocation {
+    /// Makes sure it'll fit in a LOC record
+    pub fn validate(&self) -> Result<(), GoatNsError> {
+        if !(-90.0..=90.0).contains(&self.latitude) {
+            return Err(GoatNsError::InvalidValue(
+                "Latitude needs to be between -90 and 90".to_string(),
+            ));
+        }
+        if !(-180.0..=180.0).contains(&self.longitude) {
+            return Err(GoatNsError::InvalidValue(
+                "Longitude needs to be between -180 and 180".to_string(),
+            ));
+        }
+        if !(LOC_MIN_ALTITUDE..=LOC_MAX_ALTITUDE).contains(&self.altitude) {
+            return Err(GoatNsError::InvalidValue(format!(
+                "Altitude needs to be between {LOC_MIN_ALTITUDE}m and {LOC_MAX_ALTITUDE}m"
+            )));
+        }
+        for (field, value) in [
+            ("Size", self.size),
+            ("Horizontal precision", self.horiz_pre),
+            ("Vertical precision", self.vert_pre),
+        ] {
+            if value > LOC_MAX_PRECISION {
+                return Err(GoatNsError::InvalidValue(format!(
+                    "{field} can't be more than {LOC_MAX_PRECISION}m"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The LOC rdata, eg `51 30 12.748 N 0 7 39.611 W 0m 1m 10000m 10m`
+    pub fn to_rdata(&self) -> Result<String, GoatNsError> {
+        self.validate()?;
+        let (d1, m1, s1) = decimal_to_dms(self.latitude);
+        let (d2, m2, s2) = decimal_to_dms(self.longitude);
+        let lat_dir = match self.latitude < 0.0 {
+            true => "S",
+            false => "N",
+        };
+        let lon_dir = match self.longitude < 0.0 {
+            true => "W",
+            false => "E",
+        };
+        Ok(format!(
+            "{d1} {m1} {}.{:03} {lat_dir} {d2} {m2} {}.{:03} {lon_dir} {}m {}m {}m {}m",
+            s1 / 1000,
+            s1 % 1000,
+            s2 / 1000,
+            s2 % 1000,
+            self.altitude,
+            self.size,
+            self.horiz_pre,
+            self.vert_pre
+        ))
+    }
+}
+
+/// Splits decimal degrees into degrees, minutes and thousandths of a second (the most LOC can hold), ignoring the
+/// sign. Rounding happens on the thousandths so you never end up with 60 seconds.
+pub fn decimal_to_dms(degrees: f64) -> (u8, u8, u32) {
+    let total = (degrees.abs() * 3_600_000.0).round() as u64;
+    (
+        (total / 3_600_000) as u8,
+        ((total / 60_000) % 60) as u8,
+        (total % 60_000) as u32,
+    )
+}
+
 /// tests to ensure that no label in the name is longer than 63 octets (bytes)
 pub fn check_long_labels(testval: &str) -> bool {
     testval.split('.').any(|x| x.len() > 63)
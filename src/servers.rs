@@ -1,32 +1,74 @@
 use concread::cowcell::asynch::CowCellReadTxn;
+use ipnet::IpNet;
 use packed_struct::prelude::*;
 use std::io::Error;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::str::from_utf8;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
-use tokio::io::{self, AsyncReadExt};
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream, UdpSocket};
-use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{broadcast, mpsc, oneshot, Semaphore};
 use tokio::task::JoinHandle;
 use tokio::time::timeout;
 use tracing::{error, field, instrument};
 
-use crate::config::ConfigFile;
+use crate::config::{ConfigFile, DisabledZoneRcode, QueryChecks};
 use crate::datastore::Command;
+use crate::edns::{udp_reply_size, Edns, EDNS_TCP_KEEPALIVE};
 use crate::enums::{Agent, AgentState, PacketType, Rcode, RecordClass, RecordType};
 use crate::error::GoatNsError;
-use crate::reply::{reply_any, reply_builder, reply_nxdomain, Reply};
+use crate::forward::Forwarded;
+use crate::health::{self_check_loop, Health, HEALTH};
+use crate::metrics::{Metrics, METRICS};
+use crate::proxy::{is_trusted_proxy, read_proxy_v2};
+use crate::query_trace::note;
+use crate::reply::{
+    reply_any, reply_builder, reply_negative, reply_nxdomain, reply_referral, Answer, Reply,
+};
 use crate::resourcerecord::{DNSCharString, InternalResourceRecord};
-use crate::zones::ZoneRecord;
-use crate::{Header, OpCode, Question, HEADER_BYTES, REPLY_TIMEOUT_MS, UDP_BUFFER_SIZE};
+use crate::utils::{bind_tcp_listener, bind_udp_socket};
+use crate::zones::{Delegation, NegativeAnswer, ZoneRecord};
+use crate::{
+    Header, OpCode, Question, HEADER_BYTES, MAX_IN_FLIGHT, REPLY_TIMEOUT_MS, UDP_BUFFER_SIZE,
+};
+
+static DISABLED_ZONE_RCODE: RwLock<DisabledZoneRcode> = RwLock::new(DisabledZoneRcode::Refused);
+
+/// Set what queries for disabled zones get, from the config at startup
+pub fn init_disabled_zone_rcode(rcode: DisabledZoneRcode) {
+    match DISABLED_ZONE_RCODE.write() {
+        Ok(mut current) => *current = rcode,
+        Err(err) => log::error!("Failed to set the disabled zone rcode: {err:?}"),
+    }
+}
+
+/// Leave out the authority and additional sections when they're not needed, see [Reply::minimal]
+static MINIMAL_RESPONSES: AtomicBool = AtomicBool::new(false);
+
+/// Turn minimal responses on or off, from the config at startup
+pub fn init_minimal_responses(minimal: bool) {
+    MINIMAL_RESPONSES.store(minimal, Ordering::Relaxed);
+}
+
+fn disabled_zone_rcode() -> Rcode {
+    DISABLED_ZONE_RCODE
+        .read()
+        .map(|rcode| Rcode::from(*rcode))
+        .unwrap_or(Rcode::Refused)
+}
 
 pub(crate) enum ChaosResult {
+    /// It's not a shutdown, send it back the way it is
+    Answer(Reply),
     Refused(Reply),
     Shutdown(Reply),
 }
 
 /// this handles a shutdown CHAOS request
-async fn check_for_shutdown(r: &Reply, allowed_shutdown: bool) -> Result<ChaosResult, GoatNsError> {
+async fn check_for_shutdown(r: Reply, allowed_shutdown: bool) -> Result<ChaosResult, GoatNsError> {
     // when you get a CHAOS from localhost with "shutdown" break dat loop
     if let Some(q) = &r.question {
         if q.qclass == RecordClass::Chaos {
@@ -37,20 +79,20 @@ async fn check_for_shutdown(r: &Reply, allowed_shutdown: bool) -> Result<ChaosRe
                 );
             })?;
             // Just don't do this on UDP, because we can't really tell who it's coming from.
-            if qname == "shutdown" {
+            if qname.eq_ignore_ascii_case("shutdown") {
                 // when we get a request, we update the response to say if we're going to do it or not
                 match allowed_shutdown {
                     true => {
                         log::info!("Got CHAOS shutdown, shutting down");
-                        let mut chaos_reply = r.clone();
-                        chaos_reply.answers.push(CHAOS_OK.clone());
+                        let mut chaos_reply = r;
+                        chaos_reply.answers.push(CHAOS_OK.clone().into());
                         return Ok(ChaosResult::Shutdown(chaos_reply));
                     }
                     false => {
                         // get lost!  🤣
                         log::warn!("Got CHAOS shutdown, ignoring!");
-                        let mut chaos_reply = r.clone();
-                        chaos_reply.answers.push(CHAOS_NO.clone());
+                        let mut chaos_reply = r;
+                        chaos_reply.answers.push(CHAOS_NO.clone().into());
                         chaos_reply.header.rcode = Rcode::Refused;
                         return Ok(ChaosResult::Refused(chaos_reply));
                     }
@@ -59,10 +101,7 @@ async fn check_for_shutdown(r: &Reply, allowed_shutdown: bool) -> Result<ChaosRe
         }
     };
 
-    let mut chaos_reply = r.clone();
-    chaos_reply.answers.push(CHAOS_NO.clone());
-    chaos_reply.header.rcode = Rcode::Refused;
-    Ok(ChaosResult::Refused(chaos_reply))
+    Ok(ChaosResult::Answer(r))
 }
 
 // this handles a version CHAOS request
@@ -87,26 +126,37 @@ async fn check_for_shutdown(r: &Reply, allowed_shutdown: bool) -> Result<ChaosRe
 
 pub async fn udp_server(
     config: CowCellReadTxn<ConfigFile>,
-    datastore_sender: mpsc::Sender<crate::datastore::Command>,
+    datastore_sender: mpsc::Sender<crate::datastore::Queued>,
     _agent_tx: broadcast::Sender<AgentState>,
 ) -> io::Result<()> {
-    let udp_sock = match UdpSocket::bind(config.dns_listener_address().map_err(|_err| {
+    let listen_addr = config.dns_listener_address().map_err(|_err| {
         GoatNsError::StartupError("Failed to get DNS listener address on startup!".to_string())
-    })?)
-    .await
-    {
-        Ok(value) => {
-            log::info!("Started UDP listener on {}:{}", config.address, config.port);
-            value
-        }
-        Err(error) => {
-            log::error!("Failed to start UDP listener: {:?}", error);
-            return Ok(());
-        }
-    };
+    })?;
+    let udp_sock =
+        match bind_udp_socket(listen_addr, config.ipv6_only).and_then(UdpSocket::from_std) {
+            Ok(value) => {
+                log::info!("Started UDP listener on {listen_addr}");
+                Health::set(&HEALTH.udp_listener, true);
+                Arc::new(value)
+            }
+            Err(error) => {
+                log::error!("Failed to start UDP listener: {:?}", error);
+                return Ok(());
+            }
+        };
+
+    if config.healthcheck_interval > 0 {
+        tokio::spawn(self_check_loop(
+            listen_addr,
+            config.healthcheck_name(),
+            Duration::from_secs(config.healthcheck_interval),
+        ));
+    }
 
     // TODO: this needs to be bigger to handle edns0-negotiated queries
     let mut udp_buffer = [0; UDP_BUFFER_SIZE];
+    let capture_packets = config.capture_packets;
+    let query_checks = config.query_checks;
 
     loop {
         let (len, addr) = match udp_sock.recv_from(&mut udp_buffer).await {
@@ -118,192 +168,447 @@ pub async fn udp_server(
         };
 
         log::debug!("{:?} bytes received from {:?}", len, addr);
+        // there's not even a header to send an error back with
+        if len < HEADER_BYTES {
+            log::debug!(
+                "Dropping a {len} byte datagram from {addr:?}, it's too short to be a query"
+            );
+            continue;
+        }
+
+        // answering can mean asking upstream for ALIAS and FORWARD records, which shouldn't hold up everyone else
+        tokio::spawn(udp_reply(
+            udp_sock.clone(),
+            udp_buffer[..len].to_vec(),
+            addr,
+            datastore_sender.clone(),
+            capture_packets,
+            query_checks,
+        ));
+    }
+}
 
-        let udp_result = match timeout(
+/// Answers a single UDP query, [parse_query] limits how many of these are going at once
+async fn udp_reply(
+    udp_sock: Arc<UdpSocket>,
+    query: Vec<u8>,
+    addr: SocketAddr,
+    datastore_sender: mpsc::Sender<crate::datastore::Queued>,
+    capture_packets: bool,
+    query_checks: QueryChecks,
+) {
+    let udp_result = match check_query(query_checks, &query, Some(addr.port())) {
+        Checked::Drop => return,
+        Checked::Reply(reply) => Ok(reply),
+        Checked::Answer => match timeout(
             Duration::from_millis(REPLY_TIMEOUT_MS),
             parse_query(
-                datastore_sender.clone(),
-                len,
-                &udp_buffer,
-                config.capture_packets,
+                datastore_sender,
+                query.len(),
+                &query,
+                capture_packets,
                 QueryProtocol::Udp,
+                Some(addr.ip().to_canonical()),
             ),
         )
         .await
         {
             Ok(reply) => reply,
             Err(_) => {
-                log::error!("Did not receive response from parse_query within 10 ms");
-                continue;
+                log::error!(
+                    "Did not receive response from parse_query within {REPLY_TIMEOUT_MS} ms"
+                );
+                return;
             }
-        };
+        },
+    };
 
-        match udp_result {
-            Ok(mut r) => {
-                log::debug!("Result: {:?}", r);
-
-                let reply_bytes: Vec<u8> = match r.as_bytes().await {
-                    Ok(value) => {
-                        // Check if it's too long and set truncate flag if so, it's safe to unwrap since we've already gone
-                        if value.len() > UDP_BUFFER_SIZE {
-                            let mut response_bytes = value.to_vec();
-                            response_bytes.truncate(UDP_BUFFER_SIZE);
-                            r = r.check_set_truncated().await;
-                            let r = r.as_bytes_udp().await;
-                            r.unwrap_or(value)
-                        } else {
-                            value
-                        }
-                    }
-                    Err(error) => {
-                        log::error!("Failed to parse reply {:?} into bytes: {:?}", r, error);
-                        continue;
-                    }
-                };
+    let r = match udp_result {
+        Ok(r) => r,
+        Err(error) => {
+            log::error!("Error: {}", error);
+            return;
+        }
+    };
+    log::debug!("Result: {:?}", r);
 
-                log::trace!("reply_bytes: {:?}", reply_bytes);
-                let len = match udp_sock.send_to(&reply_bytes as &[u8], addr).await {
-                    Ok(value) => value,
-                    Err(err) => {
-                        log::error!("Failed to send data back to {:?}: {:?}", addr, err);
-                        return Ok(());
-                    }
+    // if it's too big for the client they get it truncated, and can ask again over TCP
+    let reply_bytes: Vec<u8> = match r.as_bytes_udp(udp_reply_size(&query)) {
+        Ok(value) => value,
+        Err(error) => {
+            log::error!("Failed to parse reply {:?} into bytes: {:?}", r, error);
+            return;
+        }
+    };
+
+    log::trace!("reply_bytes: {:?}", reply_bytes);
+
+    #[cfg(feature = "fault-injection")]
+    {
+        let faults = crate::fault_injection::current();
+        if faults.drop_udp() {
+            log::debug!("Dropping the reply to {addr:?}, it's an injected fault");
+            return;
+        }
+        if let Some(delay) = faults.delay() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    match udp_sock.send_to(&reply_bytes as &[u8], addr).await {
+        Ok(len) => log::trace!("{:?} bytes sent", len),
+        Err(err) => log::error!("Failed to send data back to {:?}: {:?}", addr, err),
+    }
+}
+
+/// UDP source ports for services that answer anything sent to them (echo, daytime, chargen and time), and 0 which
+/// can't be replied to. Queries from them are forged, to start a loop or bounce traffic at someone.
+pub const FORGED_SOURCE_PORTS: [u16; 5] = [0, 7, 13, 19, 37];
+
+/// What to do with a query, after [check_query]
+#[derive(Debug)]
+pub enum Checked {
+    /// Answer it
+    Answer,
+    /// Send this back instead of answering it
+    Reply(Reply),
+    /// Don't send anything back
+    Drop,
+}
+
+/// Makes sure a query looks like one if [QueryChecks::strict] is set, the source port's only there for UDP
+pub fn check_query(checks: QueryChecks, buf: &[u8], source_port: Option<u16>) -> Checked {
+    if !checks.strict {
+        return Checked::Answer;
+    }
+    if let Some(port) = source_port.filter(|port| FORGED_SOURCE_PORTS.contains(port)) {
+        log::debug!("Dropping a query from UDP port {port}");
+        Metrics::increment(&METRICS.queries_dropped);
+        return Checked::Drop;
+    }
+    let mut split_header: [u8; HEADER_BYTES] = [0; HEADER_BYTES];
+    let header = match buf.get(0..HEADER_BYTES) {
+        Some(bytes) => {
+            split_header.copy_from_slice(bytes);
+            Header::unpack(&split_header).ok()
+        }
+        None => None,
+    };
+    let problem = match &header {
+        None => "the header's broken".to_string(),
+        Some(header) if header.qr == PacketType::Answer => "it's a response".to_string(),
+        Some(header) if header.qdcount != 1 => format!("it has {} questions", header.qdcount),
+        Some(_) => return Checked::Answer,
+    };
+    match (header, checks.drop) {
+        (Some(header), false) => {
+            log::debug!("Sending FORMERR for query id={}, {problem}", header.id);
+            match reply_builder(header.id, Rcode::FormatError) {
+                Ok(reply) => Checked::Reply(reply),
+                Err(err) => {
+                    log::error!("Failed to build FORMERR reply: {err}");
+                    Checked::Drop
+                }
+            }
+        }
+        _ => {
+            log::debug!("Dropping a query, {problem}");
+            Metrics::increment(&METRICS.queries_dropped);
+            Checked::Drop
+        }
+    }
+}
+
+/// How TCP connections are handled, from the config
+#[derive(Clone, Copy, Debug)]
+pub struct TcpSettings {
+    /// How long to wait for the next query on a connection
+    pub idle_timeout: Duration,
+    /// How long to wait for the rest of a query once its length has arrived
+    pub client_timeout: Duration,
+    /// Close the connection after this many queries
+    pub max_queries: u32,
+    /// Close the connection if a query's longer than this
+    pub max_message_size: usize,
+    pub capture_packets: bool,
+    /// What queries have to look like to get an answer
+    pub query_checks: QueryChecks,
+}
+
+impl From<&ConfigFile> for TcpSettings {
+    fn from(config: &ConfigFile) -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(config.tcp_idle_timeout),
+            client_timeout: Duration::from_secs(config.tcp_client_timeout),
+            max_queries: config.tcp_max_queries,
+            max_message_size: config.tcp_max_message_size.into(),
+            capture_packets: config.capture_packets,
+            query_checks: config.query_checks,
+        }
+    }
+}
+
+impl TcpSettings {
+    /// The idle timeout as an edns-tcp-keepalive TIMEOUT, which is in units of 100 milliseconds
+    pub fn keepalive_timeout(&self) -> u16 {
+        u16::try_from(self.idle_timeout.as_millis() / 100).unwrap_or(u16::MAX)
+    }
+}
+
+/// Handles edns-tcp-keepalive ([RFC7828](https://www.rfc-editor.org/rfc/rfc7828)) for a query that came in over TCP.
+///
+/// If they asked, the reply tells them how long they can leave the connection idle - zero means we're about to close
+/// it. Clients aren't allowed to send a timeout themselves, so that's a FORMERR.
+pub(crate) fn apply_tcp_keepalive(
+    reply: Reply,
+    query: &[u8],
+    keepalive_timeout: u16,
+) -> Result<Reply, String> {
+    let query_edns = match Edns::from_query(query) {
+        Ok(Some(value)) => value,
+        Ok(None) => return Ok(reply),
+        Err(err) => {
+            log::debug!("Couldn't parse EDNS from TCP query: {err}");
+            return Ok(reply);
+        }
+    };
+    match query_edns.option(EDNS_TCP_KEEPALIVE) {
+        None => Ok(reply),
+        Some(value) if !value.is_empty() => {
+            log::debug!("Client sent an edns-tcp-keepalive timeout, replying with FORMERR");
+            reply_builder(reply.header.id, Rcode::FormatError)
+        }
+        Some(_) => {
+            let mut edns = Edns {
+                udp_payload_size: UDP_BUFFER_SIZE as u16,
+                options: vec![],
+            };
+            edns.set_option(EDNS_TCP_KEEPALIVE, keepalive_timeout.to_be_bytes().to_vec());
+            Ok(Reply {
+                edns: Some(edns),
+                ..reply
+            })
+        }
+    }
+}
+
+/// Answers a single query from a TCP connection, with a flag for if it was a shutdown.
+///
+/// Returns `None` if there's nothing to send back, and the connection should be closed
+async fn tcp_reply(
+    buf: &[u8],
+    client: IpAddr,
+    datastore_sender: &mpsc::Sender<crate::datastore::Queued>,
+    agent_tx: &broadcast::Sender<AgentState>,
+    settings: TcpSettings,
+    allowed_shutdown: bool,
+    last_query: bool,
+) -> Option<(Vec<u8>, bool)> {
+    let result = match check_query(settings.query_checks, buf, None) {
+        Checked::Drop => return None,
+        Checked::Reply(reply) => Ok(reply),
+        Checked::Answer => match timeout(
+            Duration::from_millis(REPLY_TIMEOUT_MS),
+            parse_query(
+                datastore_sender.clone(),
+                buf.len(),
+                buf,
+                settings.capture_packets,
+                QueryProtocol::Tcp,
+                Some(client),
+            ),
+        )
+        .await
+        {
+            Ok(reply) => reply,
+            Err(_) => {
+                log::error!(
+                    "Did not receive response from parse_query within {REPLY_TIMEOUT_MS} ms"
+                );
+                return None;
+            }
+        },
+    };
+
+    let r = match result {
+        Ok(r) => r,
+        Err(error) => {
+            log::error!("Error: {}", error);
+            return None;
+        }
+    };
+    log::debug!("TCP Result: {r:?}");
+
+    // when you get a CHAOS from the allow-list with "shutdown" it's quitting time
+    let (r, shutdown) = match check_for_shutdown(r, allowed_shutdown).await {
+        // no change here
+        Err(err) => {
+            log::error!("Failed to check for shutdown: {:?}", err);
+            return None;
+        }
+        Ok(reply) => match reply {
+            ChaosResult::Answer(response) | ChaosResult::Refused(response) => (response, false),
+            ChaosResult::Shutdown(response) => {
+                if let Err(error) = agent_tx.send(AgentState::Stopped {
+                    agent: Agent::TCPServer,
+                }) {
+                    eprintln!("Failed to send UDPServer shutdown message: {error:?}");
                 };
-                // let len = sock.send_to(r.answer.as_bytes(), addr).await?;
-                log::trace!("{:?} bytes sent", len);
+                if let Err(error) = datastore_sender.send(Command::Shutdown.into()).await {
+                    eprintln!("Failed to send shutdown command to datastore.. {error:?}");
+                };
+                (response, true)
             }
-            Err(error) => log::error!("Error: {}", error),
+        },
+    };
+
+    let keepalive_timeout = match last_query || shutdown {
+        true => 0,
+        false => settings.keepalive_timeout(),
+    };
+    let r = match apply_tcp_keepalive(r, buf, keepalive_timeout) {
+        Ok(value) => value,
+        Err(error) => {
+            log::error!("Failed to build keepalive reply: {error}");
+            return None;
+        }
+    };
+
+    match r.as_bytes() {
+        Ok(value) => {
+            log::trace!("reply_bytes: {:?}", value);
+            Some((value, shutdown))
+        }
+        Err(error) => {
+            log::error!("Failed to parse reply {:?} into bytes: {:?}", r, error);
+            None
         }
     }
 }
 
+/// Handles a TCP connection, answering queries until it's idle for too long, they've sent
+/// [TcpSettings::max_queries] or they hang up.
+///
+/// Ref <https://www.rfc-editor.org/rfc/rfc7766#section-6.2>
 #[instrument(level = "info", skip_all)]
 pub async fn tcp_conn_handler(
     stream: &mut TcpStream,
     addr: SocketAddr,
-    datastore_sender: mpsc::Sender<Command>,
+    datastore_sender: mpsc::Sender<crate::datastore::Queued>,
     agent_tx: broadcast::Sender<AgentState>,
-    capture_packets: bool,
+    settings: TcpSettings,
     allowed_shutdown: bool,
 ) -> io::Result<()> {
-    let (mut reader, writer) = stream.split();
-    let msg_length: usize = reader.read_u16().await?.into();
-    log::debug!("msg_length={msg_length}");
-    let mut buf: Vec<u8> = vec![];
-
-    while buf.len() < msg_length {
-        let len = match reader.read_buf(&mut buf).await {
-            Ok(size) => size,
-            Err(error) => {
-                log::error!("Failed to read from TCP Stream: {:?}", error);
+    let (mut reader, mut writer) = stream.split();
+    let mut queries: u32 = 0;
+    loop {
+        // the first two bytes of a tcp query is the message length
+        // ref <https://www.rfc-editor.org/rfc/rfc7766#section-8>
+        let msg_length: usize = match timeout(settings.idle_timeout, reader.read_u16()).await {
+            Ok(Ok(value)) => value.into(),
+            // hanging up between queries is fine
+            Ok(Err(error)) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Ok(Err(error)) => return Err(error),
+            Err(_) => {
+                log::debug!("Closing TCP connection from {addr:?}, idle after {queries} queries");
                 return Ok(());
             }
         };
-        if len > 0 {
-            log::debug!("Read {:?} bytes from TCP stream", len);
-        }
-    }
-    // TODO: why are we hexdumping this?
-    if let Err(err) = crate::utils::hexdump(&buf) {
-        log::error!("Failed to hexdump buffer: {:?}", err);
-    };
-    // the first two bytes of a tcp query is the message length
-    // ref <https://www.rfc-editor.org/rfc/rfc7766#section-8>
-
-    // check the message is long enough
-    if buf.len() < msg_length {
-        log::warn!(
-            "Message length too short {}, wanted {}",
-            buf.len(),
-            msg_length + 2
-        );
-    } else {
-        log::info!("TCP Message length ftw!");
-    }
-
-    // skip the TCP length header because rad
-    let buf = &buf[0..msg_length];
-    let result = match timeout(
-        Duration::from_millis(REPLY_TIMEOUT_MS),
-        parse_query(
-            datastore_sender.clone(),
-            msg_length,
-            buf,
-            capture_packets,
-            QueryProtocol::Tcp,
-        ),
-    )
-    .await
-    {
-        Ok(reply) => reply,
-        Err(_) => {
-            log::error!("Did not receive response from parse_query within {REPLY_TIMEOUT_MS} ms");
+        log::debug!("msg_length={msg_length}");
+        if msg_length < HEADER_BYTES || msg_length > settings.max_message_size {
+            log::warn!(
+                "Closing TCP connection from {addr:?}, query length was {msg_length}, needs to be between {HEADER_BYTES} and {}",
+                settings.max_message_size
+            );
             return Ok(());
         }
-    };
-
-    match result {
-        Ok(r) => {
-            log::debug!("TCP Result: {r:?}");
 
-            // when you get a CHAOS from the allow-list with "shutdown" it's quitting time
-            let r = match check_for_shutdown(&r, allowed_shutdown).await {
-                // no change here
-                Err(err) => {
-                    log::error!("Failed to check for shutdown: {:?}", err);
-                    return Ok(());
-                }
-                Ok(reply) => match reply {
-                    ChaosResult::Refused(response) => response,
-                    ChaosResult::Shutdown(response) => {
-                        if let Err(error) = agent_tx.send(AgentState::Stopped {
-                            agent: Agent::TCPServer,
-                        }) {
-                            eprintln!("Failed to send UDPServer shutdown message: {error:?}");
-                        };
-                        if let Err(error) = datastore_sender.send(Command::Shutdown).await {
-                            eprintln!("Failed to send shutdown command to datastore.. {error:?}");
-                        };
-                        response
-                    }
-                },
-            };
+        let mut buf: Vec<u8> = vec![0; msg_length];
+        match timeout(settings.client_timeout, reader.read_exact(&mut buf)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(error)) => {
+                log::error!("Failed to read from TCP Stream: {:?}", error);
+                return Ok(());
+            }
+            Err(_) => {
+                log::warn!(
+                    "TCP Connection from {addr:?} terminated after {} seconds waiting for a {msg_length} byte query.",
+                    settings.client_timeout.as_secs()
+                );
+                return Ok(());
+            }
+        }
+        // TODO: why are we hexdumping this?
+        if let Err(err) = crate::utils::hexdump(&buf) {
+            log::error!("Failed to hexdump buffer: {:?}", err);
+        };
 
-            let reply_bytes: Vec<u8> = match r.as_bytes().await {
-                Ok(value) => value,
-                Err(error) => {
-                    log::error!("Failed to parse reply {:?} into bytes: {:?}", r, error);
-                    return Ok(());
-                }
-            };
+        queries += 1;
+        let last_query = queries >= settings.max_queries;
+        let Some((reply_bytes, shutdown)) = tcp_reply(
+            &buf,
+            addr.ip().to_canonical(),
+            &datastore_sender,
+            &agent_tx,
+            settings,
+            allowed_shutdown,
+            last_query,
+        )
+        .await
+        else {
+            return Ok(());
+        };
 
-            log::trace!("reply_bytes: {:?}", reply_bytes);
+        // the reply gets its length on the front too
+        let mut response: Vec<u8> = Vec::with_capacity(reply_bytes.len() + 2);
+        response.extend((reply_bytes.len() as u16).to_be_bytes());
+        response.extend(reply_bytes);
+        #[cfg(feature = "fault-injection")]
+        if let Some(delay) = crate::fault_injection::current().delay() {
+            tokio::time::sleep(delay).await;
+        }
+        if let Err(err) = writer.write_all(&response).await {
+            log::error!("Failed to send data back to {:?}: {:?}", addr, err);
+            return Ok(());
+        }
+        log::trace!("{:?} bytes sent", response.len());
 
-            let reply_bytes = &reply_bytes as &[u8];
-            // send the outgoing message length
-            let response_length: u16 = reply_bytes.len() as u16;
-            let len = match writer.try_write(&response_length.to_be_bytes()) {
-                Ok(value) => value,
-                Err(err) => {
-                    log::error!("Failed to send data back to {:?}: {:?}", addr, err);
-                    return Ok(());
-                }
-            };
-            log::trace!("{:?} bytes sent", len);
+        if last_query || shutdown {
+            log::debug!("Closing TCP connection from {addr:?} after {queries} queries");
+            return Ok(());
+        }
+    }
+}
 
-            // send the data
-            let len = match writer.try_write(reply_bytes) {
-                Ok(value) => value,
-                Err(err) => {
-                    log::error!("Failed to send data back to {:?}: {:?}", addr, err);
-                    return Ok(());
-                }
-            };
-            log::trace!("{:?} bytes sent", len);
+/// Reads the PROXY header from a connection, if it's from somewhere that's allowed to send one.
+///
+/// Returns `None` if the connection should be dropped
+async fn tcp_proxied_client(
+    stream: &mut TcpStream,
+    peer: SocketAddr,
+    trusted_proxies: &[IpNet],
+    settings: TcpSettings,
+) -> Option<SocketAddr> {
+    if !is_trusted_proxy(&peer.ip(), trusted_proxies) {
+        log::warn!("Dropping TCP connection from {peer:?}, it's not in trusted_proxies so it can't send a PROXY header");
+        return None;
+    }
+    match timeout(settings.client_timeout, read_proxy_v2(stream, peer)).await {
+        Ok(Ok(addr)) => {
+            log::debug!("TCP connection from {addr:?} via proxy {peer:?}");
+            Some(addr)
+        }
+        Ok(Err(error)) => {
+            log::warn!("Dropping TCP connection from {peer:?}: {error}");
+            None
+        }
+        Err(_) => {
+            log::warn!(
+                "Dropping TCP connection from {peer:?}, it didn't send a PROXY header in time"
+            );
+            None
         }
-        Err(error) => log::error!("Error: {}", error),
     }
-    Ok(())
 }
 
 /// main handler for the TCP side of things
@@ -311,38 +616,33 @@ pub async fn tcp_conn_handler(
 /// Ref <https://www.rfc-editor.org/rfc/rfc7766>
 pub async fn tcp_server(
     config: CowCellReadTxn<ConfigFile>,
-    tx: mpsc::Sender<crate::datastore::Command>,
+    tx: mpsc::Sender<crate::datastore::Queued>,
     agent_tx: broadcast::Sender<AgentState>,
     // mut agent_rx: broadcast::Receiver<AgentState>,
 ) -> io::Result<()> {
     let mut agent_rx = agent_tx.subscribe();
-    let tcpserver = match TcpListener::bind(config.dns_listener_address().map_err(|_err| {
+    let listen_addr = config.dns_listener_address().map_err(|_err| {
         GoatNsError::StartupError("Failed to get DNS listener address on startup!".to_string())
-    })?)
-    .await
-    {
-        Ok(value) => {
-            log::info!(
-                "Started TCP listener on {}",
-                config
-                    .dns_listener_address()
-                    .map_err(|_err| GoatNsError::StartupError(
-                        "Failed to get DNS listener address on startup!".to_string()
-                    ))?
-            );
-            value
-        }
-        Err(error) => {
-            log::error!("Failed to start TCP Server: {:?}", error);
-            return Ok(());
-        }
-    };
+    })?;
+    let tcpserver =
+        match bind_tcp_listener(listen_addr, config.ipv6_only).and_then(TcpListener::from_std) {
+            Ok(value) => {
+                log::info!("Started TCP listener on {listen_addr}");
+                Health::set(&HEALTH.tcp_listener, true);
+                value
+            }
+            Err(error) => {
+                log::error!("Failed to start TCP Server: {:?}", error);
+                return Ok(());
+            }
+        };
 
-    let tcp_client_timeout = config.tcp_client_timeout;
-    let shutdown_ip_address_list = config.ip_allow_lists.shutdown.to_vec();
-    let capture_packets = config.capture_packets;
+    let settings = TcpSettings::from(&*config);
+    let shutdown_ip_address_list = Arc::new(config.ip_allow_lists.shutdown.to_vec());
+    let proxy_protocol = config.proxy_protocol;
+    let trusted_proxies = Arc::new(config.trusted_proxies.clone());
     loop {
-        let (mut stream, addr) = match tcpserver.accept().await {
+        let (mut stream, peer) = match tcpserver.accept().await {
             Ok(value) => value,
             Err(err) => {
                 error!("Couldn't get data from TcpStream: {:?}", err);
@@ -350,32 +650,36 @@ pub async fn tcp_server(
             }
         };
 
-        let allowed_shutdown = shutdown_ip_address_list.contains(&addr.ip());
-        log::debug!("TCP connection from {:?}", addr);
+        log::debug!("TCP connection from {:?}", peer);
         let loop_tx = tx.clone();
         let loop_agent_tx = agent_tx.clone();
+        let shutdown_ip_address_list = shutdown_ip_address_list.clone();
+        let trusted_proxies = trusted_proxies.clone();
         tokio::spawn(async move {
-            if timeout(
-                Duration::from_secs(tcp_client_timeout),
-                tcp_conn_handler(
-                    &mut stream,
-                    addr,
-                    loop_tx,
-                    loop_agent_tx,
-                    capture_packets,
-                    allowed_shutdown,
-                ),
+            // behind a load balancer, the client's address comes from the PROXY header
+            let addr = match proxy_protocol {
+                false => peer,
+                true => {
+                    match tcp_proxied_client(&mut stream, peer, &trusted_proxies, settings).await {
+                        Some(addr) => addr,
+                        None => return,
+                    }
+                }
+            };
+            let allowed_shutdown = shutdown_ip_address_list.contains(&addr.ip().to_canonical());
+            if let Err(error) = tcp_conn_handler(
+                &mut stream,
+                addr,
+                loop_tx,
+                loop_agent_tx,
+                settings,
+                allowed_shutdown,
             )
             .await
-            .is_err()
             {
-                log::warn!(
-                    "TCP Connection from {addr:?} terminated after {} seconds.",
-                    tcp_client_timeout
-                );
+                log::warn!("TCP Connection from {addr:?} failed: {error:?}");
             }
-        })
-        .await?;
+        });
 
         if let Ok(agent_state) = agent_rx.try_recv() {
             log::info!("Got agent state: {:?}", agent_state);
@@ -383,6 +687,7 @@ pub async fn tcp_server(
     }
 }
 
+#[derive(Clone, Copy, Debug)]
 pub(crate) enum QueryProtocol {
     Udp,
     Tcp,
@@ -402,22 +707,31 @@ impl std::fmt::Display for QueryProtocol {
 /// Parses the rest of the packets once we have stripped the header off.
 #[instrument(level = "info", skip_all, fields(protocol=protocol.to_string()))]
 pub async fn parse_query(
-    datastore: tokio::sync::mpsc::Sender<crate::datastore::Command>,
+    datastore: tokio::sync::mpsc::Sender<crate::datastore::Queued>,
     len: usize,
     buf: &[u8],
     capture_packets: bool,
     protocol: QueryProtocol,
+    client: Option<IpAddr>,
 ) -> Result<Reply, String> {
     if capture_packets {
         crate::packet_dumper::dump_bytes(
-            buf[0..len].into(),
+            buf[..len.min(buf.len())].into(),
             crate::packet_dumper::DumpType::ClientRequest,
         )
         .await;
     }
     // we only want the first 12 bytes for the header
     let mut split_header: [u8; HEADER_BYTES] = [0; HEADER_BYTES];
-    split_header.copy_from_slice(&buf[0..HEADER_BYTES]);
+    match buf.get(0..HEADER_BYTES) {
+        Some(bytes) => split_header.copy_from_slice(bytes),
+        None => {
+            return Err(format!(
+                "Query's only {} bytes, too short for a header",
+                buf.len()
+            ))
+        }
+    }
     // unpack the header for great justice
     let header = match crate::Header::unpack(&split_header) {
         Ok(value) => value,
@@ -428,37 +742,328 @@ pub async fn parse_query(
     };
     log::trace!("Buffer length: {}", len);
     log::trace!("Parsed header: {:?}", header);
-    get_result(header, len, buf, datastore).await
+
+    // if we're already flat out, tell them to try someone else rather than queueing it up
+    let _permit = match QUERY_PERMITS.try_acquire() {
+        Ok(permit) => permit,
+        Err(_) => {
+            Metrics::increment(&METRICS.queries_saturated);
+            log::warn!("{MAX_IN_FLIGHT} queries already in flight, sending SERVFAIL");
+            return reply_builder(header.id, Rcode::ServFail);
+        }
+    };
+    Metrics::increment(METRICS.queries(protocol));
+    let started = std::time::Instant::now();
+    let answer = async {
+        get_result(header, len, buf, datastore, client)
+            .await
+            .map(|reply| apply_response_policy(client, reply))
+            .map(|reply| match MINIMAL_RESPONSES.load(Ordering::Relaxed) {
+                true => reply.minimal(),
+                false => reply,
+            })
+    };
+    let res = match crate::query_trace::is_enabled() {
+        false => answer.await,
+        true => {
+            let (events, res) = crate::query_trace::capture(answer).await;
+            crate::query_trace::finish(&buf[0..len], protocol, &res, events, started.elapsed());
+            res
+        }
+    };
+    crate::logging::record_query(protocol, &res, started.elapsed());
+    res
+}
+
+/// Lets the response policy script change the answers, see [crate::policy]
+#[cfg(feature = "response-policy")]
+pub(crate) fn apply_response_policy(client: Option<IpAddr>, mut reply: Reply) -> Reply {
+    if reply.header.rcode != Rcode::NoError || reply.answers.is_empty() {
+        return reply;
+    }
+    if let Some(question) = &reply.question {
+        reply.answers = crate::policy::apply(client, question, std::mem::take(&mut reply.answers));
+    }
+    reply
+}
+
+#[cfg(not(feature = "response-policy"))]
+pub(crate) fn apply_response_policy(_client: Option<IpAddr>, reply: Reply) -> Reply {
+    reply
 }
 
 lazy_static! {
+    /// Limits how many queries we handle at once across all the protocols
+    static ref QUERY_PERMITS: Semaphore = Semaphore::new(MAX_IN_FLIGHT);
+
     static ref CHAOS_OK: InternalResourceRecord = InternalResourceRecord::TXT {
-        txtdata: DNSCharString::from("OK"),
+        txtdata: vec![DNSCharString::from("OK")],
         ttl: 0,
         class: RecordClass::Chaos,
     };
     static ref CHAOS_NO: InternalResourceRecord = InternalResourceRecord::TXT {
-        txtdata: DNSCharString::from("NO"),
+        txtdata: vec![DNSCharString::from("NO")],
         ttl: 0,
         class: RecordClass::Chaos,
     };
 }
 
+/// Ask the datastore for records, if it can't help then you get the [Rcode] to reply with
+async fn query_datastore(
+    datastore: &mpsc::Sender<crate::datastore::Queued>,
+    name: Vec<u8>,
+    rrtype: u16,
+    rclass: RecordClass,
+) -> Result<Option<ZoneRecord>, Rcode> {
+    // build the request to the datastore to make the query
+    let (tx_oneshot, rx_oneshot) = oneshot::channel();
+    let ds_req: Command = match RecordType::from(&rrtype) {
+        // types we don't know about can still be served from generic records
+        RecordType::InvalidType => Command::GetGenericRecord {
+            name,
+            rrtype,
+            rclass,
+            resp: tx_oneshot,
+        },
+        rrtype => Command::GetRecord {
+            name,
+            rrtype,
+            rclass,
+            resp: tx_oneshot,
+        },
+    };
+    ask_datastore(datastore, ds_req, rx_oneshot).await
+}
+
+/// Anything at or below a zone cut gets referred to the child zone's name servers instead of answered
+async fn query_delegation(
+    datastore: &mpsc::Sender<crate::datastore::Queued>,
+    question: &Question,
+) -> Result<Option<Delegation>, Rcode> {
+    let (tx_oneshot, rx_oneshot) = oneshot::channel();
+    let ds_req: Command = Command::GetDelegation {
+        name: question.lookup_name(),
+        rclass: question.qclass,
+        resp: tx_oneshot,
+    };
+    ask_datastore(datastore, ds_req, rx_oneshot).await
+}
+
+/// When there's nothing to answer with, find out if it's NODATA or NXDOMAIN and which zone's SOA goes with it
+async fn query_negative_answer(
+    datastore: &mpsc::Sender<crate::datastore::Queued>,
+    question: &Question,
+) -> Result<Option<NegativeAnswer>, Rcode> {
+    let (tx_oneshot, rx_oneshot) = oneshot::channel();
+    let ds_req: Command = Command::GetNegativeAnswer {
+        name: question.lookup_name(),
+        rclass: question.qclass,
+        resp: tx_oneshot,
+    };
+    ask_datastore(datastore, ds_req, rx_oneshot).await
+}
+
+/// Sends a request to the datastore and waits for the answer, anything going wrong along the way is a SERVFAIL
+async fn ask_datastore<T>(
+    datastore: &mpsc::Sender<crate::datastore::Queued>,
+    ds_req: Command,
+    rx_oneshot: oneshot::Receiver<Result<T, GoatNsError>>,
+) -> Result<T, Rcode> {
+    // here we talk to the datastore to pull the result, if its queue is full then we don't wait around
+    match datastore.try_send(ds_req.into()) {
+        Ok(_) => log::trace!("Sent a request to the datastore!"),
+        Err(TrySendError::Full(_)) => {
+            Metrics::increment(&METRICS.datastore_saturated);
+            log::warn!("Datastore queue is full, sending SERVFAIL");
+            note(|| "Datastore queue is full, SERVFAIL".to_string());
+            return Err(Rcode::ServFail);
+        }
+        Err(TrySendError::Closed(_)) => {
+            log::error!("Error sending to datastore: channel closed");
+            return Err(Rcode::ServFail);
+        }
+    };
+
+    match rx_oneshot.await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(error)) => {
+            log::error!("Datastore failed to look up the record: {:?}", error);
+            note(|| format!("Datastore lookup failed, SERVFAIL: {error:?}"));
+            Err(Rcode::ServFail)
+        }
+        Err(error) => {
+            log::error!("Failed to get response from datastore: {:?}", error);
+            Err(Rcode::ServFail)
+        }
+    }
+}
+
+/// If there's an ALIAS record at the name, resolve its target and hand back the addresses as though they were ours
+async fn flatten_alias(
+    datastore: &mpsc::Sender<crate::datastore::Queued>,
+    question: &Question,
+) -> Result<Option<ZoneRecord>, Rcode> {
+    if !crate::alias::flattens_to(question.qtype, question.qclass) {
+        return Ok(None);
+    }
+    let alias = match query_datastore(
+        datastore,
+        question.lookup_name(),
+        RecordType::ALIAS as u16,
+        question.qclass,
+    )
+    .await?
+    {
+        Some(zr) => zr,
+        None => return Ok(None),
+    };
+    let mut typerecords = vec![];
+    for record in &alias.typerecords {
+        match crate::alias::flatten(record, question.qtype).await {
+            Ok(res) => typerecords.extend(res),
+            Err(err) => {
+                log::error!("Failed to flatten ALIAS: {err:?}");
+                return Err(Rcode::ServFail);
+            }
+        }
+    }
+    match typerecords.is_empty() {
+        true => Ok(None),
+        false => Ok(Some(ZoneRecord {
+            name: alias.name,
+            typerecords,
+        })),
+    }
+}
+
+/// If the name has a FORWARD record, ask the upstreams about its target and answer with what they say
+async fn forwarded_reply(
+    datastore: &mpsc::Sender<crate::datastore::Queued>,
+    header: &Header,
+    question: &Question,
+    client: Option<IpAddr>,
+) -> Option<Result<Reply, String>> {
+    if question.qclass != RecordClass::Internet {
+        return None;
+    }
+    let forward = match query_datastore(
+        datastore,
+        question.lookup_name(),
+        RecordType::FORWARD as u16,
+        question.qclass,
+    )
+    .await
+    {
+        Ok(Some(zr)) => zr,
+        Ok(None) => return None,
+        Err(rcode) => return Some(reply_builder(header.id, rcode)),
+    };
+    let target = forward.typerecords.iter().find_map(|record| match record {
+        InternalResourceRecord::FORWARD { target, .. } => Some(target.name.clone()),
+        _ => None,
+    })?;
+    if crate::forward::is_upstream(client) {
+        log::warn!("Not forwarding a query from upstream {client:?}, it'd loop");
+        note(|| "Query came from a forward upstream, SERVFAIL".to_string());
+        return Some(reply_builder(header.id, Rcode::ServFail));
+    }
+
+    match crate::forward::resolve(&target, question.qtype_code, question.qclass).await {
+        Ok(Forwarded::Answers(answers)) => {
+            note(|| format!("Forwarded to {target}, got {} records", answers.len()));
+            Some(Ok(authoritative_answer(header, question.clone(), answers)))
+        }
+        Ok(forwarded) => {
+            note(|| format!("Forwarded to {target}, got {forwarded:?}"));
+            // the FORWARD record means the name's there for us, but upstream gets to say if it really exists
+            let name_exists = forwarded == Forwarded::NoData;
+            match query_negative_answer(datastore, question).await {
+                Ok(Some(negative)) => Some(reply_negative(
+                    header.id,
+                    question,
+                    &NegativeAnswer {
+                        name_exists,
+                        ..negative
+                    },
+                )),
+                Ok(None) => Some(reply_builder(header.id, Rcode::ServFail)),
+                Err(rcode) => Some(reply_builder(header.id, rcode)),
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to forward to {target}: {err:?}");
+            note(|| format!("Forwarding to {target} failed, SERVFAIL: {err:?}"));
+            Some(reply_builder(header.id, Rcode::ServFail))
+        }
+    }
+}
+
+/// An authoritative answer that doesn't come from the datastore
+fn authoritative_answer(header: &Header, question: Question, answers: Vec<Answer>) -> Reply {
+    Reply {
+        header: Header {
+            id: header.id,
+            qr: PacketType::Answer,
+            opcode: header.opcode,
+            authoritative: true,
+            truncated: false,
+            recursion_desired: header.recursion_desired,
+            recursion_available: header.recursion_available,
+            z: false,
+            ad: false,
+            cd: false,
+            rcode: Rcode::NoError,
+            qdcount: 1,
+            ancount: answers.len() as u16,
+            nscount: 0,
+            arcount: 0,
+        },
+        question: Some(question),
+        answers,
+        authorities: vec![],
+        additional: vec![],
+        edns: None,
+    }
+}
+
 /// The generic handler for the packets once they've been pulled out of their protocol handlers. TCP has a slightly different stream format to UDP, y'know?
 #[instrument(level="info", skip_all, fields(qname=field::Empty, qtype=field::Empty))]
 async fn get_result(
     header: Header,
     len: usize,
     buf: &[u8],
-    datastore: mpsc::Sender<crate::datastore::Command>,
+    datastore: mpsc::Sender<crate::datastore::Queued>,
+    client: Option<IpAddr>,
 ) -> Result<Reply, String> {
     log::trace!("called get_result(header={header}, len={len})");
 
+    // RFC1996 - a NOTIFY is a primary telling its secondaries to check the SOA, but we don't have any secondary zones
+    if header.opcode == OpCode::Notify {
+        log::info!(
+            "Got a NOTIFY id={}, refusing it as we don't have any secondary zones to refresh",
+            header.id
+        );
+        let mut reply = reply_builder(header.id, Rcode::Refused)?;
+        reply.header.opcode = OpCode::Notify;
+        return Ok(reply);
+    }
+
     // if we get something other than a query, yeah nah.
     if header.opcode != OpCode::Query {
         return Err(format!("Invalid OPCODE, got {:?}", header.opcode));
     };
 
+    // RFC9619 - a query has one question, there's no agreement on what more than one would mean so they're malformed
+    if header.qdcount != 1 {
+        log::debug!(
+            "Query id={} has {} questions, sending FORMERR",
+            header.id,
+            header.qdcount
+        );
+        note(|| format!("{} questions, FORMERR", header.qdcount));
+        return reply_builder(header.id, Rcode::FormatError);
+    }
+
     let question = match Question::from_packets(&buf[HEADER_BYTES..len]) {
         Ok(value) => {
             log::trace!("Parsed question: {:?}", value);
@@ -467,7 +1072,7 @@ async fn get_result(
         }
         Err(error) => {
             log::debug!("Failed to parse question: {} id={}", error, header.id);
-            return reply_builder(header.id, Rcode::ServFail);
+            return reply_builder(header.id, Rcode::FormatError);
         }
     };
 
@@ -476,15 +1081,25 @@ async fn get_result(
     if !span.is_disabled() {
         let qname_string = from_utf8(&question.qname).unwrap_or("<unable to parse>");
         span.record("qname", qname_string);
-        span.record("qtype", question.qtype.to_string());
+        span.record("qtype", crate::generic::type_name(question.qtype_code));
+    }
+    if crate::log_sinks::log_queries() {
+        let qname = from_utf8(&question.qname).unwrap_or("<unable to parse>");
+        let qtype = crate::generic::type_name(question.qtype_code);
+        log::info!(
+            target: crate::log_sinks::QUERY_LOG_TARGET,
+            id = header.id, qname = qname, qtype = qtype.as_str();
+            "Query id={} {qname} {qtype}",
+            header.id
+        );
     }
 
-    // yeet them when we get a request we can't handle
-    if !question.qtype.supported() {
+    // yeet them when we get a request we can't handle, types we don't know about might have generic records though
+    if !question.qtype.supported() && !crate::generic::is_generic_only(question.qtype_code) {
         log::debug!(
-            "Unsupported request: {} {:?}, returning NotImplemented",
+            "Unsupported request: {} {}, returning NotImplemented",
             from_utf8(&question.qname).unwrap_or("<unable to parse>"),
-            question.qtype,
+            crate::generic::type_name(question.qtype_code),
         );
         return reply_builder(header.id, Rcode::NotImplemented);
     }
@@ -500,8 +1115,60 @@ async fn get_result(
                 answers: vec![],
                 authorities: vec![],
                 additional: vec![],
+                edns: None,
             });
         }
+        if let Some(zr) = crate::builtin::server_id_answer(&crate::builtin::current(), &question) {
+            note(|| "Answered with the server's identity".to_string());
+            return Ok(authoritative_answer(&header, question, zr.into_answers()));
+        }
+    }
+
+    // we only have zones in the classes we know about
+    if question.qclass == RecordClass::InvalidType {
+        log::debug!("Query for an unknown class, refusing it");
+        return reply_builder(header.id, Rcode::Refused);
+    }
+
+    let builtin = crate::builtin::current();
+    if let Some(zr) = crate::builtin::status_answer(&builtin, &question) {
+        if !crate::builtin::status_allowed(&builtin, client) {
+            log::debug!("Refusing the server status to {client:?}");
+            return reply_builder(header.id, Rcode::Refused);
+        }
+        note(|| "Answered with the server's status".to_string());
+        return Ok(authoritative_answer(&header, question, zr.into_answers()));
+    }
+
+    // overridden names are answered ahead of anything in the zones
+    if let Some(records) = crate::host_overrides::answer(&question) {
+        note(|| {
+            format!(
+                "Answered from the host overrides with {} records",
+                records.len()
+            )
+        });
+        let answers = records.into_iter().map(Answer::from).collect();
+        return Ok(authoritative_answer(&header, question, answers));
+    }
+
+    match query_delegation(&datastore, &question).await {
+        Ok(Some(delegation)) => {
+            log::debug!(
+                "Referring query to the delegation at {:?}",
+                from_utf8(&delegation.name)
+            );
+            note(|| {
+                format!(
+                    "Below the zone cut at {:?}, referring to {} name servers",
+                    from_utf8(&delegation.name).unwrap_or("-"),
+                    delegation.nameservers.len()
+                )
+            });
+            return reply_referral(header.id, &question, &delegation);
+        }
+        Ok(None) => {}
+        Err(rcode) => return reply_builder(header.id, rcode),
     }
 
     if let RecordType::ANY {} = question.qtype {
@@ -509,37 +1176,107 @@ async fn get_result(
         return reply_any(header.id, &question);
     };
 
-    // build the request to the datastore to make the query
-    let (tx_oneshot, rx_oneshot) = oneshot::channel();
-    let ds_req: Command = Command::GetRecord {
-        name: question.qname.clone(),
-        rrtype: question.qtype,
-        rclass: question.qclass,
-        resp: tx_oneshot,
-    };
-
-    // here we talk to the datastore to pull the result
-    match datastore.send(ds_req).await {
-        Ok(_) => log::trace!("Sent a request to the datastore!"),
-        // TODO: handle errors sending to the DS properly
-        Err(error) => log::error!("Error sending to datastore: {:?}", error),
-    };
+    // ACME challenges we're in the middle of answer ahead of anything in the zones
+    if let Some(zr) = crate::acme::challenge_answer(&question) {
+        log::debug!("Answering ACME challenge for {:?}", from_utf8(&zr.name));
+        note(|| "Answered from the in-flight ACME challenges".to_string());
+        return Ok(authoritative_answer(&header, question, zr.into_answers()));
+    }
 
-    let record: ZoneRecord = match rx_oneshot.await {
-        Ok(value) => match value {
-            Some(zr) => {
-                log::debug!("DS Response: {}", zr);
-                zr
-            }
-            None => {
-                log::debug!("No response from datastore");
-                return reply_nxdomain(header.id);
+    let record: ZoneRecord = match query_datastore(
+        &datastore,
+        question.lookup_name(),
+        question.qtype_code,
+        question.qclass,
+    )
+    .await
+    {
+        Ok(Some(zr)) => {
+            log::debug!("DS Response: {}", zr);
+            note(|| format!("Datastore answered with {} records", zr.typerecords.len()));
+            zr
+        }
+        Ok(None) => {
+            // there's no address records, but there might be an ALIAS to flatten
+            note(|| "Datastore had no records, checking for an ALIAS".to_string());
+            match flatten_alias(&datastore, &question).await {
+                Ok(Some(zr)) => {
+                    note(|| format!("Flattened an ALIAS to {} records", zr.typerecords.len()));
+                    zr
+                }
+                Ok(None) => {
+                    if let Some(reply) =
+                        forwarded_reply(&datastore, &header, &question, client).await
+                    {
+                        return reply;
+                    }
+                    match query_negative_answer(&datastore, &question).await {
+                        Ok(Some(negative)) if !negative.zone_enabled => {
+                            let rcode = disabled_zone_rcode();
+                            log::debug!(
+                                "Zone {:?} is disabled, answering {rcode:?}",
+                                from_utf8(&negative.zone)
+                            );
+                            note(|| {
+                                format!(
+                                    "Zone {:?} is disabled, {rcode:?}",
+                                    from_utf8(&negative.zone).unwrap_or("-")
+                                )
+                            });
+                            return reply_builder(header.id, rcode);
+                        }
+                        Ok(Some(negative))
+                            if question.qtype == RecordType::SOA
+                                && negative.is_apex(&question.lookup_name()) =>
+                        {
+                            note(|| {
+                                format!(
+                                    "Answered with the SOA of zone {:?}",
+                                    from_utf8(&negative.zone).unwrap_or("-")
+                                )
+                            });
+                            let soa = Answer::named(negative.zone, negative.soa);
+                            return Ok(authoritative_answer(&header, question, vec![soa]));
+                        }
+                        Ok(Some(negative)) => {
+                            log::debug!(
+                                "No records, name_exists={} in zone {:?}",
+                                negative.name_exists,
+                                from_utf8(&negative.zone)
+                            );
+                            note(|| {
+                                format!(
+                                    "No records, name_exists={} in zone {:?}",
+                                    negative.name_exists,
+                                    from_utf8(&negative.zone).unwrap_or("-")
+                                )
+                            });
+                            return reply_negative(header.id, &question, &negative);
+                        }
+                        Ok(None) => {
+                            if let Some(negative) =
+                                crate::builtin::as112_answer(&crate::builtin::current(), &question)
+                            {
+                                note(|| "Not in any of our zones, answered as AS112".to_string());
+                                if question.qtype == RecordType::SOA
+                                    && negative.is_apex(&question.lookup_name())
+                                {
+                                    let soa = Answer::named(negative.zone, negative.soa);
+                                    return Ok(authoritative_answer(&header, question, vec![soa]));
+                                }
+                                return reply_negative(header.id, &question, &negative);
+                            }
+                            log::debug!("No zone in the datastore for the query");
+                            note(|| "Not in any of our zones, NXDOMAIN".to_string());
+                            return reply_nxdomain(header.id);
+                        }
+                        Err(rcode) => return reply_builder(header.id, rcode),
+                    }
+                }
+                Err(rcode) => return reply_builder(header.id, rcode),
             }
-        },
-        Err(error) => {
-            log::error!("Failed to get response from datastore: {:?}", error);
-            return reply_builder(header.id, Rcode::ServFail);
         }
+        Err(rcode) => return reply_builder(header.id, rcode),
     };
 
     // this is our reply - static until that bit's done
@@ -565,9 +1302,10 @@ async fn get_result(
             arcount: 0,
         },
         question: Some(question),
-        answers: record.typerecords,
+        answers: record.into_answers(),
         authorities: vec![], // TODO: we're authoritative, we should respond with our records!
         additional: vec![],
+        edns: None,
     })
 }
 
@@ -0,0 +1,207 @@
+//! Keeping zones the same as the zone files in a directory, which can be a git checkout - see
+//! [crate::config::SyncConfig]
+//!
+//! Zones that come from a file are marked with a [crate::db::SyncedZone] and can't be edited any other way. A zone
+//! that's already there when its file turns up is taken over, and when a file goes away its zone's either deleted or
+//! left to be edited again, depending on `prune`.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::config::ConfigFile;
+use crate::datastore::ZoneStore;
+use crate::error::GoatNsError;
+use crate::interop::from_bind;
+use crate::metrics::{Metrics, METRICS};
+use crate::zone_schema::zones_from_json;
+use crate::zonefile::{read_with_includes, soa_owner};
+use crate::zones::FileZone;
+
+/// How long git gets to clone or pull
+const GIT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// What a sync did
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct SyncSummary {
+    /// Zones that were created or changed to match their files
+    pub updated: Vec<String>,
+    /// Zones that already matched
+    pub unchanged: usize,
+    /// Zones whose files went away, which can be edited again
+    pub released: Vec<String>,
+    /// Zones whose files went away and were deleted because `prune`'s set
+    pub deleted: Vec<String>,
+}
+
+impl SyncSummary {
+    /// Did anything change?
+    pub fn is_empty(&self) -> bool {
+        self.updated.is_empty() && self.released.is_empty() && self.deleted.is_empty()
+    }
+}
+
+impl Display for SyncSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "updated={:?} unchanged={} released={:?} deleted={:?}",
+            self.updated, self.unchanged, self.released, self.deleted
+        )
+    }
+}
+
+/// Says which file an error came from
+fn in_file(source: &str, err: GoatNsError) -> GoatNsError {
+    let msg = match err {
+        GoatNsError::InvalidValue(msg) | GoatNsError::FileError(msg) => msg,
+        err => format!("{err:?}"),
+    };
+    GoatNsError::InvalidValue(format!("{source}: {msg}"))
+}
+
+/// Read a zone file, the zone's named by its SOA record, or the file name if there isn't one
+fn read_bind_file(path: &Path, allow_includes: bool) -> Result<FileZone, GoatNsError> {
+    let contents = read_with_includes(path, allow_includes, None)?;
+    let zone_name = match soa_owner(&contents)? {
+        Some(_) => None,
+        None => path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string()),
+    };
+    from_bind(&contents, zone_name.as_deref())
+}
+
+/// Every zone in `*.zone` and `*.json` files in the directory and under it, with the files they came from (relative to
+/// the directory). Hidden files and directories (like `.git`) are skipped, and a zone can only be in one file.
+pub fn read_zone_files(
+    directory: &Path,
+    allow_includes: bool,
+) -> Result<Vec<(String, FileZone)>, GoatNsError> {
+    let mut found: BTreeMap<String, (String, FileZone)> = BTreeMap::new();
+    let mut directories = vec![directory.to_path_buf()];
+    while let Some(dir) = directories.pop() {
+        let mut entries = fs::read_dir(&dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            if path.is_dir() {
+                directories.push(path);
+                continue;
+            }
+            let source = path
+                .strip_prefix(directory)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            let zones = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("zone") => read_bind_file(&path, allow_includes).map(|zone| vec![zone]),
+                Some("json") => fs::read_to_string(&path)
+                    .map_err(GoatNsError::from)
+                    .and_then(|contents| zones_from_json(&contents)),
+                _ => continue,
+            }
+            .map_err(|err| in_file(&source, err))?;
+            for zone in zones {
+                let name = zone.name.trim_end_matches('.').to_lowercase();
+                if let Some((other, _)) = found.get(&name) {
+                    return Err(GoatNsError::InvalidValue(format!(
+                        "{name} is in both {other} and {source}"
+                    )));
+                }
+                found.insert(name, (source.clone(), zone));
+            }
+        }
+    }
+    Ok(found.into_values().collect())
+}
+
+/// Run git, it's not allowed to ask for a password
+async fn git(args: &[&str]) -> Result<(), GoatNsError> {
+    let output = tokio::time::timeout(
+        GIT_TIMEOUT,
+        Command::new("git")
+            .args(args)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .map_err(|_| GoatNsError::Generic(format!("git {args:?} took too long")))??;
+    if !output.status.success() {
+        return Err(GoatNsError::Generic(format!(
+            "git {args:?} failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Clone the branch into the directory, or make the directory match the branch if it's been cloned already. Anything
+/// that's been changed in the checkout is thrown away.
+pub async fn git_pull(url: &str, branch: &str, directory: &Path) -> Result<(), GoatNsError> {
+    let dir = directory.to_string_lossy();
+    if !directory.join(".git").exists() {
+        log::info!("Cloning {branch} of {url} into {dir}");
+        return git(&[
+            "clone", "--quiet", "--depth", "1", "--branch", branch, "--", url, &dir,
+        ])
+        .await;
+    }
+    git(&[
+        "-C", &dir, "fetch", "--quiet", "--depth", "1", "origin", branch,
+    ])
+    .await?;
+    git(&["-C", &dir, "reset", "--quiet", "--hard", "FETCH_HEAD"]).await?;
+    git(&["-C", &dir, "clean", "--quiet", "-fdx"]).await
+}
+
+/// Pull the repository if there is one, then make the zones match the files
+pub async fn run_sync<S: ZoneStore>(
+    store: &S,
+    config: &ConfigFile,
+) -> Result<SyncSummary, GoatNsError> {
+    let sync = &config.sync;
+    let directory = sync.directory.as_ref().ok_or_else(|| {
+        GoatNsError::InvalidValue("There's no sync directory configured".to_string())
+    })?;
+    if let Some(url) = &sync.git_url {
+        git_pull(url, &sync.git_branch, directory).await?;
+    }
+    // anyone who can push to the repository shouldn't get to read the server's files
+    let mut zones = read_zone_files(directory, sync.git_url.is_none())?;
+    for (_, zone) in zones.iter_mut() {
+        // leaving the serial out means GoatNS looks after it
+        let serial = zone.serial;
+        zone.apply_defaults(config);
+        zone.serial = serial;
+    }
+    store
+        .sync_zones(zones, &config.import_ttl, sync.prune)
+        .await
+}
+
+/// Sync now and then every `interval_seconds`, a sync that fails doesn't change anything
+pub async fn sync_loop<S: ZoneStore>(store: S, config: ConfigFile) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.sync.interval_seconds));
+    loop {
+        interval.tick().await;
+        match run_sync(&store, &config).await {
+            Ok(summary) if summary.is_empty() => log::debug!("Zones are in sync: {summary}"),
+            Ok(summary) => log::info!("Synced zones: {summary}"),
+            Err(err) => {
+                log::error!("Zone sync failed: {err:?}");
+                Metrics::increment(&METRICS.syncs_failed);
+            }
+        }
+    }
+}
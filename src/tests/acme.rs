@@ -0,0 +1,44 @@
+use crate::acme::{add_challenge, challenge_answer, challenge_name, remove_challenges};
+use crate::enums::{RecordClass, RecordType};
+use crate::Question;
+
+fn question(qname: &str, qtype: RecordType) -> Question {
+    Question {
+        qname: qname.as_bytes().to_vec(),
+        qtype,
+        qtype_code: qtype as u16,
+        qclass: RecordClass::Internet,
+    }
+}
+
+#[test]
+fn test_challenge_name() {
+    assert_eq!(
+        challenge_name("ns1.Example.com."),
+        "_acme-challenge.ns1.example.com"
+    );
+    assert_eq!(
+        challenge_name("*.example.com"),
+        "_acme-challenge.example.com"
+    );
+}
+
+#[test]
+fn test_challenge_answer() {
+    let qname = "_acme-challenge.acme-test.example.goat";
+    assert!(challenge_answer(&question(qname, RecordType::TXT)).is_none());
+
+    add_challenge("acme-test.example.goat", "hello".to_string());
+    add_challenge("*.acme-test.example.goat", "world".to_string());
+    let answer = challenge_answer(&question(
+        "_ACME-challenge.acme-test.example.goat",
+        RecordType::TXT,
+    ))
+    .expect("should have answered the challenge");
+    assert_eq!(answer.typerecords.len(), 2);
+    // only TXT gets answered
+    assert!(challenge_answer(&question(qname, RecordType::A)).is_none());
+
+    remove_challenges("acme-test.example.goat");
+    assert!(challenge_answer(&question(qname, RecordType::TXT)).is_none());
+}
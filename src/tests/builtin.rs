@@ -0,0 +1,152 @@
+use crate::builtin::{
+    as112_answer, as112_zones, server_id_answer, status_allowed, status_answer, STATUS_ZONE,
+};
+use crate::config::BuiltinZones;
+use crate::enums::{RecordClass, RecordType};
+use crate::resourcerecord::{DNSCharString, InternalResourceRecord};
+use crate::Question;
+
+fn question(name: &str, qtype: RecordType, qclass: RecordClass) -> Question {
+    Question::new(name.as_bytes().to_vec(), qtype, qclass)
+}
+
+#[test]
+fn test_server_id_answer() {
+    let off = BuiltinZones::default();
+    let on = BuiltinZones {
+        server_id: Some("ns1.example.com".to_string()),
+        ..Default::default()
+    };
+
+    for name in ["id.server", "HOSTNAME.BIND", "hostname.bind."] {
+        let query = question(name, RecordType::TXT, RecordClass::Chaos);
+        assert!(server_id_answer(&off, &query).is_none());
+        let answer = server_id_answer(&on, &query).expect("No answer");
+        match answer.typerecords.as_slice() {
+            [InternalResourceRecord::TXT { txtdata, class, .. }] => {
+                assert_eq!(txtdata, &vec![DNSCharString::from("ns1.example.com")]);
+                assert_eq!(*class, RecordClass::Chaos);
+            }
+            other => panic!("Unexpected answer {other:?}"),
+        }
+    }
+
+    // only in CHAOS, and only those names
+    for query in [
+        question("id.server", RecordType::TXT, RecordClass::Internet),
+        question("id.server", RecordType::A, RecordClass::Chaos),
+        question("version.bind", RecordType::TXT, RecordClass::Chaos),
+    ] {
+        assert!(server_id_answer(&on, &query).is_none(), "{query:?}");
+    }
+}
+
+#[test]
+fn test_as112_answer() {
+    let zones = as112_zones();
+    assert_eq!(zones.len(), 19);
+    assert!(zones.contains(&"31.172.in-addr.arpa".to_string()));
+    assert!(!zones.contains(&"32.172.in-addr.arpa".to_string()));
+
+    let off = BuiltinZones::default();
+    let on = BuiltinZones {
+        as112: true,
+        ..Default::default()
+    };
+
+    let query = question(
+        "1.1.168.192.in-addr.arpa",
+        RecordType::PTR,
+        RecordClass::Internet,
+    );
+    assert!(as112_answer(&off, &query).is_none());
+    let negative = as112_answer(&on, &query).expect("No answer");
+    assert_eq!(negative.zone, b"168.192.in-addr.arpa");
+    assert!(!negative.name_exists);
+    assert_eq!(*negative.soa.ttl(), 604800);
+
+    // the apex exists
+    let apex = question("10.IN-ADDR.ARPA", RecordType::SOA, RecordClass::Internet);
+    assert!(as112_answer(&on, &apex).expect("No answer").name_exists);
+
+    // public space and names that only look similar aren't ours
+    for name in [
+        "1.1.1.1.in-addr.arpa",
+        "1.32.172.in-addr.arpa",
+        "110.in-addr.arpa",
+        "168.192.in-addr.arpa.example.com",
+    ] {
+        let query = question(name, RecordType::PTR, RecordClass::Internet);
+        assert!(as112_answer(&on, &query).is_none(), "{name}");
+    }
+}
+
+#[test]
+fn test_status_answer() {
+    let off = BuiltinZones::default();
+    let on = BuiltinZones {
+        status: true,
+        status_clients: vec!["192.0.2.0/24".parse().expect("Failed to parse network")],
+        ..Default::default()
+    };
+
+    let query = question(STATUS_ZONE, RecordType::TXT, RecordClass::Internet);
+    assert!(status_answer(&off, &query).is_none());
+    let answer = status_answer(&on, &query).expect("No answer");
+    let txt: Vec<String> = answer
+        .typerecords
+        .iter()
+        .map(|record| match record {
+            InternalResourceRecord::TXT { txtdata, .. } => txtdata
+                .iter()
+                .map(|data| String::from_utf8_lossy(&data.data).to_string())
+                .collect(),
+            other => panic!("Unexpected record {other:?}"),
+        })
+        .collect();
+    assert!(txt.contains(&format!("version={}", env!("CARGO_PKG_VERSION"))));
+    assert!(txt.iter().any(|value| value.starts_with("uptime=")));
+    assert!(txt.iter().any(|value| value.starts_with("queries.doh=")));
+
+    // each value's there on its own too
+    let query = question(
+        "Queries.UDP.status.goatns.internal.",
+        RecordType::TXT,
+        RecordClass::Internet,
+    );
+    let answer = status_answer(&on, &query).expect("No answer");
+    assert_eq!(answer.typerecords.len(), 1);
+
+    for query in [
+        question(STATUS_ZONE, RecordType::A, RecordClass::Internet),
+        question(STATUS_ZONE, RecordType::TXT, RecordClass::Chaos),
+        question(
+            "nope.status.goatns.internal",
+            RecordType::TXT,
+            RecordClass::Internet,
+        ),
+        question(
+            "xstatus.goatns.internal",
+            RecordType::TXT,
+            RecordClass::Internet,
+        ),
+    ] {
+        assert!(status_answer(&on, &query).is_none(), "{query:?}");
+    }
+
+    for (client, allowed) in [
+        (Some("127.0.0.1"), true),
+        (Some("::1"), true),
+        (Some("::ffff:127.0.0.1"), true),
+        (Some("192.0.2.10"), true),
+        (Some("198.51.100.1"), false),
+        (None, false),
+    ] {
+        let client = client.map(|client| client.parse().expect("Failed to parse address"));
+        assert_eq!(status_allowed(&on, client), allowed, "{client:?}");
+    }
+    assert!(!status_allowed(
+        &off,
+        Some("198.51.100.1".parse().expect("Failed to parse address"))
+    ));
+}
@@ -0,0 +1,647 @@
+//! Protocol compliance checks - raw wire queries in, replies out, the things resolvers doing QNAME
+//! minimisation and 0x20 case randomisation rely on, and TCP connection reuse
+
+use std::time::Duration;
+
+use bytes::BytesMut;
+use packed_struct::prelude::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::QueryChecks;
+use crate::datastore::{manager, Queued, SqliteStore};
+use crate::db::test::test_get_sqlite_memory;
+use crate::db::{start_db, DBEntity};
+use crate::edns::{udp_reply_size, Edns, EDNS_TCP_KEEPALIVE, MIN_UDP_PAYLOAD_SIZE};
+use crate::enums::{OpCode, RecordClass, RecordType};
+use crate::reply::Reply;
+use crate::servers::{
+    apply_tcp_keepalive, check_query, parse_query, tcp_conn_handler, Checked, QueryProtocol,
+    TcpSettings,
+};
+use crate::zones::{FileZone, FileZoneRecord};
+use crate::{Header, Question, Rcode, HEADER_BYTES, UDP_BUFFER_SIZE};
+
+fn a_record(name: &str, rdata: &str) -> FileZoneRecord {
+    FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: name.to_string(),
+        rrtype: "A".to_string(),
+        class: RecordClass::Internet,
+        rdata: rdata.to_string(),
+        ttl: 300,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    }
+}
+
+/// Starts a datastore with a couple of zones in it, one of them saved with a trailing dot
+async fn compliance_datastore() -> tokio::sync::mpsc::Sender<Queued> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await.expect("Failed to start DB");
+    for zone in [
+        FileZone {
+            name: "compliance.goat".to_string(),
+            records: vec![
+                a_record("@", "192.0.2.1"),
+                a_record("www", "192.0.2.2"),
+                a_record("Mixed.", "192.0.2.3"),
+                FileZoneRecord {
+                    rrtype: "TYPE65534".to_string(),
+                    rdata: "\\# 4 0a000001".to_string(),
+                    ..a_record("generic", "")
+                },
+            ],
+            ..Default::default()
+        },
+        FileZone {
+            name: "dotted.goat.".to_string(),
+            records: vec![a_record("www", "192.0.2.4")],
+            ..Default::default()
+        },
+    ] {
+        zone.save(&pool).await.expect("Failed to save zone");
+    }
+
+    let (tx, rx) = tokio::sync::mpsc::channel(10);
+    tokio::spawn(manager(rx, SqliteStore::new(pool), None, None, None, 4));
+    tx
+}
+
+/// A query header followed by `question`, which is whatever bytes you want on the wire
+fn raw_query(question: &[u8]) -> Vec<u8> {
+    let mut query = Header {
+        id: 4321,
+        qdcount: 1,
+        ..Default::default()
+    }
+    .pack()
+    .expect("Failed to pack header")
+    .to_vec();
+    query.extend(question);
+    query
+}
+
+/// The on-the-wire name for a list of labels, with the type and class after it
+fn wire_question(labels: &[&[u8]], qtype: u16, qclass: u16) -> Vec<u8> {
+    let mut question = vec![];
+    for label in labels {
+        question.push(label.len() as u8);
+        question.extend(*label);
+    }
+    question.push(0);
+    question.extend(qtype.to_be_bytes());
+    question.extend(qclass.to_be_bytes());
+    question
+}
+
+async fn send(datastore: &tokio::sync::mpsc::Sender<Queued>, query: &[u8]) -> Reply {
+    parse_query(
+        datastore.clone(),
+        query.len(),
+        query,
+        false,
+        QueryProtocol::Udp,
+        None,
+    )
+    .await
+    .expect("Failed to handle query")
+}
+
+#[tokio::test]
+/// 0x20 randomised queries get answered, and the question goes back exactly how they sent it
+async fn test_mixed_case_query_is_echoed() {
+    let datastore = compliance_datastore().await;
+
+    let question = wire_question(&[b"wWw", b"CoMpLiAnCe", b"GOAT"], 1, 1);
+    let query = raw_query(&question);
+    let reply = send(&datastore, &query).await;
+    assert_eq!(reply.header.rcode, Rcode::NoError);
+    assert_eq!(reply.answers.len(), 1);
+
+    let reply_bytes = reply.as_bytes().expect("Failed to encode reply");
+    assert_eq!(
+        reply_bytes[HEADER_BYTES..HEADER_BYTES + question.len()],
+        question
+    );
+}
+
+#[tokio::test]
+/// the apex, names stored in mixed case and zones stored with a trailing dot all answer
+async fn test_stored_names_are_normalized() {
+    let datastore = compliance_datastore().await;
+
+    for labels in [
+        vec![b"compliance".as_slice(), b"goat"],
+        vec![b"mixed", b"compliance", b"goat"],
+        vec![b"MIXED", b"compliance", b"goat"],
+        vec![b"www", b"dotted", b"goat"],
+    ] {
+        let query = raw_query(&wire_question(&labels, RecordType::A as u16, 1));
+        let reply = send(&datastore, &query).await;
+        assert_eq!(reply.header.rcode, Rcode::NoError, "{labels:?}");
+        assert_eq!(reply.answers.len(), 1, "{labels:?}");
+    }
+
+    // the negative answers find the zone too
+    let query = raw_query(&wire_question(
+        &[b"nope", b"dotted", b"goat"],
+        RecordType::A as u16,
+        1,
+    ));
+    let reply = send(&datastore, &query).await;
+    assert_eq!(reply.header.rcode, Rcode::NameError);
+    assert_eq!(reply.authorities.len(), 1);
+}
+
+#[tokio::test]
+/// types we don't know about get served from their generic rdata, ref RFC3597
+async fn test_generic_record_query() {
+    let datastore = compliance_datastore().await;
+
+    let question = wire_question(&[b"generic", b"compliance", b"goat"], 65534, 1);
+    let parsed = Question::from_packets(&question).expect("Failed to parse question");
+    assert_eq!(parsed.qtype, RecordType::InvalidType);
+    assert_eq!(parsed.qtype_code, 65534);
+
+    let reply = send(&datastore, &raw_query(&question)).await;
+    assert_eq!(reply.header.rcode, Rcode::NoError);
+    assert_eq!(reply.answers.len(), 1);
+    assert_eq!(reply.answers[0].record.rrtype_code(), 65534);
+
+    let reply_bytes = reply.as_bytes().expect("Failed to encode reply");
+    assert_eq!(
+        reply_bytes[HEADER_BYTES..HEADER_BYTES + question.len()],
+        question
+    );
+    // type, class, ttl, rdlength and then the rdata as it was stored
+    assert!(reply_bytes.ends_with(&[0xff, 0xfe, 0, 1, 0, 0, 1, 44, 0, 4, 10, 0, 0, 1]));
+
+    // a name that's there but doesn't have the type is NODATA
+    let query = raw_query(&wire_question(&[b"www", b"compliance", b"goat"], 65534, 1));
+    let reply = send(&datastore, &query).await;
+    assert_eq!(reply.header.rcode, Rcode::NoError);
+    assert!(reply.answers.is_empty());
+}
+
+#[tokio::test]
+/// there's no secondary zones to refresh, so a NOTIFY gets refused rather than dropped
+async fn test_notify_is_refused() {
+    let datastore = compliance_datastore().await;
+
+    let mut query = Header {
+        id: 4321,
+        opcode: OpCode::Notify,
+        qdcount: 1,
+        ..Default::default()
+    }
+    .pack()
+    .expect("Failed to pack header")
+    .to_vec();
+    query.extend(wire_question(
+        &[b"compliance", b"goat"],
+        RecordType::SOA as u16,
+        1,
+    ));
+    let reply = send(&datastore, &query).await;
+    assert_eq!(reply.header.id, 4321);
+    assert_eq!(reply.header.opcode, OpCode::Notify);
+    assert_eq!(reply.header.rcode, Rcode::Refused);
+}
+
+#[tokio::test]
+/// a query for the root is just the null label, and it's not ours
+async fn test_root_query() {
+    let question = wire_question(&[], RecordType::NS as u16, 1);
+    let parsed = Question::from_packets(&question).expect("Failed to parse root question");
+    assert!(parsed.qname.is_empty());
+    assert_eq!(parsed.qtype, RecordType::NS);
+    assert_eq!(parsed.qclass, RecordClass::Internet);
+    assert_eq!(
+        parsed
+            .try_to_bytes()
+            .expect("Failed to encode root question"),
+        question
+    );
+
+    let datastore = compliance_datastore().await;
+    let reply = send(&datastore, &raw_query(&question)).await;
+    assert_eq!(reply.header.rcode, Rcode::NameError);
+}
+
+#[tokio::test]
+/// names that aren't valid on the wire get FORMERR, not SERVFAIL
+async fn test_malformed_names_are_format_errors() {
+    let datastore = compliance_datastore().await;
+
+    let long_label = [b'a'; 64];
+    // 127 labels of "a" is 253 characters with the dots, one more is too many
+    let labels: Vec<&[u8]> = (0..128).map(|_| b"a".as_slice()).collect();
+
+    for (description, question) in [
+        (
+            "unterminated name",
+            vec![3, b'w', b'w', b'w', 4, b'g', b'o'],
+        ),
+        (
+            "64 byte label",
+            wire_question(&[&long_label, b"goat"], 1, 1),
+        ),
+        ("255 character name", wire_question(&labels, 1, 1)),
+        (
+            "compression pointer",
+            vec![3, b'w', b'w', b'w', 0xc0, 12, 0, 1, 0, 1],
+        ),
+    ] {
+        let reply = send(&datastore, &raw_query(&question)).await;
+        assert_eq!(reply.header.rcode, Rcode::FormatError, "{description}");
+        assert_eq!(reply.header.id, 4321, "{description}");
+    }
+
+    // right on the limit is fine
+    let question = wire_question(&labels[1..], 1, 1);
+    let parsed = Question::from_packets(&question).expect("Failed to parse 253 character name");
+    assert_eq!(parsed.qname.len(), 253);
+}
+
+#[tokio::test]
+/// we don't have anything in classes we don't know about
+async fn test_unknown_class_is_refused() {
+    let datastore = compliance_datastore().await;
+    let query = raw_query(&wire_question(
+        &[b"www", b"compliance", b"goat"],
+        RecordType::A as u16,
+        42,
+    ));
+    let reply = send(&datastore, &query).await;
+    assert_eq!(reply.header.rcode, Rcode::Refused);
+}
+
+/// An OPT record asking for edns-tcp-keepalive, with `timeout` as the option's value if there is one
+fn keepalive_query(timeout: Option<u16>) -> Vec<u8> {
+    let mut query = Header {
+        id: 4321,
+        qdcount: 1,
+        arcount: 1,
+        ..Default::default()
+    }
+    .pack()
+    .expect("Failed to pack header")
+    .to_vec();
+    query.extend(wire_question(
+        &[b"www", b"compliance", b"goat"],
+        RecordType::A as u16,
+        1,
+    ));
+    let value = timeout
+        .map(|t| t.to_be_bytes().to_vec())
+        .unwrap_or_default();
+    let mut opt = BytesMut::new();
+    Edns {
+        udp_payload_size: 1232,
+        options: vec![(EDNS_TCP_KEEPALIVE, value)],
+    }
+    .write_to(&mut opt);
+    query.extend(opt);
+    query
+}
+
+#[test]
+/// the OPT record comes back out of a query the way it went in
+fn test_edns_from_query() {
+    let edns = Edns::from_query(&keepalive_query(None))
+        .expect("Failed to parse query")
+        .expect("Didn't find the OPT record");
+    assert_eq!(edns.udp_payload_size, 1232);
+    assert_eq!(edns.option(EDNS_TCP_KEEPALIVE), Some([].as_slice()));
+
+    let edns = Edns::from_query(&keepalive_query(Some(50)))
+        .expect("Failed to parse query")
+        .expect("Didn't find the OPT record");
+    assert_eq!(
+        edns.option(EDNS_TCP_KEEPALIVE),
+        Some(50u16.to_be_bytes().as_slice())
+    );
+
+    let plain = raw_query(&wire_question(&[b"goat"], 1, 1));
+    assert_eq!(
+        Edns::from_query(&plain).expect("Failed to parse query"),
+        None
+    );
+}
+
+#[tokio::test]
+/// a datagram that's too short for a header is an error, not a panic in the task answering it
+async fn test_short_datagram() {
+    let datastore = compliance_datastore().await;
+    let datagram = [0x12, 0x34, 0x01];
+    assert!(parse_query(
+        datastore,
+        datagram.len(),
+        &datagram,
+        false,
+        QueryProtocol::Udp,
+        None,
+    )
+    .await
+    .is_err());
+}
+
+#[tokio::test]
+/// there has to be exactly one question, anything else is FORMERR even without the strict checks
+async fn test_question_count() {
+    let datastore = compliance_datastore().await;
+    let www = wire_question(&[b"www", b"compliance", b"goat"], 1, 1);
+
+    let mut two_questions = raw_query(&[www.clone(), wire_question(&[b"goat"], 28, 1)].concat());
+    two_questions[5] = 2;
+    // says there's two but only has one
+    let mut short = raw_query(&www);
+    short[5] = 2;
+    let mut none = raw_query(&[]);
+    none[5] = 0;
+    // the count's big-endian, so this is 257
+    let mut lots = raw_query(&www);
+    lots[4] = 1;
+
+    for query in [two_questions, short, none, lots] {
+        let reply = send(&datastore, &query).await;
+        assert_eq!(reply.header.id, 4321);
+        assert_eq!(reply.header.rcode, Rcode::FormatError);
+        assert!(reply.question.is_none());
+        assert!(reply.answers.is_empty());
+        let reply_bytes = reply.as_bytes().expect("Failed to encode reply");
+        assert_eq!(reply_bytes.len(), HEADER_BYTES);
+        // no questions go back
+        assert_eq!(reply_bytes[4..6], [0, 0]);
+    }
+
+    // one's fine
+    let reply = send(&datastore, &raw_query(&www)).await;
+    assert_eq!(reply.header.rcode, Rcode::NoError);
+    assert_eq!(reply.answers.len(), 1);
+}
+
+#[test]
+/// with the checks on, things that aren't queries get FORMERR or nothing at all, and forged UDP ports get nothing
+fn test_check_query() {
+    let query = raw_query(&wire_question(&[b"goat"], 1, 1));
+    let mut response = query.clone();
+    // the QR bit
+    response[2] |= 0b1000_0000;
+    let mut two_questions = raw_query(
+        &[
+            wire_question(&[b"goat"], 1, 1),
+            wire_question(&[b"goat"], 28, 1),
+        ]
+        .concat(),
+    );
+    two_questions[5] = 2;
+
+    // they're off by default
+    assert!(matches!(
+        check_query(QueryChecks::default(), &response, Some(19)),
+        Checked::Answer
+    ));
+
+    let strict = QueryChecks {
+        strict: true,
+        drop: false,
+    };
+    assert!(matches!(
+        check_query(strict, &query, Some(53000)),
+        Checked::Answer
+    ));
+    assert!(matches!(check_query(strict, &query, None), Checked::Answer));
+    for bad in [&response, &two_questions] {
+        match check_query(strict, bad, Some(53000)) {
+            Checked::Reply(reply) => {
+                assert_eq!(reply.header.id, 4321);
+                assert_eq!(reply.header.rcode, Rcode::FormatError);
+            }
+            other => panic!("Expected FORMERR, got {other:?}"),
+        }
+    }
+    for port in [0, 7, 19] {
+        assert!(matches!(
+            check_query(strict, &query, Some(port)),
+            Checked::Drop
+        ));
+    }
+    assert!(matches!(
+        check_query(strict, &query[..4], None),
+        Checked::Drop
+    ));
+
+    let drop = QueryChecks {
+        strict: true,
+        drop: true,
+    };
+    assert!(matches!(check_query(drop, &query, None), Checked::Answer));
+    assert!(matches!(check_query(drop, &response, None), Checked::Drop));
+    assert_eq!(
+        QueryChecks {
+            strict: false,
+            drop: true
+        }
+        .check()
+        .len(),
+        1
+    );
+}
+
+#[test]
+/// UDP replies that don't fit what the client can take come back truncated without their records, so they use TCP
+fn test_udp_reply_truncation() {
+    // 512 bytes without EDNS, otherwise what they ask for as long as it's not more than we'd take
+    let plain = raw_query(&wire_question(&[b"goat"], 1, 1));
+    assert_eq!(udp_reply_size(&plain), MIN_UDP_PAYLOAD_SIZE);
+    assert_eq!(udp_reply_size(&keepalive_query(None)), 1232);
+
+    let mut reply =
+        crate::reply::reply_builder(4321, Rcode::NoError).expect("Failed to build reply");
+    reply.question = Some(Question::new(
+        b"big.compliance.goat".to_vec(),
+        RecordType::TXT,
+        RecordClass::Internet,
+    ));
+    reply.answers = (0..8)
+        .map(|_| crate::resourcerecord::InternalResourceRecord::TXT {
+            txtdata: vec!["goat".repeat(25).as_str().into()],
+            ttl: 300,
+            class: RecordClass::Internet,
+        })
+        .map(crate::reply::Answer::from)
+        .collect();
+    let full = reply.as_bytes().expect("Failed to encode reply");
+    assert!(full.len() > MIN_UDP_PAYLOAD_SIZE);
+    assert_eq!(
+        reply
+            .as_bytes_udp(UDP_BUFFER_SIZE)
+            .expect("Failed to encode reply"),
+        full
+    );
+
+    let truncated = reply
+        .as_bytes_udp(MIN_UDP_PAYLOAD_SIZE)
+        .expect("Failed to encode reply");
+    assert!(truncated.len() <= MIN_UDP_PAYLOAD_SIZE);
+    let mut header = [0u8; HEADER_BYTES];
+    header.copy_from_slice(&truncated[..HEADER_BYTES]);
+    let header = Header::unpack(&header).expect("Failed to unpack header");
+    assert!(header.truncated);
+    assert_eq!(header.ancount, 0);
+}
+
+#[test]
+/// RFC7828 - clients ask with an empty option, and aren't allowed to send a timeout of their own
+fn test_apply_tcp_keepalive() {
+    let reply = crate::reply::reply_builder(4321, Rcode::NoError).expect("Failed to build reply");
+
+    let keepalive = apply_tcp_keepalive(reply.clone(), &keepalive_query(None), 100)
+        .expect("Failed to apply keepalive");
+    assert_eq!(keepalive.header.rcode, Rcode::NoError);
+    assert_eq!(
+        keepalive
+            .edns
+            .as_ref()
+            .and_then(|edns| edns.option(EDNS_TCP_KEEPALIVE)),
+        Some(100u16.to_be_bytes().as_slice())
+    );
+
+    let formerr = apply_tcp_keepalive(reply.clone(), &keepalive_query(Some(100)), 100)
+        .expect("Failed to apply keepalive");
+    assert_eq!(formerr.header.rcode, Rcode::FormatError);
+    assert!(formerr.edns.is_none());
+
+    let plain = raw_query(&wire_question(&[b"goat"], 1, 1));
+    let untouched = apply_tcp_keepalive(reply, &plain, 100).expect("Failed to apply keepalive");
+    assert!(untouched.edns.is_none());
+}
+
+#[tokio::test]
+/// a TCP connection stays open for more queries, until it's used up its allowance
+async fn test_tcp_connection_reuse() {
+    let datastore = compliance_datastore().await;
+    let (agent_tx, _) = tokio::sync::broadcast::channel(10);
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind listener");
+    let addr = listener
+        .local_addr()
+        .expect("Failed to get listener address");
+    let settings = TcpSettings {
+        idle_timeout: Duration::from_secs(2),
+        client_timeout: Duration::from_secs(2),
+        max_queries: 2,
+        max_message_size: 4096,
+        capture_packets: false,
+        query_checks: Default::default(),
+    };
+    tokio::spawn(async move {
+        let (mut stream, client) = listener.accept().await.expect("Failed to accept");
+        tcp_conn_handler(&mut stream, client, datastore, agent_tx, settings, false).await
+    });
+
+    let mut client = TcpStream::connect(addr).await.expect("Failed to connect");
+    // the first one gets the idle timeout, the last one's told the connection is closing
+    for expected_timeout in [20u16, 0] {
+        let query = keepalive_query(None);
+        client
+            .write_all(&(query.len() as u16).to_be_bytes())
+            .await
+            .expect("Failed to send length");
+        client
+            .write_all(&query)
+            .await
+            .expect("Failed to send query");
+
+        let reply_len = client
+            .read_u16()
+            .await
+            .expect("Failed to read reply length");
+        let mut reply = vec![0; reply_len as usize];
+        client
+            .read_exact(&mut reply)
+            .await
+            .expect("Failed to read reply");
+        let mut header = [0u8; HEADER_BYTES];
+        header.copy_from_slice(&reply[0..HEADER_BYTES]);
+        let header = Header::unpack(&header).expect("Failed to unpack header");
+        assert_eq!(header.rcode, Rcode::NoError);
+        assert_eq!(header.ancount, 1);
+        assert_eq!(header.arcount, 1);
+        // the OPT record is the last thing in the reply, and the keepalive is its only option
+        assert_eq!(
+            reply[reply.len() - 6..],
+            [
+                EDNS_TCP_KEEPALIVE.to_be_bytes(),
+                2u16.to_be_bytes(),
+                expected_timeout.to_be_bytes()
+            ]
+            .concat()
+        );
+    }
+
+    // and then it's closed
+    let mut buf = [0u8; 1];
+    assert_eq!(client.read(&mut buf).await.expect("Failed to read"), 0);
+}
+
+#[tokio::test]
+/// an ordinary query over TCP gets answered, not refused like a CHAOS shutdown
+async fn test_tcp_plain_query() {
+    let datastore = compliance_datastore().await;
+    let (agent_tx, _) = tokio::sync::broadcast::channel(10);
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind listener");
+    let addr = listener
+        .local_addr()
+        .expect("Failed to get listener address");
+    let settings = TcpSettings {
+        idle_timeout: Duration::from_secs(2),
+        client_timeout: Duration::from_secs(2),
+        max_queries: 1,
+        max_message_size: 4096,
+        capture_packets: false,
+        query_checks: Default::default(),
+    };
+    tokio::spawn(async move {
+        let (mut stream, client) = listener.accept().await.expect("Failed to accept");
+        tcp_conn_handler(&mut stream, client, datastore, agent_tx, settings, false).await
+    });
+
+    let mut client = TcpStream::connect(addr).await.expect("Failed to connect");
+    let query = raw_query(&wire_question(
+        &[b"www", b"compliance", b"goat"],
+        RecordType::A as u16,
+        1,
+    ));
+    client
+        .write_all(&(query.len() as u16).to_be_bytes())
+        .await
+        .expect("Failed to send length");
+    client
+        .write_all(&query)
+        .await
+        .expect("Failed to send query");
+
+    let reply_len = client
+        .read_u16()
+        .await
+        .expect("Failed to read reply length");
+    let mut reply = vec![0; reply_len as usize];
+    client
+        .read_exact(&mut reply)
+        .await
+        .expect("Failed to read reply");
+    let mut header = [0u8; HEADER_BYTES];
+    header.copy_from_slice(&reply[0..HEADER_BYTES]);
+    let header = Header::unpack(&header).expect("Failed to unpack header");
+    assert_eq!(header.rcode, Rcode::NoError);
+    assert_eq!(header.ancount, 1);
+    assert_eq!(header.arcount, 0);
+}
@@ -41,6 +41,7 @@ async fn userauthtoken_saves() -> Result<(), GoatNsError> {
         userid: 1,
         tokenkey: "tokenkey".to_string(),
         tokenhash: "hello world".to_string(),
+        last_used: None,
     };
     println!("Saving UAT Object to DB: {uat:?}");
 
@@ -82,6 +83,7 @@ async fn userauthtoken_expiry() -> Result<(), GoatNsError> {
         userid: 1,
         tokenkey: "hello world".to_string(),
         tokenhash,
+        last_used: None,
     };
     println!("Saving UAT Object 1 to DB: {uat:?}");
 
@@ -97,6 +99,7 @@ async fn userauthtoken_expiry() -> Result<(), GoatNsError> {
         userid: 1,
         tokenkey: "hello world".to_string(),
         tokenhash,
+        last_used: None,
     };
     println!("Saving UAT Object 2 to DB: {uat:?}");
     let res = uat.save(&pool).await;
@@ -118,6 +121,302 @@ async fn userauthtoken_expiry() -> Result<(), GoatNsError> {
     Ok(())
 }
 
+#[tokio::test]
+async fn doh_usage_by_token() -> Result<(), GoatNsError> {
+    use crate::db::{DohUsage, UserAuthToken};
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+    test_harness::create_test_user(&pool).await?;
+
+    UserAuthToken {
+        name: "Resolver".to_string(),
+        id: None,
+        issued: Utc::now(),
+        expiry: None,
+        userid: 1,
+        tokenkey: "dohtoken".to_string(),
+        tokenhash: "hello world".to_string(),
+        last_used: None,
+    }
+    .save(&pool)
+    .await?;
+
+    let today = Utc::now().date_naive();
+    let yesterday = today - TimeDelta::days(1);
+    let mut txn = pool.begin().await?;
+    for (day, name, queries) in [
+        (today, "example.com", 5),
+        (today, "example.com", 2),
+        (today, "goat.example.com", 1),
+        (yesterday, "goat.example.com", 10),
+    ] {
+        DohUsage {
+            tokenkey: "dohtoken".to_string(),
+            day,
+            name: name.to_string(),
+            queries,
+        }
+        .add_with_txn(&mut txn)
+        .await?;
+    }
+    // nobody owns this one, so it shouldn't show up
+    DohUsage {
+        tokenkey: "deletedtoken".to_string(),
+        day: today,
+        name: "example.com".to_string(),
+        queries: 1,
+    }
+    .add_with_txn(&mut txn)
+    .await?;
+
+    let usage = DohUsage::for_user(&mut txn, 1, yesterday, 10).await?;
+    assert_eq!(usage.len(), 1);
+    assert_eq!(usage[0].token_name, "Resolver");
+    assert_eq!(usage[0].days, vec![(today, 8), (yesterday, 10)]);
+    assert_eq!(
+        usage[0].top_names,
+        vec![
+            ("goat.example.com".to_string(), 11),
+            ("example.com".to_string(), 7)
+        ]
+    );
+    assert_eq!(
+        DohUsage::for_user(&mut txn, 1, yesterday, 1).await?[0].top_names,
+        vec![("goat.example.com".to_string(), 11)]
+    );
+    assert!(DohUsage::for_user(&mut txn, 2, yesterday, 10)
+        .await?
+        .is_empty());
+    txn.commit().await?;
+
+    // yesterday's gone, and so is the token that doesn't exist any more
+    assert_eq!(DohUsage::cleanup(&pool, today).await?, 2);
+    let mut conn = pool.acquire().await?;
+    let usage = DohUsage::for_user(&mut conn, 1, yesterday, 10).await?;
+    assert_eq!(usage[0].days, vec![(today, 8)]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn user_quotas() -> Result<(), GoatNsError> {
+    use crate::config::QuotaConfig;
+    use crate::db::UserQuota;
+    use crate::enums::{RecordClass, RecordType};
+    use crate::zones::{FileZone, FileZoneRecord};
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+    let user = test_harness::create_test_user(&pool).await?;
+    let userid = user.id.expect("No user id");
+
+    let zone = FileZone {
+        name: "quota.goat".to_string(),
+        records: vec![FileZoneRecord {
+            id: None,
+            zoneid: None,
+            name: "www".to_string(),
+            rrtype: RecordType::A.to_string(),
+            class: RecordClass::Internet,
+            rdata: "192.0.2.1".to_string(),
+            ttl: 300,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        }],
+        ..Default::default()
+    }
+    .save(&pool)
+    .await?;
+    ZoneOwnership {
+        id: None,
+        userid,
+        zoneid: zone.id.expect("No zone id"),
+    }
+    .save(&pool)
+    .await?;
+
+    let config = QuotaConfig {
+        max_zones: 1,
+        max_records_per_zone: 5,
+    };
+    let mut conn = pool.acquire().await?;
+
+    // no limits unless they're configured
+    UserQuota::check_zones(&mut conn, &user, &QuotaConfig::default(), 100).await?;
+    assert_eq!(
+        UserQuota::for_user(&mut conn, &user, &config).await?,
+        config
+    );
+    assert!(matches!(
+        UserQuota::check_zones(&mut conn, &user, &config, 1).await,
+        Err(GoatNsError::QuotaExceeded(_))
+    ));
+    UserQuota::check_records(&mut conn, &user, &config, zone.id, 4).await?;
+    assert!(matches!(
+        UserQuota::check_records(&mut conn, &user, &config, zone.id, 5).await,
+        Err(GoatNsError::QuotaExceeded(_))
+    ));
+    UserQuota::check_records(&mut conn, &user, &config, None, 5).await?;
+
+    // the override only replaces what it sets
+    let quota = UserQuota {
+        userid,
+        max_zones: Some(3),
+        max_records_per_zone: None,
+    };
+    quota.save(&pool).await?;
+    assert_eq!(UserQuota::get(&mut conn, userid).await?, Some(quota));
+    assert_eq!(
+        UserQuota::get_all(&mut conn).await?,
+        vec![("testuser".to_string(), quota)]
+    );
+    assert_eq!(
+        UserQuota::for_user(&mut conn, &user, &config).await?,
+        QuotaConfig {
+            max_zones: 3,
+            max_records_per_zone: 5
+        }
+    );
+    UserQuota::check_zones(&mut conn, &user, &config, 2).await?;
+
+    // admins don't have any
+    let mut admin = user.clone();
+    admin.admin = true;
+    UserQuota::check_zones(&mut conn, &admin, &config, 100).await?;
+    UserQuota::check_records(&mut conn, &admin, &config, zone.id, 100).await?;
+
+    UserQuota::delete(&pool, userid).await?;
+    assert!(UserQuota::get(&mut conn, userid).await?.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn public_zones_for_rdap() -> Result<(), GoatNsError> {
+    use crate::enums::{RecordClass, RecordType};
+    use crate::web::rdap::public_domains;
+    use crate::zones::{FileZone, FileZoneRecord};
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let record = |name: &str, rdata: &str| FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: name.to_string(),
+        rrtype: RecordType::NS.to_string(),
+        class: RecordClass::Internet,
+        rdata: rdata.to_string(),
+        ttl: 300,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    };
+    let public = FileZone {
+        name: "public.goat".to_string(),
+        rname: "hostmaster.public.goat".to_string(),
+        serial: 5,
+        records: vec![
+            record("@", "ns2.public.goat."),
+            record("@", "ns1.public.goat"),
+            record("child", "ns1.child.public.goat"),
+        ],
+        public: true,
+        ..Default::default()
+    }
+    .save(&pool)
+    .await?;
+    let private = FileZone {
+        name: "private.goat".to_string(),
+        records: vec![record("@", "ns1.private.goat")],
+        ..Default::default()
+    }
+    .save(&pool)
+    .await?;
+
+    let mut conn = pool.acquire().await?;
+    let domains = public_domains(&mut conn, "*", 10).await?;
+    assert_eq!(domains.len(), 1);
+    let json = serde_json::to_value(&domains[0]).expect("Failed to serialize");
+    assert_eq!(json["ldhName"], "public.goat");
+    assert_eq!(json["objectClassName"], "domain");
+    assert_eq!(json["status"], serde_json::json!(["active"]));
+    assert_eq!(json["secureDNS"]["delegationSigned"], false);
+    // only the apex, and without the trailing dots
+    assert_eq!(
+        json["nameservers"],
+        serde_json::json!([
+            {"objectClassName": "nameserver", "ldhName": "ns1.public.goat"},
+            {"objectClassName": "nameserver", "ldhName": "ns2.public.goat"},
+        ])
+    );
+    assert_eq!(json["soa"]["mname"], "ns1.public.goat");
+    assert_eq!(json["soa"]["serial"], 5);
+    assert!(json.get("unicodeName").is_none());
+
+    assert_eq!(public_domains(&mut conn, "public.goat", 1).await?.len(), 1);
+    assert!(public_domains(&mut conn, "private.goat", 1)
+        .await?
+        .is_empty());
+    assert!(public_domains(&mut conn, "*.goat_", 10).await?.is_empty());
+
+    // it has to be turned on
+    let private_id = private.id.expect("No zone id");
+    FileZone::set_public_with_txn(private_id, true, &mut conn).await?;
+    assert_eq!(public_domains(&mut conn, "*.goat", 10).await?.len(), 2);
+    FileZone::set_public_with_txn(public.id.expect("No zone id"), false, &mut conn).await?;
+    let domains = public_domains(&mut conn, "*", 10).await?;
+    assert_eq!(domains.len(), 1);
+    assert_eq!(domains[0].ldh_name, "private.goat");
+    assert!(FileZone::set_public_with_txn(12345, true, &mut conn)
+        .await
+        .is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_zone_registration() -> Result<(), GoatNsError> {
+    use crate::db::get_zone_with_txn;
+    use crate::zones::{FileZone, ZoneRegistration};
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let registration = ZoneRegistration {
+        registrar: Some("Goat Registry".to_string()),
+        expires: chrono::NaiveDate::from_ymd_opt(2030, 1, 31),
+        notes: Some("Paid for by the goats".to_string()),
+    };
+    let zone = FileZone {
+        name: "registered.goat".to_string(),
+        registration: registration.clone(),
+        ..Default::default()
+    }
+    .save(&pool)
+    .await?;
+    assert_eq!(zone.registration, registration);
+    let zoneid = zone.id.expect("No zone id");
+
+    let mut conn = pool.acquire().await?;
+    FileZone::set_registration_with_txn(zoneid, &ZoneRegistration::default(), &mut conn).await?;
+    let zone = get_zone_with_txn(&mut conn, Some(zoneid), None)
+        .await?
+        .expect("Zone went away");
+    assert!(zone.registration.is_empty());
+    // and it's there when getting all the zones
+    FileZone::set_registration_with_txn(zoneid, &registration, &mut conn).await?;
+    let zones = get_zones_with_txn(&mut conn, 10, 0).await?;
+    assert_eq!(zones[0].registration, registration);
+
+    assert!(
+        FileZone::set_registration_with_txn(12345, &registration, &mut conn)
+            .await
+            .is_err()
+    );
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_cron_db_cleanup() -> Result<(), GoatNsError> {
     let pool = test_get_sqlite_memory().await;
@@ -157,3 +456,631 @@ async fn testget_zones_with_txn() -> Result<(), GoatNsError> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn datastore_responds_with_errors() -> Result<(), GoatNsError> {
+    use crate::datastore::Command;
+    use crate::interop::ZoneFileFormat;
+    use tokio::sync::{mpsc, oneshot};
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let (tx, rx) = mpsc::channel(10);
+    let manager = tokio::spawn(crate::datastore::manager(
+        rx,
+        crate::datastore::SqliteStore::new(pool.clone()),
+        None,
+        None,
+        None,
+        4,
+    ));
+
+    // a failed import should come back to the caller rather than stopping the datastore
+    let (os_tx, os_rx) = oneshot::channel();
+    tx.send(
+        Command::ImportFile {
+            filename: "/this/does/not/exist.json".to_string(),
+            zone_name: None,
+            zone_name_from_filename: false,
+            format: ZoneFileFormat::GoatNs,
+            ttl_policy: Default::default(),
+            strategy: Default::default(),
+            dry_run: false,
+            progress: None,
+            resp: os_tx,
+        }
+        .into(),
+    )
+    .await
+    .expect("Failed to send to datastore");
+    assert!(os_rx.await.expect("Datastore didn't respond").is_err());
+
+    // not found isn't an error
+    let (os_tx, os_rx) = oneshot::channel();
+    tx.send(
+        Command::GetZone {
+            id: None,
+            name: Some("missing.goat".to_string()),
+            resp: os_tx,
+        }
+        .into(),
+    )
+    .await
+    .expect("Failed to send to datastore");
+    assert!(os_rx.await.expect("Datastore didn't respond")?.is_none());
+
+    tx.send(Command::Shutdown.into())
+        .await
+        .expect("Failed to send to datastore");
+    let _ = manager.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn sqlite_store_zones_and_ownership() -> Result<(), GoatNsError> {
+    use crate::datastore::{SqliteStore, ZoneStore};
+    use crate::db::User;
+    use crate::enums::{RecordClass, RecordType};
+    use crate::zones::{FileZone, FileZoneRecord};
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+    let store = SqliteStore::new(pool.clone());
+
+    let user = store
+        .create_user(User {
+            username: "store_test".to_string(),
+            email: "store_test@hello.goat".to_string(),
+            ..Default::default()
+        })
+        .await?;
+    let userid = user.id.expect("user has no id");
+
+    let zone = store
+        .create_zone(
+            FileZone {
+                name: "store.goat".to_string(),
+                ..Default::default()
+            },
+            userid,
+        )
+        .await?;
+    let zoneid = zone.id.expect("zone has no id");
+    assert_eq!(store.get_ownership(userid).await?.len(), 1);
+
+    store
+        .put_record(FileZoneRecord {
+            id: None,
+            zoneid: Some(zoneid),
+            name: "www".to_string(),
+            rrtype: RecordType::A.to_string(),
+            class: RecordClass::Internet,
+            rdata: "192.0.2.1".to_string(),
+            ttl: 300,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        })
+        .await?;
+    let records = store
+        .get_record("www.store.goat", RecordType::A, RecordClass::Internet)
+        .await?;
+    assert_eq!(records.len(), 1);
+
+    store.delete_ownership(zoneid, Some(userid)).await?;
+    assert!(store.get_ownership(userid).await?.is_empty());
+
+    store.delete_zone(zoneid).await?;
+    assert!(store.get_zone(Some(zoneid), None).await?.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn sqlite_store_export_and_restore() -> Result<(), GoatNsError> {
+    use crate::config::ImportTtlPolicy;
+    use crate::datastore::{SqliteStore, ZoneStore};
+    use crate::db::User;
+    use crate::enums::RecordClass;
+    use crate::zones::{FileZone, FileZoneRecord, ImportStrategy};
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+    let store = SqliteStore::new(pool.clone());
+
+    let user = store
+        .create_user(User {
+            username: "backup_test".to_string(),
+            email: "backup_test@hello.goat".to_string(),
+            ..Default::default()
+        })
+        .await?;
+    let userid = user.id.expect("user has no id");
+    let zone = store
+        .create_zone(
+            FileZone {
+                name: "backup.goat".to_string(),
+                records: vec![FileZoneRecord {
+                    id: None,
+                    zoneid: None,
+                    name: "www".to_string(),
+                    rrtype: "A".to_string(),
+                    class: RecordClass::Internet,
+                    rdata: "192.0.2.1".to_string(),
+                    ttl: 300,
+                    comment: Some("backed up".to_string()),
+                    tags: Default::default(),
+                    expires_at: None,
+                }],
+                ..Default::default()
+            },
+            userid,
+        )
+        .await?;
+    let zoneid = zone.id.expect("zone has no id");
+
+    let mut backup = store.export_all().await?;
+    assert_eq!(backup.zones.len(), 1);
+    assert_eq!(backup.zones[0].records.len(), 1);
+    assert_eq!(
+        backup.owners.get("backup.goat"),
+        Some(&vec!["backup_test".to_string()])
+    );
+    // users that aren't there any more are skipped
+    backup
+        .owners
+        .entry("backup.goat".to_string())
+        .or_default()
+        .push("nobody".to_string());
+
+    store.delete_zone(zoneid).await?;
+    assert!(store.export_all().await?.zones.is_empty());
+
+    let policy = ImportTtlPolicy::default();
+    let summary = store
+        .restore_backup(backup.clone(), &policy, ImportStrategy::Replace, true)
+        .await?;
+    assert!(summary.dry_run);
+    assert!(store.export_all().await?.zones.is_empty());
+
+    store
+        .restore_backup(backup, &policy, ImportStrategy::Replace, false)
+        .await?;
+    let restored = store.export_all().await?;
+    assert_eq!(restored.zones.len(), 1);
+    assert_eq!(restored.zones[0].records[0].rdata, "192.0.2.1");
+    assert_eq!(
+        restored.zones[0].records[0].comment.as_deref(),
+        Some("backed up")
+    );
+    assert_eq!(
+        restored.owners.get("backup.goat"),
+        Some(&vec!["backup_test".to_string()])
+    );
+    assert_eq!(store.get_ownership(userid).await?.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn sqlite_store_record_cache() -> Result<(), GoatNsError> {
+    use crate::datastore::{handle_message, CachePurge, Command, SqliteStore, ZoneStore};
+    use crate::db::User;
+    use crate::enums::{RecordClass, RecordType};
+    use crate::resourcerecord::InternalResourceRecord;
+    use crate::zones::{FileZone, FileZoneRecord};
+    use std::time::Duration;
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+    let store = SqliteStore::new(pool.clone()).with_record_cache(Duration::from_secs(60));
+
+    let user = store
+        .create_user(User {
+            username: "cache_test".to_string(),
+            email: "cache_test@hello.goat".to_string(),
+            ..Default::default()
+        })
+        .await?;
+    let zone = store
+        .create_zone(
+            FileZone {
+                name: "cache.goat".to_string(),
+                ..Default::default()
+            },
+            user.id.expect("user has no id"),
+        )
+        .await?;
+    let zoneid = zone.id.expect("zone has no id");
+    store
+        .put_record(FileZoneRecord {
+            id: None,
+            zoneid: Some(zoneid),
+            name: "www".to_string(),
+            rrtype: RecordType::A.to_string(),
+            class: RecordClass::Internet,
+            rdata: "192.0.2.1".to_string(),
+            ttl: 300,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        })
+        .await?;
+
+    let address = |records: Vec<InternalResourceRecord>| match records.first() {
+        Some(InternalResourceRecord::A { address, .. }) => Some(*address),
+        _ => None,
+    };
+    let lookup = || store.get_record("www.cache.goat", RecordType::A, RecordClass::Internet);
+    assert_eq!(address(lookup().await?), Some(0xc0000201));
+
+    // editing the db behind the store's back doesn't show up until it's purged
+    sqlx::query("UPDATE records SET rdata = '192.0.2.2' WHERE zoneid = ?")
+        .bind(zoneid)
+        .execute(&pool)
+        .await?;
+    assert_eq!(address(lookup().await?), Some(0xc0000201));
+    assert_eq!(
+        store.purge_cache(&CachePurge::Name {
+            name: "WWW.cache.goat.".to_string(),
+            rrtype: Some(RecordType::AAAA as u16),
+        }),
+        0
+    );
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    handle_message(
+        Command::ReloadZone {
+            id: zoneid,
+            resp: tx,
+        },
+        &store,
+    )
+    .await
+    .expect("datastore shut down");
+    let reload = rx.await.expect("no response")?;
+    assert_eq!(reload.zone.name, "cache.goat");
+    assert_eq!(reload.purged, 1);
+    assert_eq!(address(lookup().await?), Some(0xc0000202));
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    handle_message(
+        Command::PurgeCache {
+            purge: CachePurge::All,
+            resp: tx,
+        },
+        &store,
+    )
+    .await
+    .expect("datastore shut down");
+    assert_eq!(rx.await.expect("no response")?, 1);
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    handle_message(
+        Command::ReloadZone {
+            id: 12345,
+            resp: tx,
+        },
+        &store,
+    )
+    .await
+    .expect("datastore shut down");
+    assert!(rx.await.expect("no response").is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn sqlite_store_reads_from_read_pool() -> Result<(), GoatNsError> {
+    use crate::datastore::{SqliteStore, ZoneStore};
+    use crate::enums::{RecordClass, RecordType};
+    use crate::zones::{FileZone, FileZoneRecord};
+
+    let write_pool = test_get_sqlite_memory().await;
+    start_db(&write_pool).await?;
+    let read_pool = test_get_sqlite_memory().await;
+    start_db(&read_pool).await?;
+
+    // only the "replica" has the record
+    FileZone {
+        name: "replica.goat".to_string(),
+        records: vec![FileZoneRecord {
+            id: None,
+            zoneid: None,
+            name: "www".to_string(),
+            rrtype: RecordType::A.to_string(),
+            class: RecordClass::Internet,
+            rdata: "192.0.2.1".to_string(),
+            ttl: 300,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        }],
+        ..Default::default()
+    }
+    .save(&read_pool)
+    .await?;
+
+    let store = SqliteStore::new(write_pool.clone());
+    assert!(store
+        .get_record("www.replica.goat", RecordType::A, RecordClass::Internet)
+        .await?
+        .is_empty());
+
+    let store = store.with_read_pool(read_pool);
+    assert_eq!(
+        store
+            .get_record("www.replica.goat", RecordType::A, RecordClass::Internet)
+            .await?
+            .len(),
+        1
+    );
+    // everything else still uses the write pool
+    assert!(store
+        .get_zone(None, Some("replica.goat".to_string()))
+        .await?
+        .is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn sqlite_store_negative_answers() -> Result<(), GoatNsError> {
+    use crate::datastore::{SqliteStore, ZoneStore};
+    use crate::enums::{RecordClass, RecordType};
+    use crate::resourcerecord::InternalResourceRecord;
+    use crate::zones::{FileZone, FileZoneRecord};
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let record = |name: &str, rrtype: RecordType, rdata: &str| FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: name.to_string(),
+        rrtype: rrtype.to_string(),
+        class: RecordClass::Internet,
+        rdata: rdata.to_string(),
+        ttl: 300,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    };
+    FileZone {
+        name: "neg.goat".to_string(),
+        rname: "hostmaster@neg.goat".to_string(),
+        minimum: 120,
+        records: vec![
+            record("@", RecordType::NS, "ns1.neg.goat"),
+            record("www", RecordType::A, "192.0.2.1"),
+            record("a.deep", RecordType::A, "192.0.2.2"),
+        ],
+        ..Default::default()
+    }
+    .save(&pool)
+    .await?;
+    FileZone {
+        name: "sub.neg.goat".to_string(),
+        ..Default::default()
+    }
+    .save(&pool)
+    .await?;
+
+    let store = SqliteStore::new(pool);
+
+    let answer = store
+        .get_negative_answer("www.neg.goat", RecordClass::Internet)
+        .await?
+        .expect("www.neg.goat should be in neg.goat");
+    assert_eq!(answer.zone, b"neg.goat".to_vec());
+    assert!(answer.name_exists);
+    assert!(matches!(
+        &answer.soa,
+        InternalResourceRecord::SOA { mname, rname, minimum: 120, .. }
+            if mname.name == "ns1.neg.goat" && rname.name == "hostmaster.neg.goat"
+    ));
+
+    for (name, exists) in [
+        // the apex is always there
+        ("neg.goat", true),
+        // empty non-terminal
+        ("deep.neg.goat", true),
+        ("a.deep.neg.goat", true),
+        ("nope.neg.goat", false),
+        ("eep.neg.goat", false),
+        ("b.deep.neg.goat", false),
+    ] {
+        let answer = store
+            .get_negative_answer(name, RecordClass::Internet)
+            .await?
+            .expect("should be in neg.goat");
+        assert_eq!(answer.zone, b"neg.goat".to_vec(), "{name}");
+        assert_eq!(answer.name_exists, exists, "{name}");
+    }
+
+    // the closest zone wins, and there's no NS so the zone's the mname
+    let answer = store
+        .get_negative_answer("x.sub.neg.goat", RecordClass::Internet)
+        .await?
+        .expect("x.sub.neg.goat should be in sub.neg.goat");
+    assert_eq!(answer.zone, b"sub.neg.goat".to_vec());
+    assert!(!answer.name_exists);
+    assert!(matches!(
+        &answer.soa,
+        InternalResourceRecord::SOA { mname, .. } if mname.name == "sub.neg.goat"
+    ));
+
+    // not ours at all
+    for name in ["example.com", "myneg.goat", "goat"] {
+        assert!(store
+            .get_negative_answer(name, RecordClass::Internet)
+            .await?
+            .is_none());
+    }
+
+    // walking up the labels finds the closest zone, whatever the case
+    for (name, zone) in [
+        ("neg.goat", Some("neg.goat")),
+        ("A.Deep.NEG.goat.", Some("neg.goat")),
+        ("sub.neg.goat", Some("sub.neg.goat")),
+        ("x.y.sub.neg.goat", Some("sub.neg.goat")),
+        ("myneg.goat", None),
+        ("", None),
+    ] {
+        assert_eq!(
+            store
+                .get_closest_zone(name)
+                .await?
+                .map(|zone| zone.name.trim_end_matches('.').to_string())
+                .as_deref(),
+            zone,
+            "{name}"
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn sqlite_store_disabled_zones() -> Result<(), GoatNsError> {
+    use crate::datastore::{SqliteStore, ZoneStore};
+    use crate::enums::{RecordClass, RecordType};
+    use crate::zones::{FileZone, FileZoneRecord};
+    use std::time::Duration;
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    FileZone {
+        name: "parked.goat".to_string(),
+        records: vec![FileZoneRecord {
+            id: None,
+            zoneid: None,
+            name: "www".to_string(),
+            rrtype: "A".to_string(),
+            class: RecordClass::Internet,
+            rdata: "192.0.2.1".to_string(),
+            ttl: 300,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        }],
+        ..Default::default()
+    }
+    .save(&pool)
+    .await?;
+    let zone = FileZone::get_by_name(&mut *pool.acquire().await?, "parked.goat")
+        .await?
+        .expect("Couldn't find the zone");
+    assert!(zone.enabled);
+    let zoneid = zone.id.expect("The zone should have an ID");
+
+    let store = SqliteStore::new(pool.clone()).with_record_cache(Duration::from_secs(60));
+    let www = || store.get_record("www.parked.goat", RecordType::A, RecordClass::Internet);
+    assert_eq!(www().await?.len(), 1);
+    let answer = store
+        .get_negative_answer("nope.parked.goat", RecordClass::Internet)
+        .await?
+        .expect("Should be in parked.goat");
+    assert!(answer.zone_enabled);
+
+    // turning it off drops the cached answer, and the records are still there
+    store.set_zone_enabled(zoneid, false).await?;
+    assert!(www().await?.is_empty());
+    let answer = store
+        .get_negative_answer("www.parked.goat", RecordClass::Internet)
+        .await?
+        .expect("Should be in parked.goat");
+    assert!(!answer.zone_enabled);
+    let zone = FileZone::get(&pool, zoneid).await?;
+    assert!(!zone.enabled);
+    assert_eq!(zone.records.len(), 1);
+
+    store.set_zone_enabled(zoneid, true).await?;
+    assert_eq!(www().await?.len(), 1);
+
+    assert!(store.set_zone_enabled(zoneid + 100, false).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn sqlite_store_delegations() -> Result<(), GoatNsError> {
+    use crate::datastore::{SqliteStore, ZoneStore};
+    use crate::enums::{RecordClass, RecordType};
+    use crate::zones::{FileZone, FileZoneRecord};
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    let record = |name: &str, rrtype: RecordType, rdata: &str| FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: name.to_string(),
+        rrtype: rrtype.to_string(),
+        class: RecordClass::Internet,
+        rdata: rdata.to_string(),
+        ttl: 300,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    };
+    FileZone {
+        name: "parent.goat".to_string(),
+        records: vec![
+            record("@", RecordType::NS, "ns1.parent.goat"),
+            record("www", RecordType::A, "192.0.2.1"),
+            record("child", RecordType::NS, "ns1.child.parent.goat"),
+            record("child", RecordType::NS, "ns.elsewhere.goat"),
+            record("ns1.child", RecordType::A, "192.0.2.53"),
+            record("ns1.child", RecordType::AAAA, "2001:db8::53"),
+            record("hosted", RecordType::NS, "ns1.parent.goat"),
+        ],
+        ..Default::default()
+    }
+    .save(&pool)
+    .await?;
+    // we host this one ourselves, so it's not a delegation as far as we're concerned
+    FileZone {
+        name: "hosted.parent.goat".to_string(),
+        records: vec![record("www", RecordType::A, "192.0.2.2")],
+        ..Default::default()
+    }
+    .save(&pool)
+    .await?;
+
+    let store = SqliteStore::new(pool);
+
+    for name in [
+        "child.parent.goat",
+        "www.child.parent.goat",
+        "ns1.child.parent.goat",
+    ] {
+        let delegation = store
+            .get_delegation(name, RecordClass::Internet)
+            .await?
+            .expect("should be delegated");
+        assert_eq!(delegation.name, b"child.parent.goat".to_vec(), "{name}");
+        assert_eq!(delegation.nameservers.len(), 2, "{name}");
+        // only the name server under the cut gets glue
+        assert_eq!(delegation.glue.len(), 2, "{name}");
+        assert!(delegation
+            .glue
+            .iter()
+            .all(|(glue_name, _)| glue_name == b"ns1.child.parent.goat"));
+    }
+
+    for name in [
+        "parent.goat",
+        "www.parent.goat",
+        "nope.parent.goat",
+        "notchild.parent.goat",
+        "hosted.parent.goat",
+        "www.hosted.parent.goat",
+        "example.com",
+    ] {
+        assert!(
+            store
+                .get_delegation(name, RecordClass::Internet)
+                .await?
+                .is_none(),
+            "{name}"
+        );
+    }
+    Ok(())
+}
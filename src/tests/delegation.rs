@@ -0,0 +1,120 @@
+use trust_dns_resolver::proto::op::Message;
+use trust_dns_resolver::proto::rr::rdata::{A, NS, NULL};
+use trust_dns_resolver::proto::rr::{Name, RData, Record, RecordType};
+
+use crate::delegation::{check_ds, check_referral, expected_nameservers, DelegationProblem};
+use crate::enums::RecordClass;
+use crate::zones::{FileZone, FileZoneRecord};
+
+fn name(name: &str) -> Name {
+    Name::from_ascii(name).expect("Failed to parse name")
+}
+
+fn ns(zone: &str, nameserver: &str) -> Record {
+    Record::from_rdata(name(zone), 3600, RData::NS(NS(name(nameserver))))
+}
+
+fn referral(nameservers: &[&str], glue: &[&str]) -> Message {
+    let mut reply = Message::new();
+    for nameserver in nameservers {
+        reply.add_name_server(ns("example.com.", nameserver));
+    }
+    for nameserver in glue {
+        reply.add_additional(Record::from_rdata(
+            name(nameserver),
+            3600,
+            RData::A(A::new(192, 0, 2, 1)),
+        ));
+    }
+    reply
+}
+
+#[test]
+fn test_expected_nameservers() {
+    let record = |name: &str, rrtype: &str, rdata: &str| FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: name.to_string(),
+        rrtype: rrtype.to_string(),
+        class: RecordClass::Internet,
+        rdata: rdata.to_string(),
+        ttl: 0,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    };
+    let zone = FileZone {
+        name: "example.com".to_string(),
+        records: vec![
+            record("@", "NS", "NS2.example.net."),
+            record("example.com.", "ns", "ns1.example.net"),
+            record("@", "NS", "ns2.example.net"),
+            record("sub", "NS", "ns.elsewhere.org"),
+            record("@", "A", "192.0.2.1"),
+        ],
+        ..Default::default()
+    };
+    assert_eq!(
+        expected_nameservers(&zone),
+        vec!["ns1.example.net".to_string(), "ns2.example.net".to_string()]
+    );
+}
+
+#[test]
+fn test_check_referral() {
+    let expected = vec!["ns1.example.com".to_string(), "ns2.example.net".to_string()];
+
+    let good = referral(
+        &["ns1.example.com.", "ns2.example.net."],
+        &["ns1.example.com."],
+    );
+    assert!(check_referral("example.com", &expected, "parent", &good).is_empty());
+
+    let no_glue = referral(&["ns1.example.com.", "ns2.example.net."], &[]);
+    assert_eq!(
+        check_referral("example.com", &expected, "parent", &no_glue),
+        vec![DelegationProblem::MissingGlue {
+            server: "parent".to_string(),
+            nameserver: "ns1.example.com".to_string(),
+        }]
+    );
+
+    let wrong = referral(&["ns2.example.net."], &[]);
+    assert_eq!(
+        check_referral("example.com", &expected, "parent", &wrong),
+        vec![DelegationProblem::WrongNameServers {
+            server: "parent".to_string(),
+            expected: expected.clone(),
+            found: vec!["ns2.example.net".to_string()],
+        }]
+    );
+
+    assert_eq!(
+        check_referral("example.com", &expected, "parent", &Message::new()),
+        vec![DelegationProblem::NotDelegated {
+            server: "parent".to_string()
+        }]
+    );
+}
+
+#[test]
+fn test_check_ds() {
+    assert!(check_ds("parent", &Message::new()).is_empty());
+
+    // without DNSSEC support they're parsed as unknown records
+    let mut reply = Message::new();
+    reply.add_answer(Record::from_rdata(
+        name("example.com."),
+        3600,
+        RData::Unknown {
+            code: RecordType::DS,
+            rdata: NULL::with(vec![0x30, 0x39, 13, 2]),
+        },
+    ));
+    assert_eq!(
+        check_ds("parent", &reply),
+        vec![DelegationProblem::UnexpectedDs {
+            server: "parent".to_string()
+        }]
+    );
+}
@@ -0,0 +1,161 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use rcgen::{date_time_ymd, CertificateParams, DnType, KeyPair};
+use trust_dns_resolver::proto::op::{Message, ResponseCode};
+use trust_dns_resolver::proto::rr::rdata::A;
+use trust_dns_resolver::proto::rr::{Name, RData, Record};
+
+use crate::config::{ConfigFile, ImportTtlPolicy};
+use crate::db::test::test_get_sqlite_memory;
+use crate::db::{start_db, DBEntity};
+use crate::doctor::{
+    answer_check, certificate_check, integrity_check, zone_file_drift, CheckStatus, DoctorOptions,
+    DoctorReport,
+};
+use crate::enums::RecordType;
+use crate::error::GoatNsError;
+use crate::zones::{FileZone, FileZoneRecord};
+
+fn cert_pem(common_name: &str, not_after: (i32, u8, u8)) -> String {
+    let mut params =
+        CertificateParams::new(vec!["goat.example.com".to_string()]).expect("Bad params");
+    params
+        .distinguished_name
+        .push(DnType::CommonName, common_name);
+    params.not_before = date_time_ymd(2020, 1, 1);
+    params.not_after = date_time_ymd(not_after.0, not_after.1, not_after.2);
+    let key = KeyPair::generate().expect("Failed to make a key");
+    params
+        .self_signed(&key)
+        .expect("Failed to make a certificate")
+        .pem()
+}
+
+fn at(year: i32, month: u32, day: u32) -> chrono::DateTime<chrono::Utc> {
+    chrono::NaiveDate::from_ymd_opt(year, month, day)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .expect("Bad date")
+        .and_utc()
+}
+
+#[test]
+fn test_doctor_options() {
+    let options = DoctorOptions::new(&ConfigFile::default());
+    assert_eq!(options.rrtype, RecordType::A);
+    let options = options
+        .with_question("www.example.com.:aaaa")
+        .expect("Failed to parse question");
+    assert_eq!(options.qname, "www.example.com");
+    assert_eq!(options.rrtype, RecordType::AAAA);
+    let options = DoctorOptions::new(&ConfigFile::default());
+    assert!(options.clone().with_question(":A").is_err());
+    assert!(options.with_question("example.com:GOAT").is_err());
+}
+
+#[test]
+fn test_doctor_answer_check() {
+    let elapsed = Duration::from_millis(3);
+    let mut reply = Message::new();
+    reply.add_answer(Record::from_rdata(
+        Name::from_ascii("example.com.").expect("Bad name"),
+        300,
+        RData::A(A::new(192, 0, 2, 1)),
+    ));
+    let check = answer_check("dns_udp", "example.com A", Ok(reply), elapsed);
+    assert_eq!(check.status, CheckStatus::Ok);
+    assert_eq!(check.detail, "Answered example.com A with 1 records in 3ms");
+
+    // it's answering, it just doesn't have it
+    let mut reply = Message::new();
+    reply.set_response_code(ResponseCode::NXDomain);
+    let check = answer_check("dns_udp", "example.com A", Ok(reply), elapsed);
+    assert_eq!(check.status, CheckStatus::Warn);
+    assert_eq!(
+        answer_check("dns_udp", "example.com A", Ok(Message::new()), elapsed).status,
+        CheckStatus::Warn
+    );
+
+    let mut reply = Message::new();
+    reply.set_response_code(ResponseCode::ServFail);
+    let check = answer_check("dns_tcp", "example.com A", Ok(reply), elapsed);
+    assert_eq!(check.status, CheckStatus::Fail);
+    let check = answer_check(
+        "doh",
+        "example.com A",
+        Err(GoatNsError::Generic("timed out".to_string())),
+        elapsed,
+    );
+    assert_eq!(check.status, CheckStatus::Fail);
+
+    let report = DoctorReport {
+        checks: vec![check],
+    };
+    assert!(!report.passed());
+    assert!(report.to_string().starts_with("[FAIL] doh: "));
+    assert!(report
+        .to_string()
+        .ends_with("0 ok, 0 warnings, 1 failed, 0 skipped"));
+}
+
+#[test]
+fn test_doctor_certificate_check() {
+    let pem = cert_pem("goat.example.com", (2030, 1, 1));
+    let check = certificate_check(pem.as_bytes(), at(2029, 1, 1), 14);
+    assert_eq!(check.status, CheckStatus::Ok, "{check:?}");
+    assert!(check.detail.contains("expires in 365 days"));
+    assert_eq!(
+        certificate_check(pem.as_bytes(), at(2029, 12, 25), 14).status,
+        CheckStatus::Warn
+    );
+    assert_eq!(
+        certificate_check(pem.as_bytes(), at(2030, 1, 2), 14).status,
+        CheckStatus::Fail
+    );
+    assert_eq!(
+        certificate_check(pem.as_bytes(), at(2019, 1, 1), 14).status,
+        CheckStatus::Fail
+    );
+
+    // the second one didn't issue the first
+    let chain = format!("{pem}{}", cert_pem("someone else", (2030, 1, 1)));
+    let check = certificate_check(chain.as_bytes(), at(2029, 1, 1), 14);
+    assert_eq!(check.status, CheckStatus::Warn);
+    assert!(check.detail.contains("out of order"));
+
+    assert_eq!(
+        certificate_check(b"not a certificate", at(2029, 1, 1), 14).status,
+        CheckStatus::Fail
+    );
+}
+
+#[tokio::test]
+async fn test_doctor_database_checks() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+    assert_eq!(integrity_check(&pool).await.status, CheckStatus::Ok);
+
+    let zone = FileZone::builder("drift.goat", "hostmaster.drift.goat")
+        .serial(5)
+        .record(FileZoneRecord::a("www", Ipv4Addr::new(192, 0, 2, 1), 300))
+        .build()?;
+    zone.save(&pool).await?;
+    let policy = ImportTtlPolicy::default();
+
+    let check = zone_file_drift(&pool, vec![zone], &policy).await?;
+    assert_eq!(check.status, CheckStatus::Ok, "{check:?}");
+
+    let changed = FileZone::builder("drift.goat", "hostmaster.drift.goat")
+        .serial(6)
+        .record(FileZoneRecord::a("www", Ipv4Addr::new(192, 0, 2, 2), 300))
+        .build()?;
+    let missing = FileZone::builder("missing.goat", "hostmaster.missing.goat").build()?;
+    let check = zone_file_drift(&pool, vec![changed, missing], &policy).await?;
+    assert_eq!(check.status, CheckStatus::Warn);
+    assert_eq!(
+        check.detail,
+        "2 of 2 zones are different in the database - drift.goat: serial differ, 1 records only in the file, \
+        1 records only in the database, missing.goat isn't in the database"
+    );
+    Ok(())
+}
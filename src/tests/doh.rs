@@ -28,6 +28,9 @@ async fn test_doh_get_json() -> Result<(), ()> {
         class: RecordClass::Internet,
         rdata: "1.2.3.4".to_string(),
         ttl: 1,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
     }
     .save(&pool)
     .await
@@ -61,9 +64,51 @@ async fn test_doh_get_json() -> Result<(), ()> {
         .unwrap();
     assert_eq!(res.status(), reqwest::StatusCode::from_u16(200).unwrap());
     eprintln!("{:?}", res);
+    assert_eq!(
+        res.headers()
+            .get("cache-control")
+            .and_then(|v| v.to_str().ok()),
+        Some("max-age=1")
+    );
+    assert!(res.headers().contains_key("expires"));
+    let etag = res
+        .headers()
+        .get("etag")
+        .expect("No ETag on the response")
+        .clone();
+    let last_modified = res
+        .headers()
+        .get("last-modified")
+        .expect("No Last-Modified on the response")
+        .clone();
     eprintln!("{:?}", res.bytes().await);
 
-    // TODO: finish this
+    // asking again with what we've got gets a 304
+    let url = format!("https://localhost:{api_port}/dns-query?name=test.example.com&type=A");
+    let res = client
+        .get(&url)
+        .header("If-None-Match", etag.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_MODIFIED);
+    assert_eq!(res.headers().get("etag"), Some(&etag));
+    let res = client
+        .get(&url)
+        .header("If-Modified-Since", last_modified)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_MODIFIED);
+
+    // a different answer's a different ETag
+    let res = client
+        .get(&url)
+        .header("If-None-Match", "W/\"nope\"")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
     Ok(())
 }
 
@@ -153,3 +198,173 @@ async fn test_doh_ask_wrong_accept() -> Result<(), ()> {
     assert_eq!(res.status(), reqwest::StatusCode::from_u16(406).unwrap());
     Ok(())
 }
+
+#[test]
+fn test_doh_auth_tokens() {
+    use crate::config::DohAuthConfig;
+    use crate::web::middleware::doh_auth::{bearer_token, static_token_valid};
+    use axum::body::Body;
+    use axum::extract::Request;
+
+    let request = |auth: Option<&str>| {
+        let mut builder = Request::builder().uri("/dns-query");
+        if let Some(auth) = auth {
+            builder = builder.header("Authorization", auth);
+        }
+        builder
+            .body(Body::empty())
+            .expect("failed to build request")
+    };
+    assert_eq!(bearer_token(&request(Some("Bearer hello"))), Some("hello"));
+    assert_eq!(
+        bearer_token(&request(Some("bearer  hello "))),
+        Some("hello")
+    );
+    assert_eq!(bearer_token(&request(Some("Basic aGVsbG8="))), None);
+    assert_eq!(bearer_token(&request(None)), None);
+
+    let mut auth = DohAuthConfig::default();
+    assert!(!auth.enabled());
+    assert!(!static_token_valid(&auth, "hello"));
+    auth.bearer_tokens = vec!["hello".to_string(), "world".to_string()];
+    assert!(auth.enabled());
+    assert!(static_token_valid(&auth, "world"));
+    assert!(!static_token_valid(&auth, "goat"));
+}
+
+#[tokio::test]
+/// good tokens are cached, but deleting one or disabling its user has to stop it working straight away
+async fn test_doh_auth_api_token_forgotten() {
+    use crate::db::{start_db, test::test_get_sqlite_memory, User, UserAuthToken};
+    use crate::tests::test_api::insert_test_user_api_token;
+    use crate::web::middleware::doh_auth::api_token_valid;
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await.expect("Failed to start DB");
+    let user = insert_test_user(&pool).await;
+    let userid = user.id.expect("no user id found");
+
+    let token = insert_test_user_api_token(&pool, userid)
+        .await
+        .expect("Failed to make token");
+    let bearer = format!("{}:{}", token.token_key, token.token_secret);
+    assert!(api_token_valid(&pool, &bearer).await);
+    assert!(api_token_valid(&pool, &bearer).await);
+    UserAuthToken::get_authtoken(&pool, token.token_key.clone())
+        .await
+        .expect("Failed to get token")
+        .delete(&pool)
+        .await
+        .expect("Failed to delete token");
+    assert!(!api_token_valid(&pool, &bearer).await);
+
+    let token = insert_test_user_api_token(&pool, userid)
+        .await
+        .expect("Failed to make token");
+    let bearer = format!("{}:{}", token.token_key, token.token_secret);
+    assert!(api_token_valid(&pool, &bearer).await);
+    let mut txn = pool.begin().await.expect("Failed to start transaction");
+    User {
+        disabled: true,
+        ..*user
+    }
+    .update_with_txn(&mut txn)
+    .await
+    .expect("Failed to disable user");
+    txn.commit().await.expect("Failed to commit");
+    assert!(!api_token_valid(&pool, &bearer).await);
+}
+
+#[tokio::test]
+async fn test_doh_usage_record() {
+    use crate::db::test::test_get_sqlite_memory;
+    use crate::web::doh::usage::{flush, record, take};
+
+    record("usagetesttoken", "Example.COM.");
+    record("usagetesttoken", "example.com");
+    record("usagetesttoken", "test.example.com");
+
+    let mut usage: Vec<_> = take()
+        .into_iter()
+        .filter(|row| row.tokenkey == "usagetesttoken")
+        .map(|row| (row.name, row.queries))
+        .collect();
+    usage.sort();
+    assert_eq!(
+        usage,
+        vec![
+            ("example.com".to_string(), 2),
+            ("test.example.com".to_string(), 1)
+        ]
+    );
+    // and it's been emptied
+    assert!(!take().iter().any(|row| row.tokenkey == "usagetesttoken"));
+
+    // there's no tables so it can't be written, and it's kept for the next time instead of being lost
+    let pool = test_get_sqlite_memory().await;
+    record("usagetesttoken", "example.com");
+    record("usagetesttoken", "example.com");
+    assert!(flush(&pool).await.is_err());
+    record("usagetesttoken", "example.com");
+    let usage: Vec<_> = take()
+        .into_iter()
+        .filter(|row| row.tokenkey == "usagetesttoken")
+        .map(|row| (row.name, row.queries))
+        .collect();
+    assert_eq!(usage, vec![("example.com".to_string(), 3)]);
+}
+
+#[test]
+fn test_doh_cache_info() {
+    use crate::web::doh::http_cache::CacheInfo;
+    use axum::http::header::{IF_MODIFIED_SINCE, IF_NONE_MATCH};
+    use axum::http::HeaderMap;
+
+    let cache = CacheInfo::new("application/dns-message", b"answer", &[1], 300);
+    assert!(cache.etag.starts_with("W/\""));
+    // the same answer's the same ETag, but not if the zone's changed or it's the other format
+    assert_eq!(
+        CacheInfo::new("application/dns-message", b"answer", &[1], 300).etag,
+        cache.etag
+    );
+    assert_ne!(
+        CacheInfo::new("application/dns-message", b"answer", &[2], 300).etag,
+        cache.etag
+    );
+    assert_ne!(
+        CacheInfo::new("application/dns-json", b"answer", &[1], 300).etag,
+        cache.etag
+    );
+
+    let mut headers = HeaderMap::new();
+    assert!(!cache.not_modified(&headers));
+    headers.insert(
+        IF_NONE_MATCH,
+        format!("\"other\", {}", cache.etag.trim_start_matches("W/"))
+            .parse()
+            .expect("Failed to parse header"),
+    );
+    assert!(cache.not_modified(&headers));
+
+    // If-None-Match wins over If-Modified-Since
+    headers.insert(
+        IF_NONE_MATCH,
+        "\"other\"".parse().expect("Failed to parse header"),
+    );
+    headers.insert(
+        IF_MODIFIED_SINCE,
+        "Fri, 01 Jan 2100 00:00:00 GMT"
+            .parse()
+            .expect("Failed to parse header"),
+    );
+    assert!(!cache.not_modified(&headers));
+    headers.remove(IF_NONE_MATCH);
+    assert!(cache.not_modified(&headers));
+    headers.insert(
+        IF_MODIFIED_SINCE,
+        "Thu, 01 Jan 1970 00:00:00 GMT"
+            .parse()
+            .expect("Failed to parse header"),
+    );
+    assert!(!cache.not_modified(&headers));
+}
@@ -46,8 +46,11 @@ mod tests {
         // start all the things!
         let datastore_manager = tokio::spawn(crate::datastore::manager(
             datastore_rx,
-            connpool.clone(),
+            crate::datastore::SqliteStore::new(connpool.clone()),
             None,
+            None,
+            None,
+            4,
         ));
 
         println!("Starting API Server");
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use crate::fault_injection::{Faults, MAX_DELAY_MS};
+
+#[test]
+fn test_faults_check() {
+    assert!(Faults::default().check().is_ok());
+
+    let faults = Faults {
+        delay_probability: 0.5,
+        delay_ms: 100,
+        drop_udp_probability: 1.0,
+        db_error_probability: 0.0,
+    };
+    assert!(faults.check().is_ok());
+
+    for faults in [
+        Faults {
+            delay_probability: 1.5,
+            ..faults
+        },
+        Faults {
+            drop_udp_probability: -0.1,
+            ..faults
+        },
+        Faults {
+            db_error_probability: f64::NAN,
+            ..faults
+        },
+        Faults {
+            delay_ms: MAX_DELAY_MS + 1,
+            ..faults
+        },
+    ] {
+        assert!(faults.check().is_err(), "{faults:?}");
+    }
+}
+
+#[test]
+fn test_faults_happen() {
+    // nothing happens by default
+    let faults = Faults::default();
+    for _ in 0..100 {
+        assert_eq!(faults.delay(), None);
+        assert!(!faults.drop_udp());
+        assert!(faults.db_error().is_ok());
+    }
+
+    let faults = Faults {
+        delay_probability: 1.0,
+        delay_ms: 250,
+        drop_udp_probability: 1.0,
+        db_error_probability: 1.0,
+    };
+    assert_eq!(faults.delay(), Some(Duration::from_millis(250)));
+    assert!(faults.drop_udp());
+    assert!(faults.db_error().is_err());
+
+    // a delay of nothing isn't one
+    let faults = Faults {
+        delay_ms: 0,
+        ..faults
+    };
+    assert_eq!(faults.delay(), None);
+}
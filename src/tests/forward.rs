@@ -0,0 +1,101 @@
+use trust_dns_resolver::proto::op::{Message, ResponseCode};
+use trust_dns_resolver::proto::rr::rdata::{A, CNAME, SOA};
+use trust_dns_resolver::proto::rr::{Name, RData, Record};
+
+use crate::enums::RecordClass;
+use crate::forward::{from_message, Forwarded, NEGATIVE_TTL};
+use crate::reply::Answer;
+use crate::resourcerecord::InternalResourceRecord;
+
+fn name(name: &str) -> Name {
+    Name::from_ascii(name).expect("Failed to parse name")
+}
+
+fn soa(minimum: u32) -> Record {
+    Record::from_rdata(
+        name("example.net."),
+        3600,
+        RData::SOA(SOA::new(
+            name("ns1.example.net."),
+            name("hostmaster.example.net."),
+            1,
+            7200,
+            3600,
+            1209600,
+            minimum,
+        )),
+    )
+}
+
+#[test]
+fn test_forwarded_answers() {
+    let mut reply = Message::new();
+    reply.set_authoritative(true);
+    reply.add_answer(Record::from_rdata(
+        name("Legacy.Example.NET."),
+        3600,
+        RData::CNAME(CNAME(name("web.example.net."))),
+    ));
+    reply.add_answer(Record::from_rdata(
+        name("web.example.net."),
+        60,
+        RData::A(A::new(192, 0, 2, 80)),
+    ));
+
+    let (forwarded, ttl) = from_message(&reply, "legacy.example.net", RecordClass::Internet, 300)
+        .expect("Failed to convert the reply");
+    // the target's records are ours now, anything further along keeps its name
+    assert_eq!(
+        forwarded,
+        Forwarded::Answers(vec![
+            Answer::from(InternalResourceRecord::Generic {
+                rrtype: 5,
+                rdata: b"\x03web\x07example\x03net\x00".to_vec(),
+                ttl: 300,
+                rclass: RecordClass::Internet,
+            }),
+            Answer::named(
+                b"web.example.net".to_vec(),
+                InternalResourceRecord::A {
+                    address: u32::from_be_bytes([192, 0, 2, 80]),
+                    ttl: 60,
+                    rclass: RecordClass::Internet,
+                }
+            ),
+        ])
+    );
+    assert_eq!(ttl, 60);
+}
+
+#[test]
+fn test_forwarded_negative_answers() {
+    let mut reply = Message::new();
+    reply.set_authoritative(true);
+    reply.set_response_code(ResponseCode::NXDomain);
+    reply.add_name_server(soa(120));
+    assert_eq!(
+        from_message(&reply, "nope.example.net", RecordClass::Internet, 300)
+            .expect("Failed to convert the reply"),
+        (Forwarded::NameError, 120)
+    );
+    // max_ttl wins
+    assert_eq!(
+        from_message(&reply, "nope.example.net", RecordClass::Internet, 30)
+            .expect("Failed to convert the reply"),
+        (Forwarded::NameError, 30)
+    );
+
+    let mut reply = Message::new();
+    reply.set_authoritative(true);
+    assert_eq!(
+        from_message(&reply, "legacy.example.net", RecordClass::Internet, 300)
+            .expect("Failed to convert the reply"),
+        (Forwarded::NoData, NEGATIVE_TTL)
+    );
+
+    // a referral or a lame server isn't something to pass on
+    reply.set_authoritative(false);
+    assert!(from_message(&reply, "legacy.example.net", RecordClass::Internet, 300).is_err());
+    reply.set_response_code(ResponseCode::Refused);
+    assert!(from_message(&reply, "legacy.example.net", RecordClass::Internet, 300).is_err());
+}
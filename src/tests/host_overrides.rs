@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use crate::enums::{RecordClass, RecordType};
+use crate::host_overrides::{HostOverrides, OVERRIDE_TTL};
+use crate::resourcerecord::InternalResourceRecord;
+use crate::Question;
+
+fn overrides() -> HostOverrides {
+    let mut hosts: BTreeMap<String, Vec<IpAddr>> = BTreeMap::new();
+    hosts.insert(
+        "NAS.lan.".to_string(),
+        vec![
+            "192.168.1.10".parse().expect("Failed to parse address"),
+            "fd00::10".parse().expect("Failed to parse address"),
+        ],
+    );
+    HostOverrides::from(&hosts)
+}
+
+#[test]
+fn test_host_overrides_answer() {
+    let overrides = overrides();
+    assert_eq!(overrides.len(), 1);
+
+    let question = Question::new(b"nas.LAN".to_vec(), RecordType::A, RecordClass::Internet);
+    match overrides.answer(&question).expect("No answer").as_slice() {
+        [InternalResourceRecord::A { address, ttl, .. }] => {
+            assert_eq!(*address, u32::from_be_bytes([192, 168, 1, 10]));
+            assert_eq!(*ttl, OVERRIDE_TTL);
+        }
+        other => panic!("Unexpected answer {other:?}"),
+    }
+
+    let question = Question::new(b"nas.lan".to_vec(), RecordType::AAAA, RecordClass::Internet);
+    let answer = overrides.answer(&question).expect("No answer");
+    assert!(matches!(
+        answer.as_slice(),
+        [InternalResourceRecord::AAAA { .. }]
+    ));
+
+    let question = Question::new(b"nas.lan".to_vec(), RecordType::ANY, RecordClass::Internet);
+    assert_eq!(overrides.answer(&question).expect("No answer").len(), 2);
+
+    // the name's overridden, so there's nothing else there
+    let question = Question::new(b"nas.lan".to_vec(), RecordType::MX, RecordClass::Internet);
+    assert_eq!(overrides.answer(&question), Some(vec![]));
+}
+
+#[test]
+fn test_host_overrides_leave_the_rest() {
+    let overrides = overrides();
+    for (name, qclass) in [
+        ("www.nas.lan", RecordClass::Internet),
+        ("lan", RecordClass::Internet),
+        ("other.lan", RecordClass::Internet),
+        ("nas.lan", RecordClass::Chaos),
+    ] {
+        let question = Question::new(name.as_bytes().to_vec(), RecordType::A, qclass);
+        assert!(
+            overrides.answer(&question).is_none(),
+            "{name} was overridden"
+        );
+    }
+    assert!(HostOverrides::default().is_empty());
+}
@@ -0,0 +1,145 @@
+use std::io::{Cursor, Write};
+
+use crate::import_source::{sources_from_bytes, zone_name_from_filename, ZoneSource};
+use crate::interop::{load_import, ZoneFileFormat};
+
+const EXAMPLE_COM: &str = "@ 300 IN SOA ns1.example.com. hostmaster.example.com. 1 7200 3600 1209600 300\nwww 60 IN A 192.0.2.1\n";
+const NO_SOA: &str = "www 60 IN A 192.0.2.2\n";
+
+fn tar(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (name, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, name, contents.as_bytes())
+            .expect("Failed to add to tar");
+    }
+    builder.into_inner().expect("Failed to finish tar")
+}
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes).expect("Failed to gzip");
+    encoder.finish().expect("Failed to finish gzip")
+}
+
+fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in files {
+        writer
+            .start_file(*name, zip::write::SimpleFileOptions::default())
+            .expect("Failed to add to zip");
+        writer
+            .write_all(contents.as_bytes())
+            .expect("Failed to write to zip");
+    }
+    writer.finish().expect("Failed to finish zip").into_inner()
+}
+
+fn source(name: &str, contents: &str) -> ZoneSource {
+    ZoneSource {
+        name: name.to_string(),
+        contents: contents.to_string(),
+    }
+}
+
+#[test]
+fn test_zone_name_from_filename() {
+    for (filename, expected) in [
+        ("example.com.zone", Some("example.com")),
+        ("zones/example.com.zone.gz", Some("example.com")),
+        ("example.com.json", Some("example.com")),
+        ("example.com", Some("example.com")),
+        ("2.0.192.in-addr.arpa.db", Some("2.0.192.in-addr.arpa")),
+        ("zones\\example.net.zone", Some("example.net")),
+        (".zone", None),
+    ] {
+        assert_eq!(
+            zone_name_from_filename(filename).as_deref(),
+            expected,
+            "{filename}"
+        );
+    }
+}
+
+#[test]
+fn test_sources_from_archives() {
+    let files = [
+        ("zones/example.com.zone", EXAMPLE_COM),
+        ("zones/example.net.zone", NO_SOA),
+    ];
+    let expected = vec![
+        source("zones/example.com.zone", EXAMPLE_COM),
+        source("zones/example.net.zone", NO_SOA),
+    ];
+
+    // plain text's just the one file
+    assert_eq!(
+        sources_from_bytes("stdin", EXAMPLE_COM.as_bytes().to_vec()).expect("Failed to read"),
+        vec![source("stdin", EXAMPLE_COM)]
+    );
+    assert_eq!(
+        sources_from_bytes("example.com.zone.gz", gzip(EXAMPLE_COM.as_bytes()))
+            .expect("Failed to read gzip"),
+        vec![source("example.com.zone", EXAMPLE_COM)]
+    );
+    assert_eq!(
+        sources_from_bytes("stdin", tar(&files)).expect("Failed to read tar"),
+        expected
+    );
+    assert_eq!(
+        sources_from_bytes("stdin", gzip(&tar(&files))).expect("Failed to read tar.gz"),
+        expected
+    );
+    // macOS leaves its droppings in zip files
+    let mut with_junk = files.to_vec();
+    with_junk.push(("__MACOSX/zones/._example.com.zone", "junk"));
+    with_junk.push(("zones/._example.com.zone", "junk"));
+    assert_eq!(
+        sources_from_bytes("zones.zip", zip(&with_junk)).expect("Failed to read zip"),
+        expected
+    );
+
+    assert!(sources_from_bytes("binary", vec![0xff, 0xfe, 0x00]).is_err());
+    assert!(sources_from_bytes("broken.zip", b"PK\x03\x04nope".to_vec()).is_err());
+}
+
+#[test]
+fn test_load_import_archive() {
+    let path = std::env::temp_dir().join(format!("goatns-import-{}.tar.gz", std::process::id()));
+    let filename = path.to_string_lossy().to_string();
+
+    // the one without a SOA needs a name from somewhere
+    std::fs::write(
+        &path,
+        gzip(&tar(&[
+            ("example.com.zone", EXAMPLE_COM),
+            ("example.net.zone", NO_SOA),
+        ])),
+    )
+    .expect("Failed to write archive");
+    assert!(load_import(&filename, ZoneFileFormat::Bind, None, false).is_err());
+    let zones = load_import(&filename, ZoneFileFormat::Bind, None, true)
+        .expect("Failed to load the archive");
+    let names: Vec<&str> = zones.iter().map(|zone| zone.name.as_str()).collect();
+    assert_eq!(names, vec!["example.com", "example.net"]);
+    assert_eq!(zones[1].records.len(), 1);
+
+    // a file has to have the zone it's named for, BIND files are whatever they're named but JSON ones say
+    let json = r#"[{"name": "example.com", "rname": "hostmaster@example.com", "records": []}]"#;
+    std::fs::write(&path, gzip(&tar(&[("example.org.json", json)])))
+        .expect("Failed to write archive");
+    assert!(load_import(&filename, ZoneFileFormat::GoatNs, None, true).is_err());
+    std::fs::write(&path, gzip(&tar(&[("example.com.json", json)])))
+        .expect("Failed to write archive");
+    assert_eq!(
+        load_import(&filename, ZoneFileFormat::GoatNs, None, true)
+            .expect("Failed to load the archive")
+            .len(),
+        1
+    );
+
+    std::fs::remove_file(&path).expect("Failed to clean up");
+}
@@ -0,0 +1,336 @@
+use crate::interop::*;
+use crate::zones::FileZone;
+
+const CLOUDFLARE_EXPORT: &str = r#"{
+    "success": true,
+    "errors": [],
+    "result": [
+        {
+            "id": "023e105f4ecef8ad9ca31a8372d0c353",
+            "zone_id": "372e67954025e0ba6aaa6d586b9e0b59",
+            "zone_name": "example.com",
+            "name": "example.com",
+            "type": "A",
+            "content": "198.51.100.4",
+            "proxied": false,
+            "ttl": 1
+        },
+        {
+            "zone_name": "example.com",
+            "name": "example.com",
+            "type": "MX",
+            "content": "mail.example.com",
+            "priority": 10,
+            "ttl": 3600,
+            "comment": "Main mail server",
+            "tags": ["team:mail"]
+        },
+        {
+            "zone_name": "example.com",
+            "name": "www.example.com",
+            "type": "CNAME",
+            "content": "example.com",
+            "proxied": true,
+            "ttl": 1
+        },
+        {
+            "zone_name": "example.com",
+            "name": "elsewhere.example.net",
+            "type": "A",
+            "content": "198.51.100.5",
+            "ttl": 60
+        }
+    ]
+}"#;
+
+const ROUTE53_EXPORT: &str = r#"{
+    "ResourceRecordSets": [
+        {
+            "Name": "example.com.",
+            "Type": "NS",
+            "TTL": 172800,
+            "ResourceRecords": [
+                { "Value": "ns-1.awsdns-01.org." },
+                { "Value": "ns-2.awsdns-02.net." }
+            ]
+        },
+        {
+            "Name": "example.com.",
+            "Type": "SOA",
+            "TTL": 900,
+            "ResourceRecords": [
+                { "Value": "ns-1.awsdns-01.org. awsdns-hostmaster.amazon.com. 7 7200 900 1209600 86400" }
+            ]
+        },
+        {
+            "Name": "example.com.",
+            "Type": "TXT",
+            "TTL": 300,
+            "ResourceRecords": [
+                { "Value": "\"v=spf1 include:example.net \" \"-all\"" }
+            ]
+        },
+        {
+            "Name": "\\052.example.com.",
+            "Type": "A",
+            "TTL": 60,
+            "ResourceRecords": [
+                { "Value": "198.51.100.4" }
+            ]
+        },
+        {
+            "Name": "cdn.example.com.",
+            "Type": "A",
+            "AliasTarget": {
+                "HostedZoneId": "Z2FDTNDATAQYW2",
+                "DNSName": "d111111abcdef8.cloudfront.net.",
+                "EvaluateTargetHealth": false
+            }
+        }
+    ]
+}"#;
+
+#[test]
+fn test_zone_file_format_from_str() {
+    assert_eq!(
+        "Cloudflare"
+            .parse::<ZoneFileFormat>()
+            .expect("Failed to parse"),
+        ZoneFileFormat::Cloudflare
+    );
+    assert_eq!(
+        "route53"
+            .parse::<ZoneFileFormat>()
+            .expect("Failed to parse"),
+        ZoneFileFormat::Route53
+    );
+    assert_eq!(
+        "BIND".parse::<ZoneFileFormat>().expect("Failed to parse"),
+        ZoneFileFormat::Bind
+    );
+    assert!("tinydns".parse::<ZoneFileFormat>().is_err());
+}
+
+#[test]
+fn test_from_cloudflare() {
+    let zones = from_cloudflare(CLOUDFLARE_EXPORT, None).expect("Failed to parse export");
+    assert_eq!(zones.len(), 1);
+    let zone = &zones[0];
+    assert_eq!(zone.name, "example.com");
+    // the record for another zone gets skipped
+    assert_eq!(zone.records.len(), 3);
+
+    let apex = &zone.records[0];
+    assert_eq!(apex.name, "@");
+    assert_eq!(apex.ttl, 300, "automatic TTLs should be converted");
+
+    let mx = &zone.records[1];
+    assert_eq!(mx.rdata, "10 mail.example.com");
+    assert_eq!(mx.comment.as_deref(), Some("Main mail server"));
+    assert_eq!(mx.tags.get("team").map(String::as_str), Some("mail"));
+
+    assert_eq!(zone.records[2].name, "www");
+}
+
+#[test]
+fn test_cloudflare_requires_zone_name() {
+    let export = r#"[{"name": "www.example.com", "type": "A", "content": "198.51.100.4"}]"#;
+    assert!(from_cloudflare(export, None).is_err());
+    let zones = from_cloudflare(export, Some("example.com")).expect("Failed to parse export");
+    assert_eq!(zones[0].records[0].name, "www");
+}
+
+#[test]
+fn test_cloudflare_roundtrip() {
+    let zones = from_cloudflare(CLOUDFLARE_EXPORT, None).expect("Failed to parse export");
+    let exported = to_cloudflare(&zones[0]).expect("Failed to export");
+    let reimported = from_cloudflare(&exported, None).expect("Failed to parse our own export");
+    assert_eq!(reimported[0].records, zones[0].records);
+}
+
+#[test]
+fn test_from_route53() {
+    let zone = from_route53(ROUTE53_EXPORT, None).expect("Failed to parse export");
+    assert_eq!(zone.name, "example.com");
+    assert_eq!(zone.rname, "awsdns-hostmaster.amazon.com");
+    assert_eq!(zone.serial, 7);
+    assert_eq!(zone.minimum, 86400);
+
+    // two NS, a TXT and the wildcard, the SOA and the alias don't become records
+    assert_eq!(zone.records.len(), 4);
+    assert_eq!(zone.records[0].rdata, "ns-1.awsdns-01.org");
+    assert_eq!(zone.records[2].rdata, "v=spf1 include:example.net -all");
+    assert_eq!(zone.records[3].name, "*");
+}
+
+#[test]
+fn test_route53_requires_soa_or_zone_name() {
+    let export = r#"{"ResourceRecordSets": [{"Name": "www.example.com.", "Type": "A", "TTL": 60, "ResourceRecords": [{"Value": "198.51.100.4"}]}]}"#;
+    assert!(from_route53(export, None).is_err());
+    let zone = from_route53(export, Some("example.com")).expect("Failed to parse export");
+    assert_eq!(zone.records[0].name, "www");
+}
+
+#[test]
+fn test_route53_roundtrip() {
+    let zone = from_route53(ROUTE53_EXPORT, None).expect("Failed to parse export");
+    let exported = to_route53(&zone).expect("Failed to export");
+    let reimported: FileZone =
+        from_route53(&exported, None).expect("Failed to parse our own export");
+    assert!(reimported.matching_data(&zone));
+    assert_eq!(reimported.records.len(), zone.records.len());
+    for record in zone.records.iter() {
+        assert!(
+            reimported.records.contains(record),
+            "{record} went missing in the round trip"
+        );
+    }
+}
+
+#[test]
+fn test_route53_long_txt() {
+    let zone = FileZone {
+        name: "example.com".to_string(),
+        records: vec![crate::zones::FileZoneRecord {
+            id: None,
+            zoneid: None,
+            name: "@".to_string(),
+            rrtype: "TXT".to_string(),
+            class: crate::enums::RecordClass::Internet,
+            rdata: "a".repeat(300),
+            ttl: 60,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        }],
+        ..Default::default()
+    };
+    let exported = to_route53(&zone).expect("Failed to export");
+    assert!(exported.contains(&format!(
+        "\\\"{}\\\" \\\"{}\\\"",
+        "a".repeat(255),
+        "a".repeat(45)
+    )));
+    let reimported = from_route53(&exported, None).expect("Failed to parse our own export");
+    assert_eq!(reimported.records[0].rdata, zone.records[0].rdata);
+}
+
+#[test]
+fn test_route53_keeps_txt_strings() {
+    let zone = FileZone {
+        name: "example.com".to_string(),
+        records: vec![crate::zones::FileZoneRecord {
+            id: None,
+            zoneid: None,
+            name: "@".to_string(),
+            rrtype: "TXT".to_string(),
+            class: crate::enums::RecordClass::Internet,
+            rdata: r#""v=spf1 include:example.net " "-all""#.to_string(),
+            ttl: 60,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        }],
+        ..Default::default()
+    };
+    let exported = to_route53(&zone).expect("Failed to export");
+    assert!(exported.contains(r#"\"v=spf1 include:example.net \" \"-all\""#));
+}
+
+const BIND_ZONE: &str = r#"$ORIGIN example.com.
+$TTL 3600
+@ IN SOA ns1.example.com. hostmaster.example.com. (
+    2024010101 ; serial
+    2h         ; refresh
+    1h         ; retry
+    2w         ; expire
+    300 )      ; minimum
+    NS  ns1
+    NS  ns2.example.net.
+www 60 A 198.51.100.4
+txt TXT "v=spf1 include:example.net -all"
+split TXT "v=spf1 include:example.net " "-all"
+"#;
+
+#[test]
+fn test_from_bind() {
+    let zone = from_bind(BIND_ZONE, None).expect("Failed to parse zone file");
+    assert_eq!(zone.name, "example.com");
+    assert_eq!(zone.rname, "hostmaster.example.com");
+    assert_eq!(zone.serial, 2024010101);
+    assert_eq!(zone.refresh, 7200);
+    assert_eq!(zone.retry, 3600);
+    assert_eq!(zone.expire, 1209600);
+    assert_eq!(zone.minimum, 300);
+    assert_eq!(zone.default_ttl, Some(3600));
+
+    // the SOA doesn't become a record
+    assert_eq!(zone.records.len(), 5);
+    assert_eq!(zone.records[0].rdata, "ns1.example.com");
+    assert_eq!(zone.records[2].ttl, 60);
+    assert_eq!(zone.records[3].rdata, "v=spf1 include:example.net -all");
+    assert_eq!(
+        zone.records[4].rdata,
+        r#""v=spf1 include:example.net " "-all""#
+    );
+}
+
+#[test]
+fn test_bind_requires_soa_or_zone_name() {
+    let input = "www.example.com. 60 IN A 198.51.100.4\n";
+    assert!(from_bind(input, None).is_err());
+    let zone = from_bind(input, Some("example.com.")).expect("Failed to parse zone file");
+    assert_eq!(zone.name, "example.com");
+    assert_eq!(zone.records[0].name, "www");
+    assert_eq!(zone.rname, crate::zones::rname_default());
+    assert_eq!(zone.default_ttl, None);
+
+    // a broken SOA says which line it's on
+    let err = from_bind("\n@ SOA ns1 hostmaster 1 2 3\n", Some("example.com"))
+        .expect_err("Broken SOA was accepted");
+    assert!(format!("{err:?}").contains("line 2"));
+}
+
+#[test]
+fn test_bind_roundtrip() {
+    let zone = from_bind(BIND_ZONE, None).expect("Failed to parse zone file");
+    let exported = to_bind(&zone).expect("Failed to export");
+    assert!(exported.starts_with("$ORIGIN example.com.\n$TTL 3600\n"));
+    assert!(exported.contains(
+        "@ 300 IN SOA ns1.example.com. hostmaster.example.com. 2024010101 7200 3600 1209600 300"
+    ));
+    assert!(exported.contains("@ 3600 IN NS ns2.example.net."));
+    let reimported = from_bind(&exported, None).expect("Failed to parse our own export");
+    assert!(reimported.matching_data(&zone));
+    assert_eq!(reimported.records, zone.records);
+
+    // and the other way around, starting from a zone that didn't come from a file
+    let zone = FileZone {
+        name: "example.org".to_string(),
+        rname: "admin@example.org".to_string(),
+        serial: 5,
+        refresh: 60,
+        retry: 60,
+        expire: 600,
+        minimum: 30,
+        records: vec![crate::zones::FileZoneRecord {
+            id: None,
+            zoneid: None,
+            name: "mail".to_string(),
+            rrtype: "MX".to_string(),
+            class: crate::enums::RecordClass::Internet,
+            rdata: "10 mx.example.net".to_string(),
+            ttl: 60,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        }],
+        ..Default::default()
+    };
+    let reimported =
+        from_bind(&to_bind(&zone).expect("Failed to export"), None).expect("Failed to parse");
+    assert_eq!(reimported.rname, "admin.example.org");
+    assert_eq!(reimported.serial, zone.serial);
+    assert_eq!(reimported.minimum, zone.minimum);
+    assert_eq!(reimported.records, zone.records);
+}
@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use packed_struct::PackedStruct;
+use tokio::net::UdpSocket;
+
+use crate::enums::{Rcode, RecordType};
+use crate::loadtest::*;
+use crate::reply::reply_builder;
+use crate::{Header, Question, HEADER_BYTES, UDP_BUFFER_SIZE};
+
+#[test]
+fn test_parse_name_mix() {
+    let mix = parse_name_mix("example.com:A:3, www.example.com.:aaaa,,hello.example.com")
+        .expect("Failed to parse mix");
+    assert_eq!(
+        mix,
+        vec![
+            WeightedQuestion {
+                name: "example.com".to_string(),
+                rrtype: RecordType::A,
+                weight: 3,
+            },
+            WeightedQuestion {
+                name: "www.example.com".to_string(),
+                rrtype: RecordType::AAAA,
+                weight: 1,
+            },
+            WeightedQuestion {
+                name: "hello.example.com".to_string(),
+                rrtype: RecordType::A,
+                weight: 1,
+            },
+        ]
+    );
+
+    for bad in [
+        "",
+        ":A",
+        "example.com:NOTATYPE",
+        "example.com:A:lots",
+        "example.com:A:1:2",
+        "example.com:A:0",
+    ] {
+        assert!(parse_name_mix(bad).is_err(), "{bad:?} should fail");
+    }
+}
+
+#[test]
+fn test_percentile() {
+    assert_eq!(percentile(&[], 50.0), None);
+    let sorted: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+    assert_eq!(percentile(&sorted, 50.0), Some(Duration::from_millis(50)));
+    assert_eq!(percentile(&sorted, 99.0), Some(Duration::from_millis(99)));
+    assert_eq!(percentile(&sorted, 100.0), Some(Duration::from_millis(100)));
+    assert_eq!(percentile(&sorted, 0.0), Some(Duration::from_millis(1)));
+    assert_eq!(
+        percentile(&[Duration::from_millis(7)], 99.0),
+        Some(Duration::from_millis(7))
+    );
+}
+
+#[test]
+fn test_build_query() {
+    let query = build_query(4321, "example.com", RecordType::MX).expect("Failed to build query");
+    let mut header: [u8; HEADER_BYTES] = [0; HEADER_BYTES];
+    header.copy_from_slice(&query[0..HEADER_BYTES]);
+    let header = Header::unpack(&header).expect("Failed to parse header");
+    assert_eq!(header.id(), 4321);
+    let question =
+        Question::from_packets(&query[HEADER_BYTES..]).expect("Failed to parse question");
+    assert_eq!(question.qname, b"example.com".to_vec());
+    assert_eq!(question.qtype, RecordType::MX);
+}
+
+#[test]
+fn test_report_check() {
+    let mut report = LoadTestReport {
+        sent: 1000,
+        answered: 990,
+        timeouts: 5,
+        p99: Some(Duration::from_millis(20)),
+        ..Default::default()
+    };
+    report.rcodes.insert(format!("{:?}", Rcode::NoError), 980);
+    report.rcodes.insert(format!("{:?}", Rcode::NameError), 5);
+    report.rcodes.insert(format!("{:?}", Rcode::ServFail), 5);
+    assert_eq!(report.error_answers(), 5);
+    assert!((report.error_rate() - 0.01).abs() < f64::EPSILON);
+
+    let mut options = LoadTestOptions::default();
+    assert!(report.check(&options).is_ok());
+
+    options.max_p99 = Some(Duration::from_millis(10));
+    options.max_error_rate = Some(0.001);
+    assert_eq!(report.check(&options).map_err(|e| e.len()), Err(2));
+
+    options.max_p99 = Some(Duration::from_millis(50));
+    options.max_error_rate = Some(0.05);
+    assert!(report.check(&options).is_ok());
+}
+
+/// Answers A queries with NOERROR and everything else with SERVFAIL
+async fn responder(socket: UdpSocket) {
+    let mut buf = [0u8; UDP_BUFFER_SIZE];
+    while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+        let mut header: [u8; HEADER_BYTES] = [0; HEADER_BYTES];
+        header.copy_from_slice(&buf[0..HEADER_BYTES]);
+        let header = Header::unpack(&header).expect("Failed to parse header");
+        let question =
+            Question::from_packets(&buf[HEADER_BYTES..len]).expect("Failed to parse question");
+        let rcode = match question.qtype {
+            RecordType::A => Rcode::NoError,
+            _ => Rcode::ServFail,
+        };
+        let mut reply = reply_builder(header.id(), rcode).expect("Failed to build reply");
+        reply.question = Some(question);
+        socket
+            .send_to(&reply.as_bytes().expect("Failed to serialize reply"), peer)
+            .await
+            .expect("Failed to send reply");
+    }
+}
+
+#[tokio::test]
+async fn test_loadtest_run() {
+    let socket = UdpSocket::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind responder");
+    let target = socket
+        .local_addr()
+        .expect("Failed to get responder address");
+    let responder = tokio::spawn(responder(socket));
+
+    let options = LoadTestOptions {
+        target,
+        qps: 200,
+        duration: Duration::from_millis(500),
+        names: parse_name_mix("example.com:A:3,example.com:AAAA").expect("Failed to parse mix"),
+        timeout: Duration::from_secs(1),
+        max_p99: Some(Duration::from_secs(1)),
+        max_error_rate: Some(0.5),
+    };
+    let report = run(&options).await.expect("Failed to run load test");
+    responder.abort();
+    eprintln!("{report}");
+
+    assert!(report.sent > 0);
+    assert_eq!(report.answered, report.sent);
+    assert_eq!(report.timeouts, 0);
+    assert_eq!(report.bad_replies, 0);
+    // one in four was an AAAA
+    assert_eq!(report.error_answers(), report.sent / 4);
+    assert!(report.p50.is_some());
+    assert!(report.p50 <= report.p99);
+    assert!(report.check(&options).is_ok());
+    assert!(report.to_string().contains("p99="));
+}
@@ -0,0 +1,136 @@
+use chrono::{TimeZone, Utc};
+use flexi_logger::writers::LogWriter;
+use flexi_logger::DeferredNow;
+use log::{Level, Record};
+
+use crate::config::{LoggingConfig, SyslogConfig, SyslogTransport};
+use crate::log_sinks::*;
+
+#[test]
+fn test_rfc5424() {
+    let when = Utc
+        .with_ymd_and_hms(2024, 1, 2, 3, 4, 5)
+        .single()
+        .expect("Failed to build the test time");
+    let message = rfc5424(
+        &Record::builder()
+            .args(format_args!("Query id=1 example.com A"))
+            .level(Level::Info)
+            .target(QUERY_LOG_TARGET)
+            .key_values(&[("qname", "example.com"), ("qtype", "A"), ("odd", "a\"b]c")])
+            .build(),
+        when,
+        "ns1.example.com",
+        "goatns",
+        facility_code("daemon").expect("daemon should be a facility"),
+    );
+    assert_eq!(
+        message,
+        format!(
+            "<30>1 2024-01-02T03:04:05.000000Z ns1.example.com goatns {} query [goatns@32473 qname=\"example.com\" qtype=\"A\" odd=\"a\\\"b\\]c\"] Query id=1 example.com A",
+            std::process::id()
+        )
+    );
+
+    let message = rfc5424(
+        &Record::builder()
+            .args(format_args!("Something broke"))
+            .level(Level::Error)
+            .target("goatns::servers")
+            .build(),
+        when,
+        "",
+        "goat ns",
+        facility_code("LOCAL0").expect("local0 should be a facility"),
+    );
+    assert!(message.starts_with("<131>1 2024-01-02T03:04:05.000000Z - goat_ns "));
+    assert!(message.ends_with(" - - Something broke"));
+    assert!(facility_code("nope").is_none());
+}
+
+#[test]
+fn test_journal_entry() {
+    let entry = journal_entry(
+        &Record::builder()
+            .args(format_args!("two\nlines"))
+            .level(Level::Warn)
+            .target(QUERY_LOG_TARGET)
+            .line(Some(42))
+            .key_values(&[("qname", "example.com"), ("_private-thing", "x")])
+            .build(),
+        JOURNALD_IDENTIFIER,
+    );
+    let mut expected = b"MESSAGE\n".to_vec();
+    expected.extend_from_slice(&9u64.to_le_bytes());
+    expected.extend_from_slice(b"two\nlines\n");
+    expected.extend_from_slice(
+        b"PRIORITY=4\nSYSLOG_IDENTIFIER=goatns\nTARGET=goatns::query\nCODE_LINE=42\nQNAME=example.com\nPRIVATE_THING=x\n",
+    );
+    assert_eq!(entry, expected);
+}
+
+#[test]
+fn test_syslog_udp() {
+    let server = std::net::UdpSocket::bind("127.0.0.1:0").expect("Failed to bind test socket");
+    server
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .expect("Failed to set the timeout");
+    let config = SyslogConfig {
+        transport: SyslogTransport::Udp,
+        address: server
+            .local_addr()
+            .expect("Failed to get the address")
+            .to_string(),
+        ..Default::default()
+    };
+    assert!(config.check().is_empty());
+    let writer = SyslogWriter::new(&config, "ns1.example.com").expect("Failed to connect");
+    writer
+        .write(
+            &mut DeferredNow::new(),
+            &Record::builder()
+                .args(format_args!("hello"))
+                .level(Level::Info)
+                .build(),
+        )
+        .expect("Failed to send the message");
+
+    let mut buf = [0u8; 1024];
+    let len = server.recv(&mut buf).expect("Didn't get the message");
+    let message = String::from_utf8_lossy(&buf[..len]);
+    assert!(message.starts_with("<30>1 "), "{message}");
+    assert!(message.contains(" ns1.example.com goatns "), "{message}");
+    assert!(message.ends_with(" - - hello"), "{message}");
+}
+
+#[test]
+fn test_logging_config_check() {
+    assert!(LoggingConfig::default().check().is_empty());
+    assert_eq!(
+        LoggingConfig {
+            console: false,
+            ..Default::default()
+        }
+        .check()
+        .len(),
+        1
+    );
+    assert!(LoggingConfig {
+        console: false,
+        journald: true,
+        ..Default::default()
+    }
+    .check()
+    .is_empty());
+    let errors = LoggingConfig {
+        syslog: Some(SyslogConfig {
+            transport: SyslogTransport::Tcp,
+            address: "syslog.example.com".to_string(),
+            facility: "nope".to_string(),
+            app_name: String::new(),
+        }),
+        ..Default::default()
+    }
+    .check();
+    assert_eq!(errors.len(), 3, "{errors:?}");
+}
@@ -1,12 +1,37 @@
+mod acme;
+mod backup;
+mod builtin;
+mod compliance;
 mod config;
 mod db;
+mod delegation;
+mod doctor;
 mod doh;
 mod e2e_test;
 mod enums;
+#[cfg(feature = "fault-injection")]
+mod fault_injection;
+mod forward;
+mod host_overrides;
+mod import_source;
+mod interop;
+mod loadtest;
+mod log_sinks;
+mod name_policy;
+mod packet_dumper;
+#[cfg(feature = "response-policy")]
+mod policy;
+mod proxy;
 mod resourcerecord;
+mod serial;
+mod sync;
 mod test_api;
 pub mod test_harness;
 mod utils;
+mod wizards;
+mod zone_events;
+mod zone_schema;
+mod zonefile;
 
 use crate::db::test::test_get_sqlite_memory;
 use crate::db::*;
@@ -83,11 +108,11 @@ async fn test_build_iana_org_a_reply() {
         nscount: 0,
     };
     let qname = "iana.org".as_bytes().to_vec();
-    let question = Question {
-        qname: qname.clone(),
-        qtype: crate::RecordType::A,
-        qclass: crate::RecordClass::Internet,
-    };
+    let question = Question::new(
+        qname.clone(),
+        crate::RecordType::A,
+        crate::RecordClass::Internet,
+    );
     let question_length = question
         .try_to_bytes()
         .expect("Failed to convert question to bytes")
@@ -98,13 +123,15 @@ async fn test_build_iana_org_a_reply() {
         ttl: 350,
         address: address.into(),
         rclass: crate::RecordClass::Internet,
-    }];
+    }
+    .into()];
     let reply = Reply {
         header,
         question: Some(question),
         answers,
         authorities: vec![],
         additional: vec![],
+        edns: None,
     };
     let reply_bytes: Vec<u8> = reply
         .as_bytes()
@@ -203,11 +230,11 @@ async fn test_cloudflare_soa_reply() {
         nscount: 0,
     };
     let qname = "cloudflare.com".as_bytes().to_vec();
-    let question = Question {
-        qname: qname.clone(),
-        qtype: crate::RecordType::SOA,
-        qclass: crate::RecordClass::Internet,
-    };
+    let question = Question::new(
+        qname.clone(),
+        crate::RecordType::SOA,
+        crate::RecordClass::Internet,
+    );
     let question_length = question
         .try_to_bytes()
         .expect("Failed to convert question to bytes")
@@ -228,7 +255,7 @@ async fn test_cloudflare_soa_reply() {
     };
 
     // let rdata = rdata.as_bytes();
-    let answers = vec![rdata];
+    let answers = vec![rdata.into()];
 
     let mut reply = Reply {
         header: header.clone(),
@@ -236,10 +263,11 @@ async fn test_cloudflare_soa_reply() {
         answers,
         authorities: vec![],
         additional: vec![],
+        edns: None,
     };
     reply.header.recursion_available = true;
     debug!("{:?}", reply);
-    let reply_bytes: Vec<u8> = reply.as_bytes().await.unwrap();
+    let reply_bytes: Vec<u8> = reply.as_bytes().unwrap();
     debug!("{:?}", reply_bytes);
 
     // testing if I was parsing it right...
@@ -313,11 +341,11 @@ async fn build_ackcdn_allzeros() {
         nscount: 0,
     };
     let qname = "ackcdn.com".as_bytes().to_vec();
-    let question = Question {
-        qname: qname.clone(),
-        qtype: crate::RecordType::A,
-        qclass: crate::RecordClass::Internet,
-    };
+    let question = Question::new(
+        qname.clone(),
+        crate::RecordType::A,
+        crate::RecordClass::Internet,
+    );
     let question_length = question
         .try_to_bytes()
         .expect("Failed to convert question to bytes")
@@ -342,7 +370,8 @@ async fn build_ackcdn_allzeros() {
         // rdlength,
         // rdata: rdata.into(),
         // compression: true,
-    }];
+    }
+    .into()];
 
     let reply = Reply {
         header,
@@ -350,8 +379,9 @@ async fn build_ackcdn_allzeros() {
         answers,
         authorities: vec![],
         additional: vec![],
+        edns: None,
     };
-    let reply_bytes: Vec<u8> = reply.as_bytes().await.unwrap();
+    let reply_bytes: Vec<u8> = reply.as_bytes().unwrap();
     debug!("{} bytes: {:?}", reply_bytes.len(), reply_bytes);
 
     let expected_bytes = [
@@ -603,20 +633,36 @@ fn test_all_record_class_conversions() {
 
 #[test]
 fn test_normalize_name() {
-    let q = Question {
-        qname: String::from("HellO.world").into_bytes(),
-        qtype: crate::enums::RecordType::A,
-        qclass: crate::enums::RecordClass::Internet,
-    };
+    let q = Question::new(
+        String::from("HellO.world").into_bytes(),
+        crate::enums::RecordType::A,
+        crate::enums::RecordClass::Internet,
+    );
     assert_eq!(q.normalized_name().unwrap(), String::from("hello.world"));
-    let q = Question {
-        qname: String::from("hello.world").into_bytes(),
-        qtype: crate::enums::RecordType::A,
-        qclass: crate::enums::RecordClass::Internet,
-    };
+    let q = Question::new(
+        String::from("hello.world").into_bytes(),
+        crate::enums::RecordType::A,
+        crate::enums::RecordClass::Internet,
+    );
     assert_eq!(q.normalized_name().unwrap(), String::from("hello.world"));
 }
 
+#[test]
+fn test_question_lookup_name() {
+    for (qname, expected) in [
+        ("Hello.World", "hello.world"),
+        ("hello.world.", "hello.world"),
+        ("Bücher.example", "xn--bcher-kva.example"),
+    ] {
+        let q = Question::new(
+            qname.as_bytes().to_vec(),
+            crate::enums::RecordType::A,
+            crate::enums::RecordClass::Internet,
+        );
+        assert_eq!(q.lookup_name(), expected.as_bytes());
+    }
+}
+
 #[test]
 fn test_get_question_qname() {
     assert!(get_question_qname(&[23, 0]).is_err());
@@ -737,3 +783,781 @@ async fn test_dont_normalize_ttls() {
     assert!(found_records.len() == 3);
     println!(" OK");
 }
+
+#[tokio::test]
+/// if the datastore's queue is full we should SERVFAIL straight away rather than waiting
+async fn test_datastore_full_servfail() {
+    use crate::datastore::Command;
+    use crate::metrics::{Metrics, METRICS};
+    use crate::servers::{parse_query, QueryProtocol};
+
+    let (tx, _rx) = tokio::sync::mpsc::channel(1);
+    tx.try_send(Command::Shutdown.into())
+        .expect("Failed to fill the datastore channel");
+    let saturated_before = Metrics::get(&METRICS.datastore_saturated);
+
+    let query = [
+        /* header - 12 bytes */
+        0xa3, 0x70, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        /* question - 14 bytes */
+        0x04, 0x69, 0x61, 0x6e, 0x61, 0x03, 0x6f, 0x72, 0x67, 0x00, 0x00, 0x01, 0x00, 0x01,
+    ];
+    let reply = parse_query(tx, query.len(), &query, false, QueryProtocol::Udp, None)
+        .await
+        .expect("Failed to handle query");
+    assert_eq!(reply.header.id, 0xa370);
+    assert_eq!(reply.header.rcode, crate::Rcode::ServFail);
+    assert!(Metrics::get(&METRICS.datastore_saturated) > saturated_before);
+}
+
+#[tokio::test]
+/// queries matching the trace pattern get kept with what happened to them, others don't
+async fn test_query_trace() {
+    use crate::datastore::Command;
+    use crate::servers::{parse_query, QueryProtocol};
+
+    let (tx, _rx) = tokio::sync::mpsc::channel(1);
+    tx.try_send(Command::Shutdown.into())
+        .expect("Failed to fill the datastore channel");
+
+    let query = |name: &str| {
+        let mut query = vec![
+            0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        query.extend(name_as_bytes(name.as_bytes(), None, None).expect("failed to encode name"));
+        query.extend([0x00, 0x10, 0x00, 0x01]);
+        query
+    };
+
+    assert!(crate::query_trace::enable("(", None).is_err());
+    crate::query_trace::enable(r"^trace-test\.goat$", Some(2)).expect("failed to start tracing");
+    for name in [
+        "trace-test.goat",
+        "TRACE-test.goat",
+        "other.goat",
+        "trace-test.goat",
+    ] {
+        let query = query(name);
+        parse_query(
+            tx.clone(),
+            query.len(),
+            &query,
+            false,
+            QueryProtocol::Udp,
+            None,
+        )
+        .await
+        .expect("Failed to handle query");
+    }
+
+    let status = crate::query_trace::status();
+    assert_eq!(status.pattern.as_deref(), Some(r"^trace-test\.goat$"));
+    // three matched, but there's only room for two
+    assert_eq!(status.entries.len(), 2);
+    assert_eq!(status.entries[0].qname, "TRACE-test.goat");
+    assert_eq!(status.entries[0].qtype, "TXT");
+    assert!(status.entries[0].request.starts_with("1234"));
+    assert!(status.entries[0]
+        .events
+        .iter()
+        .any(|event| event.contains("SERVFAIL")));
+
+    crate::query_trace::disable();
+    assert!(crate::query_trace::status().entries.is_empty());
+}
+
+#[tokio::test]
+/// empty non-terminals and names without the type asked for are NODATA, and both kinds of negative answer carry the SOA
+async fn test_negative_answers() {
+    use crate::datastore::{manager, SqliteStore};
+    use crate::db::test::test_get_sqlite_memory;
+    use crate::db::{start_db, DBEntity};
+    use crate::reply::Answer;
+    use crate::servers::{parse_query, QueryProtocol};
+    use crate::zones::{FileZone, FileZoneRecord};
+    use crate::{Header, HEADER_BYTES};
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await.expect("Failed to start DB");
+    FileZone {
+        name: "ent.goat".to_string(),
+        minimum: 60,
+        records: vec![FileZoneRecord {
+            id: None,
+            zoneid: None,
+            name: "_sip._tcp".to_string(),
+            rrtype: "TXT".to_string(),
+            class: crate::RecordClass::Internet,
+            rdata: "hello".to_string(),
+            ttl: 300,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        }],
+        ..Default::default()
+    }
+    .save(&pool)
+    .await
+    .expect("Failed to save zone");
+
+    let (tx, rx) = tokio::sync::mpsc::channel(10);
+    tokio::spawn(manager(rx, SqliteStore::new(pool), None, None, None, 4));
+
+    for (qname, qtype, rcode) in [
+        ("_tcp.ent.goat", crate::RecordType::A, crate::Rcode::NoError),
+        (
+            "_sip._tcp.ent.goat",
+            crate::RecordType::A,
+            crate::Rcode::NoError,
+        ),
+        ("ent.goat", crate::RecordType::TXT, crate::Rcode::NoError),
+        // the SOA's only an answer at the apex
+        (
+            "_tcp.ent.goat",
+            crate::RecordType::SOA,
+            crate::Rcode::NoError,
+        ),
+        (
+            "nope.ent.goat",
+            crate::RecordType::A,
+            crate::Rcode::NameError,
+        ),
+    ] {
+        let mut query = Header {
+            id: 1234,
+            qdcount: 1,
+            ..Default::default()
+        }
+        .pack()
+        .expect("Failed to pack header")
+        .to_vec();
+        query.extend(
+            Question::new(
+                qname.as_bytes().to_vec(),
+                qtype,
+                crate::RecordClass::Internet,
+            )
+            .try_to_bytes()
+            .expect("Failed to pack question"),
+        );
+
+        let reply = parse_query(
+            tx.clone(),
+            query.len(),
+            &query,
+            false,
+            QueryProtocol::Udp,
+            None,
+        )
+        .await
+        .expect("Failed to handle query");
+        assert_eq!(reply.header.rcode, rcode, "{qname} {qtype}");
+        assert!(reply.header.authoritative);
+        assert!(reply.answers.is_empty());
+        assert_eq!(reply.authorities.len(), 1);
+        assert_eq!(reply.authorities[0].name, b"ent.goat".to_vec());
+        assert_eq!(reply.authorities[0].record_type, crate::RecordType::SOA);
+        assert_eq!(reply.authorities[0].ttl, 60);
+
+        // the SOA makes it onto the wire, named with a pointer into the question
+        let reply_bytes = reply.as_bytes().expect("Failed to encode reply");
+        let mut header_bytes = [0u8; HEADER_BYTES];
+        header_bytes.copy_from_slice(&reply_bytes[0..HEADER_BYTES]);
+        let header = Header::unpack(&header_bytes).expect("Failed to unpack header");
+        assert_eq!(header.ancount, 0);
+        assert_eq!(header.nscount, 1);
+        let authority_start = query.len();
+        let zone_offset = (HEADER_BYTES + qname.len() - "ent.goat".len()) as u16;
+        assert_eq!(
+            reply_bytes[authority_start..authority_start + 2],
+            (0b1100000000000000 | zone_offset).to_be_bytes()
+        );
+    }
+
+    // at the apex the SOA is the answer
+    let mut query = Header {
+        id: 1234,
+        qdcount: 1,
+        ..Default::default()
+    }
+    .pack()
+    .expect("Failed to pack header")
+    .to_vec();
+    query.extend(
+        Question::new(
+            b"ENT.goat".to_vec(),
+            crate::RecordType::SOA,
+            crate::RecordClass::Internet,
+        )
+        .try_to_bytes()
+        .expect("Failed to pack question"),
+    );
+    let reply = parse_query(tx, query.len(), &query, false, QueryProtocol::Udp, None)
+        .await
+        .expect("Failed to handle query");
+    assert_eq!(reply.header.rcode, crate::Rcode::NoError);
+    assert!(reply.header.authoritative);
+    assert!(reply.authorities.is_empty());
+    assert!(matches!(
+        reply.answers.as_slice(),
+        [Answer {
+            name: Some(_),
+            record: InternalResourceRecord::SOA { minimum: 60, .. },
+        }]
+    ));
+}
+
+#[tokio::test]
+/// queries under a zone cut get a referral to the child's name servers, not an answer
+async fn test_delegation_referral() {
+    use crate::datastore::{manager, SqliteStore};
+    use crate::db::test::test_get_sqlite_memory;
+    use crate::db::{start_db, DBEntity};
+    use crate::servers::{parse_query, QueryProtocol};
+    use crate::zones::{FileZone, FileZoneRecord};
+    use crate::{Header, HEADER_BYTES};
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await.expect("Failed to start DB");
+    let record = |name: &str, rrtype: &str, rdata: &str| FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: name.to_string(),
+        rrtype: rrtype.to_string(),
+        class: crate::RecordClass::Internet,
+        rdata: rdata.to_string(),
+        ttl: 300,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    };
+    FileZone {
+        name: "cut.goat".to_string(),
+        records: vec![
+            record("child", "NS", "ns1.child.cut.goat"),
+            record("ns1.child", "A", "192.0.2.53"),
+        ],
+        ..Default::default()
+    }
+    .save(&pool)
+    .await
+    .expect("Failed to save zone");
+
+    let (tx, rx) = tokio::sync::mpsc::channel(10);
+    tokio::spawn(manager(rx, SqliteStore::new(pool), None, None, None, 4));
+
+    // the glue's in our zone, but it's the child's data so it still gets referred
+    for qname in ["www.child.cut.goat", "ns1.child.cut.goat"] {
+        let mut query = Header {
+            id: 4321,
+            qdcount: 1,
+            ..Default::default()
+        }
+        .pack()
+        .expect("Failed to pack header")
+        .to_vec();
+        query.extend(
+            Question::new(
+                qname.as_bytes().to_vec(),
+                crate::RecordType::A,
+                crate::RecordClass::Internet,
+            )
+            .try_to_bytes()
+            .expect("Failed to pack question"),
+        );
+
+        let reply = parse_query(
+            tx.clone(),
+            query.len(),
+            &query,
+            false,
+            QueryProtocol::Udp,
+            None,
+        )
+        .await
+        .expect("Failed to handle query");
+        assert_eq!(reply.header.rcode, crate::Rcode::NoError, "{qname}");
+        assert!(!reply.header.authoritative);
+        assert!(reply.answers.is_empty());
+        assert_eq!(reply.authorities.len(), 1);
+        assert_eq!(reply.authorities[0].name, b"child.cut.goat".to_vec());
+        assert_eq!(reply.authorities[0].record_type, crate::RecordType::NS);
+        assert_eq!(reply.additional.len(), 1);
+        assert_eq!(reply.additional[0].name, b"ns1.child.cut.goat".to_vec());
+        assert_eq!(reply.additional[0].rdata, vec![192, 0, 2, 53]);
+
+        let reply_bytes = reply.as_bytes().expect("Failed to encode reply");
+        let mut header_bytes = [0u8; HEADER_BYTES];
+        header_bytes.copy_from_slice(&reply_bytes[0..HEADER_BYTES]);
+        let header = Header::unpack(&header_bytes).expect("Failed to unpack header");
+        assert_eq!(header.ancount, 0);
+        assert_eq!(header.nscount, 1);
+        assert_eq!(header.arcount, 1);
+    }
+}
+
+#[test]
+/// minimal responses only drop the extra sections from answers, referrals and negative answers need them
+fn test_minimal_responses() {
+    use crate::reply::ReplyBuilder;
+    use crate::{Header, ResourceRecord};
+
+    let ns = ResourceRecord {
+        name: b"example.goat".to_vec(),
+        record_type: RecordType::NS,
+        class: RecordClass::Internet,
+        ttl: 300,
+        rdata: vec![0],
+    };
+    let glue = ResourceRecord {
+        name: b"ns1.example.goat".to_vec(),
+        record_type: RecordType::A,
+        class: RecordClass::Internet,
+        ttl: 300,
+        rdata: vec![192, 0, 2, 53],
+    };
+    let reply = |qtype: RecordType, answer: Option<InternalResourceRecord>| {
+        ReplyBuilder::new(Header::new(1))
+            .question(Question::new(
+                b"www.example.goat".to_vec(),
+                qtype,
+                RecordClass::Internet,
+            ))
+            .answers(answer)
+            .authority(ns.clone())
+            .additional(glue.clone())
+            .build()
+    };
+    let a_record = InternalResourceRecord::A {
+        address: u32::from_be_bytes([192, 0, 2, 1]),
+        ttl: 300,
+        rclass: RecordClass::Internet,
+    };
+
+    let minimal = reply(RecordType::A, Some(a_record.clone())).minimal();
+    assert_eq!(minimal.answers.len(), 1);
+    assert!(minimal.authorities.is_empty());
+    assert!(minimal.additional.is_empty());
+
+    // a referral, or the answers don't include what was asked for
+    for full in [
+        reply(RecordType::A, None),
+        reply(RecordType::MX, Some(a_record.clone())),
+    ] {
+        let minimal = full.minimal();
+        assert_eq!(minimal.authorities.len(), 1);
+        assert_eq!(minimal.additional.len(), 1);
+    }
+
+    // negative answers keep their SOA
+    let mut nxdomain = reply(RecordType::A, Some(a_record));
+    nxdomain.header.rcode = crate::Rcode::NameError;
+    let nxdomain = nxdomain.minimal();
+    assert_eq!(nxdomain.authorities.len(), 1);
+}
+
+#[test]
+/// answers are written out with their own owner names, pointing into the question where they can
+fn test_answer_owner_names() {
+    use crate::reply::ReplyBuilder;
+    use crate::resourcerecord::DomainName;
+    use crate::Header;
+
+    let qname = b"WWW.example.goat";
+    let cname = InternalResourceRecord::CNAME {
+        cname: DomainName::from("target.other.goat"),
+        ttl: 300,
+        rclass: RecordClass::Internet,
+    };
+    let address = InternalResourceRecord::A {
+        address: u32::from_be_bytes([192, 0, 2, 1]),
+        ttl: 300,
+        rclass: RecordClass::Internet,
+    };
+    let question = Question::new(qname.to_vec(), RecordType::A, RecordClass::Internet);
+    let question_bytes = question.try_to_bytes().expect("Failed to pack question");
+    let reply = ReplyBuilder::new(Header::new(1))
+        .question(question)
+        .named_answer(b"www.example.goat", cname.clone())
+        .named_answer(b"target.other.goat", address.clone())
+        .named_answer(b"example.goat", address)
+        .build();
+    let bytes = reply.as_bytes().expect("Failed to encode reply");
+    let cname_rdata = cname
+        .as_bytes(&qname.to_vec())
+        .expect("Failed to encode CNAME");
+
+    // the CNAME's owned by the question name, so it points at it and keeps the question's case
+    let mut answer_start = crate::HEADER_BYTES + question_bytes.len();
+    assert_eq!(bytes[answer_start..answer_start + 2], [0xc0, 12]);
+    // then the target's written out in full
+    answer_start += 2 + 10 + cname_rdata.len();
+    let target = name_as_bytes(b"target.other.goat", None, None).expect("Failed to encode name");
+    assert_eq!(bytes[answer_start..answer_start + target.len()], target);
+    // and the zone's at the end of the question
+    answer_start += target.len() + 10 + 4;
+    assert_eq!(bytes[answer_start..answer_start + 2], [0xc0, 12 + 4]);
+    assert_eq!(bytes.len(), answer_start + 2 + 10 + 4);
+}
+
+#[test]
+fn test_health_report_ready() {
+    use crate::health::HealthReport;
+
+    let mut report = HealthReport {
+        datastore: true,
+        udp_listener: true,
+        tcp_listener: true,
+        database: true,
+        self_check: None,
+    };
+    assert!(report.ready());
+    report.self_check = Some(false);
+    assert!(!report.ready());
+    report.self_check = Some(true);
+    assert!(report.ready());
+    report.database = false;
+    assert!(!report.ready());
+}
+
+#[tokio::test]
+async fn test_health_self_check() {
+    use crate::enums::Rcode;
+    use crate::health::{self_check, self_check_target};
+    use crate::{Header, HEADER_BYTES};
+
+    assert_eq!(
+        self_check_target("0.0.0.0:15353".parse().unwrap()),
+        "127.0.0.1:15353".parse().unwrap()
+    );
+    assert_eq!(
+        self_check_target("[::]:15353".parse().unwrap()),
+        "[::1]:15353".parse().unwrap()
+    );
+
+    // pretend to be a server that answers everything with the given rcode
+    async fn fake_server(rcode: Rcode) -> std::net::SocketAddr {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            let mut header_bytes = [0u8; HEADER_BYTES];
+            header_bytes.copy_from_slice(&buf[0..HEADER_BYTES]);
+            let mut header = Header::unpack(&header_bytes).unwrap();
+            header.qr = PacketType::Answer;
+            header.rcode = rcode;
+            let mut reply = header.pack().unwrap().to_vec();
+            reply.extend_from_slice(&buf[HEADER_BYTES..len]);
+            socket.send_to(&reply, peer).await.unwrap();
+        });
+        addr
+    }
+
+    let addr = fake_server(Rcode::NameError).await;
+    assert!(self_check(addr, "example.goat").await.is_ok());
+    let addr = fake_server(Rcode::ServFail).await;
+    assert!(self_check(addr, "example.goat").await.is_err());
+}
+
+#[test]
+fn test_oidc_metadata_is_fresh() {
+    use crate::web::auth::metadata_is_fresh;
+
+    assert!(metadata_is_fresh(chrono::Utc::now(), 60));
+    assert!(!metadata_is_fresh(
+        chrono::Utc::now() - chrono::TimeDelta::seconds(120),
+        60
+    ));
+    assert!(metadata_is_fresh(
+        chrono::Utc::now() - chrono::TimeDelta::seconds(120),
+        u64::MAX
+    ));
+}
+
+#[test]
+fn test_static_asset_hashes() {
+    use crate::web::assets::{asset_url, cache_control_for, hash_static_dir, init_asset_hashes};
+    use std::path::Path;
+
+    let static_dir = Path::new("./static_files");
+    let hashes = hash_static_dir(static_dir);
+    let hash = hashes
+        .get("css/goatns.css")
+        .expect("goatns.css wasn't hashed");
+    assert_eq!(hash.len(), 16);
+
+    init_asset_hashes(static_dir);
+    assert_eq!(
+        asset_url("css/goatns.css"),
+        format!("/static/css/goatns.css?v={hash}")
+    );
+    assert_eq!(asset_url("/nope.css"), "/static/nope.css");
+
+    assert_eq!(
+        cache_control_for("/css/goatns.css", Some(&format!("v={hash}"))),
+        "public, max-age=31536000, immutable"
+    );
+    assert_eq!(
+        cache_control_for("/css/goatns.css", Some("v=0000")),
+        "no-cache"
+    );
+    assert_eq!(cache_control_for("/css/goatns.css", None), "no-cache");
+}
+
+#[test]
+fn test_csp_nonce_policy() {
+    use crate::web::middleware::csp::{generate_nonce, nonce_policy, wants_nonce_policy};
+
+    let nonce = generate_nonce();
+    assert_eq!(nonce.len(), 24);
+    assert_ne!(nonce, generate_nonce());
+
+    let policy = nonce_policy(&nonce, None);
+    assert!(policy.contains(&format!("script-src 'self' 'nonce-{nonce}'")));
+    assert!(policy.contains("frame-ancestors 'none'"));
+    assert!(!policy.contains("img-src"));
+
+    let policy = nonce_policy(&nonce, Some("https://tile.openstreetmap.org"));
+    assert!(policy.contains("img-src 'self' https://tile.openstreetmap.org;"));
+
+    assert!(wants_nonce_policy("/"));
+    assert!(wants_nonce_policy("/ui/zones/list"));
+    assert!(wants_nonce_policy("/auth/login"));
+    assert!(!wants_nonce_policy("/api/zone"));
+    assert!(!wants_nonce_policy("/dns-query"));
+}
+
+#[tokio::test]
+async fn test_metrics_render() {
+    let pool = crate::db::test::test_get_sqlite_memory().await;
+    crate::metrics::register_pool("metrics_test", pool);
+
+    let output = crate::metrics::render();
+    assert!(output.contains("# TYPE goatns_queries_saturated_total counter"));
+    assert!(output.contains(r#"goatns_queries_total{protocol="DoH"} "#));
+    assert!(output.contains("goatns_datastore_saturated_total "));
+    assert!(output.contains(r#"goatns_db_pool_connections{pool="metrics_test",state="idle"}"#));
+    assert!(output.contains(r#"goatns_db_pool_connections{pool="metrics_test",state="in_use"}"#));
+}
+
+#[tokio::test]
+/// the datastore counts and times what it's asked to do
+async fn test_datastore_command_metrics() {
+    use crate::datastore::{manager, CachePurge, Command, SqliteStore};
+    use crate::metrics::command_stats;
+
+    let pool = crate::db::test::test_get_sqlite_memory().await;
+    crate::db::start_db(&pool)
+        .await
+        .expect("Failed to start DB");
+    let before = command_stats("purge_cache").unwrap_or_default();
+
+    // everything's slow if the limit's zero
+    let (tx, rx) = tokio::sync::mpsc::channel(10);
+    let datastore = tokio::spawn(manager(
+        rx,
+        SqliteStore::new(pool),
+        None,
+        None,
+        Some(std::time::Duration::ZERO),
+        4,
+    ));
+    for _ in 0..3 {
+        let (os_tx, os_rx) = tokio::sync::oneshot::channel();
+        tx.send(
+            Command::PurgeCache {
+                purge: CachePurge::All,
+                resp: os_tx,
+            }
+            .into(),
+        )
+        .await
+        .expect("Failed to send to datastore");
+        os_rx
+            .await
+            .expect("Datastore didn't respond")
+            .expect("Failed to purge cache");
+    }
+    tx.send(Command::Shutdown.into())
+        .await
+        .expect("Failed to send to datastore");
+    let _ = datastore.await;
+
+    let after = command_stats("purge_cache").expect("Purges weren't counted");
+    assert!(after.count >= before.count + 3);
+    assert!(after.slow >= before.slow + 3);
+    assert!(after.handling > before.handling);
+
+    let output = crate::metrics::render();
+    assert!(output.contains("# TYPE goatns_datastore_command_seconds_total counter"));
+    assert!(output.contains(r#"goatns_datastore_commands_total{command="purge_cache"}"#));
+    assert!(output.contains(r#"goatns_datastore_queue_wait_seconds_total{command="shutdown"}"#));
+    assert!(output.contains("goatns_datastore_queue_depth_max "));
+}
+
+#[tokio::test]
+/// changes to a zone take turns, other zones and lookups don't wait for them, and bulk changes wait for everything
+async fn test_datastore_zone_locks() {
+    use crate::datastore::{Scope, ZoneLocks};
+    use tokio::time::timeout;
+
+    let wait = std::time::Duration::from_millis(50);
+    let locks = ZoneLocks::default();
+
+    let first = locks.lock(&Scope::ZoneId(1)).await;
+    assert!(timeout(wait, locks.lock(&Scope::ZoneId(1))).await.is_err());
+    let second = timeout(wait, locks.lock(&Scope::ZoneId(2)))
+        .await
+        .expect("Another zone waited");
+    let _ = timeout(wait, locks.lock(&Scope::ZoneName("new.goat".to_string())))
+        .await
+        .expect("A new zone waited");
+    let _ = timeout(wait, locks.lock(&Scope::Free))
+        .await
+        .expect("A lookup waited");
+    assert!(timeout(wait, locks.lock(&Scope::Everything)).await.is_err());
+
+    drop(first);
+    drop(second);
+    let everything = timeout(wait, locks.lock(&Scope::Everything))
+        .await
+        .expect("Bulk change waited after the zones were done");
+    assert!(timeout(wait, locks.lock(&Scope::ZoneId(2))).await.is_err());
+    let _ = timeout(wait, locks.lock(&Scope::Free))
+        .await
+        .expect("A lookup waited for a bulk change");
+
+    drop(everything);
+    let _ = timeout(wait, locks.lock(&Scope::ZoneId(1)))
+        .await
+        .expect("Zone was still locked");
+}
+
+#[tokio::test]
+/// naming a zone that's already there locks the same thing as using its ID
+async fn test_datastore_resolve_scope() {
+    use crate::datastore::{resolve_scope, Scope, SqliteStore};
+    use crate::zones::FileZone;
+
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await.expect("Failed to start DB");
+    let zone = FileZone::builder("locked.goat", "hostmaster.locked.goat")
+        .build()
+        .expect("Failed to build zone")
+        .save(&pool)
+        .await
+        .expect("Failed to save zone");
+    let store = SqliteStore::new(pool);
+
+    let id = zone.id.expect("Zone has no id");
+    assert_eq!(
+        resolve_scope(Scope::ZoneName("locked.goat".to_string()), &store).await,
+        Scope::ZoneId(id)
+    );
+    assert_eq!(
+        resolve_scope(Scope::ZoneName("new.goat".to_string()), &store).await,
+        Scope::ZoneName("new.goat".to_string())
+    );
+    assert_eq!(
+        resolve_scope(Scope::ZoneId(id), &store).await,
+        Scope::ZoneId(id)
+    );
+}
+
+#[test]
+fn test_zone_diff() {
+    use crate::zones::{FileZone, FileZoneRecord, SoaChange};
+
+    let record =
+        |id: Option<i64>, name: &str, rrtype: &str, rdata: &str, ttl: u32| FileZoneRecord {
+            id,
+            zoneid: id.map(|_| 1),
+            name: name.to_string(),
+            rrtype: rrtype.to_string(),
+            class: RecordClass::Internet,
+            rdata: rdata.to_string(),
+            ttl,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        };
+    let current = FileZone {
+        id: Some(1),
+        name: "example.goat".to_string(),
+        rname: "admin@example.goat".to_string(),
+        serial: 2024010101,
+        refresh: 7200,
+        records: vec![
+            record(Some(1), "", "NS", "ns1.example.goat", 3600),
+            record(Some(2), "www", "A", "192.0.2.1", 300),
+            record(Some(3), "mail", "A", "192.0.2.2", 300),
+            record(Some(4), "old", "TXT", "bye", 300),
+        ],
+        ..Default::default()
+    };
+
+    // the same records, with @ for the apex and the rname written with a dot
+    let same = FileZone {
+        id: None,
+        rname: "admin.example.goat".to_string(),
+        serial: 0,
+        records: current
+            .records
+            .iter()
+            .map(|r| FileZoneRecord {
+                id: None,
+                zoneid: None,
+                name: match r.name.as_str() {
+                    "" => "@".to_string(),
+                    name => name.to_uppercase(),
+                },
+                ..r.clone()
+            })
+            .collect(),
+        ..current.clone()
+    };
+    let diff = current.diff(&same);
+    assert!(diff.is_empty(), "{diff:?}");
+    assert_eq!(diff.unchanged, 4);
+
+    let proposed = FileZone {
+        refresh: 3600,
+        records: vec![
+            record(None, "@", "NS", "ns1.example.goat", 3600),
+            // rdata changes are a removal and an addition
+            record(None, "www", "A", "192.0.2.10", 300),
+            record(None, "mail", "A", "192.0.2.2", 60),
+            record(None, "new", "AAAA", "2001:db8::1", 300),
+        ],
+        ..same
+    };
+    let diff = current.diff(&proposed);
+    assert_eq!(
+        diff.soa,
+        vec![SoaChange {
+            field: "refresh".to_string(),
+            current: "7200".to_string(),
+            proposed: "3600".to_string(),
+        }]
+    );
+    assert_eq!(
+        diff.added,
+        vec![
+            record(None, "www", "A", "192.0.2.10", 300),
+            record(None, "new", "AAAA", "2001:db8::1", 300),
+        ]
+    );
+    assert_eq!(diff.updated.len(), 1);
+    assert_eq!(diff.updated[0].current, current.records[2]);
+    assert_eq!(
+        diff.updated[0].proposed,
+        record(Some(3), "mail", "A", "192.0.2.2", 60)
+    );
+    // removals come out sorted by name, type and rdata
+    assert_eq!(
+        diff.removed,
+        vec![current.records[3].clone(), current.records[1].clone()]
+    );
+    assert_eq!(diff.unchanged, 1);
+}
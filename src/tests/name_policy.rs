@@ -0,0 +1,122 @@
+use crate::config::ConfigFile;
+use crate::db::test::test_get_sqlite_memory;
+use crate::db::{start_db, ReservedName};
+use crate::error::GoatNsError;
+use crate::name_policy::*;
+
+fn test_config() -> ConfigFile {
+    ConfigFile {
+        hostname: "ns1.goat.farm".to_string(),
+        ..ConfigFile::default()
+    }
+}
+
+#[test]
+fn test_normalise() {
+    assert_eq!(normalise("Example.COM."), "example.com");
+    assert_eq!(normalise(" www.example.com "), "www.example.com");
+    assert_eq!(normalise("."), "");
+    // internationalised names are stored as punycode
+    assert_eq!(normalise("Bücher.example."), "xn--bcher-kva.example");
+    assert_eq!(normalise("xn--bcher-kva.example"), "xn--bcher-kva.example");
+}
+
+#[test]
+fn test_relative_name() {
+    let zone = "Example.com.";
+    assert_eq!(relative_name("", zone).expect("apex"), "@");
+    assert_eq!(relative_name("@", zone).expect("apex"), "@");
+    assert_eq!(relative_name("example.com.", zone).expect("apex"), "@");
+    assert_eq!(relative_name("EXAMPLE.COM", zone).expect("apex"), "@");
+    assert_eq!(relative_name("WWW", zone).expect("relative"), "www");
+    assert_eq!(
+        relative_name("www.example.com.", zone).expect("absolute"),
+        "www"
+    );
+    assert_eq!(
+        relative_name("a.b.Example.Com", zone).expect("unqualified"),
+        "a.b"
+    );
+    assert_eq!(
+        relative_name("Bücher.example.com", zone).expect("idn"),
+        "xn--bcher-kva"
+    );
+    // without the dot it's relative, so it stays as it is
+    assert_eq!(
+        relative_name("www.example.net", zone).expect("relative"),
+        "www.example.net"
+    );
+    assert!(relative_name("www.example.net.", zone).is_err());
+    assert!(relative_name("notexample.com.", zone).is_err());
+}
+
+#[test]
+fn test_name_policy_reserved() {
+    let policy = NamePolicy::new(&test_config(), vec!["Hello.Goat.".to_string()]);
+
+    assert!(policy.check("example.goat").is_ok());
+    assert!(policy.check("localhost").is_err());
+    assert!(policy.check("www.example.com").is_err());
+    assert!(policy.check("EXAMPLE.NET.").is_err());
+    // the server's own name
+    assert!(policy.check("ns1.goat.farm").is_err());
+    // and the ones from the database, which are normalised
+    assert_eq!(policy.reserved_by("deep.hello.goat"), Some("hello.goat"));
+    assert_eq!(policy.reserved_by("otherhello.goat"), None);
+}
+
+#[test]
+fn test_name_policy_tlds() {
+    let config = ConfigFile {
+        allowed_tlds: vec!["goat".to_string()],
+        ..test_config()
+    };
+    let policy = NamePolicy::new(&config, vec![]);
+    assert!(policy.check("example.goat").is_ok());
+    assert!(policy.check("example.cheese").is_err());
+    assert!(policy.check("").is_err());
+    assert!(policy.check("not a name.goat").is_err());
+}
+
+#[tokio::test]
+async fn test_name_policy_load() -> Result<(), GoatNsError> {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await?;
+
+    ReservedName {
+        name: "Reserved.Goat.".to_string(),
+        comment: "Keeping this one".to_string(),
+    }
+    .save(&pool)
+    .await?;
+    let mut conn = pool.acquire().await?;
+    let reserved = ReservedName::get_all(&mut conn).await?;
+    assert_eq!(reserved.len(), 1);
+    assert_eq!(reserved[0].name, "reserved.goat");
+
+    let policy = NamePolicy::load(&mut conn, &test_config()).await?;
+    assert!(policy.check("www.reserved.goat").is_err());
+
+    ReservedName::delete(&pool, "reserved.goat").await?;
+    let policy = NamePolicy::load(&mut conn, &test_config()).await?;
+    assert!(policy.check("www.reserved.goat").is_ok());
+    assert!(ReservedName::delete(&pool, "reserved.goat").await.is_err());
+    Ok(())
+}
+
+#[test]
+fn test_to_unicode() {
+    assert!(is_idn("xn--bcher-kva.example"));
+    assert!(is_idn("www.XN--bcher-kva.example"));
+    assert!(!is_idn("example.com"));
+    assert!(!is_idn("xn--"));
+
+    assert_eq!(to_unicode("xn--bcher-kva.example"), "bücher.example");
+    assert_eq!(to_unicode("xn--bcher-kva.example."), "bücher.example.");
+    // record names are relative
+    assert_eq!(to_unicode("xn--bcher-kva"), "bücher");
+    assert_eq!(to_unicode("example.com"), "example.com");
+    assert_eq!(to_unicode("@"), "@");
+    // it's the other way from normalise
+    assert_eq!(to_unicode(&normalise("Bücher.example.")), "bücher.example");
+}
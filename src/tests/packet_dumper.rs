@@ -0,0 +1,64 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::packet_dumper::{apply_retention, pcap_header, pcap_record, DumpType};
+
+#[test]
+fn test_pcap_header() {
+    let header = pcap_header();
+    assert_eq!(header.len(), 24);
+    assert_eq!(header[0..4], [0xd4, 0xc3, 0xb2, 0xa1]);
+    // LINKTYPE_RAW
+    assert_eq!(header[20..24], 101u32.to_le_bytes());
+}
+
+#[test]
+fn test_pcap_record() {
+    let payload = [0xa3, 0x70, 0x01, 0x00];
+    let timestamp = UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+    let record = pcap_record(timestamp, &payload, DumpType::ClientRequest);
+
+    assert_eq!(record.len(), 16 + 20 + 8 + payload.len());
+    assert_eq!(record[0..4], 1_700_000_000u32.to_le_bytes());
+    assert_eq!(record[4..8], 123_456u32.to_le_bytes());
+    assert_eq!(record[8..12], 32u32.to_le_bytes());
+    assert_eq!(record[8..12], record[12..16]);
+
+    let ip = &record[16..36];
+    assert_eq!(ip[0], 0x45);
+    assert_eq!(ip[2..4], 32u16.to_be_bytes());
+    assert_eq!(ip[9], 17);
+    // a valid header checksums to zero
+    let mut sum: u32 = ip
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], pair[1]])))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    assert_eq!(sum, 0xffff);
+
+    let udp = &record[36..44];
+    assert_eq!(udp[2..4], 53u16.to_be_bytes());
+    assert_eq!(udp[4..6], 12u16.to_be_bytes());
+    assert_eq!(record[44..], payload);
+}
+
+#[test]
+fn test_capture_retention() {
+    let dir = std::env::temp_dir().join(format!("goatns-captures-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("failed to create temp dir");
+    for name in [
+        "goatns-2024-01-01T000000.000000Z.pcap",
+        "goatns-2024-01-02T000000.000000Z.pcap",
+        "goatns-2024-01-03T000000.000000Z.pcap",
+        "someone-elses.pcap",
+    ] {
+        std::fs::write(dir.join(name), "").expect("failed to write file");
+    }
+    apply_retention(&dir, 2);
+    assert!(!dir.join("goatns-2024-01-01T000000.000000Z.pcap").exists());
+    assert!(dir.join("goatns-2024-01-02T000000.000000Z.pcap").exists());
+    assert!(dir.join("goatns-2024-01-03T000000.000000Z.pcap").exists());
+    assert!(dir.join("someone-elses.pcap").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,108 @@
+use std::net::IpAddr;
+
+use crate::enums::{RecordClass, RecordType};
+use crate::policy::{Policy, Script, DEFAULT_TTL};
+use crate::reply::Answer;
+use crate::resourcerecord::InternalResourceRecord;
+use crate::Question;
+
+fn policy(source: &str) -> Result<Policy, crate::error::GoatNsError> {
+    Policy::new(&Script {
+        name: "test.lua".to_string(),
+        source: source.to_string(),
+        function: "policy".to_string(),
+        max_instructions: 100_000,
+    })
+}
+
+fn a_record(last: u8, ttl: u32) -> Answer {
+    InternalResourceRecord::A {
+        address: u32::from_be_bytes([192, 0, 2, last]),
+        ttl,
+        rclass: RecordClass::Internet,
+    }
+    .into()
+}
+
+fn question() -> Question {
+    Question::new(
+        b"www.example.com".to_vec(),
+        RecordType::A,
+        RecordClass::Internet,
+    )
+}
+
+#[test]
+fn test_policy_reorders_and_filters() {
+    let policy = policy(
+        r#"
+        function policy(client, qname, qtype, answers)
+            assert(qname == "www.example.com")
+            assert(qtype == "A")
+            assert(answers[1].name == "www.example.com")
+            assert(answers[1].type == "A")
+            assert(answers[1].record.A.address == "192.0.2.1")
+            if client == "198.51.100.1" then
+                return { 3, 1 }
+            end
+            return nil
+        end
+        "#,
+    )
+    .expect("Failed to load policy");
+    let answers = vec![a_record(1, 300), a_record(2, 300), a_record(3, 300)];
+
+    let client: IpAddr = "198.51.100.1".parse().expect("Failed to parse address");
+    let changed = policy
+        .apply(Some(client), &question(), &answers)
+        .expect("Failed to run policy");
+    assert_eq!(changed, vec![a_record(3, 300), a_record(1, 300)]);
+
+    // nil means leave them alone
+    let unchanged = policy
+        .apply(None, &question(), &answers)
+        .expect("Failed to run policy");
+    assert_eq!(unchanged, answers);
+}
+
+#[test]
+fn test_policy_rewrites() {
+    let policy = policy(
+        r#"
+        function policy(client, qname, qtype, answers)
+            return {
+                { index = 1, ttl = 30 },
+                { type = "A", data = "192.0.2.99" },
+            }
+        end
+        "#,
+    )
+    .expect("Failed to load policy");
+    let changed = policy
+        .apply(None, &question(), &[a_record(1, 300)])
+        .expect("Failed to run policy");
+    assert_eq!(changed, vec![a_record(1, 30), a_record(99, DEFAULT_TTL)]);
+}
+
+#[test]
+fn test_policy_failures() {
+    // there's no io or os to play with
+    assert!(policy("io.open('/etc/passwd')").is_err());
+    assert!(policy("dofile('/etc/passwd')").is_err());
+    assert!(policy("function other() end").is_err());
+
+    let answers = [a_record(1, 300)];
+    for source in [
+        "function policy() return { 2 } end",
+        "function policy() return { 0 } end",
+        "function policy() return { { type = 'A', data = 'not an address' } } end",
+        "function policy() error('nope') end",
+        "function policy() while true do end end",
+    ] {
+        let policy = policy(source).expect("Failed to load policy");
+        assert!(
+            policy.apply(None, &question(), &answers).is_err(),
+            "{source}"
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use ipnet::IpNet;
+
+use crate::proxy::*;
+
+fn proxy_v2_header(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+    let mut header = PROXY_V2_SIGNATURE.to_vec();
+    header.push(0x20 | command);
+    header.push(family);
+    header.extend((addresses.len() as u16).to_be_bytes());
+    header.extend(addresses);
+    header
+}
+
+#[test]
+fn test_parse_proxy_v2() {
+    // TCP over IPv4 from 192.0.2.1:5353 to 198.51.100.1:53
+    let header = proxy_v2_header(
+        0x1,
+        0x11,
+        &[192, 0, 2, 1, 198, 51, 100, 1, 0x14, 0xe9, 0, 53],
+    );
+    assert_eq!(
+        parse_proxy_v2(&header).expect("Failed to parse IPv4 header"),
+        Some(SocketAddr::from_str("192.0.2.1:5353").expect("Failed to parse address"))
+    );
+
+    let mut addresses = vec![];
+    addresses.extend(
+        "2001:db8::1"
+            .parse::<std::net::Ipv6Addr>()
+            .expect("Failed to parse address")
+            .octets(),
+    );
+    addresses.extend([0u8; 16]);
+    addresses.extend([0x14, 0xe9, 0, 53]);
+    // TLVs on the end get skipped
+    addresses.extend([0x04, 0, 1, 0]);
+    let header = proxy_v2_header(0x1, 0x21, &addresses);
+    assert_eq!(
+        parse_proxy_v2(&header).expect("Failed to parse IPv6 header"),
+        Some(SocketAddr::from_str("[2001:db8::1]:5353").expect("Failed to parse address"))
+    );
+
+    // health checks from the proxy itself
+    let header = proxy_v2_header(0x0, 0x00, &[]);
+    assert_eq!(
+        parse_proxy_v2(&header).expect("Failed to parse LOCAL header"),
+        None
+    );
+
+    let mut bad_signature = proxy_v2_header(0x0, 0x00, &[]);
+    bad_signature[0] = b'P';
+    assert!(parse_proxy_v2(&bad_signature).is_err());
+    assert!(parse_proxy_v2(&proxy_v2_header(0x1, 0x11, &[192, 0, 2, 1])).is_err());
+    let mut truncated = proxy_v2_header(0x1, 0x11, &[192, 0, 2, 1, 198, 51, 100, 1, 0, 1, 0, 53]);
+    truncated.truncate(20);
+    assert!(parse_proxy_v2(&truncated).is_err());
+}
+
+#[tokio::test]
+/// the header gets read off the front, and what's left is the DNS stream
+async fn test_read_proxy_v2() {
+    let peer = SocketAddr::from_str("10.0.0.1:40000").expect("Failed to parse address");
+    let mut stream = proxy_v2_header(
+        0x1,
+        0x11,
+        &[192, 0, 2, 1, 198, 51, 100, 1, 0x14, 0xe9, 0, 53],
+    );
+    stream.extend([0, 12]);
+    let mut reader = stream.as_slice();
+    assert_eq!(
+        read_proxy_v2(&mut reader, peer)
+            .await
+            .expect("Failed to read header"),
+        SocketAddr::from_str("192.0.2.1:5353").expect("Failed to parse address")
+    );
+    assert_eq!(reader, [0, 12]);
+
+    let stream = proxy_v2_header(0x0, 0x00, &[]);
+    assert_eq!(
+        read_proxy_v2(&mut stream.as_slice(), peer)
+            .await
+            .expect("Failed to read header"),
+        peer
+    );
+}
+
+#[test]
+fn test_client_ip_from_headers() {
+    let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().expect("Failed to parse network")];
+    let ip = |addr: &str| IpAddr::from_str(addr).expect("Failed to parse address");
+
+    // not from a proxy, so the headers could say anything
+    assert_eq!(
+        client_ip_from_headers(ip("192.0.2.1"), None, Some("198.51.100.1"), &trusted),
+        ip("192.0.2.1")
+    );
+    // from a proxy without any headers
+    assert_eq!(
+        client_ip_from_headers(ip("10.0.0.1"), None, None, &trusted),
+        ip("10.0.0.1")
+    );
+    // the client can put whatever they like on the front, only the last untrusted hop counts
+    assert_eq!(
+        client_ip_from_headers(
+            ip("10.0.0.1"),
+            None,
+            Some("203.0.113.9, 198.51.100.1, 10.0.0.2"),
+            &trusted
+        ),
+        ip("198.51.100.1")
+    );
+    // Forwarded wins over X-Forwarded-For, and can have ports and IPv6 in it
+    assert_eq!(
+        client_ip_from_headers(
+            ip("10.0.0.1"),
+            Some(r#"for=192.0.2.60;proto=https, For="[2001:db8:cafe::17]:4711""#),
+            Some("198.51.100.1"),
+            &trusted
+        ),
+        ip("2001:db8:cafe::17")
+    );
+    assert_eq!(
+        client_ip_from_headers(ip("10.0.0.1"), Some("for=192.0.2.60:8080"), None, &trusted),
+        ip("192.0.2.60")
+    );
+    // obfuscated identifiers stop the walk at the proxy that added them
+    assert_eq!(
+        client_ip_from_headers(
+            ip("10.0.0.1"),
+            Some("for=192.0.2.60, for=_hidden, for=10.0.0.2"),
+            None,
+            &trusted
+        ),
+        ip("10.0.0.2")
+    );
+    // IPv4-mapped peers are still trusted
+    assert!(is_trusted_proxy(&ip("::ffff:10.0.0.1"), &trusted));
+    assert!(!is_trusted_proxy(&ip("192.0.2.1"), &trusted));
+}
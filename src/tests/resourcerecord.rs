@@ -12,3 +12,429 @@ fn test_check_long_labels() {
         )
     );
 }
+
+#[test]
+fn test_alias_record() {
+    use crate::enums::{RecordClass, RecordType};
+    use crate::resourcerecord::{DomainName, InternalResourceRecord};
+    use crate::zones::FileZoneRecord;
+
+    assert_eq!(RecordType::from("ALIAS"), RecordType::ALIAS);
+    assert_eq!(RecordType::from(&65401u16), RecordType::ALIAS);
+    assert_eq!(RecordType::ALIAS.to_string(), "ALIAS");
+    // you can't ask for one directly
+    assert!(!RecordType::ALIAS.supported());
+
+    let record = InternalResourceRecord::try_from(FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: "@".to_string(),
+        rrtype: "ALIAS".to_string(),
+        class: RecordClass::Internet,
+        rdata: "lb.example.net".to_string(),
+        ttl: 300,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    })
+    .expect("failed to parse ALIAS record");
+    assert_eq!(
+        record,
+        InternalResourceRecord::ALIAS {
+            target: DomainName::from("lb.example.net"),
+            ttl: 300,
+            rclass: RecordClass::Internet,
+        }
+    );
+
+    assert!(crate::alias::flattens_to(
+        RecordType::A,
+        RecordClass::Internet
+    ));
+    assert!(crate::alias::flattens_to(
+        RecordType::AAAA,
+        RecordClass::Internet
+    ));
+    assert!(!crate::alias::flattens_to(
+        RecordType::TXT,
+        RecordClass::Internet
+    ));
+}
+
+#[test]
+fn test_forward_record() {
+    use crate::enums::{RecordClass, RecordType};
+    use crate::resourcerecord::{DomainName, InternalResourceRecord};
+    use crate::zones::FileZoneRecord;
+
+    assert_eq!(RecordType::from("FORWARD"), RecordType::FORWARD);
+    assert_eq!(RecordType::from(&65402u16), RecordType::FORWARD);
+    assert_eq!(RecordType::FORWARD.to_string(), "FORWARD");
+    // it's ours, so nobody gets to ask for it
+    assert!(!RecordType::FORWARD.supported());
+    assert!(!crate::generic::is_generic_only(RecordType::FORWARD as u16));
+
+    let record = InternalResourceRecord::try_from(FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: "legacy".to_string(),
+        rrtype: "FORWARD".to_string(),
+        class: RecordClass::Internet,
+        rdata: "legacy.example.net".to_string(),
+        ttl: 300,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    })
+    .expect("failed to parse FORWARD record");
+    assert_eq!(
+        record,
+        InternalResourceRecord::FORWARD {
+            target: DomainName::from("legacy.example.net"),
+            ttl: 300,
+            rclass: RecordClass::Internet,
+        }
+    );
+    assert_eq!(RecordType::from(&record), RecordType::FORWARD);
+}
+
+#[test]
+fn test_record_constructors() {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::enums::{RecordClass, RecordType};
+    use crate::resourcerecord::{DNSCharString, DomainName, InternalResourceRecord};
+    use crate::zones::FileZoneRecord;
+
+    let record = FileZoneRecord::a("www", Ipv4Addr::new(192, 0, 2, 1), 300)
+        .with_comment("the web server")
+        .with_tag("env", "prod");
+    assert_eq!(record.rrtype, "A");
+    assert_eq!(record.record_type(), RecordType::A);
+    assert_eq!(record.comment.as_deref(), Some("the web server"));
+    assert_eq!(record.tags.get("env").map(String::as_str), Some("prod"));
+    assert_eq!(
+        InternalResourceRecord::try_from(record).expect("failed to convert A"),
+        InternalResourceRecord::A {
+            address: u32::from(Ipv4Addr::new(192, 0, 2, 1)),
+            ttl: 300,
+            rclass: RecordClass::Internet,
+        }
+    );
+
+    let record = FileZoneRecord::aaaa("www", Ipv6Addr::LOCALHOST, 300);
+    assert_eq!(record.rdata, "::1");
+    assert_eq!(record.record_type(), RecordType::AAAA);
+
+    assert_eq!(
+        InternalResourceRecord::try_from(FileZoneRecord::mx("@", 10, "mail.example.com", 300))
+            .expect("failed to convert MX"),
+        InternalResourceRecord::MX {
+            preference: 10,
+            exchange: DomainName::from("mail.example.com"),
+            ttl: 300,
+            rclass: RecordClass::Internet,
+        }
+    );
+    assert_eq!(
+        InternalResourceRecord::try_from(FileZoneRecord::txt("@", "v=spf1 -all", 300))
+            .expect("failed to convert TXT"),
+        InternalResourceRecord::TXT {
+            txtdata: vec![DNSCharString::from("v=spf1 -all")],
+            ttl: 300,
+            class: RecordClass::Internet,
+        }
+    );
+    assert_eq!(
+        FileZoneRecord::ns("@", "ns1.example.com", 86400).record_type(),
+        RecordType::NS
+    );
+    assert_eq!(
+        FileZoneRecord::cname("ftp", "www.example.com", 300).record_type(),
+        RecordType::CNAME
+    );
+
+    // types we only know the number of don't have a name to go with them
+    let generic = FileZoneRecord {
+        rrtype: "TYPE65534".to_string(),
+        ..FileZoneRecord::txt("@", r"\# 0", 300)
+    };
+    assert_eq!(generic.record_type(), RecordType::InvalidType);
+    assert_eq!(generic.rrtype_code(), 65534);
+}
+
+#[test]
+fn test_naptr_enum_record() {
+    use crate::enums::RecordClass;
+    use crate::resourcerecord::{split_quoted_fields, InternalResourceRecord};
+    use crate::zones::FileZoneRecord;
+
+    assert_eq!(
+        split_quoted_fields(r#"10 "a \"quoted\" bit" ."#).expect("failed to split"),
+        vec!["10", r#"a "quoted" bit"#, "."]
+    );
+    assert!(split_quoted_fields(r#"10 "unterminated"#).is_err());
+
+    let record = |rdata: &str| FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: "4.3.2.1.5.5.5.0.0.8.1.e164.arpa".to_string(),
+        rrtype: "NAPTR".to_string(),
+        class: RecordClass::Internet,
+        rdata: rdata.to_string(),
+        ttl: 300,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    };
+
+    // RFC6116 style ENUM record
+    let regexp = "!^.*$!sip:info@example.com!";
+    let naptr =
+        InternalResourceRecord::try_from(record(&format!(r#"100 10 "u" "E2U+sip" "{regexp}" ."#)))
+            .expect("failed to parse ENUM NAPTR");
+
+    let mut expected: Vec<u8> = vec![0, 100, 0, 10, 1, b'U', 7];
+    expected.extend(b"E2U+sip");
+    expected.push(regexp.len() as u8);
+    expected.extend(regexp.as_bytes());
+    expected.push(0);
+    assert_eq!(
+        naptr
+            .as_bytes(&b"4.3.2.1.5.5.5.0.0.8.1.e164.arpa".to_vec())
+            .expect("failed to serialize"),
+        expected
+    );
+
+    // SRV-style rewrite with a replacement and no regexp
+    let naptr = InternalResourceRecord::try_from(record(
+        r#"100 50 "s" "SIP+D2U" "" _sip._udp.example.com."#,
+    ))
+    .expect("failed to parse replacement NAPTR");
+    let bytes = naptr.as_bytes(&vec![]).expect("failed to serialize");
+    let mut expected: Vec<u8> = vec![0, 100, 0, 50, 1, b'S', 7];
+    expected.extend(b"SIP+D2U");
+    expected.push(0);
+    expected.extend([4, b'_', b's', b'i', b'p', 4, b'_', b'u', b'd', b'p']);
+    expected.extend([
+        7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+    ]);
+    assert_eq!(bytes, expected);
+
+    // bad records
+    assert!(InternalResourceRecord::try_from(record(r#"100 10 "u" "E2U+sip""#)).is_err());
+    assert!(InternalResourceRecord::try_from(record(r#"cheese 10 "u" "E2U+sip" "" ."#)).is_err());
+    assert!(InternalResourceRecord::try_from(record(r#"100 10 "u!" "E2U+sip" "" ."#)).is_err());
+    assert!(InternalResourceRecord::try_from(record(&format!(
+        r#"100 10 "u" "E2U+sip" "{regexp}" example.com"#
+    )))
+    .is_err());
+}
+
+#[test]
+fn test_hinfo_record() {
+    use crate::enums::RecordClass;
+    use crate::resourcerecord::{DNSCharString, InternalResourceRecord};
+    use crate::zones::FileZoneRecord;
+
+    let record = |rdata: &str| FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: "hinfo".to_string(),
+        rrtype: "HINFO".to_string(),
+        class: RecordClass::Internet,
+        rdata: rdata.to_string(),
+        ttl: 300,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    };
+
+    let hinfo = InternalResourceRecord::try_from(record(r#""goat" "Goat OS""#))
+        .expect("failed to parse HINFO");
+    assert_eq!(
+        hinfo,
+        InternalResourceRecord::HINFO {
+            cpu: Some(DNSCharString::from("goat")),
+            os: Some(DNSCharString::from("Goat OS")),
+            ttl: 300,
+            rclass: RecordClass::Internet,
+        }
+    );
+    let mut expected: Vec<u8> = vec![4];
+    expected.extend(b"goat");
+    expected.push(7);
+    expected.extend(b"Goat OS");
+    assert_eq!(
+        hinfo.as_bytes(&vec![]).expect("failed to serialize"),
+        expected
+    );
+
+    // nothing about the host leaks out when the fields are empty
+    let empty = InternalResourceRecord::HINFO {
+        cpu: None,
+        os: None,
+        ttl: 300,
+        rclass: RecordClass::Internet,
+    };
+    assert_eq!(
+        empty.as_bytes(&vec![]).expect("failed to serialize"),
+        vec![0, 0]
+    );
+
+    assert!(InternalResourceRecord::try_from(record(r#""goat""#)).is_err());
+}
+
+#[test]
+fn test_generic_record() {
+    use crate::enums::RecordClass;
+    use crate::generic::{format_rdata, parse_rdata, parse_type, type_name};
+    use crate::resourcerecord::InternalResourceRecord;
+    use crate::zones::FileZoneRecord;
+
+    assert_eq!(parse_type("TYPE65534"), Some(65534));
+    assert_eq!(parse_type("type1"), Some(1));
+    assert_eq!(parse_type("MX"), Some(15));
+    assert_eq!(parse_type("a"), Some(1));
+    assert_eq!(parse_type("TYPE65536"), None);
+    assert_eq!(parse_type("BORK"), None);
+    assert_eq!(type_name(65534), "TYPE65534");
+    assert_eq!(type_name(1), "A");
+
+    assert_eq!(
+        parse_rdata("\\# 4 0a000001").expect("valid"),
+        vec![10, 0, 0, 1]
+    );
+    assert_eq!(
+        parse_rdata("\\# 4 0a00 0001").expect("valid"),
+        vec![10, 0, 0, 1]
+    );
+    assert_eq!(parse_rdata("\\# 0").expect("valid"), Vec::<u8>::new());
+    // lengths have to match, and it has to be hex
+    assert!(parse_rdata("\\# 3 0a000001").is_err());
+    assert!(parse_rdata("\\# 1 zz").is_err());
+    assert!(parse_rdata("\\# 0a000001").is_err());
+    assert_eq!(format_rdata(&[10, 0, 0, 1]), "\\# 4 0a000001");
+    assert_eq!(format_rdata(&[]), "\\# 0");
+
+    let fzr = |rrtype: &str, rdata: &str| FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: "@".to_string(),
+        rrtype: rrtype.to_string(),
+        class: RecordClass::Internet,
+        rdata: rdata.to_string(),
+        ttl: 300,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    };
+
+    let record = InternalResourceRecord::try_from(fzr("TYPE65534", "\\# 4 0a000001"))
+        .expect("failed to parse generic record");
+    assert_eq!(
+        record,
+        InternalResourceRecord::Generic {
+            rrtype: 65534,
+            rdata: vec![10, 0, 0, 1],
+            ttl: 300,
+            rclass: RecordClass::Internet,
+        }
+    );
+    assert_eq!(record.rrtype_code(), 65534);
+    assert_eq!(record.as_bytes(&vec![]).expect("bytes"), vec![10, 0, 0, 1]);
+
+    // known types work with the generic syntax too, and TYPE1 is just A
+    let record =
+        InternalResourceRecord::try_from(fzr("A", "\\# 4 c0000201")).expect("generic A record");
+    assert_eq!(record.rrtype_code(), 1);
+    assert!(matches!(
+        InternalResourceRecord::try_from(fzr("TYPE1", "192.0.2.1")),
+        Ok(InternalResourceRecord::A { .. })
+    ));
+
+    // unknown types need generic rdata, and meta types can't be stored
+    assert!(InternalResourceRecord::try_from(fzr("TYPE65534", "hello")).is_err());
+    assert!(InternalResourceRecord::try_from(fzr("TYPE255", "\\# 0")).is_err());
+    assert!(InternalResourceRecord::try_from(fzr("BORK", "\\# 0")).is_err());
+}
+
+#[test]
+fn test_txt_multiple_strings() {
+    use crate::enums::RecordClass;
+    use crate::resourcerecord::{
+        parse_txt_rdata, txt_value, DNSCharString, InternalResourceRecord,
+    };
+    use crate::zones::FileZoneRecord;
+
+    let txt_bytes = |rdata: &str| {
+        InternalResourceRecord::try_from(FileZoneRecord {
+            id: None,
+            zoneid: None,
+            name: "@".to_string(),
+            rrtype: "TXT".to_string(),
+            class: RecordClass::Internet,
+            rdata: rdata.to_string(),
+            ttl: 300,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        })
+        .expect("failed to parse TXT record")
+        .as_bytes(&vec![])
+        .expect("failed to get bytes")
+    };
+
+    // plain rdata is one string
+    assert_eq!(
+        txt_bytes("hello world"),
+        [&[11u8][..], b"hello world"].concat()
+    );
+    assert_eq!(txt_bytes(""), vec![0]);
+
+    // long values used to get cut off at 255, now they're split
+    let long = "a".repeat(300);
+    let bytes = txt_bytes(&long);
+    assert_eq!(bytes.len(), 302);
+    assert_eq!(bytes[0], 255);
+    assert_eq!(bytes[256], 45);
+    assert!(bytes[1..256]
+        .iter()
+        .chain(&bytes[257..])
+        .all(|b| *b == b'a'));
+
+    // quoted strings are kept as they were written
+    let spf = r#""v=spf1 include:example.net " "-all""#;
+    assert_eq!(
+        parse_txt_rdata(spf),
+        vec![
+            DNSCharString::from("v=spf1 include:example.net "),
+            DNSCharString::from("-all")
+        ]
+    );
+    assert_eq!(
+        txt_bytes(spf),
+        [
+            &[27u8][..],
+            b"v=spf1 include:example.net ",
+            &[4u8][..],
+            b"-all"
+        ]
+        .concat()
+    );
+    assert_eq!(txt_value(spf), "v=spf1 include:example.net -all");
+    assert_eq!(
+        parse_txt_rdata(r#""say \"hi\"""#),
+        vec![DNSCharString::from(r#"say "hi""#)]
+    );
+
+    // quotes that aren't around the whole thing are just part of the text
+    assert_eq!(
+        parse_txt_rdata(r#"say "hi" to them"#),
+        vec![DNSCharString::from(r#"say "hi" to them"#)]
+    );
+    assert_eq!(
+        parse_txt_rdata(r#""unterminated \""#),
+        vec![DNSCharString::from(r#""unterminated \""#)]
+    );
+}
@@ -0,0 +1,70 @@
+use std::cmp::Ordering;
+use std::time::Duration;
+
+use proptest::prelude::*;
+
+use crate::zones::{next_serial, serial_add, serial_cmp, FileZone, SERIAL_HALF};
+
+#[test]
+fn test_serial_cmp() {
+    assert_eq!(serial_cmp(1, 1), Some(Ordering::Equal));
+    assert_eq!(serial_cmp(1, 2), Some(Ordering::Less));
+    assert_eq!(serial_cmp(2, 1), Some(Ordering::Greater));
+    // it's wrapped, so 1 comes after u32::MAX
+    assert_eq!(serial_cmp(u32::MAX, 1), Some(Ordering::Less));
+    assert_eq!(serial_cmp(1, u32::MAX), Some(Ordering::Greater));
+    // the examples from RFC1982 section 5.2, with SERIAL_BITS = 32
+    assert_eq!(serial_cmp(0, SERIAL_HALF - 1), Some(Ordering::Less));
+    assert_eq!(serial_cmp(0, SERIAL_HALF), None);
+    assert_eq!(serial_cmp(0, SERIAL_HALF + 1), Some(Ordering::Greater));
+
+    assert_eq!(serial_add(u32::MAX, 2), Some(1));
+    assert_eq!(serial_add(0, SERIAL_HALF - 1), Some(SERIAL_HALF - 1));
+    assert_eq!(serial_add(0, SERIAL_HALF), None);
+}
+
+#[test]
+fn test_zone_serial_helpers() {
+    let zone = FileZone::builder("example.com", "hostmaster@example.com")
+        .serial(u32::MAX)
+        .refresh(7200)
+        .retry(3600)
+        .expire(1209600)
+        .build()
+        .expect("Failed to build zone");
+    assert!(zone.is_newer_than(u32::MAX - 1));
+    assert!(!zone.is_newer_than(u32::MAX));
+    assert!(!zone.is_newer_than(0));
+    assert_eq!(zone.refresh_interval(), Duration::from_secs(7200));
+    assert_eq!(zone.retry_interval(), Duration::from_secs(3600));
+    assert_eq!(zone.expire_interval(), Duration::from_secs(1209600));
+}
+
+proptest! {
+    #[test]
+    fn prop_serial_cmp_is_antisymmetric(a: u32, b: u32) {
+        prop_assert_eq!(serial_cmp(a, b), serial_cmp(b, a).map(Ordering::reverse));
+        prop_assert_eq!(serial_cmp(a, b).is_none(), a.wrapping_sub(b) == SERIAL_HALF);
+    }
+
+    #[test]
+    fn prop_adding_makes_it_newer(serial: u32, n in 1..SERIAL_HALF) {
+        let added = serial_add(serial, n);
+        prop_assert_eq!(added, Some(serial.wrapping_add(n)));
+        prop_assert_eq!(serial_cmp(serial, serial.wrapping_add(n)), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn prop_adding_too_much_fails(serial: u32, n in SERIAL_HALF..=u32::MAX) {
+        prop_assert_eq!(serial_add(serial, n), None);
+    }
+
+    #[test]
+    fn prop_next_serial_is_newer(serial: u32) {
+        let zone = FileZone {
+            serial: next_serial(serial),
+            ..FileZone::default()
+        };
+        prop_assert!(zone.is_newer_than(serial));
+    }
+}
@@ -0,0 +1,163 @@
+use crate::config::ImportTtlPolicy;
+use crate::datastore::{SqliteStore, ZoneStore};
+use crate::db::test::test_get_sqlite_memory;
+use crate::db::{get_zone_with_txn, start_db, SyncedZone};
+use crate::interop::from_bind;
+use crate::sync::read_zone_files;
+use crate::zones::{default_serial, next_serial, FileZone};
+
+fn example_zone(name: &str, serial: u32, address: &str) -> FileZone {
+    from_bind(
+        &format!(
+            "@ 300 IN SOA ns1.{name}. hostmaster.{name}. {serial} 7200 3600 1209600 300\n@ 3600 IN NS ns1.{name}.\nwww 60 IN A {address}\n"
+        ),
+        None,
+    )
+    .expect("Failed to parse zone")
+}
+
+#[test]
+fn test_read_zone_files() {
+    let dir = std::env::temp_dir().join(format!("goatns-sync-{}", std::process::id()));
+    for subdir in ["internal", ".git"] {
+        std::fs::create_dir_all(dir.join(subdir)).expect("Failed to make dirs");
+    }
+    for (filename, contents) in [
+        (
+            "example.com.zone",
+            "@ 300 IN SOA ns1.example.com. hostmaster.example.com. 1 7200 3600 1209600 300\nwww 60 IN A 192.0.2.1\n",
+        ),
+        // no SOA, so it's named after the file
+        ("internal/example.net.zone", "www 60 IN A 192.0.2.2\n"),
+        (
+            "example.org.json",
+            r#"[{"name": "example.org", "records": [{"name": "www", "rrtype": "A", "rdata": "192.0.2.3"}]}]"#,
+        ),
+        (".git/broken.zone", "this isn't a zone file"),
+        ("README.md", "# Zones"),
+    ] {
+        std::fs::write(dir.join(filename), contents).expect("Failed to write file");
+    }
+
+    let zones = read_zone_files(&dir, false).expect("Failed to read zones");
+    assert_eq!(
+        zones
+            .iter()
+            .map(|(source, zone)| (source.as_str(), zone.name.as_str()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("example.com.zone", "example.com"),
+            ("internal/example.net.zone", "example.net"),
+            ("example.org.json", "example.org"),
+        ]
+    );
+
+    // a zone can't come from two places
+    std::fs::write(
+        dir.join("internal/example.com.zone"),
+        "www 60 IN A 192.0.2.4\n",
+    )
+    .expect("Failed to write file");
+    let err = read_zone_files(&dir, false).expect_err("Duplicate zone was accepted");
+    assert!(format!("{err:?}").contains("example.com is in both"));
+
+    // and broken files say which file they are
+    std::fs::write(dir.join("internal/example.com.zone"), "@ SOA broken\n")
+        .expect("Failed to write file");
+    let err = read_zone_files(&dir, false).expect_err("Broken zone was accepted");
+    assert!(format!("{err:?}").contains("internal/example.com.zone"));
+
+    std::fs::remove_dir_all(&dir).expect("Failed to clean up");
+}
+
+#[tokio::test]
+async fn test_sync_zones() {
+    let pool = test_get_sqlite_memory().await;
+    start_db(&pool).await.expect("Failed to start DB");
+    let store = SqliteStore::new(pool.clone());
+    let policy = ImportTtlPolicy::default();
+    let get_zone = |name: &'static str| {
+        let pool = pool.clone();
+        async move {
+            let mut conn = pool.acquire().await.expect("Failed to get connection");
+            get_zone_with_txn(&mut conn, None, Some(name.to_string()))
+                .await
+                .expect("Failed to get zone")
+        }
+    };
+
+    // leaving the serial out means one's picked
+    let zones = vec![
+        (
+            "example.com.zone".to_string(),
+            example_zone("example.com", 0, "192.0.2.1"),
+        ),
+        (
+            "example.net.zone".to_string(),
+            example_zone("example.net", 5, "192.0.2.2"),
+        ),
+    ];
+    let summary = store
+        .sync_zones(zones.clone(), &policy, false)
+        .await
+        .expect("Failed to sync");
+    assert_eq!(summary.updated, vec!["example.com", "example.net"]);
+    let zone = get_zone("example.com").await.expect("Zone wasn't created");
+    assert_eq!(zone.serial, default_serial());
+    assert_eq!(zone.records.len(), 2);
+    let serial = zone.serial;
+    let zoneid = zone.id.expect("Zone has no id");
+
+    // they're read-only now
+    let mut conn = pool.acquire().await.expect("Failed to get connection");
+    let err = SyncedZone::check_editable(&mut conn, zoneid)
+        .await
+        .expect_err("Synced zone was editable");
+    assert!(format!("{err:?}").contains("example.com.zone"));
+    drop(conn);
+
+    // nothing's changed, so nothing happens
+    let summary = store
+        .sync_zones(zones, &policy, false)
+        .await
+        .expect("Failed to sync");
+    assert!(summary.is_empty());
+    assert_eq!(summary.unchanged, 2);
+
+    // a change moves the serial on, and the zone that's gone is left to be edited
+    let summary = store
+        .sync_zones(
+            vec![(
+                "example.com.zone".to_string(),
+                example_zone("example.com", 0, "192.0.2.10"),
+            )],
+            &policy,
+            false,
+        )
+        .await
+        .expect("Failed to sync");
+    assert_eq!(summary.updated, vec!["example.com"]);
+    assert_eq!(summary.released, vec!["example.net"]);
+    let zone = get_zone("example.com").await.expect("Zone went away");
+    assert_eq!(zone.id, Some(zoneid));
+    assert_eq!(zone.serial, next_serial(serial));
+    assert!(zone
+        .records
+        .iter()
+        .any(|record| record.rdata == "192.0.2.10"));
+    let released = get_zone("example.net").await.expect("Zone was deleted");
+    let mut conn = pool.acquire().await.expect("Failed to get connection");
+    SyncedZone::check_editable(&mut conn, released.id.expect("Zone has no id"))
+        .await
+        .expect("Released zone isn't editable");
+    drop(conn);
+
+    // with prune set, zones go when their files do
+    let summary = store
+        .sync_zones(vec![], &policy, true)
+        .await
+        .expect("Failed to sync");
+    assert_eq!(summary.deleted, vec!["example.com"]);
+    assert!(get_zone("example.com").await.is_none());
+    assert!(get_zone("example.net").await.is_some());
+}
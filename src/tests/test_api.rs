@@ -6,7 +6,7 @@ use crate::error::GoatNsError;
 use crate::servers::{self, Servers};
 use crate::web::api::auth::AuthPayload;
 use crate::web::utils::{create_api_token, ApiToken};
-use crate::zones::{FileZone, FileZoneRecord};
+use crate::zones::{next_serial, FileZone, FileZoneRecord};
 use concread::cowcell::asynch::CowCell;
 use sqlx::SqlitePool;
 use tokio::net::TcpStream;
@@ -16,6 +16,13 @@ pub async fn is_free_port(port: u16) -> bool {
 }
 
 pub async fn start_test_server() -> (SqlitePool, Servers, CowCell<ConfigFile>) {
+    start_test_server_with_record_cache(0).await
+}
+
+/// Same as [start_test_server], with the datastore caching lookups for `record_cache_seconds`
+pub async fn start_test_server_with_record_cache(
+    record_cache_seconds: u64,
+) -> (SqlitePool, Servers, CowCell<ConfigFile>) {
     let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
     let pool = test_get_sqlite_memory().await;
 
@@ -36,9 +43,16 @@ pub async fn start_test_server() -> (SqlitePool, Servers, CowCell<ConfigFile>) {
         }
         port = rng.gen_range(2000..=65000);
     }
+    // so tests that query the DNS server get their own
+    let mut dns_port: u16 = rng.gen_range(2000..=65000);
+    while dns_port == port || !is_free_port(dns_port).await {
+        dns_port = rng.gen_range(2000..=65000);
+    }
 
     let mut config_tx = config.write().await;
     config_tx.api_port = port;
+    config_tx.port = dns_port;
+    config_tx.record_cache_seconds = record_cache_seconds;
     config_tx.commit();
 
     // println!("Starting channels");
@@ -55,8 +69,15 @@ pub async fn start_test_server() -> (SqlitePool, Servers, CowCell<ConfigFile>) {
         agent_sender.clone(),
     ));
     // start all the things!
-    let datastore_manager =
-        tokio::spawn(crate::datastore::manager(datastore_rx, pool.clone(), None));
+    let datastore_manager = tokio::spawn(crate::datastore::manager(
+        datastore_rx,
+        crate::datastore::SqliteStore::new(pool.clone())
+            .with_record_cache(std::time::Duration::from_secs(record_cache_seconds)),
+        None,
+        None,
+        None,
+        4,
+    ));
 
     println!("Starting API Server on port {port}");
     let apiserver = crate::web::build(datastore_tx.clone(), config.read(), pool.clone())
@@ -91,7 +112,7 @@ pub async fn insert_test_user(pool: &SqlitePool) -> Box<User> {
 }
 
 /// Shoves an API token into the DB for a user
-async fn insert_test_user_api_token(pool: &SqlitePool, userid: i64) -> Result<ApiToken, ()> {
+pub async fn insert_test_user_api_token(pool: &SqlitePool, userid: i64) -> Result<ApiToken, ()> {
     println!("creating test token for user {userid:?}");
     let token = create_api_token("lols".as_bytes(), 900, userid);
 
@@ -103,6 +124,7 @@ async fn insert_test_user_api_token(pool: &SqlitePool, userid: i64) -> Result<Ap
         tokenkey: token.token_key.to_owned(),
         tokenhash: token.token_hash.to_owned(),
         userid,
+        last_used: None,
     }
     .save(&pool)
     .await
@@ -182,6 +204,113 @@ async fn api_zone_create() -> Result<(), GoatNsError> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+/// zones created through the API can start from a template, and its records get checked like the rest
+async fn api_zone_create_from_template() -> Result<(), GoatNsError> {
+    use crate::zones::ZoneTemplate;
+
+    let (pool, _servers, config) = start_test_server().await;
+    let api_port = config.read().api_port;
+    let default_ttl = config.read().import_ttl.default_ttl;
+    let user = insert_test_user(&pool).await;
+    let token = insert_test_user_api_token(&pool, user.id.expect("no user id found"))
+        .await
+        .expect("Failed to create API token");
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .cookie_store(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .expect("Failed to build client");
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/login"))
+        .json(&AuthPayload {
+            token_key: token.token_key,
+            token_secret: token.token_secret.to_owned(),
+        })
+        .send()
+        .await
+        .expect("Failed to log in");
+    assert_eq!(res.status(), 200);
+
+    let www = FileZoneRecord {
+        id: None,
+        zoneid: None,
+        name: "www".to_string(),
+        rrtype: RecordType::CNAME.to_string(),
+        class: crate::enums::RecordClass::Internet,
+        rdata: "{zone}".to_string(),
+        ttl: 0,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    };
+    let template = ZoneTemplate {
+        id: None,
+        name: "web hosting".to_string(),
+        description: "www".to_string(),
+        records: vec![www.clone()],
+    }
+    .save(&pool)
+    .await?;
+    let template_id = template.id.expect("no template id");
+
+    let create = |name: &str, records: Vec<FileZoneRecord>, template_id: i64| {
+        let mut body = serde_json::to_value(FileZone {
+            name: name.to_string(),
+            rname: format!("bob@{name}"),
+            records,
+            ..Default::default()
+        })
+        .expect("Failed to serialize zone");
+        body["template_id"] = template_id.into();
+        client
+            .post(format!("https://localhost:{api_port}/api/zone"))
+            .json(&body)
+            .send()
+    };
+
+    let res = create("templated.goat", vec![], template_id)
+        .await
+        .expect("Failed to create zone");
+    assert_eq!(res.status(), 200);
+    let zone: FileZone = res.json().await.expect("Failed to parse zone");
+    let record = zone
+        .records
+        .iter()
+        .find(|record| record.rrtype == "CNAME")
+        .expect("The template's record wasn't added");
+    assert!(record.rdata.starts_with("templated.goat"));
+    // it gets the default TTL like any other record that leaves it out
+    assert_eq!(record.ttl, default_ttl);
+
+    // a CNAME can't share a name with anything else, even when the other one's from the template
+    let clash = FileZoneRecord {
+        rrtype: RecordType::A.to_string(),
+        rdata: "1.2.3.4".to_string(),
+        ..www
+    };
+    let res = create("clashing.goat", vec![clash], template_id)
+        .await
+        .expect("Failed to create zone");
+    assert_eq!(res.status(), 400);
+
+    let res = create("missing.goat", vec![], template_id + 1000)
+        .await
+        .expect("Failed to create zone");
+    assert_eq!(res.status(), 400);
+
+    let mut conn = pool.acquire().await?;
+    assert!(FileZone::get_by_name(&mut *conn, "clashing.goat")
+        .await?
+        .is_none());
+    assert!(FileZone::get_by_name(&mut *conn, "missing.goat")
+        .await?
+        .is_none());
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn api_zone_create_delete() -> Result<(), sqlx::Error> {
     // here we stand up the servers
@@ -381,6 +510,7 @@ async fn api_record_create() -> Result<(), GoatNsError> {
         serial: 12345,
         expire: 30,
         minimum: 1235,
+        default_ttl: Some(600),
         ..Default::default()
     }
     .save(&pool)
@@ -404,6 +534,9 @@ async fn api_record_create() -> Result<(), GoatNsError> {
         rrtype: RecordType::A.to_string(),
         ttl: 33,
         rdata: "1.2.3.4".to_string(),
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
     };
     println!("Sending record create");
     let res = client
@@ -420,6 +553,27 @@ async fn api_record_create() -> Result<(), GoatNsError> {
         .await
         .inspect_err(|err| eprintln!("Failed to get response content: {err:?}"))?;
     assert_eq!(response_record.name, "doggo");
+    assert_eq!(response_record.ttl, 33);
+
+    // leaving the TTL out gets the zone's default
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/record"))
+        .header("Authorization", format!("Bearer {}", token.token_secret))
+        .json(&FileZoneRecord {
+            id: None,
+            name: "kitteh".to_string(),
+            ttl: 0,
+            ..fzr
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let response_record: FileZoneRecord = res
+        .json()
+        .await
+        .inspect_err(|err| eprintln!("Failed to get response content: {err:?}"))?;
+    assert_eq!(response_record.ttl, 600);
     drop(pool);
     Ok(())
 }
@@ -498,6 +652,9 @@ async fn api_record_delete() -> Result<(), GoatNsError> {
         rrtype: RecordType::A.to_string(),
         ttl: 33,
         rdata: "1.2.3.4".to_string(),
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
     }
     .save(&pool)
     .await?;
@@ -520,3 +677,1214 @@ async fn api_record_delete() -> Result<(), GoatNsError> {
     drop(pool);
     Ok(())
 }
+
+/// Ask the test server's DNS listener for a name's address, with a new resolver each time so it's not cached there
+async fn lookup_a(dns_port: u16, name: &str) -> Option<String> {
+    use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+    use trust_dns_resolver::AsyncResolver;
+
+    let mut resolver_config = ResolverConfig::new();
+    resolver_config.add_name_server(NameServerConfig::new(
+        std::net::SocketAddr::new(std::net::Ipv4Addr::LOCALHOST.into(), dns_port),
+        Protocol::Udp,
+    ));
+    let resolver = AsyncResolver::tokio(resolver_config, ResolverOpts::default());
+    let res = resolver.lookup_ip(name).await.ok()?;
+    res.iter().next().map(|ip| ip.to_string())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+/// records changed through the API don't keep getting answered from the cache
+async fn api_record_update_purges_cache() -> Result<(), GoatNsError> {
+    let (pool, _servers, config) = start_test_server_with_record_cache(300).await;
+    let api_port = config.read().api_port;
+    let user = insert_test_user(&pool).await;
+    let token = insert_test_user_api_token(&pool, user.id.expect("no user id found"))
+        .await
+        .expect("Failed to create API token");
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .cookie_store(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .expect("Failed to build client");
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/login"))
+        .json(&AuthPayload {
+            token_key: token.token_key,
+            token_secret: token.token_secret.to_owned(),
+        })
+        .send()
+        .await
+        .expect("Failed to log in");
+    assert_eq!(res.status(), 200);
+
+    FileZone {
+        id: Some(555),
+        name: "cached.goat".to_string(),
+        rname: "bob@cached.goat".to_string(),
+        serial: 12345,
+        ..Default::default()
+    }
+    .save(&pool)
+    .await?;
+    ZoneOwnership {
+        id: None,
+        userid: user.id.expect("no user id found"),
+        zoneid: 555,
+    }
+    .save(&pool)
+    .await?;
+    let record = FileZoneRecord {
+        id: None,
+        class: crate::enums::RecordClass::Internet,
+        name: "www".to_string(),
+        zoneid: Some(555),
+        rrtype: RecordType::A.to_string(),
+        ttl: 300,
+        rdata: "1.2.3.4".to_string(),
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    }
+    .save(&pool)
+    .await?;
+
+    let dns_port = config.read().port;
+    // the first one fills the cache
+    assert_eq!(
+        lookup_a(dns_port, "www.cached.goat.").await.as_deref(),
+        Some("1.2.3.4")
+    );
+
+    let res = client
+        .put(&format!("https://localhost:{api_port}/api/record"))
+        .json(&FileZoneRecord {
+            rdata: "5.6.7.8".to_string(),
+            ..(*record).clone()
+        })
+        .send()
+        .await
+        .expect("Failed to update record");
+    assert_eq!(res.status(), 200);
+    assert_eq!(
+        lookup_a(dns_port, "www.cached.goat.").await.as_deref(),
+        Some("5.6.7.8")
+    );
+
+    let res = client
+        .delete(&format!(
+            "https://localhost:{api_port}/api/record/{}",
+            record.id.expect("no record id")
+        ))
+        .send()
+        .await
+        .expect("Failed to delete record");
+    assert_eq!(res.status(), 200);
+    assert_eq!(lookup_a(dns_port, "www.cached.goat.").await, None);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+/// approving a change-set lets anyone watching the zone know it's changed
+async fn api_changeset_approve_publishes_event() -> Result<(), GoatNsError> {
+    use crate::zone_events::{subscribe, ZoneEvent};
+    use crate::zones::{RecordChange, ZoneChangeSet};
+
+    let (pool, _servers, config) = start_test_server().await;
+    let api_port = config.read().api_port;
+    let user = insert_test_user(&pool).await;
+    let token = insert_test_user_api_token(&pool, user.id.expect("no user id found"))
+        .await
+        .expect("Failed to create API token");
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .cookie_store(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .expect("Failed to build client");
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/login"))
+        .json(&AuthPayload {
+            token_key: token.token_key,
+            token_secret: token.token_secret.to_owned(),
+        })
+        .send()
+        .await
+        .expect("Failed to log in");
+    assert_eq!(res.status(), 200);
+
+    FileZone {
+        id: Some(666),
+        name: "review.goat".to_string(),
+        rname: "bob@review.goat".to_string(),
+        serial: 12345,
+        review_required: true,
+        ..Default::default()
+    }
+    .save(&pool)
+    .await?;
+    ZoneOwnership {
+        id: None,
+        userid: user.id.expect("no user id found"),
+        zoneid: 666,
+    }
+    .save(&pool)
+    .await?;
+
+    let res = client
+        .post(&format!(
+            "https://localhost:{api_port}/api/zone/666/changesets"
+        ))
+        .json(&serde_json::json!({
+            "description": "add www",
+            "changes": [RecordChange::Create(FileZoneRecord {
+                id: None,
+                class: crate::enums::RecordClass::Internet,
+                name: "www".to_string(),
+                zoneid: Some(666),
+                rrtype: RecordType::A.to_string(),
+                ttl: 300,
+                rdata: "1.2.3.4".to_string(),
+                comment: None,
+                tags: Default::default(),
+                expires_at: None,
+            })],
+        }))
+        .send()
+        .await
+        .expect("Failed to propose change-set");
+    assert_eq!(res.status(), 200);
+    let changeset: ZoneChangeSet = res.json().await.expect("Failed to parse change-set");
+
+    let mut events = subscribe();
+    let res = client
+        .post(&format!(
+            "https://localhost:{api_port}/api/changeset/{}/approve",
+            changeset.id.expect("no change-set id")
+        ))
+        .send()
+        .await
+        .expect("Failed to approve change-set");
+    assert_eq!(res.status(), 200);
+
+    let event = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        // other tests are changing zones too
+        loop {
+            let event = events.recv().await.expect("Failed to get event");
+            if event.zoneid() == 666 {
+                return event;
+            }
+        }
+    })
+    .await
+    .expect("Didn't hear about the change-set");
+    let serial = FileZone::get_serial_with_txn(666, &mut *pool.acquire().await?).await?;
+    assert_eq!(
+        event,
+        ZoneEvent::SerialBumped {
+            zoneid: 666,
+            serial
+        }
+    );
+    assert_ne!(serial, 12345);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_zone_get_etag() -> Result<(), GoatNsError> {
+    let (pool, _servers, config) = start_test_server().await;
+    let api_port = config.read().api_port;
+    let user = insert_test_user(&pool).await;
+    let token = insert_test_user_api_token(&pool, user.id.expect("no user id found"))
+        .await
+        .expect("Failed to create API token");
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .cookie_store(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .expect("Failed to build client");
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/login"))
+        .json(&AuthPayload {
+            token_key: token.token_key,
+            token_secret: token.token_secret.to_owned(),
+        })
+        .send()
+        .await
+        .expect("Failed to log in");
+    assert_eq!(res.status(), 200);
+
+    FileZone {
+        id: Some(444),
+        name: "etag.goat".to_string(),
+        rname: "bob@etag.goat".to_string(),
+        serial: 12345,
+        ..Default::default()
+    }
+    .save(&pool)
+    .await?;
+    ZoneOwnership {
+        id: None,
+        userid: user.id.expect("no user id found"),
+        zoneid: 444,
+    }
+    .save(&pool)
+    .await?;
+
+    let zone_url = format!("https://localhost:{api_port}/api/zone/444");
+    let res = client
+        .get(&zone_url)
+        .send()
+        .await
+        .expect("Failed to get zone");
+    assert_eq!(res.status(), 200);
+    let etag = res
+        .headers()
+        .get("etag")
+        .and_then(|val| val.to_str().ok())
+        .expect("No ETag on the zone")
+        .to_string();
+    assert_eq!(etag, "W/\"zone-444-12345\"");
+
+    let res = client
+        .get(&zone_url)
+        .header("If-None-Match", &etag)
+        .send()
+        .await
+        .expect("Failed to get zone");
+    assert_eq!(res.status(), 304);
+
+    // adding a record moves the serial on, so the old ETag's stale
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/record"))
+        .json(&FileZoneRecord {
+            id: None,
+            class: crate::enums::RecordClass::Internet,
+            name: "www".to_string(),
+            zoneid: Some(444),
+            rrtype: RecordType::A.to_string(),
+            ttl: 300,
+            rdata: "192.0.2.1".to_string(),
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        })
+        .send()
+        .await
+        .expect("Failed to create record");
+    assert_eq!(res.status(), 200);
+    let record: FileZoneRecord = res.json().await.expect("Failed to parse record");
+
+    let res = client
+        .get(&zone_url)
+        .header("If-None-Match", &etag)
+        .send()
+        .await
+        .expect("Failed to get zone");
+    assert_eq!(res.status(), 200);
+    let zone: FileZone = res.json().await.expect("Failed to parse zone");
+    assert_eq!(zone.serial, next_serial(12345));
+
+    let res = client
+        .get(&format!(
+            "https://localhost:{api_port}/api/record/{}",
+            record.id.expect("Record had no ID")
+        ))
+        .header("If-None-Match", "W/\"something-else\", *")
+        .send()
+        .await
+        .expect("Failed to get record");
+    assert_eq!(res.status(), 304);
+
+    drop(pool);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_error_has_request_id() -> Result<(), GoatNsError> {
+    let (pool, _servers, config) = start_test_server().await;
+    let api_port = config.read().api_port;
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .unwrap();
+
+    // not logged in, so this fails
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/zone"))
+        .header("x-request-id", "goat-request-1234")
+        .json(&FileZone::default())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 403);
+    assert_eq!(
+        res.headers()
+            .get("x-request-id")
+            .and_then(|val| val.to_str().ok()),
+        Some("goat-request-1234")
+    );
+    let error: crate::web::api::ErrorResult = res.json().await.unwrap();
+    assert_eq!(error.request_id, Some("goat-request-1234".to_string()));
+    assert_eq!(error.code, "forbidden");
+    assert!(!error.message.is_empty());
+
+    // junk IDs get replaced
+    let res = client
+        .get(&format!("https://localhost:{api_port}/status"))
+        .header("x-request-id", "not a valid id!")
+        .send()
+        .await
+        .unwrap();
+    let request_id = res
+        .headers()
+        .get("x-request-id")
+        .and_then(|val| val.to_str().ok())
+        .unwrap()
+        .to_string();
+    assert_ne!(request_id, "not a valid id!");
+    assert_eq!(request_id.len(), 32);
+    drop(pool);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn errors_are_consistent() -> Result<(), GoatNsError> {
+    let (pool, _servers, config) = start_test_server().await;
+    let api_port = config.read().api_port;
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .unwrap();
+
+    // no such endpoint
+    let res = client
+        .get(&format!("https://localhost:{api_port}/api/nope"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+    let error: crate::web::api::ErrorResult = res.json().await.unwrap();
+    assert_eq!(error.code, "not_found");
+    assert!(error.request_id.is_some());
+
+    // the extractor rejects it before our code sees it
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/login"))
+        .header("content-type", "application/json")
+        .body("this isn't json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 400);
+    let error: crate::web::api::ErrorResult = res.json().await.unwrap();
+    assert_eq!(error.code, "bad_request");
+    assert!(error.message.contains("JSON"));
+
+    // bad credentials
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/login"))
+        .json(&AuthPayload {
+            token_key: "GAnope".to_string(),
+            token_secret: "goatns_nope".to_string(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 401);
+    let error: crate::web::api::ErrorResult = res.json().await.unwrap();
+    assert_eq!(error.code, "unauthorized");
+
+    // browsers get a page
+    let res = client
+        .get(&format!("https://localhost:{api_port}/nope"))
+        .header("accept", "text/html,application/xhtml+xml")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+    assert!(res
+        .headers()
+        .get("content-type")
+        .and_then(|val| val.to_str().ok())
+        .is_some_and(|val| val.starts_with("text/html")));
+    let request_id = res
+        .headers()
+        .get("x-request-id")
+        .and_then(|val| val.to_str().ok())
+        .unwrap()
+        .to_string();
+    let body = res.text().await.unwrap();
+    assert!(body.contains("404 Not Found"));
+    assert!(body.contains(&request_id));
+
+    // and everything else is left alone
+    let res = client
+        .get(&format!("https://localhost:{api_port}/nope"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+    assert!(res.text().await.unwrap().is_empty());
+
+    drop(pool);
+    Ok(())
+}
+
+#[test]
+fn test_error_status_codes() {
+    use crate::web::api::error_code;
+    use crate::web::middleware::error_pages::{error_format, ErrorFormat};
+    use axum::http::{HeaderMap, HeaderValue, StatusCode};
+
+    assert_eq!(
+        GoatNsError::InvalidValue("nope".to_string()).status_code(),
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        GoatNsError::InvalidValue("nope".to_string()).user_message(),
+        "nope"
+    );
+    assert_eq!(
+        GoatNsError::Csrf("expired".to_string()).status_code(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        GoatNsError::SqlxError(sqlx::Error::RowNotFound).status_code(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        GoatNsError::Oidc("down".to_string()).status_code(),
+        StatusCode::BAD_GATEWAY
+    );
+    // internal details stay internal
+    let err = GoatNsError::Generic("the database is on fire".to_string());
+    assert_eq!(err.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(!err.user_message().contains("fire"));
+
+    assert_eq!(error_code(StatusCode::NOT_FOUND), "not_found");
+    assert_eq!(
+        error_code(StatusCode::TOO_MANY_REQUESTS),
+        "too_many_requests"
+    );
+    assert_eq!(
+        error_code(StatusCode::from_u16(599).expect("Failed to make status")),
+        "http_599"
+    );
+
+    let mut headers = HeaderMap::new();
+    assert_eq!(error_format("/api/zone", &headers), ErrorFormat::Json);
+    assert_eq!(error_format("/apiary", &headers), ErrorFormat::Unchanged);
+    assert_eq!(
+        error_format("/ui/zones/1", &headers),
+        ErrorFormat::Unchanged
+    );
+    headers.insert("accept", HeaderValue::from_static("text/html,*/*;q=0.8"));
+    assert_eq!(error_format("/ui/zones/1", &headers), ErrorFormat::Html);
+    assert_eq!(error_format("/api/zone", &headers), ErrorFormat::Json);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_zone_import() -> Result<(), GoatNsError> {
+    let (pool, _servers, config) = start_test_server().await;
+    let api_port = config.read().api_port;
+    let user = insert_test_user(&pool).await;
+    let token = insert_test_user_api_token(&pool, user.id.expect("no user id found"))
+        .await
+        .unwrap();
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .cookie_store(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .unwrap();
+
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/login"))
+        .json(&AuthPayload {
+            token_key: token.token_key,
+            token_secret: token.token_secret.to_owned(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let import = serde_json::json!({
+        "zones": [{
+            "name": "imported.goat",
+            "records": [
+                {"name": "www", "rrtype": "A", "rdata": "192.0.2.1", "ttl": 300},
+                {"name": "@", "rrtype": "TXT", "rdata": "hello"}
+            ]
+        }],
+        "strategy": "merge",
+        "dry_run": true
+    });
+
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/zone/import"))
+        .json(&import)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let summary: crate::zones::ImportSummary = res.json().await.unwrap();
+    assert!(summary.dry_run);
+    assert_eq!(summary.zones, 1);
+    // new zones get the default NS records too
+    assert!(summary.imported >= 2);
+    assert!(summary.adjusted >= 1);
+
+    let mut txn = pool.begin().await?;
+    assert!(FileZone::get_by_name(&mut txn, "imported.goat")
+        .await?
+        .is_none());
+    drop(txn);
+
+    let mut import = import;
+    import["dry_run"] = serde_json::Value::Bool(false);
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/zone/import"))
+        .json(&import)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let summary: crate::zones::ImportSummary = res.json().await.unwrap();
+    assert!(!summary.dry_run);
+    let imported = summary.imported;
+
+    // doing it again with merge shouldn't add anything
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/zone/import"))
+        .json(&import)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let summary: crate::zones::ImportSummary = res.json().await.unwrap();
+    assert_eq!(summary.imported, 0);
+    assert_eq!(summary.unchanged, 2);
+
+    let mut txn = pool.begin().await?;
+    let zone = crate::db::get_zone_with_txn(&mut txn, None, Some("imported.goat".to_string()))
+        .await?
+        .expect("zone wasn't imported");
+    assert_eq!(zone.records.len(), imported);
+    assert!(ZoneOwnership::get_ownership_by_userid(
+        &mut txn,
+        &user.id.expect("no user id"),
+        &zone.id.expect("no zone id")
+    )
+    .await?
+    .is_some());
+    drop(txn);
+    drop(pool);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_zone_diff() -> Result<(), GoatNsError> {
+    use crate::zones::ZoneDiff;
+
+    let (pool, _servers, config) = start_test_server().await;
+    let api_port = config.read().api_port;
+    let user = insert_test_user(&pool).await;
+    let token = insert_test_user_api_token(&pool, user.id.expect("no user id found"))
+        .await
+        .unwrap();
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .cookie_store(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .unwrap();
+
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/login"))
+        .json(&AuthPayload {
+            token_key: token.token_key,
+            token_secret: token.token_secret.to_owned(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/zone/import"))
+        .json(&serde_json::json!({
+            "zones": [{
+                "name": "diff.goat",
+                "records": [
+                    {"name": "www", "rrtype": "A", "rdata": "192.0.2.1", "ttl": 300},
+                    {"name": "mail", "rrtype": "A", "rdata": "192.0.2.2", "ttl": 300},
+                    {"name": "@", "rrtype": "TXT", "rdata": "hello", "ttl": 300}
+                ]
+            }]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let mut txn = pool.begin().await?;
+    let zone = crate::db::get_zone_with_txn(&mut txn, None, Some("diff.goat".to_string()))
+        .await?
+        .expect("zone wasn't imported");
+    drop(txn);
+    let zoneid = zone.id.expect("no zone id");
+    let url = format!("https://localhost:{api_port}/api/zone/{zoneid}/diff");
+
+    let res = client
+        .post(&url)
+        .header("Content-Type", "text/dns")
+        .body("$TTL 300\nwww IN A 192.0.2.1\nmail 600 IN A 192.0.2.2\nnew IN A 192.0.2.3\n")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let diff: ZoneDiff = res.json().await.unwrap();
+    assert!(diff.soa.is_empty());
+    assert_eq!(diff.unchanged, 1);
+    assert_eq!(diff.added.len(), 1);
+    assert_eq!(diff.added[0].rdata, "192.0.2.3");
+    assert_eq!(diff.updated.len(), 1);
+    assert_eq!(diff.updated[0].proposed.ttl, 600);
+    // the default NS records go too, since they're not in the proposal
+    let removed: Vec<&str> = diff
+        .removed
+        .iter()
+        .filter(|record| record.rrtype != "NS")
+        .map(|record| record.rdata.as_str())
+        .collect();
+    assert_eq!(removed, vec!["hello"]);
+
+    // nothing's actually changed
+    let mut txn = pool.begin().await?;
+    let after = crate::db::get_zone_with_txn(&mut txn, None, Some("diff.goat".to_string()))
+        .await?
+        .expect("zone went away");
+    drop(txn);
+    assert_eq!(after.records.len(), zone.records.len());
+
+    let res = client
+        .post(&url)
+        .json(&serde_json::json!({"name": "other.goat", "records": []}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 400);
+
+    let res = client
+        .post(&format!(
+            "https://localhost:{api_port}/api/zone/{}/diff",
+            zoneid + 1000
+        ))
+        .json(&serde_json::json!({"name": "diff.goat", "records": []}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+    drop(pool);
+    Ok(())
+}
+
+#[test]
+fn test_rate_limiter_windows() {
+    use crate::web::middleware::rate_limit::RateLimiter;
+    use std::time::{Duration, Instant};
+
+    let limiter = RateLimiter::default();
+    let window = Duration::from_secs(60);
+    let now = Instant::now();
+
+    for expected in (0..3).rev() {
+        let status = limiter.check("ip:192.0.2.1", 3, window, now);
+        assert!(!status.limited);
+        assert_eq!(status.remaining, expected);
+    }
+    let status = limiter.check("ip:192.0.2.1", 3, window, now + Duration::from_secs(15));
+    assert!(status.limited);
+    assert_eq!(status.remaining, 0);
+    assert_eq!(status.reset, 45);
+    // someone else has their own budget
+    assert!(!limiter.check("ip:192.0.2.2", 3, window, now).limited);
+    // and it's topped back up once the window's done
+    let status = limiter.check("ip:192.0.2.1", 3, window, now + window);
+    assert!(!status.limited);
+    assert_eq!(status.remaining, 2);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_rate_limit_headers() -> Result<(), GoatNsError> {
+    let (pool, _servers, config) = start_test_server().await;
+    let api_port = config.read().api_port;
+    let per_ip = config.read().rate_limits.per_ip;
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .unwrap();
+
+    let res = client
+        .get(&format!("https://localhost:{api_port}/api/zone/1"))
+        .send()
+        .await
+        .unwrap();
+    let header = |name: &str| {
+        res.headers()
+            .get(name)
+            .and_then(|val| val.to_str().ok())
+            .map(|val| val.to_string())
+    };
+    assert_eq!(header("ratelimit-limit"), Some(per_ip.to_string()));
+    assert_eq!(
+        header("ratelimit-remaining"),
+        Some((per_ip - 1).to_string())
+    );
+    assert!(header("ratelimit-reset").is_some());
+
+    // only /api and /auth are limited
+    let res = client
+        .get(&format!("https://localhost:{api_port}/status"))
+        .send()
+        .await
+        .unwrap();
+    assert!(res.headers().get("ratelimit-limit").is_none());
+    drop(pool);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_token_rotate() -> Result<(), GoatNsError> {
+    use crate::web::api::apitoken::{ApiTokenSecret, ApiTokenSummary};
+
+    let (pool, _servers, config) = start_test_server().await;
+    let api_port = config.read().api_port;
+
+    let user = insert_test_user(&pool).await;
+    let token = insert_test_user_api_token(&pool, user.id.expect("no user id found"))
+        .await
+        .unwrap();
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .cookie_store(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .unwrap();
+    let login = |secret: String| {
+        client
+            .post(&format!("https://localhost:{api_port}/api/login"))
+            .json(&AuthPayload {
+                token_key: token.token_key.clone(),
+                token_secret: secret,
+            })
+            .send()
+    };
+    assert_eq!(
+        login(token.token_secret.clone()).await.unwrap().status(),
+        200
+    );
+
+    let res = client
+        .get(&format!("https://localhost:{api_port}/api/tokens"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let body = res.text().await.unwrap();
+    assert!(!body.contains("tokenhash"));
+    let tokens: Vec<ApiTokenSummary> = serde_json::from_str(&body).unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].tokenkey, token.token_key);
+    // logging in counts as using it
+    assert!(tokens[0].last_used.is_some());
+
+    let res = client
+        .post(&format!(
+            "https://localhost:{api_port}/api/token/{}/rotate",
+            tokens[0].id.unwrap()
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let rotated: ApiTokenSecret = res.json().await.unwrap();
+    assert_eq!(rotated.token.id, tokens[0].id);
+    assert_eq!(rotated.token.tokenkey, token.token_key);
+    assert_ne!(rotated.token_secret, token.token_secret);
+
+    // the old secret's done, the new one works
+    assert!(!login(token.token_secret.clone())
+        .await
+        .unwrap()
+        .status()
+        .is_success());
+    assert_eq!(login(rotated.token_secret).await.unwrap().status(), 200);
+
+    // can't rotate tokens that aren't yours
+    let res = client
+        .post(&format!(
+            "https://localhost:{api_port}/api/token/9999/rotate"
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+    drop(pool);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_wizard_caa() -> Result<(), GoatNsError> {
+    let (pool, _servers, config) = start_test_server().await;
+    let api_port = config.read().api_port;
+
+    let user = insert_test_user(&pool).await;
+    let token = insert_test_user_api_token(&pool, user.id.expect("no user id found"))
+        .await
+        .unwrap();
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .cookie_store(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .unwrap();
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/login"))
+        .json(&AuthPayload {
+            token_key: token.token_key,
+            token_secret: token.token_secret,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let res = client
+        .get(&format!(
+            "https://localhost:{api_port}/api/wizard/caa/presets"
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let presets: Vec<serde_json::Value> = res.json().await.unwrap();
+    assert!(presets
+        .iter()
+        .any(|preset| preset["issuer"] == "letsencrypt.org"));
+
+    let zone = FileZone {
+        id: Some(555),
+        name: "caa.goat".to_string(),
+        rname: "bob@caa.goat".to_string(),
+        ..Default::default()
+    }
+    .save(&pool)
+    .await
+    .unwrap();
+    ZoneOwnership {
+        id: None,
+        userid: user.id.expect("no user id found"),
+        zoneid: zone.id.unwrap(),
+    }
+    .save(&pool)
+    .await
+    .unwrap();
+
+    // validating doesn't need a zone, or save anything
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/wizard/caa"))
+        .json(&serde_json::json!({
+            "tag": "issue",
+            "value": "letsencrypt.org",
+            "validate_only": true,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let result: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(result["rdata"], "0 issue \"letsencrypt.org\"");
+    assert!(result.get("record").is_none());
+
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/wizard/caa"))
+        .json(&serde_json::json!({
+            "tag": "iodef",
+            "value": "not a url",
+            "validate_only": true,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 400);
+
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/wizard/caa"))
+        .json(&serde_json::json!({
+            "zoneid": zone.id,
+            "ttl": 300,
+            "critical": true,
+            "tag": "issuewild",
+            "value": ";",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let result: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(result["rdata"], "128 issuewild \";\"");
+    assert_eq!(result["record"]["rrtype"], "CAA");
+    assert_eq!(result["record"]["name"], "@");
+    drop(pool);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn api_wizard_mail() -> Result<(), GoatNsError> {
+    let (pool, _servers, config) = start_test_server().await;
+    let api_port = config.read().api_port;
+
+    let user = insert_test_user(&pool).await;
+    let token = insert_test_user_api_token(&pool, user.id.expect("no user id found"))
+        .await
+        .unwrap();
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .cookie_store(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .unwrap();
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/login"))
+        .json(&AuthPayload {
+            token_key: token.token_key,
+            token_secret: token.token_secret,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    FileZone {
+        id: Some(556),
+        name: "mail.goat".to_string(),
+        rname: "bob@mail.goat".to_string(),
+        ..Default::default()
+    }
+    .save(&pool)
+    .await
+    .unwrap();
+    ZoneOwnership {
+        id: None,
+        userid: user.id.expect("no user id found"),
+        zoneid: 556,
+    }
+    .save(&pool)
+    .await
+    .unwrap();
+
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/wizard/spf"))
+        .json(&serde_json::json!({
+            "mx": true,
+            "include": ["_spf.google.com"],
+            "all": "fail",
+            "validate_only": true,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let result: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(result["rdata"], "v=spf1 mx include:_spf.google.com -all");
+    assert_eq!(result["strings"].as_array().map(|s| s.len()), Some(1));
+    assert!(result.get("record").is_none());
+
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/wizard/dkim"))
+        .json(&serde_json::json!({
+            "selector": "mail",
+            "public_key": "nope",
+            "validate_only": true,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 400);
+
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/wizard/dmarc"))
+        .json(&serde_json::json!({
+            "zoneid": 556,
+            "ttl": 300,
+            "policy": "reject",
+            "rua": ["dmarc@mail.goat"],
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let result: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(
+        result["rdata"],
+        "v=DMARC1; p=reject; rua=mailto:dmarc@mail.goat"
+    );
+    assert_eq!(result["record"]["name"], "_dmarc");
+    assert_eq!(result["record"]["rrtype"], "TXT");
+    drop(pool);
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_lookup() -> Result<(), GoatNsError> {
+    let (pool, _servers, config) = start_test_server().await;
+    let api_port = config.read().api_port;
+
+    let user = insert_test_user(&pool).await;
+    let token = insert_test_user_api_token(&pool, user.id.expect("no user id found"))
+        .await
+        .unwrap();
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .cookie_store(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .unwrap();
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/login"))
+        .json(&AuthPayload {
+            token_key: token.token_key,
+            token_secret: token.token_secret,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    FileZone {
+        id: Some(557),
+        name: "lookup.goat".to_string(),
+        rname: "bob@lookup.goat".to_string(),
+        records: vec![FileZoneRecord {
+            id: None,
+            zoneid: Some(557),
+            name: "www".to_string(),
+            rrtype: RecordType::A.to_string(),
+            class: crate::enums::RecordClass::Internet,
+            rdata: "198.51.100.7".to_string(),
+            ttl: 300,
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        }],
+        ..Default::default()
+    }
+    .save(&pool)
+    .await
+    .unwrap();
+
+    let res = client
+        .get(&format!(
+            "https://localhost:{api_port}/api/lookup?ip=198.51.100.7"
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let result: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(result["reverse_name"], "7.100.51.198.in-addr.arpa");
+    assert_eq!(result["forward"][0]["name"], "www.lookup.goat");
+    assert_eq!(result["reverse"].as_array().map(|r| r.len()), Some(0));
+
+    let res = client
+        .get(&format!(
+            "https://localhost:{api_port}/api/lookup?ip=lookup.goat"
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 400);
+    drop(pool);
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_zone_enabled() -> Result<(), GoatNsError> {
+    let (pool, _servers, config) = start_test_server().await;
+    let api_port = config.read().api_port;
+
+    let user = insert_test_user(&pool).await;
+    let token = insert_test_user_api_token(&pool, user.id.expect("no user id found"))
+        .await
+        .unwrap();
+
+    let client = reqwest::ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .cookie_store(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .unwrap();
+    let res = client
+        .post(&format!("https://localhost:{api_port}/api/login"))
+        .json(&AuthPayload {
+            token_key: token.token_key,
+            token_secret: token.token_secret,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    FileZone {
+        id: Some(558),
+        name: "parked.goat".to_string(),
+        rname: "bob@parked.goat".to_string(),
+        ..Default::default()
+    }
+    .save(&pool)
+    .await
+    .unwrap();
+
+    let res = client
+        .post(&format!(
+            "https://localhost:{api_port}/api/zone/558/enabled"
+        ))
+        .json(&serde_json::json!({"enabled": false}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert!(!FileZone::get(&pool, 558).await?.enabled);
+
+    let res = client
+        .post(&format!(
+            "https://localhost:{api_port}/api/zone/559/enabled"
+        ))
+        .json(&serde_json::json!({"enabled": false}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+    drop(pool);
+    Ok(())
+}
@@ -1,17 +1,24 @@
 use sqlx::{Pool, Sqlite};
 
-use crate::datastore::handle_import_file;
+use crate::datastore::{handle_import_file, SqliteStore};
 use crate::db::{DBEntity, User};
 use crate::error::GoatNsError;
+use crate::interop::ZoneFileFormat;
 
 pub async fn import_test_zone_file(pool: &Pool<Sqlite>) -> Result<(), GoatNsError> {
     println!("#####################################################################");
     println!("importing test zone ./examples/test_config/zones.json");
     println!("#####################################################################");
     handle_import_file(
-        &pool,
+        &SqliteStore::new(pool.clone()),
         "./examples/test_config/zones.json".to_string(),
         Some("hello.goat".to_string()),
+        false,
+        ZoneFileFormat::GoatNs,
+        &Default::default(),
+        Default::default(),
+        false,
+        None,
     )
     .await
     .map_err(|e| GoatNsError::Generic(format!("Failed to import test zones.json: {e:?}")))?;
@@ -2,7 +2,10 @@ use std::str::from_utf8;
 
 use url::Url;
 
-use crate::utils::{check_valid_tld, find_tail_match, loc_size_to_u8, name_as_bytes};
+use crate::utils::{
+    check_valid_tld, enclosing_names, find_tail_match, label_suffix_offset, loc_size_to_u8,
+    name_as_bytes, reverse_name,
+};
 use std::thread::sleep;
 use std::time::Duration;
 
@@ -134,3 +137,47 @@ fn test_test_valid_tld() {
     let zone_name = "hello.example.goat.cheese";
     assert_eq!(check_valid_tld(&zone_name, &valid_tlds), false);
 }
+
+#[test]
+fn test_label_suffix_offset() {
+    assert_eq!(
+        label_suffix_offset(b"www.example.com", b"example.com"),
+        Some(4)
+    );
+    assert_eq!(label_suffix_offset(b"example.com", b"example.com"), Some(0));
+    assert_eq!(
+        label_suffix_offset(b"WWW.Example.COM", b"example.com"),
+        Some(4)
+    );
+    assert_eq!(label_suffix_offset(b"myexample.com", b"example.com"), None);
+    assert_eq!(label_suffix_offset(b"com", b"example.com"), None);
+    assert_eq!(label_suffix_offset(b"example.com", b""), None);
+}
+
+#[test]
+fn test_enclosing_names() {
+    assert_eq!(
+        enclosing_names("www.example.com."),
+        vec!["www.example.com", "example.com", "com"]
+    );
+    assert_eq!(enclosing_names("com"), vec!["com"]);
+    assert!(enclosing_names("").is_empty());
+    assert!(enclosing_names(".").is_empty());
+}
+
+#[test]
+fn test_reverse_name() {
+    assert_eq!(
+        reverse_name(&"192.0.2.10".parse().expect("Failed to parse IP")),
+        "10.2.0.192.in-addr.arpa"
+    );
+    assert_eq!(
+        // the example from RFC3596
+        reverse_name(
+            &"4321:0:1:2:3:4:567:89ab"
+                .parse()
+                .expect("Failed to parse IP")
+        ),
+        "b.a.9.8.7.6.5.0.4.0.0.0.3.0.0.0.2.0.0.0.1.0.0.0.0.0.0.0.1.2.3.4.ip6.arpa"
+    );
+}
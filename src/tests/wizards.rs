@@ -0,0 +1,351 @@
+use crate::error::GoatNsError;
+use crate::resourcerecord::InternalResourceRecord;
+use crate::wizards::caa::{CaaRecord, CaaTag, CA_PRESETS};
+
+fn caa(tag: CaaTag, value: &str) -> CaaRecord {
+    CaaRecord {
+        critical: false,
+        tag,
+        value: value.to_string(),
+    }
+}
+
+#[test]
+fn test_caa_presets_make_valid_records() {
+    for preset in CA_PRESETS {
+        let record = caa(CaaTag::Issue, preset.issuer);
+        assert_eq!(
+            record.rdata().expect("preset should be valid"),
+            format!("0 issue \"{}\"", preset.issuer)
+        );
+        let record = record
+            .to_record(Some(1), "@", 300)
+            .expect("failed to build record");
+        assert!(matches!(
+            InternalResourceRecord::try_from(record),
+            Ok(InternalResourceRecord::CAA { flag: 0, .. })
+        ));
+    }
+}
+
+#[test]
+fn test_caa_rdata() {
+    let mut record = caa(
+        CaaTag::IssueWild,
+        "letsencrypt.org; validationmethods=dns-01",
+    );
+    record.critical = true;
+    assert_eq!(
+        record.rdata().expect("should be valid"),
+        "128 issuewild \"letsencrypt.org; validationmethods=dns-01\""
+    );
+    assert_eq!(
+        caa(CaaTag::Issue, ";").rdata().expect("should be valid"),
+        "0 issue \";\""
+    );
+    assert_eq!(
+        caa(CaaTag::Iodef, "mailto:security@example.com")
+            .rdata()
+            .expect("should be valid"),
+        "0 iodef \"mailto:security@example.com\""
+    );
+    assert!(caa(CaaTag::Iodef, "https://example.com/caa-report")
+        .validate()
+        .is_ok());
+}
+
+#[test]
+fn test_caa_invalid_values() {
+    for (tag, value) in [
+        (CaaTag::Issue, ""),
+        (CaaTag::Issue, "not a domain"),
+        (CaaTag::Issue, "letsencrypt.org; nokey"),
+        (CaaTag::Issue, "letsencrypt.org; bad-key=value"),
+        (CaaTag::Issue, "\"letsencrypt.org\""),
+        (CaaTag::Iodef, "security@example.com"),
+        (CaaTag::Iodef, "mailto:example.com"),
+        (CaaTag::Iodef, "ftp://example.com"),
+    ] {
+        assert!(
+            matches!(
+                caa(tag, value).validate(),
+                Err(GoatNsError::InvalidValue(_))
+            ),
+            "{tag} {value:?} should be invalid"
+        );
+    }
+}
+
+#[test]
+fn test_caa_tag_parsing() {
+    assert_eq!("issue".parse::<CaaTag>().expect("valid"), CaaTag::Issue);
+    assert_eq!(
+        "IssueWild".parse::<CaaTag>().expect("valid"),
+        CaaTag::IssueWild
+    );
+    assert_eq!("iodef".parse::<CaaTag>().expect("valid"), CaaTag::Iodef);
+    assert!("contactemail".parse::<CaaTag>().is_err());
+    assert_eq!(
+        serde_json::to_string(&CaaTag::IssueWild).expect("failed to serialize"),
+        "\"issuewild\""
+    );
+}
+
+#[test]
+fn test_spf_wizard() {
+    use crate::wizards::mail::{SpfAll, SpfRecord};
+    use crate::wizards::TxtWizard;
+
+    let spf = SpfRecord {
+        mx: true,
+        ip4: vec!["192.0.2.0/24".parse().expect("valid network")],
+        ip6: vec!["2001:db8::/32".parse().expect("valid network")],
+        include: vec!["_spf.google.com".to_string()],
+        all: SpfAll::Fail,
+        ..Default::default()
+    };
+    assert_eq!(
+        spf.txt().expect("should be valid"),
+        "v=spf1 mx ip4:192.0.2.0/24 ip6:2001:db8::/32 include:_spf.google.com -all"
+    );
+    let record = spf.to_record(Some(1), None, 300).expect("should build");
+    assert_eq!(record.name, "@");
+    assert_eq!(record.rrtype, "TXT");
+    let record = spf
+        .to_record(Some(1), Some("mail"), 300)
+        .expect("should build");
+    assert_eq!(record.name, "mail");
+
+    let spf = SpfRecord {
+        redirect: Some("_spf.example.com".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        spf.txt().expect("should be valid"),
+        "v=spf1 redirect=_spf.example.com"
+    );
+
+    let too_many = SpfRecord {
+        include: (0..11).map(|n| format!("spf{n}.example.com")).collect(),
+        ..Default::default()
+    };
+    assert!(matches!(
+        too_many.txt(),
+        Err(GoatNsError::InvalidValue(msg)) if msg.contains("10 DNS lookups")
+    ));
+    let bad_include = SpfRecord {
+        include: vec!["not a domain".to_string()],
+        ..Default::default()
+    };
+    assert!(bad_include.txt().is_err());
+    let anyone = SpfRecord {
+        all: SpfAll::Pass,
+        ..Default::default()
+    };
+    assert!(anyone.txt().is_err());
+}
+
+#[test]
+fn test_dkim_wizard() {
+    use crate::wizards::mail::{DkimKeyType, DkimRecord};
+    use crate::wizards::{txt_strings, TxtWizard};
+    use base64::{engine::general_purpose, Engine as _};
+
+    // a 2048-bit RSA public key is about 294 bytes of DER, which makes for a TXT string longer than 255
+    let key = general_purpose::STANDARD.encode([7u8; 294]);
+    let pem = format!(
+        "-----BEGIN PUBLIC KEY-----\n{}\n{}\n-----END PUBLIC KEY-----\n",
+        &key[..64],
+        &key[64..]
+    );
+    let dkim = DkimRecord {
+        selector: "mail".to_string(),
+        key_type: DkimKeyType::Rsa,
+        public_key: pem,
+        testing: true,
+    };
+    let txt = dkim.txt().expect("should be valid");
+    assert_eq!(txt, format!("v=DKIM1; k=rsa; t=y; p={key}"));
+    let strings = txt_strings(&txt);
+    assert_eq!(strings.len(), 2);
+    assert_eq!(strings[0].len(), 255);
+    assert_eq!(strings.concat(), txt);
+    assert_eq!(
+        dkim.to_record(None, None, 300).expect("should build").name,
+        "mail._domainkey"
+    );
+
+    let short = DkimRecord {
+        public_key: general_purpose::STANDARD.encode([7u8; 64]),
+        ..dkim.clone()
+    };
+    assert!(short.txt().is_err());
+    let ed25519 = DkimRecord {
+        key_type: DkimKeyType::Ed25519,
+        public_key: general_purpose::STANDARD.encode([7u8; 32]),
+        testing: false,
+        ..dkim.clone()
+    };
+    assert!(ed25519
+        .txt()
+        .expect("should be valid")
+        .starts_with("v=DKIM1; k=ed25519; p="));
+    let not_base64 = DkimRecord {
+        public_key: "this isn't base64!".to_string(),
+        ..dkim.clone()
+    };
+    assert!(not_base64.txt().is_err());
+    let bad_selector = DkimRecord {
+        selector: "bad selector".to_string(),
+        ..dkim
+    };
+    assert!(bad_selector.txt().is_err());
+}
+
+#[test]
+fn test_dmarc_wizard() {
+    use crate::wizards::mail::{DmarcAlignment, DmarcPolicy, DmarcRecord};
+    use crate::wizards::TxtWizard;
+
+    let dmarc = DmarcRecord {
+        policy: DmarcPolicy::Quarantine,
+        subdomain_policy: Some(DmarcPolicy::Reject),
+        pct: Some(50),
+        rua: vec![
+            "dmarc@example.com".to_string(),
+            "mailto:reports@example.net".to_string(),
+        ],
+        adkim: Some(DmarcAlignment::Strict),
+        ..Default::default()
+    };
+    assert_eq!(
+        dmarc.txt().expect("should be valid"),
+        "v=DMARC1; p=quarantine; sp=reject; pct=50; rua=mailto:dmarc@example.com,mailto:reports@example.net; adkim=s"
+    );
+    assert_eq!(
+        dmarc.to_record(None, None, 300).expect("should build").name,
+        "_dmarc"
+    );
+    assert_eq!(
+        DmarcRecord::default().txt().expect("should be valid"),
+        "v=DMARC1; p=none"
+    );
+    assert!(DmarcRecord {
+        pct: Some(101),
+        ..Default::default()
+    }
+    .txt()
+    .is_err());
+    assert!(DmarcRecord {
+        rua: vec!["not an address".to_string()],
+        ..Default::default()
+    }
+    .txt()
+    .is_err());
+}
+
+#[test]
+fn test_loc_from_decimal() {
+    use crate::resourcerecord::{decimal_to_dms, DecimalLocation};
+    use crate::wizards::loc::to_record;
+
+    let location = DecimalLocation {
+        latitude: -27.4698,
+        longitude: 153.0251,
+        altitude: 30,
+        size: 1,
+        horiz_pre: 10000,
+        vert_pre: 10,
+    };
+    assert_eq!(
+        location.to_rdata().expect("should be valid"),
+        "27 28 11.280 S 153 1 30.360 E 30m 1m 10000m 10m"
+    );
+    let record = to_record(&location, Some(1), "office", 300).expect("failed to build record");
+    assert!(matches!(
+        InternalResourceRecord::try_from(record),
+        Ok(InternalResourceRecord::LOC { .. })
+    ));
+
+    // rounding up to a whole minute carries
+    assert_eq!(decimal_to_dms(59.999_999_99), (60, 0, 0));
+    assert_eq!(decimal_to_dms(-0.5), (0, 30, 0));
+
+    for bad in [
+        DecimalLocation {
+            latitude: 91.0,
+            ..location.clone()
+        },
+        DecimalLocation {
+            longitude: -180.5,
+            ..location.clone()
+        },
+        DecimalLocation {
+            latitude: f64::NAN,
+            ..location.clone()
+        },
+        DecimalLocation {
+            altitude: -100_001,
+            ..location.clone()
+        },
+        DecimalLocation {
+            horiz_pre: 90_000_001,
+            ..location.clone()
+        },
+    ] {
+        assert!(bad.to_rdata().is_err(), "{bad:?}");
+    }
+}
+
+#[test]
+fn test_loc_map_tile() {
+    use crate::resourcerecord::{DecimalLocation, LOC_MAX_PRECISION};
+    use crate::wizards::loc::{
+        check_map_tile_url, map_tile, map_tile_origin, map_zoom, MapTile, DEFAULT_MAP_TILE_URL,
+    };
+
+    let location = DecimalLocation {
+        latitude: -27.4698,
+        longitude: 153.0251,
+        altitude: 0,
+        size: 1,
+        horiz_pre: 10000,
+        vert_pre: 10,
+    };
+    assert_eq!(
+        map_tile(DEFAULT_MAP_TILE_URL, &location),
+        Some(MapTile {
+            url: "https://tile.openstreetmap.org/9/473/296.png".to_string(),
+            zoom: 9,
+            x: 162,
+            y: 168,
+        })
+    );
+    assert_eq!(map_tile("", &location), None);
+    // the poles and the date line stay on the map
+    let tile = map_tile(
+        DEFAULT_MAP_TILE_URL,
+        &DecimalLocation {
+            latitude: 90.0,
+            longitude: 180.0,
+            ..location
+        },
+    )
+    .expect("should get a tile");
+    assert_eq!(tile.url, "https://tile.openstreetmap.org/9/511/0.png");
+
+    assert_eq!(map_zoom(0), 17);
+    assert_eq!(map_zoom(LOC_MAX_PRECISION), 1);
+
+    assert_eq!(
+        map_tile_origin(DEFAULT_MAP_TILE_URL),
+        Some("https://tile.openstreetmap.org".to_string())
+    );
+    assert_eq!(map_tile_origin(""), None);
+    assert!(check_map_tile_url(DEFAULT_MAP_TILE_URL).is_empty());
+    assert!(check_map_tile_url("").is_empty());
+    assert_eq!(
+        check_map_tile_url("ftp://tiles.example.com/{z}/{x}.png").len(),
+        2
+    );
+}
@@ -0,0 +1,51 @@
+use crate::zone_events::{publish, subscribe, ZoneEvent};
+
+#[test]
+fn test_zone_event_json() {
+    let event = ZoneEvent::RecordDeleted {
+        zoneid: 1,
+        serial: 2024010100,
+        id: 5,
+    };
+    assert_eq!(event.zoneid(), 1);
+    assert_eq!(
+        serde_json::to_value(&event).expect("Failed to serialize"),
+        serde_json::json!({"event": "record_deleted", "zoneid": 1, "serial": 2024010100, "id": 5})
+    );
+    let event = ZoneEvent::SerialBumped {
+        zoneid: 1,
+        serial: 5,
+    };
+    assert_eq!(
+        serde_json::to_value(&event).expect("Failed to serialize")["event"],
+        event.kind()
+    );
+}
+
+#[tokio::test]
+async fn test_zone_events_are_broadcast() {
+    // nobody's listening, so it goes nowhere
+    publish(ZoneEvent::SerialBumped {
+        zoneid: -1,
+        serial: 1,
+    });
+
+    let mut first = subscribe();
+    let mut second = subscribe();
+    let event = ZoneEvent::SerialBumped {
+        zoneid: -1,
+        serial: 2,
+    };
+    publish(event.clone());
+
+    for rx in [&mut first, &mut second] {
+        // other tests are changing zones too
+        loop {
+            let received = rx.recv().await.expect("Failed to get event");
+            if received.zoneid() == -1 {
+                assert_eq!(received, event);
+                break;
+            }
+        }
+    }
+}
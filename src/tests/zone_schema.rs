@@ -0,0 +1,238 @@
+use crate::enums::RecordClass;
+use crate::zone_schema::{
+    backup_from_json, backup_to_json, zones_from_json, zones_to_json, ZoneBackup,
+    ZONE_SCHEMA_VERSION,
+};
+use crate::zones::{FileZone, FileZoneRecord, ZoneRegistration};
+
+fn example_zone() -> FileZone {
+    FileZone {
+        id: Some(5),
+        name: "example.com".to_string(),
+        rname: "admin.example.com".to_string(),
+        serial: 2024010101,
+        refresh: 7200,
+        retry: 3600,
+        expire: 1209600,
+        minimum: 300,
+        default_ttl: Some(3600),
+        records: vec![FileZoneRecord {
+            id: Some(12),
+            zoneid: Some(5),
+            name: "www".to_string(),
+            rrtype: "A".to_string(),
+            class: RecordClass::Internet,
+            rdata: "192.0.2.1".to_string(),
+            ttl: 60,
+            comment: Some("the website".to_string()),
+            tags: [("team".to_string(), "web".to_string())].into(),
+            expires_at: None,
+        }],
+        review_required: true,
+        enabled: true,
+        public: true,
+        registration: ZoneRegistration {
+            registrar: Some("Goat Registry".to_string()),
+            expires: chrono::NaiveDate::from_ymd_opt(2030, 1, 31),
+            notes: Some("Renewed by the goats".to_string()),
+        },
+    }
+}
+
+#[test]
+fn test_zone_schema_roundtrip() {
+    let zone = example_zone();
+    let json = zones_to_json(&[zone.clone()]).expect("Failed to export");
+    let value: serde_json::Value = serde_json::from_str(&json).expect("Export isn't JSON");
+    assert_eq!(value["schema"], ZONE_SCHEMA_VERSION);
+    // database IDs don't go in exports
+    assert!(value["zones"][0].get("id").is_none());
+    assert!(value["zones"][0]["records"][0].get("zoneid").is_none());
+
+    let zones = zones_from_json(&json).expect("Failed to read our own export");
+    assert_eq!(zones.len(), 1);
+    assert!(zones[0].matching_data(&zone));
+    assert_eq!(zones[0].id, None);
+    let record = &zones[0].records[0];
+    assert_eq!(record.id, None);
+    assert_eq!(record.comment, zone.records[0].comment);
+    assert_eq!(record.tags, zone.records[0].tags);
+    assert_eq!(record.rdata, zone.records[0].rdata);
+
+    // enabled is only written out when it's off
+    assert!(value["zones"][0].get("enabled").is_none());
+    assert!(zones[0].enabled);
+    // and public when it's on
+    assert_eq!(value["zones"][0]["public"], true);
+    assert!(zones[0].public);
+    assert_eq!(value["zones"][0]["registration"]["expires"], "2030-01-31");
+    assert_eq!(zones[0].registration, zone.registration);
+    let disabled = FileZone {
+        enabled: false,
+        ..zone
+    };
+    let json = zones_to_json(&[disabled]).expect("Failed to export");
+    let zones = zones_from_json(&json).expect("Failed to read our own export");
+    assert!(!zones[0].enabled);
+}
+
+#[test]
+fn test_zone_registration() {
+    let registration = example_zone().registration;
+    let date = |month, day| chrono::NaiveDate::from_ymd_opt(2030, month, day).expect("Bad date");
+    assert_eq!(registration.days_left(date(1, 1)), Some(30));
+    assert!(registration.expiring_soon(date(1, 1)));
+    assert!(!registration.expiring_soon(date(1, 1) - chrono::Days::new(1)));
+    assert_eq!(
+        registration.expiry_warning(date(1, 1) - chrono::Days::new(1)),
+        None
+    );
+    assert_eq!(
+        registration.expiry_warning(date(1, 30)),
+        Some("Expires tomorrow".to_string())
+    );
+    assert_eq!(
+        registration.expiry_warning(date(2, 3)),
+        Some("Expired 3 days ago".to_string())
+    );
+    assert!(!ZoneRegistration::default().expiring_soon(date(1, 1)));
+
+    // blank strings are the same as not having them
+    let blank = ZoneRegistration {
+        registrar: Some(" ".to_string()),
+        expires: None,
+        notes: Some(String::new()),
+    };
+    assert!(blank.check().expect("Failed to check").is_empty());
+    let too_long = ZoneRegistration {
+        registrar: Some("a".repeat(256)),
+        ..Default::default()
+    };
+    assert!(too_long.check().is_err());
+
+    // empty ones aren't exported, and bad ones don't get imported
+    let json = zones_to_json(&[FileZone {
+        registration: ZoneRegistration::default(),
+        ..example_zone()
+    }])
+    .expect("Failed to export");
+    assert!(!json.contains("registration"));
+    let json = json.replacen(
+        r#""name": "example.com""#,
+        r#""name": "example.com", "registration": {"expires": "next tuesday"}"#,
+        1,
+    );
+    assert!(json.contains("next tuesday"));
+    assert!(zones_from_json(&json).is_err());
+}
+
+#[test]
+fn test_zone_schema_owners() {
+    let backup = ZoneBackup {
+        zones: vec![example_zone()],
+        owners: [("example.com".to_string(), vec!["alice".to_string()])].into(),
+    };
+    let json = backup_to_json(&backup).expect("Failed to export");
+    let restored = backup_from_json(&json).expect("Failed to read our own export");
+    assert_eq!(restored.owners, backup.owners);
+    assert!(restored.zones[0].matching_data(&backup.zones[0]));
+
+    // plain zone exports don't have any
+    let json = zones_to_json(&backup.zones).expect("Failed to export");
+    assert!(!json.contains("owners"));
+    assert!(backup_from_json(&json)
+        .expect("Failed to read export")
+        .owners
+        .is_empty());
+}
+
+#[test]
+fn test_zone_schema_reads_old_files() {
+    // a zone on its own, like the old single-zone exports
+    let zones = zones_from_json(
+        r#"{"id": 1, "name": "example.com", "rname": "admin@example.com", "serial": 1, "records": [
+            {"id": 4, "zoneid": 1, "name": "www", "rrtype": "A", "rdata": "192.0.2.1", "ttl": 60}
+        ]}"#,
+    )
+    .expect("Failed to read a single zone");
+    assert_eq!(zones.len(), 1);
+    assert_eq!(zones[0].records[0].name, "www");
+
+    // and a list of them, with JSON5's comments
+    let zones = zones_from_json(
+        r#"[
+            // the first one
+            {"name": "example.com", "records": [{"rrtype": "NS", "rdata": "ns1.example.com"}]},
+            {"name": "example.net", "records": []},
+        ]"#,
+    )
+    .expect("Failed to read a list of zones");
+    assert_eq!(zones.len(), 2);
+    assert_eq!(zones[0].records[0].name, "@");
+    assert_eq!(zones[0].records[0].class, RecordClass::Internet);
+
+    // the examples that ship with GoatNS
+    for filename in [
+        "./examples/test_config/zones.json",
+        "./examples/test_config/single-zone.json",
+    ] {
+        let contents = std::fs::read_to_string(filename).expect("Failed to read example");
+        assert!(!zones_from_json(&contents)
+            .expect("Failed to read example")
+            .is_empty());
+    }
+}
+
+#[test]
+fn test_zone_schema_errors() {
+    // (input, something the error should say)
+    for (input, expected) in [
+        (r#"{"schema": 2, "zones": []}"#, "newer than this version"),
+        (r#"{"schema": "one", "zones": []}"#, "unknown version"),
+        (r#"{"schema": 1}"#, "zones"),
+        (r#"{"schema": 1, "zones": [], "extra": true}"#, "extra"),
+        (
+            r#"{"schema": 1, "zones": [{"name": "example.com", "id": 1}]}"#,
+            "zones[0]: unknown field `id`",
+        ),
+        (
+            r#"{"schema": 1, "zones": [{"name": "example.com", "records": [
+                {"rrtype": "A", "rdata": "192.0.2.1"},
+                {"rrtype": "A"}
+            ]}]}"#,
+            "zones[0].records[1]: missing field `rdata`",
+        ),
+        (
+            r#"{"schema": 1, "zones": [{"name": "example.com", "records": [{"rrtype": "GOAT", "rdata": "1"}]}]}"#,
+            "zones[0].records[0]: unknown record type",
+        ),
+        (
+            r#"{"schema": 1, "zones": [{"name": "example.com", "records": [{"rrtype": "A", "class": "XX", "rdata": "1"}]}]}"#,
+            "unknown record class",
+        ),
+        (
+            r#"{"schema": 1, "zones": [{"name": "", "records": []}]}"#,
+            "needs a name",
+        ),
+        (
+            r#"[{"name": "example.com", "records": [{"rrtype": "A", "rdata": " "}]}]"#,
+            "[0].records[0]: the rdata's empty",
+        ),
+        (
+            r#"{"schema": 1, "zones": [], "owners": {"example.com": ["alice"]}}"#,
+            "owners.example.com: there's no zone with that name",
+        ),
+        (
+            r#"{"schema": 1, "zones": [], "owners": ["alice"]}"#,
+            "owners: invalid type",
+        ),
+        ("42", "the top level"),
+        ("{", "Failed to read JSON"),
+    ] {
+        let err = zones_from_json(input).expect_err(input);
+        assert!(
+            format!("{err:?}").contains(expected),
+            "{input} gave {err:?}"
+        );
+    }
+}
@@ -0,0 +1,601 @@
+use crate::enums::RecordClass;
+use crate::zonefile::{
+    absolute_name, absolute_rdata, expand_generate, generate_substitute, parse_generate_range,
+    parse_records, parse_ttl, qualified_rdata, read_with_includes, soa_owner, tokenize,
+    ttl_directive, Soa, ZoneFileError, MAX_INCLUDE_DEPTH,
+};
+
+#[test]
+fn test_generate_range() {
+    assert_eq!(parse_generate_range("1-254").expect("failed"), (1, 254, 1));
+    assert_eq!(
+        parse_generate_range("0-100/10").expect("failed"),
+        (0, 100, 10)
+    );
+    assert_eq!(parse_generate_range("5-5").expect("failed"), (5, 5, 1));
+
+    for range in [
+        "10-1",
+        "1-10/0",
+        "1",
+        "a-b",
+        "1-",
+        "0-65536",
+        "0-4294967295",
+    ] {
+        assert!(parse_generate_range(range).is_err(), "{range}");
+    }
+    // the limit's on how many records it makes, not how big the numbers are
+    assert!(parse_generate_range("0-655350/10").is_ok());
+}
+
+#[test]
+fn test_generate_substitute() {
+    assert_eq!(generate_substitute("host-$", 7).expect("failed"), "host-7");
+    assert_eq!(generate_substitute("$.$", 7).expect("failed"), "7.7");
+    assert_eq!(generate_substitute("\\$-$", 7).expect("failed"), "$-7");
+    assert_eq!(generate_substitute("${10}", 7).expect("failed"), "17");
+    assert_eq!(generate_substitute("${-2,3}", 7).expect("failed"), "005");
+    assert_eq!(
+        generate_substitute("${0,4,x}", 255).expect("failed"),
+        "00ff"
+    );
+    assert_eq!(generate_substitute("${0,0,X}", 255).expect("failed"), "FF");
+    assert_eq!(generate_substitute("${0,0,o}", 8).expect("failed"), "10");
+    assert_eq!(
+        generate_substitute("${0,0,n}", 0x1a).expect("failed"),
+        "a.1"
+    );
+    assert_eq!(
+        generate_substitute("${0,7,N}", 0x1a).expect("failed"),
+        "A.1.0.0"
+    );
+
+    for template in ["${", "${1,2,q}", "${-8}", "${a}", "${1,2,d,4}"] {
+        assert!(generate_substitute(template, 7).is_err(), "{template}");
+    }
+}
+
+#[test]
+fn test_expand_generate() {
+    let records = expand_generate(
+        "$GENERATE 1-254 $ PTR host-$.example.com.",
+        "2.0.192.in-addr.arpa",
+        "2.0.192.in-addr.arpa",
+    )
+    .expect("failed to expand");
+    assert_eq!(records.len(), 254);
+    assert_eq!(records[0].name, "1");
+    assert_eq!(records[0].rrtype, "PTR");
+    assert_eq!(records[0].rdata, "host-1.example.com");
+    assert_eq!(records[0].ttl, 0);
+    assert_eq!(records[253].name, "254");
+    assert_eq!(records[253].rdata, "host-254.example.com");
+
+    // TTL and class can go either way around, and relative names get the zone added
+    let records = expand_generate(
+        "$GENERATE 10-20/5 web${0,2}.example.com. IN 300 CNAME lb$",
+        "example.com.",
+        "example.com.",
+    )
+    .expect("failed to expand");
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[1].name, "web15");
+    assert_eq!(records[1].class, RecordClass::Internet);
+    assert_eq!(records[1].ttl, 300);
+    assert_eq!(records[1].rdata, "lb15.example.com");
+
+    let records = expand_generate(
+        "$GENERATE 1-2 txt$ 60 TXT \"v=$ hello\"",
+        "example.com",
+        "example.com",
+    )
+    .expect("failed to expand");
+    assert_eq!(records[1].rdata, "\"v=2 hello\"");
+
+    // relative names are relative to the $ORIGIN, which might be below the zone
+    let records = expand_generate(
+        "$GENERATE 1-2 host$ MX 10 @",
+        "hosts.example.com",
+        "example.com",
+    )
+    .expect("failed to expand");
+    assert_eq!(records[0].name, "host1.hosts");
+    assert_eq!(records[0].rdata, "10 hosts.example.com");
+
+    for line in [
+        "$GENERATE 1-2 $",
+        "$GENERATE 1-2 $ PTR",
+        "$GENERATE 1-2 $ GOAT thing",
+        "$GENERATE 1-100000 $ A 192.0.2.1",
+        "$GENERATE 1-2 host$.example.net. A 192.0.2.1",
+        "$INCLUDE other.zone",
+    ] {
+        assert!(
+            expand_generate(line, "example.com", "example.com").is_err(),
+            "{line}"
+        );
+    }
+    assert!(expand_generate(
+        "$GENERATE 1-2 host$ A 192.0.2.1",
+        "example.net",
+        "example.com"
+    )
+    .is_err());
+}
+
+#[test]
+fn test_absolute_name() {
+    // (name, origin, expected)
+    for (name, origin, expected) in [
+        ("@", "example.com.", "example.com"),
+        ("@", "example.com", "example.com"),
+        ("www", "example.com.", "www.example.com"),
+        ("www.sub", "example.com.", "www.sub.example.com"),
+        ("www.example.com.", "example.com.", "www.example.com"),
+        ("www.example.net.", "example.com.", "www.example.net"),
+        ("*", "example.com.", "*.example.com"),
+        ("*.sub", "example.com.", "*.sub.example.com"),
+        ("_sip._tcp", "example.com.", "_sip._tcp.example.com"),
+        // names that happen to look like the origin are still relative
+        ("example.com", "example.com.", "example.com.example.com"),
+        // no $ORIGIN yet
+        ("www", "", "www"),
+        ("@", "", ""),
+        ("www.example.com.", "", "www.example.com"),
+    ] {
+        assert_eq!(absolute_name(name, origin), expected, "{name} in {origin}");
+    }
+}
+
+#[test]
+fn test_absolute_rdata() {
+    let origin = "example.com.";
+    // (type, rdata, expected)
+    for (rrtype, rdata, expected) in [
+        ("CNAME", "www", "www.example.com"),
+        ("cname", "@", "example.com"),
+        ("CNAME", "www.example.net.", "www.example.net"),
+        ("ALIAS", "lb", "lb.example.com"),
+        ("DNAME", "other.example.net.", "other.example.net"),
+        ("NS", "ns1", "ns1.example.com"),
+        ("PTR", "host.example.net.", "host.example.net"),
+        ("MX", "10 mail", "10 mail.example.com"),
+        ("MX", "0 @", "0 example.com"),
+        ("MX", "10   mail.example.net.", "10 mail.example.net"),
+        ("SRV", "10 5 5060 sip", "10 5 5060 sip.example.com"),
+        ("SRV", "0 0 0 .", "0 0 0 ."),
+        ("AFSDB", "1 afs", "1 afs.example.com"),
+        ("KX", "10 kx", "10 kx.example.com"),
+        ("RT", "10 relay", "10 relay.example.com"),
+        ("RP", "admin @", "admin.example.com example.com"),
+        (
+            "SOA",
+            "ns1 hostmaster 1 7200 3600 1209600 3600",
+            "ns1.example.com hostmaster.example.com 1 7200 3600 1209600 3600",
+        ),
+        (
+            "SOA",
+            "ns1.example.net. hostmaster.example.net. 1 7200 3600 1209600 3600",
+            "ns1.example.net hostmaster.example.net 1 7200 3600 1209600 3600",
+        ),
+        // types without names in them are left alone
+        ("A", "192.0.2.1", "192.0.2.1"),
+        ("AAAA", "2001:db8::1", "2001:db8::1"),
+        ("TXT", "\"hello @ world\"", "\"hello @ world\""),
+        (
+            "CAA",
+            "0 issue \"letsencrypt.org\"",
+            "0 issue \"letsencrypt.org\"",
+        ),
+        // as is anything too short to have the name in it
+        ("MX", "mail", "mail"),
+        ("SRV", "10 5 sip", "10 5 sip"),
+        // quoted strings with spaces in them don't throw out which field's which
+        (
+            "NAPTR",
+            "100 10 \"S\" \"SIP+D2U\" \"!^.* $!sip:info@example.com!\" _sip._udp",
+            "100 10 \"S\" \"SIP+D2U\" \"!^.* $!sip:info@example.com!\" _sip._udp.example.com",
+        ),
+        (
+            "NAPTR",
+            "100 10 \"u\" \"E2U+sip\" \"!^.*$!sip:info@example.com!\" .",
+            "100 10 \"u\" \"E2U+sip\" \"!^.*$!sip:info@example.com!\" .",
+        ),
+    ] {
+        assert_eq!(
+            absolute_rdata(rrtype, rdata, origin),
+            expected,
+            "{rrtype} {rdata}"
+        );
+    }
+}
+
+#[test]
+fn test_read_with_includes() {
+    let dir = std::env::temp_dir().join(format!("goatns-includes-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("sub")).expect("failed to create dirs");
+    let write = |name: &str, contents: &str| {
+        std::fs::write(dir.join(name), contents).expect("failed to write zone file");
+    };
+
+    write(
+        "main.zone",
+        "$ORIGIN example.com.\nwww A 192.0.2.1\n$INCLUDE sub/hosts.zone hosts.example.com. ; comment\nmail A 192.0.2.2\n",
+    );
+    // relative to the file that includes it, not the first one
+    write("sub/hosts.zone", "a A 192.0.2.3\n$INCLUDE ../leaf.zone\n");
+    write("leaf.zone", "b A 192.0.2.4\n");
+    assert_eq!(
+        read_with_includes(&dir.join("main.zone"), true, None).expect("failed to read"),
+        "$ORIGIN example.com.\nwww A 192.0.2.1\n$ORIGIN hosts.example.com.\na A 192.0.2.3\nb A 192.0.2.4\n$ORIGIN hosts.example.com.\n$ORIGIN example.com.\nmail A 192.0.2.2\n"
+    );
+    // the origin's put back even when the including file never set one, or set a relative one
+    write("moves.zone", "$ORIGIN moved.example.com.\nx A 192.0.2.6\n");
+    write(
+        "plain.zone",
+        "www A 192.0.2.1\n$INCLUDE moves.zone\nmail A 192.0.2.2\n",
+    );
+    let contents = read_with_includes(&dir.join("plain.zone"), true, Some("example.com"))
+        .expect("failed to read");
+    assert_eq!(
+        contents,
+        "www A 192.0.2.1\n$ORIGIN moved.example.com.\nx A 192.0.2.6\n$ORIGIN example.com.\nmail A 192.0.2.2\n"
+    );
+    let records = parse_records(&contents, "example.com").expect("failed to parse");
+    assert_eq!(
+        records.last().map(|record| record.name.as_str()),
+        Some("mail")
+    );
+    write(
+        "relative.zone",
+        "$ORIGIN sub\n$INCLUDE moves.zone\nc A 192.0.2.7\n",
+    );
+    assert_eq!(
+        read_with_includes(&dir.join("relative.zone"), true, Some("example.com"))
+            .expect("failed to read"),
+        "$ORIGIN sub\n$ORIGIN moved.example.com.\nx A 192.0.2.6\n$ORIGIN sub.example.com.\nc A 192.0.2.7\n"
+    );
+    assert!(read_with_includes(&dir.join("main.zone"), false, None).is_err());
+    // files without includes are fine either way
+    assert!(read_with_includes(&dir.join("leaf.zone"), false, None).is_ok());
+
+    write("loop_a.zone", "$INCLUDE loop_b.zone\n");
+    write("loop_b.zone", "$INCLUDE loop_a.zone\n");
+    let err =
+        read_with_includes(&dir.join("loop_a.zone"), true, None).expect_err("loop wasn't caught");
+    assert!(format!("{err:?}").contains("loop"));
+
+    for depth in 0..=MAX_INCLUDE_DEPTH {
+        write(
+            &format!("deep{depth}.zone"),
+            &format!("$INCLUDE deep{}.zone\n", depth + 1),
+        );
+    }
+    write(
+        &format!("deep{}.zone", MAX_INCLUDE_DEPTH + 1),
+        "c A 192.0.2.5\n",
+    );
+    assert!(read_with_includes(&dir.join("deep0.zone"), true, None).is_err());
+    assert!(read_with_includes(&dir.join("deep1.zone"), true, None).is_ok());
+
+    write("missing.zone", "$INCLUDE nope.zone\n");
+    assert!(read_with_includes(&dir.join("missing.zone"), true, None).is_err());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_tokenize() {
+    let entries = tokenize(
+        "@ IN SOA ns1 hostmaster ( 1 ; serial\n  7200 3600\n  1209600 3600 )\n\n; just a comment\n  A 192.0.2.1\nwww TXT \"a; b (c)\" \"\\\"quoted\\\"\"\n",
+    )
+    .expect("failed to tokenize");
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0].line, 1);
+    assert!(!entries[0].blank_owner);
+    assert_eq!(
+        entries[0]
+            .tokens
+            .iter()
+            .map(|token| token.text.as_str())
+            .collect::<Vec<&str>>(),
+        vec![
+            "@",
+            "IN",
+            "SOA",
+            "ns1",
+            "hostmaster",
+            "1",
+            "7200",
+            "3600",
+            "1209600",
+            "3600"
+        ]
+    );
+    assert_eq!(entries[1].line, 6);
+    assert!(entries[1].blank_owner);
+    assert_eq!(entries[2].line, 7);
+    assert_eq!(entries[2].tokens[2].text, "\"a; b (c)\"");
+    assert!(entries[2].tokens[2].quoted);
+    assert_eq!(entries[2].tokens[3].text, "\"\\\"quoted\\\"\"");
+
+    // escaped characters don't do what they'd normally do
+    let entries = tokenize("a\\;b TXT x\\ y").expect("failed to tokenize");
+    assert_eq!(entries[0].tokens[0].text, "a\\;b");
+    assert_eq!(entries[0].tokens[2].text, "x\\ y");
+
+    for (contents, expected) in [
+        (
+            "www A 192.0.2.1\nwww TXT \"oops\n",
+            ZoneFileError::UnterminatedQuote { line: 2 },
+        ),
+        (
+            "www TXT \"oops",
+            ZoneFileError::UnterminatedQuote { line: 1 },
+        ),
+        (
+            "\n@ SOA ns1 hostmaster ( 1 2\n3 4 5\n",
+            ZoneFileError::UnbalancedParentheses { line: 2 },
+        ),
+        (
+            "www A 192.0.2.1 )",
+            ZoneFileError::UnbalancedParentheses { line: 1 },
+        ),
+    ] {
+        assert_eq!(tokenize(contents), Err(expected), "{contents:?}");
+    }
+}
+
+#[test]
+fn test_parse_ttl() {
+    assert_eq!(parse_ttl("3600"), Some(3600));
+    assert_eq!(parse_ttl("1h"), Some(3600));
+    assert_eq!(parse_ttl("1H30m"), Some(5400));
+    assert_eq!(parse_ttl("2w1d"), Some(1296000));
+    assert_eq!(parse_ttl("1m5"), Some(65));
+    for ttl in ["", "1y", "h", "99999999999", "9999999w"] {
+        assert_eq!(parse_ttl(ttl), None, "{ttl}");
+    }
+}
+
+#[test]
+fn test_parse_records() {
+    let contents = r#"$ORIGIN example.com.
+$TTL 1h
+@   IN  SOA ns1 hostmaster (
+        2024010101 ; serial
+        7200       ; refresh
+        3600 1209600 3600 )
+    IN  NS  ns1
+    IN  NS  ns2.example.net.
+    300 IN MX 10 mail
+ns1     A   192.0.2.1
+www 60 IN A 192.0.2.2
+        AAAA 2001:db8::2
+txt     TXT "v=spf1 include:example.net -all; (really)" "second"
+$ORIGIN sub.example.com.
+host    IN 120 CNAME www.example.com.
+*       CNAME host
+$GENERATE 1-2 gen$ A 192.0.2.$
+"#;
+    let records = parse_records(contents, "example.com").expect("failed to parse");
+    let summary: Vec<(&str, &str, &str, u32)> = records
+        .iter()
+        .map(|record| {
+            (
+                record.name.as_str(),
+                record.rrtype.as_str(),
+                record.rdata.as_str(),
+                record.ttl,
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (
+                "@",
+                "SOA",
+                "ns1.example.com hostmaster.example.com 2024010101 7200 3600 1209600 3600",
+                3600
+            ),
+            ("@", "NS", "ns1.example.com", 3600),
+            ("@", "NS", "ns2.example.net", 3600),
+            ("@", "MX", "10 mail.example.com", 300),
+            ("ns1", "A", "192.0.2.1", 3600),
+            ("www", "A", "192.0.2.2", 60),
+            ("www", "AAAA", "2001:db8::2", 3600),
+            (
+                "txt",
+                "TXT",
+                "\"v=spf1 include:example.net -all; (really)\" \"second\"",
+                3600
+            ),
+            ("host.sub", "CNAME", "www.example.com", 120),
+            ("*.sub", "CNAME", "host.sub.example.com", 3600),
+            ("gen1.sub", "A", "192.0.2.1", 0),
+            ("gen2.sub", "A", "192.0.2.2", 0),
+        ]
+    );
+    assert!(records
+        .iter()
+        .all(|record| record.class == RecordClass::Internet));
+
+    // no $TTL means the import default
+    let records = parse_records("www CH TXT hello", "example.com.").expect("failed to parse");
+    assert_eq!(records[0].ttl, 0);
+    assert_eq!(records[0].class, RecordClass::Chaos);
+
+    for (contents, expected) in [
+        ("  A 192.0.2.1", ZoneFileError::NoOwner { line: 1 }),
+        (
+            "www IN",
+            ZoneFileError::UnknownType {
+                line: 1,
+                rrtype: String::new(),
+            },
+        ),
+        (
+            "\nwww IN GOAT 1",
+            ZoneFileError::UnknownType {
+                line: 2,
+                rrtype: "GOAT".to_string(),
+            },
+        ),
+        ("www 60 A", ZoneFileError::NoRdata { line: 1 }),
+        (
+            "www 1y A 192.0.2.1",
+            ZoneFileError::InvalidTtl {
+                line: 1,
+                ttl: "1y".to_string(),
+            },
+        ),
+        (
+            "www.example.net. A 192.0.2.1",
+            ZoneFileError::OutsideZone {
+                line: 1,
+                name: "www.example.net".to_string(),
+            },
+        ),
+        (
+            "$FOO bar",
+            ZoneFileError::UnknownDirective {
+                line: 1,
+                directive: "$FOO".to_string(),
+            },
+        ),
+        (
+            "$TTL",
+            ZoneFileError::InvalidDirective {
+                line: 1,
+                message: "$TTL needs a TTL".to_string(),
+            },
+        ),
+        (
+            "$INCLUDE other.zone",
+            ZoneFileError::InvalidDirective {
+                line: 1,
+                message: "$INCLUDE isn't allowed here".to_string(),
+            },
+        ),
+    ] {
+        assert_eq!(
+            parse_records(contents, "example.com"),
+            Err(expected),
+            "{contents:?}"
+        );
+    }
+
+    // garbage gets errors, not panics
+    for contents in [
+        "(",
+        ")",
+        "\"",
+        "\\",
+        "$",
+        "@",
+        "@ (",
+        "( )",
+        "$GENERATE",
+        "@ IN ( A ) \"x",
+    ] {
+        let _ = parse_records(contents, "example.com");
+    }
+}
+
+#[test]
+fn test_soa() {
+    let soa: Soa = "ns1.example.com. hostmaster.example.com. 2024010101 2h 1h 2w 300"
+        .parse()
+        .expect("failed to parse");
+    assert_eq!(
+        soa,
+        Soa {
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 2024010101,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+        }
+    );
+    assert_eq!(
+        soa.to_string(),
+        "ns1.example.com hostmaster.example.com 2024010101 7200 3600 1209600 300"
+    );
+    for rdata in [
+        "",
+        "ns1 hostmaster 1 2 3 4",
+        "ns1 hostmaster 1 2 3 4 5 6",
+        "ns1 hostmaster 1h 2 3 4 5",
+        "ns1 hostmaster 1 2 3 4 1y",
+    ] {
+        assert!(rdata.parse::<Soa>().is_err(), "{rdata}");
+    }
+
+    // the SOA's rdata is tidied up when the file's parsed
+    let records = parse_records("@ SOA ns1 hostmaster ( 1 1h 1h 1w 1h )", "example.com")
+        .expect("failed to parse");
+    assert_eq!(
+        records[0].rdata,
+        "ns1.example.com hostmaster.example.com 1 3600 3600 604800 3600"
+    );
+    assert!(matches!(
+        parse_records("www A 192.0.2.1\n@ SOA ns1 hostmaster 1", "example.com"),
+        Err(ZoneFileError::InvalidRdata { line: 2, .. })
+    ));
+}
+
+#[test]
+fn test_qualified_rdata() {
+    // (type, rdata, expected)
+    for (rrtype, rdata, expected) in [
+        ("CNAME", "www.example.com", "www.example.com."),
+        ("CNAME", "www.example.com.", "www.example.com."),
+        ("MX", "10 mail.example.com", "10 mail.example.com."),
+        ("SRV", "0 0 0 .", "0 0 0 ."),
+        ("A", "192.0.2.1", "192.0.2.1"),
+    ] {
+        assert_eq!(qualified_rdata(rrtype, rdata), expected, "{rrtype} {rdata}");
+    }
+}
+
+#[test]
+fn test_soa_owner() {
+    assert_eq!(
+        soa_owner("$ORIGIN example.com.\n$TTL 1h\n@ 3600 IN SOA ns1 hostmaster 1 2 3 4 5\n"),
+        Ok(Some("example.com".to_string()))
+    );
+    assert_eq!(
+        soa_owner("$ORIGIN com.\nexample IN SOA ns1 hostmaster 1 2 3 4 5\n"),
+        Ok(Some("example.com".to_string()))
+    );
+    assert_eq!(
+        soa_owner("example.net. 60 A 192.0.2.1\n  SOA ns1 hostmaster 1 2 3 4 5\n"),
+        Ok(Some("example.net".to_string()))
+    );
+    assert_eq!(soa_owner("www.example.com. A 192.0.2.1\n"), Ok(None));
+    assert!(soa_owner("www TXT \"oops\n").is_err());
+}
+
+#[test]
+fn test_ttl_directive() {
+    assert_eq!(
+        ttl_directive("$ORIGIN example.com.\n$TTL 1h\n@ 60 IN A 192.0.2.1\n$TTL 60\n"),
+        Ok(Some(3600))
+    );
+    assert_eq!(ttl_directive("www 60 IN A 192.0.2.1\n"), Ok(None));
+    assert_eq!(
+        ttl_directive("www TXT \"$TTL 5\"\n\"$TTL\" 60 A 192.0.2.1\n"),
+        Ok(None)
+    );
+    assert_eq!(
+        ttl_directive("\n$TTL forever\n"),
+        Err(ZoneFileError::InvalidTtl {
+            line: 2,
+            ttl: "forever".to_string()
+        })
+    );
+}
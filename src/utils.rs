@@ -1,8 +1,10 @@
-use crate::datastore::Command;
+use crate::datastore::Queued;
 use crate::enums::AgentState;
 use crate::error::GoatNsError;
 use crate::HEADER_BYTES;
 use log::{debug, trace};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, SocketAddr};
 use std::str::from_utf8;
 use tokio::sync::{broadcast, mpsc};
 
@@ -15,6 +17,26 @@ pub fn vec_find(item: u8, search: &[u8]) -> Option<usize> {
     None
 }
 
+/// The name PTR records for an address go under, eg `4.3.2.1.in-addr.arpa` for `1.2.3.4`
+pub fn reverse_name(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets: Vec<String> = ip.octets().iter().rev().map(u8::to_string).collect();
+            format!("{}.in-addr.arpa", octets.join("."))
+        }
+        IpAddr::V6(ip) => {
+            let nibbles: Vec<String> = ip
+                .octets()
+                .iter()
+                .rev()
+                .flat_map(|octet| [octet & 0x0f, octet >> 4])
+                .map(|nibble| format!("{nibble:x}"))
+                .collect();
+            format!("{}.ip6.arpa", nibbles.join("."))
+        }
+    }
+}
+
 /// does the conversion from "example.com" to "7example3com" BUT DOES NOT DO THE TRAILING NULL BECAUSE REASONS
 fn seven_dot_three_conversion(name: &[u8]) -> Vec<u8> {
     trace!("7.3 conversion for {name:?} {:?}", from_utf8(name));
@@ -129,6 +151,11 @@ pub fn name_as_bytes(
         return Ok(result);
     };
 
+    // the root is just the null
+    if name.is_empty() {
+        return Ok(vec![0]);
+    }
+
     let mut result: Vec<u8> = vec![];
     // if somehow it's a weird bare domain then we don't have to do much it
     if !name.contains(&46) {
@@ -207,6 +234,39 @@ pub fn name_as_bytes(
     Ok(result)
 }
 
+/// Where `suffix` starts in `name`, if it's made up of whole labels at the end of it (or is all of it).
+///
+/// Since a label's length byte takes the place of the dot before it, this is also where the suffix starts once `name` is in wire format, which is what you want for compression pointers. eg `example.com` is at 4 in `www.example.com`, but isn't a suffix of `myexample.com`.
+pub fn label_suffix_offset(name: &[u8], suffix: &[u8]) -> Option<usize> {
+    if suffix.is_empty() || name.len() < suffix.len() {
+        return None;
+    }
+    let start = name.len() - suffix.len();
+    if !name[start..].eq_ignore_ascii_case(suffix) {
+        return None;
+    }
+    match start {
+        0 => Some(0),
+        _ if name[start - 1] == b'.' => Some(start),
+        _ => None,
+    }
+}
+
+/// The name and each of the names above it, closest first - eg `www.example.com`, `example.com` then `com`. The root
+/// isn't included.
+pub fn enclosing_names(name: &str) -> Vec<&str> {
+    let mut names = vec![];
+    let mut rest = name.trim_end_matches('.');
+    while !rest.is_empty() {
+        names.push(rest);
+        rest = rest
+            .split_once('.')
+            .map(|(_, parent)| parent)
+            .unwrap_or_default();
+    }
+    names
+}
+
 // lazy_static!{
 //     static ref GOATNS_VERSION: DNSCharString = DNSCharString::from(format!("GoatNS {}", env!("CARGO_PKG_VERSION")).as_str());
 // }
@@ -305,12 +365,12 @@ pub fn loc_size_to_u8(input: f32) -> u8 {
 /// Get all the widgets for agent signalling
 pub fn start_channels() -> (
     broadcast::Sender<AgentState>,
-    mpsc::Sender<Command>,
-    mpsc::Receiver<Command>,
+    mpsc::Sender<Queued>,
+    mpsc::Receiver<Queued>,
 ) {
     let (agent_tx, _) = broadcast::channel(32);
-    let datastore_sender: mpsc::Sender<Command>;
-    let datastore_receiver: mpsc::Receiver<Command>;
+    let datastore_sender: mpsc::Sender<Queued>;
+    let datastore_receiver: mpsc::Receiver<Queued>;
     (datastore_sender, datastore_receiver) = mpsc::channel(crate::MAX_IN_FLIGHT);
     (agent_tx, datastore_sender, datastore_receiver)
 }
@@ -342,3 +402,40 @@ pub fn check_valid_tld(zone_name: &str, allowed_tlds: &[String]) -> bool {
     }
     false
 }
+
+fn bind_socket(
+    addr: SocketAddr,
+    socket_type: Type,
+    protocol: Protocol,
+    ipv6_only: Option<bool>,
+) -> std::io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), socket_type, Some(protocol))?;
+    if let (SocketAddr::V6(_), Some(only_v6)) = (addr, ipv6_only) {
+        socket.set_only_v6(only_v6)?;
+    }
+    if socket_type == Type::STREAM {
+        // the same as std/tokio do, so restarts don't fail while old connections are in TIME_WAIT
+        socket.set_reuse_address(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    Ok(socket)
+}
+
+/// Bind a UDP socket, `ipv6_only` sets IPV6_V6ONLY on IPv6 addresses, [None] leaves it at the OS default
+pub fn bind_udp_socket(
+    addr: SocketAddr,
+    ipv6_only: Option<bool>,
+) -> std::io::Result<std::net::UdpSocket> {
+    Ok(bind_socket(addr, Type::DGRAM, Protocol::UDP, ipv6_only)?.into())
+}
+
+/// Bind a listening TCP socket, `ipv6_only` sets IPV6_V6ONLY on IPv6 addresses, [None] leaves it at the OS default
+pub fn bind_tcp_listener(
+    addr: SocketAddr,
+    ipv6_only: Option<bool>,
+) -> std::io::Result<std::net::TcpListener> {
+    let socket = bind_socket(addr, Type::STREAM, Protocol::TCP, ipv6_only)?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
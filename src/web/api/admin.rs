@@ -0,0 +1,619 @@
+//! API endpoints for administrators, for looking into what the server's doing
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use crate::backup::{backup_files, backup_from_files};
+use crate::datastore::{CachePurge, Command};
+use crate::db::{DBEntity, User};
+use crate::error::GoatNsError;
+use crate::error_result_json;
+use crate::interop::ZoneFileFormat;
+use crate::query_trace::{self, TraceStatus};
+use crate::zones::{FileZone, ImportStrategy, ImportSummary};
+use axum::extract::Query;
+use goatns_macros::check_api_auth;
+use tower_sessions::Session;
+use utoipa::ToSchema;
+
+use super::*;
+
+/// What to start tracing
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct TraceRequest {
+    /// A regular expression that query names are matched against, case-insensitively, eg `^www\.example\.com$`
+    pub pattern: String,
+    /// How many of the most recent matching queries to keep, defaults to 200
+    #[serde(default)]
+    pub capacity: Option<usize>,
+}
+
+/// What to purge from the record cache, everything if there's no name
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
+pub struct CachePurgeRequest {
+    /// The name to purge, eg `www.example.com`
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Only purge this type for the name, eg `A` or `TYPE65534`
+    #[serde(default)]
+    pub rrtype: Option<String>,
+}
+
+/// How a purge or reload went
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct CachePurgeResponse {
+    /// How many cached lookups were dropped
+    pub purged: usize,
+}
+
+/// What a zone reload found
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ZoneReloadResponse {
+    pub id: i64,
+    pub name: String,
+    /// How many records the zone has now
+    pub records: usize,
+    /// How many cached lookups for the zone were dropped
+    pub purged: usize,
+}
+
+/// A backup of every zone and who owns them, as the files it'd be written to disk as
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct BackupArchive {
+    /// What the zones are in, `goatns` or `bind`
+    pub format: String,
+    /// Filename -> contents
+    pub files: BTreeMap<String, String>,
+}
+
+/// A zone nobody owns
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct OrphanedZone {
+    pub id: i64,
+    pub name: String,
+}
+
+/// Orphaned zones to give to someone
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct AssignOrphansRequest {
+    /// The zones' IDs
+    pub zones: Vec<i64>,
+    /// Who's getting them
+    pub username: String,
+}
+
+/// What got assigned
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct AssignOrphansResponse {
+    /// The names of the zones that were assigned, ones that had an owner already are skipped
+    pub assigned: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ExportQuery {
+    /// `goatns` or `bind`, defaults to `goatns`
+    #[serde(default)]
+    format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RestoreQuery {
+    /// What to do with zones that already exist, defaults to replacing them
+    #[serde(default)]
+    strategy: Option<String>,
+    /// Work out what would happen, but don't save anything
+    #[serde(default)]
+    dry_run: bool,
+    /// Give the restored zones that don't end up with an owner to whoever's restoring them
+    #[serde(default)]
+    assign_orphans: bool,
+}
+
+/// Only admins get to see other people's queries
+macro_rules! check_admin {
+    ($user:expr) => {
+        if !$user.admin {
+            return error_result_json!("You need to be an admin to do that", StatusCode::FORBIDDEN);
+        }
+    };
+}
+
+/// What's being traced, and the queries caught so far
+#[utoipa::path(
+    get,
+    path = "/api/admin/trace",
+    operation_id = "trace_get",
+    responses(
+        (status = 200, description = "Successful", body = TraceStatus),
+        (status = 403, description = "Auth failed, or you're not an admin"),
+    ),
+    tag = "Admin",
+)]
+pub(crate) async fn api_trace_get(
+    session: Session,
+) -> Result<Json<TraceStatus>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    check_admin!(user);
+    Ok(Json(query_trace::status()))
+}
+
+/// Start tracing queries for names matching a pattern, this throws away anything caught before
+#[utoipa::path(
+    post,
+    path = "/api/admin/trace",
+    operation_id = "trace_start",
+    request_body = TraceRequest,
+    responses(
+        (status = 200, description = "Tracing's started", body = TraceStatus),
+        (status = 400, description = "The pattern or capacity wasn't valid"),
+        (status = 403, description = "Auth failed, or you're not an admin"),
+    ),
+    tag = "Admin",
+)]
+pub(crate) async fn api_trace_start(
+    session: Session,
+    Json(payload): Json<TraceRequest>,
+) -> Result<Json<TraceStatus>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    check_admin!(user);
+    if let Err(err) = query_trace::enable(&payload.pattern, payload.capacity) {
+        return error_result_json!(
+            format!("Couldn't start tracing: {err:?}").as_str(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+    log::info!(
+        "id={:?} action=trace_start pattern={:?}",
+        user.id,
+        payload.pattern
+    );
+    Ok(Json(query_trace::status()))
+}
+
+/// Stop tracing queries, and throw away the ones caught so far
+#[utoipa::path(
+    delete,
+    path = "/api/admin/trace",
+    operation_id = "trace_stop",
+    responses(
+        (status = 204, description = "Tracing's stopped"),
+        (status = 403, description = "Auth failed, or you're not an admin"),
+    ),
+    tag = "Admin",
+)]
+pub(crate) async fn api_trace_stop(
+    session: Session,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    check_admin!(user);
+    query_trace::disable();
+    log::info!("id={:?} action=trace_stop", user.id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// What faults are being injected. Only there with the `fault-injection` feature, so it's not in the OpenAPI docs.
+#[cfg(feature = "fault-injection")]
+pub(crate) async fn api_faults_get(
+    session: Session,
+) -> Result<Json<crate::fault_injection::Faults>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    check_admin!(user);
+    Ok(Json(crate::fault_injection::current()))
+}
+
+/// Change what faults are being injected, send `{}` to turn them all off
+#[cfg(feature = "fault-injection")]
+pub(crate) async fn api_faults_set(
+    session: Session,
+    Json(payload): Json<crate::fault_injection::Faults>,
+) -> Result<Json<crate::fault_injection::Faults>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    check_admin!(user);
+    if let Err(err) = crate::fault_injection::set(payload) {
+        return error_result_json!(
+            format!("Couldn't set faults: {err:?}").as_str(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+    log::warn!("id={:?} action=faults_set faults={payload:?}", user.id);
+    Ok(Json(payload))
+}
+
+/// Send a command to the datastore and wait for the answer
+async fn ask_datastore<T>(
+    state: &GoatState,
+    cmd: Command,
+    rx: tokio::sync::oneshot::Receiver<Result<T, GoatNsError>>,
+) -> Result<Result<T, GoatNsError>, (StatusCode, Json<ErrorResult>)> {
+    if let Err(err) = state.read().await.tx.send(cmd.into()).await {
+        log::error!("Error sending message to datastore: {err:?}");
+        return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    match rx.await {
+        Ok(res) => Ok(res),
+        Err(err) => {
+            log::error!("Datastore didn't respond: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Drop cached lookups so they're answered from the database again, eg after it's been edited directly. Send a name
+/// (and optionally a type) to purge just that, or nothing to purge everything.
+#[utoipa::path(
+    post,
+    path = "/api/admin/cache/purge",
+    operation_id = "cache_purge",
+    request_body = CachePurgeRequest,
+    responses(
+        (status = 200, description = "Successful", body = CachePurgeResponse),
+        (status = 400, description = "The type wasn't valid, or there was a type without a name"),
+        (status = 403, description = "Auth failed, or you're not an admin"),
+    ),
+    tag = "Admin",
+)]
+pub(crate) async fn api_cache_purge(
+    State(state): State<GoatState>,
+    session: Session,
+    Json(payload): Json<CachePurgeRequest>,
+) -> Result<Json<CachePurgeResponse>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    check_admin!(user);
+    let rrtype = match payload.rrtype.as_deref() {
+        Some(rrtype) => match crate::generic::parse_type(rrtype) {
+            Some(code) => Some(code),
+            None => {
+                return error_result_json!(
+                    format!("Unknown record type {rrtype:?}").as_str(),
+                    StatusCode::BAD_REQUEST
+                )
+            }
+        },
+        None => None,
+    };
+    let purge = match (payload.name, rrtype) {
+        (Some(name), rrtype) => CachePurge::Name { name, rrtype },
+        (None, None) => CachePurge::All,
+        (None, Some(_)) => {
+            return error_result_json!(
+                "You need to give a name to purge a type",
+                StatusCode::BAD_REQUEST
+            )
+        }
+    };
+    log::info!("id={:?} action=cache_purge purge={purge:?}", user.id);
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    match ask_datastore(&state, Command::PurgeCache { purge, resp: tx }, rx).await? {
+        Ok(purged) => Ok(Json(CachePurgeResponse { purged })),
+        Err(err) => {
+            log::error!("Failed to purge the cache: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Re-read a zone from the database and drop anything cached for it
+#[utoipa::path(
+    post,
+    path = "/api/admin/zone/{id}/reload",
+    operation_id = "zone_reload",
+    params(
+        ("id" = i64, Path, description = "Zone ID"),
+    ),
+    responses(
+        (status = 200, description = "Successful", body = ZoneReloadResponse),
+        (status = 403, description = "Auth failed, or you're not an admin"),
+        (status = 404, description = "Zone not found"),
+    ),
+    tag = "Admin",
+)]
+pub(crate) async fn api_zone_reload(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> Result<Json<ZoneReloadResponse>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    check_admin!(user);
+    log::info!("id={:?} action=zone_reload zoneid={id}", user.id);
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    match ask_datastore(&state, Command::ReloadZone { id, resp: tx }, rx).await? {
+        Ok(reload) => Ok(Json(ZoneReloadResponse {
+            id,
+            name: reload.zone.name,
+            records: reload.zone.records.len(),
+            purged: reload.purged,
+        })),
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            error_result_json!("Zone not found", StatusCode::NOT_FOUND)
+        }
+        Err(err) => {
+            log::error!("Failed to reload zoneid={id}: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Back up every zone and who owns them
+#[utoipa::path(
+    get,
+    path = "/api/admin/export",
+    operation_id = "admin_export",
+    params(
+        ("format" = Option<String>, Query, description = "goatns (the default) for one JSON file, or bind for a zone file per zone"),
+    ),
+    responses(
+        (status = 200, description = "Successful", body = BackupArchive),
+        (status = 400, description = "The format can't be used for backups"),
+        (status = 403, description = "Auth failed, or you're not an admin"),
+    ),
+    tag = "Admin",
+)]
+pub(crate) async fn api_export(
+    State(state): State<GoatState>,
+    session: Session,
+    Query(query): Query<ExportQuery>,
+) -> Result<Json<BackupArchive>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    check_admin!(user);
+    let format = match query.format.as_deref() {
+        Some(format) => match ZoneFileFormat::from_str(format) {
+            Ok(format) => format,
+            Err(_) => {
+                return error_result_json!(
+                    format!("Unknown format {format:?}").as_str(),
+                    StatusCode::BAD_REQUEST
+                )
+            }
+        },
+        None => ZoneFileFormat::GoatNs,
+    };
+    log::info!("id={:?} action=export format={format}", user.id);
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let backup = match ask_datastore(&state, Command::ExportAll { resp: tx }, rx).await? {
+        Ok(backup) => backup,
+        Err(err) => {
+            log::error!("Failed to export zones: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    match backup_files(&backup, format) {
+        Ok(files) => Ok(Json(BackupArchive {
+            format: format.to_string(),
+            files,
+        })),
+        Err(GoatNsError::InvalidValue(message)) => {
+            error_result_json!(message.as_str(), StatusCode::BAD_REQUEST)
+        }
+        Err(err) => {
+            log::error!("Failed to write out the backup: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Restore a backup from `GET /api/admin/export`, in one go so either all of it's restored or none of it is
+#[utoipa::path(
+    post,
+    path = "/api/admin/restore",
+    operation_id = "admin_restore",
+    params(
+        ("strategy" = Option<String>, Query, description = "What to do with zones that already exist: replace (the default), merge or skip-existing"),
+        ("dry_run" = Option<bool>, Query, description = "Work out what would happen, but don't save anything"),
+        ("assign_orphans" = Option<bool>, Query, description = "Give the restored zones that don't end up with an owner to you"),
+    ),
+    request_body = BackupArchive,
+    responses(
+        (status = 200, description = "Successful", body = ImportSummary),
+        (status = 400, description = "The backup or strategy wasn't valid"),
+        (status = 403, description = "Auth failed, or you're not an admin"),
+    ),
+    tag = "Admin",
+)]
+pub(crate) async fn api_restore(
+    State(state): State<GoatState>,
+    session: Session,
+    Query(query): Query<RestoreQuery>,
+    Json(archive): Json<BackupArchive>,
+) -> Result<Json<ImportSummary>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    check_admin!(user);
+    let strategy = match query.strategy.as_deref() {
+        Some(strategy) => match ImportStrategy::from_str(strategy) {
+            Ok(strategy) => strategy,
+            Err(_) => {
+                return error_result_json!(
+                    format!("Unknown strategy {strategy:?}").as_str(),
+                    StatusCode::BAD_REQUEST
+                )
+            }
+        },
+        None => ImportStrategy::Replace,
+    };
+    let backup = match backup_from_files(&archive.files) {
+        Ok(backup) => backup,
+        Err(err) => {
+            log::debug!("Invalid backup: {err:?}");
+            return error_result_json!(
+                format!("Invalid backup: {err:?}").as_str(),
+                StatusCode::BAD_REQUEST
+            );
+        }
+    };
+    log::info!(
+        "id={:?} action=restore zones={} strategy={strategy} dry_run={} assign_orphans={}",
+        user.id,
+        backup.zones.len(),
+        query.dry_run,
+        query.assign_orphans
+    );
+    let restored: Vec<String> = backup.zones.iter().map(|zone| zone.name.clone()).collect();
+
+    let ttl_policy = state.read().await.config.import_ttl.clone();
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let cmd = Command::RestoreBackup {
+        backup,
+        ttl_policy,
+        strategy,
+        dry_run: query.dry_run,
+        resp: tx,
+    };
+    match ask_datastore(&state, cmd, rx).await? {
+        Ok(summary) => {
+            if query.assign_orphans && !query.dry_run {
+                if let Some(userid) = user.id {
+                    assign_restored_orphans(&state, &restored, userid).await?;
+                }
+            }
+            Ok(Json(summary))
+        }
+        Err(err) => {
+            log::error!("Failed to restore backup: {err:?}");
+            error_result_json!(
+                format!("Failed to restore backup: {err:?}").as_str(),
+                StatusCode::BAD_REQUEST
+            )
+        }
+    }
+}
+
+/// Give the zones that were just restored and don't have an owner to the user
+async fn assign_restored_orphans(
+    state: &GoatState,
+    restored: &[String],
+    userid: i64,
+) -> Result<(), (StatusCode, Json<ErrorResult>)> {
+    let res = async {
+        let mut txn = state.connpool().await.begin().await?;
+        let zoneids: Vec<i64> = FileZone::get_orphans(&mut txn)
+            .await?
+            .into_iter()
+            .filter(|zone| restored.contains(&zone.name))
+            .filter_map(|zone| zone.id)
+            .collect();
+        let assigned = FileZone::assign_orphans(&mut txn, &zoneids, userid).await?;
+        txn.commit().await?;
+        Ok::<_, GoatNsError>(assigned)
+    }
+    .await;
+    match res {
+        Ok(assigned) => {
+            log::info!("id={userid} action=assign_orphans zones={assigned:?}");
+            Ok(())
+        }
+        Err(err) => {
+            log::error!("Restored the backup, but failed to assign the orphaned zones: {err:?}");
+            error_result_json!(
+                "Restored the backup, but failed to assign the orphaned zones",
+                StatusCode::INTERNAL_SERVER_ERROR
+            )
+        }
+    }
+}
+
+/// Zones nobody owns
+#[utoipa::path(
+    get,
+    path = "/api/admin/orphans",
+    operation_id = "admin_orphans",
+    responses(
+        (status = 200, description = "Successful", body = [OrphanedZone]),
+        (status = 403, description = "Auth failed, or you're not an admin"),
+    ),
+    tag = "Admin",
+)]
+pub(crate) async fn api_orphans_get(
+    State(state): State<GoatState>,
+    session: Session,
+) -> Result<Json<Vec<OrphanedZone>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    check_admin!(user);
+    let res = match state.connpool().await.acquire().await {
+        Ok(mut conn) => FileZone::get_orphans(&mut conn).await,
+        Err(err) => Err(err.into()),
+    };
+    match res {
+        Ok(zones) => Ok(Json(
+            zones
+                .into_iter()
+                .filter_map(|zone| {
+                    zone.id.map(|id| OrphanedZone {
+                        id,
+                        name: zone.name,
+                    })
+                })
+                .collect(),
+        )),
+        Err(err) => {
+            log::error!("Failed to get orphaned zones: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Give orphaned zones to a user, any that have an owner by now are skipped
+#[utoipa::path(
+    post,
+    path = "/api/admin/orphans/assign",
+    operation_id = "admin_orphans_assign",
+    request_body = AssignOrphansRequest,
+    responses(
+        (status = 200, description = "Successful", body = AssignOrphansResponse),
+        (status = 400, description = "There's no user with that name"),
+        (status = 403, description = "Auth failed, or you're not an admin"),
+    ),
+    tag = "Admin",
+)]
+pub(crate) async fn api_orphans_assign(
+    State(state): State<GoatState>,
+    session: Session,
+    Json(payload): Json<AssignOrphansRequest>,
+) -> Result<Json<AssignOrphansResponse>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    check_admin!(user);
+
+    let mut txn = match state.connpool().await.begin().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let owner = match User::get_by_name(&mut txn, &payload.username).await {
+        Ok(Some(owner)) => owner,
+        Ok(None) => {
+            return error_result_json!(
+                format!("There's no user called {:?}", payload.username).as_str(),
+                StatusCode::BAD_REQUEST
+            )
+        }
+        Err(err) => {
+            log::error!("Failed to get user {:?}: {err:?}", payload.username);
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let Some(ownerid) = owner.id else {
+        log::error!("User {:?} doesn't have an ID", payload.username);
+        return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+    };
+
+    let assigned = match FileZone::assign_orphans(&mut txn, &payload.zones, ownerid).await {
+        Ok(assigned) => assigned,
+        Err(err) => {
+            log::error!("Failed to assign orphaned zones: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    if let Err(err) = txn.commit().await {
+        log::error!("Failed to commit orphaned zone assignment: {err:?}");
+        return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    log::info!(
+        "id={:?} action=assign_orphans owner={} zones={assigned:?}",
+        user.id,
+        payload.username
+    );
+    Ok(Json(AssignOrphansResponse { assigned }))
+}
@@ -0,0 +1,170 @@
+//! API endpoints for users to look after their own API tokens.
+//!
+//! Token secrets are only ever handed out once, when the token's created or rotated - the database only has a hash
+//! of them.
+
+use crate::db::{DBEntity, User, UserAuthToken};
+use crate::error_result_json;
+use crate::web::utils::{rotate_api_token, ApiToken};
+use chrono::{DateTime, Utc};
+use goatns_macros::check_api_auth;
+use tower_sessions::Session;
+use utoipa::ToSchema;
+
+use super::*;
+
+/// A token, without anything secret in it
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ApiTokenSummary {
+    pub id: Option<i64>,
+    pub name: String,
+    pub tokenkey: String,
+    #[schema(value_type = String)]
+    pub issued: DateTime<Utc>,
+    #[schema(value_type = Option<String>)]
+    pub expiry: Option<DateTime<Utc>>,
+    #[schema(value_type = Option<String>)]
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl From<&UserAuthToken> for ApiTokenSummary {
+    fn from(token: &UserAuthToken) -> Self {
+        Self {
+            id: token.id,
+            name: token.name.clone(),
+            tokenkey: token.tokenkey.clone(),
+            issued: token.issued,
+            expiry: token.expiry,
+            last_used: token.last_used,
+        }
+    }
+}
+
+/// A token's new secret, this is the only time it's shown
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ApiTokenSecret {
+    #[serde(flatten)]
+    pub token: ApiTokenSummary,
+    pub token_secret: String,
+}
+
+/// Give the token a new secret, the old one stops working straight away. Users can only rotate their own tokens.
+pub(crate) async fn rotate_token(
+    state: &GoatState,
+    user: &User,
+    id: i64,
+) -> Result<(Box<UserAuthToken>, ApiToken), (StatusCode, Json<ErrorResult>)> {
+    let (pool, api_cookie_secret) = {
+        let reader = state.read().await;
+        (
+            reader.connpool.clone(),
+            reader.config.api_cookie_secret().to_vec(),
+        )
+    };
+
+    let token = match UserAuthToken::get(&pool, id).await {
+        Ok(val) if Some(val.userid) == user.id => val,
+        Ok(_) | Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            return error_result_json!("Token not found", StatusCode::NOT_FOUND);
+        }
+        Err(err) => {
+            error!("Failed to get token id={id}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let api_token = rotate_api_token(&api_cookie_secret, &token);
+    let mut txn = match pool.begin().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let token = match token
+        .rotate_with_txn(
+            &mut txn,
+            api_token.issued,
+            api_token.expiry,
+            api_token.token_hash.clone(),
+        )
+        .await
+    {
+        Ok(val) => val,
+        Err(err) => {
+            error!("Failed to rotate token id={id}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    if let Err(err) = txn.commit().await {
+        error!("Failed to commit transaction rotating token id={id}: {err:?}");
+        return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    log::info!("id={} action=api_token_rotate token_id={id}", token.userid);
+    Ok((token, api_token))
+}
+
+/// List your tokens
+#[utoipa::path(
+    get,
+    path = "/api/tokens",
+    operation_id = "token_list",
+    responses(
+        (status = 200, description = "Successful", body = [ApiTokenSummary]),
+        (status = 403, description = "Auth failed"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Authentication",
+)]
+pub(crate) async fn api_list(
+    State(state): State<GoatState>,
+    session: Session,
+) -> Result<Json<Vec<ApiTokenSummary>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    let userid = match user.id {
+        Some(val) => val,
+        None => {
+            return error_result_json!("No user id found in session", StatusCode::UNAUTHORIZED);
+        }
+    };
+
+    match UserAuthToken::get_all_user(&state.connpool().await, userid).await {
+        Ok(tokens) => Ok(Json(
+            tokens
+                .iter()
+                .map(|token| ApiTokenSummary::from(token.as_ref()))
+                .collect(),
+        )),
+        Err(err) => {
+            error!("Failed to list tokens for user id={userid}: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Give one of your tokens a new secret
+#[utoipa::path(
+    post,
+    path = "/api/token/{id}/rotate",
+    operation_id = "token_rotate",
+    params(("id" = i64, Path, description = "The token's ID")),
+    responses(
+        (status = 200, description = "Successful, this is the only time the secret's shown", body = ApiTokenSecret),
+        (status = 403, description = "Auth failed"),
+        (status = 404, description = "Token not found"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Authentication",
+)]
+pub(crate) async fn api_rotate(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> Result<Json<ApiTokenSecret>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    let (token, api_token) = rotate_token(&state, &user, id).await?;
+    Ok(Json(ApiTokenSecret {
+        token: ApiTokenSummary::from(token.as_ref()),
+        token_secret: api_token.token_secret,
+    }))
+}
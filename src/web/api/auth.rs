@@ -5,7 +5,10 @@ use tower_sessions::Session;
 use tracing::error;
 use utoipa::ToSchema;
 
-use crate::db::User;
+use crate::db::{User, UserAuthToken};
+use crate::error_result_json;
+use crate::web::api::ErrorResult;
+use crate::web::middleware::rate_limit::SESSION_TOKEN_KEY;
 use crate::web::utils::validate_api_token;
 use crate::web::GoatState;
 
@@ -33,8 +36,8 @@ impl From<String> for AuthResponse {
     request_body = AuthPayload,
     responses(
         (status = 200, description = "Login Successful"),
-        (status = 403, description = "Auth failed"),
-        (status = 500, description = "Something broke!"),
+        (status = 401, description = "Auth failed", body = ErrorResult),
+        (status = 500, description = "Something broke!", body = ErrorResult),
     ),
     tag = "Authentication",
 )]
@@ -42,7 +45,7 @@ pub async fn login(
     State(state): State<GoatState>,
     session: Session,
     payload: Json<AuthPayload>,
-) -> Result<(StatusCode, Json<AuthResponse>), (StatusCode, Json<AuthResponse>)> {
+) -> Result<(StatusCode, Json<AuthResponse>), (StatusCode, Json<ErrorResult>)> {
     #[cfg(test)]
     println!("Got login payload: {payload:?}");
     #[cfg(not(test))]
@@ -60,13 +63,13 @@ pub async fn login(
                 error!("Failed to flush session: {err:?}");
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(AuthResponse::from("Failed to flush session!".to_string())),
+                    Json(ErrorResult::new(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to flush session!",
+                    )),
                 )
             })?;
-            let resp = AuthResponse {
-                message: "token not found".to_string(),
-            };
-            return Err((StatusCode::UNAUTHORIZED, Json(resp)));
+            return error_result_json!("token not found", StatusCode::UNAUTHORIZED);
         }
     };
 
@@ -76,23 +79,38 @@ pub async fn login(
             let session_user = session.insert("user", &token.user).await;
             let session_authref = session.insert("authref", token.user.authref).await;
             let session_signin = session.insert("signed_in", true).await;
+            // so the token gets its own rate limit
+            let session_token = session.insert(SESSION_TOKEN_KEY, &payload.token_key).await;
 
-            if session_authref.is_err() | session_user.is_err() | session_signin.is_err() {
+            if session_authref.is_err()
+                | session_user.is_err()
+                | session_signin.is_err()
+                | session_token.is_err()
+            {
                 session.flush().await.map_err(|err| {
                     error!("Failed to flush session: {err:?}");
                     (
                         StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(AuthResponse::from("Failed to flush session!".to_string())),
+                        Json(ErrorResult::new(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            "Failed to flush session!",
+                        )),
                     )
                 })?;
                 log::info!("action=api_login tokenkey={} result=failure reason=\"failed to store session for user\"", payload.token_key);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(AuthResponse {
-                        message: "system failure, please contact an admin".to_string(),
-                    }),
-                ));
+                return error_result_json!(
+                    "system failure, please contact an admin",
+                    StatusCode::INTERNAL_SERVER_ERROR
+                );
             };
+            if let Err(err) =
+                UserAuthToken::record_use(&pool, &payload.token_key, chrono::Utc::now()).await
+            {
+                log::warn!(
+                    "Failed to record the use of token {}: {err:?}",
+                    payload.token_key
+                );
+            }
             log::info!("action=api_login user={} result=success", payload.token_key);
             Ok((
                 StatusCode::OK,
@@ -106,7 +124,10 @@ pub async fn login(
                 error!("Failed to flush session: {err:?}");
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(AuthResponse::from("Failed to flush session!".to_string())),
+                    Json(ErrorResult::new(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to flush session!",
+                    )),
                 )
             })?;
             #[cfg(test)]
@@ -117,12 +138,7 @@ pub async fn login(
         token.user.id.map(|id| id.to_string()).unwrap_or("<unknown user id>".to_string()),
         payload.token_key,
         );
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(AuthResponse {
-                    message: "system failure, please contact an admin".to_string(),
-                }),
-            ))
+            error_result_json!("token not found", StatusCode::UNAUTHORIZED)
         }
     }
 }
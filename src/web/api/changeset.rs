@@ -0,0 +1,276 @@
+//! API endpoints for reviewing changes to zones which have `review_required` set.
+//!
+//! Anyone logged in can propose a change-set, only the zone's owners (or admins) can list, approve or reject them.
+
+use crate::db::{DBEntity, User, ZoneOwnership};
+use crate::error_result_json;
+use crate::zone_events::ZoneEvent;
+use crate::zones::{ChangeSetStatus, FileZone, RecordChange, ZoneChangeSet};
+use goatns_macros::check_api_auth;
+use sqlx::SqliteConnection;
+use tower_sessions::Session;
+
+use super::*;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ChangeSetProposal {
+    #[serde(default)]
+    pub description: String,
+    pub changes: Vec<RecordChange>,
+}
+
+/// Checks the user owns the zone, admins count as owners
+pub(crate) async fn user_can_review(
+    txn: &mut SqliteConnection,
+    user: &User,
+    zoneid: i64,
+) -> Result<bool, GoatNsError> {
+    if user.admin {
+        return Ok(true);
+    }
+    let userid = match user.id {
+        Some(val) => val,
+        None => return Ok(false),
+    };
+    Ok(
+        ZoneOwnership::get_ownership_by_userid(txn, &userid, &zoneid)
+            .await?
+            .is_some(),
+    )
+}
+
+/// Propose changes to a zone
+pub(crate) async fn api_propose(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(zoneid): Path<i64>,
+    Json(proposal): Json<ChangeSetProposal>,
+) -> Result<Json<Box<ZoneChangeSet>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    let userid = match user.id {
+        Some(val) => val,
+        None => {
+            return error_result_json!("No user id found in session", StatusCode::UNAUTHORIZED);
+        }
+    };
+    if proposal.changes.is_empty() {
+        return error_result_json!("No changes specified", StatusCode::BAD_REQUEST);
+    }
+
+    let mut txn = match state.connpool().await.begin().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let zone = match FileZone::get_with_txn(&mut txn, &zoneid).await {
+        Ok(val) => val,
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            return error_result_json!("Zone not found", StatusCode::NOT_FOUND);
+        }
+        Err(err) => {
+            error!("Failed to get zone id={zoneid}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    if !zone.review_required {
+        return error_result_json!(
+            "This zone doesn't take change-sets",
+            StatusCode::BAD_REQUEST
+        );
+    }
+    check_zone_editable(&mut txn, zoneid).await?;
+
+    let changeset = ZoneChangeSet {
+        id: None,
+        zoneid,
+        userid,
+        description: proposal.description,
+        created: chrono::Utc::now(),
+        status: ChangeSetStatus::Pending,
+        changes: proposal.changes,
+    };
+
+    let res = match changeset.save_with_txn(&mut txn).await {
+        Ok(val) => val,
+        Err(err) => {
+            error!("Failed to save change-set for zone id={zoneid}: {err:?}");
+            return error_result_json!(
+                "Server error saving change-set!",
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+    };
+    if let Err(err) = txn.commit().await {
+        error!("Failed to commit transaction saving change-set: {err:?}");
+        return error_result_json!(
+            "Server error saving change-set!",
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+    log::info!(
+        "action=changeset_propose user={userid} zone={zoneid} changeset={:?}",
+        res.id
+    );
+    Ok(Json(res))
+}
+
+/// List the pending change-sets for a zone, owners only
+pub(crate) async fn api_list(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(zoneid): Path<i64>,
+) -> Result<Json<Vec<ZoneChangeSet>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+
+    let mut conn = match state.connpool().await.acquire().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    match user_can_review(&mut conn, &user, zoneid).await {
+        Ok(true) => {}
+        Ok(false) => return error_result_json!("", StatusCode::FORBIDDEN),
+        Err(err) => {
+            error!("Failed to check ownership of zone id={zoneid}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    match ZoneChangeSet::get_pending_for_zone(&mut conn, zoneid).await {
+        Ok(val) => Ok(Json(val)),
+        Err(err) => {
+            error!("Failed to list change-sets for zone id={zoneid}: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Approve or reject a change-set
+pub(crate) async fn review_changeset(
+    state: &GoatState,
+    user: &User,
+    id: i64,
+    approve: bool,
+) -> Result<Box<ZoneChangeSet>, (StatusCode, Json<ErrorResult>)> {
+    let mut txn = match state.connpool().await.begin().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let changeset = match ZoneChangeSet::get_with_txn(&mut txn, &id).await {
+        Ok(val) => val,
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            return error_result_json!("Change-set not found", StatusCode::NOT_FOUND);
+        }
+        Err(err) => {
+            error!("Failed to get change-set id={id}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    match user_can_review(&mut txn, user, changeset.zoneid).await {
+        Ok(true) => {}
+        Ok(false) => return error_result_json!("", StatusCode::FORBIDDEN),
+        Err(err) => {
+            error!(
+                "Failed to check ownership of zone id={}: {err:?}",
+                changeset.zoneid
+            );
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    // rejecting's fine, it doesn't change the zone
+    if approve {
+        check_zone_editable(&mut txn, changeset.zoneid).await?;
+    }
+
+    let res = match approve {
+        true => changeset.approve_with_txn(&mut txn).await,
+        false => changeset.reject_with_txn(&mut txn).await,
+    };
+    let res = match res {
+        Ok(val) => val,
+        Err(GoatNsError::InvalidValue(msg)) => {
+            return error_result_json!(msg.as_str(), StatusCode::BAD_REQUEST);
+        }
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            return error_result_json!(
+                "A record in the change-set no longer exists",
+                StatusCode::CONFLICT
+            );
+        }
+        Err(err) => {
+            error!("Failed to review change-set id={id}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    // approving moved the serial on, anyone watching the zone needs to know where to
+    let serial = match approve {
+        true => match FileZone::get_serial_with_txn(changeset.zoneid, &mut txn).await {
+            Ok(val) => Some(val),
+            Err(err) => {
+                error!(
+                    "Failed to get the serial for zoneid={}: {err:?}",
+                    changeset.zoneid
+                );
+                return error_result_json!(
+                    "Internal server error",
+                    StatusCode::INTERNAL_SERVER_ERROR
+                );
+            }
+        },
+        false => None,
+    };
+
+    if let Err(err) = txn.commit().await {
+        error!("Failed to commit transaction reviewing change-set id={id}: {err:?}");
+        return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    if let Some(serial) = serial {
+        zone_changed(
+            state,
+            ZoneEvent::SerialBumped {
+                zoneid: changeset.zoneid,
+                serial,
+            },
+        )
+        .await;
+    }
+    log::info!(
+        "action=changeset_review user={:?} changeset={id} status={}",
+        user.id,
+        res.status
+    );
+    Ok(res)
+}
+
+/// Approve a change-set, applying it to the zone
+pub(crate) async fn api_approve(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> Result<Json<Box<ZoneChangeSet>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    review_changeset(&state, &user, id, true).await.map(Json)
+}
+
+/// Reject a change-set
+pub(crate) async fn api_reject(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> Result<Json<Box<ZoneChangeSet>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    review_changeset(&state, &user, id, false).await.map(Json)
+}
@@ -1,19 +1,76 @@
 use utoipa::{Modify, OpenApi};
 
-use crate::zones::FileZoneRecord;
+use crate::zones::{FileZoneRecord, ZoneTemplate};
 use crate::RecordClass;
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
+        super::admin::api_trace_get,
+        super::admin::api_trace_start,
+        super::admin::api_trace_stop,
+        super::admin::api_cache_purge,
+        super::admin::api_zone_reload,
+        super::admin::api_export,
+        super::admin::api_restore,
+        super::admin::api_orphans_get,
+        super::admin::api_orphans_assign,
         super::auth::login,
+        super::apitoken::api_list,
+        super::apitoken::api_rotate,
         super::filezonerecord::api_create,
+        super::settings::api_get,
+        super::settings::api_update,
+        super::filezonerecord::api_search,
+        super::lookup::api_lookup,
+        super::wizard::api_caa,
+        super::wizard::api_caa_presets,
+        super::wizard::api_loc,
+        super::wizard::api_spf,
+        super::wizard::api_dkim,
+        super::wizard::api_dmarc,
+        super::zonetemplate::api_list,
+        super::zonetemplate::api_create,
+        super::zonetemplate::api_update,
+        super::zonetemplate::api_get,
+        super::zonetemplate::api_delete,
     ),
     components(
         schemas(
+            super::admin::TraceRequest,
+            super::admin::CachePurgeRequest,
+            super::admin::CachePurgeResponse,
+            super::admin::ZoneReloadResponse,
+            super::admin::BackupArchive,
+            super::admin::OrphanedZone,
+            super::admin::AssignOrphansRequest,
+            super::admin::AssignOrphansResponse,
+            crate::zones::ImportSummary,
+            crate::query_trace::TraceStatus,
+            crate::query_trace::TraceEntry,
             super::auth::AuthPayload,
             super::auth::AuthResponse,
+            super::ErrorResult,
+            super::lookup::AddressLookup,
+            super::apitoken::ApiTokenSummary,
+            super::apitoken::ApiTokenSecret,
+            crate::db::UserSettings,
+            super::wizard::CaaWizardRequest,
+            super::wizard::LocWizardRequest,
+            crate::resourcerecord::DecimalLocation,
+            super::wizard::WizardResult,
+            crate::wizards::caa::CaPreset,
+            crate::wizards::caa::CaaRecord,
+            crate::wizards::caa::CaaTag,
+            crate::wizards::mail::SpfRecord,
+            crate::wizards::mail::SpfAll,
+            crate::wizards::mail::DkimRecord,
+            crate::wizards::mail::DkimKeyType,
+            crate::wizards::mail::DmarcRecord,
+            crate::wizards::mail::DmarcPolicy,
+            crate::wizards::mail::DmarcAlignment,
             FileZoneRecord,
+            ZoneTemplate,
             RecordClass,
         )
     ),
@@ -22,6 +79,9 @@ use crate::RecordClass;
         (name = "Authentication", description = "Authentication-related tasks"),
         (name = "Records", description = "DNS Record operations"),
         (name = "Zones", description = "DNS Zone operations"),
+        (name = "Templates", description = "Zone template operations"),
+        (name = "Admin", description = "Server administration"),
+        (name = "Settings", description = "Your own preferences"),
     )
 )]
 pub(crate) struct ApiDoc;
@@ -1,16 +1,31 @@
 use super::*;
+use crate::config::ConfigFile;
+use crate::datastore::Command;
 use crate::db::DBEntity;
 
 use crate::db::User;
+use crate::db::UserQuota;
 use crate::db::ZoneOwnership;
 use crate::error_result_json;
-use crate::utils::check_valid_tld;
-use crate::zones::FileZone;
+use crate::interop::from_bind;
+use crate::name_policy::{normalise, NamePolicy};
+use crate::zone_events::{self, ZoneEvent};
+use crate::zone_schema::zones_from_json;
+use crate::zones::{
+    FileZone, ImportStrategy, ImportSummary, ZoneDiff, ZoneRegistration, ZoneTemplate,
+};
 use axum::extract::Path;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Response;
 use axum::Json;
+use futures::Stream;
 use goatns_macros::check_api_auth;
 use serde::Deserialize;
 use serde::Serialize;
+use sqlx::SqliteConnection;
+use tokio::sync::broadcast::error::RecvError;
 use tower_sessions::Session;
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -20,15 +35,96 @@ pub struct FileZoneResponse {
     pub id: Option<i64>,
 }
 
+/// A batch of zones to import, in GoatNS JSON format
+#[derive(Deserialize, Debug)]
+pub(crate) struct ZoneImport {
+    pub zones: Vec<FileZone>,
+    #[serde(default)]
+    pub strategy: ImportStrategy,
+    /// Work out what would happen, but don't save anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// The [NamePolicy] for checking zone names, with the reserved names from the database
+pub(crate) async fn load_name_policy(
+    txn: &mut SqliteConnection,
+    config: &ConfigFile,
+) -> Result<NamePolicy, (StatusCode, Json<ErrorResult>)> {
+    match NamePolicy::load(txn, config).await {
+        Ok(val) => Ok(val),
+        Err(err) => {
+            log::error!("Failed to load the reserved zone names: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Turns a [NamePolicy::check] failure into a response
+pub(crate) fn name_policy_error<T>(
+    err: &GoatNsError,
+) -> Result<T, (StatusCode, Json<ErrorResult>)> {
+    match err {
+        GoatNsError::InvalidValue(msg) => error_result_json!(msg.as_str(), StatusCode::BAD_REQUEST),
+        err => {
+            log::error!("Failed to check zone name: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// A new zone, which can start off with the records from a [ZoneTemplate]
+#[derive(Deserialize, Debug)]
+pub(crate) struct NewZone {
+    #[serde(flatten)]
+    pub zone: FileZone,
+    /// The ID of the template to add records from, they go in alongside any records the zone comes with
+    #[serde(default)]
+    pub template_id: Option<i64>,
+}
+
 pub(crate) async fn api_create(
     State(state): State<GoatState>,
     session: Session,
-    Json(zone): Json<FileZone>,
+    Json(new_zone): Json<NewZone>,
 ) -> Result<Json<Box<FileZone>>, (StatusCode, Json<ErrorResult>)> {
     check_api_auth!();
 
-    if !check_valid_tld(&zone.name, &state.read().await.config.allowed_tlds) {
-        return error_result_json!("Invalid TLD for this system", StatusCode::BAD_REQUEST);
+    let mut zone = new_zone.zone;
+    // the template's records get checked along with the rest of the zone's
+    if let Some(template_id) = new_zone.template_id {
+        let template = match ZoneTemplate::get(&state.connpool().await, template_id).await {
+            Ok(val) => val,
+            Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+                return error_result_json!("Template not found", StatusCode::BAD_REQUEST);
+            }
+            Err(err) => {
+                log::error!("Failed to get zone template {template_id}: {err:?}");
+                return error_result_json!(
+                    "Internal server error",
+                    StatusCode::INTERNAL_SERVER_ERROR
+                );
+            }
+        };
+        log::debug!(
+            "Applying template {} to new zone {}",
+            template.name,
+            zone.name
+        );
+        zone.records.extend(template.apply(&zone.name));
+    }
+
+    if let Err(err) = zone.normalise_names() {
+        return Err(api_error(err));
+    }
+    zone.apply_defaults(&state.read().await.config);
+    let ttl_policy = state.read().await.config.import_ttl.clone();
+    if let Err(GoatNsError::InvalidValue(msg)) = zone.check_default_ttl(&ttl_policy) {
+        return error_result_json!(msg.as_str(), StatusCode::BAD_REQUEST);
+    }
+    zone.fill_default_ttls(ttl_policy.default_ttl);
+    if let Err(GoatNsError::InvalidValue(msg)) = zone.check_cname_rules() {
+        return error_result_json!(msg.as_str(), StatusCode::BAD_REQUEST);
     }
 
     // check to see if the zone exists
@@ -42,6 +138,19 @@ pub(crate) async fn api_create(
             );
         }
     };
+    let name_policy = load_name_policy(&mut txn, &state.read().await.config).await?;
+    if let Err(err) = name_policy.check(&zone.name) {
+        return name_policy_error(&err);
+    }
+    let quotas = state.read().await.config.quotas;
+    if let Err(err) = UserQuota::check_zones(&mut txn, &user, &quotas, 1).await {
+        return Err(api_error(err));
+    }
+    if let Err(err) =
+        UserQuota::check_records(&mut txn, &user, &quotas, None, zone.records.len()).await
+    {
+        return Err(api_error(err));
+    }
 
     match FileZone::get_by_name(&mut txn, &zone.name).await {
         Ok(Some(_)) => {
@@ -84,6 +193,7 @@ pub(crate) async fn api_create(
             StatusCode::INTERNAL_SERVER_ERROR
         );
     }
+    forget_cached_records(&state).await;
     // start a new transaction!
     let mut txn = match state.connpool().await.begin().await {
         Ok(val) => val,
@@ -169,7 +279,7 @@ pub(crate) async fn api_create(
 pub(crate) async fn api_update(
     State(state): State<GoatState>,
     session: Session,
-    Json(zone): Json<FileZone>,
+    Json(mut zone): Json<FileZone>,
 ) -> Result<Json<String>, (StatusCode, Json<ErrorResult>)> {
     check_api_auth!();
 
@@ -179,8 +289,16 @@ pub(crate) async fn api_update(
             return error_result_json!("No zone ID specified", StatusCode::BAD_REQUEST);
         }
     };
-    if !check_valid_tld(&zone.name, &state.read().await.config.allowed_tlds) {
-        return error_result_json!("Invalid TLD for this system", StatusCode::BAD_REQUEST);
+    if let Err(err) = zone.normalise_names() {
+        return Err(api_error(err));
+    }
+    let ttl_policy = state.read().await.config.import_ttl.clone();
+    if let Err(GoatNsError::InvalidValue(msg)) = zone.check_default_ttl(&ttl_policy) {
+        return error_result_json!(msg.as_str(), StatusCode::BAD_REQUEST);
+    }
+    zone.fill_default_ttls(ttl_policy.default_ttl);
+    if let Err(GoatNsError::InvalidValue(msg)) = zone.check_cname_rules() {
+        return error_result_json!(msg.as_str(), StatusCode::BAD_REQUEST);
     }
 
     // get a db transaction
@@ -211,12 +329,37 @@ pub(crate) async fn api_update(
         println!("Failed to validate user owns zone: {err:?}");
         return Err((
             StatusCode::UNAUTHORIZED,
-            Json(ErrorResult {
-                message: "".to_string(),
-            }),
+            Json(ErrorResult::new(StatusCode::UNAUTHORIZED, "")),
         ));
     };
     println!("looks like user owns zone");
+    check_zone_editable(&mut txn, zone_id).await?;
+
+    // only renaming a zone has to pass the name policy, so zones from before a name was reserved can still be edited
+    match FileZone::get_with_txn(&mut txn, &zone_id).await {
+        Ok(existing) if normalise(&existing.name) == zone.name => {}
+        Ok(_) => {
+            let name_policy = load_name_policy(&mut txn, &state.read().await.config).await?;
+            if let Err(err) = name_policy.check(&zone.name) {
+                return name_policy_error(&err);
+            }
+        }
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            return error_result_json!("Zone not found", StatusCode::NOT_FOUND);
+        }
+        Err(err) => {
+            log::error!("Failed to get zoneid={zone_id}: {err:?}");
+            return error_result_json!("failed to save zone", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    let current_serial = match FileZone::get_serial_with_txn(zone_id, &mut txn).await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("Failed to get the serial for zoneid={zone_id}: {err:?}");
+            return error_result_json!("failed to save zone", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
 
     // save the zone data
 
@@ -225,21 +368,45 @@ pub(crate) async fn api_update(
         println!("Failed to save zone: {err:?}");
         return Err((
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResult {
-                message: "failed to save zone".to_string(),
-            }),
+            Json(ErrorResult::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to save zone",
+            )),
         ));
     };
+    // if they didn't move the serial on, do it for them so secondaries and API clients see the change
+    if !zone.is_newer_than(current_serial) {
+        if let Err(err) = FileZone::bump_serial_with_txn(zone_id, &mut txn).await {
+            log::error!("Failed to bump the serial for zoneid={zone_id}: {err:?}");
+            return error_result_json!("failed to save zone", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    let serial = match FileZone::get_serial_with_txn(zone_id, &mut txn).await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("Failed to get the serial for zoneid={zone_id}: {err:?}");
+            return error_result_json!("failed to save zone", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
     if let Err(err) = txn.commit().await {
         // TODO: make this a better log
         println!("Failed to commit transaction while saving zone: {err:?}");
         return Err((
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResult {
-                message: "failed to save zone".to_string(),
-            }),
+            Json(ErrorResult::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to save zone",
+            )),
         ));
     };
+    zone_changed(
+        &state,
+        ZoneEvent::SerialBumped {
+            zoneid: zone_id,
+            serial,
+        },
+    )
+    .await;
     Ok(Json("success".to_string()))
 }
 
@@ -285,6 +452,8 @@ pub(crate) async fn api_delete(
         }
     };
 
+    check_zone_editable(&mut txn, id).await?;
+
     // get the zone
     let zone = match FileZone::get_with_txn(&mut txn, &id).await {
         Ok(val) => val,
@@ -315,14 +484,194 @@ pub(crate) async fn api_delete(
         );
         return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
     };
+    forget_cached_records(&state).await;
     res
 }
 
+/// Body for turning a zone on or off
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct ZoneEnabled {
+    pub enabled: bool,
+}
+
+/// Turn a zone on or off, queries for a disabled zone get `disabled_zone_rcode` but its records are kept
+pub(crate) async fn api_set_enabled(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(id): Path<i64>,
+    Json(payload): Json<ZoneEnabled>,
+) -> Result<Json<ZoneEnabled>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+
+    let mut conn = match state.connpool().await.acquire().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!(
+                "Failed to get a connection to the database!",
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+    };
+    match changeset::user_can_review(&mut conn, &user, id).await {
+        Ok(true) => {}
+        Ok(false) => return error_result_json!("", StatusCode::FORBIDDEN),
+        Err(err) => {
+            log::error!("Failed to check ownership of zoneid={id}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    drop(conn);
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let cmd = Command::SetZoneEnabled {
+        zoneid: id,
+        enabled: payload.enabled,
+        resp: tx,
+    };
+    if let Err(err) = state.read().await.tx.send(cmd.into()).await {
+        log::error!("Error sending message to datastore: {err:?}");
+        return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    match rx.await {
+        Ok(Ok(())) => {
+            log::info!(
+                "id={:?} action=zone_set_enabled zoneid={id} enabled={}",
+                user.id,
+                payload.enabled
+            );
+            Ok(Json(payload))
+        }
+        Ok(Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound))) => {
+            error_result_json!("Zone not found", StatusCode::NOT_FOUND)
+        }
+        Ok(Err(err)) => {
+            log::error!("Failed to set enabled for zoneid={id}: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        Err(err) => {
+            log::error!("Datastore didn't respond: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Body for making a zone public or not
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct ZonePublic {
+    pub public: bool,
+}
+
+/// Make a zone show up in `/rdap`, or stop it
+pub(crate) async fn api_set_public(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(id): Path<i64>,
+    Json(payload): Json<ZonePublic>,
+) -> Result<Json<ZonePublic>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+
+    let mut conn = match state.connpool().await.acquire().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!(
+                "Failed to get a connection to the database!",
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+    };
+    match changeset::user_can_review(&mut conn, &user, id).await {
+        Ok(true) => {}
+        Ok(false) => return error_result_json!("", StatusCode::FORBIDDEN),
+        Err(err) => {
+            log::error!("Failed to check ownership of zoneid={id}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    match FileZone::set_public_with_txn(id, payload.public, &mut conn).await {
+        Ok(()) => {
+            log::info!(
+                "id={:?} action=zone_set_public zoneid={id} public={}",
+                user.id,
+                payload.public
+            );
+            Ok(Json(payload))
+        }
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            error_result_json!("Zone not found", StatusCode::NOT_FOUND)
+        }
+        Err(err) => {
+            log::error!("Failed to set public for zoneid={id}: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Set a zone's registrar, domain expiry and notes, anything left out is cleared
+pub(crate) async fn api_set_registration(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(id): Path<i64>,
+    Json(payload): Json<ZoneRegistration>,
+) -> Result<Json<ZoneRegistration>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+
+    let registration = match payload.check() {
+        Ok(val) => val,
+        Err(GoatNsError::InvalidValue(err)) => {
+            return error_result_json!(err.as_str(), StatusCode::BAD_REQUEST)
+        }
+        Err(err) => {
+            log::error!("Failed to check registration for zoneid={id}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let mut conn = match state.connpool().await.acquire().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!(
+                "Failed to get a connection to the database!",
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+    };
+    match changeset::user_can_review(&mut conn, &user, id).await {
+        Ok(true) => {}
+        Ok(false) => return error_result_json!("", StatusCode::FORBIDDEN),
+        Err(err) => {
+            log::error!("Failed to check ownership of zoneid={id}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    match FileZone::set_registration_with_txn(id, &registration, &mut conn).await {
+        Ok(()) => {
+            log::info!(
+                "id={:?} action=zone_set_registration zoneid={id} registrar={:?} expires={:?}",
+                user.id,
+                registration.registrar,
+                registration.expires
+            );
+            Ok(Json(registration))
+        }
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            error_result_json!("Zone not found", StatusCode::NOT_FOUND)
+        }
+        Err(err) => {
+            log::error!("Failed to set registration for zoneid={id}: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Get a zone, it comes with an ETag based on the serial so clients can send `If-None-Match` and get a 304 if it hasn't changed
 pub(crate) async fn api_get(
     State(state): State<GoatState>,
     session: Session,
+    headers: HeaderMap,
     Path(id): Path<i64>,
-) -> Result<Json<Box<FileZone>>, (StatusCode, Json<ErrorResult>)> {
+) -> Result<Response, (StatusCode, Json<ErrorResult>)> {
     check_api_auth!();
 
     let mut txn = match state.connpool().await.begin().await {
@@ -352,8 +701,26 @@ pub(crate) async fn api_get(
         return error_result_json!("", StatusCode::UNAUTHORIZED);
     };
 
+    // no point loading all the records if they've already got them
+    let etag = match FileZone::get_serial_with_txn(id, &mut txn).await {
+        Ok(serial) => serial_etag("zone", id, serial),
+        Err(err) => {
+            error!(
+                "Couldn't get the serial for zone id {}: error: {:?}",
+                id, err
+            );
+            return error_result_json!(
+                format!("Couldn't get zone id {}", id).as_ref(),
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+    };
+    if etag_matches(&headers, &etag) {
+        return Ok(etag_response(&headers, etag, ()));
+    }
+
     log::debug!("Searching for zone id {id:?}");
-    let zone = match FileZone::get(&state.connpool().await, id).await {
+    let zone = match FileZone::get_with_txn(&mut txn, &id).await {
         Ok(val) => val,
         Err(err) => {
             error!("Couldn't get zone id {}: error: {:?}", id, err);
@@ -364,5 +731,328 @@ pub(crate) async fn api_get(
         }
     };
 
-    Ok(Json::from(zone))
+    // the serial might've moved on since we looked
+    let etag = serial_etag("zone", id, zone.serial);
+    Ok(etag_response(&headers, etag, zone))
+}
+
+/// A stream of [ZoneEvent]s for the zone as it's changed, as server-sent events named after the `event` in their JSON.
+/// If the connection falls behind and events are dropped, it gets a `lagged` event with how many it missed, and
+/// should load the zone again.
+pub(crate) async fn api_events(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+
+    let user_id = match user.id {
+        Some(val) => val,
+        None => {
+            log::error!("User id not found in session, something went wrong");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let mut conn = match state.connpool().await.acquire().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!(
+                "Failed to get a connection to the database!",
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+    };
+    if ZoneOwnership::get_ownership_by_userid(&mut conn, &user_id, &id)
+        .await
+        .is_err()
+    {
+        log::error!("User {:?} not authorized for zoneid={}", &user_id, id);
+        return error_result_json!("", StatusCode::UNAUTHORIZED);
+    };
+    drop(conn);
+
+    let stream = futures::stream::unfold(zone_events::subscribe(), move |mut rx| async move {
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) if event.zoneid() == id => {
+                    Event::default().event(event.kind()).json_data(&event)
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    Ok(Event::default().event("lagged").data(missed.to_string()))
+                }
+                Err(RecvError::Closed) => return None,
+            };
+            return Some((event, rx));
+        }
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Import zones, all in one transaction. Existing zones need to be owned by the user, new ones are owned by them afterwards.
+pub(crate) async fn api_import(
+    State(state): State<GoatState>,
+    session: Session,
+    Json(import): Json<ZoneImport>,
+) -> Result<Json<ImportSummary>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+
+    let userid = match user.id {
+        Some(val) => val,
+        None => {
+            log::error!("User id not found in session, something went wrong");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    if import.zones.is_empty() {
+        return error_result_json!("No zones to import", StatusCode::BAD_REQUEST);
+    }
+    let config = state.read().await.config.clone();
+
+    let mut txn = match state.connpool().await.begin().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!(
+                "Failed to get a connection to the database!",
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+    };
+
+    let mut summary = ImportSummary {
+        dry_run: import.dry_run,
+        ..Default::default()
+    };
+    let name_policy = load_name_policy(&mut txn, &config).await?;
+    for mut zone in import.zones {
+        if let Err(err) = zone.normalise_names() {
+            return Err(api_error(err));
+        }
+        if let Err(err) = name_policy.check(&zone.name) {
+            return name_policy_error(&err);
+        }
+        zone.id = None;
+        let existing = match FileZone::get_by_name(&mut txn, &zone.name).await {
+            Ok(val) => val,
+            Err(err) => {
+                log::error!("Couldn't get zone {} during import: {err:?}", zone.name);
+                return error_result_json!(
+                    "Server error querying zone!",
+                    StatusCode::INTERNAL_SERVER_ERROR
+                );
+            }
+        };
+        match existing.as_ref().and_then(|ez| ez.id) {
+            Some(zoneid) => match changeset::user_can_review(&mut txn, &user, zoneid).await {
+                Ok(true) => check_zone_editable(&mut txn, zoneid).await?,
+                Ok(false) => {
+                    return error_result_json!(
+                        format!("You don't own {}", zone.name).as_str(),
+                        StatusCode::FORBIDDEN
+                    );
+                }
+                Err(err) => {
+                    log::error!("Failed to check ownership of zoneid={zoneid}: {err:?}");
+                    return error_result_json!(
+                        "Internal server error",
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    );
+                }
+            },
+            None => {
+                zone.apply_defaults(&config);
+                if let Err(err) = UserQuota::check_zones(&mut txn, &user, &config.quotas, 1).await {
+                    return Err(api_error(err));
+                }
+                if let Err(err) = UserQuota::check_records(
+                    &mut txn,
+                    &user,
+                    &config.quotas,
+                    None,
+                    zone.records.len(),
+                )
+                .await
+                {
+                    return Err(api_error(err));
+                }
+            }
+        }
+        if zone.default_ttl.is_none() {
+            zone.default_ttl = existing.as_ref().and_then(|ez| ez.default_ttl);
+        }
+
+        let zone_summary = zone.apply_ttl_policy(&config.import_ttl);
+        let zone_summary = match zone
+            .import_with_txn(&mut txn, import.strategy, zone_summary)
+            .await
+        {
+            Ok(val) => val,
+            Err(GoatNsError::InvalidValue(msg)) => {
+                return error_result_json!(
+                    format!("Failed to import zone {}: {msg}", zone.name).as_str(),
+                    StatusCode::BAD_REQUEST
+                );
+            }
+            Err(err) => {
+                log::error!("Failed to import zone {}: {err:?}", zone.name);
+                return error_result_json!(
+                    format!("Failed to import zone {}", zone.name).as_str(),
+                    StatusCode::INTERNAL_SERVER_ERROR
+                );
+            }
+        };
+        // merging into a zone can take it over the limit
+        if let Some(zoneid) = existing.as_ref().and_then(|ez| ez.id) {
+            if let Err(err) =
+                UserQuota::check_records(&mut txn, &user, &config.quotas, Some(zoneid), 0).await
+            {
+                return Err(api_error(err));
+            }
+        }
+
+        if existing.is_none() {
+            let zoneid = match FileZone::get_by_name(&mut txn, &zone.name).await {
+                Ok(Some(val)) => val.id,
+                Ok(None) => None,
+                Err(err) => {
+                    log::error!("Couldn't get imported zone {}: {err:?}", zone.name);
+                    None
+                }
+            };
+            let zoneid = match zoneid {
+                Some(val) => val,
+                None => {
+                    return error_result_json!(
+                        "Server error creating zone, contact the admins!",
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    );
+                }
+            };
+            let ownership = ZoneOwnership {
+                id: None,
+                userid,
+                zoneid,
+            };
+            if let Err(err) = ownership.save_with_txn(&mut txn).await {
+                log::error!("Couldn't store zone ownership {ownership:?}: {err:?}");
+                return error_result_json!(
+                    "Server error creating zone ownership, contact the admins!",
+                    StatusCode::INTERNAL_SERVER_ERROR
+                );
+            }
+        }
+        summary.add(&zone_summary);
+    }
+
+    let res = match import.dry_run {
+        true => txn.rollback().await,
+        false => txn.commit().await,
+    };
+    if let Err(err) = res {
+        log::error!("Failed to finish import transaction: {err:?}");
+        return error_result_json!("Failed to import zones", StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    if !import.dry_run {
+        forget_cached_records(&state).await;
+    }
+    log::info!(
+        "Zone import by user={userid} with strategy {}: {summary}",
+        import.strategy
+    );
+    Ok(Json(summary))
+}
+
+/// Read a proposed replacement for a zone, it's JSON if that's the content type, otherwise a master file
+pub(crate) fn parse_proposed_zone(
+    headers: &HeaderMap,
+    body: &str,
+    zone_name: &str,
+) -> Result<FileZone, GoatNsError> {
+    let is_json = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let mut zone = match is_json {
+        false => from_bind(body, Some(zone_name))?,
+        true => {
+            let mut zones = zones_from_json(body)?;
+            if zones.len() != 1 {
+                return Err(GoatNsError::InvalidValue(format!(
+                    "Expected one zone, got {}",
+                    zones.len()
+                )));
+            }
+            zones.remove(0)
+        }
+    };
+    zone.normalise_names()?;
+    if zone.name != normalise(zone_name) {
+        return Err(GoatNsError::InvalidValue(format!(
+            "The proposal's for {}, not {zone_name}",
+            zone.name
+        )));
+    }
+    Ok(zone)
+}
+
+/// Show what replacing a zone with the one in the body would change, without changing anything
+pub(crate) async fn api_diff(
+    State(state): State<GoatState>,
+    session: Session,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    body: String,
+) -> Result<Json<ZoneDiff>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+
+    let mut conn = match state.connpool().await.acquire().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!(
+                "Failed to get a connection to the database!",
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+    };
+    match changeset::user_can_review(&mut conn, &user, id).await {
+        Ok(true) => {}
+        Ok(false) => return error_result_json!("", StatusCode::FORBIDDEN),
+        Err(err) => {
+            log::error!("Failed to check ownership of zoneid={id}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    let zone = match FileZone::get_with_txn(&mut conn, &id).await {
+        Ok(val) => val,
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            return error_result_json!("Zone not found", StatusCode::NOT_FOUND);
+        }
+        Err(err) => {
+            log::error!("Couldn't get zone id {id}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let mut proposed = match parse_proposed_zone(&headers, &body, &zone.name) {
+        Ok(val) => val,
+        Err(err) => {
+            let msg = match err {
+                GoatNsError::InvalidValue(msg) | GoatNsError::FileError(msg) => msg,
+                err => format!("{err:?}"),
+            };
+            return error_result_json!(msg.as_str(), StatusCode::BAD_REQUEST);
+        }
+    };
+    // it's what an import would do to it first
+    if proposed.default_ttl.is_none() {
+        proposed.default_ttl = zone.default_ttl;
+    }
+    proposed.apply_ttl_policy(&state.read().await.config.import_ttl);
+    if let Err(GoatNsError::InvalidValue(msg)) = proposed.check_cname_rules() {
+        return error_result_json!(msg.as_str(), StatusCode::BAD_REQUEST);
+    }
+    Ok(Json(zone.diff(&proposed)))
 }
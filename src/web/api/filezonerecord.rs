@@ -1,12 +1,27 @@
-use crate::db::{DBEntity, User, ZoneOwnership};
+use crate::db::{is_write_conflict, with_write_txn, DBEntity, User, UserQuota, ZoneOwnership};
 use crate::error_result_json;
+use crate::zone_events::ZoneEvent;
 use crate::zones::FileZoneRecord;
+use axum::extract::Query;
+use axum::http::HeaderMap;
+use axum::response::Response;
 use goatns_macros::check_api_auth;
+use sqlx::SqliteConnection;
 use tower_sessions::Session;
 use tracing::debug;
 
 use super::*;
 
+/// For the request's errors inside [with_write_txn], they roll the transaction back and go straight to the client
+macro_rules! try_api {
+    ($res:expr) => {
+        match $res {
+            Ok(val) => val,
+            Err(err) => return Ok(Err(err)),
+        }
+    };
+}
+
 /// Save the entity to the database
 #[utoipa::path(
     post,
@@ -26,7 +41,15 @@ pub(crate) async fn api_create(
     Json(record): Json<FileZoneRecord>,
 ) -> Result<Json<Box<FileZoneRecord>>, (StatusCode, Json<ErrorResult>)> {
     check_api_auth!();
+    create_record(&state, &user, record).await.map(Json)
+}
 
+/// Saves a new record, if the user owns the zone it's going in. Shared with the record wizards.
+pub(crate) async fn create_record(
+    state: &GoatState,
+    user: &User,
+    record: FileZoneRecord,
+) -> Result<Box<FileZoneRecord>, (StatusCode, Json<ErrorResult>)> {
     let user_id = match user.id {
         Some(val) => val,
         None => {
@@ -43,34 +66,68 @@ pub(crate) async fn api_create(
         }
     };
 
-    let mut txn = state.connpool().await.begin().await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json::from(ErrorResult::from("Database error")),
-        )
-    })?;
-    debug!("looking for ZO for user: {} zoneid: {}", user_id, zone_id);
-    if let Err(err) = ZoneOwnership::get_ownership_by_userid(&mut txn, &user_id, &zone_id).await {
-        eprintln!("Error getting ownership: {err:?}");
-        return error_result_json!("", StatusCode::UNAUTHORIZED);
+    let (pool, quotas) = {
+        let reader = state.read().await;
+        (reader.connpool.clone(), reader.config.quotas)
     };
+    let res = with_write_txn(&pool, |txn| {
+        let state = state.clone();
+        let user = user.clone();
+        let mut record = record.clone();
+        Box::pin(async move {
+            debug!("looking for ZO for user: {} zoneid: {}", user_id, zone_id);
+            if let Err(err) = ZoneOwnership::get_ownership_by_userid(txn, &user_id, &zone_id).await
+            {
+                eprintln!("Error getting ownership: {err:?}");
+                return Ok(error_result_json!("", StatusCode::UNAUTHORIZED));
+            };
+            try_api!(check_zone_editable(txn, zone_id).await);
+            try_api!(
+                UserQuota::check_records(txn, &user, &quotas, Some(zone_id), 1)
+                    .await
+                    .map_err(api_error)
+            );
+            try_api!(fill_from_zone(&state, txn, &mut record, zone_id).await);
 
-    match record.save_with_txn(&mut txn).await {
+            let val = match record.save_with_txn(txn).await {
+                Ok(val) => val,
+                Err(err) if is_write_conflict(&err) => return Err(err),
+                Err(err) => {
+                    eprintln!("Error saving record: {err:?}");
+                    // TODO: this needs to handle index conflicts
+                    return Ok(error_result_json!(
+                        "Error saving record",
+                        StatusCode::BAD_REQUEST
+                    ));
+                }
+            };
+            try_api!(check_cname_rules(txn, zone_id).await);
+            let serial = try_api!(bump_serial(txn, zone_id).await?);
+            Ok(Ok((val, serial)))
+        })
+    })
+    .await;
+    match res {
+        Ok(res) => {
+            let (val, serial) = res?;
+            zone_changed(
+                state,
+                ZoneEvent::RecordAdded {
+                    zoneid: zone_id,
+                    serial,
+                    record: (*val).clone(),
+                },
+            )
+            .await;
+            Ok(val)
+        }
         Err(err) => {
-            eprintln!("Error saving record: {err:?}");
-            // TODO: this needs to handle index conflicts
-            error_result_json!("Error saving record", StatusCode::BAD_REQUEST)
-        }
-        Ok(val) => {
-            if let Err(err) = txn.commit().await {
-                // TODO: This error message needs improving
-                eprintln!("error committing transaction! {err:?}");
-                return error_result_json!(
-                    "Error saving record, see the admins",
-                    StatusCode::INTERNAL_SERVER_ERROR
-                );
-            }
-            Ok(Json(val))
+            // TODO: This error message needs improving
+            eprintln!("error committing transaction! {err:?}");
+            error_result_json!(
+                "Error saving record, see the admins",
+                StatusCode::INTERNAL_SERVER_ERROR
+            )
         }
     }
 }
@@ -89,41 +146,82 @@ pub(crate) async fn api_update(
             return error_result_json!("Failed to parse object", StatusCode::BAD_REQUEST);
         }
     };
-    let mut txn = state.connpool().await.begin().await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json::from(ErrorResult::from("Database error")),
-        )
-    })?;
+    let user_id = user.id;
+    let pool = state.connpool().await;
+    let res = with_write_txn(&pool, |txn| {
+        let state = state.clone();
+        let mut record = record.clone();
+        Box::pin(async move {
+            if let Some(zone_id) = record.zoneid {
+                try_api!(fill_from_zone(&state, txn, &mut record, zone_id).await);
+            }
 
-    let res = match record.update_with_txn(&mut txn).await {
-        Ok(val) => val,
-        Err(err) => {
-            // TODO: this should handle missing OR failures
-            eprintln!("Error getting record: {err:?}");
-            return error_result_json!("", StatusCode::NOT_FOUND);
-        }
-    };
+            let res = match record.update_with_txn(txn).await {
+                Ok(val) => val,
+                Err(err) if is_write_conflict(&err) => return Err(err),
+                Err(err) => {
+                    // TODO: this should handle missing OR failures
+                    eprintln!("Error getting record: {err:?}");
+                    return Ok(error_result_json!("", StatusCode::NOT_FOUND));
+                }
+            };
 
-    let user_id = match user.id {
-        Some(val) => val,
-        None => {
-            debug!("No user id found in session");
-            return error_result_json!("No user id found in session", StatusCode::UNAUTHORIZED);
-        }
-    };
+            let user_id = match user_id {
+                Some(val) => val,
+                None => {
+                    debug!("No user id found in session");
+                    return Ok(error_result_json!(
+                        "No user id found in session",
+                        StatusCode::UNAUTHORIZED
+                    ));
+                }
+            };
 
-    let zone_id = match record.zoneid {
-        Some(val) => val,
-        None => {
-            debug!("No zone id found in record");
-            return error_result_json!("No zone id found in record", StatusCode::BAD_REQUEST);
-        }
-    };
+            let zone_id = match record.zoneid {
+                Some(val) => val,
+                None => {
+                    debug!("No zone id found in record");
+                    return Ok(error_result_json!(
+                        "No zone id found in record",
+                        StatusCode::BAD_REQUEST
+                    ));
+                }
+            };
+
+            if let Err(err) = ZoneOwnership::get_ownership_by_userid(txn, &user_id, &zone_id).await
+            {
+                eprintln!("Error getting ownership: {err:?}");
+                return Ok(error_result_json!("", StatusCode::UNAUTHORIZED));
+            };
+            try_api!(check_zone_editable(txn, zone_id).await);
 
-    if let Err(err) = ZoneOwnership::get_ownership_by_userid(&mut txn, &user_id, &zone_id).await {
-        eprintln!("Error getting ownership: {err:?}");
-        return error_result_json!("", StatusCode::UNAUTHORIZED);
+            try_api!(check_cname_rules(txn, zone_id).await);
+            let serial = try_api!(bump_serial(txn, zone_id).await?);
+            Ok(Ok((res, zone_id, serial)))
+        })
+    })
+    .await;
+    let res = match res {
+        Ok(res) => {
+            let (res, zoneid, serial) = res?;
+            zone_changed(
+                &state,
+                ZoneEvent::RecordUpdated {
+                    zoneid,
+                    serial,
+                    record: (*res).clone(),
+                },
+            )
+            .await;
+            res
+        }
+        Err(err) => {
+            error!("Failed to commit transaction while updating record: {err:?}");
+            return error_result_json!(
+                "Error saving record, see the admins",
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
     };
 
     let res = match serde_json::to_string(&res) {
@@ -136,15 +234,145 @@ pub(crate) async fn api_update(
 
     Ok(Json(res))
 }
+/// Puts the record's name relative to its zone, and records that leave the TTL out get the zone's default, or the
+/// server's if the zone doesn't have one
+async fn fill_from_zone(
+    state: &GoatState,
+    txn: &mut SqliteConnection,
+    record: &mut FileZoneRecord,
+    zone_id: i64,
+) -> Result<(), (StatusCode, Json<ErrorResult>)> {
+    let zone = match FileZone::get_with_txn(txn, &zone_id).await {
+        Ok(zone) => zone,
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            return error_result_json!("Zone not found", StatusCode::NOT_FOUND);
+        }
+        Err(err) => {
+            error!("Failed to get zoneid={zone_id}: {err:?}");
+            return error_result_json!("Error saving record", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    record.normalise_name(&zone.name).map_err(api_error)?;
+    if record.ttl == 0 {
+        record.ttl = match zone.default_ttl {
+            Some(ttl) => ttl,
+            None => state.read().await.config.import_ttl.default_ttl,
+        };
+    }
+    Ok(())
+}
+
+/// Make sure the records in the zone still follow the CNAME rules once this transaction's changes are in
+async fn check_cname_rules(
+    txn: &mut SqliteConnection,
+    zone_id: i64,
+) -> Result<(), (StatusCode, Json<ErrorResult>)> {
+    match FileZone::check_cname_rules_with_txn(zone_id, txn).await {
+        Ok(()) => Ok(()),
+        Err(GoatNsError::InvalidValue(msg)) => {
+            error_result_json!(msg.as_str(), StatusCode::BAD_REQUEST)
+        }
+        Err(err) => {
+            error!("Failed to check the CNAME rules for zoneid={zone_id}: {err:?}");
+            error_result_json!("Error saving record", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Changing records changes the zone, so its serial (and the ETags based on it) need to move on. Conflicts with other
+/// writers come back as the outer error, so [with_write_txn] can try again. Gives back the new serial.
+async fn bump_serial(
+    txn: &mut SqliteConnection,
+    zone_id: i64,
+) -> Result<Result<u32, (StatusCode, Json<ErrorResult>)>, GoatNsError> {
+    let res = match FileZone::bump_serial_with_txn(zone_id, txn).await {
+        Ok(()) => FileZone::get_serial_with_txn(zone_id, txn).await,
+        Err(err) => Err(err),
+    };
+    match res {
+        Ok(serial) => Ok(Ok(serial)),
+        Err(err) if is_write_conflict(&err) => Err(err),
+        Err(err) => {
+            error!("Failed to bump the serial for zoneid={zone_id}: {err:?}");
+            Ok(error_result_json!(
+                "Error saving record",
+                StatusCode::INTERNAL_SERVER_ERROR
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RecordSearchQuery {
+    /// Free text to look for in comments and tags, or `key=value` to match a tag
+    q: String,
+}
+
+/// Search the records the user can see by comment or tag
+#[utoipa::path(
+    get,
+    path = "/api/records/search",
+    operation_id = "record_search",
+    params(
+        ("q" = String, Query, description = "Free text to match against comments and tags, or key=value to match a tag"),
+    ),
+    responses(
+        (status = 200, description = "Successful", body = [FileZoneRecord]),
+        (status = 403, description = "Auth failed"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Records",
+)]
+pub(crate) async fn api_search(
+    State(state): State<GoatState>,
+    session: Session,
+    Query(query): Query<RecordSearchQuery>,
+) -> Result<Json<Vec<FileZoneRecord>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+
+    if query.q.trim().is_empty() {
+        return error_result_json!("Search query cannot be empty", StatusCode::BAD_REQUEST);
+    }
+
+    let mut txn = state.connpool().await.begin().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json::from(ErrorResult::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Database error",
+            )),
+        )
+    })?;
+
+    match FileZoneRecord::search(&mut txn, &user, query.q.trim()).await {
+        Ok(val) => Ok(Json(val)),
+        Err(err) => {
+            error!("Failed to search records for {:?}: {err:?}", query.q);
+            error_result_json!("Error searching records", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Get a record, the ETag's based on its zone's serial
 pub(crate) async fn api_get(
     State(state): State<GoatState>,
     session: Session,
+    headers: HeaderMap,
     Path(id): Path<i64>,
-) -> Result<Json<Box<FileZoneRecord>>, (StatusCode, Json<ErrorResult>)> {
+) -> Result<Response, (StatusCode, Json<ErrorResult>)> {
     check_api_auth!();
 
     let pool = state.connpool().await;
-    let res = match FileZoneRecord::get(&pool, id).await {
+    let mut conn = pool.acquire().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json::from(ErrorResult::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Database error",
+            )),
+        )
+    })?;
+    let res = match FileZoneRecord::get_with_txn(&mut conn, &id).await {
         Ok(val) => val,
         Err(err) => {
             // TODO: this should handle missing OR failures
@@ -152,7 +380,21 @@ pub(crate) async fn api_get(
             return error_result_json!("", StatusCode::NOT_FOUND);
         }
     };
-    Ok(Json(res))
+    let serial = match res.zoneid {
+        Some(zoneid) => FileZone::get_serial_with_txn(zoneid, &mut conn).await,
+        None => Err(sqlx::Error::RowNotFound.into()),
+    };
+    match serial {
+        Ok(serial) => Ok(etag_response(
+            &headers,
+            serial_etag("record", id, serial),
+            res,
+        )),
+        Err(err) => {
+            error!("Failed to get the zone serial for record id={id}: {err:?}");
+            error_result_json!("Error getting record", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
 /// Delete an object
@@ -164,20 +406,6 @@ pub(crate) async fn api_delete(
 ) -> Result<(), (StatusCode, Json<ErrorResult>)> {
     check_api_auth!();
 
-    let mut txn = state.connpool().await.begin().await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json::from(ErrorResult::from("Database error")),
-        )
-    })?;
-
-    let record = match FileZoneRecord::get_with_txn(&mut txn, &id).await {
-        Ok(val) => val,
-        Err(err) => {
-            let resmsg = format!("error getting record: {err:?}");
-            return error_result_json!(resmsg.as_str(), StatusCode::UNAUTHORIZED);
-        }
-    };
     let user_id = match user.id {
         Some(val) => val,
         None => {
@@ -186,34 +414,70 @@ pub(crate) async fn api_delete(
         }
     };
 
-    let zone_id = match record.zoneid {
-        Some(val) => val,
-        None => {
-            debug!("No zone id found in record");
-            return error_result_json!("No zone id found in record", StatusCode::BAD_REQUEST);
-        }
-    };
-    if let Err(err) = ZoneOwnership::get_ownership_by_userid(&mut txn, &user_id, &zone_id).await {
-        eprintln!("Error getting ownership: {err:?}");
-        return error_result_json!("no zone ownership found", StatusCode::UNAUTHORIZED);
-    };
+    let pool = state.connpool().await;
+    let res = with_write_txn(&pool, |txn| {
+        Box::pin(async move {
+            let record = match FileZoneRecord::get_with_txn(txn, &id).await {
+                Ok(val) => val,
+                Err(err) => {
+                    let resmsg = format!("error getting record: {err:?}");
+                    return Ok(error_result_json!(
+                        resmsg.as_str(),
+                        StatusCode::UNAUTHORIZED
+                    ));
+                }
+            };
 
-    if let Err(err) = record.delete_with_txn(&mut txn).await {
-        // TODO: This error message needs improving
-        eprintln!("error committing transaction! {err:?}");
-        return error_result_json!(
-            "Error deleting record, see the admins",
-            StatusCode::INTERNAL_SERVER_ERROR
-        );
-    }
-    if let Err(err) = txn.commit().await {
-        // TODO: This error message needs improving
-        eprintln!("error committing transaction! {err:?}");
-        return error_result_json!(
-            "Error deleting record, see the admins",
-            StatusCode::INTERNAL_SERVER_ERROR
-        );
-    };
+            let zone_id = match record.zoneid {
+                Some(val) => val,
+                None => {
+                    debug!("No zone id found in record");
+                    return Ok(error_result_json!(
+                        "No zone id found in record",
+                        StatusCode::BAD_REQUEST
+                    ));
+                }
+            };
+            if let Err(err) = ZoneOwnership::get_ownership_by_userid(txn, &user_id, &zone_id).await
+            {
+                eprintln!("Error getting ownership: {err:?}");
+                return Ok(error_result_json!(
+                    "no zone ownership found",
+                    StatusCode::UNAUTHORIZED
+                ));
+            };
+            try_api!(check_zone_editable(txn, zone_id).await);
 
-    Ok(())
+            match record.delete_with_txn(txn).await {
+                Ok(_) => {}
+                Err(err) if is_write_conflict(&err) => return Err(err),
+                Err(err) => {
+                    // TODO: This error message needs improving
+                    eprintln!("error committing transaction! {err:?}");
+                    return Ok(error_result_json!(
+                        "Error deleting record, see the admins",
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    ));
+                }
+            }
+            let serial = try_api!(bump_serial(txn, zone_id).await?);
+            Ok(Ok((zone_id, serial)))
+        })
+    })
+    .await;
+    match res {
+        Ok(res) => {
+            let (zoneid, serial) = res?;
+            zone_changed(&state, ZoneEvent::RecordDeleted { zoneid, serial, id }).await;
+            Ok(())
+        }
+        Err(err) => {
+            // TODO: This error message needs improving
+            eprintln!("error committing transaction! {err:?}");
+            error_result_json!(
+                "Error deleting record, see the admins",
+                StatusCode::INTERNAL_SERVER_ERROR
+            )
+        }
+    }
 }
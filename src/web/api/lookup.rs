@@ -0,0 +1,84 @@
+//! "What points at this IP?" without needing to go digging in the database
+
+use std::net::IpAddr;
+
+use crate::error_result_json;
+use crate::utils::reverse_name;
+use crate::zones::FileZoneRecord;
+use axum::extract::Query;
+use goatns_macros::check_api_auth;
+use tower_sessions::Session;
+
+use super::*;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct LookupQuery {
+    ip: String,
+}
+
+/// What's pointing at an address, out of the zones you can see
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AddressLookup {
+    #[schema(value_type = String)]
+    pub ip: IpAddr,
+    /// Where PTR records for the address go, eg `4.3.2.1.in-addr.arpa`
+    pub reverse_name: String,
+    /// A and AAAA records with the address, their names are the full names
+    pub forward: Vec<FileZoneRecord>,
+    /// PTR records for the reverse name
+    pub reverse: Vec<FileZoneRecord>,
+}
+
+/// Find the A, AAAA and PTR records for an address
+#[utoipa::path(
+    get,
+    path = "/api/lookup",
+    operation_id = "address_lookup",
+    params(
+        ("ip" = String, Query, description = "An IPv4 or IPv6 address"),
+    ),
+    responses(
+        (status = 200, description = "Successful", body = AddressLookup),
+        (status = 400, description = "That's not an IP address", body = ErrorResult),
+        (status = 403, description = "Auth failed"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Records",
+)]
+pub(crate) async fn api_lookup(
+    State(state): State<GoatState>,
+    session: Session,
+    Query(query): Query<LookupQuery>,
+) -> Result<Json<AddressLookup>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+
+    let ip: IpAddr = match query.ip.trim().parse() {
+        Ok(val) => val,
+        Err(_) => {
+            return error_result_json!(
+                format!("{:?} isn't an IP address", query.ip).as_str(),
+                StatusCode::BAD_REQUEST
+            );
+        }
+    };
+
+    let mut txn = state
+        .connpool()
+        .await
+        .begin()
+        .await
+        .map_err(|err| api_error(err.into()))?;
+    let (reverse, forward): (Vec<FileZoneRecord>, Vec<FileZoneRecord>) =
+        FileZoneRecord::lookup_address(&mut txn, &user, &ip)
+            .await
+            .map_err(api_error)?
+            .into_iter()
+            .partition(|record| record.rrtype == "PTR");
+
+    Ok(Json(AddressLookup {
+        ip,
+        reverse_name: reverse_name(&ip),
+        forward,
+        reverse,
+    }))
+}
@@ -1,22 +1,36 @@
 use super::*;
+use crate::web::middleware::request_id::current_request_id;
+use crate::zone_events::{self, ZoneEvent};
 use crate::zones::FileZone;
 use axum::extract::Path;
 use axum::extract::State;
+use axum::http::header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
 use axum::routing::{delete, post, put};
 use axum::Json;
 use serde::Deserialize;
 use serde::Serialize;
+use utoipa::ToSchema;
 
+pub mod admin;
+pub mod apitoken;
 pub mod auth;
+pub mod changeset;
 pub(crate) mod docs;
 pub mod filezone;
 pub mod filezonerecord;
+pub mod lookup;
+pub mod settings;
+pub mod transfer;
+pub mod wizard;
+pub mod zonetemplate;
 
 #[macro_export]
 /// message, status
 macro_rules! error_result_json {
     ($msg:expr, $status:expr) => {
-        Err(($status, Json(ErrorResult::from($msg))))
+        Err(($status, Json(ErrorResult::new($status, $msg))))
     };
 }
 
@@ -33,21 +47,129 @@ impl Default for NotImplemented {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[allow(dead_code)]
+/// What every API error looks like
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct ErrorResult {
-    #[allow(dead_code)]
+    /// Machine-readable, from the status - `not_found`, `forbidden`, `too_many_requests` etc
+    #[serde(default)]
+    pub code: String,
     pub message: String,
+    /// So people can tell us which request broke
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
-impl From<&str> for ErrorResult {
-    fn from(input: &str) -> Self {
+impl ErrorResult {
+    /// An error for the current request, if there's no message you get the status's reason
+    pub fn new(status: StatusCode, message: impl ToString) -> Self {
+        let message = match message.to_string() {
+            message if message.trim().is_empty() => status
+                .canonical_reason()
+                .unwrap_or("Unknown error")
+                .to_string(),
+            message => message,
+        };
         ErrorResult {
-            message: input.to_string(),
+            code: error_code(status),
+            message,
+            request_id: current_request_id(),
         }
     }
 }
 
+/// The `code` for a status, its reason in snake case
+pub fn error_code(status: StatusCode) -> String {
+    match status.canonical_reason() {
+        Some(reason) => reason
+            .to_lowercase()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+        None => format!("http_{}", status.as_u16()),
+    }
+}
+
+/// Turns an error into the API response for it, what the user sees comes from [GoatNsError::user_message] and
+/// anything that's our fault is logged
+pub(crate) fn api_error(err: GoatNsError) -> (StatusCode, Json<ErrorResult>) {
+    let status = err.status_code();
+    if status.is_server_error() {
+        error!(
+            "API request failed request_id={} error={err:?}",
+            current_request_id().unwrap_or_default()
+        );
+    }
+    (status, Json(ErrorResult::new(status, err.user_message())))
+}
+
+/// A weak ETag for something that changes whenever its zone's serial does
+pub(crate) fn serial_etag(kind: &str, id: i64, serial: u32) -> String {
+    format!("W/\"{kind}-{id}-{serial}\"")
+}
+
+/// If the client's sent this ETag in `If-None-Match`, they've already got the current version. They're compared weakly,
+/// per [RFC9110](https://www.rfc-editor.org/rfc/rfc9110#section-13.1.2)
+pub(crate) fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
+
+/// A 304 if the client's already got this version, otherwise the body. Both come with the ETag, and clients have to
+/// check back before using what they've cached
+pub(crate) fn etag_response<T: Serialize>(headers: &HeaderMap, etag: String, body: T) -> Response {
+    let cache_headers = [
+        (ETAG, etag.clone()),
+        (CACHE_CONTROL, "private, no-cache".to_string()),
+    ];
+    if etag_matches(headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+    (cache_headers, Json(body)).into_response()
+}
+
+/// Zones that are synced from files can only be changed by changing the files
+pub(crate) async fn check_zone_editable(
+    txn: &mut sqlx::SqliteConnection,
+    zone_id: i64,
+) -> Result<(), (StatusCode, Json<ErrorResult>)> {
+    match crate::db::SyncedZone::check_editable(txn, zone_id).await {
+        Ok(()) => Ok(()),
+        Err(GoatNsError::InvalidValue(msg)) => {
+            error_result_json!(msg.as_str(), StatusCode::CONFLICT)
+        }
+        Err(err) => Err(api_error(err)),
+    }
+}
+
+/// Once a change to a zone's committed, the datastore has to drop what it's cached or DNS queries keep getting the old
+/// records until they expire. If it doesn't work the change is still saved, so it's only logged.
+pub(crate) async fn forget_cached_records(state: &GoatState) {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let cmd = datastore::Command::PurgeCache {
+        purge: datastore::CachePurge::All,
+        resp: tx,
+    };
+    if let Err(err) = state.read().await.tx.send(cmd.into()).await {
+        error!("Failed to ask the datastore to purge its cache: {err:?}");
+        return;
+    }
+    match rx.await {
+        Ok(Ok(_)) => {}
+        Ok(Err(err)) => error!("Failed to purge the cache: {err:?}"),
+        Err(err) => error!("Datastore didn't respond to the cache purge: {err:?}"),
+    }
+}
+
+/// Once a change to a zone's committed, DNS queries need to stop getting the cached records and anyone watching the
+/// zone needs to hear about it. Everything that changes zones through the API or web UI ends up here.
+pub(crate) async fn zone_changed(state: &GoatState, event: ZoneEvent) {
+    forget_cached_records(state).await;
+    zone_events::publish(event);
+}
+
 /// This gets applied to DBEntities
 // #[async_trait]
 // trait APIEntity {
@@ -101,15 +223,65 @@ pub async fn version_get() -> Json<GoatNSVersion> {
     Json::from(GoatNSVersion::default())
 }
 
+#[cfg_attr(not(feature = "fault-injection"), allow(clippy::let_and_return))]
 pub fn new() -> Router<GoatState> {
-    Router::new()
+    let router = Router::new()
         .route("/zone", post(filezone::api_create))
         .route("/zone", put(filezone::api_update))
+        .route("/zone/import", post(filezone::api_import))
         .route("/zone/:id", get(filezone::api_get))
         .route("/zone/:id", delete(filezone::api_delete))
+        .route("/zone/:id/diff", post(filezone::api_diff))
+        .route("/zone/:id/enabled", post(filezone::api_set_enabled))
+        .route("/zone/:id/public", post(filezone::api_set_public))
+        .route(
+            "/zone/:id/registration",
+            post(filezone::api_set_registration),
+        )
+        .route("/zone/:id/events", get(filezone::api_events))
+        .route("/zone/:id/changesets", get(changeset::api_list))
+        .route("/zone/:id/changesets", post(changeset::api_propose))
+        .route("/changeset/:id/approve", post(changeset::api_approve))
+        .route("/changeset/:id/reject", post(changeset::api_reject))
+        .route("/zone/:id/transfer", post(transfer::api_create))
+        .route("/transfers", get(transfer::api_list))
+        .route("/transfer/:id/accept", post(transfer::api_accept))
+        .route("/transfer/:id/decline", post(transfer::api_decline))
+        .route("/transfer/:id/cancel", post(transfer::api_cancel))
         .route("/record", post(filezonerecord::api_create))
         .route("/record", put(filezonerecord::api_update))
         .route("/record/:id", get(filezonerecord::api_get))
         .route("/record/:id", delete(filezonerecord::api_delete))
+        .route("/records/search", get(filezonerecord::api_search))
+        .route("/lookup", get(lookup::api_lookup))
+        .route("/wizard/caa", post(wizard::api_caa))
+        .route("/wizard/caa/presets", get(wizard::api_caa_presets))
+        .route("/wizard/loc", post(wizard::api_loc))
+        .route("/wizard/spf", post(wizard::api_spf))
+        .route("/wizard/dkim", post(wizard::api_dkim))
+        .route("/wizard/dmarc", post(wizard::api_dmarc))
+        .route("/templates", get(zonetemplate::api_list))
+        .route("/template", post(zonetemplate::api_create))
+        .route("/template", put(zonetemplate::api_update))
+        .route("/template/:id", get(zonetemplate::api_get))
+        .route("/template/:id", delete(zonetemplate::api_delete))
+        .route("/settings", get(settings::api_get))
+        .route("/settings", put(settings::api_update))
+        .route("/tokens", get(apitoken::api_list))
+        .route("/token/:id/rotate", post(apitoken::api_rotate))
         .route("/login", post(auth::login))
+        .route("/admin/trace", get(admin::api_trace_get))
+        .route("/admin/trace", post(admin::api_trace_start))
+        .route("/admin/trace", delete(admin::api_trace_stop))
+        .route("/admin/cache/purge", post(admin::api_cache_purge))
+        .route("/admin/zone/:id/reload", post(admin::api_zone_reload))
+        .route("/admin/export", get(admin::api_export))
+        .route("/admin/restore", post(admin::api_restore))
+        .route("/admin/orphans", get(admin::api_orphans_get))
+        .route("/admin/orphans/assign", post(admin::api_orphans_assign));
+    #[cfg(feature = "fault-injection")]
+    let router = router
+        .route("/admin/faults", get(admin::api_faults_get))
+        .route("/admin/faults", put(admin::api_faults_set));
+    router
 }
@@ -0,0 +1,87 @@
+//! API endpoints for users to look at and change their own preferences, see [UserSettings]
+
+use crate::db::UserSettings;
+use crate::error_result_json;
+use goatns_macros::check_api_auth;
+use tower_sessions::Session;
+
+use super::*;
+
+/// Your settings, the defaults if you've never changed them
+#[utoipa::path(
+    get,
+    path = "/api/settings",
+    operation_id = "settings_get",
+    responses(
+        (status = 200, description = "Successful", body = UserSettings),
+        (status = 403, description = "Auth failed"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Settings",
+)]
+pub(crate) async fn api_get(
+    State(state): State<GoatState>,
+    session: Session,
+) -> Result<Json<UserSettings>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    let userid = match user.id {
+        Some(val) => val,
+        None => {
+            return error_result_json!("No user id found in session", StatusCode::UNAUTHORIZED);
+        }
+    };
+
+    let res = match state.connpool().await.acquire().await {
+        Ok(mut conn) => UserSettings::get(&mut conn, userid).await,
+        Err(err) => Err(err.into()),
+    };
+    match res {
+        Ok(settings) => Ok(Json(settings)),
+        Err(err) => {
+            error!("Failed to get settings for user id={userid}: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Change your settings, anything left out goes back to its default
+#[utoipa::path(
+    put,
+    path = "/api/settings",
+    operation_id = "settings_update",
+    request_body = UserSettings,
+    responses(
+        (status = 200, description = "Successful", body = UserSettings),
+        (status = 400, description = "One of the settings wasn't valid"),
+        (status = 403, description = "Auth failed"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Settings",
+)]
+pub(crate) async fn api_update(
+    State(state): State<GoatState>,
+    session: Session,
+    Json(settings): Json<UserSettings>,
+) -> Result<Json<UserSettings>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    let userid = match user.id {
+        Some(val) => val,
+        None => {
+            return error_result_json!("No user id found in session", StatusCode::UNAUTHORIZED);
+        }
+    };
+
+    match settings.save(&state.connpool().await, userid).await {
+        Ok(()) => {
+            log::info!("id={userid} action=settings_update settings={settings:?}");
+            Ok(Json(settings))
+        }
+        Err(GoatNsError::InvalidValue(msg)) => {
+            error_result_json!(msg.as_str(), StatusCode::BAD_REQUEST)
+        }
+        Err(err) => {
+            error!("Failed to save settings for user id={userid}: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
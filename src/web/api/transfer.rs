@@ -0,0 +1,255 @@
+//! API endpoints for handing a zone to another user.
+//!
+//! An owner asks for the transfer, and nothing changes until the new owner accepts it. The new owner can decline it,
+//! and the old owner (or an admin) can cancel it while it's pending.
+
+use crate::db::{DBEntity, User, ZoneOwnership};
+use crate::error_result_json;
+use crate::webhook::WebhookEvent;
+use crate::zones::{FileZone, OwnershipTransferStatus, ZoneOwnershipTransfer};
+use goatns_macros::check_api_auth;
+use tower_sessions::Session;
+
+use super::*;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct TransferRequest {
+    /// The username of the new owner
+    pub username: String,
+}
+
+/// Ask for a zone to be given to another user, you have to own it
+pub(crate) async fn start_transfer(
+    state: &GoatState,
+    user: &User,
+    zoneid: i64,
+    username: &str,
+) -> Result<Box<ZoneOwnershipTransfer>, (StatusCode, Json<ErrorResult>)> {
+    let userid = match user.id {
+        Some(val) => val,
+        None => {
+            return error_result_json!("No user id found in session", StatusCode::UNAUTHORIZED);
+        }
+    };
+
+    let mut txn = match state.connpool().await.begin().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let zone = match FileZone::get_with_txn(&mut txn, &zoneid).await {
+        Ok(val) => val,
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            return error_result_json!("Zone not found", StatusCode::NOT_FOUND);
+        }
+        Err(err) => {
+            error!("Failed to get zone id={zoneid}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    // admins can already change who owns what, this is for owners
+    match ZoneOwnership::get_ownership_by_userid(&mut txn, &userid, &zoneid).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return error_result_json!("", StatusCode::FORBIDDEN),
+        Err(err) => {
+            error!("Failed to check ownership of zone id={zoneid}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    let new_owner = match User::get_by_name(&mut txn, username.trim()).await {
+        Ok(Some(val)) if !val.disabled => val,
+        Ok(_) | Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            return error_result_json!("User not found", StatusCode::NOT_FOUND);
+        }
+        Err(err) => {
+            error!("Failed to get user {username:?}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let to_userid = match new_owner.id {
+        Some(val) => val,
+        None => return error_result_json!("User not found", StatusCode::NOT_FOUND),
+    };
+
+    let transfer = ZoneOwnershipTransfer {
+        id: None,
+        zoneid,
+        from_userid: userid,
+        to_userid,
+        created: chrono::Utc::now(),
+        status: OwnershipTransferStatus::Pending,
+        zone: zone.name,
+        from_username: user.username.clone(),
+        to_username: new_owner.username,
+    };
+    let res = match transfer.save_with_txn(&mut txn).await {
+        Ok(val) => val,
+        Err(GoatNsError::InvalidValue(msg)) => {
+            return error_result_json!(msg.as_str(), StatusCode::BAD_REQUEST);
+        }
+        Err(err) => {
+            error!("Failed to save ownership transfer for zone id={zoneid}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    if let Err(err) = txn.commit().await {
+        error!("Failed to commit transaction saving ownership transfer: {err:?}");
+        return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    log::info!(
+        "action=ownership_transfer_start user={userid} zone={zoneid} to={to_userid} transfer={:?}",
+        res.id
+    );
+    crate::webhook::send(
+        state.read().await.config.webhook_url.clone(),
+        WebhookEvent::from(res.as_ref()),
+    );
+    Ok(res)
+}
+
+/// Accept, decline or cancel a transfer. Only the new owner can accept or decline it, and only the old owner or an
+/// admin can cancel it.
+pub(crate) async fn resolve_transfer(
+    state: &GoatState,
+    user: &User,
+    id: i64,
+    status: OwnershipTransferStatus,
+) -> Result<Box<ZoneOwnershipTransfer>, (StatusCode, Json<ErrorResult>)> {
+    let mut txn = match state.connpool().await.begin().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let transfer = match ZoneOwnershipTransfer::get_with_txn(&mut txn, &id).await {
+        Ok(val) => val,
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            return error_result_json!("Ownership transfer not found", StatusCode::NOT_FOUND);
+        }
+        Err(err) => {
+            error!("Failed to get ownership transfer id={id}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let allowed = match status {
+        OwnershipTransferStatus::Accepted | OwnershipTransferStatus::Declined => {
+            user.id == Some(transfer.to_userid)
+        }
+        OwnershipTransferStatus::Cancelled => user.id == Some(transfer.from_userid) || user.admin,
+        OwnershipTransferStatus::Pending => false,
+    };
+    if !allowed {
+        return error_result_json!("", StatusCode::FORBIDDEN);
+    }
+
+    let res = match transfer.resolve_with_txn(&mut txn, status).await {
+        Ok(val) => val,
+        Err(GoatNsError::InvalidValue(msg)) => {
+            return error_result_json!(msg.as_str(), StatusCode::BAD_REQUEST);
+        }
+        Err(err) => {
+            error!("Failed to resolve ownership transfer id={id}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    if let Err(err) = txn.commit().await {
+        error!("Failed to commit transaction resolving ownership transfer id={id}: {err:?}");
+        return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    log::info!(
+        "action=ownership_transfer_resolve user={:?} zone={} transfer={id} status={}",
+        user.id,
+        res.zoneid,
+        res.status
+    );
+    crate::webhook::send(
+        state.read().await.config.webhook_url.clone(),
+        WebhookEvent::from(res.as_ref()),
+    );
+    Ok(res)
+}
+
+/// Ask for a zone to be given to another user
+pub(crate) async fn api_create(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(zoneid): Path<i64>,
+    Json(request): Json<TransferRequest>,
+) -> Result<Json<Box<ZoneOwnershipTransfer>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    start_transfer(&state, &user, zoneid, &request.username)
+        .await
+        .map(Json)
+}
+
+/// The transfers waiting on the logged-in user to accept or decline them
+pub(crate) async fn api_list(
+    State(state): State<GoatState>,
+    session: Session,
+) -> Result<Json<Vec<ZoneOwnershipTransfer>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    let userid = match user.id {
+        Some(val) => val,
+        None => {
+            return error_result_json!("No user id found in session", StatusCode::UNAUTHORIZED);
+        }
+    };
+
+    let mut conn = match state.connpool().await.acquire().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    match ZoneOwnershipTransfer::get_pending_for_user(&mut conn, userid).await {
+        Ok(val) => Ok(Json(val)),
+        Err(err) => {
+            error!("Failed to list ownership transfers for user id={userid}: {err:?}");
+            error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Take ownership of the zone
+pub(crate) async fn api_accept(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> Result<Json<Box<ZoneOwnershipTransfer>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    resolve_transfer(&state, &user, id, OwnershipTransferStatus::Accepted)
+        .await
+        .map(Json)
+}
+
+/// Turn the zone down
+pub(crate) async fn api_decline(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> Result<Json<Box<ZoneOwnershipTransfer>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    resolve_transfer(&state, &user, id, OwnershipTransferStatus::Declined)
+        .await
+        .map(Json)
+}
+
+/// Keep the zone after all
+pub(crate) async fn api_cancel(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> Result<Json<Box<ZoneOwnershipTransfer>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    resolve_transfer(&state, &user, id, OwnershipTransferStatus::Cancelled)
+        .await
+        .map(Json)
+}
@@ -0,0 +1,292 @@
+//! API endpoints for the record wizards in [crate::wizards], which build the rdata for awkward record types.
+//!
+//! Each wizard either creates the record, or with `validate_only` set, just hands back the rdata it would have
+//! used - handy for checking a record before putting it in a zone file.
+
+use crate::db::User;
+use crate::error_result_json;
+use crate::resourcerecord::DecimalLocation;
+use crate::web::api::filezonerecord::create_record;
+use crate::wizards::caa::{CaPreset, CaaRecord, CA_PRESETS};
+use crate::wizards::mail::{DkimRecord, DmarcRecord, SpfRecord};
+use crate::wizards::{txt_strings, TxtWizard};
+use crate::zones::{default_record_name, FileZoneRecord};
+use goatns_macros::check_api_auth;
+use tower_sessions::Session;
+use utoipa::ToSchema;
+
+use super::*;
+
+/// A CAA record to build
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CaaWizardRequest {
+    /// The zone to add the record to, not needed when only validating
+    #[serde(default)]
+    pub zoneid: Option<i64>,
+    #[serde(default = "default_record_name")]
+    pub name: String,
+    #[serde(default)]
+    pub ttl: u32,
+    #[serde(flatten)]
+    pub caa: CaaRecord,
+    /// Don't save anything, just check the record and return its rdata
+    #[serde(default)]
+    pub validate_only: bool,
+}
+
+/// A LOC record to build, from a latitude and longitude in decimal degrees
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LocWizardRequest {
+    /// The zone to add the record to, not needed when only validating
+    #[serde(default)]
+    pub zoneid: Option<i64>,
+    #[serde(default = "default_record_name")]
+    pub name: String,
+    #[serde(default)]
+    pub ttl: u32,
+    #[serde(flatten)]
+    pub location: DecimalLocation,
+    /// Don't save anything, just check the record and return its rdata
+    #[serde(default)]
+    pub validate_only: bool,
+}
+
+/// A TXT record to build, `T` is the wizard
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TxtWizardRequest<T> {
+    /// The zone to add the record to, not needed when only validating
+    #[serde(default)]
+    pub zoneid: Option<i64>,
+    /// Defaults to where the record usually goes, eg `_dmarc`
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub ttl: u32,
+    #[serde(flatten)]
+    pub record: T,
+    /// Don't save anything, just check the record and return its rdata
+    #[serde(default)]
+    pub validate_only: bool,
+}
+
+/// What a wizard built
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WizardResult {
+    pub rdata: String,
+    /// For TXT records, the character-strings the rdata's sent as - anything over 255 bytes gets split up
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub strings: Vec<String>,
+    /// The saved record, unless it was only being validated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record: Option<Box<FileZoneRecord>>,
+}
+
+/// Turns a validation failure into a 400 with the reason in it
+fn wizard_error<T>(err: GoatNsError) -> Result<T, (StatusCode, Json<ErrorResult>)> {
+    match err {
+        GoatNsError::Generic(msg) => error_result_json!(msg.as_str(), StatusCode::BAD_REQUEST),
+        err => Err(api_error(err)),
+    }
+}
+
+/// The certificate authorities the CAA wizard knows about, this doesn't need you to be logged in
+#[utoipa::path(
+    get,
+    path = "/api/wizard/caa/presets",
+    operation_id = "wizard_caa_presets",
+    responses(
+        (status = 200, description = "Successful", body = [CaPreset]),
+    ),
+    tag = "Records",
+)]
+pub(crate) async fn api_caa_presets() -> Json<Vec<CaPreset>> {
+    Json(CA_PRESETS.to_vec())
+}
+
+/// Build (and optionally save) a CAA record
+#[utoipa::path(
+    post,
+    path = "/api/wizard/caa",
+    operation_id = "wizard_caa",
+    request_body = CaaWizardRequest,
+    responses(
+        (status = 200, description = "Successful", body = WizardResult),
+        (status = 400, description = "The record isn't valid"),
+        (status = 403, description = "Auth failed"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Records",
+)]
+pub(crate) async fn api_caa(
+    State(state): State<GoatState>,
+    session: Session,
+    Json(request): Json<CaaWizardRequest>,
+) -> Result<Json<WizardResult>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    let record = match request
+        .caa
+        .to_record(request.zoneid, &request.name, request.ttl)
+    {
+        Ok(val) => val,
+        Err(err) => return wizard_error(err),
+    };
+    save_wizard_record(&state, &user, record, request.validate_only, "caa")
+        .await
+        .map(Json)
+}
+
+/// Build (and optionally save) a LOC record from decimal degrees
+#[utoipa::path(
+    post,
+    path = "/api/wizard/loc",
+    operation_id = "wizard_loc",
+    request_body = LocWizardRequest,
+    responses(
+        (status = 200, description = "Successful", body = WizardResult),
+        (status = 400, description = "The record isn't valid"),
+        (status = 403, description = "Auth failed"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Records",
+)]
+pub(crate) async fn api_loc(
+    State(state): State<GoatState>,
+    session: Session,
+    Json(request): Json<LocWizardRequest>,
+) -> Result<Json<WizardResult>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    let record = match crate::wizards::loc::to_record(
+        &request.location,
+        request.zoneid,
+        &request.name,
+        request.ttl,
+    ) {
+        Ok(val) => val,
+        Err(err) => return wizard_error(err),
+    };
+    save_wizard_record(&state, &user, record, request.validate_only, "loc")
+        .await
+        .map(Json)
+}
+
+/// Saves what the wizard built, unless it's only validating
+async fn save_wizard_record(
+    state: &GoatState,
+    user: &User,
+    record: FileZoneRecord,
+    validate_only: bool,
+    wizard: &str,
+) -> Result<WizardResult, (StatusCode, Json<ErrorResult>)> {
+    let strings = match record.rrtype.as_str() {
+        "TXT" => txt_strings(&record.rdata),
+        _ => vec![],
+    };
+    if validate_only {
+        return Ok(WizardResult {
+            rdata: record.rdata,
+            strings,
+            record: None,
+        });
+    }
+    let record = create_record(state, user, record).await?;
+    log::info!(
+        "id={:?} action=wizard_{wizard} zoneid={:?} rdata={:?}",
+        user.id,
+        record.zoneid,
+        record.rdata
+    );
+    Ok(WizardResult {
+        rdata: record.rdata.clone(),
+        strings,
+        record: Some(record),
+    })
+}
+
+/// Builds and saves a TXT record for one of the TXT wizards
+async fn txt_wizard<T: TxtWizard>(
+    state: &GoatState,
+    user: &User,
+    request: TxtWizardRequest<T>,
+    wizard: &str,
+) -> Result<Json<WizardResult>, (StatusCode, Json<ErrorResult>)> {
+    let record =
+        match request
+            .record
+            .to_record(request.zoneid, request.name.as_deref(), request.ttl)
+        {
+            Ok(val) => val,
+            Err(err) => return wizard_error(err),
+        };
+    save_wizard_record(state, user, record, request.validate_only, wizard)
+        .await
+        .map(Json)
+}
+
+/// Build (and optionally save) an SPF record, which goes at the zone apex unless you say otherwise
+#[utoipa::path(
+    post,
+    path = "/api/wizard/spf",
+    operation_id = "wizard_spf",
+    request_body = TxtWizardRequest<SpfRecord>,
+    responses(
+        (status = 200, description = "Successful", body = WizardResult),
+        (status = 400, description = "The record isn't valid"),
+        (status = 403, description = "Auth failed"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Records",
+)]
+pub(crate) async fn api_spf(
+    State(state): State<GoatState>,
+    session: Session,
+    Json(request): Json<TxtWizardRequest<SpfRecord>>,
+) -> Result<Json<WizardResult>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    txt_wizard(&state, &user, request, "spf").await
+}
+
+/// Build (and optionally save) a DKIM key record, which goes at `<selector>._domainkey` unless you say otherwise
+#[utoipa::path(
+    post,
+    path = "/api/wizard/dkim",
+    operation_id = "wizard_dkim",
+    request_body = TxtWizardRequest<DkimRecord>,
+    responses(
+        (status = 200, description = "Successful", body = WizardResult),
+        (status = 400, description = "The record isn't valid"),
+        (status = 403, description = "Auth failed"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Records",
+)]
+pub(crate) async fn api_dkim(
+    State(state): State<GoatState>,
+    session: Session,
+    Json(request): Json<TxtWizardRequest<DkimRecord>>,
+) -> Result<Json<WizardResult>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    txt_wizard(&state, &user, request, "dkim").await
+}
+
+/// Build (and optionally save) a DMARC policy record, which goes at `_dmarc` unless you say otherwise
+#[utoipa::path(
+    post,
+    path = "/api/wizard/dmarc",
+    operation_id = "wizard_dmarc",
+    request_body = TxtWizardRequest<DmarcRecord>,
+    responses(
+        (status = 200, description = "Successful", body = WizardResult),
+        (status = 400, description = "The record isn't valid"),
+        (status = 403, description = "Auth failed"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Records",
+)]
+pub(crate) async fn api_dmarc(
+    State(state): State<GoatState>,
+    session: Session,
+    Json(request): Json<TxtWizardRequest<DmarcRecord>>,
+) -> Result<Json<WizardResult>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    txt_wizard(&state, &user, request, "dmarc").await
+}
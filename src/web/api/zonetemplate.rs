@@ -0,0 +1,252 @@
+//! API endpoints for zone templates, which pre-populate records in new zones.
+//!
+//! Anyone logged in can list and read the templates, only admins can change them.
+
+use crate::db::{DBEntity, User};
+use crate::error_result_json;
+use crate::zones::ZoneTemplate;
+use goatns_macros::check_api_auth;
+use tower_sessions::Session;
+
+use super::*;
+
+/// List the available templates
+#[utoipa::path(
+    get,
+    path = "/api/templates",
+    operation_id = "template_list",
+    responses(
+        (status = 200, description = "Successful", body = [ZoneTemplate]),
+        (status = 403, description = "Auth failed"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Templates",
+)]
+pub(crate) async fn api_list(
+    State(state): State<GoatState>,
+    session: Session,
+) -> Result<Json<Vec<ZoneTemplate>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    log::trace!("Listing templates for user={:?}", user.id);
+
+    match ZoneTemplate::get_all(&state.connpool().await).await {
+        Ok(val) => Ok(Json(val)),
+        Err(err) => {
+            error!("Failed to list zone templates: {err:?}");
+            error_result_json!(
+                "Failed to list templates",
+                StatusCode::INTERNAL_SERVER_ERROR
+            )
+        }
+    }
+}
+
+/// Create a template, admins only
+#[utoipa::path(
+    post,
+    path = "/api/template",
+    operation_id = "template_create",
+    request_body = ZoneTemplate,
+    responses(
+        (status = 200, description = "Successful"),
+        (status = 400, description = "Template already exists"),
+        (status = 403, description = "Auth failed"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Templates",
+)]
+pub(crate) async fn api_create(
+    State(state): State<GoatState>,
+    session: Session,
+    Json(template): Json<ZoneTemplate>,
+) -> Result<Json<Box<ZoneTemplate>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    if !user.admin {
+        return error_result_json!("", StatusCode::FORBIDDEN);
+    }
+    if template.id.is_some() {
+        return error_result_json!(
+            "Don't specify an ID when creating a template",
+            StatusCode::BAD_REQUEST
+        );
+    }
+    if template.name.trim().is_empty() {
+        return error_result_json!("Template name cannot be empty", StatusCode::BAD_REQUEST);
+    }
+
+    let mut txn = match state.connpool().await.begin().await {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("failed to get connection to the database: {err:?}");
+            return error_result_json!(
+                "Failed to get a connection to the database!",
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+    };
+
+    match ZoneTemplate::get_by_name(&mut txn, &template.name).await {
+        Ok(Some(_)) => {
+            return error_result_json!("Template already exists!", StatusCode::BAD_REQUEST);
+        }
+        Ok(None) => {}
+        Err(err) => {
+            error!("Failed to query template {}: {err:?}", template.name);
+            return error_result_json!(
+                "Server error querying template!",
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+    };
+
+    let res = match template.save_with_txn(&mut txn).await {
+        Ok(val) => val,
+        Err(err) => {
+            error!("Failed to save template {}: {err:?}", template.name);
+            return error_result_json!(
+                "Server error saving template!",
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+    };
+
+    if let Err(err) = txn.commit().await {
+        error!("Failed to commit transaction saving template: {err:?}");
+        return error_result_json!(
+            "Server error saving template!",
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+    log::info!(
+        "action=template_create user={:?} template={}",
+        user.id,
+        res.name
+    );
+    Ok(Json(res))
+}
+
+/// Update a template, admins only
+#[utoipa::path(
+    put,
+    path = "/api/template",
+    operation_id = "template_update",
+    request_body = ZoneTemplate,
+    responses(
+        (status = 200, description = "Successful"),
+        (status = 403, description = "Auth failed"),
+        (status = 404, description = "Template not found"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Templates",
+)]
+pub(crate) async fn api_update(
+    State(state): State<GoatState>,
+    session: Session,
+    Json(template): Json<ZoneTemplate>,
+) -> Result<Json<Box<ZoneTemplate>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    if !user.admin {
+        return error_result_json!("", StatusCode::FORBIDDEN);
+    }
+    if template.id.is_none() {
+        return error_result_json!("No template ID specified", StatusCode::BAD_REQUEST);
+    }
+
+    match template.save(&state.connpool().await).await {
+        Ok(val) => Ok(Json(val)),
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            error_result_json!("Template not found", StatusCode::NOT_FOUND)
+        }
+        Err(err) => {
+            error!("Failed to update template {:?}: {err:?}", template.id);
+            error_result_json!(
+                "Server error saving template!",
+                StatusCode::INTERNAL_SERVER_ERROR
+            )
+        }
+    }
+}
+
+/// Get a template by ID
+#[utoipa::path(
+    get,
+    path = "/api/template/{id}",
+    operation_id = "template_get",
+    params(
+        ("id" = i64, Path, description = "Template ID"),
+    ),
+    responses(
+        (status = 200, description = "Successful", body = ZoneTemplate),
+        (status = 403, description = "Auth failed"),
+        (status = 404, description = "Template not found"),
+    ),
+    tag = "Templates",
+)]
+pub(crate) async fn api_get(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> Result<Json<Box<ZoneTemplate>>, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    log::trace!("Getting template id={id} for user={:?}", user.id);
+
+    match ZoneTemplate::get(&state.connpool().await, id).await {
+        Ok(val) => Ok(Json(val)),
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            error_result_json!("Template not found", StatusCode::NOT_FOUND)
+        }
+        Err(err) => {
+            error!("Failed to get template id={id}: {err:?}");
+            error_result_json!(
+                "Server error getting template!",
+                StatusCode::INTERNAL_SERVER_ERROR
+            )
+        }
+    }
+}
+
+/// Delete a template, admins only
+#[utoipa::path(
+    delete,
+    path = "/api/template/{id}",
+    operation_id = "template_delete",
+    params(
+        ("id" = i64, Path, description = "Template ID"),
+    ),
+    responses(
+        (status = 200, description = "Successful"),
+        (status = 403, description = "Auth failed"),
+        (status = 404, description = "Template not found"),
+        (status = 500, description = "Something broke!"),
+    ),
+    tag = "Templates",
+)]
+pub(crate) async fn api_delete(
+    State(state): State<GoatState>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResult>)> {
+    check_api_auth!();
+    if !user.admin {
+        return error_result_json!("", StatusCode::FORBIDDEN);
+    }
+
+    let pool = state.connpool().await;
+    let template = match ZoneTemplate::get(&pool, id).await {
+        Ok(val) => val,
+        Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound)) => {
+            return error_result_json!("Template not found", StatusCode::NOT_FOUND);
+        }
+        Err(err) => {
+            error!("Failed to get template id={id}: {err:?}");
+            return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    if let Err(err) = template.delete(&pool).await {
+        error!("Failed to delete template id={id}: {err:?}");
+        return error_result_json!("Internal server error", StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    log::info!("action=template_delete user={:?} template={id}", user.id);
+    Ok(StatusCode::OK)
+}
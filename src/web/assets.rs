@@ -0,0 +1,129 @@
+//! Static assets - cache-busting URLs for the templates, and the caching headers that go with them.
+//!
+//! Every file in the static dir gets hashed at startup, templates link to `/static/{path}?v={hash}` via the `asset`
+//! filter, and requests with the current hash get told they can cache it forever.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use axum::extract::Request;
+use axum::http::header::CACHE_CONTROL;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use sha2::{Digest, Sha256};
+
+/// Precompressed copies sit next to the originals, they don't get their own URLs
+const PRECOMPRESSED_EXTENSIONS: [&str; 3] = ["br", "zst", "gz"];
+
+const CACHE_IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const CACHE_REVALIDATE: &str = "no-cache";
+
+static ASSET_HASHES: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Hash everything in the static dir, only the first call does anything
+pub fn init_asset_hashes(static_dir: &Path) {
+    ASSET_HASHES.get_or_init(|| {
+        let hashes = hash_static_dir(static_dir);
+        log::debug!("Hashed {} static assets", hashes.len());
+        hashes
+    });
+}
+
+/// Walks the static dir and returns a map of relative path (with `/` separators) to a short content hash
+pub fn hash_static_dir(static_dir: &Path) -> HashMap<String, String> {
+    let mut hashes = HashMap::new();
+    let mut dirs = vec![static_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(val) => val,
+            Err(err) => {
+                log::warn!("Couldn't read static dir {dir:?} to hash assets: {err:?}");
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| PRECOMPRESSED_EXTENSIONS.contains(&ext))
+            {
+                continue;
+            }
+            let relative = match path.strip_prefix(static_dir) {
+                Ok(val) => val
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                Err(_) => continue,
+            };
+            match std::fs::read(&path) {
+                Ok(contents) => {
+                    let digest = format!("{:x}", Sha256::digest(&contents));
+                    hashes.insert(relative, digest[0..16].to_string());
+                }
+                Err(err) => log::warn!("Couldn't read static asset {path:?} to hash it: {err:?}"),
+            }
+        }
+    }
+    hashes
+}
+
+fn asset_hash(path: &str) -> Option<&'static String> {
+    ASSET_HASHES.get().and_then(|hashes| hashes.get(path))
+}
+
+/// The URL for a static asset, with its hash on the end if we know it
+pub fn asset_url(path: &str) -> String {
+    let path = path.trim_start_matches('/');
+    match asset_hash(path) {
+        Some(hash) => format!("/static/{path}?v={hash}"),
+        None => format!("/static/{path}"),
+    }
+}
+
+/// Template filters, `use` this in modules with templates so `{{ "css/goatns.css"|asset|safe }}` works
+pub mod filters {
+    use super::*;
+
+    /// Turns a path relative to the static dir into a cache-busting URL
+    #[allow(clippy::needless_pass_by_value)] // askama's filter signature
+    pub fn asset<T: Display>(path: T) -> askama::Result<String> {
+        Ok(asset_url(&path.to_string()))
+    }
+
+    /// Shows punycode names in unicode, `{{ zone.name|unicode }}`
+    #[allow(clippy::needless_pass_by_value)] // askama's filter signature
+    pub fn unicode<T: Display>(name: T) -> askama::Result<String> {
+        Ok(crate::name_policy::to_unicode(&name.to_string()))
+    }
+}
+
+/// Pick the Cache-Control header for a request to the static service
+pub fn cache_control_for(path: &str, query: Option<&str>) -> &'static str {
+    let version = query.and_then(|query| query.split('&').find_map(|part| part.strip_prefix("v=")));
+    match (asset_hash(path.trim_start_matches('/')), version) {
+        (Some(hash), Some(version)) if hash == version => CACHE_IMMUTABLE,
+        _ => CACHE_REVALIDATE,
+    }
+}
+
+/// Sets caching headers on static files, hashed URLs can be cached forever since the URL changes with the content
+pub async fn cache_headers(req: Request, next: Next) -> Response {
+    let cache_control = cache_control_for(req.uri().path(), req.uri().query());
+    let mut response = next.run(req).await;
+    if response.status().is_success() {
+        response
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    }
+    response
+}
@@ -1,7 +1,8 @@
 use super::GoatState;
 use crate::config::ConfigFile;
-use crate::db::{DBEntity, User};
+use crate::db::{DBEntity, OidcCache, User};
 use crate::error::GoatNsError;
+use crate::web::assets::filters;
 use crate::web::utils::Urls;
 use crate::web::GoatStateTrait;
 use crate::COOKIE_NAME;
@@ -109,39 +110,137 @@ pub enum ParserError {
     ClaimsVerificationError { content: ClaimsVerificationError },
 }
 
-/// Pull the OIDC Discovery details
+/// Pull the OIDC Discovery details from the IdP, and stash them in the state and the DB
 pub async fn oauth_get_discover(
     state: &mut GoatState,
 ) -> Result<CustomProviderMetadata, GoatNsError> {
     log::debug!("Getting discovery data");
-    let issuer_url = IssuerUrl::new(state.read().await.config.oauth2_config_url.clone())
+    let issuer = state.read().await.config.oauth2_config_url.clone();
+    let issuer_url =
+        IssuerUrl::new(issuer.clone()).map_err(|err| GoatNsError::Oidc(err.to_string()))?;
+    let metadata = CoreProviderMetadata::discover_async(issuer_url, async_http_client)
+        .await
         .map_err(|err| GoatNsError::Oidc(err.to_string()))?;
-    match CoreProviderMetadata::discover_async(issuer_url, async_http_client).await {
-        Err(e) => Err(GoatNsError::Oidc(e.to_string())),
-        Ok(val) => {
-            state.oidc_update(val.clone()).await;
-            Ok(val)
+    let updated = Utc::now();
+    state.oidc_update(metadata.clone(), updated).await;
+
+    let cache = OidcCache {
+        issuer,
+        metadata: serde_json::to_string(&metadata)
+            .map_err(|err| GoatNsError::Oidc(err.to_string()))?,
+        jwks: serde_json::to_string(metadata.jwks())
+            .map_err(|err| GoatNsError::Oidc(err.to_string()))?,
+        updated,
+    };
+    if let Err(err) = cache.save(&state.connpool().await).await {
+        error!("Failed to store OIDC discovery data in the DB: {err:?}");
+    }
+    Ok(metadata)
+}
+
+/// Load the OIDC discovery data we stored last time, as long as it's not past the grace period
+async fn oauth_load_cached(
+    state: &mut GoatState,
+) -> Result<Option<CustomProviderMetadata>, GoatNsError> {
+    let (issuer, max_age) = {
+        let reader = state.read().await;
+        (
+            reader.config.oauth2_config_url.clone(),
+            reader
+                .config
+                .oauth2_metadata_ttl
+                .saturating_add(reader.config.oauth2_metadata_grace),
+        )
+    };
+    let cache = match OidcCache::get(&state.connpool().await, &issuer).await? {
+        Some(val) => val,
+        None => return Ok(None),
+    };
+    if !metadata_is_fresh(cache.updated, max_age) {
+        log::debug!("Cached OIDC discovery data is too old to use");
+        return Ok(None);
+    }
+    let metadata: CustomProviderMetadata =
+        serde_json::from_str(&cache.metadata).map_err(|err| GoatNsError::Oidc(err.to_string()))?;
+    let jwks: CoreJsonWebKeySet =
+        serde_json::from_str(&cache.jwks).map_err(|err| GoatNsError::Oidc(err.to_string()))?;
+    let metadata = metadata.set_jwks(jwks);
+    state.oidc_update(metadata.clone(), cache.updated).await;
+    Ok(Some(metadata))
+}
+
+/// Is something updated at `updated` less than `max_age` seconds old?
+pub fn metadata_is_fresh(updated: DateTime<Utc>, max_age: u64) -> bool {
+    let age = Utc::now() - updated;
+    age.num_seconds() < i64::try_from(max_age).unwrap_or(i64::MAX)
+}
+
+/// Get the OIDC discovery data without going to the IdP if we can help it - what's in memory, then what's in the
+/// DB, and only if neither is usable do we ask the IdP while the user waits.
+pub async fn oauth_provider_metadata(
+    state: &mut GoatState,
+) -> Result<CustomProviderMetadata, GoatNsError> {
+    {
+        let reader = state.read().await;
+        let max_age = reader
+            .config
+            .oauth2_metadata_ttl
+            .saturating_add(reader.config.oauth2_metadata_grace);
+        if let Some(metadata) = &reader.oidc_config {
+            if metadata_is_fresh(reader.oidc_config_updated, max_age) {
+                log::debug!("Using cached OIDC discovery data");
+                return Ok(metadata.clone());
+            }
         }
     }
+    match oauth_load_cached(state).await {
+        Ok(Some(metadata)) => return Ok(metadata),
+        Ok(None) => {}
+        Err(err) => error!("Failed to load cached OIDC discovery data: {err:?}"),
+    }
+    oauth_get_discover(state).await
 }
 
-pub async fn oauth_start(state: &mut GoatState) -> Result<url::Url, GoatNsError> {
-    let last_updated: DateTime<Utc> = state.read().await.oidc_config_updated;
-    let now: DateTime<Utc> = Utc::now();
-
-    let delta = now - last_updated;
-    let discovery = oauth_get_discover(state).await?;
-
-    let provider_metadata: CustomProviderMetadata = match delta.num_minutes() > 5 {
-        true => discovery,
-        false => {
-            log::debug!("Using cached OIDC discovery data");
-            let config = state.read().await.oidc_config.clone();
-            let meta = config.unwrap_or(discovery);
-            state.oidc_update(meta.clone()).await;
-            meta
+/// Keeps the OIDC discovery data fresh in the background, so logins don't wait on (or fail because of) the IdP.
+///
+/// If a refresh fails we keep using what we've got until it's past `oauth2_metadata_grace`.
+pub async fn oidc_refresh_loop(mut state: GoatState) {
+    match oauth_load_cached(&mut state).await {
+        Ok(Some(_)) => log::info!("Loaded cached OIDC discovery data from the DB"),
+        Ok(None) => {}
+        Err(err) => error!("Failed to load cached OIDC discovery data: {err:?}"),
+    }
+    let ttl = state.read().await.config.oauth2_metadata_ttl;
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(ttl.clamp(1, 60)));
+    loop {
+        interval.tick().await;
+        let (has_metadata, updated, grace) = {
+            let reader = state.read().await;
+            (
+                reader.oidc_config.is_some(),
+                reader.oidc_config_updated,
+                reader.config.oauth2_metadata_grace,
+            )
+        };
+        if has_metadata && metadata_is_fresh(updated, ttl) {
+            continue;
         }
-    };
+        match oauth_get_discover(&mut state).await {
+            Ok(_) => log::debug!("Refreshed OIDC discovery data"),
+            Err(err) => match has_metadata && metadata_is_fresh(updated, ttl.saturating_add(grace)) {
+                true => log::warn!(
+                    "Failed to refresh OIDC discovery data, using what we got at {updated}: {err:?}"
+                ),
+                false => error!(
+                    "Failed to refresh OIDC discovery data and nothing usable is cached, logins will fail: {err:?}"
+                ),
+            },
+        }
+    }
+}
+
+pub async fn oauth_start(state: &mut GoatState) -> Result<url::Url, GoatNsError> {
+    let provider_metadata = oauth_provider_metadata(state).await?;
     log::trace!("provider metadata: {provider_metadata:?}");
 
     // Generate a PKCE challenge.
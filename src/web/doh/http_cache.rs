@@ -0,0 +1,122 @@
+//! Makes DoH GET responses easy for HTTP caches to hold on to, ref <https://www.rfc-editor.org/rfc/rfc8484#section-5.1>
+//!
+//! Responses get a weak ETag from what's in them and the serials of the zones the answers came from, and
+//! `Cache-Control`, `Expires` and `Age` that agree with the lowest TTL in the answer. `If-None-Match` and
+//! `If-Modified-Since` get a 304 if the answer's the same.
+//!
+//! Nothing keeps track of when a record last changed, so `Last-Modified` is when this server first sent that ETag.
+//! That's never earlier than the real change, so a cache can't be told an old answer's still good when it isn't.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use axum::body::Body;
+use axum::http::header::{
+    AGE, CACHE_CONTROL, ETAG, EXPIRES, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, VARY,
+};
+use axum::http::response::Builder;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use chrono::{DateTime, SubsecRound, Utc};
+use sha2::{Digest, Sha256};
+
+/// How many ETags we remember the first sighting of, it starts again once it's full
+const MAX_SEEN: usize = 10_000;
+/// What dates look like in HTTP headers, ref <https://www.rfc-editor.org/rfc/rfc9110#section-5.6.7>
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+lazy_static! {
+    static ref FIRST_SEEN: Mutex<HashMap<String, DateTime<Utc>>> = Mutex::new(HashMap::new());
+}
+
+/// How a DoH GET response can be cached
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CacheInfo {
+    pub etag: String,
+    pub last_modified: DateTime<Utc>,
+    /// The lowest TTL in the answer
+    pub ttl: u32,
+    /// When the response was made
+    pub now: DateTime<Utc>,
+}
+
+impl CacheInfo {
+    /// Work it out from the response's type and body, and the serials of the zones it came from
+    pub fn new(content_type: &str, body: &[u8], serials: &[u32], ttl: u32) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(content_type.as_bytes());
+        for serial in serials {
+            hasher.update(serial.to_be_bytes());
+        }
+        hasher.update(body);
+        let digest = format!("{:x}", hasher.finalize());
+        let etag = format!("W/\"{}\"", &digest[0..32]);
+
+        let now = Utc::now().trunc_subsecs(0);
+        Self {
+            last_modified: first_seen(&etag, now),
+            etag,
+            ttl,
+            now,
+        }
+    }
+
+    /// Does the client already have this response? `If-Modified-Since` is only checked if there's no
+    /// `If-None-Match`, ref <https://www.rfc-editor.org/rfc/rfc9110#section-13.1.3>
+    pub fn not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+            let Ok(if_none_match) = if_none_match.to_str() else {
+                return false;
+            };
+            // it's a weak comparison, so W/ doesn't matter
+            let ours = self.etag.trim_start_matches("W/");
+            return if_none_match
+                .split(',')
+                .map(|etag| etag.trim())
+                .any(|etag| etag == "*" || etag.trim_start_matches("W/") == ours);
+        }
+        headers
+            .get(IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+            .is_some_and(|since| self.last_modified <= since.with_timezone(&Utc))
+    }
+
+    /// Add the caching headers to a response
+    pub fn headers(&self, builder: Builder) -> Builder {
+        let expires = self.now + chrono::Duration::seconds(self.ttl.into());
+        builder
+            .header(ETAG, &self.etag)
+            .header(
+                LAST_MODIFIED,
+                self.last_modified.format(HTTP_DATE).to_string(),
+            )
+            .header(CACHE_CONTROL, format!("max-age={}", self.ttl))
+            .header(EXPIRES, expires.format(HTTP_DATE).to_string())
+            // it's fresh from us
+            .header(AGE, "0")
+            // JSON and wire format answers come from the same URL
+            .header(VARY, "Accept")
+    }
+
+    /// Tell them what they've got is still good
+    pub fn not_modified_response(&self) -> Result<Response, axum::http::Error> {
+        self.headers(Response::builder().status(StatusCode::NOT_MODIFIED))
+            .body(Body::empty())
+    }
+}
+
+/// When we first sent this ETag
+fn first_seen(etag: &str, now: DateTime<Utc>) -> DateTime<Utc> {
+    let mut seen = match FIRST_SEEN.lock() {
+        Ok(seen) => seen,
+        Err(err) => {
+            log::error!("DoH ETag lock is poisoned: {err:?}");
+            return now;
+        }
+    };
+    if seen.len() >= MAX_SEEN && !seen.contains_key(etag) {
+        seen.clear();
+    }
+    *seen.entry(etag.to_string()).or_insert(now)
+}
@@ -2,9 +2,10 @@ use askama_axum::IntoResponse;
 use axum::body::{Body, Bytes};
 use axum::extract::{Query, State};
 use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::from_fn_with_state;
 use axum::response::Response;
 use axum::routing::{get, post};
-use axum::Router;
+use axum::{Extension, Router};
 use base64::{engine::general_purpose, Engine as _};
 use packed_struct::PackedStruct;
 use serde::{Deserialize, Serialize};
@@ -12,11 +13,20 @@ use std::str::from_utf8;
 
 use crate::db::get_all_fzr_by_name;
 use crate::enums::{Rcode, RecordClass, RecordType};
-use crate::reply::Reply;
+use crate::metrics::{Metrics, METRICS};
+use crate::reply::{Answer, Reply};
 use crate::resourcerecord::InternalResourceRecord;
-use crate::servers::{parse_query, QueryProtocol};
+use crate::servers::{apply_response_policy, check_query, parse_query, Checked, QueryProtocol};
+use crate::web::middleware::client_ip::ClientIp;
+use crate::web::middleware::doh_auth;
 use crate::web::GoatState;
+use crate::zones::FileZone;
 use crate::{Header, Question, HEADER_BYTES};
+use http_cache::CacheInfo;
+use usage::DohToken;
+
+pub mod http_cache;
+pub mod usage;
 
 // TODO: when responding to requests and have an empty response, if we can find the root zone, include the SOA minimum
 
@@ -131,7 +141,7 @@ async fn parse_raw_http(bytes: Vec<u8>) -> Result<GetQueryString, String> {
     Ok(GetQueryString {
         dns: None,
         name: Some(name),
-        rrtype: Some(question.qtype.to_string()),
+        rrtype: Some(crate::generic::type_name(question.qtype_code)),
         id: header.id,
         cd: header.cd,
         ..Default::default()
@@ -157,6 +167,14 @@ pub(crate) fn response_406() -> Response {
     )
         .into_response()
 }
+pub(crate) fn response_400() -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        [(axum::http::header::CACHE_CONTROL, "max-age=1")],
+        "",
+    )
+        .into_response()
+}
 pub(crate) fn response_500() -> Response {
     (
         StatusCode::INTERNAL_SERVER_ERROR,
@@ -168,9 +186,17 @@ pub(crate) fn response_500() -> Response {
 
 pub async fn handle_get(
     State(state): State<GoatState>,
+    client_ip: Option<Extension<ClientIp>>,
+    token: Option<Extension<DohToken>>,
     headers: HeaderMap,
     Query(query): Query<GetQueryString>,
 ) -> Result<Response, Response> {
+    let client_ip = client_ip.map(|Extension(ClientIp(client_ip))| client_ip);
+    if let Some(client_ip) = client_ip {
+        log::debug!("DoH GET from {client_ip}");
+    }
+    // GETs are answered here rather than by parse_query, so they're counted here
+    Metrics::increment(&METRICS.queries_doh);
     // TODO: accept header filtering probably should be a middleware since it applies to the whole /doh route but those things are annoying as heck
     let response_type: ResponseType = get_response_type_from_headers(&headers);
     if let ResponseType::Invalid = response_type {
@@ -201,18 +227,20 @@ pub async fn handle_get(
         id = query.id;
     }
 
+    if let Some(Extension(DohToken(tokenkey))) = token {
+        if state.read().await.config.doh.auth.usage_enabled() {
+            usage::record(&tokenkey, &qname);
+        }
+    }
+
     let mut read_txn = state.read().await.connpool.begin().await.map_err(|err| {
         log::error!("Failed to get DB connection: {err:?}");
         response_500()
     })?;
 
-    let records = match get_all_fzr_by_name(
-        &mut read_txn,
-        &qname,
-        RecordType::from(rrtype.as_str()) as u16,
-    )
-    .await
-    {
+    // TYPE65534 and friends get looked up too, ref RFC3597
+    let rrtype_code = crate::generic::parse_type(&rrtype).unwrap_or(RecordType::InvalidType as u16);
+    let records = match get_all_fzr_by_name(&mut read_txn, &qname, rrtype_code).await {
         Ok(value) => value,
         Err(error) => {
             log::error!("Failed to query {qname}/{}: {error:?}", rrtype);
@@ -222,6 +250,22 @@ pub async fn handle_get(
 
     log::trace!("Completed record request...");
 
+    // the ETag changes when any of the zones the answers came from do
+    let mut zoneids: Vec<i64> = records.iter().filter_map(|r| r.zoneid).collect();
+    zoneids.sort_unstable();
+    zoneids.dedup();
+    let mut serials = Vec::with_capacity(zoneids.len());
+    for zoneid in zoneids {
+        serials.push(
+            FileZone::get_serial_with_txn(zoneid, &mut read_txn)
+                .await
+                .map_err(|err| {
+                    log::error!("Failed to get the serial for zone {zoneid}: {err:?}");
+                    response_500()
+                })?,
+        );
+    }
+
     let ttl = records.iter().map(|r| r.ttl).min();
     let ttl = match ttl {
         Some(val) => val.to_owned(),
@@ -233,14 +277,14 @@ pub async fn handle_get(
 
     log::trace!("Returned records: {records:?}");
 
-    match response_type {
-        ResponseType::Invalid => Err(response_500()),
+    let (content_type, body) = match response_type {
+        ResponseType::Invalid => return Err(response_500()),
         ResponseType::Json => {
             let answer = records
                 .iter()
                 .map(|rec| JSONRecord {
                     name: rec.name.clone(),
-                    qtype: RecordType::from(rec.rrtype.clone()) as u16,
+                    qtype: rec.rrtype_code(),
                     ttl: rec.ttl.to_owned(),
                     data: Some(rec.rdata.clone()),
                 })
@@ -256,7 +300,7 @@ pub async fn handle_get(
                 client_dnssec_disable: false,
                 question: vec![JSONQuestion {
                     name: qname,
-                    qtype: RecordType::from(rrtype) as u16,
+                    qtype: rrtype_code,
                 }],
                 ..Default::default()
             };
@@ -265,26 +309,18 @@ pub async fn handle_get(
                 log::error!("Failed to parse DoH GET request into JSON: {err:?}");
                 response_500()
             })?;
-
-            let response_builder = axum::response::Response::builder()
-                .status(StatusCode::OK)
-                .header("Content-type", "application/dns-json")
-                .header("Cache-Control", format!("max-age={ttl}"));
             // TODO: add handler for DNSSEC responses
-            response_builder.body(Body::from(response)).map_err(|err| {
-                log::error!("Failed to turn DoH GET request into JSON: {err:?}");
-                response_500()
-            })
+            ("application/dns-json", response.into_bytes())
         }
         ResponseType::Raw => {
-            let answers: Vec<InternalResourceRecord> = records
+            let answers: Vec<Answer> = records
                 .iter()
                 .filter_map(|r| {
                     let rec: Option<InternalResourceRecord> = match r.to_owned().try_into() {
                         Ok(val) => Some(val),
                         Err(_) => None,
                     };
-                    rec
+                    rec.map(|rec| Answer::named(r.name.clone().into_bytes(), rec))
                 })
                 .collect();
 
@@ -306,40 +342,59 @@ pub async fn handle_get(
                     nscount: 0,
                     arcount: 0,
                 },
-                question: Some(Question {
-                    qname: qname.into(),
-                    qtype: RecordType::from(rrtype),
-                    qclass: RecordClass::Internet,
-                }),
+                question: Some(Question::with_type_code(
+                    qname.into(),
+                    rrtype_code,
+                    RecordClass::Internet,
+                )),
                 answers,
                 authorities: vec![], // TODO: authorities in handle_get raw response
                 additional: vec![],  // TODO: additional fields in handle_get raw response
+                edns: None,
             };
+            let reply = apply_response_policy(client_ip, reply);
 
-            match reply.as_bytes().await {
-                Ok(value) => axum::response::Response::builder()
-                    .status(StatusCode::OK)
-                    .header("Content-type", "application/dns-message")
-                    .header("Cache-Control", format!("max-age={ttl}"))
-                    .body(Body::from(value))
-                    .map_err(|err| {
-                        log::error!("Failed to turn DoH GET request into bytes: {err:?}");
-                        response_500()
-                    }),
+            match reply.as_bytes() {
+                Ok(value) => ("application/dns-message", value),
                 Err(err) => {
                     log::error!("Failed to turn DoH GET request into bytes: {err:?}");
-                    Err(response_500())
+                    return Err(response_500());
                 }
             }
         }
+    };
+
+    let cache = CacheInfo::new(content_type, &body, &serials, ttl);
+    if cache.not_modified(&headers) {
+        return cache.not_modified_response().map_err(|err| {
+            log::error!("Failed to build DoH GET 304 response: {err:?}");
+            response_500()
+        });
     }
+    cache
+        .headers(
+            axum::response::Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-type", content_type),
+        )
+        .body(Body::from(body))
+        .map_err(|err| {
+            log::error!("Failed to build DoH GET response: {err:?}");
+            response_500()
+        })
 }
 
 pub async fn handle_post(
     State(state): State<GoatState>,
+    client_ip: Option<Extension<ClientIp>>,
+    token: Option<Extension<DohToken>>,
     headers: HeaderMap,
     body: Bytes,
 ) -> Result<Response, Response> {
+    let client_ip = client_ip.map(|Extension(ClientIp(client_ip))| client_ip);
+    if let Some(client_ip) = client_ip {
+        log::debug!("DoH POST from {client_ip}");
+    }
     // TODO: accept header filtering probably should be a middleware since it applies to the whole /doh route but those things are annoying as heck
     let response_type: ResponseType = get_response_type_from_headers(&headers);
     if let ResponseType::Invalid = response_type {
@@ -353,39 +408,44 @@ pub async fn handle_post(
     let state_reader = state.read().await;
     let datastore = state_reader.tx.clone();
 
-    let res = parse_query(
-        datastore,
-        body.len(),
-        &body,
-        state_reader.config.capture_packets,
-        QueryProtocol::DoH,
-    )
-    .await;
+    let res = match check_query(state_reader.config.query_checks, &body, None) {
+        Checked::Drop => return Err(response_400()),
+        Checked::Reply(reply) => Ok(reply),
+        Checked::Answer => {
+            parse_query(
+                datastore,
+                body.len(),
+                &body,
+                state_reader.config.capture_packets,
+                QueryProtocol::DoH,
+                client_ip,
+            )
+            .await
+        }
+    };
+
+    if let (Ok(reply), Some(Extension(DohToken(tokenkey)))) = (&res, token) {
+        if let Some(question) = reply
+            .question
+            .as_ref()
+            .filter(|_| state_reader.config.doh.auth.usage_enabled())
+        {
+            usage::record(&tokenkey, from_utf8(&question.qname).unwrap_or("-"));
+        }
+    }
 
     match res {
-        Ok(mut reply) => {
-            let bytes = match reply.as_bytes().await {
-                Ok(value) => {
-                    // we need to truncate the response
-                    if value.len() > 65535 {
-                        reply.header.truncated = true;
-                        let mut bytes: Vec<u8> = reply.as_bytes().await.map_err(|err| {
-                            log::error!("Failed to turn DoH POST response into bytes: {err:?}");
-                            response_500()
-                        })?;
-                        bytes.resize(65535, 0);
-                        bytes
-                    } else {
-                        value
-                    }
-                }
+        Ok(reply) => {
+            // DNS messages can't be any bigger than this (RFC8484 section 6)
+            let bytes = match reply.as_bytes_udp(65535) {
+                Ok(value) => value,
                 Err(error) => {
                     log::error!("Failed to turn DoH POST response into bytes! {error:?}");
                     return Err(response_500());
                 }
             };
 
-            let ttl = reply.answers.iter().map(|a| a.ttl()).min();
+            let ttl = reply.answers.iter().map(|a| a.record.ttl()).min();
             let ttl = match ttl {
                 Some(ttl) => ttl.to_owned(),
                 None => 1,
@@ -408,9 +468,10 @@ pub async fn handle_post(
     }
 }
 
-pub fn new() -> Router<GoatState> {
+pub fn new(state: GoatState) -> Router<GoatState> {
     Router::new()
         // just zone things
         .route("/", get(handle_get))
         .route("/", post(handle_post))
+        .route_layer(from_fn_with_state(state, doh_auth::doh_auth))
 }
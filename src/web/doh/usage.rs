@@ -0,0 +1,119 @@
+//! Counts the DoH queries made with API tokens, so a resolver can be shared with people and you can see who's using it
+//! for what. See `doh.auth.usage_days` in [crate::config::DohAuthConfig].
+//!
+//! Queries are counted in memory and written to [DohUsage] every [FLUSH_INTERVAL], rather than the database being
+//! hit for every one.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{NaiveDate, TimeDelta, Utc};
+use sqlx::SqlitePool;
+
+use crate::db::DohUsage;
+use crate::error::GoatNsError;
+
+/// How often the counts are written to the database
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+/// How many token, day and name combinations are kept between flushes, names past this are counted as [OTHER_NAMES]
+const MAX_PENDING: usize = 10_000;
+/// What the names that didn't fit are counted as, it's not a valid name so it can't clash with one
+pub const OTHER_NAMES: &str = "(other)";
+
+/// The API token a DoH request authenticated with, [crate::web::middleware::doh_auth] adds it to the request
+#[derive(Clone, Debug)]
+pub struct DohToken(pub String);
+
+lazy_static! {
+    /// Queries that haven't been written yet, by token key, day and name
+    static ref PENDING: Mutex<HashMap<(String, NaiveDate, String), u64>> = Mutex::new(HashMap::new());
+}
+
+/// Count a query for `name` by the token
+pub fn record(tokenkey: &str, name: &str) {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let day = Utc::now().date_naive();
+    let Ok(mut pending) = PENDING.lock() else {
+        return;
+    };
+    add(&mut pending, (tokenkey.to_string(), day, name), 1);
+}
+
+fn add(
+    pending: &mut HashMap<(String, NaiveDate, String), u64>,
+    mut key: (String, NaiveDate, String),
+    queries: u64,
+) {
+    if pending.len() >= MAX_PENDING && !pending.contains_key(&key) {
+        key.2 = OTHER_NAMES.to_string();
+    }
+    *pending.entry(key).or_default() += queries;
+}
+
+/// Count them again, for when they couldn't be written
+fn put_back(usage: Vec<DohUsage>) {
+    let Ok(mut pending) = PENDING.lock() else {
+        return;
+    };
+    for row in usage {
+        add(&mut pending, (row.tokenkey, row.day, row.name), row.queries);
+    }
+}
+
+/// Everything that's been counted since the last time
+pub fn take() -> Vec<DohUsage> {
+    let pending = match PENDING.lock() {
+        Ok(mut pending) => std::mem::take(&mut *pending),
+        Err(_) => return vec![],
+    };
+    pending
+        .into_iter()
+        .map(|((tokenkey, day, name), queries)| DohUsage {
+            tokenkey,
+            day,
+            name,
+            queries,
+        })
+        .collect()
+}
+
+/// Write what's been counted to the database, returns how many rows it was. If it can't be written it's kept for the
+/// next time.
+pub async fn flush(pool: &SqlitePool) -> Result<usize, GoatNsError> {
+    let usage = take();
+    if usage.is_empty() {
+        return Ok(0);
+    }
+    match write(pool, &usage).await {
+        Ok(()) => Ok(usage.len()),
+        Err(err) => {
+            put_back(usage);
+            Err(err)
+        }
+    }
+}
+
+async fn write(pool: &SqlitePool, usage: &[DohUsage]) -> Result<(), GoatNsError> {
+    let mut txn = pool.begin().await?;
+    for row in usage {
+        row.add_with_txn(&mut txn).await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Writes the counts every [FLUSH_INTERVAL], and forgets about days older than `keep_days`
+pub async fn flush_loop(pool: SqlitePool, keep_days: u32) {
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = flush(&pool).await {
+            log::error!("Failed to store DoH usage: {err:?}");
+        }
+        let before = Utc::now().date_naive() - TimeDelta::days(i64::from(keep_days));
+        if let Err(err) = DohUsage::cleanup(&pool, before).await {
+            log::error!("Failed to clean up DoH usage: {err:?}");
+        }
+    }
+}
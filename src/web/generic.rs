@@ -1,19 +1,88 @@
 use super::*;
 use crate::enums::ContactDetails;
+use crate::health::{HealthReport, HEALTH};
+use crate::web::assets::filters;
+use crate::web::middleware::request_id::current_request_id;
 use askama::Template;
 use axum::extract::{Query, State};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
 use serde::Deserialize;
 
 pub async fn status() -> String {
     STATUS_OK.to_string()
 }
 
+/// Liveness - if we can answer this, the process is up
+pub async fn healthz() -> String {
+    STATUS_OK.to_string()
+}
+
+/// Counters and database pool stats, for Prometheus
+pub async fn metrics() -> String {
+    crate::metrics::render()
+}
+
+/// Readiness - the datastore and listeners are running, the database answers and the last DNS self-check worked
+pub(crate) async fn readyz(State(state): State<GoatState>) -> (StatusCode, Json<HealthReport>) {
+    let self_check_enabled = state.read().await.config.healthcheck_interval > 0;
+    let database = sqlx::query("SELECT 1")
+        .execute(&state.connpool().await)
+        .await
+        .is_ok();
+    let report = HEALTH.report(database, self_check_enabled);
+    match report.ready() {
+        true => (StatusCode::OK, Json(report)),
+        false => (StatusCode::SERVICE_UNAVAILABLE, Json(report)),
+    }
+}
+
 #[derive(Template)]
 #[template(path = "index.html")]
 pub(crate) struct IndexTemplate {
     admin_contact: String,
     error: Option<String>,
     message: Option<String>,
+    request_id: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+/// A friendly error page, for when there's nowhere sensible to redirect to
+pub(crate) struct ErrorPage {
+    status: u16,
+    reason: String,
+    message: String,
+    request_id: Option<String>,
+}
+
+impl ErrorPage {
+    /// A page for the status, with the message if there is one or the status's reason if not
+    pub(crate) fn new(status: StatusCode, message: Option<String>) -> Self {
+        let reason = status.canonical_reason().unwrap_or("Error").to_string();
+        let message = match message {
+            Some(message) if !message.trim().is_empty() => message,
+            _ => match status {
+                StatusCode::NOT_FOUND => "We couldn't find what you were looking for.".to_string(),
+                StatusCode::FORBIDDEN => "You don't have access to that.".to_string(),
+                status if status.is_server_error() => {
+                    "Something went wrong on our end, please try again.".to_string()
+                }
+                _ => reason.clone(),
+            },
+        };
+        Self {
+            status: status.as_u16(),
+            reason,
+            message,
+            request_id: current_request_id(),
+        }
+    }
+
+    /// The page as a response with the right status, for handlers to send back
+    pub(crate) fn response(status: StatusCode, message: impl ToString) -> Response {
+        (status, Self::new(status, Some(message.to_string()))).into_response()
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -21,6 +90,8 @@ pub(crate) struct IndexTemplate {
 pub(crate) struct QueryErrorOrMessage {
     pub(crate) error: Option<String>,
     pub(crate) message: Option<String>,
+    /// The request that caused the error
+    pub(crate) request_id: Option<String>,
 }
 
 pub(crate) async fn index(
@@ -40,5 +111,6 @@ pub(crate) async fn index(
         admin_contact,
         error: query.error,
         message: query.message,
+        request_id: query.request_id,
     })
 }
@@ -1 +1,6 @@
-
+/// The CSP nonce for the current request, for use in templates: `<script nonce="{{ csp_nonce!() }}">`
+macro_rules! csp_nonce {
+    () => {
+        $crate::web::middleware::csp::current_nonce()
+    };
+}
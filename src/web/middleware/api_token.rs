@@ -0,0 +1,30 @@
+//! Keeps track of when API tokens were last used, so people can find the ones they've forgotten about
+
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::Utc;
+use tower_sessions::Session;
+
+use crate::db::UserAuthToken;
+use crate::web::middleware::rate_limit::SESSION_TOKEN_KEY;
+use crate::web::GoatState;
+
+/// Records the use of the token the session logged in with, if it did. The write happens in the background so it
+/// doesn't hold up the request.
+pub async fn record_token_use(
+    State(state): State<GoatState>,
+    session: Session,
+    req: Request,
+    next: Next,
+) -> Response {
+    if let Ok(Some(tokenkey)) = session.get::<String>(SESSION_TOKEN_KEY).await {
+        let pool = state.read().await.connpool.clone();
+        tokio::spawn(async move {
+            if let Err(err) = UserAuthToken::record_use(&pool, &tokenkey, Utc::now()).await {
+                log::warn!("Failed to record the use of token {tokenkey}: {err:?}");
+            }
+        });
+    }
+    next.run(req).await
+}
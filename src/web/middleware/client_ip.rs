@@ -0,0 +1,48 @@
+//! Works out who's really making the request when we're behind a reverse proxy, see [crate::proxy]
+
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::header::FORWARDED;
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::proxy::client_ip_from_headers;
+use crate::web::GoatState;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// The client's address, handlers can get it with `Extension<ClientIp>`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl Display for ClientIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Adds the [ClientIp] to the request, and the request span
+pub async fn client_ip(State(state): State<GoatState>, mut req: Request, next: Next) -> Response {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let Some(peer) = peer {
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        let client = client_ip_from_headers(
+            peer,
+            header(FORWARDED.as_str()),
+            header(X_FORWARDED_FOR),
+            &state.read().await.config.trusted_proxies,
+        );
+        tracing::Span::current().record("client_ip", client.to_string());
+        req.extensions_mut().insert(ClientIp(client));
+    }
+    next.run(req).await
+}
@@ -1,7 +1,42 @@
 use axum::{extract::State, http::HeaderValue, middleware::Next, response::Response};
 use axum_csp::*;
+use base64::{engine::general_purpose, Engine as _};
 
 use crate::web::GoatState;
+use crate::wizards::loc::map_tile_origin;
+
+tokio::task_local! {
+    static CSP_NONCE: String;
+}
+
+/// The CSP nonce for the request being handled, templates get at it with `csp_nonce!()`
+pub fn current_nonce() -> String {
+    CSP_NONCE
+        .try_with(|nonce| nonce.clone())
+        .unwrap_or_default()
+}
+
+/// A fresh random nonce, base64-encoded
+pub fn generate_nonce() -> String {
+    general_purpose::STANDARD.encode(rand::random::<[u8; 16]>())
+}
+
+/// Pages we render with templates get the strict policy
+pub fn wants_nonce_policy(path: &str) -> bool {
+    path == "/" || path.starts_with("/ui") || path.starts_with("/auth")
+}
+
+/// The policy for rendered pages, scripts need to come from us *and* carry the nonce. Images can also come from
+/// `image_origin`, which is where the map tiles are.
+pub fn nonce_policy(nonce: &str, image_origin: Option<&str>) -> String {
+    let img_src = match image_origin {
+        Some(origin) => format!("img-src 'self' {origin}; "),
+        None => String::new(),
+    };
+    format!(
+        "default-src 'self'; {img_src}script-src 'self' 'nonce-{nonce}'; object-src 'none'; base-uri 'self'; form-action 'self'; frame-ancestors 'none'"
+    )
+}
 
 pub async fn cspheaders(
     State(state): State<GoatState>,
@@ -9,6 +44,22 @@ pub async fn cspheaders(
     next: Next,
 ) -> Response {
     let uri: String = req.uri().path().to_string();
+
+    if wants_nonce_policy(&uri) {
+        let nonce = generate_nonce();
+        let image_origin = map_tile_origin(&state.read().await.config.map_tile_url);
+        let mut response = CSP_NONCE.scope(nonce.clone(), next.run(req)).await;
+        match HeaderValue::from_str(&nonce_policy(&nonce, image_origin.as_deref())) {
+            Ok(header) => {
+                response
+                    .headers_mut()
+                    .insert("Content-Security-Policy", header);
+            }
+            Err(err) => log::error!("Failed to build CSP header: {err:?}"),
+        }
+        return response;
+    }
+
     let url_matcher: Option<CspUrlMatcher> = state.read().await.csp_matchers.iter().find_map(|c| {
         if c.matcher.is_match(&uri) {
             Some(c.to_owned())
@@ -0,0 +1,138 @@
+//! Keeps private DoH endpoints private, see [crate::config::DohAuthConfig]
+//!
+//! Requests are let in by a client certificate, a static bearer token or a user's API token, and everything else gets
+//! a 401 before the query's even looked at. Requests let in by an API token get a [DohToken] so their usage can be
+//! counted.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::extract::{Request, State};
+use axum::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+
+use crate::config::DohAuthConfig;
+use crate::db::User;
+use crate::web::doh::usage::DohToken;
+use crate::web::middleware::client_ip::ClientIp;
+use crate::web::tls::ClientCertificate;
+use crate::web::utils::validate_api_token;
+use crate::web::{GoatState, GoatStateTrait};
+
+/// Checking an API token means an argon2 hash, which is far too slow to do for every query, so good ones are
+/// remembered for this long
+const API_TOKEN_CACHE_TIME: Duration = Duration::from_secs(60);
+/// Stops the remembered tokens growing forever
+const API_TOKEN_CACHE_SIZE: usize = 1024;
+
+lazy_static! {
+    /// Hashes of `key:secret` for the API tokens that have worked recently, and when they stop counting
+    static ref VERIFIED_API_TOKENS: Mutex<HashMap<[u8; 32], Instant>> = Mutex::new(HashMap::new());
+}
+
+/// The token from an `Authorization: Bearer <token>` header
+pub fn bearer_token(req: &Request) -> Option<&str> {
+    let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.trim().split_once(' ')?;
+    match scheme.eq_ignore_ascii_case("bearer") {
+        true => Some(token.trim()),
+        false => None,
+    }
+}
+
+fn sha256(value: &str) -> [u8; 32] {
+    Sha256::digest(value.as_bytes()).into()
+}
+
+/// Is it one of the static tokens? They're compared by hash so how long it takes doesn't give anything away
+pub fn static_token_valid(auth: &DohAuthConfig, token: &str) -> bool {
+    let token = sha256(token);
+    auth.bearer_tokens
+        .iter()
+        .fold(false, |found, valid| found | (sha256(valid) == token))
+}
+
+/// Forget the API tokens that have worked recently, for when one's deleted or rotated or its user's disabled, otherwise
+/// they'd keep working until they dropped out of the cache
+pub fn forget_verified_api_tokens() {
+    if let Ok(mut verified) = VERIFIED_API_TOKENS.lock() {
+        verified.clear();
+    }
+}
+
+/// Is it a `key:secret` API token for a user that's not disabled?
+pub(crate) async fn api_token_valid(pool: &SqlitePool, token: &str) -> bool {
+    let Some((tokenkey, secret)) = token.split_once(':') else {
+        return false;
+    };
+    let hash = sha256(token);
+    let now = Instant::now();
+    if let Ok(verified) = VERIFIED_API_TOKENS.lock() {
+        if verified.get(&hash).is_some_and(|expiry| *expiry > now) {
+            return true;
+        }
+    }
+
+    let mut pool = pool.clone();
+    let valid = match User::get_token(&mut pool, tokenkey).await {
+        Ok(row) => validate_api_token(&row, secret).is_ok(),
+        Err(_) => false,
+    };
+    if valid {
+        if let Ok(mut verified) = VERIFIED_API_TOKENS.lock() {
+            if verified.len() >= API_TOKEN_CACHE_SIZE {
+                verified.retain(|_, expiry| *expiry > now);
+            }
+            if verified.len() < API_TOKEN_CACHE_SIZE {
+                verified.insert(hash, now + API_TOKEN_CACHE_TIME);
+            }
+        }
+    }
+    valid
+}
+
+/// Turns away DoH requests that haven't authenticated, when [DohAuthConfig] says they need to
+pub async fn doh_auth(
+    State(state): State<GoatState>,
+    client_ip: Option<Extension<ClientIp>>,
+    client_cert: Option<Extension<ClientCertificate>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let auth = state.read().await.config.doh.auth.clone();
+    if !auth.enabled() {
+        return next.run(req).await;
+    }
+    // the TLS handshake's already checked it against the CAs
+    if auth.client_ca.is_some()
+        && client_cert.is_some_and(|Extension(ClientCertificate(cert))| cert.is_some())
+    {
+        return next.run(req).await;
+    }
+    if let Some(token) = bearer_token(&req) {
+        if static_token_valid(&auth, token) {
+            return next.run(req).await;
+        }
+        if auth.api_tokens && api_token_valid(&state.connpool().await, token).await {
+            // so the handlers can count what it's used for
+            if let Some((tokenkey, _)) = token.split_once(':') {
+                let token = DohToken(tokenkey.to_string());
+                req.extensions_mut().insert(token);
+            }
+            return next.run(req).await;
+        }
+    }
+    log::info!(
+        "action=doh_auth result=failure client_ip={}",
+        client_ip
+            .map(|Extension(ip)| ip.to_string())
+            .unwrap_or("<unknown>".to_string())
+    );
+    (StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, "Bearer")]).into_response()
+}
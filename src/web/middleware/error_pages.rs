@@ -0,0 +1,100 @@
+//! Makes error responses look the same everywhere - the JSON envelope ([ErrorResult]) for the API, and an error page
+//! for browsers, no matter whether the error came from a handler, an extractor rejection or the 404 fallback.
+//!
+//! Responses that are already in the right format are left alone, so handlers can still send their own.
+
+use axum::body::{to_bytes, Body};
+use axum::extract::Request;
+use axum::http::header::{ACCEPT, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+
+use crate::web::api::ErrorResult;
+use crate::web::generic::ErrorPage;
+
+/// Plain text bodies longer than this aren't messages meant for people
+const MAX_MESSAGE_BYTES: usize = 1024;
+
+/// What kind of error the client should get back
+#[derive(Debug, Eq, PartialEq)]
+pub enum ErrorFormat {
+    Json,
+    Html,
+    /// Leave it how the handler sent it
+    Unchanged,
+}
+
+/// API requests get JSON, browsers get pages, everything else (DoH, curl, health checks) is left alone
+pub fn error_format(path: &str, headers: &HeaderMap) -> ErrorFormat {
+    if path == "/api" || path.starts_with("/api/") {
+        return ErrorFormat::Json;
+    }
+    let wants_html = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("text/html"));
+    match wants_html {
+        true => ErrorFormat::Html,
+        false => ErrorFormat::Unchanged,
+    }
+}
+
+fn has_content_type(headers: &HeaderMap, wanted: &str) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(wanted))
+}
+
+/// The message from a plain text error body, if it's something we can show people. Server errors never are, they
+/// get logged instead.
+async fn body_message(status: StatusCode, headers: &HeaderMap, body: Body) -> Option<String> {
+    if status.is_server_error()
+        || headers.contains_key(CONTENT_ENCODING)
+        || !(headers.get(CONTENT_TYPE).is_none() || has_content_type(headers, "text/plain"))
+    {
+        return None;
+    }
+    let bytes = to_bytes(body, MAX_MESSAGE_BYTES).await.ok()?;
+    let message = String::from_utf8(bytes.to_vec()).ok()?;
+    let message = message.trim();
+    match message.is_empty() {
+        true => None,
+        false => Some(message.to_string()),
+    }
+}
+
+/// Rewrites error responses into the [ErrorFormat] for the request
+pub async fn error_pages(req: Request, next: Next) -> Response {
+    let format = error_format(req.uri().path(), req.headers());
+    let response = next.run(req).await;
+    let status = response.status();
+    if !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+    let already_formatted = match format {
+        ErrorFormat::Json => has_content_type(response.headers(), "application/json"),
+        ErrorFormat::Html => has_content_type(response.headers(), "text/html"),
+        ErrorFormat::Unchanged => true,
+    };
+    if already_formatted {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let message = body_message(status, &parts.headers, body).await;
+    let replacement = match format {
+        ErrorFormat::Html => ErrorPage::new(status, message).into_response(),
+        _ => Json(ErrorResult::new(status, message.unwrap_or_default())).into_response(),
+    };
+    let (new_parts, body) = replacement.into_parts();
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.remove(CONTENT_ENCODING);
+    if let Some(content_type) = new_parts.headers.get(CONTENT_TYPE) {
+        parts.headers.insert(CONTENT_TYPE, content_type.clone());
+    }
+    Response::from_parts(parts, body)
+}
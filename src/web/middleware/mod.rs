@@ -1,2 +1,9 @@
+pub mod api_token;
+pub mod client_ip;
 pub mod csp;
+pub mod doh_auth;
+pub mod error_pages;
 pub mod gunk;
+pub mod rate_limit;
+pub mod request_id;
+pub mod security_headers;
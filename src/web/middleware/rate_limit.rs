@@ -0,0 +1,159 @@
+//! Budgets for `/api` and `/auth`, so a runaway script can't hammer the database. Configured by `rate_limits` in
+//! the config.
+//!
+//! Every client IP gets a budget of requests per window, and API tokens get their own on top of that. Responses carry
+//! the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers from
+//! [draft-ietf-httpapi-ratelimit-headers](https://datatracker.ietf.org/doc/draft-ietf-httpapi-ratelimit-headers/)
+//! for whichever budget's closest to running out.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::extract::{Request, State};
+use axum::http::header::RETRY_AFTER;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use tower_sessions::Session;
+
+use crate::web::api::ErrorResult;
+use crate::web::middleware::client_ip::ClientIp;
+use crate::web::GoatState;
+
+pub const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+pub const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+pub const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+/// The session key that holds the API token a session logged in with
+pub const SESSION_TOKEN_KEY: &str = "tokenkey";
+
+/// Once there's this many clients being tracked the finished windows get cleaned out
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+/// Where a client's at with their budget
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the budget's topped back up
+    pub reset: u64,
+    /// If this request's over the budget
+    pub limited: bool,
+}
+
+impl RateLimitStatus {
+    fn add_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(RATELIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RATELIMIT_RESET, HeaderValue::from(self.reset));
+    }
+}
+
+/// Counts requests in fixed windows, keyed by whatever the caller likes
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl RateLimiter {
+    /// Count a request against `key`'s budget
+    pub fn check(&self, key: &str, limit: u32, window: Duration, now: Instant) -> RateLimitStatus {
+        // if something panicked holding the lock the counts are still fine
+        let mut windows = self
+            .windows
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if windows.len() >= PRUNE_THRESHOLD {
+            windows.retain(|_, entry| now.duration_since(entry.started) < window);
+        }
+        let entry = windows.entry(key.to_string()).or_insert(Window {
+            started: now,
+            count: 0,
+        });
+        if now.duration_since(entry.started) >= window {
+            entry.started = now;
+            entry.count = 0;
+        }
+        entry.count = entry.count.saturating_add(1);
+
+        let elapsed = now.duration_since(entry.started);
+        RateLimitStatus {
+            limit,
+            remaining: limit.saturating_sub(entry.count),
+            reset: window.saturating_sub(elapsed).as_secs().max(1),
+            limited: entry.count > limit,
+        }
+    }
+}
+
+/// Counts the request against the client IP's budget (and the API token's, if there is one), and sends back a 429
+/// once either runs out
+pub async fn rate_limit(
+    State(state): State<GoatState>,
+    session: Session,
+    client_ip: Option<Extension<ClientIp>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (config, limiter) = {
+        let reader = state.read().await;
+        (
+            reader.config.rate_limits.clone(),
+            reader.rate_limiter.clone(),
+        )
+    };
+    if !config.enabled {
+        return next.run(req).await;
+    }
+    let window = Duration::from_secs(config.window_secs);
+    let now = Instant::now();
+
+    let mut statuses = vec![];
+    if let Some(Extension(ClientIp(ip))) = client_ip {
+        statuses.push(limiter.check(&format!("ip:{ip}"), config.per_ip, window, now));
+    }
+    if let Ok(Some(tokenkey)) = session.get::<String>(SESSION_TOKEN_KEY).await {
+        statuses.push(limiter.check(&format!("token:{tokenkey}"), config.per_token, window, now));
+    }
+
+    let status = match statuses
+        .into_iter()
+        .min_by_key(|status| (!status.limited, status.remaining))
+    {
+        Some(val) => val,
+        None => return next.run(req).await,
+    };
+
+    if status.limited {
+        log::info!(
+            "action=rate_limited client_ip={:?} path={}",
+            client_ip.map(|Extension(ip)| ip.to_string()),
+            req.uri().path()
+        );
+        let mut response = (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResult::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many requests, please slow down",
+            )),
+        )
+            .into_response();
+        status.add_headers(response.headers_mut());
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(status.reset));
+        return response;
+    }
+
+    let mut response = next.run(req).await;
+    status.add_headers(response.headers_mut());
+    response
+}
@@ -0,0 +1,100 @@
+//! Gives every web request an ID, so problems people report can be matched up with the logs
+
+use axum::extract::Request;
+use axum::http::{HeaderValue, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use tower_sessions::Session;
+use tracing::Instrument;
+
+use super::client_ip::ClientIp;
+use crate::db::User;
+
+/// The header we read request IDs from (if a proxy in front of us set one) and send them back in
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The ID of the request currently being handled, if there is one
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Only pass through IDs that won't make a mess of the logs
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Use the incoming request ID if it looks sane, otherwise make one up
+pub fn request_id_for(req: &Request) -> String {
+    match req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(id) if valid_request_id(id) => id.to_string(),
+        _ => format!("{:032x}", rand::random::<u128>()),
+    }
+}
+
+/// Tags the request with an ID, runs everything under it in a span with the ID, and sends it back in the `x-request-id` header
+pub async fn request_id(req: Request, next: Next) -> Response {
+    let id = request_id_for(&req);
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+        path = %req.uri().path(),
+        client_ip = tracing::field::Empty,
+    );
+    let mut response = REQUEST_ID
+        .scope(id.clone(), next.run(req).instrument(span))
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// Logs changes made through the API - who did it, what they did it to and how it went
+pub async fn log_api_mutations(req: Request, next: Next) -> Response {
+    let method = req.method().clone();
+    if !req.uri().path().starts_with("/api/")
+        || !matches!(
+            method,
+            Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+        )
+    {
+        return next.run(req).await;
+    }
+    let path = req.uri().path().to_string();
+    let session = req.extensions().get::<Session>().cloned();
+    let client_ip = req
+        .extensions()
+        .get::<ClientIp>()
+        .map(|client| client.to_string())
+        .unwrap_or_default();
+
+    let response = next.run(req).await;
+
+    // check the session after the handler's run, so logins show who logged in
+    let actor = match session {
+        Some(session) => match session.get::<User>("user").await {
+            Ok(Some(user)) => format!("{}({})", user.username, user.id.unwrap_or(-1)),
+            _ => "anonymous".to_string(),
+        },
+        None => "anonymous".to_string(),
+    };
+    log::info!(
+        "API change request_id={} actor={actor} client_ip={client_ip} method={method} entity={path} status={}",
+        current_request_id().unwrap_or_default(),
+        response.status().as_u16()
+    );
+    response
+}
@@ -0,0 +1,58 @@
+//! Strict-Transport-Security and the other security headers, configured by `security_headers` in the config
+
+use axum::extract::{Request, State};
+use axum::http::header::{
+    REFERRER_POLICY, STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::config::SecurityHeaders;
+use crate::web::GoatState;
+
+const PERMISSIONS_POLICY: &str = "permissions-policy";
+
+/// The headers to add for a given config
+pub fn security_header_values(config: &SecurityHeaders) -> Vec<(HeaderName, String)> {
+    let mut headers = vec![
+        (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        (X_FRAME_OPTIONS, "DENY".to_string()),
+    ];
+    if let Some(hsts) = config.hsts() {
+        headers.push((STRICT_TRANSPORT_SECURITY, hsts));
+    }
+    if !config.referrer_policy.is_empty() {
+        headers.push((REFERRER_POLICY, config.referrer_policy.clone()));
+    }
+    if !config.permissions_policy.is_empty() {
+        headers.push((
+            HeaderName::from_static(PERMISSIONS_POLICY),
+            config.permissions_policy.clone(),
+        ));
+    }
+    headers
+}
+
+/// Adds the security headers to responses, without clobbering any the handler set itself
+pub async fn security_headers(
+    State(state): State<GoatState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let headers = security_header_values(&state.read().await.config.security_headers);
+    let mut response = next.run(req).await;
+    let response_headers = response.headers_mut();
+    for (name, value) in headers {
+        if response_headers.contains_key(&name) {
+            continue;
+        }
+        match HeaderValue::from_str(&value) {
+            Ok(value) => {
+                response_headers.insert(name, value);
+            }
+            Err(err) => log::error!("Invalid value for the {name} header {value:?}: {err:?}"),
+        }
+    }
+    response
+}
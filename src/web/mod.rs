@@ -9,20 +9,23 @@ use crate::config::ConfigFile;
 use crate::datastore;
 use crate::error::GoatNsError;
 
-#[cfg(not(test))]
 use crate::logging::init_otel_subscribers;
+use crate::utils::bind_tcp_listener;
 use crate::web::api::docs::ApiDoc;
-use crate::web::middleware::csp;
+use crate::web::middleware::rate_limit::{self, RateLimiter};
+use crate::web::middleware::{
+    api_token, client_ip, csp, error_pages, request_id, security_headers,
+};
 use async_trait::async_trait;
 use axum::extract::FromRef;
 use axum::http::StatusCode;
-use axum::middleware::from_fn_with_state;
+use axum::middleware::{from_fn, from_fn_with_state};
 use axum::routing::get;
 use axum::Router;
 use axum_csp::CspUrlMatcher;
-#[cfg(not(test))]
+use axum_server::tls_rustls::RustlsConfig;
 use axum_tracing_opentelemetry::middleware::OtelAxumLayer;
-use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use chrono::{DateTime, TimeDelta, Utc};
 use concread::cowcell::asynch::CowCellReadTxn;
 use log::error;
 use oauth2::{ClientId, ClientSecret};
@@ -30,6 +33,7 @@ use openidconnect::Nonce;
 use regex::RegexSet;
 use sqlx::{Pool, Sqlite, SqlitePool};
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
@@ -47,10 +51,13 @@ use self::auth::CustomProviderMetadata;
 pub mod macros;
 
 pub mod api;
+pub mod assets;
 pub mod auth;
 pub mod doh;
 pub mod generic;
 pub mod middleware;
+pub mod rdap;
+pub mod tls;
 pub mod ui;
 pub mod utils;
 
@@ -63,7 +70,11 @@ pub const STATUS_OK: &str = "Ok";
 #[async_trait]
 pub trait GoatStateTrait {
     async fn connpool(&self) -> Pool<Sqlite>;
-    async fn oidc_update<'life0>(&'life0 mut self, response: CustomProviderMetadata);
+    async fn oidc_update<'life0>(
+        &'life0 mut self,
+        response: CustomProviderMetadata,
+        updated: DateTime<Utc>,
+    );
     async fn pop_verifier<'life0>(&'life0 mut self, csrftoken: String) -> Option<(String, Nonce)>;
     async fn oauth2_client_id(&self) -> ClientId;
     async fn oauth2_secret(&self) -> Option<ClientSecret>;
@@ -76,12 +87,16 @@ impl GoatStateTrait for GoatState {
     async fn connpool(&self) -> Pool<Sqlite> {
         self.read().await.connpool.clone()
     }
-    async fn oidc_update<'life0>(&'life0 mut self, response: CustomProviderMetadata) {
+    /// Store the OIDC discovery data, `updated` is when it was pulled from the IdP
+    async fn oidc_update<'life0>(
+        &'life0 mut self,
+        response: CustomProviderMetadata,
+        updated: DateTime<Utc>,
+    ) {
         log::debug!("Storing OIDC config!");
         let mut writer = self.write().await;
-        writer.oidc_config = Some(response.clone());
-        writer.oidc_config_updated =
-            chrono::TimeZone::from_utc_datetime(&Utc, &NaiveDateTime::default());
+        writer.oidc_config = Some(response);
+        writer.oidc_config_updated = updated;
         drop(writer);
     }
 
@@ -112,13 +127,14 @@ type GoatState = Arc<RwLock<GoatChildState>>;
 #[derive(Clone, FromRef)]
 /// Internal State handler for the datastore object within the API
 pub struct GoatChildState {
-    pub tx: Sender<datastore::Command>,
+    pub tx: Sender<datastore::Queued>,
     pub connpool: SqlitePool,
     pub config: ConfigFile,
     pub oidc_config_updated: DateTime<Utc>,
     pub oidc_config: Option<auth::CustomProviderMetadata>,
     pub oidc_verifier: std::collections::HashMap<String, (String, Nonce)>,
     pub csp_matchers: Vec<CspUrlMatcher>,
+    pub rate_limiter: Arc<RateLimiter>,
 }
 
 fn check_static_dir_exists(static_dir: &PathBuf, config: &ConfigFile) -> bool {
@@ -157,8 +173,26 @@ fn check_static_dir_exists(static_dir: &PathBuf, config: &ConfigFile) -> bool {
     false
 }
 
+/// Just DoH, for when it's on its own port - none of the UI, API or their sessions
+fn doh_only_router(config: &ConfigFile, state: GoatState) -> Router {
+    let mut router = Router::new();
+    for path in &config.doh.paths {
+        router = router.nest(path, doh::new(state.clone()));
+    }
+    let router = router
+        .with_state(state.clone())
+        .layer(from_fn_with_state(state, client_ip::client_ip));
+    let router = match config.otel.enabled {
+        true => router.layer(OtelAxumLayer::default()),
+        false => router,
+    };
+    router
+        .fallback(handler_404)
+        .layer(from_fn(request_id::request_id))
+}
+
 pub async fn build(
-    tx: Sender<datastore::Command>,
+    tx: Sender<datastore::Queued>,
     config: CowCellReadTxn<ConfigFile>,
     connpool: SqlitePool,
 ) -> Result<JoinHandle<Result<(), std::io::Error>>, GoatNsError> {
@@ -166,8 +200,7 @@ pub async fn build(
         .to_string()
         .into();
 
-    #[cfg(not(test))]
-    init_otel_subscribers().map_err(|err| {
+    init_otel_subscribers(&config.otel).map_err(|err| {
         GoatNsError::StartupError(format!("Failed to initialize OpenTelemetry tracing: {err}"))
     })?;
 
@@ -183,6 +216,13 @@ pub async fn build(
     #[allow(clippy::expect_used)]
     let oidc_config_updated = Utc::now() - TimeDelta::try_hours(1).expect("how did this fail?");
     // let config_clone: ConfigFile = ConfigFile::from(&config);
+    if config.doh.enabled && config.doh.auth.usage_enabled() {
+        tokio::spawn(doh::usage::flush_loop(
+            connpool.clone(),
+            config.doh.auth.usage_days,
+        ));
+    }
+
     let state = Arc::new(RwLock::new(GoatChildState {
         tx,
         connpool,
@@ -191,46 +231,148 @@ pub async fn build(
         oidc_config: None,
         oidc_verifier: HashMap::new(),
         csp_matchers,
+        rate_limiter: Arc::new(RateLimiter::default()),
     }));
 
+    if !config.oauth2_config_url.is_empty() {
+        tokio::spawn(auth::oidc_refresh_loop(state.clone()));
+    }
+
     let service_layer = ServiceBuilder::new()
+        .layer(from_fn_with_state(state.clone(), client_ip::client_ip))
         .layer(session_layer)
-        .layer(from_fn_with_state(state.clone(), csp::cspheaders));
+        .layer(from_fn_with_state(state.clone(), csp::cspheaders))
+        .layer(from_fn_with_state(
+            state.clone(),
+            security_headers::security_headers,
+        ))
+        .layer(from_fn(request_id::log_api_mutations));
 
-    let router = Router::new()
+    let mut router = Router::new()
         .route(Urls::Home.as_ref(), get(generic::index))
+        .route("/readyz", get(generic::readyz))
         .nest("/ui", ui::new())
-        .nest("/api", api::new())
+        .nest(
+            "/api",
+            api::new()
+                .layer(from_fn_with_state(
+                    state.clone(),
+                    api_token::record_token_use,
+                ))
+                .layer(from_fn_with_state(state.clone(), rate_limit::rate_limit)),
+        )
         .merge(
             utoipa_swagger_ui::SwaggerUi::new("/api/docs")
                 .url("/api/openapi.json", ApiDoc::openapi()),
         )
-        .nest("/auth", auth::new())
-        .nest("/dns-query", doh::new())
-        .with_state(state)
-        .layer(service_layer);
+        .nest(
+            "/auth",
+            auth::new().layer(from_fn_with_state(state.clone(), rate_limit::rate_limit)),
+        )
+        .nest(
+            "/rdap",
+            rdap::new().layer(from_fn_with_state(state.clone(), rate_limit::rate_limit)),
+        );
+    // DoH goes in with everything else unless it's got its own port
+    if config.doh.enabled && config.doh.port.is_none() {
+        for path in &config.doh.paths {
+            router = router.nest(path, doh::new(state.clone()));
+        }
+    }
+    let doh_router = match config.doh.enabled && config.doh.port.is_some() {
+        true => Some(doh_only_router(&config, state.clone())),
+        false => None,
+    };
+    let router = router.with_state(state).layer(service_layer);
 
     // here we add the tracing layer
-    #[cfg(not(test))]
-    let router = router.layer(OtelAxumLayer::default());
+    let router = match config.otel.enabled {
+        true => router.layer(OtelAxumLayer::default()),
+        false => router,
+    };
 
-    let router = router.route("/status", get(generic::status));
+    let router = router
+        .route("/status", get(generic::status))
+        .route("/healthz", get(generic::healthz))
+        .route("/metrics", get(generic::metrics));
 
     let router = match check_static_dir_exists(&static_dir, &config) {
-        true => router.nest_service("/static", ServeDir::new(&static_dir)),
+        true => {
+            assets::init_asset_hashes(&static_dir);
+            router.nest_service(
+                "/static",
+                ServiceBuilder::new()
+                    .layer(from_fn(assets::cache_headers))
+                    .service(
+                        ServeDir::new(&static_dir)
+                            .precompressed_br()
+                            .precompressed_zstd()
+                            .precompressed_gzip(),
+                    ),
+            )
+        }
         false => router,
     };
-    let router = router.layer(CompressionLayer::new()).fallback(handler_404);
+    let router = router
+        .fallback(handler_404)
+        .layer(from_fn(error_pages::error_pages))
+        .layer(CompressionLayer::new());
+    // outermost, so everything happens with a request ID
+    let router = router.layer(from_fn(request_id::request_id));
 
     let tls_config = config
         .get_tls_config()
         .await
         .map_err(GoatNsError::StartupError)?;
+    tokio::spawn(tls::reload_loop(
+        tls_config.clone(),
+        tls::CertWatcher::new(config.api_tls_cert.clone(), config.api_tls_key.clone()),
+        config.api_tls_reload_seconds,
+    ));
 
-    let res: JoinHandle<Result<(), std::io::Error>> = tokio::spawn(
-        axum_server::bind_rustls(config.api_listener_address()?, tls_config)
-            .serve(router.into_make_service()),
-    );
+    let listener = bind_tcp_listener(config.api_listener_address()?, config.ipv6_only)?;
+    let doh_server = match (doh_router, config.doh_listener_address()?) {
+        (Some(doh_router), Some(doh_addr)) => {
+            // it gets its own TLS config if it's asking for client certificates
+            let doh_tls_config = match &config.doh.auth.client_ca {
+                Some(client_ca) => {
+                    let doh_tls_config =
+                        RustlsConfig::from_config(Arc::new(tls::client_auth_config(
+                            &config.api_tls_cert,
+                            &config.api_tls_key,
+                            client_ca,
+                        )?));
+                    tokio::spawn(tls::reload_loop(
+                        doh_tls_config.clone(),
+                        tls::CertWatcher::new(
+                            config.api_tls_cert.clone(),
+                            config.api_tls_key.clone(),
+                        )
+                        .with_client_ca(client_ca.clone()),
+                        config.api_tls_reload_seconds,
+                    ));
+                    doh_tls_config
+                }
+                None => tls_config.clone(),
+            };
+            let doh_listener = bind_tcp_listener(doh_addr, config.ipv6_only)?;
+            log::info!("Started DoH server on https://{doh_addr}");
+            Some(
+                axum_server::from_tcp_rustls(doh_listener, doh_tls_config)
+                    .map(tls::ClientCertAcceptor::new)
+                    .serve(doh_router.into_make_service_with_connect_info::<SocketAddr>()),
+            )
+        }
+        _ => None,
+    };
+    let api_server = axum_server::from_tcp_rustls(listener, tls_config)
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>());
+    let res: JoinHandle<Result<(), std::io::Error>> = tokio::spawn(async move {
+        match doh_server {
+            Some(doh_server) => tokio::try_join!(api_server, doh_server).map(|_| ()),
+            None => api_server.await,
+        }
+    });
     let startup_message = format!(
         "Started Web server on https://{} / https://{}:{}",
         config.api_listener_address()?,
@@ -0,0 +1,244 @@
+//! A read-only, unauthenticated look at the zones that've been marked public, shaped like
+//! [RDAP](https://www.rfc-editor.org/rfc/rfc9083) domain objects so inventory tools can read them.
+//!
+//! It's "RDAP-ish" - there's no registrars, entities or events, and each domain has an extra `soa` member with what's
+//! in the zone's SOA. Zones that aren't public get the same 404 as zones that don't exist.
+
+use axum::extract::{Path, Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+
+use super::GoatState;
+use crate::error::GoatNsError;
+use crate::name_policy::{is_idn, normalise, to_unicode};
+use crate::zones::FileZone;
+
+/// The most domains a search gives back
+pub const MAX_SEARCH_RESULTS: i64 = 1000;
+const CONTENT_TYPE_RDAP: &str = "application/rdap+json";
+const RDAP_CONFORMANCE: [&str; 1] = ["rdap_level_0"];
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RdapDomain {
+    pub object_class_name: &'static str,
+    /// The name in ASCII, internationalised names are punycode
+    pub ldh_name: String,
+    /// Only there for internationalised names
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unicode_name: Option<String>,
+    /// `active`, or `inactive` if the zone's disabled
+    pub status: Vec<&'static str>,
+    pub nameservers: Vec<RdapNameserver>,
+    #[serde(rename = "secureDNS")]
+    pub secure_dns: RdapSecureDns,
+    /// Not part of RDAP, what's in the zone's SOA record
+    pub soa: RdapSoa,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RdapNameserver {
+    pub object_class_name: &'static str,
+    pub ldh_name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RdapSecureDns {
+    /// GoatNS doesn't sign zones, so this is always false
+    pub delegation_signed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RdapSoa {
+    pub mname: String,
+    pub rname: String,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
+impl RdapDomain {
+    /// Describe the zone, `nameservers` are its apex NS records
+    pub fn new(zone: &FileZone, nameservers: Vec<String>) -> Self {
+        let nameservers: Vec<String> = nameservers
+            .iter()
+            .map(|name| name.trim_end_matches('.').to_ascii_lowercase())
+            .collect();
+        let ldh_name = zone.name.trim_end_matches('.').to_string();
+        Self {
+            object_class_name: "domain",
+            unicode_name: is_idn(&ldh_name).then(|| to_unicode(&ldh_name)),
+            status: vec![match zone.enabled {
+                true => "active",
+                false => "inactive",
+            }],
+            secure_dns: RdapSecureDns {
+                delegation_signed: false,
+            },
+            soa: RdapSoa {
+                mname: nameservers
+                    .first()
+                    .cloned()
+                    .unwrap_or_else(|| ldh_name.clone()),
+                rname: zone.rname.clone(),
+                serial: zone.serial,
+                refresh: zone.refresh,
+                retry: zone.retry,
+                expire: zone.expire,
+                minimum: zone.minimum,
+            },
+            nameservers: nameservers
+                .into_iter()
+                .map(|ldh_name| RdapNameserver {
+                    object_class_name: "nameserver",
+                    ldh_name,
+                })
+                .collect(),
+            ldh_name,
+        }
+    }
+}
+
+/// Public zones matching the pattern, with their name servers
+pub async fn public_domains(
+    txn: &mut SqliteConnection,
+    pattern: &str,
+    limit: i64,
+) -> Result<Vec<RdapDomain>, GoatNsError> {
+    let mut domains = vec![];
+    for zone in FileZone::get_public_with_txn(txn, pattern, limit).await? {
+        let nameservers =
+            FileZone::apex_nameservers_with_txn(zone.id.unwrap_or_default(), txn).await?;
+        domains.push(RdapDomain::new(&zone, nameservers));
+    }
+    Ok(domains)
+}
+
+/// A domain on its own, from a lookup
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RdapLookup {
+    rdap_conformance: [&'static str; 1],
+    #[serde(flatten)]
+    domain: RdapDomain,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RdapSearch {
+    rdap_conformance: [&'static str; 1],
+    domain_search_results: Vec<RdapDomain>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RdapError {
+    rdap_conformance: [&'static str; 1],
+    error_code: u16,
+    title: &'static str,
+}
+
+fn rdap_response<T: Serialize>(status: StatusCode, body: T) -> Response {
+    let mut response = (status, Json(body)).into_response();
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE_RDAP));
+    response
+}
+
+fn rdap_error(status: StatusCode, title: &'static str) -> Response {
+    rdap_response(
+        status,
+        RdapError {
+            rdap_conformance: RDAP_CONFORMANCE,
+            error_code: status.as_u16(),
+            title,
+        },
+    )
+}
+
+/// `GET /rdap/domain/:name`
+pub(crate) async fn domain(State(state): State<GoatState>, Path(name): Path<String>) -> Response {
+    let name = normalise(&name);
+    // it's a lookup, not a search
+    if name.is_empty() || name.contains('*') {
+        return rdap_error(StatusCode::NOT_FOUND, "Not found");
+    }
+    let mut conn = match state.connpool().await.acquire().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            log::error!("Failed to get a database connection: {err:?}");
+            return rdap_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error");
+        }
+    };
+    match public_domains(&mut conn, &name, 1).await {
+        Ok(mut domains) => match domains.pop() {
+            Some(domain) => rdap_response(
+                StatusCode::OK,
+                RdapLookup {
+                    rdap_conformance: RDAP_CONFORMANCE,
+                    domain,
+                },
+            ),
+            None => rdap_error(StatusCode::NOT_FOUND, "Not found"),
+        },
+        Err(err) => {
+            log::error!("Failed to look up public zone {name:?}: {err:?}");
+            rdap_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct DomainSearch {
+    /// `*` matches anything, leaving it out lists every public zone
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// `GET /rdap/domains?name=*.example.com`
+pub(crate) async fn domains(
+    State(state): State<GoatState>,
+    Query(query): Query<DomainSearch>,
+) -> Response {
+    let pattern = query
+        .name
+        .as_deref()
+        .map(normalise)
+        .unwrap_or_else(|| "*".to_string());
+    let mut conn = match state.connpool().await.acquire().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            log::error!("Failed to get a database connection: {err:?}");
+            return rdap_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error");
+        }
+    };
+    match public_domains(&mut conn, &pattern, MAX_SEARCH_RESULTS).await {
+        Ok(domains) => rdap_response(
+            StatusCode::OK,
+            RdapSearch {
+                rdap_conformance: RDAP_CONFORMANCE,
+                domain_search_results: domains,
+            },
+        ),
+        Err(err) => {
+            log::error!("Failed to search public zones for {pattern:?}: {err:?}");
+            rdap_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+        }
+    }
+}
+
+pub fn new() -> Router<GoatState> {
+    Router::new()
+        .route("/domain/:name", get(domain))
+        .route("/domains", get(domains))
+}
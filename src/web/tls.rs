@@ -0,0 +1,217 @@
+//! Reloading the web server's certificate without a restart
+//!
+//! [reload_loop] watches the cert and key files and swaps them into the running listeners when they change (or when
+//! the process gets a SIGHUP), so Let's Encrypt renewals get picked up by themselves. The DoH listener can also ask
+//! for client certificates, which handlers see as a [ClientCertificate].
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use axum_server::accept::Accept;
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use futures::future::BoxFuture;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower_http::add_extension::AddExtension;
+
+use crate::error::GoatNsError;
+
+/// The certificate the client connected with, if it sent one. It's already been checked against the CAs by then.
+#[derive(Clone, Debug, Default)]
+pub struct ClientCertificate(pub Option<Vec<u8>>);
+
+/// A TLS config that asks for (but doesn't need) client certificates signed by the CAs in `client_ca`
+pub fn client_auth_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: &Path,
+) -> Result<ServerConfig, GoatNsError> {
+    let pem_error =
+        |path: &Path, err| GoatNsError::FileError(format!("Failed to read {path:?}: {err:?}"));
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| pem_error(cert, err))?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|err| pem_error(key, err))?;
+
+    let mut roots = RootCertStore::empty();
+    for ca in CertificateDer::pem_file_iter(client_ca).map_err(|err| pem_error(client_ca, err))? {
+        roots
+            .add(ca.map_err(|err| pem_error(client_ca, err))?)
+            .map_err(|err| {
+                GoatNsError::FileError(format!("Bad CA certificate in {client_ca:?}: {err:?}"))
+            })?;
+    }
+    let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+        // so clients can still use a token instead
+        .allow_unauthenticated()
+        .build()
+        .map_err(|err| {
+            GoatNsError::StartupError(format!("Failed to set up client certificates: {err:?}"))
+        })?;
+
+    let mut config = ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs, key)
+        .map_err(|err| GoatNsError::StartupError(format!("Failed to load TLS config: {err:?}")))?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// The rustls acceptor, but it adds the client's certificate to each request as a [ClientCertificate]
+#[derive(Clone)]
+pub struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl ClientCertAcceptor {
+    pub fn new(inner: RustlsAcceptor) -> Self {
+        Self { inner }
+    }
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = tokio_rustls::server::TlsStream<I>;
+    type Service = AddExtension<S, ClientCertificate>;
+    type Future = BoxFuture<'static, std::io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.inner.clone();
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            let cert = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|cert| cert.to_vec());
+            Ok((stream, AddExtension::new(service, ClientCertificate(cert))))
+        })
+    }
+}
+
+/// When the file was last changed, [None] if we can't tell
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Tracks the cert and key modification times so we only reload when something's changed
+#[derive(Debug, Clone)]
+pub struct CertWatcher {
+    cert: PathBuf,
+    key: PathBuf,
+    /// Set when the listener asks for client certificates, see [client_auth_config]
+    client_ca: Option<PathBuf>,
+    last_seen: (Option<SystemTime>, Option<SystemTime>),
+}
+
+impl CertWatcher {
+    pub fn new(cert: PathBuf, key: PathBuf) -> Self {
+        let last_seen = (modified(&cert), modified(&key));
+        Self {
+            cert,
+            key,
+            client_ca: None,
+            last_seen,
+        }
+    }
+
+    /// Keep asking for client certificates signed by these CAs when reloading
+    pub fn with_client_ca(self, client_ca: PathBuf) -> Self {
+        Self {
+            client_ca: Some(client_ca),
+            ..self
+        }
+    }
+
+    /// Returns true if either file's changed since the last time this returned true. Renewals tend to write the cert
+    /// and key one after the other, so a half-written pair gets retried next time around.
+    pub fn changed(&mut self) -> bool {
+        let current = (modified(&self.cert), modified(&self.key));
+        if current == self.last_seen || current.0.is_none() || current.1.is_none() {
+            return false;
+        }
+        self.last_seen = current;
+        true
+    }
+
+    /// Load the files into the running config, the old cert stays in use if it fails
+    pub async fn reload(&self, tls_config: &RustlsConfig) -> bool {
+        let res = match &self.client_ca {
+            Some(client_ca) => client_auth_config(&self.cert, &self.key, client_ca)
+                .map(|config| tls_config.reload_from_config(Arc::new(config)))
+                .map_err(|err| format!("{err:?}")),
+            None => tls_config
+                .reload_from_pem_file(self.cert.clone(), self.key.clone())
+                .await
+                .map_err(|err| format!("{err:?}")),
+        };
+        match res {
+            Ok(_) => {
+                log::info!("Reloaded TLS certificate from {:?}", self.cert);
+                true
+            }
+            Err(err) => {
+                log::error!(
+                    "Failed to reload TLS certificate from {:?} / {:?}, keeping the old one: {err}",
+                    self.cert,
+                    self.key
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Check for new cert files every `interval` seconds, and on SIGHUP. An `interval` of 0 only reloads on SIGHUP.
+pub async fn reload_loop(tls_config: RustlsConfig, mut watcher: CertWatcher, interval: u64) {
+    #[cfg(unix)]
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(val) => Some(val),
+        Err(err) => {
+            log::warn!(
+                "Couldn't listen for SIGHUP, TLS reloads will only happen on file changes: {err:?}"
+            );
+            None
+        }
+    };
+
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval.max(1)));
+    // the first tick's immediate, and we've only just loaded the cert
+    ticker.tick().await;
+
+    loop {
+        #[cfg(unix)]
+        let hup = async {
+            match hangup.as_mut() {
+                Some(signal) => signal.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let hup = std::future::pending::<Option<()>>();
+
+        tokio::select! {
+            _ = ticker.tick(), if interval > 0 => {
+                if watcher.changed() {
+                    watcher.reload(&tls_config).await;
+                }
+            }
+            _ = hup => {
+                log::info!("Got SIGHUP, reloading TLS certificate");
+                // update the modification times so the poll doesn't do it again
+                watcher.changed();
+                watcher.reload(&tls_config).await;
+            }
+        }
+    }
+}
@@ -1,15 +1,20 @@
-use crate::db::{DBEntity, User, ZoneOwnership};
+use crate::config::QuotaConfig;
+use crate::db::{DBEntity, ReservedName, User, UserQuota, ZoneOwnership};
+use crate::name_policy::BUILTIN_RESERVED_NAMES;
+use crate::web::assets::filters;
+use crate::web::ui::user_settings::{store_api_csrf_token, validate_csrf_expiry};
 use crate::web::utils::Urls;
 use crate::web::GoatState;
 use crate::zones::FileZone;
 use askama::Template;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::Uri;
 use axum::response::Redirect;
 use axum::routing::get;
 use axum::{Form, Router};
 use serde::Deserialize;
 use sqlx::Row;
+use std::collections::HashMap;
 use tower_sessions::Session;
 use tracing::debug;
 
@@ -92,7 +97,7 @@ pub(crate) async fn report_unowned_records(
     Ok(AdminReportUnownedRecords {
         user_is_admin: user.admin,
         records,
-        zones: FileZone::get_unowned(&mut pool).await.map_err(|err| {
+        zones: FileZone::get_orphans(&mut pool).await.map_err(|err| {
             log::error!("Failed to get unowned zones: {err:?}");
             Redirect::to(Urls::Admin.as_ref())
         })?,
@@ -168,11 +173,411 @@ pub(crate) async fn assign_zone_ownership(
     })
 }
 
+#[derive(Template)]
+#[template(path = "admin_reserved_names.html")]
+pub(crate) struct ReservedNamesTemplate {
+    user_is_admin: bool,
+    builtin: Vec<String>,
+    hostname: String,
+    reserved: Vec<ReservedName>,
+    csrftoken: String,
+    message: Option<String>,
+    error: Option<String>,
+}
+
+/// Whatever a POST sent back to say how it went
+#[derive(Deserialize)]
+pub(crate) struct MessageQuery {
+    message: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ReservedNameForm {
+    csrftoken: String,
+    /// Set when adding a name
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    comment: String,
+    /// Set when removing a name
+    #[serde(default)]
+    delete: Option<String>,
+}
+
+/// The zone names nobody's allowed to create
+pub(crate) async fn reserved_names_get(
+    mut session: Session,
+    State(state): State<GoatState>,
+    Query(query): Query<MessageQuery>,
+) -> Result<ReservedNamesTemplate, Redirect> {
+    let user = check_logged_in(&mut session, Uri::from_static(Urls::Home.as_ref())).await?;
+    if !user.admin {
+        return Err(Redirect::to(Urls::Dashboard.as_ref()));
+    }
+
+    let (pool, hostname) = {
+        let reader = state.read().await;
+        (reader.connpool.clone(), reader.config.hostname.clone())
+    };
+    let mut conn = pool.acquire().await.map_err(|err| {
+        log::error!("Failed to get DB connection: {err:?}");
+        Redirect::to(Urls::Admin.as_ref())
+    })?;
+    let reserved = ReservedName::get_all(&mut conn).await.map_err(|err| {
+        log::error!("Failed to get reserved names: {err:?}");
+        Redirect::to(Urls::Admin.as_ref())
+    })?;
+    let csrftoken = store_api_csrf_token(&mut session, None)
+        .await
+        .map_err(|err| {
+            log::error!("Failed to store csrf token in session: {err:?}");
+            Redirect::to(Urls::Admin.as_ref())
+        })?;
+
+    Ok(ReservedNamesTemplate {
+        user_is_admin: user.admin,
+        builtin: BUILTIN_RESERVED_NAMES
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+        hostname,
+        reserved,
+        csrftoken,
+        message: query.message,
+        error: query.error,
+    })
+}
+
+/// Add or remove a reserved name
+pub(crate) async fn reserved_names_post(
+    mut session: Session,
+    State(state): State<GoatState>,
+    Form(form): Form<ReservedNameForm>,
+) -> Result<Redirect, Redirect> {
+    let user = check_logged_in(&mut session, Uri::from_static(Urls::Home.as_ref())).await?;
+    if !user.admin {
+        return Err(Redirect::to(Urls::Dashboard.as_ref()));
+    }
+    let redirect =
+        |key: &str, msg: &str| Redirect::to(&format!("/ui/admin/reserved_names?{key}={msg}"));
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+
+    let pool = state.read().await.connpool.clone();
+    match (form.name, form.delete) {
+        (_, Some(name)) => match ReservedName::delete(&pool, &name).await {
+            Ok(()) => {
+                log::info!("action=reserved_name_delete user={:?} name={name}", user.id);
+                Ok(redirect("message", "Removed the reserved name"))
+            }
+            Err(err) => {
+                log::error!("Failed to delete reserved name {name:?}: {err:?}");
+                Err(redirect("error", "Failed to remove the reserved name"))
+            }
+        },
+        (Some(name), None) => {
+            let reserved = ReservedName {
+                name,
+                comment: form.comment.trim().to_string(),
+            };
+            match reserved.save(&pool).await {
+                Ok(()) => {
+                    log::info!(
+                        "action=reserved_name_add user={:?} name={}",
+                        user.id,
+                        reserved.name
+                    );
+                    Ok(redirect("message", "Added the reserved name"))
+                }
+                Err(crate::error::GoatNsError::InvalidValue(msg)) => Err(redirect("error", &msg)),
+                Err(err) => {
+                    log::error!("Failed to save reserved name {:?}: {err:?}", reserved.name);
+                    Err(redirect("error", "Failed to add the reserved name"))
+                }
+            }
+        }
+        (None, None) => Err(redirect("error", "Unknown action!")),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin_orphans.html")]
+pub(crate) struct OrphansTemplate {
+    user_is_admin: bool,
+    zones: Vec<FileZone>,
+    csrftoken: String,
+    message: Option<String>,
+    error: Option<String>,
+}
+
+/// The zones nobody owns
+pub(crate) async fn orphans_get(
+    mut session: Session,
+    State(state): State<GoatState>,
+    Query(query): Query<MessageQuery>,
+) -> Result<OrphansTemplate, Redirect> {
+    let user = check_logged_in(&mut session, Uri::from_static(Urls::Home.as_ref())).await?;
+    if !user.admin {
+        return Err(Redirect::to(Urls::Dashboard.as_ref()));
+    }
+
+    let mut conn = state.read().await.connpool.acquire().await.map_err(|err| {
+        log::error!("Failed to get DB connection: {err:?}");
+        Redirect::to(Urls::Admin.as_ref())
+    })?;
+    let zones = FileZone::get_orphans(&mut conn).await.map_err(|err| {
+        log::error!("Failed to get orphaned zones: {err:?}");
+        Redirect::to(Urls::Admin.as_ref())
+    })?;
+    let csrftoken = store_api_csrf_token(&mut session, None)
+        .await
+        .map_err(|err| {
+            log::error!("Failed to store csrf token in session: {err:?}");
+            Redirect::to(Urls::Admin.as_ref())
+        })?;
+
+    Ok(OrphansTemplate {
+        user_is_admin: user.admin,
+        zones,
+        csrftoken,
+        message: query.message,
+        error: query.error,
+    })
+}
+
+/// Give the ticked orphaned zones to a user. The checkboxes are named `zone_<id>`, so the form's taken as a map.
+pub(crate) async fn orphans_post(
+    mut session: Session,
+    State(state): State<GoatState>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Result<Redirect, Redirect> {
+    let user = check_logged_in(&mut session, Uri::from_static(Urls::Home.as_ref())).await?;
+    if !user.admin {
+        return Err(Redirect::to(Urls::Dashboard.as_ref()));
+    }
+    let redirect = |key: &str, msg: &str| Redirect::to(&format!("/ui/admin/orphans?{key}={msg}"));
+
+    let csrftoken = form
+        .get("csrftoken")
+        .map(String::as_str)
+        .unwrap_or_default();
+    if !validate_csrf_expiry(csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+    let username = form
+        .get("username")
+        .map(|username| username.trim())
+        .unwrap_or_default();
+    let zoneids: Vec<i64> = form
+        .keys()
+        .filter_map(|key| key.strip_prefix("zone_"))
+        .filter_map(|id| id.parse().ok())
+        .collect();
+    if zoneids.is_empty() {
+        return Err(redirect("error", "Pick some zones to assign"));
+    }
+
+    let mut txn = state.read().await.connpool.begin().await.map_err(|err| {
+        log::error!("Failed to start transaction: {err:?}");
+        redirect("error", "Failed to assign the zones")
+    })?;
+    let owner = match User::get_by_name(&mut txn, username).await {
+        Ok(Some(owner)) => owner,
+        Ok(None) => return Err(redirect("error", "There's no user with that name")),
+        Err(err) => {
+            log::error!("Failed to get user by name: {err:?}");
+            return Err(redirect("error", "Failed to assign the zones"));
+        }
+    };
+    let ownerid = owner
+        .id
+        .ok_or_else(|| redirect("error", "Failed to assign the zones"))?;
+    let assigned = FileZone::assign_orphans(&mut txn, &zoneids, ownerid)
+        .await
+        .map_err(|err| {
+            log::error!("Failed to assign orphaned zones: {err:?}");
+            redirect("error", "Failed to assign the zones")
+        })?;
+    txn.commit().await.map_err(|err| {
+        log::error!("Failed to commit orphaned zone assignment: {err:?}");
+        redirect("error", "Failed to assign the zones")
+    })?;
+    log::info!(
+        "action=assign_orphans user={:?} owner={username} zones={assigned:?}",
+        user.id
+    );
+    Ok(redirect(
+        "message",
+        &format!("Gave {} zones to {username}", assigned.len()),
+    ))
+}
+
+#[derive(Template)]
+#[template(path = "admin_quotas.html")]
+pub(crate) struct QuotasTemplate {
+    user_is_admin: bool,
+    /// The server's, from the config
+    quotas: QuotaConfig,
+    /// Users who've been given their own, by username
+    overrides: Vec<(String, UserQuota)>,
+    csrftoken: String,
+    message: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct QuotaForm {
+    csrftoken: String,
+    /// Set when giving someone their own quotas
+    #[serde(default)]
+    username: Option<String>,
+    /// Blank means they get the server's
+    #[serde(default)]
+    max_zones: String,
+    #[serde(default)]
+    max_records_per_zone: String,
+    /// Set when putting someone back on the server's quotas, it's their user id
+    #[serde(default)]
+    delete: Option<i64>,
+}
+
+/// Blank's [None], so the server's quota applies
+fn parse_quota(value: &str) -> Result<Option<u32>, &'static str> {
+    match value.trim() {
+        "" => Ok(None),
+        value => value
+            .parse()
+            .map(Some)
+            .map_err(|_| "Quotas need to be whole numbers, or blank for the server's"),
+    }
+}
+
+/// The per-user quotas, and the server's ones they override
+pub(crate) async fn quotas_get(
+    mut session: Session,
+    State(state): State<GoatState>,
+    Query(query): Query<MessageQuery>,
+) -> Result<QuotasTemplate, Redirect> {
+    let user = check_logged_in(&mut session, Uri::from_static(Urls::Home.as_ref())).await?;
+    if !user.admin {
+        return Err(Redirect::to(Urls::Dashboard.as_ref()));
+    }
+
+    let (pool, quotas) = {
+        let reader = state.read().await;
+        (reader.connpool.clone(), reader.config.quotas)
+    };
+    let mut conn = pool.acquire().await.map_err(|err| {
+        log::error!("Failed to get DB connection: {err:?}");
+        Redirect::to(Urls::Admin.as_ref())
+    })?;
+    let overrides = UserQuota::get_all(&mut conn).await.map_err(|err| {
+        log::error!("Failed to get user quotas: {err:?}");
+        Redirect::to(Urls::Admin.as_ref())
+    })?;
+    let csrftoken = store_api_csrf_token(&mut session, None)
+        .await
+        .map_err(|err| {
+            log::error!("Failed to store csrf token in session: {err:?}");
+            Redirect::to(Urls::Admin.as_ref())
+        })?;
+
+    Ok(QuotasTemplate {
+        user_is_admin: user.admin,
+        quotas,
+        overrides,
+        csrftoken,
+        message: query.message,
+        error: query.error,
+    })
+}
+
+pub(crate) async fn quotas_post(
+    mut session: Session,
+    State(state): State<GoatState>,
+    Form(form): Form<QuotaForm>,
+) -> Result<Redirect, Redirect> {
+    let user = check_logged_in(&mut session, Uri::from_static(Urls::Home.as_ref())).await?;
+    if !user.admin {
+        return Err(Redirect::to(Urls::Dashboard.as_ref()));
+    }
+    let redirect = |key: &str, msg: &str| Redirect::to(&format!("/ui/admin/quotas?{key}={msg}"));
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+
+    let pool = state.read().await.connpool.clone();
+    if let Some(userid) = form.delete {
+        return match UserQuota::delete(&pool, userid).await {
+            Ok(()) => {
+                log::info!("action=quota_delete user={:?} userid={userid}", user.id);
+                Ok(redirect("message", "They're back on the server's quotas"))
+            }
+            Err(err) => {
+                log::error!("Failed to delete quota for userid={userid}: {err:?}");
+                Err(redirect("error", "Failed to remove the quotas"))
+            }
+        };
+    }
+
+    let Some(username) = form.username.as_deref().map(str::trim) else {
+        return Err(redirect("error", "Unknown action!"));
+    };
+    let (max_zones, max_records_per_zone) = match (
+        parse_quota(&form.max_zones),
+        parse_quota(&form.max_records_per_zone),
+    ) {
+        (Ok(max_zones), Ok(max_records_per_zone)) => (max_zones, max_records_per_zone),
+        (Err(msg), _) | (_, Err(msg)) => return Err(redirect("error", msg)),
+    };
+    let mut conn = pool.acquire().await.map_err(|err| {
+        log::error!("Failed to get DB connection: {err:?}");
+        redirect("error", "Failed to save the quotas")
+    })?;
+    let userid = match User::get_by_name(&mut conn, username).await {
+        Ok(Some(owner)) => owner.id,
+        Ok(None) => return Err(redirect("error", "There's no user with that name")),
+        Err(err) => {
+            log::error!("Failed to get user by name: {err:?}");
+            return Err(redirect("error", "Failed to save the quotas"));
+        }
+    };
+    let userid = userid.ok_or_else(|| redirect("error", "Failed to save the quotas"))?;
+    let quota = UserQuota {
+        userid,
+        max_zones,
+        max_records_per_zone,
+    };
+    match quota.save(&pool).await {
+        Ok(()) => {
+            log::info!("action=quota_set user={:?} quota={quota:?}", user.id);
+            Ok(redirect("message", "Saved the quotas"))
+        }
+        Err(err) => {
+            log::error!("Failed to save quota {quota:?}: {err:?}");
+            Err(redirect("error", "Failed to save the quotas"))
+        }
+    }
+}
+
 /// Build the router for user settings
 pub fn router() -> Router<GoatState> {
     Router::new()
         .route("/", get(dashboard))
         .route("/reports/unowned_records", get(report_unowned_records))
+        .route(
+            "/reserved_names",
+            get(reserved_names_get).post(reserved_names_post),
+        )
+        .route("/orphans", get(orphans_get).post(orphans_post))
+        .route("/quotas", get(quotas_get).post(quotas_post))
         .route(
             "/zones/assign_ownership/:id",
             get(assign_zone_ownership).post(assign_zone_ownership),
@@ -1,9 +1,12 @@
 use std::collections::HashMap;
 
 use crate::datastore::Command;
-use crate::db::User;
+use crate::db::{SyncedZone, User, UserSettings};
+use crate::name_policy::is_idn;
+use crate::web::assets::filters;
+use crate::web::generic::ErrorPage;
 use crate::web::utils::Urls;
-use crate::zones::FileZone;
+use crate::zones::{FileZone, ZoneChangeSet, ZoneOwnershipTransfer, ZoneTemplate};
 use askama::Template;
 use axum::extract::{OriginalUri, Path, Query, State};
 use axum::http::{StatusCode, Uri};
@@ -19,28 +22,56 @@ use super::GoatState;
 mod admin_ui;
 mod profile;
 mod user_settings;
+mod wizards;
 mod zones;
 
 #[derive(Template)]
 #[template(path = "view_zones.html")]
 pub(crate) struct TemplateViewZones {
     zones: Vec<FileZone>,
+    templates: Vec<ZoneTemplate>,
+    /// Whether any of the zones have internationalised names, so there's something to toggle
+    idn: bool,
+    /// Only show the punycode names
+    punycode: bool,
     pub user_is_admin: bool,
     message: Option<String>,
     error: Option<String>,
+    request_id: Option<String>,
 }
 
 #[derive(Template)]
 #[template(path = "view_zone.html")]
 pub(crate) struct TemplateViewZone {
     zone: FileZone,
+    /// Where the zone's synced from, if it is
+    synced: Option<String>,
+    /// The last check of its delegation, if it's been checked
+    delegation: Option<crate::delegation::DelegationReport>,
+    /// Whether the zone or any of its records have internationalised names, so there's something to toggle
+    idn: bool,
+    /// Only show the punycode names
+    punycode: bool,
+    /// For showing when records expire in the user's timezone
+    settings: UserSettings,
+    /// For turning the zone on and off
+    csrftoken: String,
+    /// Shown next to the domain's expiry date if it's running out, see [crate::zones::ZoneRegistration::expiry_warning]
+    registration_warning: Option<String>,
     pub user_is_admin: bool,
+    message: Option<String>,
+    error: Option<String>,
+    request_id: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub(crate) struct ViewZonesQueryString {
     message: Option<String>,
     error: Option<String>,
+    request_id: Option<String>,
+    /// Show internationalised names as punycode, rather than unicode with the punycode next to it
+    #[serde(default)]
+    punycode: bool,
 }
 
 pub(crate) async fn zones_list(
@@ -55,19 +86,26 @@ pub(crate) async fn zones_list(
     let (os_tx, os_rx) = tokio::sync::oneshot::channel();
 
     let offset = 0;
-    let limit = 20;
+    let limit = i64::from(
+        user_settings::user_settings(&state, &user)
+            .await
+            .rows_per_page,
+    );
 
     log::trace!("Sending request for zones");
     if let Err(err) = state
         .read()
         .await
         .tx
-        .send(Command::GetZoneNames {
-            resp: os_tx,
-            user: user.clone(),
-            offset,
-            limit,
-        })
+        .send(
+            Command::GetZoneNames {
+                resp: os_tx,
+                user: user.clone(),
+                offset,
+                limit,
+            }
+            .into(),
+        )
         .await
     {
         eprintln!("failed to send GetZoneNames command to datastore: {err:?}");
@@ -75,20 +113,40 @@ pub(crate) async fn zones_list(
         return Err(Urls::Dashboard.redirect().into_response());
     };
 
-    let zones = os_rx.await.map_err(|err| {
-        error!("Failed to get zones for user={:?} error={:?}", user.id, err);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Error getting zones: {err:?}"),
-        )
-            .into_response()
-    })?;
+    let zones = match os_rx.await {
+        Ok(Ok(zones)) => zones,
+        Ok(Err(err)) => {
+            error!("Failed to get zones for user={:?} error={:?}", user.id, err);
+            return Err(ErrorPage::response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error getting zones, please try again",
+            ));
+        }
+        Err(err) => {
+            error!("Failed to get zones for user={:?} error={:?}", user.id, err);
+            return Err(ErrorPage::response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error getting zones, please try again",
+            ));
+        }
+    };
+
+    let templates = ZoneTemplate::get_all(&state.read().await.connpool)
+        .await
+        .unwrap_or_else(|err| {
+            error!("Failed to get zone templates: {err:?}");
+            vec![]
+        });
 
     Ok(TemplateViewZones {
+        idn: zones.iter().any(|zone| is_idn(&zone.name)),
+        punycode: query.punycode,
         zones,
+        templates,
         user_is_admin: user.admin,
         message: query.message,
         error: query.error,
+        request_id: query.request_id,
     })
 }
 
@@ -97,6 +155,7 @@ pub(crate) async fn zone_view(
     Path(name_or_id): Path<i64>,
     State(state): State<GoatState>,
     mut session: Session,
+    Query(query): Query<ViewZonesQueryString>,
 ) -> Result<TemplateViewZone, impl IntoResponse> {
     let user = check_logged_in(&mut session, path)
         .await
@@ -109,33 +168,77 @@ pub(crate) async fn zone_view(
         name: None,
     };
     log::debug!("{cmd:?}");
-    if let Err(err) = state.read().await.tx.send(cmd).await {
+    if let Err(err) = state.read().await.tx.send(cmd.into()).await {
         eprintln!("failed to send GetZone command to datastore: {err:?}");
         log::error!("failed to send GetZone command to datastore: {err:?}");
         return Err(Urls::ZonesList.redirect().into_response());
     };
 
     let zone = match os_rx.await {
-        Ok(zone) => match zone {
+        Ok(Ok(zone)) => match zone {
             Some(value) => value,
             None => {
-                return Err((
-                    axum::http::StatusCode::NOT_FOUND,
+                return Err(ErrorPage::response(
+                    StatusCode::NOT_FOUND,
                     format!("Zone '{}' not found", name_or_id),
-                )
-                    .into_response())
+                ));
             }
         },
+        Ok(Err(err)) => {
+            log::error!("Datastore failed to get zone {name_or_id}: {err:?}");
+            return Err(ErrorPage::response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error getting zone, please try again",
+            ));
+        }
         Err(err) => {
             log::error!("failed to get response from datastore: {err:?}");
             return Err(Urls::ZonesList.redirect().into_response());
         }
     };
 
+    let pool = state.read().await.connpool.clone();
+    let synced = match pool.acquire().await {
+        Ok(mut conn) => SyncedZone::get(&mut conn, zone.id.unwrap_or_default()).await,
+        Err(err) => Err(err.into()),
+    };
+    let synced = match synced {
+        Ok(val) => val.map(|synced| synced.source),
+        Err(err) => {
+            log::error!("Failed to check if zone {name_or_id} is synced: {err:?}");
+            return Err(ErrorPage::response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error getting zone, please try again",
+            ));
+        }
+    };
+
+    let csrftoken = user_settings::store_api_csrf_token(&mut session, None)
+        .await
+        .map_err(|err| {
+            log::error!("Failed to store csrf token in session: {err:?}");
+            ErrorPage::response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error getting zone, please try again",
+            )
+        })?;
+
     log::trace!("Returning zone: {zone:?}");
     Ok(TemplateViewZone {
+        delegation: crate::delegation::report(&zone.name),
+        idn: is_idn(&zone.name) || zone.records.iter().any(|record| is_idn(&record.name)),
+        punycode: query.punycode,
+        registration_warning: zone
+            .registration
+            .expiry_warning(chrono::Utc::now().date_naive()),
         zone,
+        synced,
+        settings: user_settings::user_settings(&state, &user).await,
+        csrftoken,
         user_is_admin: user.admin,
+        message: query.message,
+        error: query.error,
+        request_id: query.request_id,
     })
 }
 
@@ -183,16 +286,54 @@ pub async fn check_logged_in(session: &mut Session, path: Uri) -> Result<User, R
 pub(crate) struct DashboardTemplate /*<'a>*/ {
     // name: &'a str,
     pub user_is_admin: bool,
+    /// Things waiting on the user, if they've asked to be told
+    notices: Vec<(String, String)>,
 }
 
 pub(crate) async fn dashboard(
+    State(state): State<GoatState>,
     mut session: Session,
     OriginalUri(path): OriginalUri,
 ) -> Result<DashboardTemplate, Redirect> {
     let user = check_logged_in(&mut session, path).await?;
 
+    let settings = user_settings::user_settings(&state, &user).await;
+    let mut notices = vec![];
+    if let Some(userid) = user.id {
+        if let Ok(mut conn) = state.read().await.connpool.acquire().await {
+            if settings.notify_transfers {
+                match ZoneOwnershipTransfer::get_pending_for_user(&mut conn, userid).await {
+                    Ok(transfers) if !transfers.is_empty() => notices.push((
+                        format!(
+                            "{} zones are waiting for you to accept them",
+                            transfers.len()
+                        ),
+                        "/ui/transfers".to_string(),
+                    )),
+                    Ok(_) => {}
+                    Err(err) => {
+                        log::error!("Failed to get transfers for user id={userid}: {err:?}")
+                    }
+                }
+            }
+            if settings.notify_changesets {
+                match ZoneChangeSet::count_pending_for_owner(&mut conn, userid).await {
+                    Ok(0) => {}
+                    Ok(count) => notices.push((
+                        format!("{count} changes to your zones are waiting for review"),
+                        Urls::ZonesList.as_ref().to_string(),
+                    )),
+                    Err(err) => {
+                        log::error!("Failed to count change-sets for user id={userid}: {err:?}")
+                    }
+                }
+            }
+        }
+    }
+
     Ok(DashboardTemplate {
         user_is_admin: user.admin,
+        notices,
     })
 }
 
@@ -200,6 +341,26 @@ pub fn new() -> Router<GoatState> {
     Router::new()
         .route("/", get(dashboard))
         .route("/zones/:id", get(zone_view))
+        .route("/zones/:id/changesets", get(zones::zone_changesets_get))
+        .route("/zones/:id/changesets", post(zones::zone_changesets_post))
+        .route("/zones/:id/ttl", get(zones::zone_ttl_get))
+        .route("/zones/:id/ttl", post(zones::zone_ttl_post))
+        .route("/zones/:id/enabled", post(zones::zone_enabled_post))
+        .route("/zones/:id/public", post(zones::zone_public_post))
+        .route(
+            "/zones/:id/registration",
+            post(zones::zone_registration_post),
+        )
+        .route("/zones/:id/transfer", get(zones::zone_transfer_get))
+        .route("/zones/:id/transfer", post(zones::zone_transfer_post))
+        .route("/zones/:id/caa", get(wizards::zone_caa_get))
+        .route("/zones/:id/caa", post(wizards::zone_caa_post))
+        .route("/zones/:id/mail", get(wizards::zone_mail_get))
+        .route("/zones/:id/mail", post(wizards::zone_mail_post))
+        .route("/zones/:id/loc", get(wizards::zone_loc_get))
+        .route("/zones/:id/loc", post(wizards::zone_loc_post))
+        .route("/transfers", get(zones::transfers_get))
+        .route("/transfers", post(zones::transfers_post))
         .route("/zones/list", get(zones_list))
         .route("/zones/new", post(zones::zones_new_post))
         .route("/profile", get(profile::user_profile_get))
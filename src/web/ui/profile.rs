@@ -1,33 +1,65 @@
 //! User profile things
 //!
 
+use crate::web::assets::filters;
 use askama::Template;
 use axum::extract::{OriginalUri, State};
 use axum::response::Redirect;
+use chrono::{TimeDelta, Utc};
 use tower_sessions::Session;
 
-use crate::db::User;
+use crate::db::{DohTokenUsage, DohUsage, User};
 use crate::web::ui::check_logged_in;
 
 use crate::web::GoatState;
 
+/// How many days of DoH usage to show on the profile page
+const DOH_USAGE_DAYS: i64 = 7;
+/// How many of the most-queried names to show per token
+const DOH_USAGE_TOP_NAMES: usize = 10;
+
 #[derive(Template)]
 #[template(path = "view_profile.html")]
 pub(crate) struct UserProfilePage {
     pub user: User,
     pub user_is_admin: bool,
+    /// DoH queries made with the user's API tokens, if it's being counted
+    pub doh_usage: Vec<DohTokenUsage>,
 }
 
 pub(crate) async fn user_profile_get(
-    State(_state): State<GoatState>,
+    State(state): State<GoatState>,
     mut session: Session,
     OriginalUri(path): OriginalUri,
 ) -> Result<UserProfilePage, Redirect> {
     // check_logged_in!(state, session, path);
 
     let user: User = check_logged_in(&mut session, path).await?;
+
+    let (pool, usage_enabled) = {
+        let reader = state.read().await;
+        (
+            reader.connpool.clone(),
+            reader.config.doh.enabled && reader.config.doh.auth.usage_enabled(),
+        )
+    };
+    let mut doh_usage = vec![];
+    if let (true, Some(userid)) = (usage_enabled, user.id) {
+        let since = Utc::now().date_naive() - TimeDelta::days(DOH_USAGE_DAYS);
+        let res = match pool.acquire().await {
+            Ok(mut conn) => DohUsage::for_user(&mut conn, userid, since, DOH_USAGE_TOP_NAMES).await,
+            Err(err) => Err(err.into()),
+        };
+        match res {
+            Ok(val) => doh_usage = val,
+            // not worth failing the whole page over
+            Err(err) => log::error!("Failed to get DoH usage for user id={userid}: {err:?}"),
+        }
+    }
+
     Ok(UserProfilePage {
         user_is_admin: user.admin,
         user,
+        doh_usage,
     })
 }
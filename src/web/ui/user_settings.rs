@@ -1,5 +1,6 @@
-use crate::db::{DBEntity, User};
+use crate::db::{DBEntity, User, UserSettings};
 use crate::error::GoatNsError;
+use crate::web::api::apitoken::rotate_token;
 use crate::web::ui::check_logged_in;
 use crate::web::utils::{create_api_token, Urls};
 use std::collections::HashMap;
@@ -7,12 +8,13 @@ use std::fmt::Display;
 use std::str::FromStr;
 use std::sync::Arc;
 
+use crate::web::assets::filters;
 use askama::Template;
-use axum::extract::{OriginalUri, Path, State};
+use axum::extract::{OriginalUri, Path, Query, State};
 use axum::response::{Html, IntoResponse, Redirect};
 use axum::routing::get;
 use axum::routing::post;
-use axum::{Form, Router};
+use axum::{Form, Json, Router};
 
 use axum::http::Uri;
 use chrono::{DateTime, TimeDelta, Utc};
@@ -31,12 +33,114 @@ static SESSION_CSRFTOKEN_FIELD: &str = "api_token_csrf_token";
 #[template(path = "user_settings.html")]
 pub(crate) struct Settings {
     pub user_is_admin: bool,
+    settings: UserSettings,
+    csrftoken: String,
+    message: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SettingsQuery {
+    message: Option<String>,
+    error: Option<String>,
+}
+
+/// What the settings form sends, unticked checkboxes aren't sent at all
+#[derive(Deserialize)]
+pub(crate) struct SettingsForm {
+    csrftoken: String,
+    timezone: String,
+    rows_per_page: u32,
+    /// Empty means the usual default
+    #[serde(default)]
+    default_ttl: String,
+    #[serde(default)]
+    notify_transfers: Option<String>,
+    #[serde(default)]
+    notify_changesets: Option<String>,
+}
+
+/// The user's settings, or the defaults if they can't be loaded - it's not worth failing a page over
+pub(crate) async fn user_settings(state: &GoatState, user: &User) -> UserSettings {
+    let Some(userid) = user.id else {
+        return UserSettings::default();
+    };
+    let res = match state.read().await.connpool.acquire().await {
+        Ok(mut conn) => UserSettings::get(&mut conn, userid).await,
+        Err(err) => Err(err.into()),
+    };
+    res.unwrap_or_else(|err| {
+        error!("Failed to get settings for user id={userid}: {err:?}");
+        UserSettings::default()
+    })
 }
 
 /// The user settings page at /ui/settings
-pub(crate) async fn settings(State(_state): State<GoatState>) -> Settings {
-    Settings {
-        user_is_admin: true,
+pub(crate) async fn settings(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Query(query): Query<SettingsQuery>,
+) -> Result<Settings, Redirect> {
+    let user = check_logged_in(&mut session, path).await?;
+    let csrftoken = store_api_csrf_token(&mut session, None)
+        .await
+        .map_err(|err| {
+            error!("Failed to store csrf token in session: {err:?}");
+            Urls::Dashboard.redirect()
+        })?;
+    Ok(Settings {
+        user_is_admin: user.admin,
+        settings: user_settings(&state, &user).await,
+        csrftoken,
+        message: query.message,
+        error: query.error,
+    })
+}
+
+/// Save the settings form
+pub(crate) async fn settings_post(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Form(form): Form<SettingsForm>,
+) -> Result<Redirect, Redirect> {
+    let user = check_logged_in(&mut session, path).await?;
+    let redirect = |key: &str, msg: &str| Redirect::to(&format!("/ui/settings?{key}={msg}"));
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+    let userid = user
+        .id
+        .ok_or_else(|| redirect("error", "No user ID found"))?;
+    let default_ttl = match form.default_ttl.trim() {
+        "" => None,
+        ttl => Some(
+            ttl.parse::<u32>()
+                .map_err(|_| redirect("error", "The default TTL needs to be a number"))?,
+        ),
+    };
+    let settings = UserSettings {
+        timezone: form.timezone.trim().to_string(),
+        rows_per_page: form.rows_per_page,
+        default_ttl,
+        notify_transfers: form.notify_transfers.is_some(),
+        notify_changesets: form.notify_changesets.is_some(),
+    };
+
+    let pool = state.read().await.connpool.clone();
+    match settings.save(&pool, userid).await {
+        Ok(()) => {
+            info!("action=settings_update user={userid} settings={settings:?}");
+            Ok(redirect("message", "Saved your settings"))
+        }
+        Err(GoatNsError::InvalidValue(msg)) => Err(redirect("error", &msg)),
+        Err(err) => {
+            error!("Failed to save settings for user id={userid}: {err:?}");
+            Err(redirect("error", "Failed to save your settings"))
+        }
     }
 }
 
@@ -125,7 +229,7 @@ pub async fn validate_csrf_expiry(user_input: &str, session: &mut Session) -> bo
 /// Store a CSRF token with an expiry in the session store
 ///
 /// Expiry defaults to 5 (minutes)
-async fn store_api_csrf_token(
+pub(crate) async fn store_api_csrf_token(
     session: &mut Session,
     expiry_plus_seconds: Option<i64>,
 ) -> Result<String, GoatNsError> {
@@ -411,6 +515,7 @@ pub async fn api_tokens_post(
                 userid,
                 tokenkey: api_token.token_key.to_owned(),
                 tokenhash: api_token.token_hash,
+                last_used: None,
             };
             log::trace!("Starting to store token in the DB, grabbing transaction...");
 
@@ -637,12 +742,56 @@ pub async fn api_tokens_delete_post(
     );
     Err(Urls::SettingsApiTokens.redirect())
 }
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ApiTokenRotateForm {
+    pub csrftoken: String,
+}
+
+/// Give a token a new secret, and show it once on the tokens page
+pub async fn api_tokens_rotate_post(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Form(form): Form<ApiTokenRotateForm>,
+) -> Result<Redirect, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(Urls::SettingsApiTokens.redirect());
+    }
+
+    let (token, api_token) = match rotate_token(&state, &user, id).await {
+        Ok(val) => val,
+        Err((_, Json(err))) => {
+            log::debug!("Failed to rotate token id={id}: {}", err.message);
+            return Err(Urls::SettingsApiTokens.redirect());
+        }
+    };
+
+    // same as when it's created, the tokens page shows it once and takes it out of the session
+    if let Err(error) = session
+        .insert("new_api_token", &api_token.token_secret)
+        .await
+    {
+        log::error!("Failed to store the rotated API token in the session: {error:?}");
+        return Err(Urls::SettingsApiTokens.redirect());
+    }
+    if let Err(error) = session.insert("new_api_tokenkey", &token.tokenkey).await {
+        log::error!("Failed to store the rotated API tokenkey in the session: {error:?}");
+        return Err(Urls::SettingsApiTokens.redirect());
+    }
+    Ok(Urls::SettingsApiTokens.redirect())
+}
+
 /// Build the router for user settings
 pub fn router() -> Router<GoatState> {
     Router::new()
-        .route("/", get(settings))
+        .route("/", get(settings).post(settings_post))
         .route("/api_tokens", get(api_tokens_get))
         .route("/api_tokens", post(api_tokens_post))
         .route("/api_tokens/delete/:id", get(api_tokens_delete_get))
         .route("/api_tokens/delete/:id", post(api_tokens_delete_post))
+        .route("/api_tokens/rotate/:id", post(api_tokens_rotate_post))
 }
@@ -0,0 +1,845 @@
+//! The record wizard pages, which build records from [crate::wizards] so nobody has to hand-craft the rdata
+
+use std::collections::HashMap;
+
+use askama::Template;
+use axum::extract::{OriginalUri, Path, Query, State};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::{Form, Json};
+use ipnet::IpNet;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tower_sessions::Session;
+
+use crate::db::User;
+use crate::error::GoatNsError;
+use crate::resourcerecord::DecimalLocation;
+use crate::web::api::changeset::user_can_review;
+use crate::web::api::filezonerecord::create_record;
+use crate::web::ui::check_logged_in;
+use crate::web::ui::user_settings::{store_api_csrf_token, user_settings, validate_csrf_expiry};
+use crate::web::ui::zones::ChangeSetsQueryString;
+use crate::web::utils::Urls;
+use crate::web::GoatState;
+use crate::wizards::caa::{CaPreset, CaaRecord, CaaTag, CA_PRESETS};
+use crate::wizards::loc::{map_tile, MapTile};
+use crate::wizards::mail::{DkimRecord, DmarcRecord, SpfRecord};
+use crate::wizards::{txt_strings, TxtWizard};
+use crate::zones::{FileZone, FileZoneRecord};
+
+#[derive(Template)]
+#[template(path = "view_zone_caa.html")]
+pub(crate) struct TemplateZoneCaa {
+    zone: FileZone,
+    presets: Vec<CaPreset>,
+    form: ZoneCaaForm,
+    /// What the rdata would be, when they're checking a record
+    rdata: Option<String>,
+    csrftoken: String,
+    pub user_is_admin: bool,
+    message: Option<String>,
+    error: Option<String>,
+    request_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ZoneCaaForm {
+    #[serde(default)]
+    csrftoken: String,
+    name: String,
+    ttl: String,
+    /// issue, issuewild or iodef
+    tag: String,
+    /// The issuer from [CA_PRESETS], ignored if `value` is set
+    #[serde(default)]
+    preset: String,
+    /// A custom issuer, or the iodef URL
+    #[serde(default)]
+    value: String,
+    /// Set when the issuer critical box is ticked
+    #[serde(default)]
+    critical: Option<String>,
+    /// Either "check" or "add"
+    action: String,
+}
+
+impl Default for ZoneCaaForm {
+    fn default() -> Self {
+        Self {
+            csrftoken: String::new(),
+            name: "@".to_string(),
+            ttl: "3600".to_string(),
+            tag: CaaTag::Issue.to_string(),
+            preset: CA_PRESETS[0].issuer.to_string(),
+            value: String::new(),
+            critical: None,
+            action: String::new(),
+        }
+    }
+}
+
+impl ZoneCaaForm {
+    fn record(&self) -> Result<CaaRecord, GoatNsError> {
+        let tag: CaaTag = self.tag.parse()?;
+        let value = match (self.value.trim(), tag) {
+            ("", CaaTag::Issue | CaaTag::IssueWild) => self.preset.trim(),
+            (value, _) => value,
+        };
+        Ok(CaaRecord {
+            critical: self.critical.is_some(),
+            tag,
+            value: value.to_string(),
+        })
+    }
+}
+
+/// Gets the zone and a fresh CSRF token for a wizard page, as long as the user owns the zone
+async fn load_zone(
+    state: &GoatState,
+    session: &mut Session,
+    user: &User,
+    id: i64,
+) -> Result<(FileZone, String), Redirect> {
+    let error_redirect = |msg: &str| {
+        Urls::ZonesList.redirect_with_query(HashMap::from([("error".to_string(), msg.to_string())]))
+    };
+
+    let pool = state.read().await.connpool.clone();
+    let mut conn = pool.acquire().await.map_err(|err| {
+        log::error!("Failed to get a database connection: {err:?}");
+        error_redirect("Failed to get the zone, please try again.")
+    })?;
+
+    match user_can_review(&mut conn, user, id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(error_redirect("You can't change that zone!")),
+        Err(err) => {
+            log::error!("Failed to check ownership of zone id={id}: {err:?}");
+            return Err(error_redirect("Failed to get the zone, please try again."));
+        }
+    }
+
+    let zone = FileZone::get_with_txn(&mut conn, &id)
+        .await
+        .map_err(|err| {
+            log::debug!("Failed to get zone id={id}: {err:?}");
+            error_redirect("Zone not found!")
+        })?;
+
+    let csrftoken = store_api_csrf_token(session, None).await.map_err(|err| {
+        log::error!("Failed to store csrf token in session: {err:?}");
+        error_redirect("Failed to get the zone, please try again.")
+    })?;
+    Ok((*zone, csrftoken))
+}
+
+/// Loads everything the CAA page needs
+async fn zone_caa_page(
+    state: &GoatState,
+    session: &mut Session,
+    user: &User,
+    id: i64,
+    form: ZoneCaaForm,
+    rdata: Option<String>,
+    query: ChangeSetsQueryString,
+) -> Result<TemplateZoneCaa, Redirect> {
+    let (zone, csrftoken) = load_zone(state, session, user, id).await?;
+    Ok(TemplateZoneCaa {
+        zone,
+        presets: CA_PRESETS.to_vec(),
+        form,
+        rdata,
+        csrftoken,
+        user_is_admin: user.admin,
+        message: query.message,
+        error: query.error,
+        request_id: query.request_id,
+    })
+}
+
+/// The CAA wizard at /ui/zones/:id/caa
+pub(crate) async fn zone_caa_get(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Query(query): Query<ChangeSetsQueryString>,
+) -> Result<TemplateZoneCaa, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let mut form = ZoneCaaForm::default();
+    if let Some(ttl) = user_settings(&state, &user).await.default_ttl {
+        form.ttl = ttl.to_string();
+    }
+    zone_caa_page(&state, &mut session, &user, id, form, None, query).await
+}
+
+/// Check the CAA record, or add it to the zone
+pub(crate) async fn zone_caa_post(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Form(form): Form<ZoneCaaForm>,
+) -> Result<Response, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let page = format!("/ui/zones/{id}/caa");
+    let redirect = |key: &str, msg: &str| Redirect::to(&format!("{page}?{key}={msg}"));
+    // problems with the record are shown with the form still filled in
+    let error = |msg: String| ChangeSetsQueryString {
+        error: Some(msg),
+        ..Default::default()
+    };
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+
+    let policy = state.read().await.config.import_ttl.clone();
+    let ttl = match form.ttl.trim().parse::<u32>() {
+        Ok(ttl) if (policy.min_ttl..=policy.max_ttl).contains(&ttl) => ttl,
+        _ => {
+            let msg = format!(
+                "The TTL needs to be a number from {} to {}",
+                policy.min_ttl, policy.max_ttl
+            );
+            return zone_caa_page(&state, &mut session, &user, id, form, None, error(msg))
+                .await
+                .map(IntoResponse::into_response);
+        }
+    };
+
+    let record = match form
+        .record()
+        .and_then(|caa| caa.to_record(Some(id), form.name.trim(), ttl))
+    {
+        Ok(val) => val,
+        Err(GoatNsError::InvalidValue(msg)) | Err(GoatNsError::Generic(msg)) => {
+            return zone_caa_page(&state, &mut session, &user, id, form, None, error(msg))
+                .await
+                .map(IntoResponse::into_response);
+        }
+        Err(err) => {
+            log::error!("Failed to build CAA record for zone id={id}: {err:?}");
+            return Err(redirect(
+                "error",
+                "Failed to build the record, please try again.",
+            ));
+        }
+    };
+
+    match form.action.as_str() {
+        "check" => zone_caa_page(
+            &state,
+            &mut session,
+            &user,
+            id,
+            form,
+            Some(record.rdata),
+            ChangeSetsQueryString::default(),
+        )
+        .await
+        .map(IntoResponse::into_response),
+        "add" => match create_record(&state, &user, record).await {
+            Ok(record) => {
+                log::info!(
+                    "id={:?} action=wizard_caa zoneid={id} rdata={:?}",
+                    user.id,
+                    record.rdata
+                );
+                Ok(redirect("message", "Added the CAA record").into_response())
+            }
+            Err((_, Json(err))) if err.message.is_empty() => {
+                Err(redirect("error", "You can't change that zone!"))
+            }
+            Err((_, Json(err))) => zone_caa_page(
+                &state,
+                &mut session,
+                &user,
+                id,
+                form,
+                None,
+                error(err.message),
+            )
+            .await
+            .map(IntoResponse::into_response),
+        },
+        _ => Err(redirect("error", "Unknown action!")),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "view_zone_mail.html")]
+pub(crate) struct TemplateZoneMail {
+    zone: FileZone,
+    form: ZoneMailForm,
+    /// What the TXT data would be (and how it'd be split up), when they're checking a record
+    preview: Option<MailPreview>,
+    csrftoken: String,
+    pub user_is_admin: bool,
+    message: Option<String>,
+    error: Option<String>,
+    request_id: Option<String>,
+}
+
+pub(crate) struct MailPreview {
+    name: String,
+    rdata: String,
+    strings: Vec<String>,
+}
+
+/// The fields for all three mail wizards, `wizard` says which one's being used. Lists are split on commas and
+/// whitespace.
+#[derive(Deserialize, Debug)]
+pub(crate) struct ZoneMailForm {
+    #[serde(default)]
+    csrftoken: String,
+    /// Either "spf", "dkim" or "dmarc"
+    wizard: String,
+    /// Either "check" or "add"
+    action: String,
+    /// Leave it empty to use the usual place for the record
+    #[serde(default)]
+    name: String,
+    ttl: String,
+
+    #[serde(default)]
+    spf_mx: Option<String>,
+    #[serde(default)]
+    spf_a: Option<String>,
+    #[serde(default)]
+    spf_ip4: String,
+    #[serde(default)]
+    spf_ip6: String,
+    #[serde(default)]
+    spf_include: String,
+    #[serde(default)]
+    spf_redirect: String,
+    #[serde(default)]
+    spf_all: String,
+
+    #[serde(default)]
+    dkim_selector: String,
+    #[serde(default)]
+    dkim_key_type: String,
+    #[serde(default)]
+    dkim_public_key: String,
+    #[serde(default)]
+    dkim_testing: Option<String>,
+
+    #[serde(default)]
+    dmarc_policy: String,
+    #[serde(default)]
+    dmarc_sp: String,
+    #[serde(default)]
+    dmarc_pct: String,
+    #[serde(default)]
+    dmarc_rua: String,
+    #[serde(default)]
+    dmarc_ruf: String,
+    #[serde(default)]
+    dmarc_adkim: String,
+    #[serde(default)]
+    dmarc_aspf: String,
+}
+
+impl Default for ZoneMailForm {
+    fn default() -> Self {
+        Self {
+            csrftoken: String::new(),
+            wizard: String::new(),
+            action: String::new(),
+            name: String::new(),
+            ttl: "3600".to_string(),
+            spf_mx: Some("true".to_string()),
+            spf_a: None,
+            spf_ip4: String::new(),
+            spf_ip6: String::new(),
+            spf_include: String::new(),
+            spf_redirect: String::new(),
+            spf_all: "softfail".to_string(),
+            dkim_selector: String::new(),
+            dkim_key_type: "rsa".to_string(),
+            dkim_public_key: String::new(),
+            dkim_testing: None,
+            dmarc_policy: "none".to_string(),
+            dmarc_sp: String::new(),
+            dmarc_pct: String::new(),
+            dmarc_rua: String::new(),
+            dmarc_ruf: String::new(),
+            dmarc_adkim: String::new(),
+            dmarc_aspf: String::new(),
+        }
+    }
+}
+
+/// Splits a list field on commas and whitespace
+fn form_list(value: &str) -> Vec<String> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Turns a select's value into one of the wizards' enums, empty means it wasn't picked
+fn form_choice<T: DeserializeOwned>(field: &str, value: &str) -> Result<Option<T>, GoatNsError> {
+    match value.trim() {
+        "" => Ok(None),
+        value => serde_json::from_value(serde_json::Value::String(value.to_string()))
+            .map(Some)
+            .map_err(|_| GoatNsError::InvalidValue(format!("{value:?} isn't a valid {field}"))),
+    }
+}
+
+impl ZoneMailForm {
+    fn spf(&self) -> Result<SpfRecord, GoatNsError> {
+        let parse_nets = |value: &str| {
+            form_list(value)
+                .iter()
+                .map(|net| {
+                    // a plain address is a /32 or /128
+                    let net = match net.contains('/') {
+                        true => net.to_string(),
+                        false if net.contains(':') => format!("{net}/128"),
+                        false => format!("{net}/32"),
+                    };
+                    net.parse::<IpNet>().map_err(|_| {
+                        GoatNsError::InvalidValue(format!("{net:?} isn't a valid IP or network"))
+                    })
+                })
+                .collect::<Result<Vec<IpNet>, GoatNsError>>()
+        };
+        let mut ip4 = vec![];
+        let mut ip6 = vec![];
+        for net in parse_nets(&self.spf_ip4)?
+            .into_iter()
+            .chain(parse_nets(&self.spf_ip6)?)
+        {
+            match net {
+                IpNet::V4(net) => ip4.push(net),
+                IpNet::V6(net) => ip6.push(net),
+            }
+        }
+        Ok(SpfRecord {
+            mx: self.spf_mx.is_some(),
+            a: self.spf_a.is_some(),
+            ip4,
+            ip6,
+            include: form_list(&self.spf_include),
+            redirect: Some(self.spf_redirect.trim().to_string()).filter(|r| !r.is_empty()),
+            all: form_choice("SPF default", &self.spf_all)?.unwrap_or_default(),
+        })
+    }
+
+    fn dkim(&self) -> Result<DkimRecord, GoatNsError> {
+        Ok(DkimRecord {
+            selector: self.dkim_selector.trim().to_string(),
+            key_type: form_choice("key type", &self.dkim_key_type)?.unwrap_or_default(),
+            public_key: self.dkim_public_key.clone(),
+            testing: self.dkim_testing.is_some(),
+        })
+    }
+
+    fn dmarc(&self) -> Result<DmarcRecord, GoatNsError> {
+        let pct = match self.dmarc_pct.trim() {
+            "" => None,
+            pct => Some(pct.parse::<u8>().map_err(|_| {
+                GoatNsError::InvalidValue("pct is a percentage, it has to be from 0 to 100".into())
+            })?),
+        };
+        Ok(DmarcRecord {
+            policy: form_choice("policy", &self.dmarc_policy)?.unwrap_or_default(),
+            subdomain_policy: form_choice("subdomain policy", &self.dmarc_sp)?,
+            pct,
+            rua: form_list(&self.dmarc_rua),
+            ruf: form_list(&self.dmarc_ruf),
+            adkim: form_choice("DKIM alignment", &self.dmarc_adkim)?,
+            aspf: form_choice("SPF alignment", &self.dmarc_aspf)?,
+        })
+    }
+
+    /// Builds the record for whichever wizard the form was for
+    fn record(&self, zoneid: i64, ttl: u32) -> Result<FileZoneRecord, GoatNsError> {
+        let name = Some(self.name.as_str());
+        match self.wizard.as_str() {
+            "spf" => self.spf()?.to_record(Some(zoneid), name, ttl),
+            "dkim" => self.dkim()?.to_record(Some(zoneid), name, ttl),
+            "dmarc" => self.dmarc()?.to_record(Some(zoneid), name, ttl),
+            _ => Err(GoatNsError::InvalidValue("Unknown wizard!".to_string())),
+        }
+    }
+}
+
+/// Loads everything the mail records page needs
+async fn zone_mail_page(
+    state: &GoatState,
+    session: &mut Session,
+    user: &User,
+    id: i64,
+    form: ZoneMailForm,
+    preview: Option<MailPreview>,
+    query: ChangeSetsQueryString,
+) -> Result<TemplateZoneMail, Redirect> {
+    let (zone, csrftoken) = load_zone(state, session, user, id).await?;
+    Ok(TemplateZoneMail {
+        zone,
+        form,
+        preview,
+        csrftoken,
+        user_is_admin: user.admin,
+        message: query.message,
+        error: query.error,
+        request_id: query.request_id,
+    })
+}
+
+/// The SPF, DKIM and DMARC wizards at /ui/zones/:id/mail
+pub(crate) async fn zone_mail_get(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Query(query): Query<ChangeSetsQueryString>,
+) -> Result<TemplateZoneMail, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let mut form = ZoneMailForm::default();
+    if let Some(ttl) = user_settings(&state, &user).await.default_ttl {
+        form.ttl = ttl.to_string();
+    }
+    zone_mail_page(&state, &mut session, &user, id, form, None, query).await
+}
+
+/// Check one of the mail records, or add it to the zone
+pub(crate) async fn zone_mail_post(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Form(form): Form<ZoneMailForm>,
+) -> Result<Response, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let page = format!("/ui/zones/{id}/mail");
+    let redirect = |key: &str, msg: &str| Redirect::to(&format!("{page}?{key}={msg}"));
+    // problems with the record are shown with the form still filled in
+    let error = |msg: String| ChangeSetsQueryString {
+        error: Some(msg),
+        ..Default::default()
+    };
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+
+    let policy = state.read().await.config.import_ttl.clone();
+    let record = match form.ttl.trim().parse::<u32>() {
+        Ok(ttl) if (policy.min_ttl..=policy.max_ttl).contains(&ttl) => form.record(id, ttl),
+        _ => Err(GoatNsError::InvalidValue(format!(
+            "The TTL needs to be a number from {} to {}",
+            policy.min_ttl, policy.max_ttl
+        ))),
+    };
+    let record = match record {
+        Ok(val) => val,
+        Err(GoatNsError::InvalidValue(msg)) | Err(GoatNsError::Generic(msg)) => {
+            return zone_mail_page(&state, &mut session, &user, id, form, None, error(msg))
+                .await
+                .map(IntoResponse::into_response);
+        }
+        Err(err) => {
+            log::error!("Failed to build mail record for zone id={id}: {err:?}");
+            return Err(redirect(
+                "error",
+                "Failed to build the record, please try again.",
+            ));
+        }
+    };
+
+    match form.action.as_str() {
+        "check" => {
+            let preview = MailPreview {
+                strings: txt_strings(&record.rdata),
+                name: record.name,
+                rdata: record.rdata,
+            };
+            zone_mail_page(
+                &state,
+                &mut session,
+                &user,
+                id,
+                form,
+                Some(preview),
+                ChangeSetsQueryString::default(),
+            )
+            .await
+            .map(IntoResponse::into_response)
+        }
+        "add" => match create_record(&state, &user, record).await {
+            Ok(record) => {
+                log::info!(
+                    "id={:?} action=wizard_{} zoneid={id} rdata={:?}",
+                    user.id,
+                    form.wizard,
+                    record.rdata
+                );
+                Ok(redirect(
+                    "message",
+                    &format!("Added the {} record", form.wizard.to_uppercase()),
+                )
+                .into_response())
+            }
+            Err((_, Json(err))) if err.message.is_empty() => {
+                Err(redirect("error", "You can't change that zone!"))
+            }
+            Err((_, Json(err))) => zone_mail_page(
+                &state,
+                &mut session,
+                &user,
+                id,
+                form,
+                None,
+                error(err.message),
+            )
+            .await
+            .map(IntoResponse::into_response),
+        },
+        _ => Err(redirect("error", "Unknown action!")),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "view_zone_loc.html")]
+pub(crate) struct TemplateZoneLoc {
+    zone: FileZone,
+    form: ZoneLocForm,
+    /// What the rdata would be, when they're checking a record
+    rdata: Option<String>,
+    /// Where it is, when they're checking a record and there's a map configured
+    map: Option<MapTile>,
+    csrftoken: String,
+    pub user_is_admin: bool,
+    message: Option<String>,
+    error: Option<String>,
+    request_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ZoneLocForm {
+    #[serde(default)]
+    csrftoken: String,
+    name: String,
+    ttl: String,
+    latitude: String,
+    longitude: String,
+    #[serde(default)]
+    altitude: String,
+    #[serde(default)]
+    size: String,
+    #[serde(default)]
+    horiz_pre: String,
+    #[serde(default)]
+    vert_pre: String,
+    /// Either "check" or "add"
+    action: String,
+}
+
+impl Default for ZoneLocForm {
+    fn default() -> Self {
+        Self {
+            csrftoken: String::new(),
+            name: "@".to_string(),
+            ttl: "3600".to_string(),
+            latitude: String::new(),
+            longitude: String::new(),
+            altitude: "0".to_string(),
+            size: goat_lib::constants::DEFAULT_LOC_SIZE.to_string(),
+            horiz_pre: goat_lib::constants::DEFAULT_LOC_HORIZ_PRE.to_string(),
+            vert_pre: goat_lib::constants::DEFAULT_LOC_VERT_PRE.to_string(),
+            action: String::new(),
+        }
+    }
+}
+
+/// Parses a number field, empty ones get `default`
+fn form_number<T: std::str::FromStr>(
+    field: &str,
+    value: &str,
+    default: T,
+) -> Result<T, GoatNsError> {
+    match value.trim() {
+        "" => Ok(default),
+        value => value
+            .parse()
+            .map_err(|_| GoatNsError::InvalidValue(format!("{value:?} isn't a valid {field}"))),
+    }
+}
+
+impl ZoneLocForm {
+    fn location(&self) -> Result<DecimalLocation, GoatNsError> {
+        let degrees = |field: &str, value: &str| match value.trim() {
+            "" => Err(GoatNsError::InvalidValue(format!("The {field} is needed"))),
+            value => form_number(field, value, 0.0f64),
+        };
+        Ok(DecimalLocation {
+            latitude: degrees("latitude", &self.latitude)?,
+            longitude: degrees("longitude", &self.longitude)?,
+            altitude: form_number("altitude", &self.altitude, 0)?,
+            size: form_number("size", &self.size, goat_lib::constants::DEFAULT_LOC_SIZE)?,
+            horiz_pre: form_number(
+                "horizontal precision",
+                &self.horiz_pre,
+                goat_lib::constants::DEFAULT_LOC_HORIZ_PRE,
+            )?,
+            vert_pre: form_number(
+                "vertical precision",
+                &self.vert_pre,
+                goat_lib::constants::DEFAULT_LOC_VERT_PRE,
+            )?,
+        })
+    }
+}
+
+/// Loads everything the LOC page needs
+async fn zone_loc_page(
+    state: &GoatState,
+    session: &mut Session,
+    user: &User,
+    id: i64,
+    form: ZoneLocForm,
+    preview: Option<(String, Option<MapTile>)>,
+    query: ChangeSetsQueryString,
+) -> Result<TemplateZoneLoc, Redirect> {
+    let (zone, csrftoken) = load_zone(state, session, user, id).await?;
+    let (rdata, map) = match preview {
+        Some((rdata, map)) => (Some(rdata), map),
+        None => (None, None),
+    };
+    Ok(TemplateZoneLoc {
+        zone,
+        form,
+        rdata,
+        map,
+        csrftoken,
+        user_is_admin: user.admin,
+        message: query.message,
+        error: query.error,
+        request_id: query.request_id,
+    })
+}
+
+/// The LOC record editor at /ui/zones/:id/loc
+pub(crate) async fn zone_loc_get(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Query(query): Query<ChangeSetsQueryString>,
+) -> Result<TemplateZoneLoc, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let mut form = ZoneLocForm::default();
+    if let Some(ttl) = user_settings(&state, &user).await.default_ttl {
+        form.ttl = ttl.to_string();
+    }
+    zone_loc_page(&state, &mut session, &user, id, form, None, query).await
+}
+
+/// Check the LOC record and show it on the map, or add it to the zone
+pub(crate) async fn zone_loc_post(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Form(form): Form<ZoneLocForm>,
+) -> Result<Response, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let page = format!("/ui/zones/{id}/loc");
+    let redirect = |key: &str, msg: &str| Redirect::to(&format!("{page}?{key}={msg}"));
+    // problems with the record are shown with the form still filled in
+    let error = |msg: String| ChangeSetsQueryString {
+        error: Some(msg),
+        ..Default::default()
+    };
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+
+    let (policy, map_tile_url) = {
+        let state = state.read().await;
+        (
+            state.config.import_ttl.clone(),
+            state.config.map_tile_url.clone(),
+        )
+    };
+    let location = match form.ttl.trim().parse::<u32>() {
+        Ok(ttl) if (policy.min_ttl..=policy.max_ttl).contains(&ttl) => {
+            form.location().and_then(|location| {
+                crate::wizards::loc::to_record(&location, Some(id), form.name.trim(), ttl)
+                    .map(|record| (location, record))
+            })
+        }
+        _ => Err(GoatNsError::InvalidValue(format!(
+            "The TTL needs to be a number from {} to {}",
+            policy.min_ttl, policy.max_ttl
+        ))),
+    };
+    let (location, record) = match location {
+        Ok(val) => val,
+        Err(GoatNsError::InvalidValue(msg)) | Err(GoatNsError::Generic(msg)) => {
+            return zone_loc_page(&state, &mut session, &user, id, form, None, error(msg))
+                .await
+                .map(IntoResponse::into_response);
+        }
+        Err(err) => {
+            log::error!("Failed to build LOC record for zone id={id}: {err:?}");
+            return Err(redirect(
+                "error",
+                "Failed to build the record, please try again.",
+            ));
+        }
+    };
+
+    match form.action.as_str() {
+        "check" => {
+            let preview = (record.rdata, map_tile(&map_tile_url, &location));
+            zone_loc_page(
+                &state,
+                &mut session,
+                &user,
+                id,
+                form,
+                Some(preview),
+                ChangeSetsQueryString::default(),
+            )
+            .await
+            .map(IntoResponse::into_response)
+        }
+        "add" => match create_record(&state, &user, record).await {
+            Ok(record) => {
+                log::info!(
+                    "id={:?} action=wizard_loc zoneid={id} rdata={:?}",
+                    user.id,
+                    record.rdata
+                );
+                Ok(redirect("message", "Added the LOC record").into_response())
+            }
+            Err((_, Json(err))) if err.message.is_empty() => {
+                Err(redirect("error", "You can't change that zone!"))
+            }
+            Err((_, Json(err))) => zone_loc_page(
+                &state,
+                &mut session,
+                &user,
+                id,
+                form,
+                None,
+                error(err.message),
+            )
+            .await
+            .map(IntoResponse::into_response),
+        },
+        _ => Err(redirect("error", "Unknown action!")),
+    }
+}
@@ -2,24 +2,36 @@
 
 use std::collections::HashMap;
 
-use axum::extract::{OriginalUri, State};
+use crate::web::assets::filters;
+use askama::Template;
+use axum::extract::{OriginalUri, Path, Query, State};
 use axum::response::Redirect;
-use axum::Form;
-use goat_lib::validators::dns_name;
+use axum::{Form, Json};
 use serde::Deserialize;
 use tower_sessions::Session;
 use tracing::debug;
 
 use crate::datastore::Command;
-use crate::db::User;
+use crate::db::{DBEntity, SyncedZone, User, UserQuota, UserSettings};
+use crate::error::GoatNsError;
+use crate::name_policy::NamePolicy;
+use crate::web::api::changeset::{review_changeset, user_can_review};
+use crate::web::api::transfer::{resolve_transfer, start_transfer};
 use crate::web::ui::check_logged_in;
+use crate::web::ui::user_settings::{store_api_csrf_token, user_settings, validate_csrf_expiry};
 use crate::web::utils::Urls;
 use crate::web::GoatState;
-use crate::zones::FileZone;
+use crate::zones::{
+    FileZone, OwnershipTransferStatus, ZoneChangeSet, ZoneJournalEntry, ZoneOwnershipTransfer,
+    ZoneRegistration, ZoneTemplate,
+};
 
 #[derive(Deserialize, Debug)]
 pub(crate) struct NewZoneForm {
     name: String,
+    /// The ID of the template to pre-populate records from, empty if they didn't pick one
+    #[serde(default)]
+    template: Option<String>,
 }
 
 pub(crate) async fn zones_new_post(
@@ -42,19 +54,27 @@ pub(crate) async fn zones_new_post(
         }
     };
 
-    // validate the zone is valid
-    if form.name.is_empty() {
-        return Err(Urls::Home.redirect_with_query(HashMap::from([(
-            "error".to_string(),
-            "Zone name cannot be empty!".to_string(),
-        )])));
-    }
-    // Validate that form.name is a valid DNS entry
-    if !dns_name(&form.name) {
-        return Err(Urls::Home.redirect_with_query(HashMap::from([(
-            "error".to_string(),
-            "Invalid DNS name".to_string(),
-        )])));
+    // validate the zone name is allowed on this server
+    let (pool, config) = {
+        let reader = state.read().await;
+        (reader.connpool.clone(), reader.config.clone())
+    };
+    let policy = match pool.acquire().await {
+        Ok(mut conn) => NamePolicy::load(&mut conn, &config).await,
+        Err(err) => Err(err.into()),
+    };
+    match policy.and_then(|policy| policy.check(&form.name)) {
+        Ok(()) => {}
+        Err(GoatNsError::InvalidValue(msg)) => {
+            return Err(Urls::Home.redirect_with_query(HashMap::from([("error".to_string(), msg)])));
+        }
+        Err(err) => {
+            log::error!("Failed to check zone name {:?}: {err:?}", form.name);
+            return Err(Urls::Home.redirect_with_query(HashMap::from([(
+                "error".to_string(),
+                "Error checking the zone name... please try again.".to_string(),
+            )])));
+        }
     }
 
     // check if the zone already exists
@@ -67,7 +87,7 @@ pub(crate) async fn zones_new_post(
         name: Some(form.name.clone()),
         resp: os_tx,
     };
-    if let Err(err) = state.read().await.tx.send(getzonemsg).await {
+    if let Err(err) = state.read().await.tx.send(getzonemsg.into()).await {
         log::error!("Error sending message to datastore: {:?}", err);
         return Err(Urls::Home.redirect_with_query(HashMap::from([(
             "error".to_string(),
@@ -76,16 +96,27 @@ pub(crate) async fn zones_new_post(
     };
 
     match os_rx.await {
-        Ok(Some(_)) => {
+        Ok(Ok(Some(_))) => {
             log::debug!("Zone already exists: {:?}", form.name);
             return Err(Urls::Home.redirect_with_query(HashMap::from([(
                 "error".to_string(),
                 "Zone already exists!".to_string(),
             )])));
         }
-        Ok(None) => {
+        Ok(Ok(None)) => {
             log::debug!("Zone {} doesn't exist, we can continue", form.name);
         }
+        Ok(Err(err)) => {
+            log::error!(
+                "Datastore failed to check if zone {} exists: {:?}",
+                form.name,
+                err
+            );
+            return Err(Urls::Home.redirect_with_query(HashMap::from([(
+                "error".to_string(),
+                "Error checking if zone exists... please try again.".to_string(),
+            )])));
+        }
         Err(err) => {
             log::error!("Error getting zone {}: {:?}", form.name, err);
             return Err(Urls::Home.redirect_with_query(HashMap::from([(
@@ -102,17 +133,74 @@ pub(crate) async fn zones_new_post(
         )])));
     }
 
-    let zone = FileZone {
+    let records = match form.template.as_deref().map(str::trim) {
+        None | Some("") => vec![],
+        Some(template_id) => {
+            let template_id: i64 = template_id.parse().map_err(|err| {
+                log::debug!("Invalid template id {template_id:?}: {err:?}");
+                Urls::ZonesList.redirect_with_query(HashMap::from([(
+                    "error".to_string(),
+                    "Invalid template selected!".to_string(),
+                )]))
+            })?;
+            let template = ZoneTemplate::get(&state.read().await.connpool, template_id)
+                .await
+                .map_err(|err| {
+                    log::error!("Failed to get zone template {template_id}: {err:?}");
+                    Urls::ZonesList.redirect_with_query(HashMap::from([(
+                        "error".to_string(),
+                        "Couldn't find the selected template!".to_string(),
+                    )]))
+                })?;
+            debug!(
+                "Applying template {} to new zone {}",
+                template.name, form.name
+            );
+            template.apply(&form.name)
+        }
+    };
+
+    let mut zone = FileZone {
         id: None,
         name: form.name.clone(),
-        records: vec![],
+        records,
         rname: user.email.replace("@", "."),
         serial: 0,
         refresh: Default::default(),
         retry: Default::default(),
         expire: Default::default(),
         minimum: Default::default(),
+        default_ttl: None,
+        review_required: false,
+        enabled: true,
+        public: false,
+        registration: Default::default(),
     };
+    zone.apply_defaults(&state.read().await.config);
+
+    let quota = match pool.acquire().await {
+        Ok(mut conn) => match UserQuota::check_zones(&mut conn, &user, &config.quotas, 1).await {
+            Ok(()) => {
+                UserQuota::check_records(&mut conn, &user, &config.quotas, None, zone.records.len())
+                    .await
+            }
+            Err(err) => Err(err),
+        },
+        Err(err) => Err(err.into()),
+    };
+    match quota {
+        Ok(()) => {}
+        Err(GoatNsError::QuotaExceeded(msg)) => {
+            return Err(Urls::Home.redirect_with_query(HashMap::from([("error".to_string(), msg)])));
+        }
+        Err(err) => {
+            log::error!("Failed to check quotas for user id={userid}: {err:?}");
+            return Err(Urls::Home.redirect_with_query(HashMap::from([(
+                "error".to_string(),
+                "Error creating zone!".to_string(),
+            )])));
+        }
+    }
 
     let (os_tx, os_rx) = tokio::sync::oneshot::channel();
     let msg = Command::CreateZone {
@@ -121,7 +209,7 @@ pub(crate) async fn zones_new_post(
         resp: os_tx,
     };
 
-    if let Err(err) = state.read().await.tx.send(msg).await {
+    if let Err(err) = state.read().await.tx.send(msg.into()).await {
         log::error!("Error sending message to datastore: {:?}", err);
         return Err(Urls::Home.redirect_with_query(HashMap::from([(
             "error".to_string(),
@@ -130,7 +218,7 @@ pub(crate) async fn zones_new_post(
     };
 
     match os_rx.await {
-        Ok(zone) => {
+        Ok(Ok(zone)) => {
             log::info!("Zone {} created successfully", form.name);
             if let Some(id) = zone.id {
                 log::info!("Redirecting to /ui/zones/{}", id);
@@ -140,6 +228,16 @@ pub(crate) async fn zones_new_post(
                 Err(Urls::ZonesList.redirect())
             }
         }
+        Ok(Err(GoatNsError::InvalidValue(msg))) => {
+            Err(Urls::Home.redirect_with_query(HashMap::from([("error".to_string(), msg)])))
+        }
+        Ok(Err(err)) => {
+            log::error!("Datastore failed to create zone {}: {:?}", form.name, err);
+            Err(Urls::Home.redirect_with_query(HashMap::from([(
+                "error".to_string(),
+                "Error creating zone!".to_string(),
+            )])))
+        }
         Err(err) => {
             log::error!("Error creating zone {}: {:?}", form.name, err);
             Err(Urls::Home.redirect_with_query(HashMap::from([(
@@ -149,3 +247,804 @@ pub(crate) async fn zones_new_post(
         }
     }
 }
+
+#[derive(Template)]
+#[template(path = "view_zone_changesets.html")]
+pub(crate) struct TemplateZoneChangeSets {
+    zone: FileZone,
+    changesets: Vec<ZoneChangeSet>,
+    csrftoken: String,
+    /// For showing times in the user's timezone
+    settings: UserSettings,
+    pub user_is_admin: bool,
+    message: Option<String>,
+    error: Option<String>,
+    request_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct ChangeSetsQueryString {
+    pub(crate) message: Option<String>,
+    pub(crate) error: Option<String>,
+    pub(crate) request_id: Option<String>,
+}
+
+/// The review page for pending change-sets at /ui/zones/:id/changesets
+pub(crate) async fn zone_changesets_get(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Query(query): Query<ChangeSetsQueryString>,
+) -> Result<TemplateZoneChangeSets, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let error_redirect = |msg: &str| {
+        Urls::ZonesList.redirect_with_query(HashMap::from([("error".to_string(), msg.to_string())]))
+    };
+
+    let pool = state.read().await.connpool.clone();
+    let mut conn = pool.acquire().await.map_err(|err| {
+        log::error!("Failed to get a database connection: {err:?}");
+        error_redirect("Failed to get change-sets, please try again.")
+    })?;
+
+    match user_can_review(&mut conn, &user, id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(error_redirect("You can't review changes for that zone!")),
+        Err(err) => {
+            log::error!("Failed to check ownership of zone id={id}: {err:?}");
+            return Err(error_redirect(
+                "Failed to get change-sets, please try again.",
+            ));
+        }
+    }
+
+    let zone = FileZone::get_with_txn(&mut conn, &id)
+        .await
+        .map_err(|err| {
+            log::debug!("Failed to get zone id={id}: {err:?}");
+            error_redirect("Zone not found!")
+        })?;
+    let changesets = ZoneChangeSet::get_pending_for_zone(&mut conn, id)
+        .await
+        .map_err(|err| {
+            log::error!("Failed to get change-sets for zone id={id}: {err:?}");
+            error_redirect("Failed to get change-sets, please try again.")
+        })?;
+
+    let csrftoken = store_api_csrf_token(&mut session, None)
+        .await
+        .map_err(|err| {
+            log::error!("Failed to store csrf token in session: {err:?}");
+            error_redirect("Failed to get change-sets, please try again.")
+        })?;
+
+    Ok(TemplateZoneChangeSets {
+        zone: *zone,
+        changesets,
+        csrftoken,
+        settings: user_settings(&state, &user).await,
+        user_is_admin: user.admin,
+        message: query.message,
+        error: query.error,
+        request_id: query.request_id,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ChangeSetReviewForm {
+    csrftoken: String,
+    changeset: i64,
+    /// Either "approve" or "reject"
+    action: String,
+}
+
+/// Approve or reject a change-set from the review page
+pub(crate) async fn zone_changesets_post(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Form(form): Form<ChangeSetReviewForm>,
+) -> Result<Redirect, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let page = format!("/ui/zones/{id}/changesets");
+    let redirect = |key: &str, msg: &str| Redirect::to(&format!("{page}?{key}={msg}"));
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+
+    let approve = match form.action.as_str() {
+        "approve" => true,
+        "reject" => false,
+        _ => return Err(redirect("error", "Unknown action!")),
+    };
+
+    match review_changeset(&state, &user, form.changeset, approve).await {
+        Ok(changeset) => Ok(redirect(
+            "message",
+            &format!("Change-set {} {}", form.changeset, changeset.status),
+        )),
+        Err((_, Json(err))) => Err(redirect("error", &err.message)),
+    }
+}
+
+/// How many journal entries to show on the TTL page
+const JOURNAL_ENTRIES_SHOWN: i64 = 20;
+
+#[derive(Template)]
+#[template(path = "view_zone_ttl.html")]
+pub(crate) struct TemplateZoneTtl {
+    zone: FileZone,
+    journal: Vec<ZoneJournalEntry>,
+    csrftoken: String,
+    pub user_is_admin: bool,
+    message: Option<String>,
+    error: Option<String>,
+    request_id: Option<String>,
+}
+
+/// Bulk TTL changes and the undo list at /ui/zones/:id/ttl
+pub(crate) async fn zone_ttl_get(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Query(query): Query<ChangeSetsQueryString>,
+) -> Result<TemplateZoneTtl, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let error_redirect = |msg: &str| {
+        Urls::ZonesList.redirect_with_query(HashMap::from([("error".to_string(), msg.to_string())]))
+    };
+
+    let pool = state.read().await.connpool.clone();
+    let mut conn = pool.acquire().await.map_err(|err| {
+        log::error!("Failed to get a database connection: {err:?}");
+        error_redirect("Failed to get the zone, please try again.")
+    })?;
+
+    match user_can_review(&mut conn, &user, id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(error_redirect("You can't change that zone!")),
+        Err(err) => {
+            log::error!("Failed to check ownership of zone id={id}: {err:?}");
+            return Err(error_redirect("Failed to get the zone, please try again."));
+        }
+    }
+
+    let zone = FileZone::get_with_txn(&mut conn, &id)
+        .await
+        .map_err(|err| {
+            log::debug!("Failed to get zone id={id}: {err:?}");
+            error_redirect("Zone not found!")
+        })?;
+    let journal = ZoneJournalEntry::get_for_zone(&mut conn, id, JOURNAL_ENTRIES_SHOWN)
+        .await
+        .map_err(|err| {
+            log::error!("Failed to get the journal for zone id={id}: {err:?}");
+            error_redirect("Failed to get the zone, please try again.")
+        })?;
+
+    let csrftoken = store_api_csrf_token(&mut session, None)
+        .await
+        .map_err(|err| {
+            log::error!("Failed to store csrf token in session: {err:?}");
+            error_redirect("Failed to get the zone, please try again.")
+        })?;
+
+    Ok(TemplateZoneTtl {
+        zone: *zone,
+        journal,
+        csrftoken,
+        user_is_admin: user.admin,
+        message: query.message,
+        error: query.error,
+        request_id: query.request_id,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ZoneTtlForm {
+    csrftoken: String,
+    /// Set when they're changing all the TTLs
+    #[serde(default)]
+    ttl: Option<String>,
+    /// Set when they're undoing a journal entry
+    #[serde(default)]
+    undo: Option<i64>,
+    /// Set when they're changing the default TTL, empty means the server's default
+    #[serde(default)]
+    default_ttl: Option<String>,
+}
+
+/// Set every TTL in the zone, change the default TTL, or undo an earlier change
+pub(crate) async fn zone_ttl_post(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Form(form): Form<ZoneTtlForm>,
+) -> Result<Redirect, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let page = format!("/ui/zones/{id}/ttl");
+    let redirect = |key: &str, msg: &str| Redirect::to(&format!("{page}?{key}={msg}"));
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+    let userid = user
+        .id
+        .ok_or_else(|| redirect("error", "No user ID found"))?;
+
+    let pool = state.read().await.connpool.clone();
+    let mut conn = pool.acquire().await.map_err(|err| {
+        log::error!("Failed to get a database connection: {err:?}");
+        redirect("error", "Failed to update the zone, please try again.")
+    })?;
+    match user_can_review(&mut conn, &user, id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(redirect("error", "You can't change that zone!")),
+        Err(err) => {
+            log::error!("Failed to check ownership of zone id={id}: {err:?}");
+            return Err(redirect(
+                "error",
+                "Failed to update the zone, please try again.",
+            ));
+        }
+    }
+    match SyncedZone::check_editable(&mut conn, id).await {
+        Ok(()) => {}
+        Err(GoatNsError::InvalidValue(msg)) => return Err(redirect("error", &msg)),
+        Err(err) => {
+            log::error!("Failed to check if zone id={id} is synced: {err:?}");
+            return Err(redirect(
+                "error",
+                "Failed to update the zone, please try again.",
+            ));
+        }
+    }
+
+    if let Some(default_ttl) = form.default_ttl.as_deref().map(str::trim) {
+        let policy = state.read().await.config.import_ttl.clone();
+        let default_ttl = match default_ttl {
+            "" => None,
+            ttl => match ttl.parse::<u32>() {
+                Ok(ttl) if (policy.min_ttl..=policy.max_ttl).contains(&ttl) => Some(ttl),
+                _ => {
+                    return Err(redirect(
+                        "error",
+                        &format!(
+                            "The default TTL needs to be a number from {} to {}",
+                            policy.min_ttl, policy.max_ttl
+                        ),
+                    ))
+                }
+            },
+        };
+        if let Err(err) = FileZone::set_default_ttl_with_txn(id, default_ttl, &mut conn).await {
+            log::error!("Failed to set the default TTL for zone id={id}: {err:?}");
+            return Err(redirect(
+                "error",
+                "Failed to update the zone, please try again.",
+            ));
+        }
+        return Ok(match default_ttl {
+            Some(ttl) => redirect("message", &format!("Set the default TTL to {ttl}")),
+            None => redirect(
+                "message",
+                &format!(
+                    "Records will get the server's default TTL of {}",
+                    policy.default_ttl
+                ),
+            ),
+        });
+    }
+    drop(conn);
+
+    let (os_tx, os_rx) = tokio::sync::oneshot::channel();
+    let cmd = match (form.ttl.as_deref().map(str::trim), form.undo) {
+        (_, Some(journal_id)) => Command::UndoJournalEntry {
+            zoneid: id,
+            id: journal_id,
+            resp: os_tx,
+        },
+        (Some(ttl), None) => {
+            let policy = state.read().await.config.import_ttl.clone();
+            let ttl = match ttl.parse::<u32>() {
+                Ok(ttl) if (policy.min_ttl..=policy.max_ttl).contains(&ttl) => ttl,
+                _ => {
+                    return Err(redirect(
+                        "error",
+                        &format!(
+                            "The TTL needs to be a number from {} to {}",
+                            policy.min_ttl, policy.max_ttl
+                        ),
+                    ))
+                }
+            };
+            Command::SetZoneTtls {
+                zoneid: id,
+                ttl,
+                userid,
+                resp: os_tx,
+            }
+        }
+        (None, None) => return Err(redirect("error", "Unknown action!")),
+    };
+
+    if let Err(err) = state.read().await.tx.send(cmd.into()).await {
+        log::error!("Error sending message to datastore: {err:?}");
+        return Err(redirect(
+            "error",
+            "Failed to update the zone, please try again.",
+        ));
+    }
+    match os_rx.await {
+        Ok(Ok(entry)) => match form.undo {
+            Some(_) => Ok(redirect(
+                "message",
+                &format!("Undid: {}", entry.description),
+            )),
+            None => Ok(redirect("message", &entry.description)),
+        },
+        Ok(Err(GoatNsError::InvalidValue(msg))) => Err(redirect("error", &msg)),
+        Ok(Err(GoatNsError::SqlxError(sqlx::Error::RowNotFound))) => {
+            Err(redirect("error", "That change couldn't be found!"))
+        }
+        Ok(Err(err)) => {
+            log::error!("Datastore failed to update zone id={id}: {err:?}");
+            Err(redirect(
+                "error",
+                "Failed to update the zone, please try again.",
+            ))
+        }
+        Err(err) => {
+            log::error!("Failed to get response from datastore: {err:?}");
+            Err(redirect(
+                "error",
+                "Failed to update the zone, please try again.",
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ZoneEnabledForm {
+    csrftoken: String,
+    enabled: bool,
+}
+
+/// Turn the zone on or off from /ui/zones/:id
+pub(crate) async fn zone_enabled_post(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Form(form): Form<ZoneEnabledForm>,
+) -> Result<Redirect, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let page = format!("/ui/zones/{id}");
+    let redirect = |key: &str, msg: &str| Redirect::to(&format!("{page}?{key}={msg}"));
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+
+    let pool = state.read().await.connpool.clone();
+    let mut conn = pool.acquire().await.map_err(|err| {
+        log::error!("Failed to get a database connection: {err:?}");
+        redirect("error", "Failed to update the zone, please try again.")
+    })?;
+    match user_can_review(&mut conn, &user, id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(redirect("error", "You can't change that zone!")),
+        Err(err) => {
+            log::error!("Failed to check ownership of zone id={id}: {err:?}");
+            return Err(redirect(
+                "error",
+                "Failed to update the zone, please try again.",
+            ));
+        }
+    }
+    drop(conn);
+
+    let (os_tx, os_rx) = tokio::sync::oneshot::channel();
+    let cmd = Command::SetZoneEnabled {
+        zoneid: id,
+        enabled: form.enabled,
+        resp: os_tx,
+    };
+    if let Err(err) = state.read().await.tx.send(cmd.into()).await {
+        log::error!("Error sending message to datastore: {err:?}");
+        return Err(redirect(
+            "error",
+            "Failed to update the zone, please try again.",
+        ));
+    }
+    match os_rx.await {
+        Ok(Ok(())) => match form.enabled {
+            true => Ok(redirect("message", "The zone is enabled")),
+            false => Ok(redirect(
+                "message",
+                "The zone is disabled, its records are kept but nothing gets answered",
+            )),
+        },
+        Ok(Err(err)) => {
+            log::error!("Datastore failed to update zone id={id}: {err:?}");
+            Err(redirect(
+                "error",
+                "Failed to update the zone, please try again.",
+            ))
+        }
+        Err(err) => {
+            log::error!("Failed to get response from datastore: {err:?}");
+            Err(redirect(
+                "error",
+                "Failed to update the zone, please try again.",
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ZonePublicForm {
+    csrftoken: String,
+    public: bool,
+}
+
+/// Make the zone public or not from /ui/zones/:id
+pub(crate) async fn zone_public_post(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Form(form): Form<ZonePublicForm>,
+) -> Result<Redirect, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let page = format!("/ui/zones/{id}");
+    let redirect = |key: &str, msg: &str| Redirect::to(&format!("{page}?{key}={msg}"));
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+
+    let pool = state.read().await.connpool.clone();
+    let mut conn = pool.acquire().await.map_err(|err| {
+        log::error!("Failed to get a database connection: {err:?}");
+        redirect("error", "Failed to update the zone, please try again.")
+    })?;
+    match user_can_review(&mut conn, &user, id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(redirect("error", "You can't change that zone!")),
+        Err(err) => {
+            log::error!("Failed to check ownership of zone id={id}: {err:?}");
+            return Err(redirect(
+                "error",
+                "Failed to update the zone, please try again.",
+            ));
+        }
+    }
+    match FileZone::set_public_with_txn(id, form.public, &mut conn).await {
+        Ok(()) => match form.public {
+            true => Ok(redirect(
+                "message",
+                "The zone is public, anyone can see its name servers and SOA",
+            )),
+            false => Ok(redirect("message", "The zone isn't public any more")),
+        },
+        Err(err) => {
+            log::error!("Failed to set public for zone id={id}: {err:?}");
+            Err(redirect(
+                "error",
+                "Failed to update the zone, please try again.",
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ZoneRegistrationForm {
+    csrftoken: String,
+    registrar: String,
+    /// `YYYY-MM-DD` from the date picker, empty if it's not set
+    expires: String,
+    notes: String,
+}
+
+/// Set the registrar, domain expiry and notes from /ui/zones/:id
+pub(crate) async fn zone_registration_post(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Form(form): Form<ZoneRegistrationForm>,
+) -> Result<Redirect, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let page = format!("/ui/zones/{id}");
+    let redirect = |key: &str, msg: &str| Redirect::to(&format!("{page}?{key}={msg}"));
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+
+    let expires = match form.expires.trim() {
+        "" => None,
+        expires => match chrono::NaiveDate::parse_from_str(expires, "%Y-%m-%d") {
+            Ok(val) => Some(val),
+            Err(_) => return Err(redirect("error", "The expiry date needs to be YYYY-MM-DD")),
+        },
+    };
+    let registration = ZoneRegistration {
+        registrar: Some(form.registrar),
+        expires,
+        notes: Some(form.notes),
+    }
+    .check()
+    .map_err(|err| match err {
+        GoatNsError::InvalidValue(msg) => redirect("error", &msg),
+        err => {
+            log::error!("Failed to check registration for zone id={id}: {err:?}");
+            redirect("error", "Failed to update the zone, please try again.")
+        }
+    })?;
+
+    let pool = state.read().await.connpool.clone();
+    let mut conn = pool.acquire().await.map_err(|err| {
+        log::error!("Failed to get a database connection: {err:?}");
+        redirect("error", "Failed to update the zone, please try again.")
+    })?;
+    match user_can_review(&mut conn, &user, id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(redirect("error", "You can't change that zone!")),
+        Err(err) => {
+            log::error!("Failed to check ownership of zone id={id}: {err:?}");
+            return Err(redirect(
+                "error",
+                "Failed to update the zone, please try again.",
+            ));
+        }
+    }
+    match FileZone::set_registration_with_txn(id, &registration, &mut conn).await {
+        Ok(()) => Ok(redirect("message", "Saved the registration details")),
+        Err(err) => {
+            log::error!("Failed to set registration for zone id={id}: {err:?}");
+            Err(redirect(
+                "error",
+                "Failed to update the zone, please try again.",
+            ))
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "view_zone_transfer.html")]
+pub(crate) struct TemplateZoneTransfer {
+    zone: FileZone,
+    transfer: Option<ZoneOwnershipTransfer>,
+    csrftoken: String,
+    /// For showing times in the user's timezone
+    settings: UserSettings,
+    pub user_is_admin: bool,
+    message: Option<String>,
+    error: Option<String>,
+    request_id: Option<String>,
+}
+
+/// Handing the zone to someone else at /ui/zones/:id/transfer
+pub(crate) async fn zone_transfer_get(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Query(query): Query<ChangeSetsQueryString>,
+) -> Result<TemplateZoneTransfer, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let error_redirect = |msg: &str| {
+        Urls::ZonesList.redirect_with_query(HashMap::from([("error".to_string(), msg.to_string())]))
+    };
+
+    let pool = state.read().await.connpool.clone();
+    let mut conn = pool.acquire().await.map_err(|err| {
+        log::error!("Failed to get a database connection: {err:?}");
+        error_redirect("Failed to get the zone, please try again.")
+    })?;
+
+    match user_can_review(&mut conn, &user, id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(error_redirect("You can't change that zone!")),
+        Err(err) => {
+            log::error!("Failed to check ownership of zone id={id}: {err:?}");
+            return Err(error_redirect("Failed to get the zone, please try again."));
+        }
+    }
+
+    let zone = FileZone::get_with_txn(&mut conn, &id)
+        .await
+        .map_err(|err| {
+            log::debug!("Failed to get zone id={id}: {err:?}");
+            error_redirect("Zone not found!")
+        })?;
+    let transfer = ZoneOwnershipTransfer::get_pending_for_zone(&mut conn, id)
+        .await
+        .map_err(|err| {
+            log::error!("Failed to get the ownership transfer for zone id={id}: {err:?}");
+            error_redirect("Failed to get the zone, please try again.")
+        })?;
+
+    let csrftoken = store_api_csrf_token(&mut session, None)
+        .await
+        .map_err(|err| {
+            log::error!("Failed to store csrf token in session: {err:?}");
+            error_redirect("Failed to get the zone, please try again.")
+        })?;
+
+    Ok(TemplateZoneTransfer {
+        zone: *zone,
+        transfer,
+        csrftoken,
+        settings: user_settings(&state, &user).await,
+        user_is_admin: user.admin,
+        message: query.message,
+        error: query.error,
+        request_id: query.request_id,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ZoneTransferForm {
+    csrftoken: String,
+    /// Set when they're starting a transfer
+    #[serde(default)]
+    username: Option<String>,
+    /// Set when they're cancelling a transfer
+    #[serde(default)]
+    cancel: Option<i64>,
+}
+
+/// Start or cancel a transfer from the zone's transfer page
+pub(crate) async fn zone_transfer_post(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Path(id): Path<i64>,
+    Form(form): Form<ZoneTransferForm>,
+) -> Result<Redirect, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let page = format!("/ui/zones/{id}/transfer");
+    let redirect = |key: &str, msg: &str| Redirect::to(&format!("{page}?{key}={msg}"));
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+
+    match (form.username.as_deref().map(str::trim), form.cancel) {
+        (_, Some(transfer_id)) => {
+            match resolve_transfer(
+                &state,
+                &user,
+                transfer_id,
+                OwnershipTransferStatus::Cancelled,
+            )
+            .await
+            {
+                Ok(_) => Ok(redirect("message", "Ownership transfer cancelled")),
+                Err((_, Json(err))) => Err(redirect("error", &err.message)),
+            }
+        }
+        (Some(username), None) if !username.is_empty() => {
+            match start_transfer(&state, &user, id, username).await {
+                Ok(transfer) => Ok(redirect(
+                    "message",
+                    &format!("Waiting for {} to accept the zone", transfer.to_username),
+                )),
+                Err((_, Json(err))) if err.message.is_empty() => Err(redirect(
+                    "error",
+                    "You have to own the zone to transfer it!",
+                )),
+                Err((_, Json(err))) => Err(redirect("error", &err.message)),
+            }
+        }
+        _ => Err(redirect("error", "Unknown action!")),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "view_transfers.html")]
+pub(crate) struct TemplateTransfers {
+    transfers: Vec<ZoneOwnershipTransfer>,
+    csrftoken: String,
+    /// For showing times in the user's timezone
+    settings: UserSettings,
+    pub user_is_admin: bool,
+    message: Option<String>,
+    error: Option<String>,
+    request_id: Option<String>,
+}
+
+/// The zones people want to give you, at /ui/transfers
+pub(crate) async fn transfers_get(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Query(query): Query<ChangeSetsQueryString>,
+) -> Result<TemplateTransfers, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let error_redirect = |msg: &str| {
+        Urls::ZonesList.redirect_with_query(HashMap::from([("error".to_string(), msg.to_string())]))
+    };
+    let userid = user.id.ok_or_else(|| error_redirect("No user ID found"))?;
+
+    let pool = state.read().await.connpool.clone();
+    let mut conn = pool.acquire().await.map_err(|err| {
+        log::error!("Failed to get a database connection: {err:?}");
+        error_redirect("Failed to get ownership transfers, please try again.")
+    })?;
+    let transfers = ZoneOwnershipTransfer::get_pending_for_user(&mut conn, userid)
+        .await
+        .map_err(|err| {
+            log::error!("Failed to get ownership transfers for user id={userid}: {err:?}");
+            error_redirect("Failed to get ownership transfers, please try again.")
+        })?;
+
+    let csrftoken = store_api_csrf_token(&mut session, None)
+        .await
+        .map_err(|err| {
+            log::error!("Failed to store csrf token in session: {err:?}");
+            error_redirect("Failed to get ownership transfers, please try again.")
+        })?;
+
+    Ok(TemplateTransfers {
+        transfers,
+        csrftoken,
+        settings: user_settings(&state, &user).await,
+        user_is_admin: user.admin,
+        message: query.message,
+        error: query.error,
+        request_id: query.request_id,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct TransferResponseForm {
+    csrftoken: String,
+    transfer: i64,
+    /// Either "accept" or "decline"
+    action: String,
+}
+
+/// Accept or decline a zone someone's giving you
+pub(crate) async fn transfers_post(
+    State(state): State<GoatState>,
+    mut session: Session,
+    OriginalUri(path): OriginalUri,
+    Form(form): Form<TransferResponseForm>,
+) -> Result<Redirect, Redirect> {
+    let user: User = check_logged_in(&mut session, path).await?;
+    let redirect = |key: &str, msg: &str| Redirect::to(&format!("/ui/transfers?{key}={msg}"));
+
+    if !validate_csrf_expiry(&form.csrftoken, &mut session).await {
+        log::debug!("Failed to validate CSRF expiry");
+        return Err(redirect("error", "Your session expired, please try again."));
+    }
+
+    let status = match form.action.as_str() {
+        "accept" => OwnershipTransferStatus::Accepted,
+        "decline" => OwnershipTransferStatus::Declined,
+        _ => return Err(redirect("error", "Unknown action!")),
+    };
+
+    match resolve_transfer(&state, &user, form.transfer, status).await {
+        Ok(transfer) => Ok(redirect(
+            "message",
+            &format!("Ownership of {} {}", transfer.zone, transfer.status),
+        )),
+        Err((_, Json(err))) => Err(redirect("error", &err.message)),
+    }
+}
@@ -9,7 +9,8 @@ use rand::distributions::{Alphanumeric, DistString};
 use rand_core::OsRng;
 use sha2::{Digest, Sha256};
 
-use crate::db::TokenSearchRow;
+use crate::db::{TokenSearchRow, UserAuthToken};
+use crate::web::middleware::request_id::current_request_id;
 
 /// URLs for the web interface
 pub(crate) enum Urls {
@@ -54,11 +55,17 @@ impl Urls {
         if query.is_empty() {
             return self.redirect();
         }
-        let queryparts: String = query
+        let mut queryparts: Vec<String> = query
             .into_iter()
             .map(|(key, value)| format!("{}={}", key.to_string(), value.to_string()))
-            .collect::<Vec<String>>()
-            .join("&");
+            .collect();
+        // errors get the request ID tacked on so the page can show it
+        if queryparts.iter().any(|part| part.starts_with("error=")) {
+            if let Some(request_id) = current_request_id() {
+                queryparts.push(format!("request_id={request_id}"));
+            }
+        }
+        let queryparts = queryparts.join("&");
         let url = format!("{}?{}", self.as_ref(), queryparts);
         Redirect::to(&url)
     }
@@ -116,6 +123,18 @@ pub fn create_api_token(api_cookie_secret: &[u8], lifetime: i32, userid: i64) ->
     }
 }
 
+/// Make a new secret for an existing token. It keeps its key, and gets the same lifetime it had from now.
+pub fn rotate_api_token(api_cookie_secret: &[u8], token: &UserAuthToken) -> ApiToken {
+    let lifetime = match token.expiry {
+        None => -1,
+        Some(expiry) => i32::try_from((expiry - token.issued).num_seconds()).unwrap_or(i32::MAX),
+    };
+    ApiToken {
+        token_key: token.tokenkey.clone(),
+        ..create_api_token(api_cookie_secret, lifetime, token.userid)
+    }
+}
+
 /// validate an API token matches our thingamajig
 pub fn validate_api_token(token: &TokenSearchRow, payload_token: &str) -> Result<(), String> {
     let passwordhash =
@@ -137,6 +156,7 @@ pub fn validate_api_token(token: &TokenSearchRow, payload_token: &str) -> Result
         .map_err(|e| format!("validation error: {e:?}"))
 }
 
+/// Anything that doesn't match a route, [crate::web::middleware::error_pages] turns it into the right format
 pub async fn handler_404() -> (StatusCode, &'static str) {
-    (StatusCode::NOT_FOUND, "<h1>Oh no!</h1><p>You've found a 404, try <a href='#' onclick='history.back();'>going back</a> or <a href='/'>home!</a></p>")
+    (StatusCode::NOT_FOUND, "")
 }
@@ -0,0 +1,72 @@
+//! Lets something else know what's going on, by POSTing JSON events to the `webhook_url` in the config
+
+use std::time::Duration;
+
+use serde::Serialize;
+use url::Url;
+
+use crate::delegation::DelegationProblem;
+use crate::zones::{OwnershipTransferStatus, ZoneOwnershipTransfer};
+
+/// How long we wait for the webhook to answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Things that happened which someone might want to hear about
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A zone's ownership transfer was asked for, or has been finished off
+    OwnershipTransfer {
+        /// The transfer's ID
+        transfer: Option<i64>,
+        /// The zone's name
+        zone: String,
+        /// The owner giving it away
+        from: String,
+        /// Who it's going to
+        to: String,
+        /// Where the transfer's at
+        status: OwnershipTransferStatus,
+    },
+    /// A zone's delegation problems changed, an empty list means they're all fixed
+    Delegation {
+        /// The zone's name
+        zone: String,
+        /// What's wrong now
+        problems: Vec<DelegationProblem>,
+    },
+}
+
+impl From<&ZoneOwnershipTransfer> for WebhookEvent {
+    fn from(transfer: &ZoneOwnershipTransfer) -> Self {
+        WebhookEvent::OwnershipTransfer {
+            transfer: transfer.id,
+            zone: transfer.zone.clone(),
+            from: transfer.from_username.clone(),
+            to: transfer.to_username.clone(),
+            status: transfer.status,
+        }
+    }
+}
+
+/// Sends the event in the background if there's a webhook configured. It failing gets logged, but doesn't stop
+/// anything else.
+pub fn send(url: Option<Url>, event: WebhookEvent) {
+    let url = match url {
+        Some(url) => url,
+        None => return,
+    };
+    tokio::spawn(async move {
+        let res = reqwest::Client::new()
+            .post(url.clone())
+            .timeout(WEBHOOK_TIMEOUT)
+            .json(&event)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match res {
+            Ok(_) => log::debug!("Sent {event:?} to webhook"),
+            Err(err) => log::warn!("Failed to send {event:?} to webhook {url}: {err:?}"),
+        }
+    });
+}
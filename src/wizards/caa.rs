@@ -0,0 +1,212 @@
+//! CAA records ([RFC8659](https://www.rfc-editor.org/rfc/rfc8659)), which say which certificate authorities can
+//! issue certificates for a name.
+//!
+//! The presentation format is `<flag> <tag> "<value>"`, eg `0 issue "letsencrypt.org"`.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use goat_lib::validators::dns_name;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use utoipa::ToSchema;
+
+use super::build_record;
+use crate::error::GoatNsError;
+use crate::zones::FileZoneRecord;
+
+/// The flag value with the "issuer critical" bit set
+pub const CAA_CRITICAL_FLAG: u8 = 128;
+
+/// A certificate authority and the issuer domain it looks for in CAA records
+#[derive(Clone, Copy, Debug, Serialize, ToSchema)]
+pub struct CaPreset {
+    pub name: &'static str,
+    pub issuer: &'static str,
+}
+
+/// The CAs people ask for most, taken from each CA's own documentation
+pub const CA_PRESETS: [CaPreset; 9] = [
+    CaPreset {
+        name: "Let's Encrypt",
+        issuer: "letsencrypt.org",
+    },
+    CaPreset {
+        name: "DigiCert",
+        issuer: "digicert.com",
+    },
+    CaPreset {
+        name: "Sectigo (and ZeroSSL)",
+        issuer: "sectigo.com",
+    },
+    CaPreset {
+        name: "Google Trust Services",
+        issuer: "pki.goog",
+    },
+    CaPreset {
+        name: "Amazon",
+        issuer: "amazon.com",
+    },
+    CaPreset {
+        name: "GlobalSign",
+        issuer: "globalsign.com",
+    },
+    CaPreset {
+        name: "Buypass",
+        issuer: "buypass.com",
+    },
+    CaPreset {
+        name: "SSL.com",
+        issuer: "ssl.com",
+    },
+    CaPreset {
+        name: "Entrust",
+        issuer: "entrust.net",
+    },
+];
+
+/// The property tags from RFC8659
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CaaTag {
+    /// Who can issue certificates for the name
+    Issue,
+    /// Who can issue wildcard certificates for the name
+    IssueWild,
+    /// Where CAs should report requests that broke the policy
+    Iodef,
+}
+
+impl Display for CaaTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CaaTag::Issue => "issue",
+            CaaTag::IssueWild => "issuewild",
+            CaaTag::Iodef => "iodef",
+        })
+    }
+}
+
+impl FromStr for CaaTag {
+    type Err = GoatNsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "issue" => Ok(CaaTag::Issue),
+            "issuewild" => Ok(CaaTag::IssueWild),
+            "iodef" => Ok(CaaTag::Iodef),
+            other => Err(GoatNsError::InvalidValue(format!(
+                "Unknown CAA tag {other:?}, should be issue, issuewild or iodef"
+            ))),
+        }
+    }
+}
+
+/// One CAA record, before it's turned into rdata
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+pub struct CaaRecord {
+    /// Sets the issuer critical flag, so CAs that don't understand the tag must refuse to issue
+    #[serde(default)]
+    pub critical: bool,
+    pub tag: CaaTag,
+    /// The issuer domain (optionally with `; key=value` parameters, or just `;` for "nobody") for issue and
+    /// issuewild, or a `mailto:` or `http(s)://` URL for iodef
+    pub value: String,
+}
+
+impl CaaRecord {
+    /// Makes sure the value makes sense for the tag
+    pub fn validate(&self) -> Result<(), GoatNsError> {
+        let value = self.value.trim();
+        if value.contains('"') {
+            return Err(GoatNsError::InvalidValue(
+                "CAA values can't contain double quotes".to_string(),
+            ));
+        }
+        if value.len() > 255 {
+            return Err(GoatNsError::InvalidValue(
+                "CAA values can't be longer than 255 characters".to_string(),
+            ));
+        }
+        match self.tag {
+            CaaTag::Issue | CaaTag::IssueWild => validate_issuer(value),
+            CaaTag::Iodef => validate_iodef(value),
+        }
+    }
+
+    /// The rdata for the record, eg `0 issue "letsencrypt.org"`
+    pub fn rdata(&self) -> Result<String, GoatNsError> {
+        self.validate()?;
+        let flag = match self.critical {
+            true => CAA_CRITICAL_FLAG,
+            false => 0,
+        };
+        Ok(format!("{flag} {} \"{}\"", self.tag, self.value.trim()))
+    }
+
+    /// Builds the zone record
+    pub fn to_record(
+        &self,
+        zoneid: Option<i64>,
+        name: &str,
+        ttl: u32,
+    ) -> Result<FileZoneRecord, GoatNsError> {
+        build_record(zoneid, name, "CAA", self.rdata()?, ttl)
+    }
+}
+
+/// `[issuer-domain-name] *(";" tag=value)` from RFC8659 section 4.2
+fn validate_issuer(value: &str) -> Result<(), GoatNsError> {
+    let mut parts = value.split(';');
+    let issuer = parts.next().unwrap_or_default().trim();
+    if issuer.is_empty() && !value.starts_with(';') {
+        return Err(GoatNsError::InvalidValue(
+            "Pick an issuer, or use \";\" to stop anyone issuing certificates".to_string(),
+        ));
+    }
+    if !issuer.is_empty() && !dns_name(issuer) {
+        return Err(GoatNsError::InvalidValue(format!(
+            "{issuer:?} isn't a valid issuer domain name"
+        )));
+    }
+    for parameter in parts.map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, val) = match parameter.split_once('=') {
+            Some((key, val)) => (key.trim(), val.trim()),
+            None => {
+                return Err(GoatNsError::InvalidValue(format!(
+                    "CAA parameter {parameter:?} should look like key=value"
+                )))
+            }
+        };
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(GoatNsError::InvalidValue(format!(
+                "CAA parameter name {key:?} can only have letters and numbers"
+            )));
+        }
+        if !val.chars().all(|c| c.is_ascii_graphic()) {
+            return Err(GoatNsError::InvalidValue(format!(
+                "CAA parameter value {val:?} can't have spaces or non-ASCII characters"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// iodef values are URLs, RFC8659 section 4.4 only allows mailto, http and https
+fn validate_iodef(value: &str) -> Result<(), GoatNsError> {
+    let url = Url::parse(value)
+        .map_err(|err| GoatNsError::InvalidValue(format!("{value:?} isn't a valid URL: {err}")))?;
+    match url.scheme() {
+        "mailto" => match url.path().split_once('@') {
+            Some((local, domain)) if !local.is_empty() && dns_name(domain) => Ok(()),
+            _ => Err(GoatNsError::InvalidValue(format!(
+                "{:?} isn't a valid email address",
+                url.path()
+            ))),
+        },
+        "http" | "https" if url.host_str().is_some() => Ok(()),
+        _ => Err(GoatNsError::InvalidValue(
+            "iodef values need to be a mailto:, http:// or https:// URL".to_string(),
+        )),
+    }
+}
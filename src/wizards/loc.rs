@@ -0,0 +1,112 @@
+//! LOC records ([RFC1876](https://www.rfc-editor.org/rfc/rfc1876)), from a latitude and longitude in decimal
+//! degrees rather than the degrees, minutes and seconds the presentation format wants.
+//!
+//! The UI shows where it is on a map, using a single tile from [DEFAULT_MAP_TILE_URL] (or `map_tile_url` in the
+//! config) with a marker drawn over it.
+
+use std::f64::consts::PI;
+
+use url::Url;
+
+use super::build_record;
+use crate::error::GoatNsError;
+use crate::resourcerecord::DecimalLocation;
+use crate::zones::FileZoneRecord;
+
+/// OpenStreetMap's standard tiles, see their [usage policy](https://operations.osmfoundation.org/policies/tiles/)
+pub const DEFAULT_MAP_TILE_URL: &str = "https://tile.openstreetmap.org/{z}/{x}/{y}.png";
+/// How wide and high tiles are, in pixels
+pub const MAP_TILE_SIZE: f64 = 256.0;
+/// How far in map tiles go
+const MAX_ZOOM: u8 = 17;
+/// Web mercator stops here, the poles are infinitely far away
+const MAX_LATITUDE: f64 = 85.051_128;
+/// Around the equator, in metres
+const EARTH_CIRCUMFERENCE: f64 = 40_075_016.686;
+
+/// Builds the zone record
+pub fn to_record(
+    location: &DecimalLocation,
+    zoneid: Option<i64>,
+    name: &str,
+    ttl: u32,
+) -> Result<FileZoneRecord, GoatNsError> {
+    build_record(zoneid, name, "LOC", location.to_rdata()?, ttl)
+}
+
+/// The map tile a location's on, and where in the tile it is
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapTile {
+    pub url: String,
+    pub zoom: u8,
+    /// Pixels from the left of the tile
+    pub x: u16,
+    /// Pixels from the top of the tile
+    pub y: u16,
+}
+
+/// Zoomed so the tile's a few times wider than the horizontal precision, so the uncertainty's visible
+pub fn map_zoom(horiz_pre: u32) -> u8 {
+    let wanted = f64::from(horiz_pre.max(1)) * 4.0;
+    (EARTH_CIRCUMFERENCE / wanted)
+        .log2()
+        .floor()
+        .clamp(1.0, f64::from(MAX_ZOOM)) as u8
+}
+
+/// Finds the tile for the location, `template` is a URL with `{z}`, `{x}` and `{y}` in it. Nothing if there's no
+/// template.
+pub fn map_tile(template: &str, location: &DecimalLocation) -> Option<MapTile> {
+    if template.trim().is_empty() {
+        return None;
+    }
+    let zoom = map_zoom(location.horiz_pre);
+    let tiles = f64::from(1u32 << zoom);
+    let latitude = location
+        .latitude
+        .clamp(-MAX_LATITUDE, MAX_LATITUDE)
+        .to_radians();
+    let x = ((location.longitude + 180.0) / 360.0 * tiles).clamp(0.0, tiles * (1.0 - f64::EPSILON));
+    let y = ((1.0 - (latitude.tan() + 1.0 / latitude.cos()).ln() / PI) / 2.0 * tiles)
+        .clamp(0.0, tiles * (1.0 - f64::EPSILON));
+    Some(MapTile {
+        url: template
+            .replace("{z}", &zoom.to_string())
+            .replace("{x}", &(x.floor() as u32).to_string())
+            .replace("{y}", &(y.floor() as u32).to_string()),
+        zoom,
+        x: (x.fract() * MAP_TILE_SIZE) as u16,
+        y: (y.fract() * MAP_TILE_SIZE) as u16,
+    })
+}
+
+/// Where tiles come from, for the Content Security Policy's `img-src`
+pub fn map_tile_origin(template: &str) -> Option<String> {
+    if template.trim().is_empty() {
+        return None;
+    }
+    Url::parse(template)
+        .ok()
+        .map(|url| url.origin().ascii_serialization())
+        .filter(|origin| origin != "null")
+}
+
+/// Any problems with the configured tile URL
+pub fn check_map_tile_url(template: &str) -> Vec<String> {
+    if template.trim().is_empty() {
+        return vec![];
+    }
+    let mut errors = vec![];
+    match Url::parse(template) {
+        Ok(url) if ["http", "https"].contains(&url.scheme()) => {}
+        _ => errors.push(format!(
+            "map_tile_url needs to be an http or https URL, got {template:?}"
+        )),
+    }
+    for placeholder in ["{z}", "{x}", "{y}"] {
+        if !template.contains(placeholder) {
+            errors.push(format!("map_tile_url needs {placeholder} in it"));
+        }
+    }
+    errors
+}
@@ -0,0 +1,336 @@
+//! The TXT records that make email work: SPF ([RFC7208](https://www.rfc-editor.org/rfc/rfc7208)), DKIM
+//! ([RFC6376](https://www.rfc-editor.org/rfc/rfc6376)) and DMARC ([RFC7489](https://www.rfc-editor.org/rfc/rfc7489)).
+
+use std::fmt::Display;
+
+use base64::{engine::general_purpose, Engine as _};
+use ipnet::{Ipv4Net, Ipv6Net};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{valid_target, TxtWizard};
+use crate::error::GoatNsError;
+
+/// RFC7208 section 4.6.4 - checking a policy can't take more than 10 DNS lookups
+pub const SPF_MAX_LOOKUPS: usize = 10;
+
+/// What SPF says about senders that don't match anything else
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SpfAll {
+    /// `+all`, anyone can send, which is almost never what you want
+    Pass,
+    /// `?all`, no opinion
+    Neutral,
+    /// `~all`, probably not allowed
+    #[default]
+    SoftFail,
+    /// `-all`, definitely not allowed
+    Fail,
+}
+
+impl Display for SpfAll {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SpfAll::Pass => "+all",
+            SpfAll::Neutral => "?all",
+            SpfAll::SoftFail => "~all",
+            SpfAll::Fail => "-all",
+        })
+    }
+}
+
+/// Which hosts can send mail for a name
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+pub struct SpfRecord {
+    /// The hosts in the name's MX records can send
+    #[serde(default)]
+    pub mx: bool,
+    /// The name's own A/AAAA records can send
+    #[serde(default)]
+    pub a: bool,
+    #[serde(default)]
+    #[schema(value_type = Vec<String>)]
+    pub ip4: Vec<Ipv4Net>,
+    #[serde(default)]
+    #[schema(value_type = Vec<String>)]
+    pub ip6: Vec<Ipv6Net>,
+    /// Other domains' SPF policies to include, eg `_spf.google.com`
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Use another domain's policy instead of `all`
+    #[serde(default)]
+    pub redirect: Option<String>,
+    #[serde(default)]
+    pub all: SpfAll,
+}
+
+impl SpfRecord {
+    /// How many DNS lookups checking the policy takes, not counting the ones the includes do
+    pub fn lookups(&self) -> usize {
+        usize::from(self.mx)
+            + usize::from(self.a)
+            + self.include.len()
+            + usize::from(self.redirect.is_some())
+    }
+}
+
+impl TxtWizard for SpfRecord {
+    fn default_name(&self) -> String {
+        "@".to_string()
+    }
+
+    fn txt(&self) -> Result<String, GoatNsError> {
+        let mut terms = vec!["v=spf1".to_string()];
+        if self.mx {
+            terms.push("mx".to_string());
+        }
+        if self.a {
+            terms.push("a".to_string());
+        }
+        terms.extend(self.ip4.iter().map(|net| format!("ip4:{net}")));
+        terms.extend(self.ip6.iter().map(|net| format!("ip6:{net}")));
+        for include in self.include.iter().map(|include| include.trim()) {
+            if !valid_target(include) {
+                return Err(GoatNsError::InvalidValue(format!(
+                    "{include:?} isn't a valid domain to include"
+                )));
+            }
+            terms.push(format!("include:{include}"));
+        }
+        if self.lookups() > SPF_MAX_LOOKUPS {
+            return Err(GoatNsError::InvalidValue(format!(
+                "SPF records can't need more than {SPF_MAX_LOOKUPS} DNS lookups, this one needs {}",
+                self.lookups()
+            )));
+        }
+        match self.redirect.as_deref().map(str::trim) {
+            Some(redirect) if !valid_target(redirect) => {
+                return Err(GoatNsError::InvalidValue(format!(
+                    "{redirect:?} isn't a valid domain to redirect to"
+                )))
+            }
+            // a redirect's ignored if there's an all, so it's one or the other
+            Some(redirect) => terms.push(format!("redirect={redirect}")),
+            None => terms.push(self.all.to_string()),
+        }
+        if terms.len() == 2 && self.all == SpfAll::Pass {
+            return Err(GoatNsError::InvalidValue(
+                "An SPF record that's just +all lets anyone send mail as you".to_string(),
+            ));
+        }
+        Ok(terms.join(" "))
+    }
+}
+
+/// The kinds of key DKIM supports
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DkimKeyType {
+    #[default]
+    Rsa,
+    /// From [RFC8463](https://www.rfc-editor.org/rfc/rfc8463)
+    Ed25519,
+}
+
+impl Display for DkimKeyType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DkimKeyType::Rsa => "rsa",
+            DkimKeyType::Ed25519 => "ed25519",
+        })
+    }
+}
+
+/// A DKIM public key, published at `<selector>._domainkey`
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+pub struct DkimRecord {
+    pub selector: String,
+    #[serde(default)]
+    pub key_type: DkimKeyType,
+    /// The base64 public key, PEM headers and line breaks are taken out
+    pub public_key: String,
+    /// Tells verifiers the domain's still testing DKIM
+    #[serde(default)]
+    pub testing: bool,
+}
+
+impl DkimRecord {
+    /// The key without any PEM armour or whitespace
+    pub fn key(&self) -> String {
+        self.public_key
+            .lines()
+            .filter(|line| !line.trim().starts_with("-----"))
+            .flat_map(|line| line.split_whitespace())
+            .collect()
+    }
+}
+
+impl TxtWizard for DkimRecord {
+    fn default_name(&self) -> String {
+        format!("{}._domainkey", self.selector.trim())
+    }
+
+    fn txt(&self) -> Result<String, GoatNsError> {
+        let selector = self.selector.trim();
+        if selector.is_empty()
+            || !selector.split('.').all(|label| {
+                !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            })
+        {
+            return Err(GoatNsError::InvalidValue(format!(
+                "{selector:?} isn't a valid DKIM selector"
+            )));
+        }
+        let key = self.key();
+        let decoded = general_purpose::STANDARD.decode(&key).map_err(|err| {
+            GoatNsError::InvalidValue(format!("The public key isn't valid base64: {err}"))
+        })?;
+        match self.key_type {
+            // RFC8301 says verifiers have to ignore RSA keys under 1024 bits, and a DER public key that size is
+            // at least 140 bytes
+            DkimKeyType::Rsa if decoded.len() < 140 => {
+                return Err(GoatNsError::InvalidValue(
+                    "That RSA key is too short, DKIM needs at least 1024 bits".to_string(),
+                ))
+            }
+            DkimKeyType::Ed25519 if decoded.len() != 32 => {
+                return Err(GoatNsError::InvalidValue(
+                    "Ed25519 DKIM keys are 32 bytes".to_string(),
+                ))
+            }
+            _ => {}
+        }
+        let mut tags = vec![
+            "v=DKIM1".to_string(),
+            format!("k={}", self.key_type),
+            format!("p={key}"),
+        ];
+        if self.testing {
+            tags.insert(2, "t=y".to_string());
+        }
+        Ok(tags.join("; "))
+    }
+}
+
+/// What receivers should do with mail that fails DMARC
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DmarcPolicy {
+    /// Just send reports, which is where everyone should start
+    #[default]
+    None,
+    Quarantine,
+    Reject,
+}
+
+impl Display for DmarcPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DmarcPolicy::None => "none",
+            DmarcPolicy::Quarantine => "quarantine",
+            DmarcPolicy::Reject => "reject",
+        })
+    }
+}
+
+/// How closely the From domain has to match the SPF or DKIM domain
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DmarcAlignment {
+    /// Subdomains count
+    Relaxed,
+    /// It has to be the same domain
+    Strict,
+}
+
+impl Display for DmarcAlignment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DmarcAlignment::Relaxed => "r",
+            DmarcAlignment::Strict => "s",
+        })
+    }
+}
+
+/// A DMARC policy, published at `_dmarc`
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+pub struct DmarcRecord {
+    #[serde(default)]
+    pub policy: DmarcPolicy,
+    /// The policy for subdomains, if it's different
+    #[serde(default)]
+    pub subdomain_policy: Option<DmarcPolicy>,
+    /// The percentage of failing mail the policy applies to
+    #[serde(default)]
+    pub pct: Option<u8>,
+    /// Where aggregate reports go, either email addresses or mailto: URIs
+    #[serde(default)]
+    pub rua: Vec<String>,
+    /// Where failure reports go, either email addresses or mailto: URIs
+    #[serde(default)]
+    pub ruf: Vec<String>,
+    #[serde(default)]
+    pub adkim: Option<DmarcAlignment>,
+    #[serde(default)]
+    pub aspf: Option<DmarcAlignment>,
+}
+
+/// Turns report addresses into a list of mailto: URIs
+fn dmarc_uris(addresses: &[String]) -> Result<String, GoatNsError> {
+    let mut uris = vec![];
+    for address in addresses.iter().map(|address| address.trim()) {
+        let email = address.strip_prefix("mailto:").unwrap_or(address);
+        match email.split_once('@') {
+            Some((local, domain))
+                if !local.is_empty()
+                    && valid_target(domain)
+                    && !email.contains([',', ';', ' ', '!']) =>
+            {
+                uris.push(format!("mailto:{email}"))
+            }
+            _ => {
+                return Err(GoatNsError::InvalidValue(format!(
+                    "{address:?} isn't a valid report address"
+                )))
+            }
+        }
+    }
+    Ok(uris.join(","))
+}
+
+impl TxtWizard for DmarcRecord {
+    fn default_name(&self) -> String {
+        "_dmarc".to_string()
+    }
+
+    fn txt(&self) -> Result<String, GoatNsError> {
+        let mut tags = vec!["v=DMARC1".to_string(), format!("p={}", self.policy)];
+        if let Some(policy) = self.subdomain_policy {
+            tags.push(format!("sp={policy}"));
+        }
+        match self.pct {
+            Some(pct) if pct > 100 => {
+                return Err(GoatNsError::InvalidValue(
+                    "pct is a percentage, it has to be from 0 to 100".to_string(),
+                ))
+            }
+            // 100 is the default
+            Some(pct) if pct < 100 => tags.push(format!("pct={pct}")),
+            _ => {}
+        }
+        if !self.rua.is_empty() {
+            tags.push(format!("rua={}", dmarc_uris(&self.rua)?));
+        }
+        if !self.ruf.is_empty() {
+            tags.push(format!("ruf={}", dmarc_uris(&self.ruf)?));
+        }
+        if let Some(adkim) = self.adkim {
+            tags.push(format!("adkim={adkim}"));
+        }
+        if let Some(aspf) = self.aspf {
+            tags.push(format!("aspf={aspf}"));
+        }
+        Ok(tags.join("; "))
+    }
+}
@@ -0,0 +1,83 @@
+//! Builders for the record types that are fiddly to write by hand. Each one takes a few choices, checks them, and
+//! hands back the rdata string that'd go in a [crate::zones::FileZoneRecord].
+//!
+//! The API exposes these under `/api/wizard`, and the UI has a page per zone for each of them.
+
+use crate::enums::RecordClass;
+use crate::error::GoatNsError;
+use crate::resourcerecord::{parse_txt_rdata, DNSCharString, InternalResourceRecord};
+use crate::zones::FileZoneRecord;
+
+pub mod caa;
+pub mod loc;
+pub mod mail;
+
+/// A wizard that builds a TXT record, like the ones in [mail]
+pub trait TxtWizard {
+    /// Where the record goes if nobody says otherwise, relative to the zone
+    fn default_name(&self) -> String;
+
+    /// Checks everything and builds the TXT data
+    fn txt(&self) -> Result<String, GoatNsError>;
+
+    /// Builds the zone record, `name` falls back to [TxtWizard::default_name]
+    fn to_record(
+        &self,
+        zoneid: Option<i64>,
+        name: Option<&str>,
+        ttl: u32,
+    ) -> Result<FileZoneRecord, GoatNsError> {
+        let name = match name.map(str::trim) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => self.default_name(),
+        };
+        build_record(zoneid, &name, "TXT", self.txt()?, ttl)
+    }
+}
+
+/// The `<character-string>`s the TXT data gets sent as, anything over 255 bytes is split up
+pub fn txt_strings(txt: &str) -> Vec<String> {
+    parse_txt_rdata(txt)
+        .iter()
+        .flat_map(DNSCharString::chunks)
+        .map(|chunk| String::from_utf8_lossy(chunk).to_string())
+        .collect()
+}
+
+/// Builds a record, checking it'll parse the same way records loaded from the database do
+pub(crate) fn build_record(
+    zoneid: Option<i64>,
+    name: &str,
+    rrtype: &str,
+    rdata: String,
+    ttl: u32,
+) -> Result<FileZoneRecord, GoatNsError> {
+    let record = FileZoneRecord {
+        id: None,
+        zoneid,
+        name: name.to_string(),
+        rrtype: rrtype.to_string(),
+        class: RecordClass::Internet,
+        rdata,
+        ttl,
+        comment: None,
+        tags: Default::default(),
+        expires_at: None,
+    };
+    InternalResourceRecord::try_from(record.clone())?;
+    Ok(record)
+}
+
+/// Checks a hostname in a record's value, unlike zone names these can have underscores (eg `_spf.example.com`)
+pub(crate) fn valid_target(name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    name.contains('.')
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
@@ -0,0 +1,74 @@
+//! Tells whoever's watching a zone when it changes, so the zone page can keep up when someone else is editing it.
+//! See `GET /api/zone/:id/events`.
+//!
+//! Writers [publish] once their transaction's committed (the API and web UI do it with `zone_changed`), and everyone
+//! who's [subscribe]d gets every event, it's up to them to pick out the zones they care about. Nothing's kept, so if nobody's listening the events go nowhere.
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::zones::FileZoneRecord;
+
+/// How many events can be waiting for a slow listener before it starts missing them
+const CHANNEL_SIZE: usize = 256;
+
+/// Something changed in a zone
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ZoneEvent {
+    /// A record was added, `serial` is the zone's serial afterwards
+    RecordAdded {
+        zoneid: i64,
+        serial: u32,
+        record: FileZoneRecord,
+    },
+    /// A record was changed
+    RecordUpdated {
+        zoneid: i64,
+        serial: u32,
+        record: FileZoneRecord,
+    },
+    /// A record was deleted
+    RecordDeleted { zoneid: i64, serial: u32, id: i64 },
+    /// The zone changed in some other way, or lots of records did at once, so it's best to look at all of it again
+    SerialBumped { zoneid: i64, serial: u32 },
+}
+
+impl ZoneEvent {
+    /// The zone it happened to
+    pub fn zoneid(&self) -> i64 {
+        match self {
+            ZoneEvent::RecordAdded { zoneid, .. }
+            | ZoneEvent::RecordUpdated { zoneid, .. }
+            | ZoneEvent::RecordDeleted { zoneid, .. }
+            | ZoneEvent::SerialBumped { zoneid, .. } => *zoneid,
+        }
+    }
+
+    /// What it's called, the same as the `event` field in the JSON
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ZoneEvent::RecordAdded { .. } => "record_added",
+            ZoneEvent::RecordUpdated { .. } => "record_updated",
+            ZoneEvent::RecordDeleted { .. } => "record_deleted",
+            ZoneEvent::SerialBumped { .. } => "serial_bumped",
+        }
+    }
+}
+
+lazy_static! {
+    static ref EVENTS: broadcast::Sender<ZoneEvent> = broadcast::channel(CHANNEL_SIZE).0;
+}
+
+/// Let everyone listening know, call it after the change is committed
+pub fn publish(event: ZoneEvent) {
+    // it's only an error if nobody's listening, which is fine
+    if EVENTS.send(event).is_err() {
+        log::trace!("Nobody's listening for zone events");
+    }
+}
+
+/// Start hearing about changes, to every zone
+pub fn subscribe() -> broadcast::Receiver<ZoneEvent> {
+    EVENTS.subscribe()
+}
@@ -0,0 +1,334 @@
+//! The JSON format zones are exported in, which is versioned so backups keep working when GoatNS's insides change.
+//!
+//! Schema 1 is `{"schema": 1, "zones": [...]}`, where the zones and records only have the fields that describe them -
+//! there's no database IDs. Files from before there was a schema (a zone on its own, or a list of them) are still
+//! read, and both get checked the same way so mistakes are pointed out with where they are.
+//!
+//! Backups of the whole server are the same thing with an `"owners"` key as well, which maps zone names to the
+//! usernames of the people who own them.
+
+use std::collections::BTreeMap;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::enums::RecordClass;
+use crate::error::GoatNsError;
+use crate::generic::parse_type;
+use crate::zones::{
+    default_record_class, default_record_name, enabled_default, rname_default, FileZone,
+    FileZoneRecord, ZoneRegistration,
+};
+
+/// The schema version that's written out
+pub const ZONE_SCHEMA_VERSION: u64 = 1;
+
+/// A zone in schema 1
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ZoneV1 {
+    pub name: String,
+    #[serde(default = "rname_default")]
+    pub rname: String,
+    #[serde(default)]
+    pub serial: u32,
+    #[serde(default)]
+    pub refresh: u32,
+    #[serde(default)]
+    pub retry: u32,
+    #[serde(default)]
+    pub expire: u32,
+    #[serde(default)]
+    pub minimum: u32,
+    /// What records without a TTL get
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_ttl: Option<u32>,
+    #[serde(default)]
+    pub review_required: bool,
+    /// Only written out when it's off
+    #[serde(default = "enabled_default", skip_serializing_if = "is_true")]
+    pub enabled: bool,
+    /// Only written out when it's on
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub public: bool,
+    /// Registrar, domain expiry and notes, only written out when there's something in it
+    #[serde(default, skip_serializing_if = "ZoneRegistration::is_empty")]
+    pub registration: ZoneRegistration,
+    #[serde(default)]
+    pub records: Vec<RecordV1>,
+}
+
+/// A record in schema 1
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RecordV1 {
+    /// Relative to the zone, `@` is the apex
+    #[serde(default = "default_record_name")]
+    pub name: String,
+    pub rrtype: String,
+    #[serde(default = "default_record_class")]
+    pub class: RecordClass,
+    pub rdata: String,
+    /// 0 means the import default
+    #[serde(default)]
+    pub ttl: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A whole schema 1 file
+#[derive(Clone, Debug, Serialize)]
+struct ZoneExportV1 {
+    schema: u64,
+    zones: Vec<ZoneV1>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    owners: BTreeMap<String, Vec<String>>,
+}
+
+/// Every zone, and who owns them
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ZoneBackup {
+    pub zones: Vec<FileZone>,
+    /// Zone name -> the usernames of its owners
+    pub owners: BTreeMap<String, Vec<String>>,
+}
+
+impl From<&FileZone> for ZoneV1 {
+    fn from(zone: &FileZone) -> Self {
+        ZoneV1 {
+            name: zone.name.clone(),
+            rname: zone.rname.clone(),
+            serial: zone.serial,
+            refresh: zone.refresh,
+            retry: zone.retry,
+            expire: zone.expire,
+            minimum: zone.minimum,
+            default_ttl: zone.default_ttl,
+            review_required: zone.review_required,
+            enabled: zone.enabled,
+            public: zone.public,
+            registration: zone.registration.clone(),
+            records: zone
+                .records
+                .iter()
+                .map(|record| RecordV1 {
+                    name: record.name.clone(),
+                    rrtype: record.rrtype.clone(),
+                    class: record.class,
+                    rdata: record.rdata.clone(),
+                    ttl: record.ttl,
+                    comment: record.comment.clone(),
+                    tags: record.tags.clone(),
+                    expires_at: record.expires_at,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<ZoneV1> for FileZone {
+    fn from(zone: ZoneV1) -> Self {
+        FileZone {
+            id: None,
+            name: zone.name,
+            rname: zone.rname,
+            serial: zone.serial,
+            refresh: zone.refresh,
+            retry: zone.retry,
+            expire: zone.expire,
+            minimum: zone.minimum,
+            default_ttl: zone.default_ttl,
+            review_required: zone.review_required,
+            enabled: zone.enabled,
+            public: zone.public,
+            registration: zone.registration,
+            records: zone
+                .records
+                .into_iter()
+                .map(|record| FileZoneRecord {
+                    id: None,
+                    zoneid: None,
+                    name: record.name,
+                    rrtype: record.rrtype,
+                    class: record.class,
+                    rdata: record.rdata,
+                    ttl: record.ttl,
+                    comment: record.comment,
+                    tags: record.tags,
+                    expires_at: record.expires_at,
+                })
+                .collect(),
+        }
+    }
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn invalid(path: &str, message: &str) -> GoatNsError {
+    GoatNsError::InvalidValue(format!("Invalid zone file, {path}: {message}"))
+}
+
+/// Deserialize part of the file, with where it is in the errors
+fn from_value<T: DeserializeOwned>(value: Value, path: &str) -> Result<T, GoatNsError> {
+    serde_json::from_value(value).map_err(|err| invalid(path, &err.to_string()))
+}
+
+/// Read a zone, one record at a time so errors can say which record's wrong
+fn read_zone_v1(mut value: Value, path: &str) -> Result<FileZone, GoatNsError> {
+    let records = match value
+        .as_object_mut()
+        .and_then(|zone| zone.remove("records"))
+    {
+        Some(Value::Array(records)) => records,
+        Some(_) => return Err(invalid(path, "records needs to be a list")),
+        None => vec![],
+    };
+    let mut zone: ZoneV1 = from_value(value, path)?;
+    for (index, record) in records.into_iter().enumerate() {
+        zone.records
+            .push(from_value(record, &format!("{path}.records[{index}]"))?);
+    }
+    Ok(zone.into())
+}
+
+/// The checks serde can't do, which apply whichever schema the zone came from
+fn validate_zone(zone: &FileZone, path: &str) -> Result<(), GoatNsError> {
+    if zone.name.trim().is_empty() {
+        return Err(invalid(path, "the zone needs a name"));
+    }
+    if let Err(err) = zone.registration.clone().check() {
+        return Err(invalid(
+            &format!("{path}.registration"),
+            &format!("{err:?}"),
+        ));
+    }
+    for (index, record) in zone.records.iter().enumerate() {
+        let path = format!("{path}.records[{index}]");
+        if parse_type(&record.rrtype).is_none() {
+            return Err(invalid(
+                &path,
+                &format!("unknown record type {:?}", record.rrtype),
+            ));
+        }
+        if record.class == RecordClass::InvalidType {
+            return Err(invalid(&path, "unknown record class"));
+        }
+        if record.rdata.trim().is_empty() {
+            return Err(invalid(&path, "the rdata's empty"));
+        }
+    }
+    Ok(())
+}
+
+/// Read zones from JSON (or JSON5) in any schema we know, checking them on the way
+pub fn zones_from_json(input: &str) -> Result<Vec<FileZone>, GoatNsError> {
+    backup_from_json(input).map(|backup| backup.zones)
+}
+
+/// Read zones and their owners from JSON, older files just don't have any owners
+pub fn backup_from_json(input: &str) -> Result<ZoneBackup, GoatNsError> {
+    let value: Value = json5::from_str(input)
+        .map_err(|err| GoatNsError::FileError(format!("Failed to read JSON file: {err}")))?;
+    let mut owners = BTreeMap::new();
+    let zones: Vec<(FileZone, String)> = match value {
+        Value::Object(mut object) => match object.remove("schema") {
+            Some(schema) => {
+                match schema.as_u64() {
+                    Some(ZONE_SCHEMA_VERSION) => {}
+                    Some(version) if version > ZONE_SCHEMA_VERSION => {
+                        return Err(invalid(
+                            "schema",
+                            &format!("version {version} is newer than this version of GoatNS can read ({ZONE_SCHEMA_VERSION})"),
+                        ))
+                    }
+                    _ => return Err(invalid("schema", &format!("unknown version {schema}"))),
+                }
+                let zones = match object.remove("zones") {
+                    Some(Value::Array(zones)) => zones,
+                    _ => return Err(invalid("zones", "needs to be a list of zones")),
+                };
+                if let Some(value) = object.remove("owners") {
+                    owners = from_value(value, "owners")?;
+                }
+                if let Some(field) = object.keys().next() {
+                    return Err(invalid(field, "unknown field"));
+                }
+                zones
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, zone)| {
+                        let path = format!("zones[{index}]");
+                        read_zone_v1(zone, &path).map(|zone| (zone, path))
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            // from before there was a schema, a zone on its own
+            None => vec![(
+                from_value(Value::Object(object), "zone")?,
+                "zone".to_string(),
+            )],
+        },
+        // also from before there was a schema, a list of zones
+        Value::Array(zones) => zones
+            .into_iter()
+            .enumerate()
+            .map(|(index, zone)| {
+                let path = format!("[{index}]");
+                from_value(zone, &path).map(|zone| (zone, path))
+            })
+            .collect::<Result<_, _>>()?,
+        _ => {
+            return Err(invalid(
+                "the top level",
+                "expected an object with a schema, a zone or a list of zones",
+            ))
+        }
+    };
+    for (zone, path) in zones.iter() {
+        validate_zone(zone, path)?;
+    }
+    let zones: Vec<FileZone> = zones.into_iter().map(|(zone, _)| zone).collect();
+    if let Some(name) = owners
+        .keys()
+        .find(|name| !zones.iter().any(|zone| &zone.name == *name))
+    {
+        return Err(invalid(
+            &format!("owners.{name}"),
+            "there's no zone with that name",
+        ));
+    }
+    Ok(ZoneBackup { zones, owners })
+}
+
+/// Write zones out in the current schema
+pub fn zones_to_json(zones: &[FileZone]) -> Result<String, GoatNsError> {
+    write_json(zones, BTreeMap::new())
+}
+
+/// Write zones and their owners out in the current schema
+pub fn backup_to_json(backup: &ZoneBackup) -> Result<String, GoatNsError> {
+    write_json(&backup.zones, backup.owners.clone())
+}
+
+fn write_json(
+    zones: &[FileZone],
+    owners: BTreeMap<String, Vec<String>>,
+) -> Result<String, GoatNsError> {
+    serde_json::to_string_pretty(&ZoneExportV1 {
+        schema: ZONE_SCHEMA_VERSION,
+        zones: zones.iter().map(ZoneV1::from).collect(),
+        owners,
+    })
+    .map_err(|err| GoatNsError::InvalidValue(format!("Failed to serialize zones: {err:?}")))
+}
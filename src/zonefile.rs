@@ -0,0 +1,975 @@
+//! BIND's zone file syntax.
+//!
+//! [tokenize] splits a file up into entries (one per record or directive, however many lines the parentheses spread
+//! it over), and [parse_records] turns those into records. `$GENERATE` turns one line into a run of records - eg
+//! `$GENERATE 1-254 $ PTR host-$.example.com.` for a reverse zone, and [read_with_includes] pulls in `$INCLUDE`s before
+//! any of that happens.
+
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::enums::RecordClass;
+use crate::error::GoatNsError;
+use crate::generic::{parse_type, type_name};
+use crate::interop::relative_name;
+use crate::zones::FileZoneRecord;
+
+/// The most records a single `$GENERATE` can make, so a typo in the range can't fill the database
+pub const MAX_GENERATE_RECORDS: u64 = 65536;
+/// How deep `$INCLUDE`s can go, counting from the file that's being read
+pub const MAX_INCLUDE_DEPTH: usize = 8;
+/// Which fields of the rdata are names for each type, so relative ones can be made absolute
+const RDATA_NAME_FIELDS: &[(&str, &[usize])] = &[
+    ("AFSDB", &[1]),
+    ("ALIAS", &[0]),
+    ("CNAME", &[0]),
+    ("DNAME", &[0]),
+    ("FORWARD", &[0]),
+    ("KX", &[1]),
+    ("MX", &[1]),
+    ("NAPTR", &[5]),
+    ("NS", &[0]),
+    ("PTR", &[0]),
+    ("RP", &[0, 1]),
+    ("RT", &[1]),
+    ("SOA", &[0, 1]),
+    ("SRV", &[3]),
+];
+
+/// What's wrong with a zone file, and the line it's wrong on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZoneFileError {
+    /// A quoted string that's still open at the end of the line
+    UnterminatedQuote {
+        line: usize,
+    },
+    /// A `(` that's never closed, or a `)` that was never opened
+    UnbalancedParentheses {
+        line: usize,
+    },
+    /// A record that starts with whitespace when there's no previous owner to use
+    NoOwner {
+        line: usize,
+    },
+    /// A record without a type, or with one we don't know
+    UnknownType {
+        line: usize,
+        rrtype: String,
+    },
+    /// A record with nothing after the type
+    NoRdata {
+        line: usize,
+    },
+    InvalidTtl {
+        line: usize,
+        ttl: String,
+    },
+    /// Rdata that doesn't make sense for the type, which is only checked for SOAs here
+    InvalidRdata {
+        line: usize,
+        message: String,
+    },
+    /// A name that isn't in the zone that's being read
+    OutsideZone {
+        line: usize,
+        name: String,
+    },
+    UnknownDirective {
+        line: usize,
+        directive: String,
+    },
+    /// A directive we know, but with the wrong things after it
+    InvalidDirective {
+        line: usize,
+        message: String,
+    },
+}
+
+impl Display for ZoneFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ZoneFileError::UnterminatedQuote { line } => {
+                write!(f, "line {line}: quoted string isn't closed")
+            }
+            ZoneFileError::UnbalancedParentheses { line } => {
+                write!(f, "line {line}: parentheses don't match up")
+            }
+            ZoneFileError::NoOwner { line } => {
+                write!(f, "line {line}: record has no owner name")
+            }
+            ZoneFileError::UnknownType { line, rrtype } => {
+                write!(f, "line {line}: unknown record type {rrtype:?}")
+            }
+            ZoneFileError::NoRdata { line } => write!(f, "line {line}: record has no rdata"),
+            ZoneFileError::InvalidTtl { line, ttl } => {
+                write!(f, "line {line}: invalid TTL {ttl:?}")
+            }
+            ZoneFileError::InvalidRdata { line, message } => write!(f, "line {line}: {message}"),
+            ZoneFileError::OutsideZone { line, name } => {
+                write!(f, "line {line}: {name} isn't in the zone")
+            }
+            ZoneFileError::UnknownDirective { line, directive } => {
+                write!(f, "line {line}: unknown directive {directive}")
+            }
+            ZoneFileError::InvalidDirective { line, message } => {
+                write!(f, "line {line}: {message}")
+            }
+        }
+    }
+}
+
+impl From<ZoneFileError> for GoatNsError {
+    fn from(error: ZoneFileError) -> Self {
+        GoatNsError::InvalidValue(format!("Failed to parse zone file, {error}"))
+    }
+}
+
+fn generate_error(line: &str, message: &str) -> GoatNsError {
+    GoatNsError::InvalidValue(format!("Invalid $GENERATE {line:?}: {message}"))
+}
+
+/// Make a name from a zone file fully qualified, without the trailing dot. `@` is the origin, names ending in a dot are
+/// already fully qualified and everything else is relative to the origin.
+pub fn absolute_name(name: &str, origin: &str) -> String {
+    let origin = origin.trim_end_matches('.');
+    if name == "@" {
+        return origin.to_string();
+    }
+    if let Some(fqdn) = name.strip_suffix('.') {
+        return fqdn.to_string();
+    }
+    match origin.is_empty() {
+        true => name.to_string(),
+        false => format!("{name}.{origin}"),
+    }
+}
+
+/// Change the names in a record's rdata, for the types that have names in them. Anything that doesn't look like the
+/// type's usual rdata is left alone, for the record's own checks to complain about.
+fn map_rdata_names<F: Fn(&str) -> String>(rrtype: &str, rdata: &str, map_name: F) -> String {
+    let Some((_, name_fields)) = RDATA_NAME_FIELDS
+        .iter()
+        .find(|(name_type, _)| name_type.eq_ignore_ascii_case(rrtype))
+    else {
+        return rdata.to_string();
+    };
+    let mut fields = rdata_fields(rdata);
+    if name_fields.iter().any(|index| *index >= fields.len()) {
+        return rdata.to_string();
+    }
+    for index in name_fields.iter() {
+        // the root's the only name that's left with its dot, since there's nothing else to leave
+        if fields[*index] != "." {
+            fields[*index] = map_name(&fields[*index]);
+        }
+    }
+    fields.join(" ")
+}
+
+/// Make the names in a record's rdata fully qualified (see [absolute_name]), the way GoatNS stores them
+pub fn absolute_rdata(rrtype: &str, rdata: &str, origin: &str) -> String {
+    map_rdata_names(rrtype, rdata, |name| absolute_name(name, origin))
+}
+
+/// Put the trailing dots back on the names in stored rdata, for writing out to a zone file
+pub fn qualified_rdata(rrtype: &str, rdata: &str) -> String {
+    map_rdata_names(rrtype, rdata, |name| {
+        format!("{}.", name.trim_end_matches('.'))
+    })
+}
+
+/// Split rdata up on whitespace, keeping quoted strings (quotes and all) in one piece
+fn rdata_fields(rdata: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in rdata.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    fields.push(std::mem::take(&mut current));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        fields.push(current);
+    }
+    fields
+}
+
+/// The next whitespace-separated token and what's after it
+fn next_token(input: &str) -> Option<(&str, &str)> {
+    let input = input.trim_start();
+    if input.is_empty() {
+        return None;
+    }
+    match input.split_once(char::is_whitespace) {
+        Some((token, rest)) => Some((token, rest.trim_start())),
+        None => Some((input, "")),
+    }
+}
+
+/// `start-stop` or `start-stop/step`, returns the start, stop and step
+pub fn parse_generate_range(range: &str) -> Result<(u64, u64, u64), GoatNsError> {
+    let invalid =
+        |message: &str| GoatNsError::InvalidValue(format!("Invalid range {range:?}: {message}"));
+    let (range_part, step) = match range.split_once('/') {
+        Some((range_part, step)) => (
+            range_part,
+            step.parse::<u64>()
+                .map_err(|_| invalid("the step isn't a number"))?,
+        ),
+        None => (range, 1),
+    };
+    let (start, stop) = range_part
+        .split_once('-')
+        .ok_or_else(|| invalid("it needs to be start-stop"))?;
+    let start: u64 = start
+        .parse()
+        .map_err(|_| invalid("the start isn't a number"))?;
+    let stop: u64 = stop
+        .parse()
+        .map_err(|_| invalid("the stop isn't a number"))?;
+    if step == 0 {
+        return Err(invalid("the step can't be 0"));
+    }
+    if start > stop {
+        return Err(invalid("the start is after the stop"));
+    }
+    if (stop - start) / step + 1 > MAX_GENERATE_RECORDS {
+        return Err(invalid(&format!(
+            "it'd make more than {MAX_GENERATE_RECORDS} records"
+        )));
+    }
+    Ok((start, stop, step))
+}
+
+/// A value in BIND's nibble format, eg 0x1a is `a.1`, padded with zeroes until it's at least `width` characters
+fn nibbles(value: u64, width: usize, uppercase: bool) -> String {
+    let hex = match uppercase {
+        true => format!("{value:X}"),
+        false => format!("{value:x}"),
+    };
+    // each nibble after the first adds a dot
+    let digits = hex.len().max(width.div_ceil(2));
+    format!("{hex:0>digits$}")
+        .chars()
+        .rev()
+        .map(String::from)
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/// Fill in the `$`s in a template for one iteration. `${offset,width,base}` adds to the value and formats it, where the
+/// base is one of `d`, `o`, `x`, `X`, `n` or `N`, and `\$` is a literal `$`
+pub fn generate_substitute(template: &str, iteration: u64) -> Result<String, GoatNsError> {
+    let invalid = |message: &str| {
+        GoatNsError::InvalidValue(format!(
+            "Invalid $GENERATE template {template:?}: {message}"
+        ))
+    };
+    let mut res = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'$') => {
+                res.push('$');
+                chars.next();
+            }
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let mut modifier = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => modifier.push(c),
+                        None => return Err(invalid("there's a ${ without a }")),
+                    }
+                }
+                let mut fields = modifier.split(',');
+                let offset: i64 = match fields.next().map(str::trim) {
+                    Some("") | None => 0,
+                    Some(offset) => offset
+                        .parse()
+                        .map_err(|_| invalid("the offset isn't a number"))?,
+                };
+                let width: usize = match fields.next().map(str::trim) {
+                    Some("") | None => 0,
+                    Some(width) => width
+                        .parse()
+                        .map_err(|_| invalid("the width isn't a number"))?,
+                };
+                let base = fields.next().map(str::trim).unwrap_or("d");
+                if fields.next().is_some() {
+                    return Err(invalid("modifiers are ${offset,width,base}"));
+                }
+                let value = i64::try_from(iteration)
+                    .ok()
+                    .and_then(|iteration| iteration.checked_add(offset))
+                    .and_then(|value| u64::try_from(value).ok())
+                    .ok_or_else(|| invalid("the offset takes the value below zero"))?;
+                match base {
+                    "d" | "" => res.push_str(&format!("{value:0width$}")),
+                    "o" => res.push_str(&format!("{value:0width$o}")),
+                    "x" => res.push_str(&format!("{value:0width$x}")),
+                    "X" => res.push_str(&format!("{value:0width$X}")),
+                    "n" => res.push_str(&nibbles(value, width, false)),
+                    "N" => res.push_str(&nibbles(value, width, true)),
+                    _ => return Err(invalid(&format!("unknown base {base:?}"))),
+                }
+            }
+            '$' => res.push_str(&iteration.to_string()),
+            c => res.push(c),
+        }
+    }
+    Ok(res)
+}
+
+/// Expand a `$GENERATE range lhs [ttl] [class] type rhs` line into the records it stands for. Relative names in it are
+/// relative to `origin` (ie the current `$ORIGIN`), and the records' names end up relative to the zone. The TTL's 0 (the
+/// import default) if it's not given.
+pub fn expand_generate(
+    line: &str,
+    origin: &str,
+    zone_name: &str,
+) -> Result<Vec<FileZoneRecord>, GoatNsError> {
+    let zone_name = zone_name.trim_end_matches('.');
+    let (directive, rest) = next_token(line).ok_or_else(|| generate_error(line, "it's empty"))?;
+    if !directive.eq_ignore_ascii_case("$GENERATE") {
+        return Err(generate_error(line, "it doesn't start with $GENERATE"));
+    }
+    let (range, rest) = next_token(rest).ok_or_else(|| generate_error(line, "there's no range"))?;
+    let (start, stop, step) = parse_generate_range(range)?;
+    let (lhs, mut rest) =
+        next_token(rest).ok_or_else(|| generate_error(line, "there's no owner name"))?;
+
+    // the TTL and class are both optional, and can come in either order
+    let mut ttl: Option<u32> = None;
+    let mut class: Option<RecordClass> = None;
+    let rrtype = loop {
+        let (token, after) =
+            next_token(rest).ok_or_else(|| generate_error(line, "there's no record type"))?;
+        rest = after;
+        if ttl.is_none() {
+            if let Ok(value) = token.parse::<u32>() {
+                ttl = Some(value);
+                continue;
+            }
+        }
+        if class.is_none() {
+            match RecordClass::from(token.to_ascii_uppercase().as_str()) {
+                RecordClass::InvalidType => {}
+                value => {
+                    class = Some(value);
+                    continue;
+                }
+            }
+        }
+        break parse_type(token)
+            .map(type_name)
+            .ok_or_else(|| generate_error(line, &format!("unknown record type {token:?}")))?;
+    };
+    let rhs = rest.trim();
+    if rhs.is_empty() {
+        return Err(generate_error(line, "there's no rdata"));
+    }
+
+    let mut records = vec![];
+    for iteration in (start..=stop).step_by(step as usize) {
+        let name = absolute_name(&generate_substitute(lhs, iteration)?, origin);
+        let name = relative_name(&name, zone_name)
+            .ok_or_else(|| generate_error(line, &format!("{name} isn't in {zone_name}")))?;
+        let rdata = absolute_rdata(&rrtype, &generate_substitute(rhs, iteration)?, origin);
+        records.push(FileZoneRecord {
+            id: None,
+            zoneid: None,
+            name,
+            rrtype: rrtype.clone(),
+            class: class.unwrap_or(RecordClass::Internet),
+            rdata,
+            ttl: ttl.unwrap_or(0),
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        });
+    }
+    Ok(records)
+}
+
+/// Read a zone file with its `$INCLUDE`s pulled in, where they're relative to the file that includes them. An
+/// include's origin is set with `$ORIGIN`, and the including file's is always put back afterwards.
+///
+/// The origin starts out as `zone_name`, or if that's not known the owner of the file's SOA, or failing that the file's
+/// name without the extension.
+///
+/// When `allow_includes` is false any `$INCLUDE` is an error, for files that've come from somewhere that shouldn't get
+/// to read the server's files (eg uploads).
+pub fn read_with_includes(
+    path: &Path,
+    allow_includes: bool,
+    zone_name: Option<&str>,
+) -> Result<String, GoatNsError> {
+    let mut res = String::new();
+    include_file(path, allow_includes, zone_name, &mut vec![], &mut res)?;
+    Ok(res)
+}
+
+/// Adds the file to `res`, `origin`'s the one it starts with if it's included
+fn include_file(
+    path: &Path,
+    allow_includes: bool,
+    origin: Option<&str>,
+    stack: &mut Vec<PathBuf>,
+    res: &mut String,
+) -> Result<(), GoatNsError> {
+    let path = path
+        .canonicalize()
+        .map_err(|err| GoatNsError::FileError(format!("Failed to open {path:?}: {err}")))?;
+    if stack.contains(&path) {
+        let chain: Vec<String> = stack
+            .iter()
+            .chain([&path])
+            .map(|path| path.display().to_string())
+            .collect();
+        return Err(GoatNsError::InvalidValue(format!(
+            "$INCLUDE loop: {}",
+            chain.join(" -> ")
+        )));
+    }
+    if stack.len() > MAX_INCLUDE_DEPTH {
+        return Err(GoatNsError::InvalidValue(format!(
+            "$INCLUDEs go more than {MAX_INCLUDE_DEPTH} deep at {path:?}"
+        )));
+    }
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|err| GoatNsError::FileError(format!("Failed to read {path:?}: {err}")))?;
+    let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut origin = match origin {
+        Some(origin) => absolute_name(origin, ""),
+        None => match soa_owner(&contents)? {
+            Some(owner) => owner,
+            None => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        },
+    };
+    stack.push(path);
+
+    for line in contents.lines() {
+        // directives have to be at the start of the line, otherwise it's a record for the previous owner
+        let directive = match line.starts_with('$') {
+            true => next_token(line),
+            false => None,
+        };
+        match directive {
+            Some((directive, rest)) if directive.eq_ignore_ascii_case("$INCLUDE") => {
+                if !allow_includes {
+                    return Err(GoatNsError::InvalidValue(format!(
+                        "$INCLUDE isn't allowed here: {line:?}"
+                    )));
+                }
+                let (filename, rest) = next_token(rest)
+                    .filter(|(filename, _)| !filename.starts_with(';'))
+                    .ok_or_else(|| {
+                        GoatNsError::InvalidValue(format!("$INCLUDE without a file: {line:?}"))
+                    })?;
+                let include_origin = match next_token(rest)
+                    .filter(|(include_origin, _)| !include_origin.starts_with(';'))
+                {
+                    Some((include_origin, _)) => {
+                        let include_origin = absolute_name(include_origin, &origin);
+                        res.push_str(&format!("$ORIGIN {include_origin}.\n"));
+                        include_origin
+                    }
+                    None => origin.clone(),
+                };
+                include_file(
+                    &directory.join(filename.trim_matches('"')),
+                    allow_includes,
+                    Some(&include_origin),
+                    stack,
+                    res,
+                )?;
+                // whatever the include did to the origin doesn't last
+                res.push_str(&format!("$ORIGIN {origin}.\n"));
+            }
+            Some((directive, rest)) if directive.eq_ignore_ascii_case("$ORIGIN") => {
+                if let Some((value, _)) = next_token(rest) {
+                    origin = absolute_name(value, &origin);
+                }
+                res.push_str(line);
+                res.push('\n');
+            }
+            _ => {
+                res.push_str(line);
+                res.push('\n');
+            }
+        }
+    }
+    stack.pop();
+    Ok(())
+}
+
+/// An SOA record's rdata, with the names fully qualified and without their trailing dots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Soa {
+    pub mname: String,
+    pub rname: String,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
+impl FromStr for Soa {
+    type Err = GoatNsError;
+
+    /// `mname rname serial refresh retry expire minimum`, where the timers can use BIND's units (see [parse_ttl])
+    fn from_str(rdata: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = rdata.split_whitespace().collect();
+        let [mname, rname, serial, refresh, retry, expire, minimum] = fields.as_slice() else {
+            return Err(GoatNsError::InvalidValue(format!(
+                "SOA needs mname, rname, serial, refresh, retry, expire and minimum, got {rdata:?}"
+            )));
+        };
+        let serial = serial.parse::<u32>().map_err(|_| {
+            GoatNsError::InvalidValue(format!("SOA serial {serial:?} isn't a number"))
+        })?;
+        let timer = |name: &str, value: &str| {
+            parse_ttl(value).ok_or_else(|| {
+                GoatNsError::InvalidValue(format!("SOA {name} {value:?} isn't a valid time"))
+            })
+        };
+        Ok(Soa {
+            mname: mname.trim_end_matches('.').to_string(),
+            rname: rname.trim_end_matches('.').to_string(),
+            serial,
+            refresh: timer("refresh", refresh)?,
+            retry: timer("retry", retry)?,
+            expire: timer("expire", expire)?,
+            minimum: timer("minimum", minimum)?,
+        })
+    }
+}
+
+impl Display for Soa {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {} {}",
+            self.mname,
+            self.rname,
+            self.serial,
+            self.refresh,
+            self.retry,
+            self.expire,
+            self.minimum
+        )
+    }
+}
+
+/// A word from a zone file. Quoted strings keep their quotes, and escapes are left as they were written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    pub quoted: bool,
+}
+
+/// A record or directive, which is one line unless parentheses carry it on to more
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Where it starts, counting from 1
+    pub line: usize,
+    /// It started with whitespace, so it's for the same owner as the one before
+    pub blank_owner: bool,
+    pub tokens: Vec<Token>,
+}
+
+/// Split a zone file up into [Entry]s, dropping comments and blank lines
+pub fn tokenize(contents: &str) -> Result<Vec<Entry>, ZoneFileError> {
+    let mut entries = vec![];
+    let mut line = 1;
+    // where the current entry, quoted string and outermost parenthesis started
+    let mut entry_line = 1;
+    let mut quote_line = 0;
+    let mut paren_line = 0;
+    let mut depth = 0;
+    let mut blank_owner = false;
+    let mut at_line_start = true;
+    let mut in_comment = false;
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut token: Option<Token> = None;
+    let mut tokens: Vec<Token> = vec![];
+
+    let finish_token = |token: &mut Option<Token>, tokens: &mut Vec<Token>| {
+        if let Some(token) = token.take() {
+            tokens.push(token);
+        }
+    };
+
+    for c in contents.chars() {
+        if in_comment {
+            if c != '\n' {
+                continue;
+            }
+            in_comment = false;
+        }
+        if quoted {
+            if let Some(token) = token.as_mut() {
+                token.text.push(c);
+            }
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = false,
+                '\n' => return Err(ZoneFileError::UnterminatedQuote { line: quote_line }),
+                _ => {}
+            }
+            // a quoted string's a token on its own, even if there's no space after it
+            if !quoted {
+                finish_token(&mut token, &mut tokens);
+            }
+            continue;
+        }
+        if escaped {
+            escaped = false;
+            token
+                .get_or_insert_with(|| Token {
+                    text: String::new(),
+                    quoted: false,
+                })
+                .text
+                .push(c);
+            continue;
+        }
+        if at_line_start && depth == 0 && c != '\n' {
+            entry_line = line;
+            blank_owner = c.is_whitespace();
+        }
+        at_line_start = false;
+        match c {
+            '\n' => {
+                finish_token(&mut token, &mut tokens);
+                if depth == 0 && !tokens.is_empty() {
+                    entries.push(Entry {
+                        line: entry_line,
+                        blank_owner,
+                        tokens: std::mem::take(&mut tokens),
+                    });
+                }
+                line += 1;
+                at_line_start = true;
+            }
+            ';' => {
+                finish_token(&mut token, &mut tokens);
+                in_comment = true;
+            }
+            '(' => {
+                finish_token(&mut token, &mut tokens);
+                if depth == 0 {
+                    paren_line = line;
+                }
+                depth += 1;
+            }
+            ')' => {
+                finish_token(&mut token, &mut tokens);
+                if depth == 0 {
+                    return Err(ZoneFileError::UnbalancedParentheses { line });
+                }
+                depth -= 1;
+            }
+            '"' => {
+                finish_token(&mut token, &mut tokens);
+                quoted = true;
+                quote_line = line;
+                token = Some(Token {
+                    text: String::from('"'),
+                    quoted: true,
+                });
+            }
+            c if c.is_whitespace() => finish_token(&mut token, &mut tokens),
+            c => {
+                let token = token.get_or_insert_with(|| Token {
+                    text: String::new(),
+                    quoted: false,
+                });
+                token.text.push(c);
+                if c == '\\' {
+                    escaped = true;
+                }
+            }
+        }
+    }
+    if quoted {
+        return Err(ZoneFileError::UnterminatedQuote { line: quote_line });
+    }
+    if depth > 0 {
+        return Err(ZoneFileError::UnbalancedParentheses { line: paren_line });
+    }
+    finish_token(&mut token, &mut tokens);
+    if !tokens.is_empty() {
+        entries.push(Entry {
+            line: entry_line,
+            blank_owner,
+            tokens,
+        });
+    }
+    Ok(entries)
+}
+
+/// A TTL in seconds, or BIND's units (eg `1h30m`, `2w`)
+pub fn parse_ttl(value: &str) -> Option<u32> {
+    if let Ok(ttl) = value.parse::<u32>() {
+        return Some(ttl);
+    }
+    let mut total: u32 = 0;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let multiplier = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return None,
+        };
+        let value: u32 = std::mem::take(&mut number).parse().ok()?;
+        total = total.checked_add(value.checked_mul(multiplier)?)?;
+    }
+    // trailing digits without a unit are seconds
+    if !number.is_empty() {
+        total = total.checked_add(number.parse().ok()?)?;
+    }
+    match value.is_empty() {
+        true => None,
+        false => Some(total),
+    }
+}
+
+/// The classes BIND knows, by the names it uses for them
+fn parse_class(value: &str) -> Option<RecordClass> {
+    match value.to_ascii_uppercase().as_str() {
+        "IN" => Some(RecordClass::Internet),
+        "CS" => Some(RecordClass::CsNet),
+        "CH" | "CHAOS" => Some(RecordClass::Chaos),
+        "HS" | "HESIOD" => Some(RecordClass::Hesiod),
+        _ => None,
+    }
+}
+
+/// Parse a zone file for `zone_name` into its records, with names relative to the zone. It's expected to have been
+/// through [read_with_includes] first, so `$INCLUDE`s are an error. Records without a TTL get the last `$TTL`, or 0
+/// (the import default) if there's not been one.
+pub fn parse_records(
+    contents: &str,
+    zone_name: &str,
+) -> Result<Vec<FileZoneRecord>, ZoneFileError> {
+    let zone_name = zone_name.trim_end_matches('.');
+    let mut origin = zone_name.to_string();
+    let mut default_ttl: Option<u32> = None;
+    let mut last_owner: Option<String> = None;
+    let mut records = vec![];
+
+    for entry in tokenize(contents)? {
+        let line = entry.line;
+        let mut tokens = entry.tokens.iter().map(|token| token.text.as_str());
+        if !entry.blank_owner {
+            if let Some(directive) = entry
+                .tokens
+                .first()
+                .filter(|token| !token.quoted && token.text.starts_with('$'))
+            {
+                let invalid = |message: &str| ZoneFileError::InvalidDirective {
+                    line,
+                    message: format!("{} {message}", directive.text),
+                };
+                let args: Vec<&str> = tokens.skip(1).collect();
+                match directive.text.to_ascii_uppercase().as_str() {
+                    "$ORIGIN" => match args.as_slice() {
+                        [value] => origin = absolute_name(value, &origin),
+                        _ => return Err(invalid("needs a name")),
+                    },
+                    "$TTL" => match args.as_slice() {
+                        [value] => {
+                            default_ttl =
+                                Some(parse_ttl(value).ok_or_else(|| ZoneFileError::InvalidTtl {
+                                    line,
+                                    ttl: value.to_string(),
+                                })?)
+                        }
+                        _ => return Err(invalid("needs a TTL")),
+                    },
+                    "$GENERATE" => {
+                        let generate_line = format!("$GENERATE {}", args.join(" "));
+                        let generated = expand_generate(&generate_line, &origin, zone_name)
+                            .map_err(|err| match err {
+                                GoatNsError::InvalidValue(message) => {
+                                    ZoneFileError::InvalidDirective { line, message }
+                                }
+                                err => invalid(&format!("{err:?}")),
+                            })?;
+                        records.extend(generated);
+                    }
+                    "$INCLUDE" => return Err(invalid("isn't allowed here")),
+                    _ => {
+                        return Err(ZoneFileError::UnknownDirective {
+                            line,
+                            directive: directive.text.clone(),
+                        })
+                    }
+                }
+                continue;
+            }
+        }
+
+        let owner = match entry.blank_owner {
+            true => last_owner.clone().ok_or(ZoneFileError::NoOwner { line })?,
+            false => {
+                let owner = tokens.next().ok_or(ZoneFileError::NoOwner { line })?;
+                absolute_name(owner, &origin)
+            }
+        };
+        let name = relative_name(&owner, zone_name).ok_or_else(|| ZoneFileError::OutsideZone {
+            line,
+            name: owner.clone(),
+        })?;
+        last_owner = Some(owner);
+
+        // the TTL and class are both optional, and can come in either order
+        let mut ttl: Option<u32> = None;
+        let mut class: Option<RecordClass> = None;
+        let rrtype = loop {
+            let token = tokens.next().ok_or_else(|| ZoneFileError::UnknownType {
+                line,
+                rrtype: String::new(),
+            })?;
+            if ttl.is_none() && token.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(parse_ttl(token).ok_or_else(|| ZoneFileError::InvalidTtl {
+                    line,
+                    ttl: token.to_string(),
+                })?);
+                continue;
+            }
+            if class.is_none() {
+                if let Some(value) = parse_class(token) {
+                    class = Some(value);
+                    continue;
+                }
+            }
+            break parse_type(token)
+                .map(type_name)
+                .ok_or_else(|| ZoneFileError::UnknownType {
+                    line,
+                    rrtype: token.to_string(),
+                })?;
+        };
+        let rdata: Vec<&str> = tokens.collect();
+        if rdata.is_empty() {
+            return Err(ZoneFileError::NoRdata { line });
+        }
+        let mut rdata = absolute_rdata(&rrtype, &rdata.join(" "), &origin);
+        // SOAs end up in the zone's fields, so they're checked (and their timers turned into seconds) here where
+        // there's a line number to point at
+        if rrtype == "SOA" {
+            let soa = Soa::from_str(&rdata).map_err(|err| ZoneFileError::InvalidRdata {
+                line,
+                message: match err {
+                    GoatNsError::InvalidValue(message) => message,
+                    err => format!("{err:?}"),
+                },
+            })?;
+            rdata = soa.to_string();
+        }
+        records.push(FileZoneRecord {
+            id: None,
+            zoneid: None,
+            name,
+            rdata,
+            rrtype,
+            class: class.unwrap_or(RecordClass::Internet),
+            ttl: ttl.or(default_ttl).unwrap_or(0),
+            comment: None,
+            tags: Default::default(),
+            expires_at: None,
+        });
+    }
+    Ok(records)
+}
+
+/// The first `$TTL` in the file, which is what becomes the zone's default TTL
+pub fn ttl_directive(contents: &str) -> Result<Option<u32>, ZoneFileError> {
+    for entry in tokenize(contents)? {
+        let is_ttl = entry
+            .tokens
+            .first()
+            .is_some_and(|token| !token.quoted && token.text.eq_ignore_ascii_case("$TTL"));
+        if entry.blank_owner || !is_ttl {
+            continue;
+        }
+        return match entry.tokens.get(1) {
+            Some(value) => {
+                parse_ttl(&value.text)
+                    .map(Some)
+                    .ok_or_else(|| ZoneFileError::InvalidTtl {
+                        line: entry.line,
+                        ttl: value.text.clone(),
+                    })
+            }
+            None => Err(ZoneFileError::InvalidDirective {
+                line: entry.line,
+                message: "$TTL needs a TTL".to_string(),
+            }),
+        };
+    }
+    Ok(None)
+}
+
+/// The fully qualified owner of the first SOA record, for working out which zone a file's for when it isn't said
+pub fn soa_owner(contents: &str) -> Result<Option<String>, ZoneFileError> {
+    let mut origin = String::new();
+    let mut last_owner: Option<String> = None;
+    for entry in tokenize(contents)? {
+        let mut tokens = entry.tokens.iter().map(|token| token.text.as_str());
+        if !entry.blank_owner {
+            let Some(first) = tokens.next() else {
+                continue;
+            };
+            if first.eq_ignore_ascii_case("$ORIGIN") {
+                if let Some(value) = tokens.next() {
+                    origin = absolute_name(value, &origin);
+                }
+                continue;
+            }
+            if first.starts_with('$') {
+                continue;
+            }
+            last_owner = Some(absolute_name(first, &origin));
+        }
+        // the type's the first thing that isn't a TTL or class
+        let is_soa = tokens
+            .find(|token| {
+                !token.starts_with(|c: char| c.is_ascii_digit()) && parse_class(token).is_none()
+            })
+            .is_some_and(|rrtype| rrtype.eq_ignore_ascii_case("SOA"));
+        if is_soa {
+            return Ok(last_owner);
+        }
+    }
+    Ok(None)
+}
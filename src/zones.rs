@@ -1,19 +1,28 @@
-use crate::enums::RecordClass;
+use crate::config::{ConfigFile, ImportTtlPolicy, TtlOutOfRange};
+use crate::enums::{RecordClass, RecordType};
 use crate::error::GoatNsError;
-use crate::resourcerecord::InternalResourceRecord;
+use crate::name_policy::{normalise, relative_name};
+use crate::reply::Answer;
+use crate::resourcerecord::{DomainName, InternalResourceRecord};
+use crate::zone_schema::zones_from_json;
+use goat_lib::validators::record_name;
 use log::*;
 
 use serde::{Deserialize, Serialize};
-use sqlx::SqliteConnection;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::fs::File;
 use std::io::Read;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::path::Path;
 use std::str::from_utf8;
+use std::sync::Arc;
+use std::time::Duration;
 use utoipa::ToSchema;
 
 /// A DNS Zone
-#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
 #[serde(rename(serialize = "UPPERCASE"))]
 pub struct FileZone {
     /// Database row ID
@@ -40,11 +49,195 @@ pub struct FileZone {
     /// MINIMUM - The unsigned 32 bit minimum TTL field that should be exported with any RR from this zone.
     #[serde(default)]
     pub minimum: u32,
+    /// What records that don't have a TTL get, like `$TTL` in a zone file. Unset means the server's import default
+    #[serde(default)]
+    pub default_ttl: Option<u32>,
     /// The records associated with this zone
     pub records: Vec<FileZoneRecord>,
+    /// If set, edits by users who don't own the zone are stored as a [ZoneChangeSet] for an owner to approve
+    #[serde(default)]
+    pub review_required: bool,
+    /// Turned off zones keep their records, but queries for anything in them get `disabled_zone_rcode` back
+    #[serde(default = "enabled_default")]
+    pub enabled: bool,
+    /// Public zones show up in the unauthenticated RDAP-ish endpoint, see [crate::web::rdap]
+    #[serde(default)]
+    pub public: bool,
+    /// Where the domain's registered and notes about it, see [ZoneRegistration]
+    #[serde(default)]
+    pub registration: ZoneRegistration,
+}
+
+/// How many days out a domain's registration counts as expiring soon
+pub const REGISTRATION_EXPIRING_SOON_DAYS: i64 = 30;
+/// The longest notes can be, in characters
+pub const REGISTRATION_NOTES_MAX: usize = 4096;
+
+/// Where a zone's domain is registered and when that runs out, so it can be kept track of next to the DNS. It's only
+/// for people to read, nothing talks to the registrar.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ZoneRegistration {
+    /// Who the domain's registered with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registrar: Option<String>,
+    /// When the domain's registration runs out, eg `2025-06-30`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub expires: Option<chrono::NaiveDate>,
+    /// Free text, eg who pays for it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+impl ZoneRegistration {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Empty strings are the same as not setting them, and the registrar and notes aren't too long
+    pub fn check(self) -> Result<Self, GoatNsError> {
+        let non_empty = |value: Option<String>| {
+            value
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        let registration = Self {
+            registrar: non_empty(self.registrar),
+            expires: self.expires,
+            notes: non_empty(self.notes),
+        };
+        if registration
+            .registrar
+            .as_ref()
+            .is_some_and(|registrar| registrar.chars().count() > 255)
+        {
+            return Err(GoatNsError::InvalidValue(
+                "The registrar can't be more than 255 characters".to_string(),
+            ));
+        }
+        if registration
+            .notes
+            .as_ref()
+            .is_some_and(|notes| notes.chars().count() > REGISTRATION_NOTES_MAX)
+        {
+            return Err(GoatNsError::InvalidValue(format!(
+                "Notes can't be more than {REGISTRATION_NOTES_MAX} characters"
+            )));
+        }
+        Ok(registration)
+    }
+
+    /// How many days until the registration runs out, negative if it already has
+    pub fn days_left(&self, today: chrono::NaiveDate) -> Option<i64> {
+        self.expires
+            .map(|expires| expires.signed_duration_since(today).num_days())
+    }
+
+    /// Does it run out in the next [REGISTRATION_EXPIRING_SOON_DAYS], or has it already?
+    pub fn expiring_soon(&self, today: chrono::NaiveDate) -> bool {
+        self.days_left(today)
+            .is_some_and(|days| days <= REGISTRATION_EXPIRING_SOON_DAYS)
+    }
+
+    /// Something to show next to the expiry date if it's [Self::expiring_soon]
+    pub fn expiry_warning(&self, today: chrono::NaiveDate) -> Option<String> {
+        match self.days_left(today)? {
+            -1 => Some("Expired yesterday".to_string()),
+            days if days < 0 => Some(format!("Expired {} days ago", -days)),
+            0 => Some("Expires today".to_string()),
+            1 => Some("Expires tomorrow".to_string()),
+            days if days <= REGISTRATION_EXPIRING_SOON_DAYS => {
+                Some(format!("Expiring soon, {days} days left"))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for FileZone {
+    fn default() -> Self {
+        Self {
+            id: None,
+            name: String::new(),
+            rname: String::new(),
+            serial: 0,
+            refresh: 0,
+            retry: 0,
+            expire: 0,
+            minimum: 0,
+            default_ttl: None,
+            records: vec![],
+            review_required: false,
+            enabled: true,
+            public: false,
+            registration: ZoneRegistration::default(),
+        }
+    }
 }
 
 impl FileZone {
+    /// Start building a zone, see [FileZoneBuilder]
+    pub fn builder(name: &str, rname: &str) -> FileZoneBuilder {
+        FileZoneBuilder {
+            zone: FileZone {
+                name: name.to_string(),
+                rname: rname.to_string(),
+                ..FileZone::default()
+            },
+        }
+    }
+
+    /// The zone's SOA record, `mname` is the primary name server and defaults to the zone name if there isn't one
+    pub fn soa(&self, mname: Option<&str>) -> InternalResourceRecord {
+        InternalResourceRecord::SOA {
+            zone: DomainName::from(self.name.as_str()),
+            mname: DomainName::from(mname.unwrap_or(&self.name)),
+            // rnames are mailboxes, the first @ becomes a dot
+            rname: DomainName::from(self.rname.replacen('@', ".", 1)),
+            serial: self.serial,
+            refresh: self.refresh,
+            retry: self.retry,
+            expire: self.expire,
+            minimum: self.minimum,
+            rclass: RecordClass::Internet,
+        }
+    }
+
+    /// Is the zone's serial newer than `serial`? It's [serial_cmp], so it copes with serials wrapping around.
+    pub fn is_newer_than(&self, serial: u32) -> bool {
+        serial_cmp(self.serial, serial) == Some(Ordering::Greater)
+    }
+
+    /// How often secondaries should check the serial
+    pub fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.refresh.into())
+    }
+
+    /// How long secondaries wait to try again after a refresh fails
+    pub fn retry_interval(&self) -> Duration {
+        Duration::from_secs(self.retry.into())
+    }
+
+    /// How long secondaries keep answering for the zone when they can't refresh it
+    pub fn expire_interval(&self) -> Duration {
+        Duration::from_secs(self.expire.into())
+    }
+
+    /// Normalises the zone's name and puts its records' names relative to it, see [crate::name_policy::relative_name]
+    pub fn normalise_names(&mut self) -> Result<(), GoatNsError> {
+        self.name = normalise(&self.name);
+        for record in self.records.iter_mut() {
+            record.normalise_name(&self.name)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the records don't break the CNAME rules, see [check_cname_rules]
+    pub fn check_cname_rules(&self) -> Result<(), GoatNsError> {
+        check_cname_rules(&self.name, &self.records)
+    }
+
     /// Checks if they're equal, ignores the zone id and records
     pub fn matching_data(&self, cmp: &FileZone) -> bool {
         self.expire == cmp.expire
@@ -54,13 +247,509 @@ impl FileZone {
             && self.retry == cmp.retry
             && self.rname == cmp.rname
             && self.serial == cmp.serial
+            && self.review_required == cmp.review_required
+            && self.default_ttl == cmp.default_ttl
+    }
+
+    /// Fill in anything that's unset on a new zone from the config, and add NS records at the apex if it doesn't have any
+    pub fn apply_defaults(&mut self, config: &ConfigFile) {
+        let defaults = &config.zone_defaults;
+        if self.rname.is_empty() || self.rname == rname_default() {
+            if let Some(rname) = &defaults.rname {
+                self.rname.clone_from(rname);
+            }
+        }
+        if self.serial == 0 {
+            self.serial = default_serial();
+        }
+        if self.refresh == 0 {
+            self.refresh = defaults.refresh;
+        }
+        if self.retry == 0 {
+            self.retry = defaults.retry;
+        }
+        if self.expire == 0 {
+            self.expire = defaults.expire;
+        }
+        if self.minimum == 0 {
+            self.minimum = defaults.minimum;
+        }
+
+        let has_apex_ns = self
+            .records
+            .iter()
+            .any(|r| r.record_type() == RecordType::NS && (r.name == "@" || r.name.is_empty()));
+        if !has_apex_ns {
+            for nameserver in config.default_nameservers() {
+                self.records.push(FileZoneRecord {
+                    zoneid: self.id,
+                    ..FileZoneRecord::ns("@", &nameserver, defaults.ns_ttl)
+                });
+            }
+        }
+    }
+}
+/// Builds a [FileZone] without having to fill in every field, and checks it's sensible on the way out.
+///
+/// ```
+/// use std::net::Ipv4Addr;
+/// use goatns::zones::{FileZone, FileZoneRecord};
+///
+/// let zone = FileZone::builder("Example.com.", "hostmaster@example.com")
+///     .serial(2024010101)
+///     .record(FileZoneRecord::ns("@", "ns1.example.com", 86400))
+///     .record(FileZoneRecord::a("www", Ipv4Addr::new(192, 0, 2, 1), 300))
+///     .record(FileZoneRecord::cname("ftp", "www.example.com", 300))
+///     .build()
+///     .expect("failed to build the zone");
+/// assert_eq!(zone.name, "example.com");
+/// assert_eq!(zone.records.len(), 3);
+///
+/// // a CNAME can't share its name
+/// assert!(FileZone::builder("example.com", "hostmaster@example.com")
+///     .record(FileZoneRecord::a("www", Ipv4Addr::new(192, 0, 2, 1), 300))
+///     .record(FileZoneRecord::cname("www", "example.net", 300))
+///     .build()
+///     .is_err());
+/// ```
+#[derive(Clone, Debug)]
+pub struct FileZoneBuilder {
+    zone: FileZone,
+}
+
+impl FileZoneBuilder {
+    pub fn serial(mut self, serial: u32) -> Self {
+        self.zone.serial = serial;
+        self
+    }
+
+    pub fn refresh(mut self, refresh: u32) -> Self {
+        self.zone.refresh = refresh;
+        self
+    }
+
+    pub fn retry(mut self, retry: u32) -> Self {
+        self.zone.retry = retry;
+        self
+    }
+
+    pub fn expire(mut self, expire: u32) -> Self {
+        self.zone.expire = expire;
+        self
+    }
+
+    pub fn minimum(mut self, minimum: u32) -> Self {
+        self.zone.minimum = minimum;
+        self
+    }
+
+    /// What records with a TTL of 0 get, see [FileZone::default_ttl]
+    pub fn default_ttl(mut self, default_ttl: u32) -> Self {
+        self.zone.default_ttl = Some(default_ttl);
+        self
+    }
+
+    pub fn review_required(mut self, review_required: bool) -> Self {
+        self.zone.review_required = review_required;
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.zone.enabled = enabled;
+        self
+    }
+
+    pub fn public(mut self, public: bool) -> Self {
+        self.zone.public = public;
+        self
+    }
+
+    /// Add a record, its name can be relative to the zone or fully qualified
+    pub fn record(mut self, record: FileZoneRecord) -> Self {
+        self.zone.records.push(record);
+        self
+    }
+
+    /// Add records, see [FileZoneBuilder::record]
+    pub fn records<I: IntoIterator<Item = FileZoneRecord>>(mut self, records: I) -> Self {
+        self.zone.records.extend(records);
+        self
+    }
+
+    /// Finish up. Names are normalised, and it fails if a record's rdata doesn't parse or the CNAME rules are broken.
+    pub fn build(self) -> Result<FileZone, GoatNsError> {
+        let mut zone = self.zone;
+        zone.normalise_names()?;
+        for record in &zone.records {
+            InternalResourceRecord::try_from(record.clone())?;
+        }
+        zone.check_cname_rules()?;
+        Ok(zone)
+    }
+}
+
+/// How an import deals with zones that are already in the database
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImportStrategy {
+    /// Throw away the existing records and use the imported ones
+    #[default]
+    Replace,
+    /// Add new records and update existing ones, matched on name, type and rdata
+    Merge,
+    /// Only add records for names which aren't already in the zone
+    SkipExisting,
+}
+
+impl Display for ImportStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ImportStrategy::Replace => "replace",
+            ImportStrategy::Merge => "merge",
+            ImportStrategy::SkipExisting => "skip-existing",
+        })
+    }
+}
+
+impl std::str::FromStr for ImportStrategy {
+    type Err = GoatNsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "replace" => Ok(ImportStrategy::Replace),
+            "merge" => Ok(ImportStrategy::Merge),
+            "skip-existing" => Ok(ImportStrategy::SkipExisting),
+            _ => Err(GoatNsError::InvalidValue(format!(
+                "Unknown import strategy: {s}"
+            ))),
+        }
+    }
+}
+
+/// What happened to the records in an import
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+pub struct ImportSummary {
+    /// Zones imported
+    pub zones: usize,
+    /// New records written, including the adjusted ones
+    pub imported: usize,
+    /// Existing records which were updated by a merge
+    pub updated: usize,
+    /// Existing records which already matched what was imported
+    pub unchanged: usize,
+    /// Records left out because of their TTL, or because the name exists when using [ImportStrategy::SkipExisting]
+    pub skipped: usize,
+    /// Records that had their TTL changed
+    pub adjusted: usize,
+    /// Existing records that went away because the import replaced them
+    pub removed: usize,
+    /// Nothing was saved, this is what would have happened
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl ImportSummary {
+    /// Add another summary's numbers to this one
+    pub fn add(&mut self, other: &ImportSummary) {
+        self.zones += other.zones;
+        self.imported += other.imported;
+        self.updated += other.updated;
+        self.unchanged += other.unchanged;
+        self.skipped += other.skipped;
+        self.adjusted += other.adjusted;
+        self.removed += other.removed;
+    }
+}
+
+impl Display for ImportSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "zones={} records imported={} updated={} unchanged={} skipped={} adjusted={} removed={}{}",
+            self.zones,
+            self.imported,
+            self.updated,
+            self.unchanged,
+            self.skipped,
+            self.adjusted,
+            self.removed,
+            if self.dry_run { " (dry run)" } else { "" }
+        )
+    }
+}
+
+/// How far through an import we are
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ImportProgress {
+    /// Zones that have been written
+    pub zones_done: usize,
+    /// Zones in the import
+    pub zones_total: usize,
+    /// Records in the zones that have been written
+    pub records_done: usize,
+    /// Records in the import
+    pub records_total: usize,
+}
+
+/// Gets called after each zone in an import is written, eg to draw a progress bar
+#[derive(Clone)]
+pub struct ImportProgressCallback(Arc<dyn Fn(&ImportProgress) + Send + Sync>);
+
+impl ImportProgressCallback {
+    /// Wrap up a function to call with progress updates
+    pub fn new(callback: impl Fn(&ImportProgress) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    /// Let it know how things are going
+    pub fn update(&self, progress: &ImportProgress) {
+        (self.0)(progress)
+    }
+}
+
+impl std::fmt::Debug for ImportProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ImportProgressCallback")
+    }
+}
+
+impl FileZone {
+    /// Check record TTLs against the import policy, filling in missing TTLs and clamping or dropping ones out of range
+    pub fn apply_ttl_policy(&mut self, policy: &ImportTtlPolicy) -> ImportSummary {
+        let mut summary = ImportSummary {
+            zones: 1,
+            ..Default::default()
+        };
+        let zone_name = self.name.clone();
+        self.default_ttl = self
+            .default_ttl
+            .map(|ttl| ttl.max(policy.min_ttl).min(policy.max_ttl));
+        let default_ttl = self.default_ttl.unwrap_or(policy.default_ttl);
+        self.records.retain_mut(|record| {
+            let ttl = match record.ttl {
+                0 => default_ttl,
+                ttl => ttl,
+            };
+            let ttl = match (
+                ttl < policy.min_ttl,
+                ttl > policy.max_ttl,
+                policy.out_of_range,
+            ) {
+                (false, false, _) => ttl,
+                (_, _, TtlOutOfRange::Skip) => {
+                    warn!(
+                        "Skipping {} {} in {zone_name}, TTL {ttl} is outside {}-{}",
+                        record.name, record.rrtype, policy.min_ttl, policy.max_ttl
+                    );
+                    summary.skipped += 1;
+                    return false;
+                }
+                (true, _, TtlOutOfRange::Clamp) => policy.min_ttl,
+                (false, true, TtlOutOfRange::Clamp) => policy.max_ttl,
+            };
+            if ttl != record.ttl {
+                debug!(
+                    "Adjusted TTL of {} {} in {zone_name} from {} to {ttl}",
+                    record.name, record.rrtype, record.ttl
+                );
+                record.ttl = ttl;
+                summary.adjusted += 1;
+            }
+            summary.imported += 1;
+            true
+        });
+        summary
+    }
+
+    /// Records without a TTL get the zone's default, or `fallback` if it doesn't have one
+    pub fn fill_default_ttls(&mut self, fallback: u32) {
+        let ttl = self.default_ttl.unwrap_or(fallback);
+        self.records
+            .iter_mut()
+            .filter(|record| record.ttl == 0)
+            .for_each(|record| record.ttl = ttl);
+    }
+
+    /// The default TTL has to be something the import policy would allow
+    pub fn check_default_ttl(&self, policy: &ImportTtlPolicy) -> Result<(), GoatNsError> {
+        match self.default_ttl {
+            Some(ttl) if !(policy.min_ttl..=policy.max_ttl).contains(&ttl) => {
+                Err(GoatNsError::InvalidValue(format!(
+                    "The default TTL needs to be from {} to {}",
+                    policy.min_ttl, policy.max_ttl
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// What replacing this zone with `proposed` would do. Records are matched on [FileZoneRecord::import_key] like
+    /// imports do, so changing the rdata is a removal and an addition. SOA fields that are zero (or the default rname)
+    /// in the proposal count as unset and aren't compared, and so does a default TTL that isn't set.
+    pub fn diff(&self, proposed: &FileZone) -> ZoneDiff {
+        let mut diff = ZoneDiff::default();
+
+        let mut soa_field = |field: &str, current: String, new: String, unset: bool| {
+            if !unset && current != new {
+                diff.soa.push(SoaChange {
+                    field: field.to_string(),
+                    current,
+                    proposed: new,
+                });
+            }
+        };
+        // rnames are mailboxes, so the first @ is a dot
+        soa_field(
+            "rname",
+            self.rname.replacen('@', ".", 1),
+            proposed.rname.replacen('@', ".", 1),
+            proposed.rname.is_empty() || proposed.rname == rname_default(),
+        );
+        for (field, current, new) in [
+            ("serial", self.serial, proposed.serial),
+            ("refresh", self.refresh, proposed.refresh),
+            ("retry", self.retry, proposed.retry),
+            ("expire", self.expire, proposed.expire),
+            ("minimum", self.minimum, proposed.minimum),
+        ] {
+            soa_field(field, current.to_string(), new.to_string(), new == 0);
+        }
+        soa_field(
+            "default_ttl",
+            self.default_ttl
+                .map(|ttl| ttl.to_string())
+                .unwrap_or_default(),
+            proposed
+                .default_ttl
+                .map(|ttl| ttl.to_string())
+                .unwrap_or_default(),
+            proposed.default_ttl.is_none(),
+        );
+
+        let mut current: BTreeMap<_, &FileZoneRecord> = self
+            .records
+            .iter()
+            .map(|record| (record.import_key(), record))
+            .collect();
+        for record in proposed.records.iter() {
+            match current.remove(&record.import_key()) {
+                None => diff.added.push(record.clone()),
+                Some(existing)
+                    if existing.ttl == record.ttl
+                        && existing.comment == record.comment
+                        && existing.tags == record.tags
+                        && existing.expires_at == record.expires_at =>
+                {
+                    diff.unchanged += 1
+                }
+                Some(existing) => diff.updated.push(RecordUpdate {
+                    current: existing.clone(),
+                    proposed: FileZoneRecord {
+                        id: existing.id,
+                        zoneid: existing.zoneid,
+                        ..record.clone()
+                    },
+                }),
+            }
+        }
+        diff.removed = current.into_values().cloned().collect();
+        diff
+    }
+}
+
+/// An SOA field that'd change, see [FileZone::diff]
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+pub struct SoaChange {
+    pub field: String,
+    pub current: String,
+    pub proposed: String,
+}
+
+/// A record that'd have its TTL, comment or tags changed
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+pub struct RecordUpdate {
+    pub current: FileZoneRecord,
+    /// What it'd become, with the current record's id
+    pub proposed: FileZoneRecord,
+}
+
+/// The difference between a zone and a proposed replacement for it, from [FileZone::diff]
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+pub struct ZoneDiff {
+    pub soa: Vec<SoaChange>,
+    /// Records that aren't in the zone yet
+    pub added: Vec<FileZoneRecord>,
+    pub updated: Vec<RecordUpdate>,
+    /// Records in the zone that aren't in the proposal
+    pub removed: Vec<FileZoneRecord>,
+    /// How many records are the same in both
+    pub unchanged: usize,
+}
+
+impl ZoneDiff {
+    /// Would anything change?
+    pub fn is_empty(&self) -> bool {
+        self.soa.is_empty()
+            && self.added.is_empty()
+            && self.updated.is_empty()
+            && self.removed.is_empty()
+    }
+}
+
+/// The first serial for a new zone, YYYYMMDDnn for today since it's the convention most people expect
+pub fn default_serial() -> u32 {
+    chrono::Utc::now()
+        .format("%Y%m%d01")
+        .to_string()
+        .parse()
+        .unwrap_or(1)
+}
+
+/// Today's YYYYMMDD00, bumped serials don't go below it so they end up following the convention
+pub fn serial_floor() -> u32 {
+    chrono::Utc::now()
+        .format("%Y%m%d00")
+        .to_string()
+        .parse()
+        .unwrap_or(0)
+}
+
+/// The serial that comes after `serial`, see [serial_floor]
+pub fn next_serial(serial: u32) -> u32 {
+    serial.wrapping_add(1).max(serial_floor())
+}
+
+/// Serials more than this far ahead are behind, ref [RFC1982](https://www.rfc-editor.org/rfc/rfc1982) section 2
+pub const SERIAL_HALF: u32 = 1 << 31;
+
+/// Compares serials with [RFC1982](https://www.rfc-editor.org/rfc/rfc1982) serial number arithmetic, so
+/// `4294967295` is older than `1` once it's wrapped. Serials exactly [SERIAL_HALF] apart can't be compared and get None.
+pub fn serial_cmp(serial: u32, other: u32) -> Option<Ordering> {
+    match other.wrapping_sub(serial) {
+        0 => Some(Ordering::Equal),
+        SERIAL_HALF => None,
+        ahead if ahead < SERIAL_HALF => Some(Ordering::Less),
+        _ => Some(Ordering::Greater),
+    }
+}
+
+/// Adds to a serial the way RFC1982 section 3.1 says to, you can only add up to `SERIAL_HALF - 1` at a time
+pub fn serial_add(serial: u32, n: u32) -> Option<u32> {
+    match n < SERIAL_HALF {
+        true => Some(serial.wrapping_add(n)),
+        false => None,
     }
 }
+
 /// default RNAME value for FileZone
 pub fn rname_default() -> String {
     String::from("barry.dot.goat")
 }
 
+pub fn enabled_default() -> bool {
+    true
+}
+
 /// A DNS Record from the JSON file
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, ToSchema)]
 pub struct FileZoneRecord {
@@ -79,15 +768,173 @@ pub struct FileZoneRecord {
     pub class: RecordClass,
     /// The actual data for the record
     pub rdata: String,
-    /// Time to live
+    /// Time to live, 0 (or leaving it out) means "use the default" when importing
+    #[serde(default)]
     pub ttl: u32,
+    /// Free-text comment, eg why this odd TXT record exists
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Key/value tags for grouping and searching records
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// When the record's deleted, for temporary things like validation TXT records
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl FileZoneRecord {
+    /// A record in class IN, with the rdata in zone file format. Use the type's own constructor if there is one, the
+    /// rdata's only checked when the record's saved or goes through [FileZoneBuilder::build].
+    pub fn new(name: &str, rrtype: RecordType, rdata: &str, ttl: u32) -> Self {
+        Self {
+            id: None,
+            zoneid: None,
+            name: name.to_string(),
+            rrtype: rrtype.to_string(),
+            class: RecordClass::Internet,
+            rdata: rdata.to_string(),
+            ttl,
+            comment: None,
+            tags: BTreeMap::new(),
+            expires_at: None,
+        }
+    }
+
+    pub fn a(name: &str, address: Ipv4Addr, ttl: u32) -> Self {
+        Self::new(name, RecordType::A, &address.to_string(), ttl)
+    }
+
+    pub fn aaaa(name: &str, address: Ipv6Addr, ttl: u32) -> Self {
+        Self::new(name, RecordType::AAAA, &address.to_string(), ttl)
+    }
+
+    pub fn cname(name: &str, target: &str, ttl: u32) -> Self {
+        Self::new(name, RecordType::CNAME, target, ttl)
+    }
+
+    pub fn ns(name: &str, nameserver: &str, ttl: u32) -> Self {
+        Self::new(name, RecordType::NS, nameserver, ttl)
+    }
+
+    pub fn mx(name: &str, preference: u16, exchange: &str, ttl: u32) -> Self {
+        Self::new(
+            name,
+            RecordType::MX,
+            &format!("{preference} {exchange}"),
+            ttl,
+        )
+    }
+
+    /// One string, it's split up into 255-byte chunks when it's sent
+    pub fn txt(name: &str, text: &str, ttl: u32) -> Self {
+        Self::new(name, RecordType::TXT, text, ttl)
+    }
+
+    /// Add a comment, see [FileZoneRecord::comment]
+    pub fn with_comment(mut self, comment: &str) -> Self {
+        self.comment = Some(comment.to_string());
+        self
+    }
+
+    /// Add a tag, see [FileZoneRecord::tags]
+    pub fn with_tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// The record's type, types that only have generic support (eg `TYPE65534`) are [RecordType::InvalidType], see
+    /// [FileZoneRecord::rrtype_code] for those
+    pub fn record_type(&self) -> RecordType {
+        RecordType::from(&self.rrtype_code())
+    }
+
+    /// What imports match existing records on - the name (with `@` meaning the apex), type and rdata
+    pub fn import_key(&self) -> (String, String, String) {
+        let name = match self.name.as_str() {
+            "@" => String::new(),
+            name => name.to_lowercase(),
+        };
+        let rrtype = match crate::generic::parse_type(&self.rrtype) {
+            Some(code) => crate::generic::type_name(code),
+            None => self.rrtype.to_uppercase(),
+        };
+        (name, rrtype, self.rdata.clone())
+    }
+
+    /// [FileZoneRecord::expires_at] the way it's stored
+    pub fn expires_at_timestamp(&self) -> Option<i64> {
+        self.expires_at.map(|expires_at| expires_at.timestamp())
+    }
+
+    /// Makes the name relative to the zone it's in (see [crate::name_policy::relative_name]) and checks it with
+    /// [FileZoneRecord::check_name]
+    pub fn normalise_name(&mut self, zone_name: &str) -> Result<(), GoatNsError> {
+        self.name = relative_name(&self.name, zone_name)?;
+        self.check_name()
+    }
+
+    /// Checks the name's legal for the record type - names like `_dmarc` and `_443._tcp` are fine for TXT, SRV, TLSA
+    /// and friends, but [HOSTNAME_RRTYPES] need hostnames, which can't have underscores.
+    pub fn check_name(&self) -> Result<(), GoatNsError> {
+        let hostname = HOSTNAME_RRTYPES
+            .iter()
+            .any(|rrtype| rrtype.eq_ignore_ascii_case(&self.rrtype));
+        if record_name(&self.name, !hostname) {
+            return Ok(());
+        }
+        Err(GoatNsError::InvalidValue(
+            match hostname && record_name(&self.name, true) {
+                true => format!(
+                    "{} records need a hostname, {:?} can't have underscores in it",
+                    self.rrtype.to_uppercase(),
+                    self.name
+                ),
+                false => format!("{:?} isn't a valid record name", self.name),
+            },
+        ))
+    }
+
+    /// The type code that gets stored and goes on the wire, this handles `TYPE<number>` as well as the names we know
+    pub fn rrtype_code(&self) -> u16 {
+        crate::generic::parse_type(&self.rrtype).unwrap_or(RecordType::InvalidType as u16)
+    }
+
+    /// Serialize the tags for storage, returns None if there aren't any
+    pub fn tags_json(&self) -> Option<String> {
+        if self.tags.is_empty() {
+            return None;
+        }
+        serde_json::to_string(&self.tags)
+            .inspect_err(|err| error!("Failed to serialize tags for {self}: {err:?}"))
+            .ok()
+    }
+
+    /// Checks if the record matches a search string, either `key=value` for a tag or free text against the comment and tags
+    pub fn matches_search(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        if let Some((key, value)) = query.split_once('=') {
+            return self
+                .tags
+                .iter()
+                .any(|(k, v)| k.to_lowercase() == key && v.to_lowercase() == value);
+        }
+        self.comment
+            .as_ref()
+            .map(|c| c.to_lowercase().contains(&query))
+            .unwrap_or(false)
+            || self.tags.iter().any(|(k, v)| {
+                k.to_lowercase().contains(&query) || v.to_lowercase().contains(&query)
+            })
+    }
 }
+
 /// If you don't specify a name, it's the root.
-fn default_record_name() -> String {
+pub(crate) fn default_record_name() -> String {
     String::from("@")
 }
 /// Sets a default of IN because well, what else would you use?
-fn default_record_class() -> RecordClass {
+pub(crate) fn default_record_class() -> RecordClass {
     RecordClass::Internet
 }
 
@@ -100,6 +947,232 @@ impl Display for FileZoneRecord {
     }
 }
 
+/// Placeholder in template records which is replaced with the zone name when the template's applied
+/// Record types whose names have to be hostnames, see [FileZoneRecord::check_name]
+pub const HOSTNAME_RRTYPES: [&str; 2] = ["A", "AAAA"];
+
+pub const TEMPLATE_ZONE_PLACEHOLDER: &str = "{zone}";
+
+/// A set of records which pre-populate a new zone, defined by admins
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, ToSchema)]
+pub struct ZoneTemplate {
+    /// Database row ID
+    #[serde(default)]
+    pub id: Option<i64>,
+    /// The name of the template, eg "web hosting"
+    pub name: String,
+    /// What the template is for
+    #[serde(default)]
+    pub description: String,
+    /// The records that'll be created, `{zone}` in the name or rdata is replaced with the zone name
+    #[serde(default)]
+    pub records: Vec<FileZoneRecord>,
+}
+
+impl ZoneTemplate {
+    /// Build the records for a new zone from this template
+    pub fn apply(&self, zone_name: &str) -> Vec<FileZoneRecord> {
+        self.records
+            .iter()
+            .map(|record| FileZoneRecord {
+                id: None,
+                zoneid: None,
+                name: record.name.replace(TEMPLATE_ZONE_PLACEHOLDER, zone_name),
+                rdata: record.rdata.replace(TEMPLATE_ZONE_PLACEHOLDER, zone_name),
+                ..record.clone()
+            })
+            .collect()
+    }
+}
+
+/// A single change to a zone's records, proposed as part of a [ZoneChangeSet]
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(tag = "action", content = "record", rename_all = "lowercase")]
+pub enum RecordChange {
+    /// Add a new record
+    Create(FileZoneRecord),
+    /// Replace an existing record, matched by its id
+    Update(FileZoneRecord),
+    /// Remove the record with this id
+    Delete(i64),
+    /// Change just the TTL of the record with this id
+    #[serde(rename = "set_ttl")]
+    SetTtl {
+        /// The record's ID
+        id: i64,
+        /// Its new TTL
+        ttl: u32,
+    },
+}
+
+impl Display for RecordChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordChange::Create(record) => f.write_fmt(format_args!(
+                "Create {} {} {} {}",
+                record.name, record.rrtype, record.ttl, record.rdata
+            )),
+            RecordChange::Update(record) => f.write_fmt(format_args!(
+                "Update id={:?} to {} {} {} {}",
+                record.id, record.name, record.rrtype, record.ttl, record.rdata
+            )),
+            RecordChange::Delete(id) => f.write_fmt(format_args!("Delete id={id}")),
+            RecordChange::SetTtl { id, ttl } => {
+                f.write_fmt(format_args!("Set TTL of id={id} to {ttl}"))
+            }
+        }
+    }
+}
+
+/// Where a [ZoneChangeSet] is at in the review process
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub enum ChangeSetStatus {
+    /// Waiting for an owner
+    #[default]
+    Pending,
+    /// Applied to the zone
+    Approved,
+    /// Thrown away
+    Rejected,
+}
+
+impl Display for ChangeSetStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ChangeSetStatus::Pending => "Pending",
+            ChangeSetStatus::Approved => "Approved",
+            ChangeSetStatus::Rejected => "Rejected",
+        })
+    }
+}
+
+impl TryFrom<&str> for ChangeSetStatus {
+    type Error = GoatNsError;
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        match input {
+            "Pending" => Ok(ChangeSetStatus::Pending),
+            "Approved" => Ok(ChangeSetStatus::Approved),
+            "Rejected" => Ok(ChangeSetStatus::Rejected),
+            _ => Err(GoatNsError::InvalidValue(format!(
+                "Unknown change-set status: {input}"
+            ))),
+        }
+    }
+}
+
+/// A set of edits to a zone proposed by someone who doesn't own it, which are applied all at once if an owner approves them
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ZoneChangeSet {
+    /// Database row ID
+    #[serde(default)]
+    pub id: Option<i64>,
+    /// Foreign key to id in [FileZone::id]
+    pub zoneid: i64,
+    /// The user who proposed the changes
+    #[serde(default)]
+    pub userid: i64,
+    /// Why the changes are needed
+    #[serde(default)]
+    pub description: String,
+    /// When it was proposed
+    #[serde(default = "chrono::Utc::now")]
+    pub created: chrono::DateTime<chrono::Utc>,
+    /// Where it's at in review
+    #[serde(default)]
+    pub status: ChangeSetStatus,
+    /// The changes to apply
+    pub changes: Vec<RecordChange>,
+}
+
+/// Where a [ZoneOwnershipTransfer] is at
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub enum OwnershipTransferStatus {
+    /// Waiting on the new owner
+    #[default]
+    Pending,
+    /// The new owner took the zone
+    Accepted,
+    /// The new owner didn't want it
+    Declined,
+    /// The old owner changed their mind
+    Cancelled,
+}
+
+impl Display for OwnershipTransferStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            OwnershipTransferStatus::Pending => "Pending",
+            OwnershipTransferStatus::Accepted => "Accepted",
+            OwnershipTransferStatus::Declined => "Declined",
+            OwnershipTransferStatus::Cancelled => "Cancelled",
+        })
+    }
+}
+
+impl TryFrom<&str> for OwnershipTransferStatus {
+    type Error = GoatNsError;
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        match input {
+            "Pending" => Ok(OwnershipTransferStatus::Pending),
+            "Accepted" => Ok(OwnershipTransferStatus::Accepted),
+            "Declined" => Ok(OwnershipTransferStatus::Declined),
+            "Cancelled" => Ok(OwnershipTransferStatus::Cancelled),
+            _ => Err(GoatNsError::InvalidValue(format!(
+                "Unknown ownership transfer status: {input}"
+            ))),
+        }
+    }
+}
+
+/// A zone owner handing their zone to someone else, it doesn't happen until the new owner accepts it
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ZoneOwnershipTransfer {
+    /// Database row ID
+    #[serde(default)]
+    pub id: Option<i64>,
+    /// Foreign key to id in [FileZone::id]
+    pub zoneid: i64,
+    /// The owner giving the zone away
+    pub from_userid: i64,
+    /// Who it's going to
+    pub to_userid: i64,
+    /// When it was asked for
+    pub created: chrono::DateTime<chrono::Utc>,
+    /// Where it's at
+    #[serde(default)]
+    pub status: OwnershipTransferStatus,
+    /// The zone's name, filled in when it's loaded
+    #[serde(default)]
+    pub zone: String,
+    /// The old owner's username, filled in when it's loaded
+    #[serde(default)]
+    pub from_username: String,
+    /// The new owner's username, filled in when it's loaded
+    #[serde(default)]
+    pub to_username: String,
+}
+
+/// Something that was done to a zone, and the changes that'll put it back how it was
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ZoneJournalEntry {
+    /// Database row ID
+    #[serde(default)]
+    pub id: Option<i64>,
+    /// Foreign key to id in [FileZone::id]
+    pub zoneid: i64,
+    /// The user who made the change
+    pub userid: i64,
+    /// What was done, eg "Set all TTLs to 300"
+    pub description: String,
+    /// When it happened
+    pub created: chrono::DateTime<chrono::Utc>,
+    /// Applying these undoes it
+    pub undo: Vec<RecordChange>,
+    /// If it's been undone already
+    #[serde(default)]
+    pub undone: bool,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 /// A list of records associated with a given name - ie `foo.example.com -> [A { 1.2.3.4}, AAAA { 2000:cafe:beef }` etc
 pub struct ZoneRecord {
@@ -109,6 +1182,17 @@ pub struct ZoneRecord {
     pub typerecords: Vec<InternalResourceRecord>,
 }
 
+impl ZoneRecord {
+    /// The records for the answer section, owned by this name
+    pub fn into_answers(self) -> Vec<Answer> {
+        let name = self.name;
+        self.typerecords
+            .into_iter()
+            .map(|record| Answer::named(name.clone(), record))
+            .collect()
+    }
+}
+
 impl Display for ZoneRecord {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!(
@@ -120,32 +1204,104 @@ impl Display for ZoneRecord {
     }
 }
 
-/// Loads a zone file
-pub fn load_zone_from_file(filename: &Path) -> Result<FileZone, GoatNsError> {
-    let mut file = match File::open(filename) {
-        Ok(value) => value,
-        Err(error) => {
-            return Err(GoatNsError::FileError(format!(
-                "Failed to open zone file: {:?}",
-                error
-            )));
-        }
-    };
-    let mut buf: String = String::new();
-    file.read_to_string(&mut buf)
-        .inspect_err(|err| error!("Failed to read {}: {:?}", &filename.display(), err))?;
-    let jsonstruct: FileZone = match json5::from_str(&buf) {
-        Ok(value) => value,
-        Err(error) => {
-            let emsg = format!("Failed to read JSON file: {:?}", error);
-            error!("{}", emsg);
-            return Err(GoatNsError::FileError(emsg));
+#[derive(Debug, PartialEq, Eq, Clone)]
+/// What we know about a name that had no records of the type asked for - RFC 2308 negative answers need the zone's SOA in the authority section
+pub struct NegativeAnswer {
+    /// The closest zone we're authoritative for that the name's in
+    pub zone: Vec<u8>,
+    /// The zone's SOA, for the authority section
+    pub soa: InternalResourceRecord,
+    /// If something's at or below the name it's NODATA rather than NXDOMAIN - this covers empty non-terminals like `_tcp.example.com` when there's only `_sip._tcp.example.com`
+    pub name_exists: bool,
+    /// If the zone's been turned off the query gets `disabled_zone_rcode` instead
+    pub zone_enabled: bool,
+}
+
+impl NegativeAnswer {
+    /// Is the name the zone's apex? Queries for the SOA there get it as the answer.
+    pub fn is_apex(&self, name: &[u8]) -> bool {
+        self.zone.eq_ignore_ascii_case(name)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+/// A zone cut inside one of our zones, we don't answer for anything at or below it, we refer the query to the child's name servers
+pub struct Delegation {
+    /// Where the cut is, eg `child.example.com`
+    pub name: Vec<u8>,
+    /// The NS records at the cut
+    pub nameservers: Vec<InternalResourceRecord>,
+    /// Addresses for the name servers that are under the cut - the resolver can't look those up without us
+    pub glue: Vec<(Vec<u8>, InternalResourceRecord)>,
+}
+
+/// RFC 1034 section 3.6.2 - if a name has a CNAME it can't have any other data, and there can only be the one.
+/// FORWARD records are the same, since the whole name's answered from upstream.
+///
+/// The apex always has a SOA and NS records so it can't have a CNAME, an ALIAS record does the same job there.
+pub fn check_cname_rules<'a>(
+    zone_name: &str,
+    records: impl IntoIterator<Item = &'a FileZoneRecord>,
+) -> Result<(), GoatNsError> {
+    let mut names: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for record in records {
+        let (name, rrtype, _) = record.import_key();
+        names.entry(name).or_default().push(rrtype);
+    }
+    for (name, rrtypes) in names {
+        for exclusive in ["CNAME", "FORWARD"] {
+            let count = rrtypes.iter().filter(|rrtype| *rrtype == exclusive).count();
+            if count == 0 {
+                continue;
+            }
+            if name.is_empty() {
+                return Err(GoatNsError::InvalidValue(match exclusive {
+                    "CNAME" => format!(
+                        "{zone_name} is the zone apex so it can't have a CNAME record, use an ALIAS record instead"
+                    ),
+                    _ => format!("{zone_name} is the zone apex so it can't have a {exclusive} record"),
+                }));
+            }
+            let full_name = format!("{name}.{zone_name}");
+            if count > 1 {
+                return Err(GoatNsError::InvalidValue(format!(
+                    "{full_name} can only have one {exclusive} record, found {count}"
+                )));
+            }
+            let mut others: Vec<&String> = rrtypes
+                .iter()
+                .filter(|rrtype| *rrtype != exclusive)
+                .collect();
+            if !others.is_empty() {
+                others.sort();
+                others.dedup();
+                return Err(GoatNsError::InvalidValue(format!(
+                    "{full_name} has a {exclusive} record so it can't have any others, found {}",
+                    others
+                        .iter()
+                        .map(|rrtype| rrtype.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )));
+            }
         }
-    };
-    Ok(jsonstruct)
+    }
+    Ok(())
 }
 
-/// Loads a zone file
+/// Loads a zone file with a single zone in it
+pub fn load_zone_from_file(filename: &Path) -> Result<FileZone, GoatNsError> {
+    let mut zones = load_zones(&filename.display().to_string())?;
+    match (zones.pop(), zones.is_empty()) {
+        (Some(zone), true) => Ok(zone),
+        _ => Err(GoatNsError::FileError(format!(
+            "{} needs to have exactly one zone in it",
+            filename.display()
+        ))),
+    }
+}
+
+/// Loads a zone file, in any of the schemas [crate::zone_schema] knows about
 pub fn load_zones(filename: &str) -> Result<Vec<FileZone>, GoatNsError> {
     let mut file = match File::open(filename) {
         Ok(value) => value,
@@ -160,23 +1316,5 @@ pub fn load_zones(filename: &str) -> Result<Vec<FileZone>, GoatNsError> {
     let mut buf: String = String::new();
     file.read_to_string(&mut buf)
         .inspect_err(|err| error!("Failed to read {}: {:?}", filename, err))?;
-    let jsonstruct: Result<Vec<FileZone>, GoatNsError> = json5::from_str(&buf)
-        .map_err(|e| GoatNsError::FileError(format!("Failed to read JSON file: {e:?}")));
-    jsonstruct
-}
-
-impl FileZone {
-    pub(crate) async fn get_unowned(
-        pool: &mut SqliteConnection,
-    ) -> Result<Vec<FileZone>, GoatNsError> {
-        let rows = sqlx::query(
-            "select * from zones where id not in (select DISTINCT zoneid from ownership)",
-        )
-        .fetch_all(&mut *pool)
-        .await?
-        .into_iter()
-        .map(|row| row.into())
-        .collect();
-        Ok(rows)
-    }
+    zones_from_json(&buf).inspect_err(|err| error!("Failed to load {filename}: {err:?}"))
 }
@@ -1,3 +1,35 @@
 document.addEventListener('DOMContentLoaded', function() {
     feather.replace();
+    watchZone();
 });
+
+// keeps the zone page up to date when someone else changes the zone
+function watchZone() {
+    const banner = document.getElementById('zone-events');
+    if (!banner || !window.EventSource) {
+        return;
+    }
+    const events = new EventSource('/api/zone/' + banner.dataset.zoneId + '/events');
+    const changed = function(serial) {
+        if (serial !== undefined) {
+            banner.querySelector('.zone-serial').textContent = serial;
+        }
+        banner.classList.remove('d-none');
+    };
+    events.addEventListener('record_deleted', function(event) {
+        const data = JSON.parse(event.data);
+        const row = document.querySelector('tr[data-record-id="' + data.id + '"]');
+        if (row) {
+            row.classList.add('text-decoration-line-through', 'text-muted');
+        }
+        changed(data.serial);
+    });
+    for (const name of ['record_added', 'record_updated', 'serial_bumped']) {
+        events.addEventListener(name, function(event) {
+            changed(JSON.parse(event.data).serial);
+        });
+    }
+    events.addEventListener('lagged', function() {
+        changed();
+    });
+}